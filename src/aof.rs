@@ -0,0 +1,1389 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::byte_reader::ByteReader;
+use crate::client::Client;
+use crate::commands::{bytes, keyword, CommandSpec, Response};
+use crate::database::now_ms;
+use crate::object::Object;
+use crate::rdb::{self, Snapshot};
+use crate::Data;
+
+pub(crate) const DEFAULT_APPEND_FILENAME: &str = "appendonly.aof";
+pub(crate) const DEFAULT_APPEND_DIRNAME: &str = "appendonlydir";
+/// Like Redis's `auto-aof-rewrite-percentage`: rewrite once the files have doubled in size since the latest rewrite.
+pub(crate) const DEFAULT_REWRITE_PERCENTAGE: u64 = 100;
+/// Like Redis's `auto-aof-rewrite-min-size`: but not before they're 64 MB.
+pub(crate) const DEFAULT_REWRITE_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// When the append-only file is flushed to disk, like Redis's `appendfsync`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FsyncPolicy {
+    /// After every command, before it's replied to. The safest, and the slowest.
+    Always,
+    /// Once a second, on another thread, so that at most about a second of writes can be lost.
+    EverySec,
+    /// Whenever the operating system decides to.
+    No,
+}
+
+impl FsyncPolicy {
+    pub(crate) fn parse(name: &str) -> Option<FsyncPolicy> {
+        match name.to_lowercase().as_str() {
+            "always" => Some(FsyncPolicy::Always),
+            "everysec" => Some(FsyncPolicy::EverySec),
+            "no" => Some(FsyncPolicy::No),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileType {
+    /// The data as of the latest rewrite, as an RDB snapshot or as commands.
+    Base,
+    /// The commands run since.
+    Incremental,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ManifestEntry {
+    name: String,
+    seq: u64,
+    file_type: FileType,
+}
+
+/// The files the append-only file is made of, like Redis 7's multi-part AOF: a base file, and incremental files that
+/// are replayed after it in order. A rewrite replaces them all with a new base file.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Manifest {
+    base: Option<ManifestEntry>,
+    incrementals: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Parses lines like `file appendonly.aof.1.base.rdb seq 1 type b`. Redis's history files, which are only kept
+    /// until they're deleted, are skipped.
+    fn parse(text: &str) -> Result<Manifest, String> {
+        let mut manifest = Manifest::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let field = |name: &str| {
+                fields
+                    .chunks_exact(2)
+                    .find(|pair| pair[0] == name)
+                    .map(|pair| pair[1])
+            };
+            let invalid = || format!("invalid manifest line '{line}'");
+            let name = field("file").ok_or_else(invalid)?.to_string();
+            let seq = field("seq")
+                .and_then(|seq| seq.parse().ok())
+                .ok_or_else(invalid)?;
+
+            match field("type") {
+                Some("b") if manifest.base.is_none() => {
+                    manifest.base = Some(ManifestEntry {
+                        name,
+                        seq,
+                        file_type: FileType::Base,
+                    })
+                }
+                Some("i") => manifest.incrementals.push(ManifestEntry {
+                    name,
+                    seq,
+                    file_type: FileType::Incremental,
+                }),
+                Some("h") => {}
+                _ => return Err(invalid()),
+            }
+        }
+
+        manifest.incrementals.sort_by_key(|entry| entry.seq);
+
+        Ok(manifest)
+    }
+
+    fn format(&self) -> String {
+        self.files()
+            .map(|entry| {
+                let file_type = match entry.file_type {
+                    FileType::Base => 'b',
+                    FileType::Incremental => 'i',
+                };
+
+                format!("file {} seq {} type {file_type}\n", entry.name, entry.seq)
+            })
+            .collect()
+    }
+
+    /// The base file, then the incremental files, in the order they're replayed.
+    fn files(&self) -> impl Iterator<Item = &ManifestEntry> {
+        self.base.iter().chain(&self.incrementals)
+    }
+
+    /// Reads the manifest at `path`, or returns `None` if there's no file there.
+    fn read(path: &Path) -> Result<Option<Manifest>, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Manifest::parse(&text).map(Some),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        rdb::write_file(path, self.format().as_bytes())
+    }
+}
+
+/// A rewrite whose base file is being written on another thread.
+#[derive(Debug)]
+struct Rewrite {
+    /// The files the new base file replaces: the ones from before the rewrite started.
+    replaced: Vec<ManifestEntry>,
+    /// Receives the new base file once it's written.
+    done: Receiver<io::Result<ManifestEntry>>,
+}
+
+/// The append-only file, which every write command is logged to, so that the data can be rebuilt by replaying them.
+/// It's a directory of files listed in a [`Manifest`], so that it can be rewritten without blocking writes: new
+/// commands go to a new incremental file while the data is written to a new base file.
+#[derive(Debug)]
+pub(crate) struct Aof {
+    /// The directory the directory of files is in. An append-only file from before they were split into several
+    /// files is in this directory directly.
+    dir: PathBuf,
+    dirname: String,
+    filename: String,
+    fsync: FsyncPolicy,
+    /// Whether base files are RDB snapshots rather than commands.
+    use_rdb_preamble: bool,
+    rewrite_percentage: u64,
+    rewrite_min_size: u64,
+    manifest: Manifest,
+    /// The incremental file that commands are appended to, or `None` while the append-only file is disabled.
+    file: Option<File>,
+    /// The database the commands in the file apply to, as set by the last `SELECT` written, or `None` before the first.
+    selected: Option<usize>,
+    /// The commands logged by the command being run, with their database. They're written together once it finishes,
+    /// so that the effects of a transaction or script are replayed as a whole.
+    pending: Vec<(usize, Vec<Vec<u8>>)>,
+    /// Whether anything was written since the file was last flushed to disk.
+    unsynced: bool,
+    /// How many bytes were written since the server started, and how many of them are known to be on disk, which
+    /// `WAITAOF` compares.
+    written: u64,
+    synced: Arc<AtomicU64>,
+    /// When the file was last flushed to disk, in milliseconds since the Unix epoch.
+    last_fsync: u64,
+    syncing: Arc<AtomicBool>,
+    last_write_failed: Arc<AtomicBool>,
+    /// The total size of the files in the manifest.
+    current_size: u64,
+    /// The total size after the latest rewrite, or when the files were opened, which automatic rewrites compare with.
+    base_size: u64,
+    rewrite: Option<Rewrite>,
+    last_rewrite_failed: bool,
+}
+
+impl Aof {
+    pub(crate) fn new(dir: PathBuf, dirname: String, filename: String, fsync: FsyncPolicy) -> Aof {
+        Aof {
+            dir,
+            dirname,
+            filename,
+            fsync,
+            use_rdb_preamble: true,
+            rewrite_percentage: DEFAULT_REWRITE_PERCENTAGE,
+            rewrite_min_size: DEFAULT_REWRITE_MIN_SIZE,
+            manifest: Manifest::default(),
+            file: None,
+            selected: None,
+            pending: Vec::new(),
+            unsynced: false,
+            written: 0,
+            synced: Arc::new(AtomicU64::new(0)),
+            last_fsync: now_ms(),
+            syncing: Arc::new(AtomicBool::new(false)),
+            last_write_failed: Arc::new(AtomicBool::new(false)),
+            current_size: 0,
+            base_size: 0,
+            rewrite: None,
+            last_rewrite_failed: false,
+        }
+    }
+
+    pub(crate) fn dirname(&self) -> &str {
+        &self.dirname
+    }
+
+    pub(crate) fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub(crate) fn fsync(&self) -> FsyncPolicy {
+        self.fsync
+    }
+
+    pub(crate) fn set_fsync(&mut self, fsync: FsyncPolicy) {
+        self.fsync = fsync;
+    }
+
+    pub(crate) fn use_rdb_preamble(&self) -> bool {
+        self.use_rdb_preamble
+    }
+
+    pub(crate) fn set_use_rdb_preamble(&mut self, use_rdb_preamble: bool) {
+        self.use_rdb_preamble = use_rdb_preamble;
+    }
+
+    pub(crate) fn rewrite_percentage(&self) -> u64 {
+        self.rewrite_percentage
+    }
+
+    pub(crate) fn rewrite_min_size(&self) -> u64 {
+        self.rewrite_min_size
+    }
+
+    /// Sets when the files are rewritten automatically: once they've grown by `percentage` percent since the latest
+    /// rewrite, and are at least `min_size` bytes. A percentage of zero disables automatic rewrites.
+    pub(crate) fn set_rewrite_growth(&mut self, percentage: u64, min_size: u64) {
+        self.rewrite_percentage = percentage;
+        self.rewrite_min_size = min_size;
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Whether the latest write to the file, or flush to disk, succeeded. `true` if there hasn't been one.
+    pub(crate) fn last_write_succeeded(&self) -> bool {
+        !self.last_write_failed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_rewriting(&self) -> bool {
+        self.rewrite.is_some()
+    }
+
+    /// Whether the latest rewrite succeeded. `true` if there hasn't been one.
+    pub(crate) fn last_rewrite_succeeded(&self) -> bool {
+        !self.last_rewrite_failed
+    }
+
+    /// How many bytes were written since the server started.
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// How many of the bytes written are known to be on disk.
+    pub(crate) fn synced(&self) -> u64 {
+        self.synced.load(Ordering::Relaxed)
+    }
+
+    /// Whether everything written is known to be on disk.
+    pub(crate) fn is_synced(&self) -> bool {
+        self.synced() >= self.written
+    }
+
+    pub(crate) fn current_size(&self) -> u64 {
+        self.current_size
+    }
+
+    pub(crate) fn base_size(&self) -> u64 {
+        self.base_size
+    }
+
+    fn directory(&self) -> PathBuf {
+        self.dir.join(&self.dirname)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.directory().join(format!("{}.manifest", self.filename))
+    }
+
+    /// Where an append-only file from before they were split into several files would be.
+    fn legacy_path(&self) -> PathBuf {
+        self.dir.join(&self.filename)
+    }
+
+    fn size_of(&self, entry: &ManifestEntry) -> u64 {
+        fs::metadata(self.directory().join(&entry.name)).map_or(0, |metadata| metadata.len())
+    }
+
+    /// Rebuilds the data by replaying the files, running each command like a client would. Returns `false` if there
+    /// aren't any.
+    ///
+    /// If the last file ends in the middle of a command or transaction, like after a crash, the incomplete part is
+    /// removed when `allow_truncated` is set, and the rest is loaded. Otherwise it's an error.
+    pub(crate) fn load(&self, allow_truncated: bool, data: &Mutex<Data>) -> Result<bool, String> {
+        let Some(manifest) = Manifest::read(&self.manifest_path())? else {
+            let legacy = self.legacy_path();
+
+            if !legacy.exists() {
+                return Ok(false);
+            }
+
+            load_file(&legacy, allow_truncated, data)?;
+
+            return Ok(true);
+        };
+        let last = manifest.files().count();
+
+        for (index, entry) in manifest.files().enumerate() {
+            load_file(
+                &self.directory().join(&entry.name),
+                allow_truncated && index + 1 == last,
+                data,
+            )
+            .map_err(|error| format!("{}: {error}", entry.name))?;
+        }
+
+        Ok(true)
+    }
+
+    /// Opens the files to append commands to them. If there aren't any, they're created, starting with a base file
+    /// holding `snapshot`, the data that's already loaded. An append-only file from before they were split into
+    /// several files becomes the base file.
+    pub(crate) fn enable(&mut self, snapshot: impl FnOnce() -> Snapshot) -> io::Result<()> {
+        fs::create_dir_all(self.directory())?;
+
+        let manifest = Manifest::read(&self.manifest_path()).map_err(io::Error::other)?;
+
+        self.manifest = match manifest {
+            Some(manifest) => manifest,
+            None => {
+                let legacy = self.legacy_path();
+                let base = if legacy.exists() {
+                    fs::rename(&legacy, self.directory().join(&self.filename))?;
+
+                    ManifestEntry {
+                        name: self.filename.clone(),
+                        seq: 1,
+                        file_type: FileType::Base,
+                    }
+                } else {
+                    write_base(
+                        &self.directory(),
+                        &self.filename,
+                        1,
+                        &snapshot(),
+                        self.use_rdb_preamble,
+                    )?
+                };
+
+                Manifest {
+                    base: Some(base),
+                    incrementals: Vec::new(),
+                }
+            }
+        };
+
+        match self.manifest.incrementals.last() {
+            Some(last) => {
+                self.file = Some(
+                    OpenOptions::new()
+                        .append(true)
+                        .open(self.directory().join(&last.name))?,
+                );
+            }
+            None => self.open_incremental()?,
+        }
+
+        self.selected = None;
+        self.current_size = self.manifest.files().map(|entry| self.size_of(entry)).sum();
+        self.base_size = self.current_size;
+
+        Ok(())
+    }
+
+    /// Starts a new incremental file for commands to be appended to, and adds it to the manifest.
+    fn open_incremental(&mut self) -> io::Result<()> {
+        let seq = self
+            .manifest
+            .incrementals
+            .last()
+            .map_or(1, |last| last.seq + 1);
+        let entry = ManifestEntry {
+            name: format!("{}.{seq}.incr.aof", self.filename),
+            seq,
+            file_type: FileType::Incremental,
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory().join(&entry.name))?;
+        let mut manifest = self.manifest.clone();
+
+        manifest.incrementals.push(entry);
+        manifest.write(&self.manifest_path())?;
+
+        self.manifest = manifest;
+        self.file = Some(file);
+        self.selected = None;
+
+        Ok(())
+    }
+
+    /// Logs a command that ran against `database`. It's written by the next [`flush`](Aof::flush).
+    pub(crate) fn feed(&mut self, database: usize, command: Vec<Vec<u8>>) {
+        if self.is_enabled() {
+            self.pending.push((database, command));
+        }
+    }
+
+    /// Writes the commands logged since the last call, wrapped in `MULTI` and `EXEC` if there are several. With the
+    /// `always` policy, they're also flushed to disk.
+    pub(crate) fn flush(&mut self) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let bytes = encode_transaction(&mut self.selected, self.pending.drain(..));
+        let mut result = file.write_all(&bytes);
+
+        if result.is_ok() {
+            self.current_size += bytes.len() as u64;
+            self.written += bytes.len() as u64;
+        }
+
+        if result.is_ok() && self.fsync == FsyncPolicy::Always {
+            result = file.sync_data();
+            self.last_fsync = now_ms();
+
+            if result.is_ok() {
+                self.synced.store(self.written, Ordering::Relaxed);
+            }
+        } else {
+            self.unsynced = true;
+        }
+
+        if let Err(error) = &result {
+            eprintln!("Error writing to the append only file: {error}");
+        }
+
+        self.last_write_failed
+            .store(result.is_err(), Ordering::Relaxed);
+    }
+
+    /// Flushes the file to disk on another thread if the policy is `everysec` and a second has passed since the last
+    /// time. Called periodically.
+    pub(crate) fn sync_if_needed(&mut self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        if self.fsync != FsyncPolicy::EverySec
+            || !self.unsynced
+            || now_ms().saturating_sub(self.last_fsync) < 1000
+            || self.syncing.swap(true, Ordering::Relaxed)
+        {
+            return;
+        }
+
+        let file = match file.try_clone() {
+            Ok(file) => file,
+            Err(error) => {
+                eprintln!("Error flushing the append only file: {error}");
+                self.syncing.store(false, Ordering::Relaxed);
+
+                return;
+            }
+        };
+        let syncing = Arc::clone(&self.syncing);
+        let last_write_failed = Arc::clone(&self.last_write_failed);
+        let synced = Arc::clone(&self.synced);
+        let written = self.written;
+
+        self.unsynced = false;
+        self.last_fsync = now_ms();
+
+        thread::spawn(move || {
+            match file.sync_data() {
+                Ok(()) => {
+                    synced.fetch_max(written, Ordering::Relaxed);
+                }
+                Err(error) => {
+                    eprintln!("Error flushing the append only file: {error}");
+                    last_write_failed.store(true, Ordering::Relaxed);
+                }
+            }
+
+            syncing.store(false, Ordering::Relaxed);
+        });
+    }
+
+    /// Checks that a rewrite can be started.
+    pub(crate) fn check_rewrite(&self) -> Result<(), &'static str> {
+        if !self.is_enabled() {
+            return Err("Append only file is disabled");
+        }
+
+        if self.is_rewriting() {
+            return Err("Background append only file rewriting already in progress");
+        }
+
+        Ok(())
+    }
+
+    /// Starts replacing the files with a new base file holding `snapshot`, the data as it is now, which is written on
+    /// another thread. Commands run in the meantime go to a new incremental file, which is kept.
+    pub(crate) fn rewrite(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        self.check_rewrite()?;
+        self.flush();
+
+        let replaced: Vec<ManifestEntry> = self.manifest.files().cloned().collect();
+
+        self.open_incremental().map_err(|error| error.to_string())?;
+
+        let (sender, done) = mpsc::channel();
+        let directory = self.directory();
+        let filename = self.filename.clone();
+        let seq = self.manifest.base.as_ref().map_or(1, |base| base.seq + 1);
+        let use_rdb_preamble = self.use_rdb_preamble;
+
+        thread::spawn(move || {
+            let _ = sender.send(write_base(
+                &directory,
+                &filename,
+                seq,
+                &snapshot,
+                use_rdb_preamble,
+            ));
+        });
+
+        self.rewrite = Some(Rewrite { replaced, done });
+
+        Ok(())
+    }
+
+    /// Switches to the new base file if the rewrite in progress is done, and deletes the files it replaces. Called
+    /// periodically.
+    pub(crate) fn finish_rewrite(&mut self) {
+        let Some(rewrite) = &self.rewrite else {
+            return;
+        };
+
+        let result = match rewrite.done.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(io::Error::other("the rewrite was interrupted")),
+        };
+        let rewrite = self.rewrite.take().expect("a rewrite is in progress");
+        let mut manifest = self.manifest.clone();
+
+        let result = result.and_then(|base| {
+            manifest.base = Some(base);
+            manifest
+                .incrementals
+                .retain(|entry| !rewrite.replaced.contains(entry));
+            manifest.write(&self.manifest_path())
+        });
+
+        self.last_rewrite_failed = result.is_err();
+
+        if let Err(error) = result {
+            eprintln!("Background AOF rewrite failed: {error}");
+
+            return;
+        }
+
+        for entry in &rewrite.replaced {
+            let _ = fs::remove_file(self.directory().join(&entry.name));
+        }
+
+        self.manifest = manifest;
+        self.current_size = self.manifest.files().map(|entry| self.size_of(entry)).sum();
+        self.base_size = self.current_size;
+
+        println!("Background AOF rewrite finished successfully");
+    }
+
+    /// Whether the files have grown enough since the latest rewrite to be rewritten automatically.
+    pub(crate) fn should_rewrite(&self) -> bool {
+        let growth = self.current_size.saturating_sub(self.base_size) * 100 / self.base_size.max(1);
+
+        self.is_enabled()
+            && !self.is_rewriting()
+            && self.rewrite_percentage > 0
+            && self.current_size >= self.rewrite_min_size
+            && growth >= self.rewrite_percentage
+    }
+}
+
+/// Writes a base file holding `snapshot`, returning its manifest entry. It's an RDB snapshot if `use_rdb_preamble`
+/// is set, or if some values can only be saved that way. Otherwise it's commands.
+fn write_base(
+    directory: &Path,
+    filename: &str,
+    seq: u64,
+    snapshot: &Snapshot,
+    use_rdb_preamble: bool,
+) -> io::Result<ManifestEntry> {
+    let commands = if use_rdb_preamble {
+        None
+    } else {
+        commands(snapshot)
+    };
+    let (bytes, extension) = match commands {
+        Some(commands) => (commands, "aof"),
+        None => (rdb::encode(snapshot), "rdb"),
+    };
+    let entry = ManifestEntry {
+        name: format!("{filename}.{seq}.base.{extension}"),
+        seq,
+        file_type: FileType::Base,
+    };
+
+    rdb::write_file(&directory.join(&entry.name), &bytes)?;
+
+    Ok(entry)
+}
+
+/// The commands that rebuild the data in `snapshot`, or `None` if some values, like probabilistic filters, can't be
+/// created with commands.
+fn commands(snapshot: &Snapshot) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for code in &snapshot.libraries {
+        encode(
+            &mut bytes,
+            &[b"FUNCTION".to_vec(), b"LOAD".to_vec(), code.clone()],
+        );
+    }
+
+    for (index, entries) in snapshot.databases.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+
+        encode(
+            &mut bytes,
+            &[b"SELECT".to_vec(), index.to_string().into_bytes()],
+        );
+
+        for (key, object, expires_at) in entries {
+            let key = match key {
+                BulkString::Filled(key) => key.clone(),
+                _ => Vec::new(),
+            };
+
+            match object {
+                Object::String(value) => {
+                    let value = match value {
+                        BulkString::Filled(value) => value.clone(),
+                        _ => Vec::new(),
+                    };
+
+                    encode(&mut bytes, &[b"SET".to_vec(), key.clone(), value]);
+                }
+                Object::Json(json) => encode(
+                    &mut bytes,
+                    &[
+                        b"JSON.SET".to_vec(),
+                        key.clone(),
+                        b"$".to_vec(),
+                        json.serialize().into_bytes(),
+                    ],
+                ),
+                Object::TimeSeries(series) => {
+                    let mut create = vec![
+                        b"TS.CREATE".to_vec(),
+                        key.clone(),
+                        b"RETENTION".to_vec(),
+                        series.retention.to_string().into_bytes(),
+                        b"ENCODING".to_vec(),
+                        if series.is_compressed() {
+                            b"COMPRESSED".to_vec()
+                        } else {
+                            b"UNCOMPRESSED".to_vec()
+                        },
+                        b"CHUNK_SIZE".to_vec(),
+                        series.chunk_size.to_string().into_bytes(),
+                        b"DUPLICATE_POLICY".to_vec(),
+                        series.duplicate_policy.name().as_bytes().to_vec(),
+                    ];
+
+                    if !series.labels.is_empty() {
+                        create.push(b"LABELS".to_vec());
+
+                        for (label, value) in &series.labels {
+                            create.push(label.as_bytes().to_vec());
+                            create.push(value.as_bytes().to_vec());
+                        }
+                    }
+
+                    encode(&mut bytes, &create);
+
+                    for (timestamp, value) in series.range(0, u64::MAX) {
+                        encode(
+                            &mut bytes,
+                            &[
+                                b"TS.ADD".to_vec(),
+                                key.clone(),
+                                timestamp.to_string().into_bytes(),
+                                value.to_string().into_bytes(),
+                            ],
+                        );
+                    }
+                }
+                Object::BloomFilter(_) | Object::CuckooFilter(_) => return None,
+            }
+
+            if let Some(at) = expires_at {
+                encode(
+                    &mut bytes,
+                    &[b"PEXPIREAT".to_vec(), key, at.to_string().into_bytes()],
+                );
+            }
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Appends `command` to `bytes` as a RESP array of bulk strings, the way clients send commands.
+pub(crate) fn encode(bytes: &mut Vec<u8>, command: &[Vec<u8>]) {
+    bytes.extend(format!("*{}\r\n", command.len()).as_bytes());
+
+    for argument in command {
+        bytes.extend(format!("${}\r\n", argument.len()).as_bytes());
+        bytes.extend(argument);
+        bytes.extend(b"\r\n");
+    }
+}
+
+/// Encodes the commands a command, transaction or script logged, with their database, wrapped in `MULTI` and `EXEC`
+/// if there are several. A `SELECT` is added before a command whose database isn't `selected`, which is updated.
+pub(crate) fn encode_transaction(
+    selected: &mut Option<usize>,
+    commands: impl ExactSizeIterator<Item = (usize, Vec<Vec<u8>>)>,
+) -> Vec<u8> {
+    let transaction = commands.len() > 1;
+    let mut bytes = Vec::new();
+
+    if transaction {
+        encode(&mut bytes, &[b"MULTI".to_vec()]);
+    }
+
+    for (database, command) in commands {
+        if *selected != Some(database) {
+            encode(
+                &mut bytes,
+                &[b"SELECT".to_vec(), database.to_string().into_bytes()],
+            );
+            *selected = Some(database);
+        }
+
+        encode(&mut bytes, &command);
+    }
+
+    if transaction {
+        encode(&mut bytes, &[b"EXEC".to_vec()]);
+    }
+
+    bytes
+}
+
+fn argument_bytes(argument: &Value) -> Vec<u8> {
+    bytes(argument).unwrap_or_default().to_vec()
+}
+
+/// The commands to log for a command that ran against `data`, so that replaying them has the same effect: commands
+/// that failed or didn't write anything aren't logged, and times relative to when a command ran are made absolute,
+/// since the file may be replayed much later.
+pub(crate) fn effects(
+    data: &mut Data,
+    spec: &CommandSpec,
+    arguments: &[Value],
+    response: &Response,
+) -> Vec<Vec<Vec<u8>>> {
+    if matches!(response, Response::Error(_) | Response::OwnedError(_)) {
+        return Vec::new();
+    }
+
+    let command = || {
+        let mut command = vec![spec.name.as_bytes().to_vec()];
+
+        command.extend(arguments.iter().map(argument_bytes));
+        command
+    };
+
+    match spec.name {
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+            if *response != Response::Integer(1) {
+                return Vec::new();
+            }
+
+            let Value::BulkString(key) = &arguments[0];
+            let key_bytes = argument_bytes(&arguments[0]);
+
+            // An expiry time in the past deletes the key.
+            match data.expires_at(key) {
+                Some(at) => vec![vec![
+                    b"PEXPIREAT".to_vec(),
+                    key_bytes,
+                    at.to_string().into_bytes(),
+                ]],
+                None => vec![vec![b"DEL".to_vec(), key_bytes]],
+            }
+        }
+        // The TTL is replaced by the key's expiry time, which `ABSTTL` says is absolute.
+        "RESTORE" | "RESTORE-ASKING" => {
+            let Value::BulkString(key) = &arguments[0];
+            let mut command = command();
+
+            command[0] = b"RESTORE".to_vec();
+
+            if let Some(at) = data.expires_at(key) {
+                command[2] = at.to_string().into_bytes();
+
+                if !command[4..]
+                    .iter()
+                    .any(|option| option.eq_ignore_ascii_case(b"ABSTTL"))
+                {
+                    command.push(b"ABSTTL".to_vec());
+                }
+            }
+
+            vec![command]
+        }
+        // The keys moved to another instance are deleted here, unless they were copied.
+        "MIGRATE" => {
+            let copy = arguments[5..]
+                .iter()
+                .take_while(|option| keyword(option).as_deref() != Some("KEYS"))
+                .any(|option| keyword(option).as_deref() == Some("COPY"));
+
+            if copy || *response != Response::SimpleString("OK") {
+                return Vec::new();
+            }
+
+            let mut command = vec![b"DEL".to_vec()];
+
+            command.extend(spec.keys(arguments).into_iter().map(|key| match key {
+                BulkString::Filled(key) => key.clone(),
+                _ => Vec::new(),
+            }));
+
+            vec![command]
+        }
+        "TS.ADD" => {
+            let mut command = command();
+
+            if let (b"*", Response::Integer(timestamp)) = (command[2].as_slice(), response) {
+                command[2] = timestamp.to_string().into_bytes();
+            }
+
+            vec![command]
+        }
+        // Functions are part of the data, though loading them isn't a write to the keyspace.
+        "FUNCTION" => match arguments.first().and_then(bytes) {
+            Some(subcommand)
+                if ["LOAD", "DELETE", "FLUSH", "RESTORE"]
+                    .iter()
+                    .any(|name| subcommand.eq_ignore_ascii_case(name.as_bytes())) =>
+            {
+                vec![command()]
+            }
+            _ => Vec::new(),
+        },
+        _ if spec.is_write() => vec![command()],
+        _ => Vec::new(),
+    }
+}
+
+/// Why a command couldn't be read from the file.
+#[derive(Debug, PartialEq)]
+enum ReadError {
+    /// The file ends in the middle of the command, like after a crash while it was being written.
+    Truncated,
+    Malformed,
+}
+
+fn read_line<'a>(reader: &mut ByteReader<'a>) -> Result<&'a [u8], ReadError> {
+    let line = reader.read_while(|byte| byte != b'\r');
+
+    match reader.read_bytes(2) {
+        Some(b"\r\n") => Ok(line),
+        Some(_) => Err(ReadError::Malformed),
+        None => Err(ReadError::Truncated),
+    }
+}
+
+/// Reads a line made of `prefix` followed by a length.
+fn read_length(reader: &mut ByteReader, prefix: u8) -> Result<usize, ReadError> {
+    match reader.read_byte() {
+        Some(byte) if byte == prefix => {}
+        Some(_) => return Err(ReadError::Malformed),
+        None => return Err(ReadError::Truncated),
+    }
+
+    str::from_utf8(read_line(reader)?)
+        .ok()
+        .and_then(|length| length.parse().ok())
+        .ok_or(ReadError::Malformed)
+}
+
+/// Reads a command written by [`encode`].
+fn read_command(reader: &mut ByteReader) -> Result<Vec<Vec<u8>>, ReadError> {
+    let count = read_length(reader, b'*')?;
+
+    if count == 0 {
+        return Err(ReadError::Malformed);
+    }
+
+    (0..count)
+        .map(|_| {
+            let length = read_length(reader, b'$')?;
+            let argument = reader.read_bytes(length).ok_or(ReadError::Truncated)?;
+
+            match reader.read_bytes(2) {
+                Some(b"\r\n") => Ok(argument.to_vec()),
+                Some(_) => Err(ReadError::Malformed),
+                None => Err(ReadError::Truncated),
+            }
+        })
+        .collect()
+}
+
+/// Replays the file at `path`, running each command like a client would. It may start with an RDB snapshot, which is
+/// loaded first.
+///
+/// If the file ends in the middle of a command or transaction, the incomplete part is removed when `allow_truncated`
+/// is set, and the rest is loaded. Otherwise it's an error.
+fn load_file(path: &Path, allow_truncated: bool, data: &Mutex<Data>) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|error| error.to_string())?;
+    let mut reader = ByteReader::new(&bytes);
+
+    if bytes.starts_with(b"REDIS") {
+        let (snapshot, length) = rdb::decode_prefix(&bytes)?;
+
+        data.lock()
+            .expect("failed to acquire lock")
+            .load(snapshot)?;
+        reader.slice(length);
+    }
+
+    let mut client = Client::new(mpsc::channel().0);
+    // Where the last complete command ends, not counting a transaction that's still open.
+    let mut valid = bytes.len() - reader.bytes_remaining();
+    let mut in_transaction = false;
+
+    while reader.bytes_remaining() > 0 {
+        let offset = bytes.len() - reader.bytes_remaining();
+        let command = match read_command(&mut reader) {
+            Ok(command) => command,
+            Err(ReadError::Truncated) => break,
+            Err(ReadError::Malformed) => return Err(format!("bad file format at offset {offset}")),
+        };
+        let name = String::from_utf8_lossy(&command[0]).to_uppercase();
+        let arguments: Vec<Value> = command[1..]
+            .iter()
+            .map(|argument| match argument.as_slice() {
+                [] => Value::BulkString(BulkString::Empty),
+                argument => Value::BulkString(BulkString::Filled(argument.to_vec())),
+            })
+            .collect();
+
+        match name.as_str() {
+            "MULTI" => in_transaction = true,
+            "EXEC" => in_transaction = false,
+            _ => {}
+        }
+
+        client.process(data, &name, &arguments);
+
+        if !in_transaction {
+            valid = bytes.len() - reader.bytes_remaining();
+        }
+    }
+
+    if valid < bytes.len() {
+        if !allow_truncated {
+            return Err("unexpected end of file".to_string());
+        }
+
+        eprintln!(
+            "Warning: the append only file is truncated. Removing the last {} bytes",
+            bytes.len() - valid
+        );
+
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(valid as u64))
+            .map_err(|error| error.to_string())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::json;
+    use crate::time_series::{DuplicatePolicy, TimeSeries, DEFAULT_CHUNK_SIZE};
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    fn command(arguments: &[&str]) -> Vec<Vec<u8>> {
+        arguments.iter().map(|a| a.as_bytes().to_vec()).collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("red-{}-{name}", process::id()))
+    }
+
+    /// An append-only file in a new temporary directory.
+    fn temp_aof(name: &str, use_rdb_preamble: bool) -> Aof {
+        let dir = temp_path(name);
+        let _ = fs::remove_dir_all(&dir);
+        let mut aof = Aof::new(
+            dir,
+            DEFAULT_APPEND_DIRNAME.to_string(),
+            DEFAULT_APPEND_FILENAME.to_string(),
+            FsyncPolicy::No,
+        );
+
+        aof.set_use_rdb_preamble(use_rdb_preamble);
+
+        aof
+    }
+
+    fn loaded(aof: &Aof) -> Data {
+        let data = Mutex::new(Data::new());
+
+        assert_eq!(Ok(true), aof.load(false, &data));
+
+        let mut data = data.into_inner().unwrap();
+
+        data.select(0);
+        data
+    }
+
+    #[test]
+    fn read_commands() {
+        let mut bytes = Vec::new();
+
+        encode(&mut bytes, &command(&["SET", "a", ""]));
+
+        assert_eq!(
+            Ok(command(&["SET", "a", ""])),
+            read_command(&mut ByteReader::new(&bytes))
+        );
+
+        for length in 0..bytes.len() {
+            assert_eq!(
+                Err(ReadError::Truncated),
+                read_command(&mut ByteReader::new(&bytes[..length])),
+                "{length} bytes"
+            );
+        }
+
+        assert_eq!(
+            Err(ReadError::Malformed),
+            read_command(&mut ByteReader::new(b"*1\r\n$1\r\nab\r\n"))
+        );
+        assert_eq!(
+            Err(ReadError::Malformed),
+            read_command(&mut ByteReader::new(b"+OK\r\n"))
+        );
+    }
+
+    #[test]
+    fn manifest() {
+        let text = "file appendonly.aof.2.base.rdb seq 2 type b\n\
+            file appendonly.aof.3.incr.aof seq 3 type i\n\
+            file appendonly.aof.1.base.rdb seq 1 type h\n\
+            file appendonly.aof.2.incr.aof seq 2 type i\n";
+        let manifest = Manifest::parse(text).unwrap();
+
+        assert_eq!(
+            vec![
+                "appendonly.aof.2.base.rdb",
+                "appendonly.aof.2.incr.aof",
+                "appendonly.aof.3.incr.aof"
+            ],
+            manifest
+                .files()
+                .map(|entry| entry.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Ok(manifest.clone()), Manifest::parse(&manifest.format()));
+        assert!(Manifest::parse("file a seq x type i").is_err());
+        assert!(Manifest::parse("file a seq 1 type q").is_err());
+    }
+
+    #[test]
+    fn log_and_replay() {
+        let mut aof = temp_aof("replay", true);
+
+        aof.enable(Snapshot::default).unwrap();
+        aof.feed(0, command(&["SET", "a", "1"]));
+        aof.flush();
+        aof.feed(2, command(&["SET", "b", "2"]));
+        aof.feed(2, command(&["DEL", "a"]));
+        aof.flush();
+
+        let written = fs::read(aof.directory().join("appendonly.aof.1.incr.aof")).unwrap();
+
+        assert!(written.starts_with(b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$3\r\nSET\r\n"));
+        assert!(written.ends_with(b"*2\r\n$3\r\nDEL\r\n$1\r\na\r\n*1\r\n$4\r\nEXEC\r\n"));
+        assert_eq!(aof.current_size(), aof.base_size() + written.len() as u64);
+
+        let mut data = loaded(&aof);
+
+        assert!(data.contains_key(&bulk_string!("a")));
+        data.select(2);
+        assert!(data.contains_key(&bulk_string!("b")));
+
+        fs::remove_dir_all(&aof.dir).unwrap();
+        assert_eq!(
+            Ok(false),
+            temp_aof("missing", true).load(false, &Mutex::new(Data::new()))
+        );
+    }
+
+    #[test]
+    fn synced_bytes() {
+        let mut aof = temp_aof("synced", true);
+
+        aof.enable(Snapshot::default).unwrap();
+        assert!(aof.is_synced());
+
+        // Without flushes to disk, written bytes never count as synced.
+        aof.feed(0, command(&["SET", "a", "1"]));
+        aof.flush();
+        assert!(aof.written() > 0);
+        assert_eq!(0, aof.synced());
+        assert!(!aof.is_synced());
+
+        aof.set_fsync(FsyncPolicy::Always);
+        aof.feed(0, command(&["SET", "a", "2"]));
+        aof.flush();
+        assert_eq!(aof.written(), aof.synced());
+        assert!(aof.is_synced());
+
+        fs::remove_dir_all(&aof.dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_file() {
+        let path = temp_path("truncated.aof");
+        let mut bytes = Vec::new();
+
+        encode(&mut bytes, &command(&["SET", "a", "1"]));
+
+        let complete = bytes.len();
+
+        // An unfinished transaction is discarded too.
+        encode(&mut bytes, &command(&["MULTI"]));
+        encode(&mut bytes, &command(&["SET", "b", "2"]));
+        encode(&mut bytes, &command(&["EXEC"]));
+        bytes.truncate(bytes.len() - 3);
+        fs::write(&path, &bytes).unwrap();
+
+        assert!(load_file(&path, false, &Mutex::new(Data::new())).is_err());
+
+        let data = Mutex::new(Data::new());
+
+        assert_eq!(Ok(()), load_file(&path, true, &data));
+        assert!(!data.lock().unwrap().contains_key(&bulk_string!("b")));
+        assert_eq!(complete, fs::read(&path).unwrap().len());
+
+        fs::write(&path, b"*1\r\n$4\r\nPING\r\n?").unwrap();
+        assert_eq!(
+            Err("bad file format at offset 14".to_string()),
+            load_file(&path, true, &Mutex::new(Data::new()))
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn legacy_file_becomes_the_base() {
+        let mut aof = temp_aof("legacy", true);
+        let base = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+        let mut bytes = rdb::encode(&base.snapshot());
+
+        encode(&mut bytes, &command(&["SET", "b", "2"]));
+        fs::create_dir_all(&aof.dir).unwrap();
+        fs::write(aof.legacy_path(), bytes).unwrap();
+
+        let mut data = loaded(&aof);
+
+        assert!(data.contains_key(&bulk_string!("a")));
+        assert!(data.contains_key(&bulk_string!("b")));
+
+        aof.enable(Snapshot::default).unwrap();
+
+        assert!(!aof.legacy_path().exists());
+        assert_eq!(
+            "file appendonly.aof seq 1 type b\nfile appendonly.aof.1.incr.aof seq 1 type i\n",
+            fs::read_to_string(aof.manifest_path()).unwrap()
+        );
+        assert!(loaded(&aof).contains_key(&bulk_string!("b")));
+
+        fs::remove_dir_all(&aof.dir).unwrap();
+    }
+
+    fn rewrite(aof: &mut Aof, data: &Data) {
+        aof.rewrite(data.snapshot()).unwrap();
+
+        assert_eq!(
+            Err("Background append only file rewriting already in progress"),
+            aof.check_rewrite()
+        );
+
+        while aof.is_rewriting() {
+            thread::sleep(std::time::Duration::from_millis(1));
+            aof.finish_rewrite();
+        }
+
+        assert!(aof.last_rewrite_succeeded());
+    }
+
+    #[test]
+    fn rewrites() {
+        for use_rdb_preamble in [true, false] {
+            let mut aof = temp_aof(&format!("rewrite-{use_rdb_preamble}"), use_rdb_preamble);
+            let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+
+            data.set_expires_at(&bulk_string!("a"), now_ms() + 60_000);
+            aof.enable(|| data.snapshot()).unwrap();
+            aof.feed(0, command(&["SET", "b", "2"]));
+            aof.flush();
+            data.insert(bulk_string!("b"), Object::String(bulk_string!("2")));
+            data.insert(
+                bulk_string!("json"),
+                Object::Json(json::parse(br#"{"a":[1,"x"]}"#).unwrap()),
+            );
+
+            let mut series = TimeSeries::new(
+                0,
+                false,
+                DEFAULT_CHUNK_SIZE,
+                DuplicatePolicy::Last,
+                vec![("kind".to_string(), "test".to_string())],
+            );
+
+            series.add(1, 0.1, None).unwrap();
+            series.add(2, -2.5, None).unwrap();
+            data.insert(bulk_string!("series"), Object::TimeSeries(series));
+
+            rewrite(&mut aof, &data);
+            rewrite(&mut aof, &data);
+
+            let extension = if use_rdb_preamble { "rdb" } else { "aof" };
+
+            assert_eq!(
+                format!(
+                    "file appendonly.aof.3.base.{extension} seq 3 type b\n\
+                    file appendonly.aof.3.incr.aof seq 3 type i\n"
+                ),
+                fs::read_to_string(aof.manifest_path()).unwrap()
+            );
+            assert_eq!(3, fs::read_dir(aof.directory()).unwrap().count());
+
+            aof.feed(0, command(&["SET", "c", "3"]));
+            aof.flush();
+
+            let mut loaded = loaded(&aof);
+
+            assert!(loaded.expires_at(&bulk_string!("a")).is_some());
+            assert!(loaded.contains_key(&bulk_string!("b")));
+            assert!(loaded.contains_key(&bulk_string!("c")));
+            assert_eq!(
+                data.get(&bulk_string!("json")).cloned(),
+                loaded.get(&bulk_string!("json")).cloned()
+            );
+            assert_eq!(
+                data.get(&bulk_string!("series")).cloned(),
+                loaded.get(&bulk_string!("series")).cloned()
+            );
+
+            fs::remove_dir_all(&aof.dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn automatic_rewrites() {
+        let mut aof = temp_aof("automatic", true);
+
+        aof.enable(Snapshot::default).unwrap();
+        aof.set_rewrite_growth(100, 200);
+
+        while aof.current_size() < 200 {
+            assert!(!aof.should_rewrite());
+            aof.feed(0, command(&["SET", "a", "1"]));
+            aof.flush();
+        }
+
+        assert!(aof.should_rewrite());
+
+        aof.set_rewrite_growth(0, 200);
+
+        assert!(!aof.should_rewrite());
+
+        fs::remove_dir_all(&aof.dir).unwrap();
+    }
+
+    #[test]
+    fn relative_times_are_made_absolute() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+        let expire = crate::commands::get_command("EXPIRE").unwrap();
+        let arguments = [
+            Value::BulkString(bulk_string!("a")),
+            Value::BulkString(bulk_string!("100")),
+        ];
+        let response = expire.command.execute(&mut data, &arguments);
+        let at = data.expires_at(&bulk_string!("a")).unwrap().to_string();
+
+        assert_eq!(
+            vec![command(&["PEXPIREAT", "a", &at])],
+            effects(&mut data, expire, &arguments, &response)
+        );
+
+        let arguments = [
+            Value::BulkString(bulk_string!("a")),
+            Value::BulkString(bulk_string!("-1")),
+        ];
+        let response = expire.command.execute(&mut data, &arguments);
+
+        assert_eq!(
+            vec![command(&["DEL", "a"])],
+            effects(&mut data, expire, &arguments, &response)
+        );
+        assert!(effects(&mut data, expire, &arguments, &Response::Integer(0)).is_empty());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::bulk_string::{parse as parse_bulk_string, read_crlf, read_length, BulkString};
+use crate::bulk_string::{
+    parse as parse_bulk_string, read_crlf, read_length, BulkString, MAX_BULK_STRING_LENGTH,
+};
 use crate::byte_reader::ByteReader;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -8,7 +10,7 @@ pub(crate) enum Array {
     Filled(Vec<Value>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) enum Value {
     BulkString(BulkString),
     // TODO: Other types
@@ -32,6 +34,67 @@ fn parse_value(reader: &mut ByteReader) -> Result<Value, ArrayFormatError> {
     }
 }
 
+/// Reads a line made of `prefix` followed by a length, or returns `None` if `reader` ends before the line does.
+fn read_header(reader: &mut ByteReader, prefix: u8) -> Result<Option<isize>, ArrayFormatError> {
+    match reader.read_byte() {
+        None => return Ok(None),
+        Some(byte) if byte != prefix => return Err(ArrayFormatError::Prefix),
+        Some(_) => {}
+    }
+
+    let digits = reader.read_while(|b| b != b'\r');
+
+    if reader.bytes_remaining() < 2 {
+        // A length has at most 20 characters, so anything longer isn't one.
+        return if digits.len() > 20 {
+            Err(ArrayFormatError::Length)
+        } else {
+            Ok(None)
+        };
+    }
+
+    if !read_crlf(reader) {
+        return Err(ArrayFormatError::LengthTrailer);
+    }
+
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .map(Some)
+        .ok_or(ArrayFormatError::Length)
+}
+
+/// The length of the array of bulk strings at the start of `data`, or `None` if `data` ends before it does. Commands
+/// can arrive split across reads, or several in one, so this finds where each ends before it's parsed.
+pub(crate) fn frame_length(data: &[u8]) -> Result<Option<usize>, ArrayFormatError> {
+    let mut reader = ByteReader::new(data);
+    let count = match read_header(&mut reader, b'*')? {
+        Some(count) if count < -1 => return Err(ArrayFormatError::Length),
+        Some(count) => count,
+        None => return Ok(None),
+    };
+
+    for _ in 0..count {
+        let length = match read_header(&mut reader, b'$')? {
+            Some(length) if !(-1..=MAX_BULK_STRING_LENGTH).contains(&length) => {
+                return Err(ArrayFormatError::Data)
+            }
+            Some(length) => length,
+            None => return Ok(None),
+        };
+
+        if length >= 0 {
+            if reader.bytes_remaining() < length as usize + 2 {
+                return Ok(None);
+            }
+
+            reader.slice(length as usize + 2);
+        }
+    }
+
+    Ok(Some(data.len() - reader.bytes_remaining()))
+}
+
 pub(crate) fn parse(data: &[u8]) -> Result<Array, ArrayFormatError> {
     let mut reader = ByteReader::new(data);
 
@@ -119,6 +182,30 @@ mod tests {
         todo!()
     }
 
+    #[test]
+    fn frame_lengths() {
+        let command = b"*2\r\n$3\r\nGET\r\n$1\r\na\r\n";
+
+        for end in 0..command.len() {
+            assert_eq!(Ok(None), frame_length(&command[..end]));
+        }
+
+        assert_eq!(Ok(Some(command.len())), frame_length(command));
+        assert_eq!(
+            Ok(Some(command.len())),
+            frame_length(&[&command[..], b"*1\r\n$4\r\nPING\r\n"].concat())
+        );
+        assert_eq!(Ok(Some(4)), frame_length(b"*0\r\n*0\r\n"));
+        assert_eq!(Ok(Some(9)), frame_length(b"*1\r\n$-1\r\n"));
+        assert_eq!(Err(ArrayFormatError::Prefix), frame_length(b"PING\r\n"));
+        assert_eq!(Err(ArrayFormatError::Length), frame_length(b"*-2\r\n"));
+        assert_eq!(Err(ArrayFormatError::Length), frame_length(b"*x\r\n"));
+        assert_eq!(
+            Err(ArrayFormatError::Data),
+            frame_length(b"*1\r\n$1000000000\r\n")
+        );
+    }
+
     #[test]
     fn parse_null_array() {
         assert_eq!(Ok(Array::Null), parse(b"*-1\r\n"));
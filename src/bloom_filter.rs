@@ -0,0 +1,279 @@
+use std::f64::consts::LN_2;
+
+use crate::murmur::murmur_hash_64a;
+use crate::rdb::{ModuleReader, ModuleWriter};
+
+pub(crate) const DEFAULT_ERROR_RATE: f64 = 0.01;
+pub(crate) const DEFAULT_CAPACITY: u64 = 100;
+pub(crate) const DEFAULT_EXPANSION: u64 = 2;
+pub(crate) const MAX_EXPANSION: u64 = 32768;
+
+/// The most memory a single layer may take, in bytes, so a huge capacity or a tiny error rate gets an error instead
+/// of an allocation that takes the server down.
+const MAX_LAYER_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Each new layer of a scalable filter gets a tighter error rate, so the compound error rate stays bounded.
+const ERROR_TIGHTENING_RATIO: f64 = 0.5;
+
+const HASH_SEED: u64 = 0xc6a4a7935bd1e995;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum BloomFilterError {
+    Full,
+    TooLarge,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Layer {
+    bits: Vec<u8>,
+    bit_count: u64,
+    hash_count: u32,
+    capacity: u64,
+    error_rate: f64,
+    items: u64,
+}
+
+impl Layer {
+    fn new(capacity: u64, error_rate: f64) -> Result<Layer, BloomFilterError> {
+        let bits_per_entry = -error_rate.ln() / (LN_2 * LN_2);
+        let bits = (capacity as f64 * bits_per_entry).ceil();
+
+        if bits > (MAX_LAYER_SIZE * 8) as f64 {
+            return Err(BloomFilterError::TooLarge);
+        }
+
+        let bit_count = (bits as u64).max(1);
+        let hash_count = ((LN_2 * bits_per_entry).ceil() as u32).max(1);
+
+        Ok(Layer {
+            bits: vec![0; bit_count.div_ceil(8) as usize],
+            bit_count,
+            hash_count,
+            capacity,
+            error_rate,
+            items: 0,
+        })
+    }
+
+    fn positions(&self, hashes: (u64, u64)) -> impl Iterator<Item = u64> + '_ {
+        let (a, b) = hashes;
+
+        (0..self.hash_count as u64).map(move |i| a.wrapping_add(i.wrapping_mul(b)) % self.bit_count)
+    }
+
+    fn contains(&self, hashes: (u64, u64)) -> bool {
+        self.positions(hashes)
+            .all(|position| self.bits[(position / 8) as usize] & (1 << (position % 8)) != 0)
+    }
+
+    /// Sets the bits for `hashes`, returning `false` if they were all set already.
+    fn insert(&mut self, hashes: (u64, u64)) -> bool {
+        let positions: Vec<u64> = self.positions(hashes).collect();
+        let mut changed = false;
+
+        for position in positions {
+            let byte = &mut self.bits[(position / 8) as usize];
+            let mask = 1 << (position % 8);
+
+            if *byte & mask == 0 {
+                *byte |= mask;
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.items += 1;
+        }
+
+        changed
+    }
+}
+
+/// A Bloom filter that grows by stacking additional layers once the current one reaches its capacity.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct ScalableBloomFilter {
+    layers: Vec<Layer>,
+    /// `None` for non-scaling filters.
+    expansion: Option<u64>,
+}
+
+impl ScalableBloomFilter {
+    pub(crate) fn new(
+        error_rate: f64,
+        capacity: u64,
+        expansion: Option<u64>,
+    ) -> Result<ScalableBloomFilter, BloomFilterError> {
+        Ok(ScalableBloomFilter {
+            layers: vec![Layer::new(capacity, error_rate)?],
+            expansion,
+        })
+    }
+
+    fn hashes(item: &[u8]) -> (u64, u64) {
+        let a = murmur_hash_64a(item, HASH_SEED);
+        let b = murmur_hash_64a(item, a);
+
+        (a, b)
+    }
+
+    pub(crate) fn contains(&self, item: &[u8]) -> bool {
+        let hashes = Self::hashes(item);
+
+        self.layers.iter().any(|layer| layer.contains(hashes))
+    }
+
+    /// Adds `item` to the filter, returning `Ok(false)` if it was (probably) present already.
+    pub(crate) fn insert(&mut self, item: &[u8]) -> Result<bool, BloomFilterError> {
+        let hashes = Self::hashes(item);
+
+        if self.layers.iter().any(|layer| layer.contains(hashes)) {
+            return Ok(false);
+        }
+
+        let last = self.layers.last().unwrap();
+
+        if last.items >= last.capacity {
+            let expansion = self.expansion.ok_or(BloomFilterError::Full)?;
+
+            let layer = Layer::new(
+                last.capacity.saturating_mul(expansion),
+                last.error_rate * ERROR_TIGHTENING_RATIO,
+            )?;
+
+            self.layers.push(layer);
+        }
+
+        Ok(self.layers.last_mut().unwrap().insert(hashes))
+    }
+
+    pub(crate) fn capacity(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.capacity).sum()
+    }
+
+    /// The approximate memory used by the filter, in bytes.
+    pub(crate) fn size(&self) -> u64 {
+        let bits: usize = self.layers.iter().map(|layer| layer.bits.len()).sum();
+
+        (std::mem::size_of::<ScalableBloomFilter>()
+            + self.layers.len() * std::mem::size_of::<Layer>()
+            + bits) as u64
+    }
+
+    pub(crate) fn filter_count(&self) -> u64 {
+        self.layers.len() as u64
+    }
+
+    pub(crate) fn items(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.items).sum()
+    }
+
+    pub(crate) fn expansion(&self) -> Option<u64> {
+        self.expansion
+    }
+
+    /// Saves the filter to an RDB file. An expansion of 0 stands for a non-scaling filter.
+    pub(crate) fn save(&self, writer: &mut ModuleWriter) {
+        writer.save_unsigned(self.expansion.unwrap_or(0));
+        writer.save_unsigned(self.layers.len() as u64);
+
+        for layer in &self.layers {
+            writer.save_unsigned(layer.bit_count);
+            writer.save_unsigned(layer.hash_count as u64);
+            writer.save_unsigned(layer.capacity);
+            writer.save_double(layer.error_rate);
+            writer.save_unsigned(layer.items);
+            writer.save_string(&layer.bits);
+        }
+    }
+
+    pub(crate) fn load(reader: &mut ModuleReader) -> Option<ScalableBloomFilter> {
+        let expansion = Some(reader.load_unsigned()?).filter(|&expansion| expansion > 0);
+        let layer_count = reader.load_unsigned()?;
+        let mut layers = Vec::new();
+
+        for _ in 0..layer_count {
+            let layer = Layer {
+                bit_count: reader.load_unsigned()?,
+                hash_count: u32::try_from(reader.load_unsigned()?).ok()?,
+                capacity: reader.load_unsigned()?,
+                error_rate: reader.load_double()?,
+                items: reader.load_unsigned()?,
+                bits: reader.load_string()?,
+            };
+
+            if layer.bit_count == 0 || layer.bits.len() as u64 != layer.bit_count.div_ceil(8) {
+                return None;
+            }
+
+            layers.push(layer);
+        }
+
+        if layers.is_empty() {
+            return None;
+        }
+
+        Some(ScalableBloomFilter { layers, expansion })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_contains() {
+        let mut filter = ScalableBloomFilter::new(0.01, 100, Some(2)).unwrap();
+
+        assert!(!filter.contains(b"foo"));
+        assert_eq!(Ok(true), filter.insert(b"foo"));
+        assert!(filter.contains(b"foo"));
+        assert_eq!(Ok(false), filter.insert(b"foo"));
+        assert_eq!(1, filter.items());
+    }
+
+    #[test]
+    fn scales_when_full() {
+        let mut filter = ScalableBloomFilter::new(0.01, 10, Some(2)).unwrap();
+
+        for i in 0..100 {
+            filter.insert(format!("item{i}").as_bytes()).unwrap();
+        }
+
+        assert!(filter.filter_count() > 1);
+        assert_eq!(10 + 20 + 40 + 80, filter.capacity());
+
+        for i in 0..100 {
+            assert!(filter.contains(format!("item{i}").as_bytes()));
+        }
+    }
+
+    #[test]
+    fn non_scaling_filter_fills_up() {
+        let mut filter = ScalableBloomFilter::new(0.01, 10, None).unwrap();
+
+        let result =
+            (0..100).try_for_each(|i| filter.insert(format!("item{i}").as_bytes()).map(|_| ()));
+
+        assert_eq!(Err(BloomFilterError::Full), result);
+        assert_eq!(1, filter.filter_count());
+    }
+
+    #[test]
+    fn refuses_huge_filters() {
+        assert_eq!(
+            Err(BloomFilterError::TooLarge),
+            ScalableBloomFilter::new(0.01, u64::MAX, Some(2))
+        );
+        assert_eq!(
+            Err(BloomFilterError::TooLarge),
+            ScalableBloomFilter::new(f64::MIN_POSITIVE, 10_000_000, Some(2))
+        );
+
+        let mut filter = ScalableBloomFilter::new(0.01, 1 << 20, Some(MAX_EXPANSION)).unwrap();
+
+        filter.layers[0].items = filter.layers[0].capacity;
+
+        assert_eq!(Err(BloomFilterError::TooLarge), filter.insert(b"foo"));
+        assert_eq!(1, filter.filter_count());
+    }
+}
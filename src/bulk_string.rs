@@ -2,9 +2,9 @@ use std::str;
 
 use crate::byte_reader::ByteReader;
 
-const MAX_BULK_STRING_LENGTH: isize = 512 * 1024 * 1024;
+pub(crate) const MAX_BULK_STRING_LENGTH: isize = 512 * 1024 * 1024;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub(crate) enum BulkString {
     Null,
     Empty,
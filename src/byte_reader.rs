@@ -56,4 +56,13 @@ impl<'a> ByteReader<'a> {
 
         s
     }
+
+    /// Like [`slice`](ByteReader::slice), but returns `None` instead of panicking if there aren't enough bytes left.
+    pub(crate) fn read_bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        if length > self.bytes_remaining() {
+            return None;
+        }
+
+        Some(self.slice(length))
+    }
 }
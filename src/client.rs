@@ -0,0 +1,1581 @@
+use std::collections::HashSet;
+use std::mem;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::cluster::{slot, Query};
+use crate::commands::{
+    bulk_string, bytes, get_command, keyword, number, CommandSpec, Migration, Response,
+};
+use crate::database::now_ms;
+use crate::pubsub::{ClientId, Kind};
+use crate::rdb;
+use crate::replication::Replica;
+use crate::scripting;
+use crate::Data;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The commands a connection can run while it's subscribed to channels or patterns.
+const SUBSCRIBED_COMMANDS: [&str; 9] = [
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "PING",
+    "QUIT",
+    "RESET",
+];
+
+/// The commands a sentinel runs. Like Redis Sentinel, it doesn't store data, so the others are unknown.
+const SENTINEL_COMMANDS: [&str; 10] = [
+    "PING",
+    "INFO",
+    "ROLE",
+    "SENTINEL",
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "QUIT",
+    "RESET",
+];
+
+/// Parses the name of a subscription command into what it subscribes to, and whether it subscribes or unsubscribes.
+fn subscription_command(name: &str) -> Option<(Kind, bool)> {
+    match name {
+        "SUBSCRIBE" => Some((Kind::Channel, true)),
+        "PSUBSCRIBE" => Some((Kind::Pattern, true)),
+        "SSUBSCRIBE" => Some((Kind::ShardChannel, true)),
+        "UNSUBSCRIBE" => Some((Kind::Channel, false)),
+        "PUNSUBSCRIBE" => Some((Kind::Pattern, false)),
+        "SUNSUBSCRIBE" => Some((Kind::ShardChannel, false)),
+        _ => None,
+    }
+}
+
+/// A command queued in a transaction.
+enum Queued {
+    Command(&'static CommandSpec, Vec<Value>),
+    /// `UNWATCH` has no effect in a transaction, because `EXEC` unwatches every key anyway, but it still gets a reply.
+    Unwatch,
+}
+
+/// The state of a connection.
+pub(crate) struct Client {
+    id: ClientId,
+    /// Delivers replies, and messages published by other connections, to the socket.
+    sender: Sender<Vec<u8>>,
+    /// Whether the connection should be closed once the pending replies are written.
+    closing: bool,
+    selected: usize,
+    /// The commands queued since `MULTI`, or `None` outside a transaction.
+    transaction: Option<Vec<Queued>>,
+    /// Whether a command couldn't be queued, in which case `EXEC` discards the transaction.
+    transaction_failed: bool,
+    /// The keys watched by `WATCH`, with their database and the version they had at the time.
+    watched: Vec<(usize, BulkString, u64)>,
+    channels: HashSet<BulkString>,
+    patterns: HashSet<BulkString>,
+    shard_channels: HashSet<BulkString>,
+    /// The socket, which is `None` for connections made by the server itself.
+    stream: Option<TcpStream>,
+    /// The port the connection listens on if it's a replica, as announced with `REPLCONF listening-port`.
+    listening_port: u16,
+    /// Whether the connection is a replica, which is sent the replication stream rather than replies.
+    replica: bool,
+    /// Whether the connection applies the replication stream of this server's primary, so it can write even though
+    /// replicas are read-only.
+    primary: bool,
+    /// The offset in the replication stream after the latest command this connection replicated, which `WAIT` waits
+    /// for replicas to acknowledge.
+    write_offset: u64,
+    /// Whether the connection sent `ASKING`, which lets its next command use a slot this node is importing.
+    asking: bool,
+    /// Whether the connection sent `READONLY`, which lets it read from a replica in a cluster.
+    read_only: bool,
+    /// Whether the server is a sentinel, which only runs [`SENTINEL_COMMANDS`].
+    sentinel: bool,
+}
+
+impl Client {
+    pub(crate) fn new(sender: Sender<Vec<u8>>) -> Client {
+        Client {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            sender,
+            closing: false,
+            selected: 0,
+            transaction: None,
+            transaction_failed: false,
+            watched: Vec::new(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+            stream: None,
+            listening_port: 0,
+            replica: false,
+            primary: false,
+            write_offset: 0,
+            asking: false,
+            read_only: false,
+            sentinel: false,
+        }
+    }
+
+    /// Creates the connection that applies the replication stream received from this server's primary.
+    pub(crate) fn for_primary(sender: Sender<Vec<u8>>) -> Client {
+        Client {
+            primary: true,
+            ..Client::new(sender)
+        }
+    }
+
+    /// Makes the connection one to a sentinel.
+    pub(crate) fn set_sentinel(&mut self) {
+        self.sentinel = true;
+    }
+
+    pub(crate) fn set_stream(&mut self, stream: TcpStream) {
+        self.stream = Some(stream);
+    }
+
+    /// Queues a reply to be written to the socket.
+    pub(crate) fn send(&self, response: Response) {
+        // The writer only stops once the socket fails, at which point the reply can't be delivered anyway.
+        let _ = self.sender.send(response.into());
+    }
+
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+
+    fn subscriptions_mut(&mut self, kind: Kind) -> &mut HashSet<BulkString> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    /// The number of subscriptions reported when subscribing or unsubscribing. Sharded channels are counted separately.
+    fn subscription_count(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Channel | Kind::Pattern => self.channels.len() + self.patterns.len(),
+            Kind::ShardChannel => self.shard_channels.len(),
+        }
+    }
+
+    /// Releases the resources the connection holds in `data`. Called when it's closed.
+    pub(crate) fn disconnect(&mut self, data: &Mutex<Data>) {
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        self.unwatch(&mut data);
+        self.unsubscribe_all(&mut data);
+
+        if self.replica {
+            data.replication_mut().remove_replica(self.id);
+        }
+    }
+
+    /// Runs the command `name` (uppercased) with `arguments`, or queues it if a transaction is open.
+    pub(crate) fn process(
+        &mut self,
+        data: &Mutex<Data>,
+        name: &str,
+        arguments: &[Value],
+    ) -> Response {
+        if let Some(response) = scripting::intercept(name, arguments) {
+            return response;
+        }
+
+        if self.sentinel && !SENTINEL_COMMANDS.contains(&name) {
+            return Response::Error("unknown command");
+        }
+
+        // `ASKING` only applies to the command right after it.
+        let asking = mem::take(&mut self.asking);
+
+        if self.is_subscribed() && !SUBSCRIBED_COMMANDS.contains(&name) {
+            return Response::Error(
+                "Can't execute command: only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            );
+        }
+
+        if let Some((kind, subscribe)) = subscription_command(name) {
+            return self.process_subscription(data, kind, subscribe, arguments);
+        }
+
+        match name {
+            "QUIT" => {
+                self.closing = true;
+
+                Response::SimpleString("OK")
+            }
+            "RESET" => {
+                self.reset(&mut data.lock().expect("failed to acquire lock"));
+
+                Response::SimpleString("RESET")
+            }
+            "PING" if self.is_subscribed() => match arguments {
+                [] => Response::Array(vec![bulk_string("pong"), bulk_string("")]),
+                [Value::BulkString(message)] => Response::Array(vec![
+                    bulk_string("pong"),
+                    Response::BulkString(message.clone()),
+                ]),
+                _ => Response::Error("wrong number of arguments"),
+            },
+            "MULTI" | "EXEC" | "DISCARD" | "UNWATCH" if !arguments.is_empty() => {
+                self.fail_transaction();
+
+                Response::Error("wrong number of arguments")
+            }
+            "MULTI" if self.transaction.is_some() => {
+                Response::Error("MULTI calls can not be nested")
+            }
+            "MULTI" => {
+                self.transaction = Some(Vec::new());
+                self.transaction_failed = false;
+
+                Response::SimpleString("OK")
+            }
+            "EXEC" => self.exec(data),
+            "DISCARD" => match self.transaction.take() {
+                Some(_) => {
+                    self.unwatch(&mut data.lock().expect("failed to acquire lock"));
+
+                    Response::SimpleString("OK")
+                }
+                None => Response::Error("DISCARD without MULTI"),
+            },
+            "WATCH" if arguments.is_empty() => {
+                self.fail_transaction();
+
+                Response::Error("wrong number of arguments")
+            }
+            "WATCH" if self.transaction.is_some() => {
+                self.fail_transaction();
+
+                Response::Error("WATCH inside MULTI is not allowed")
+            }
+            "WATCH" => {
+                let mut data = data.lock().expect("failed to acquire lock");
+                let keys: Vec<&BulkString> =
+                    arguments.iter().map(|Value::BulkString(key)| key).collect();
+
+                match self.redirect(&mut data, &keys, false, asking) {
+                    Some(response) => response,
+                    None => self.watch(&mut data, arguments),
+                }
+            }
+            "ASKING" | "READONLY" | "READWRITE" if !arguments.is_empty() => {
+                Response::Error("wrong number of arguments")
+            }
+            "ASKING" | "READONLY" | "READWRITE" => {
+                if !data
+                    .lock()
+                    .expect("failed to acquire lock")
+                    .cluster()
+                    .is_enabled()
+                {
+                    return Response::Error("This instance has cluster support disabled");
+                }
+
+                match name {
+                    "ASKING" => self.asking = true,
+                    "READONLY" => self.read_only = true,
+                    _ => self.read_only = false,
+                }
+
+                Response::SimpleString("OK")
+            }
+            "SYNC" | "PSYNC" | "REPLCONF" | "WAIT" | "WAITAOF" if self.transaction.is_some() => {
+                self.fail_transaction();
+
+                Response::Error("Command not allowed inside a transaction")
+            }
+            "SYNC" if !arguments.is_empty() => Response::Error("wrong number of arguments"),
+            "PSYNC" if arguments.len() != 2 => Response::Error("wrong number of arguments"),
+            "SYNC" | "PSYNC" => self.sync(data, name == "PSYNC", arguments),
+            "REPLCONF" => {
+                self.replconf(&mut data.lock().expect("failed to acquire lock"), arguments)
+            }
+            "WAIT" if arguments.len() != 2 => Response::Error("wrong number of arguments"),
+            "WAITAOF" if arguments.len() != 3 => Response::Error("wrong number of arguments"),
+            "WAIT" => wait(data, self.write_offset, arguments),
+            "WAITAOF" => wait_aof(data, self.write_offset, arguments),
+            "UNWATCH" => match &mut self.transaction {
+                Some(queue) => {
+                    queue.push(Queued::Unwatch);
+
+                    Response::SimpleString("QUEUED")
+                }
+                None => {
+                    self.unwatch(&mut data.lock().expect("failed to acquire lock"));
+
+                    Response::SimpleString("OK")
+                }
+            },
+            _ => {
+                let spec = match get_command(name) {
+                    Some(spec) if spec.accepts(arguments.len()) => spec,
+                    Some(_) => {
+                        self.fail_transaction();
+
+                        return Response::Error("wrong number of arguments");
+                    }
+                    None => {
+                        self.fail_transaction();
+
+                        return Response::Error("unknown command");
+                    }
+                };
+
+                let redirect = {
+                    let mut data = data.lock().expect("failed to acquire lock");
+                    let mut keys = spec.keys(arguments);
+                    let mut write = spec.is_write();
+
+                    // A transaction runs on a single node, so its commands are routed together.
+                    if let Some(queue) = &self.transaction {
+                        let (queued_keys, queued_write) = queued_keys(queue);
+
+                        keys.extend(queued_keys);
+                        write |= queued_write;
+                    }
+
+                    self.redirect(&mut data, &keys, write, asking || spec.is_asking())
+                };
+
+                if let Some(response) = redirect {
+                    self.fail_transaction();
+
+                    return response;
+                }
+
+                if spec.is_write()
+                    && !self.primary
+                    && data
+                        .lock()
+                        .expect("failed to acquire lock")
+                        .replication()
+                        .refuses_writes()
+                {
+                    self.fail_transaction();
+
+                    return Response::Error(
+                        "READONLY You can't write against a read only replica.",
+                    );
+                }
+
+                if let Some(queue) = &mut self.transaction {
+                    queue.push(Queued::Command(spec, arguments.to_vec()));
+
+                    return Response::SimpleString("QUEUED");
+                }
+
+                if spec.name == "MIGRATE" {
+                    return self.migrate(data, arguments);
+                }
+
+                let mut data = data.lock().expect("failed to acquire lock");
+                let response = self.execute(&mut data, spec, arguments);
+
+                self.flush_propagated(&mut data);
+
+                response
+            }
+        }
+    }
+
+    fn process_subscription(
+        &mut self,
+        data: &Mutex<Data>,
+        kind: Kind,
+        subscribe: bool,
+        arguments: &[Value],
+    ) -> Response {
+        if self.transaction.is_some() {
+            self.fail_transaction();
+
+            return Response::Error("Command not allowed inside a transaction");
+        }
+
+        if subscribe && arguments.is_empty() {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let names: Vec<BulkString> = arguments
+            .iter()
+            .map(|name| {
+                let Value::BulkString(name) = name;
+
+                name.clone()
+            })
+            .collect();
+
+        // Sharded channels are routed by hash slot, so a single command can only name channels in the same slot.
+        if kind == Kind::ShardChannel
+            && names
+                .windows(2)
+                .any(|pair| slot(&pair[0]) != slot(&pair[1]))
+        {
+            return Response::Error("CROSSSLOT Keys in request don't hash to the same slot");
+        }
+
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        if kind == Kind::ShardChannel && subscribe && data.cluster().is_enabled() {
+            let query = Query {
+                slot: slot(&names[0]),
+                keys: names.len(),
+                read_only: true,
+                ..Query::default()
+            };
+
+            if let Some(response) = data.cluster().redirect(query) {
+                return response;
+            }
+        }
+
+        match subscribe {
+            true => self.subscribe(&mut data, kind, names),
+            false => self.unsubscribe(&mut data, kind, names),
+        }
+    }
+
+    /// Subscribes to `names`, replying with the number of subscriptions after each.
+    fn subscribe(&mut self, data: &mut Data, kind: Kind, names: Vec<BulkString>) -> Response {
+        let mut replies = Vec::new();
+
+        for name in names {
+            if self.subscriptions_mut(kind).insert(name.clone()) {
+                data.subscriptions_mut()
+                    .subscribe(kind, &name, self.id, &self.sender);
+            }
+
+            replies.push(self.subscription_reply(kind.subscribe_reply(), kind, name));
+        }
+
+        Response::Sequence(replies)
+    }
+
+    /// Unsubscribes from `names`, or from everything of the given kind if there are none.
+    fn unsubscribe(&mut self, data: &mut Data, kind: Kind, names: Vec<BulkString>) -> Response {
+        let names: Vec<BulkString> = match names.is_empty() {
+            true => self.subscriptions_mut(kind).iter().cloned().collect(),
+            false => names,
+        };
+
+        if names.is_empty() {
+            return self.subscription_reply(kind.unsubscribe_reply(), kind, BulkString::Null);
+        }
+
+        let mut replies = Vec::new();
+
+        for name in names {
+            if self.subscriptions_mut(kind).remove(&name) {
+                data.subscriptions_mut().unsubscribe(kind, &name, self.id);
+            }
+
+            replies.push(self.subscription_reply(kind.unsubscribe_reply(), kind, name));
+        }
+
+        Response::Sequence(replies)
+    }
+
+    fn subscription_reply(&self, reply: &'static str, kind: Kind, name: BulkString) -> Response {
+        Response::Array(vec![
+            bulk_string(reply),
+            Response::BulkString(name),
+            Response::Integer(self.subscription_count(kind) as i64),
+        ])
+    }
+
+    fn unsubscribe_all(&mut self, data: &mut Data) {
+        for kind in [Kind::Channel, Kind::Pattern, Kind::ShardChannel] {
+            for name in self.subscriptions_mut(kind).drain().collect::<Vec<_>>() {
+                data.subscriptions_mut().unsubscribe(kind, &name, self.id);
+            }
+        }
+    }
+
+    /// Puts the connection back in its initial state.
+    fn reset(&mut self, data: &mut Data) {
+        self.transaction = None;
+        self.transaction_failed = false;
+        self.selected = 0;
+        self.read_only = false;
+        self.unwatch(data);
+        self.unsubscribe_all(data);
+    }
+
+    /// Marks the open transaction, if any, as failed.
+    fn fail_transaction(&mut self) {
+        if self.transaction.is_some() {
+            self.transaction_failed = true;
+        }
+    }
+
+    /// Checks that this node serves `keys` in cluster mode, or returns the error that redirects the client to the node
+    /// that does. `write` tells whether the command writes, which only the primary serving the keys can do.
+    fn redirect(
+        &self,
+        data: &mut Data,
+        keys: &[&BulkString],
+        write: bool,
+        asking: bool,
+    ) -> Option<Response> {
+        if self.primary || !data.cluster().is_enabled() || keys.is_empty() {
+            return None;
+        }
+
+        let first = slot(keys[0]);
+
+        if keys.iter().any(|key| slot(key) != first) {
+            return Some(Response::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot",
+            ));
+        }
+
+        let database = data.database_mut(self.selected);
+        let missing = keys
+            .iter()
+            .filter(|key| !database.contains_key(key))
+            .count();
+
+        data.cluster().redirect(Query {
+            slot: first,
+            write,
+            asking,
+            read_only: self.read_only,
+            keys: keys.len(),
+            missing,
+        })
+    }
+
+    fn watch(&mut self, data: &mut Data, keys: &[Value]) -> Response {
+        for key in keys {
+            let key = match key {
+                Value::BulkString(key @ BulkString::Filled(_)) => key,
+                _ => return Response::Error("invalid argument"),
+            };
+
+            if self
+                .watched
+                .iter()
+                .any(|(database, watched, _)| *database == self.selected && watched == key)
+            {
+                continue;
+            }
+
+            let version = data.database_mut(self.selected).watch(key);
+
+            self.watched.push((self.selected, key.clone(), version));
+        }
+
+        Response::SimpleString("OK")
+    }
+
+    fn unwatch(&mut self, data: &mut Data) {
+        for (database, key, _) in self.watched.drain(..) {
+            data.database_mut(database).unwatch(&key);
+        }
+    }
+
+    /// Whether any watched key was modified since it was watched.
+    fn watched_keys_modified(&self, data: &mut Data) -> bool {
+        self.watched.iter().any(|(database, key, version)| {
+            data.database_mut(*database).watched_version(key) != Some(*version)
+        })
+    }
+
+    /// Makes the connection a replica, which is sent the replication stream. With `PSYNC`, a replica that has part of
+    /// the stream is sent what it missed if the backlog still has it. Otherwise, it's sent a snapshot of the data
+    /// first.
+    fn sync(&mut self, shared: &Mutex<Data>, psync: bool, arguments: &[Value]) -> Response {
+        let mut data = shared.lock().expect("failed to acquire lock");
+
+        if self.replica {
+            return Response::Sequence(Vec::new());
+        }
+
+        if !data.replication().can_sync() {
+            return Response::Error("NOMASTERLINK Can't SYNC while not connected with my master");
+        }
+
+        let replica = Replica::new(
+            self.id,
+            self.sender.clone(),
+            self.stream
+                .as_ref()
+                .and_then(|stream| stream.try_clone().ok()),
+            self.listening_port,
+        );
+
+        self.replica = true;
+
+        // The offset asked for is that of the first byte that's missing, so it's one more than what the replica has.
+        if let [id, offset] = arguments {
+            let id = String::from_utf8_lossy(bytes(id).unwrap_or_default());
+            let missed = number::<u64>(offset)
+                .filter(|&offset| offset > 0)
+                .and_then(|offset| data.replication().continuation(&id, offset - 1));
+
+            if let Some(missed) = missed {
+                let replication = data.replication_mut();
+                let mut bytes = format!("+CONTINUE {}\r\n", replication.id()).into_bytes();
+
+                println!(
+                    "Partial resynchronization request from {} accepted, sending {} bytes of backlog",
+                    replica.address(),
+                    missed.len()
+                );
+                bytes.extend(missed);
+                let _ = self.sender.send(bytes);
+                replication.add_replica(replica);
+
+                return Response::Sequence(Vec::new());
+            }
+        }
+
+        println!(
+            "Full resync requested by replica {}:{}",
+            replica.address(),
+            replica.port()
+        );
+
+        // Encoding a large snapshot takes a while, so it's done without the lock, like BGSAVE does. What's written in
+        // the meantime is held back until the snapshot is sent, since the replica applies the stream on top of it.
+        let snapshot = data.snapshot();
+        let replication = data.replication_mut();
+        let mut bytes = Vec::new();
+
+        if psync {
+            bytes.extend(
+                format!(
+                    "+FULLRESYNC {} {}\r\n",
+                    replication.id(),
+                    replication.offset()
+                )
+                .as_bytes(),
+            );
+        }
+
+        let (held, held_back) = mpsc::channel();
+
+        replication.add_replica(replica);
+        replication.set_replica_sender(self.id, held);
+        drop(data);
+
+        let payload = rdb::encode(&snapshot);
+
+        // Unlike a bulk string reply, the snapshot isn't followed by a line ending.
+        bytes.extend(format!("${}\r\n", payload.len()).as_bytes());
+        bytes.extend(payload);
+        let _ = self.sender.send(bytes);
+
+        let mut data = shared.lock().expect("failed to acquire lock");
+
+        for bytes in held_back.try_iter() {
+            let _ = self.sender.send(bytes);
+        }
+
+        data.replication_mut()
+            .set_replica_sender(self.id, self.sender.clone());
+
+        // The replies were sent already, so that nothing in the stream can come before them.
+        Response::Sequence(Vec::new())
+    }
+
+    /// Handles the options a replica sets, and the acknowledgements it sends, which get no reply.
+    fn replconf(&mut self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+            return Response::Error("syntax error");
+        }
+
+        let mut ack = None;
+        let mut aof_ack = None;
+
+        for pair in arguments.chunks_exact(2) {
+            match keyword(&pair[0]).as_deref() {
+                Some("LISTENING-PORT") => match number(&pair[1]) {
+                    Some(port) => self.listening_port = port,
+                    None => return Response::Error("value is not an integer or out of range"),
+                },
+                Some("ACK") => ack = number(&pair[1]),
+                Some("FACK") => aof_ack = number(&pair[1]),
+                Some("GETACK") => return Response::Sequence(Vec::new()),
+                Some("CAPA" | "IP-ADDRESS") => {}
+                _ => {
+                    return Response::OwnedError(format!(
+                        "Unrecognized REPLCONF option: {}",
+                        String::from_utf8_lossy(bytes(&pair[0]).unwrap_or_default())
+                    ))
+                }
+            }
+        }
+
+        if let Some(offset) = ack {
+            data.replication_mut().acknowledge(self.id, offset, aof_ack);
+
+            return Response::Sequence(Vec::new());
+        }
+
+        Response::SimpleString("OK")
+    }
+
+    fn execute(&mut self, data: &mut Data, spec: &CommandSpec, arguments: &[Value]) -> Response {
+        data.select(self.selected);
+
+        let response = spec.command.execute(data, arguments);
+
+        data.propagate(spec, arguments, &response);
+        self.selected = data.selected();
+        data.publish_events();
+
+        response
+    }
+
+    /// Propagates what the commands run replicated, recording where it ends in the replication stream.
+    fn flush_propagated(&mut self, data: &mut Data) {
+        let offset = data.replication().offset();
+
+        data.flush_propagated();
+
+        if data.replication().offset() != offset {
+            self.write_offset = data.replication().offset();
+        }
+    }
+
+    /// Runs `MIGRATE` without holding the lock while the keys are sent, so that other connections aren't blocked
+    /// meanwhile. See [`Migration`].
+    fn migrate(&mut self, data: &Mutex<Data>, arguments: &[Value]) -> Response {
+        let migration = {
+            let mut data = data.lock().expect("failed to acquire lock");
+
+            data.select(self.selected);
+
+            match Migration::prepare(&mut data, arguments) {
+                Ok(migration) => migration,
+                Err(response) => return response,
+            }
+        };
+        let replies = migration.transfer();
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        data.select(self.selected);
+
+        let (response, deleted) = migration.finish(&mut data, replies);
+
+        if !deleted.is_empty() {
+            let del = get_command("DEL").expect("DEL is a command");
+            let keys: Vec<Value> = deleted.into_iter().map(Value::BulkString).collect();
+
+            data.propagate(del, &keys, &Response::Integer(keys.len() as i64));
+        }
+
+        self.flush_propagated(&mut data);
+
+        response
+    }
+
+    /// Runs the queued commands under a single acquisition of the lock, so no other connection sees them half done.
+    fn exec(&mut self, data: &Mutex<Data>) -> Response {
+        let queue = match self.transaction.take() {
+            Some(queue) => queue,
+            None => return Response::Error("EXEC without MULTI"),
+        };
+
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        if self.transaction_failed {
+            self.unwatch(&mut data);
+
+            return Response::Error("EXECABORT Transaction discarded because of previous errors.");
+        }
+
+        // The slots may have moved since the commands were queued.
+        let (keys, write) = queued_keys(&queue);
+
+        if let Some(response) = self.redirect(&mut data, &keys, write, false) {
+            self.unwatch(&mut data);
+
+            return response;
+        }
+
+        let modified = self.watched_keys_modified(&mut data);
+
+        self.unwatch(&mut data);
+
+        if modified {
+            return Response::NullArray;
+        }
+
+        let replies = queue
+            .iter()
+            .map(|queued| match queued {
+                Queued::Command(spec, arguments) => self.execute(&mut data, spec, arguments),
+                Queued::Unwatch => Response::SimpleString("OK"),
+            })
+            .collect();
+
+        self.flush_propagated(&mut data);
+
+        Response::Array(replies)
+    }
+}
+
+/// The keys of the commands queued in a transaction, and whether any of them writes.
+fn queued_keys(queue: &[Queued]) -> (Vec<&BulkString>, bool) {
+    let mut keys = Vec::new();
+    let mut write = false;
+
+    for queued in queue {
+        if let Queued::Command(spec, arguments) = queued {
+            keys.extend(spec.keys(arguments));
+            write |= spec.is_write();
+        }
+    }
+
+    (keys, write)
+}
+
+/// How often a blocked `WAIT` or `WAITAOF` checks whether it's done.
+const WAIT_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// Parses the number of acknowledgements to wait for, and the timeout in milliseconds, where 0 means forever.
+fn wait_arguments(count: &Value, timeout: &Value) -> Result<(usize, u64), Response> {
+    let count =
+        number::<i64>(count).ok_or(Response::Error("value is not an integer or out of range"))?;
+    let timeout = number::<i64>(timeout)
+        .ok_or(Response::Error("timeout is not an integer or out of range"))?;
+
+    if timeout < 0 {
+        return Err(Response::Error("timeout is negative"));
+    }
+
+    Ok((count.max(0) as usize, timeout as u64))
+}
+
+/// Checks `done` until it returns `true`, or `timeout` milliseconds pass, and returns `result` as of the last check.
+/// The lock is only held for each check, so other connections aren't blocked while this one waits.
+fn block_until<T>(
+    data: &Mutex<Data>,
+    timeout: u64,
+    mut check: impl FnMut(&mut Data) -> (bool, T),
+) -> T {
+    let deadline = (timeout > 0).then(|| now_ms() + timeout);
+
+    loop {
+        let (done, result) = check(&mut data.lock().expect("failed to acquire lock"));
+
+        if done || deadline.is_some_and(|deadline| now_ms() >= deadline) {
+            return result;
+        }
+
+        thread::sleep(WAIT_POLL_PERIOD);
+    }
+}
+
+/// `WAIT numreplicas timeout`: blocks until `numreplicas` replicas acknowledge the replication stream up to `offset`,
+/// where the connection's latest write ends, and returns how many did.
+fn wait(data: &Mutex<Data>, offset: u64, arguments: &[Value]) -> Response {
+    let (replicas, timeout) = match wait_arguments(&arguments[0], &arguments[1]) {
+        Ok(arguments) => arguments,
+        Err(response) => return response,
+    };
+
+    {
+        let mut data = data.lock().expect("failed to acquire lock");
+        let replication = data.replication_mut();
+
+        if replication.primary().is_some() {
+            return Response::Error("WAIT cannot be used with replica instances. Please also note that since Redis 4.0 if a replica is configured to be writable (which is not the default) writes to replicas are just local and are not propagated.");
+        }
+
+        if replication.acknowledged(offset, false) < replicas {
+            replication.request_acks();
+        }
+    }
+
+    let acknowledged = block_until(data, timeout, |data| {
+        let acknowledged = data.replication().acknowledged(offset, false);
+
+        (acknowledged >= replicas, acknowledged)
+    });
+
+    Response::Integer(acknowledged as i64)
+}
+
+/// `WAITAOF numlocal numreplicas timeout`: blocks until everything written so far is in the append-only file on disk,
+/// if `numlocal` is set, and the replication stream up to `offset` is in that of `numreplicas` replicas. Returns
+/// whether it's on disk locally, and on how many replicas.
+fn wait_aof(data: &Mutex<Data>, offset: u64, arguments: &[Value]) -> Response {
+    let Some(local) = number::<i64>(&arguments[0]) else {
+        return Response::Error("value is not an integer or out of range");
+    };
+    let (replicas, timeout) = match wait_arguments(&arguments[1], &arguments[2]) {
+        Ok(arguments) => arguments,
+        Err(response) => return response,
+    };
+
+    let written = {
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        if data.replication().primary().is_some() {
+            return Response::Error("WAITAOF cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.");
+        }
+
+        if local > 0 && !data.aof().is_enabled() {
+            return Response::Error(
+                "WAITAOF cannot be used when numlocal is set but appendonly is disabled.",
+            );
+        }
+
+        let written = data.aof().written();
+        let replication = data.replication_mut();
+
+        if replication.acknowledged(offset, true) < replicas {
+            replication.request_acks();
+        }
+
+        written
+    };
+
+    let (local, replicas) = block_until(data, timeout, |data| {
+        let synced = data.aof().is_enabled() && data.aof().synced() >= written;
+        let acknowledged = data.replication().acknowledged(offset, true);
+
+        (
+            (synced || local <= 0) && acknowledged >= replicas,
+            (synced, acknowledged),
+        )
+    });
+
+    Response::Array(vec![
+        Response::Integer(local as i64),
+        Response::Integer(replicas as i64),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::commands::WRONG_TYPE;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    fn new_client() -> Client {
+        Client::new(mpsc::channel().0)
+    }
+
+    #[test]
+    fn transaction() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(&data, "MULTI", &[])
+        );
+        assert_eq!(
+            Response::SimpleString("QUEUED"),
+            client.process(&data, "SET", arguments!["a", "1"])
+        );
+        assert_eq!(
+            Response::SimpleString("QUEUED"),
+            client.process(&data, "GET", arguments!["a"])
+        );
+        assert_eq!(
+            Response::Error("MULTI calls can not be nested"),
+            client.process(&data, "MULTI", &[])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                Response::SimpleString("OK"),
+                Response::BulkString(BulkString::Filled(b"1".to_vec())),
+            ]),
+            client.process(&data, "EXEC", &[])
+        );
+        assert_eq!(
+            Response::Error("EXEC without MULTI"),
+            client.process(&data, "EXEC", &[])
+        );
+    }
+
+    #[test]
+    fn queueing_errors_abort_the_transaction() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+
+        client.process(&data, "MULTI", &[]);
+        client.process(&data, "SET", arguments!["a", "1"]);
+
+        assert_eq!(
+            Response::Error("wrong number of arguments"),
+            client.process(&data, "GET", &[])
+        );
+        assert_eq!(
+            Response::Error("EXECABORT Transaction discarded because of previous errors."),
+            client.process(&data, "EXEC", &[])
+        );
+        assert!(data.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn watch() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+        let mut other = new_client();
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(&data, "WATCH", arguments!["a", "b"])
+        );
+
+        other.process(&data, "SET", arguments!["b", "1"]);
+        client.process(&data, "MULTI", &[]);
+        client.process(&data, "SET", arguments!["a", "1"]);
+
+        assert_eq!(Response::NullArray, client.process(&data, "EXEC", &[]));
+        assert!(!data.lock().unwrap().contains_key(&bulk_string!("a")));
+
+        // The keys aren't watched anymore, so this transaction goes through.
+        other.process(&data, "SET", arguments!["b", "2"]);
+        client.process(&data, "MULTI", &[]);
+        client.process(&data, "SET", arguments!["a", "1"]);
+
+        assert_eq!(
+            Response::Array(vec![Response::SimpleString("OK")]),
+            client.process(&data, "EXEC", &[])
+        );
+    }
+
+    #[test]
+    fn flush_and_expiry_modify_watched_keys() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+        let mut other = new_client();
+
+        other.process(&data, "SET", arguments!["a", "1"]);
+        client.process(&data, "WATCH", arguments!["a"]);
+        other.process(&data, "FLUSHDB", &[]);
+        client.process(&data, "MULTI", &[]);
+
+        assert_eq!(Response::NullArray, client.process(&data, "EXEC", &[]));
+
+        other.process(&data, "SET", arguments!["a", "1"]);
+        other.process(&data, "PEXPIRE", arguments!["a", "1"]);
+        client.process(&data, "WATCH", arguments!["a"]);
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        client.process(&data, "MULTI", &[]);
+
+        assert_eq!(Response::NullArray, client.process(&data, "EXEC", &[]));
+
+        // Unrelated changes, and flushing a database without the key, don't count.
+        client.process(&data, "WATCH", arguments!["a"]);
+        other.process(&data, "SET", arguments!["b", "1"]);
+        other.process(&data, "FLUSHDB", &[]);
+        client.process(&data, "MULTI", &[]);
+
+        assert_eq!(
+            Response::Array(Vec::new()),
+            client.process(&data, "EXEC", &[])
+        );
+    }
+
+    #[test]
+    fn failed_writes_dont_modify_watched_keys() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+        let mut other = new_client();
+
+        other.process(&data, "SET", arguments!["a", "1"]);
+        other.process(&data, "BF.ADD", arguments!["b", "x"]);
+        client.process(&data, "WATCH", arguments!["a", "b"]);
+
+        let changes = data.lock().unwrap().changes();
+
+        assert_eq!(
+            Response::Error(WRONG_TYPE),
+            other.process(&data, "CF.ADD", arguments!["a", "x"])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            other.process(&data, "BF.ADD", arguments!["b", "x"])
+        );
+        assert_eq!(changes, data.lock().unwrap().changes());
+
+        client.process(&data, "MULTI", &[]);
+
+        assert_eq!(
+            Response::Array(Vec::new()),
+            client.process(&data, "EXEC", &[])
+        );
+    }
+
+    #[test]
+    fn watch_inside_multi() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+
+        client.process(&data, "MULTI", &[]);
+
+        assert_eq!(
+            Response::Error("WATCH inside MULTI is not allowed"),
+            client.process(&data, "WATCH", arguments!["a"])
+        );
+        assert_eq!(
+            Response::SimpleString("QUEUED"),
+            client.process(&data, "UNWATCH", &[])
+        );
+    }
+
+    #[test]
+    fn discard() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+
+        client.process(&data, "MULTI", &[]);
+        client.process(&data, "SELECT", arguments!["1"]);
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(&data, "DISCARD", &[])
+        );
+        assert_eq!(
+            Response::Error("DISCARD without MULTI"),
+            client.process(&data, "DISCARD", &[])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(&data, "SET", arguments!["a", "1"])
+        );
+        assert_eq!(1, data.lock().unwrap().database_mut(0).len());
+    }
+
+    #[test]
+    fn wait() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+
+        assert_eq!(
+            Response::Integer(0),
+            client.process(&data, "WAIT", arguments!["0", "0"])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            client.process(&data, "WAIT", arguments!["1", "10"])
+        );
+        assert_eq!(
+            Response::Error("timeout is negative"),
+            client.process(&data, "WAIT", arguments!["1", "-1"])
+        );
+        assert_eq!(
+            Response::Error("value is not an integer or out of range"),
+            client.process(&data, "WAIT", arguments!["one", "0"])
+        );
+
+        // The replica is dropped once its connection closes, which is when the receiver is.
+        let (sender, _receiver) = mpsc::channel();
+        let mut replica = Client::new(sender);
+
+        replica.process(&data, "PSYNC", arguments!["?", "-1"]);
+        client.process(&data, "SET", arguments!["a", "1"]);
+
+        let offset = data.lock().unwrap().replication().offset().to_string();
+
+        // Waiting asks the replica to acknowledge the stream, which it hasn't yet.
+        assert_eq!(
+            Response::Integer(0),
+            client.process(&data, "WAIT", arguments!["1", "10"])
+        );
+        assert_eq!(
+            Response::Sequence(Vec::new()),
+            replica.process(&data, "REPLCONF", arguments!["ACK", &offset])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            client.process(&data, "WAIT", arguments!["1", "0"])
+        );
+
+        // Writes to the append-only file on disk are acknowledged separately.
+        assert_eq!(
+            Response::Array(vec![Response::Integer(0), Response::Integer(0)]),
+            client.process(&data, "WAITAOF", arguments!["0", "1", "10"])
+        );
+        replica.process(
+            &data,
+            "REPLCONF",
+            arguments!["ACK", &offset, "FACK", &offset],
+        );
+        assert_eq!(
+            Response::Array(vec![Response::Integer(0), Response::Integer(1)]),
+            client.process(&data, "WAITAOF", arguments!["0", "1", "0"])
+        );
+        assert_eq!(
+            Response::Error(
+                "WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+            ),
+            client.process(&data, "WAITAOF", arguments!["1", "0", "0"])
+        );
+
+        client.process(&data, "MULTI", &[]);
+        assert_eq!(
+            Response::Error("Command not allowed inside a transaction"),
+            client.process(&data, "WAIT", arguments!["0", "0"])
+        );
+    }
+
+    #[test]
+    fn full_resync() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+        let (sender, receiver) = mpsc::channel();
+        let mut replica = Client::new(sender);
+
+        client.process(&data, "SET", arguments!["a", "1"]);
+        replica.process(&data, "PSYNC", arguments!["?", "-1"]);
+        client.process(&data, "SET", arguments!["b", "2"]);
+
+        // The snapshot comes first, then the stream from where it was taken.
+        let sent: Vec<Vec<u8>> = receiver.try_iter().collect();
+
+        assert_eq!(2, sent.len());
+        assert!(sent[0].starts_with(b"+FULLRESYNC "));
+        assert!(sent[1].ends_with(b"*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n"));
+
+        // Writes go straight to the replica once it has the snapshot.
+        client.process(&data, "SET", arguments!["c", "3"]);
+
+        assert_eq!(
+            vec![b"*3\r\n$3\r\nSET\r\n$1\r\nc\r\n$1\r\n3\r\n".to_vec()],
+            receiver.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn expiry_is_propagated() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+        let (sender, receiver) = mpsc::channel();
+        let mut replica = Client::new(sender);
+
+        replica.process(&data, "PSYNC", arguments!["?", "-1"]);
+        receiver.try_iter().for_each(drop);
+        client.process(&data, "SET", arguments!["a", "1"]);
+        client.process(&data, "SET", arguments!["b", "2"]);
+        client.process(&data, "PEXPIRE", arguments!["a", "1"]);
+        client.process(&data, "PEXPIRE", arguments!["b", "1"]);
+        receiver.try_iter().for_each(drop);
+        thread::sleep(Duration::from_millis(5));
+
+        // Looking up an expired key deletes it.
+        assert_eq!(
+            Response::BulkString(BulkString::Null),
+            client.process(&data, "GET", arguments!["a"])
+        );
+        assert_eq!(
+            vec![b"*2\r\n$3\r\nDEL\r\n$1\r\na\r\n".to_vec()],
+            receiver.try_iter().collect::<Vec<_>>()
+        );
+
+        // So does the active expiry cycle.
+        data.lock().unwrap().active_expire_cycle();
+
+        assert_eq!(
+            vec![b"*2\r\n$3\r\nDEL\r\n$1\r\nb\r\n".to_vec()],
+            receiver.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn cluster_redirections() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+        let path = env::temp_dir().join(format!("red-{}-client-cluster.conf", process::id()));
+
+        fs::write(
+            &path,
+            "aaaa 127.0.0.1:7001@17001 myself,master - 0 0 1 connected 0-8191 [15495-<-bbbb]\n\
+            bbbb 127.0.0.1:7002@17002 master - 0 0 2 connected 8192-16383\n",
+        )
+        .unwrap();
+        data.lock()
+            .unwrap()
+            .cluster_mut()
+            .enable(path, 7001)
+            .unwrap();
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(&data, "SET", arguments!["b", "1"])
+        );
+        assert_eq!(
+            Response::OwnedError("MOVED 12182 127.0.0.1:7002".to_string()),
+            client.process(&data, "GET", arguments!["foo"])
+        );
+        assert_eq!(
+            Response::Error("CROSSSLOT Keys in request don't hash to the same slot"),
+            client.process(&data, "DEL", arguments!["b", "bar"])
+        );
+        assert_eq!(
+            Response::OwnedError("MOVED 15495 127.0.0.1:7002".to_string()),
+            client.process(&data, "GET", arguments!["a"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(&data, "ASKING", &[])
+        );
+        assert_eq!(
+            Response::BulkString(BulkString::Null),
+            client.process(&data, "GET", arguments!["a"])
+        );
+        // ASKING only applies to the next command.
+        assert_eq!(
+            Response::OwnedError("MOVED 15495 127.0.0.1:7002".to_string()),
+            client.process(&data, "GET", arguments!["a"])
+        );
+        // RESTORE-ASKING is always treated as if it followed ASKING, so it reaches the command.
+        assert_eq!(
+            Response::Error("DUMP payload version or checksum are wrong"),
+            client.process(&data, "RESTORE-ASKING", arguments!["a", "0", "garbage"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(&data, "MULTI", &[])
+        );
+        assert_eq!(
+            Response::OwnedError("MOVED 12182 127.0.0.1:7002".to_string()),
+            client.process(&data, "GET", arguments!["foo"])
+        );
+        assert_eq!(
+            Response::Error("EXECABORT Transaction discarded because of previous errors."),
+            client.process(&data, "EXEC", &[])
+        );
+    }
+
+    #[test]
+    fn subscribe() {
+        let data = Mutex::new(Data::new());
+        let (sender, receiver) = mpsc::channel();
+        let mut client = Client::new(sender);
+        let mut other = new_client();
+
+        let reply = |kind: &str, name: &str, count| {
+            Response::Array(vec![
+                bulk_string(kind),
+                bulk_string(name),
+                Response::Integer(count),
+            ])
+        };
+
+        assert_eq!(
+            Response::Sequence(vec![reply("subscribe", "a", 1), reply("subscribe", "b", 2)]),
+            client.process(&data, "SUBSCRIBE", arguments!["a", "b"])
+        );
+        assert_eq!(
+            Response::Sequence(vec![reply("psubscribe", "a*", 3)]),
+            client.process(&data, "PSUBSCRIBE", arguments!["a*"])
+        );
+        assert_eq!(
+            Response::Error(
+                "Can't execute command: only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"
+            ),
+            client.process(&data, "GET", arguments!["a"])
+        );
+        assert_eq!(
+            Response::Array(vec![bulk_string("pong"), bulk_string("")]),
+            client.process(&data, "PING", &[])
+        );
+        assert_eq!(
+            Response::Integer(2),
+            other.process(&data, "PUBLISH", arguments!["a", "hi"])
+        );
+        assert_eq!(2, receiver.try_iter().count());
+        assert_eq!(
+            Response::Sequence(vec![
+                reply("unsubscribe", "a", 2),
+                reply("unsubscribe", "b", 1)
+            ]),
+            client.process(&data, "UNSUBSCRIBE", arguments!["a", "b"])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                bulk_string("unsubscribe"),
+                Response::BulkString(BulkString::Null),
+                Response::Integer(1),
+            ]),
+            client.process(&data, "UNSUBSCRIBE", &[])
+        );
+        assert_eq!(
+            Response::SimpleString("RESET"),
+            client.process(&data, "RESET", &[])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            other.process(&data, "PUBLISH", arguments!["a", "hi"])
+        );
+    }
+
+    #[test]
+    fn ssubscribe() {
+        let data = Mutex::new(Data::new());
+        let mut client = new_client();
+
+        assert_eq!(
+            Response::Error("CROSSSLOT Keys in request don't hash to the same slot"),
+            client.process(&data, "SSUBSCRIBE", arguments!["a", "b"])
+        );
+        assert_eq!(
+            Response::Sequence(vec![Response::Array(vec![
+                bulk_string("ssubscribe"),
+                bulk_string("{a}1"),
+                Response::Integer(1),
+            ])]),
+            client.process(&data, "SSUBSCRIBE", arguments!["{a}1"])
+        );
+        assert_eq!(
+            Response::Sequence(vec![Response::Array(vec![
+                bulk_string("subscribe"),
+                bulk_string("b"),
+                Response::Integer(1),
+            ])]),
+            client.process(&data, "SUBSCRIBE", arguments!["b"])
+        );
+        assert_eq!(
+            Response::Sequence(vec![Response::Array(vec![
+                bulk_string("sunsubscribe"),
+                bulk_string("{a}1"),
+                Response::Integer(0),
+            ])]),
+            client.process(&data, "SUNSUBSCRIBE", &[])
+        );
+    }
+
+    #[test]
+    fn keyspace_events() {
+        let data = Mutex::new(Data::new());
+        let (sender, receiver) = mpsc::channel();
+        let mut subscriber = Client::new(sender);
+        let mut client = new_client();
+
+        client.process(
+            &data,
+            "CONFIG",
+            arguments!["SET", "notify-keyspace-events", "KEg$"],
+        );
+        subscriber.process(
+            &data,
+            "SUBSCRIBE",
+            arguments!["__keyspace@0__:a", "__keyevent@0__:del"],
+        );
+        client.process(&data, "SET", arguments!["a", "1"]);
+        client.process(&data, "DEL", arguments!["a"]);
+
+        assert_eq!(
+            vec![
+                b"*3\r\n$7\r\nmessage\r\n$16\r\n__keyspace@0__:a\r\n$3\r\nset\r\n".to_vec(),
+                b"*3\r\n$7\r\nmessage\r\n$16\r\n__keyspace@0__:a\r\n$3\r\ndel\r\n".to_vec(),
+                b"*3\r\n$7\r\nmessage\r\n$18\r\n__keyevent@0__:del\r\n$1\r\na\r\n".to_vec(),
+            ],
+            receiver.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sentinel() {
+        let data = Mutex::new(Data::new());
+        let (sender, receiver) = mpsc::channel();
+        let mut subscriber = Client::new(sender);
+        let mut client = new_client();
+
+        data.lock()
+            .unwrap()
+            .sentinel_mut()
+            .enable(26379, 30_000, 180_000);
+        subscriber.set_sentinel();
+        client.set_sentinel();
+
+        assert_eq!(
+            Response::Error("unknown command"),
+            client.process(&data, "SET", arguments!["a", "1"])
+        );
+
+        subscriber.process(&data, "SUBSCRIBE", arguments!["+monitor"]);
+        receiver.try_iter().for_each(drop);
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            client.process(
+                &data,
+                "SENTINEL",
+                arguments!["MONITOR", "mymaster", "127.0.0.1", "6379", "2"]
+            )
+        );
+        assert_eq!(
+            vec![b"*3\r\n$7\r\nmessage\r\n$8\r\n+monitor\r\n$39\r\nmaster mymaster 127.0.0.1 6379 quorum 2\r\n"
+                .to_vec()],
+            receiver.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn deep_recursion_fails_the_script() {
+        let nested = format!(
+            "local function f(n) return {}f(n + 1){} end return f(1)",
+            "1 + (".repeat(60),
+            ")".repeat(60)
+        );
+
+        // Clients run on threads with this much stack.
+        thread::Builder::new()
+            .stack_size(crate::lua::STACK_SIZE)
+            .spawn(move || {
+                let data = Mutex::new(Data::new());
+                let mut client = new_client();
+
+                for script in [
+                    "local function f(n) return 1 + f(n + 1) end return f(1)",
+                    &nested,
+                ] {
+                    match client.process(&data, "EVAL", arguments![script, "0"]) {
+                        Response::OwnedError(message) => {
+                            assert!(message.contains("stack overflow"), "{message}")
+                        }
+                        other => panic!("unexpected response {other:?}"),
+                    }
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
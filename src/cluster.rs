@@ -0,0 +1,2216 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::bulk_string::BulkString;
+use crate::commands::Response;
+use crate::crc16::{key_hash_slot, SLOTS};
+use crate::rdb;
+use crate::replication::new_id;
+use crate::Data;
+
+pub(crate) const DEFAULT_CONFIG_FILE: &str = "nodes.conf";
+/// The cluster bus port is the client port plus this, like Redis's default.
+pub(crate) const BUS_PORT_OFFSET: u16 = 10000;
+/// How long a node may not answer pings before it's suspected of having failed, in milliseconds, like Redis's
+/// `cluster-node-timeout`.
+pub(crate) const DEFAULT_NODE_TIMEOUT: u64 = 15_000;
+/// How often each node is pinged at most, in milliseconds. Nodes are pinged more often if the node timeout is short.
+const PING_PERIOD: u64 = 1000;
+/// How long a manual failover may take, in milliseconds, like Redis's.
+const MANUAL_FAILOVER_TIMEOUT: u64 = 5000;
+/// How long a node removed with `CLUSTER FORGET` isn't added back when other nodes mention it, in milliseconds.
+const FORGET_PERIOD: u64 = 60_000;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A node of the cluster, as this node knows it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Node {
+    id: String,
+    ip: String,
+    port: u16,
+    bus_port: u16,
+    /// The ID of the primary the node replicates, or `None` if it's a primary.
+    primary: Option<String>,
+    /// The epoch in which the node last claimed its slots, which settles conflicting claims.
+    config_epoch: u64,
+    /// When the oldest ping the node hasn't answered was sent, or 0 if it answered them all.
+    ping_sent: u64,
+    pong_received: u64,
+    /// When this node last pinged the node.
+    last_ping: u64,
+    /// Whether the node hasn't answered for longer than the node timeout, which only this node thinks so far.
+    pfail: bool,
+    /// When the primaries agreed that the node failed.
+    fail_time: Option<u64>,
+    /// The primaries that reported the node as failing, with when they last did.
+    failure_reports: BTreeMap<String, u64>,
+    /// The replication offset the node last told.
+    offset: u64,
+    /// When this node last voted for a replica of the node to replace it.
+    voted_time: u64,
+}
+
+impl Node {
+    fn new(id: String, ip: String, port: u16, bus_port: u16) -> Node {
+        Node {
+            id,
+            ip,
+            port,
+            bus_port,
+            primary: None,
+            config_epoch: 0,
+            ping_sent: 0,
+            pong_received: 0,
+            last_ping: 0,
+            pfail: false,
+            fail_time: None,
+            failure_reports: BTreeMap::new(),
+            offset: 0,
+            voted_time: 0,
+        }
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn ip(&self) -> &str {
+        &self.ip
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn primary(&self) -> Option<&str> {
+        self.primary.as_deref()
+    }
+
+    pub(crate) fn config_epoch(&self) -> u64 {
+        self.config_epoch
+    }
+
+    /// The address clients are redirected to, as `ip:port`.
+    pub(crate) fn address(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    pub(crate) fn has_failed(&self) -> bool {
+        self.fail_time.is_some()
+    }
+
+    /// Whether the node is suspected of having failed, or agreed to have failed.
+    pub(crate) fn is_failing(&self) -> bool {
+        self.pfail || self.has_failed()
+    }
+
+    pub(crate) fn failure_reports(&self) -> usize {
+        self.failure_reports.len()
+    }
+}
+
+/// How `CLUSTER FAILOVER` promotes a replica.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FailoverMode {
+    /// Wait for the replica to catch up with its primary, then hold an election that primaries vote in although the
+    /// primary didn't fail.
+    Default,
+    /// Hold the election right away.
+    Force,
+    /// Take the primary's slots without an election.
+    Takeover,
+}
+
+/// What `CLUSTER SETSLOT` does with a slot, with the ID of the other node involved.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum SlotState {
+    /// Start moving the slot to the node.
+    Migrating(String),
+    /// Start moving the slot from the node.
+    Importing(String),
+    /// Stop moving the slot.
+    Stable,
+    /// Assign the slot to the node, once its keys have moved.
+    Node(String),
+}
+
+/// The kinds of messages nodes send each other on the cluster bus.
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    /// Asks a node to add the sender to its cluster, and is answered like a ping.
+    Meet,
+    Ping,
+    Pong,
+    /// Tells that the primaries agreed that a node failed.
+    Fail(String),
+    /// Asks the primaries to vote for the sender to replace its primary. `force` asks them to vote although the
+    /// primary didn't fail, for a manual failover.
+    AuthRequest {
+        force: bool,
+    },
+    /// A primary's vote.
+    AuthAck,
+}
+
+/// What the sender of a ping knows about another node, so that nodes learn about each other, and agree that a node
+/// failed.
+#[derive(Clone, Debug, PartialEq)]
+struct Gossip {
+    id: String,
+    ip: String,
+    port: u16,
+    bus_port: u16,
+    failing: bool,
+}
+
+/// A message on the cluster bus. Like Redis's, every message describes its sender, so any message lets the other
+/// nodes update their view of it.
+///
+/// Messages are sent as lines of fields separated by spaces: the kind, the sender's ID, port, bus port, primary (`-`
+/// for none), configuration epoch, current epoch, replication offset and slots (`-` for none), followed by the ID of
+/// the failed node for `FAIL`, `force` or `-` for `AUTH-REQUEST`, and the gossip for pings, as
+/// `id,ip,port,bus_port,fail?|ok`.
+#[derive(Clone, Debug, PartialEq)]
+struct Message {
+    kind: Kind,
+    sender: String,
+    port: u16,
+    bus_port: u16,
+    primary: Option<String>,
+    /// The sender's configuration epoch, or its primary's if it's a replica.
+    config_epoch: u64,
+    current_epoch: u64,
+    offset: u64,
+    /// The slots the sender serves, or its primary serves if it's a replica.
+    slots: Vec<(u16, u16)>,
+    gossip: Vec<Gossip>,
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let kind = match &self.kind {
+            Kind::Meet => "MEET",
+            Kind::Ping => "PING",
+            Kind::Pong => "PONG",
+            Kind::Fail(_) => "FAIL",
+            Kind::AuthRequest { .. } => "AUTH-REQUEST",
+            Kind::AuthAck => "AUTH-ACK",
+        };
+        let slots: Vec<String> = self
+            .slots
+            .iter()
+            .map(|(start, end)| format!("{start}-{end}"))
+            .collect();
+        let mut fields = vec![
+            kind.to_string(),
+            self.sender.clone(),
+            self.port.to_string(),
+            self.bus_port.to_string(),
+            self.primary.clone().unwrap_or_else(|| "-".to_string()),
+            self.config_epoch.to_string(),
+            self.current_epoch.to_string(),
+            self.offset.to_string(),
+            if slots.is_empty() {
+                "-".to_string()
+            } else {
+                slots.join(",")
+            },
+        ];
+
+        match &self.kind {
+            Kind::Fail(id) => fields.push(id.clone()),
+            Kind::AuthRequest { force } => {
+                fields.push(if *force { "force" } else { "-" }.to_string())
+            }
+            _ => {}
+        }
+
+        fields.extend(self.gossip.iter().map(|gossip| {
+            format!(
+                "{},{},{},{},{}",
+                gossip.id,
+                gossip.ip,
+                gossip.port,
+                gossip.bus_port,
+                if gossip.failing { "fail?" } else { "ok" }
+            )
+        }));
+
+        let mut line = fields.join(" ");
+
+        line.push('\n');
+        line.into_bytes()
+    }
+
+    fn decode(line: &str) -> Option<Message> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [kind, sender, port, bus_port, primary, config_epoch, current_epoch, offset, slots, rest @ ..] =
+            &fields[..]
+        else {
+            return None;
+        };
+        let (kind, gossip) = match (*kind, rest) {
+            ("MEET", gossip) => (Kind::Meet, gossip),
+            ("PING", gossip) => (Kind::Ping, gossip),
+            ("PONG", gossip) => (Kind::Pong, gossip),
+            ("FAIL", [id]) => (Kind::Fail(id.to_string()), &[][..]),
+            ("AUTH-REQUEST", [force]) => (
+                Kind::AuthRequest {
+                    force: *force == "force",
+                },
+                &[][..],
+            ),
+            ("AUTH-ACK", []) => (Kind::AuthAck, &[][..]),
+            _ => return None,
+        };
+        let slots = match *slots {
+            "-" => Vec::new(),
+            slots => slots
+                .split(',')
+                .map(|range| {
+                    let (start, end) = range.split_once('-')?;
+
+                    Some((parse_slot(start)?, parse_slot(end)?))
+                })
+                .collect::<Option<_>>()?,
+        };
+        let gossip = gossip
+            .iter()
+            .map(|entry| match entry.split(',').collect::<Vec<_>>()[..] {
+                [id, ip, port, bus_port, flags] => Some(Gossip {
+                    id: id.to_string(),
+                    ip: ip.to_string(),
+                    port: port.parse().ok()?,
+                    bus_port: bus_port.parse().ok()?,
+                    failing: flags == "fail?",
+                }),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Message {
+            kind,
+            sender: sender.to_string(),
+            port: port.parse().ok()?,
+            bus_port: bus_port.parse().ok()?,
+            primary: (*primary != "-").then(|| primary.to_string()),
+            config_epoch: config_epoch.parse().ok()?,
+            current_epoch: current_epoch.parse().ok()?,
+            offset: offset.parse().ok()?,
+            slots,
+            gossip,
+        })
+    }
+}
+
+/// A node this node is introducing itself to, met with `CLUSTER MEET` or heard of from another node, whose ID isn't
+/// known until it answers.
+#[derive(Debug)]
+struct Handshake {
+    ip: String,
+    bus_port: u16,
+    started: u64,
+    last_ping: u64,
+    /// Whether to send `MEET` rather than `PING`, so that the node adds this one although it doesn't know it.
+    meet: bool,
+}
+
+/// A replica's attempt to replace its primary.
+#[derive(Debug)]
+struct Election {
+    /// When to ask for votes. Replicas with less data wait longer, so that the one with the most data likely wins.
+    start: u64,
+    /// When to give up.
+    deadline: u64,
+    /// The epoch votes were asked for, once they were.
+    epoch: Option<u64>,
+    /// The primaries that voted for this node.
+    votes: BTreeSet<String>,
+    /// Whether the election was started by `CLUSTER FAILOVER`, so the primary doesn't need to have failed.
+    manual: bool,
+    /// Whether to wait until this node has the data its primary last said it had.
+    catch_up: bool,
+}
+
+/// What's known about a command when deciding whether this node serves it.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Query {
+    pub(crate) slot: u16,
+    pub(crate) write: bool,
+    /// Whether the connection sent `ASKING` just before, so it may use a slot being imported.
+    pub(crate) asking: bool,
+    /// Whether the connection sent `READONLY`, so a replica serves its reads.
+    pub(crate) read_only: bool,
+    pub(crate) keys: usize,
+    /// How many of the keys don't exist here.
+    pub(crate) missing: usize,
+}
+
+/// This node's view of the cluster: the nodes, and which primary serves each of the hash slots keys are divided into.
+///
+/// Like Redis, the view is saved to a configuration file whenever it changes, in the format of `CLUSTER NODES` with an
+/// extra line for the epochs, and loaded at startup, so several processes given files that agree form a cluster. While
+/// a slot moves between nodes, the node it's moved from is _migrating_ it and the node it's moved to is _importing_ it:
+/// keys that aren't on the former anymore are looked up on the latter with `ASK` redirections.
+///
+/// Nodes ping each other on the cluster bus, and tell each other about the nodes they know, which ones they suspect
+/// of having failed, and which slots they serve. When a majority of the primaries suspect a node, it's agreed to have
+/// failed, and one of its replicas is elected by the primaries to replace it. Claims to slots are settled by epochs:
+/// the node that claimed a slot in the latest epoch serves it.
+#[derive(Debug)]
+pub(crate) struct Cluster {
+    enabled: bool,
+    path: PathBuf,
+    /// The ID of this node.
+    myself: String,
+    nodes: BTreeMap<String, Node>,
+    /// The ID of the node serving each slot.
+    slots: Vec<Option<String>>,
+    /// The slots moved away from this node, with the ID of the node they're moved to.
+    migrating: BTreeMap<u16, String>,
+    /// The slots moved to this node, with the ID of the node they're moved from.
+    importing: BTreeMap<u16, String>,
+    current_epoch: u64,
+    last_vote_epoch: u64,
+    /// How long a node may not answer pings before it's suspected of having failed, in milliseconds.
+    node_timeout: u64,
+    handshakes: Vec<Handshake>,
+    /// The nodes removed with `CLUSTER FORGET`, with when they may be added back.
+    forgotten: BTreeMap<String, u64>,
+    /// This node's replication offset, which its messages tell.
+    offset: u64,
+    election: Option<Election>,
+    /// Whether the cluster can serve requests, as of the last time its state changed. See [`Cluster::update_state`].
+    ok: bool,
+    /// The messages to send, with the IP and bus port of the node to send each to.
+    outbox: Vec<(String, u16, Vec<u8>)>,
+}
+
+impl Cluster {
+    pub(crate) fn new() -> Cluster {
+        Cluster {
+            enabled: false,
+            path: PathBuf::from(DEFAULT_CONFIG_FILE),
+            myself: String::new(),
+            nodes: BTreeMap::new(),
+            slots: vec![None; SLOTS as usize],
+            migrating: BTreeMap::new(),
+            importing: BTreeMap::new(),
+            current_epoch: 0,
+            last_vote_epoch: 0,
+            node_timeout: DEFAULT_NODE_TIMEOUT,
+            handshakes: Vec::new(),
+            forgotten: BTreeMap::new(),
+            offset: 0,
+            election: None,
+            ok: false,
+            outbox: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enables cluster mode, loading the configuration file at `path`, or creating it with this node alone if it
+    /// doesn't exist. This node listens on `port`.
+    pub(crate) fn enable(&mut self, path: PathBuf, port: u16) -> Result<(), String> {
+        let bus_port = port
+            .checked_add(BUS_PORT_OFFSET)
+            .ok_or_else(|| format!("port {port} leaves no room for the cluster bus port"))?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error.to_string()),
+        };
+
+        self.path = path;
+        self.enabled = true;
+
+        match text {
+            Some(text) => self.parse(&text)?,
+            None => {
+                let id = new_id();
+
+                self.nodes.insert(
+                    id.clone(),
+                    Node::new(id.clone(), "127.0.0.1".to_string(), port, bus_port),
+                );
+                self.myself = id;
+            }
+        }
+
+        // Like Redis, don't let the current epoch fall behind any node's, which a handwritten file may do.
+        self.current_epoch = self
+            .nodes
+            .values()
+            .map(|node| node.config_epoch)
+            .fold(self.current_epoch, u64::max);
+
+        let myself = self.myself_mut();
+
+        myself.port = port;
+        myself.bus_port = bus_port;
+
+        self.update_state();
+        self.save().map_err(|error| error.to_string())
+    }
+
+    pub(crate) fn set_node_timeout(&mut self, timeout: u64) {
+        self.node_timeout = timeout;
+    }
+
+    pub(crate) fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    /// Parses a configuration file, which lists the nodes like `CLUSTER NODES`, followed by the epochs.
+    fn parse(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("invalid cluster configuration line '{line}'");
+
+            if let ["vars", vars @ ..] = &fields[..] {
+                for pair in vars.chunks(2) {
+                    match pair {
+                        ["currentEpoch", epoch] => {
+                            self.current_epoch = epoch.parse().map_err(|_| invalid())?
+                        }
+                        ["lastVoteEpoch", epoch] => {
+                            self.last_vote_epoch = epoch.parse().map_err(|_| invalid())?
+                        }
+                        _ => return Err(invalid()),
+                    }
+                }
+
+                continue;
+            }
+
+            let [id, address, flags, primary, _ping_sent, _pong_received, config_epoch, _link_state, slots @ ..] =
+                &fields[..]
+            else {
+                return Err(invalid());
+            };
+
+            let (ip, port, bus_port) = parse_address(address).ok_or_else(invalid)?;
+            let flags: Vec<&str> = flags.split(',').collect();
+
+            if flags.contains(&"myself") {
+                self.myself = id.to_string();
+            }
+
+            let mut node = Node::new(id.to_string(), ip, port, bus_port);
+
+            node.primary = (*primary != "-").then(|| primary.to_string());
+            node.config_epoch = config_epoch.parse().map_err(|_| invalid())?;
+            // Like Redis, a node that had failed is considered failed until it answers.
+            node.pfail = flags.contains(&"fail?");
+            node.fail_time = flags.contains(&"fail").then_some(0);
+            self.nodes.insert(id.to_string(), node);
+
+            for slots in slots {
+                self.parse_slots(id, slots).ok_or_else(invalid)?;
+            }
+        }
+
+        if !self.nodes.contains_key(&self.myself) {
+            return Err("the cluster configuration doesn't say which node is this one".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Parses a slot, a range of slots, or the slot being migrated or imported, which `id` serves.
+    fn parse_slots(&mut self, id: &str, slots: &str) -> Option<()> {
+        if let Some(state) = slots.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            if let Some((slot, target)) = state.split_once("->-") {
+                self.migrating.insert(parse_slot(slot)?, target.to_string());
+            } else {
+                let (slot, source) = state.split_once("-<-")?;
+
+                self.importing.insert(parse_slot(slot)?, source.to_string());
+            }
+
+            return Some(());
+        }
+
+        let (start, end) = match slots.split_once('-') {
+            Some((start, end)) => (parse_slot(start)?, parse_slot(end)?),
+            None => (parse_slot(slots)?, parse_slot(slots)?),
+        };
+
+        for slot in start..=end {
+            self.slots[slot as usize] = Some(id.to_string());
+        }
+
+        Some(())
+    }
+
+    /// Saves the configuration file. Called whenever the configuration changes.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let mut text: String = self
+            .nodes
+            .values()
+            .map(|node| self.describe(node) + "\n")
+            .collect();
+
+        text.push_str(&format!(
+            "vars currentEpoch {} lastVoteEpoch {}\n",
+            self.current_epoch, self.last_vote_epoch
+        ));
+
+        rdb::write_file(&self.path, text.as_bytes())
+    }
+
+    /// Describes a node like a line of `CLUSTER NODES`.
+    pub(crate) fn describe(&self, node: &Node) -> String {
+        let mut flags = Vec::new();
+
+        if node.id == self.myself {
+            flags.push("myself");
+        }
+
+        flags.push(if node.primary.is_some() {
+            "slave"
+        } else {
+            "master"
+        });
+
+        if node.pfail {
+            flags.push("fail?");
+        }
+
+        if node.has_failed() {
+            flags.push("fail");
+        }
+
+        let mut line = format!(
+            "{} {}:{}@{} {} {} {} {} {} {}",
+            node.id,
+            node.ip,
+            node.port,
+            node.bus_port,
+            flags.join(","),
+            node.primary.as_deref().unwrap_or("-"),
+            node.ping_sent,
+            node.pong_received,
+            node.config_epoch,
+            if node.is_failing() {
+                "disconnected"
+            } else {
+                "connected"
+            }
+        );
+
+        for (start, end) in self.ranges(&node.id) {
+            if start == end {
+                line.push_str(&format!(" {start}"));
+            } else {
+                line.push_str(&format!(" {start}-{end}"));
+            }
+        }
+
+        if node.id == self.myself {
+            for (slot, target) in &self.migrating {
+                line.push_str(&format!(" [{slot}->-{target}]"));
+            }
+
+            for (slot, source) in &self.importing {
+                line.push_str(&format!(" [{slot}-<-{source}]"));
+            }
+        }
+
+        line
+    }
+
+    pub(crate) fn myself(&self) -> &Node {
+        &self.nodes[&self.myself]
+    }
+
+    fn myself_mut(&mut self) -> &mut Node {
+        self.nodes
+            .get_mut(&self.myself)
+            .expect("this node is known")
+    }
+
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub(crate) fn node(&self, id: &str) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    /// The nodes that replicate the primary `id`.
+    pub(crate) fn replicas_of<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.nodes
+            .values()
+            .filter(move |node| node.primary.as_deref() == Some(id))
+    }
+
+    /// The node serving `slot`, if it's assigned.
+    pub(crate) fn owner(&self, slot: u16) -> Option<&Node> {
+        self.slots[slot as usize]
+            .as_ref()
+            .and_then(|id| self.nodes.get(id))
+    }
+
+    /// The ranges of consecutive slots that the node `id` serves, with both ends included.
+    pub(crate) fn ranges(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+
+        for slot in 0..SLOTS {
+            if self.slots[slot as usize].as_deref() != Some(id) {
+                continue;
+            }
+
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+
+        ranges
+    }
+
+    /// The ranges of consecutive slots served by the same node, with the node, in order.
+    pub(crate) fn slot_ranges(&self) -> Vec<(u16, u16, &Node)> {
+        let mut ranges: Vec<(u16, u16, &Node)> = Vec::new();
+
+        for slot in 0..SLOTS {
+            let Some(owner) = self.owner(slot) else {
+                continue;
+            };
+
+            match ranges.last_mut() {
+                Some((_, end, node)) if *end + 1 == slot && node.id == owner.id => *end = slot,
+                _ => ranges.push((slot, slot, owner)),
+            }
+        }
+
+        ranges
+    }
+
+    pub(crate) fn assigned_slots(&self) -> usize {
+        self.slots.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    /// How many slots are served by nodes suspected of having failed, and by nodes agreed to have failed.
+    pub(crate) fn failing_slots(&self) -> (usize, usize) {
+        let owners = self
+            .slots
+            .iter()
+            .flatten()
+            .filter_map(|id| self.nodes.get(id));
+
+        owners.fold((0, 0), |(pfail, fail), node| {
+            (
+                pfail + node.pfail as usize,
+                fail + node.has_failed() as usize,
+            )
+        })
+    }
+
+    /// The number of primaries serving slots, which are the ones that vote.
+    pub(crate) fn size(&self) -> usize {
+        self.slots.iter().flatten().collect::<BTreeSet<_>>().len()
+    }
+
+    /// How many primaries must agree that a node failed, or vote for a replica.
+    fn quorum(&self) -> usize {
+        self.size() / 2 + 1
+    }
+
+    /// Whether the cluster can serve requests.
+    pub(crate) fn is_ok(&self) -> bool {
+        self.ok
+    }
+
+    /// Works out whether the cluster can serve requests: every slot is served by a node that didn't fail, and this
+    /// node reaches a majority of the primaries, so it's not on the minority side of a partition. Done whenever the
+    /// nodes or slots change rather than for every request.
+    fn update_state(&mut self) {
+        let served = self.slots.iter().all(|owner| {
+            owner
+                .as_ref()
+                .and_then(|id| self.nodes.get(id))
+                .is_some_and(|node| !node.has_failed())
+        });
+        let reachable = self
+            .slots
+            .iter()
+            .flatten()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|id| self.nodes.get(*id).is_some_and(|node| !node.is_failing()))
+            .count();
+
+        self.ok = served && reachable >= self.quorum();
+    }
+
+    pub(crate) fn current_epoch(&self) -> u64 {
+        self.current_epoch
+    }
+
+    /// Assigns slots to this node, as `CLUSTER ADDSLOTS` does. None are assigned if any already is.
+    pub(crate) fn add_slots(&mut self, slots: &[u16]) -> Result<(), String> {
+        let mut seen = vec![false; SLOTS as usize];
+
+        for &slot in slots {
+            if self.slots[slot as usize].is_some() {
+                return Err(format!("Slot {slot} is already busy"));
+            }
+
+            if mem::replace(&mut seen[slot as usize], true) {
+                return Err(format!("Slot {slot} specified multiple times"));
+            }
+        }
+
+        for &slot in slots {
+            self.slots[slot as usize] = Some(self.myself.clone());
+            self.importing.remove(&slot);
+        }
+
+        self.update_state();
+        self.save().map_err(|error| error.to_string())
+    }
+
+    /// Unassigns slots, as `CLUSTER DELSLOTS` does. None are unassigned if any already is.
+    pub(crate) fn delete_slots(&mut self, slots: &[u16]) -> Result<(), String> {
+        let mut seen = vec![false; SLOTS as usize];
+
+        for &slot in slots {
+            if self.slots[slot as usize].is_none() {
+                return Err(format!("Slot {slot} is already unassigned"));
+            }
+
+            if mem::replace(&mut seen[slot as usize], true) {
+                return Err(format!("Slot {slot} specified multiple times"));
+            }
+        }
+
+        for &slot in slots {
+            self.slots[slot as usize] = None;
+            self.migrating.remove(&slot);
+            self.importing.remove(&slot);
+        }
+
+        self.update_state();
+        self.save().map_err(|error| error.to_string())
+    }
+
+    /// Changes the state of `slot`, as `CLUSTER SETSLOT` does. `keys` is how many keys this node has in the slot,
+    /// which must have moved before it's assigned to another node. Like Redis, a node that's assigned a slot it was
+    /// importing moves to a new epoch without asking the others, so that its claim wins.
+    pub(crate) fn set_slot(
+        &mut self,
+        slot: u16,
+        state: SlotState,
+        keys: usize,
+    ) -> Result<(), String> {
+        if self.myself().primary.is_some() {
+            return Err("Please use SETSLOT only with masters.".to_string());
+        }
+
+        let owned = self.slots[slot as usize].as_deref() == Some(&self.myself);
+        let target = match &state {
+            SlotState::Migrating(id) | SlotState::Importing(id) | SlotState::Node(id) => {
+                match self.nodes.get(id) {
+                    Some(node) if node.primary.is_some() => {
+                        return Err("Target node is not a master".to_string())
+                    }
+                    Some(node) => Some(node.id.clone()),
+                    None if matches!(state, SlotState::Node(_)) => {
+                        return Err(format!("Unknown node {id}"))
+                    }
+                    None => return Err(format!("I don't know about node {id}")),
+                }
+            }
+            SlotState::Stable => None,
+        };
+
+        match (state, target) {
+            (SlotState::Migrating(_), Some(target)) => {
+                if !owned {
+                    return Err(format!("I'm not the owner of hash slot {slot}"));
+                }
+
+                self.migrating.insert(slot, target);
+            }
+            (SlotState::Importing(_), Some(source)) => {
+                if owned {
+                    return Err(format!("I'm already the owner of hash slot {slot}"));
+                }
+
+                self.importing.insert(slot, source);
+            }
+            (SlotState::Node(_), Some(target)) => {
+                if owned && target != self.myself && keys > 0 {
+                    return Err(format!(
+                        "Can't assign hashslot {slot} to a different node while I still hold keys for this \
+                        hash slot."
+                    ));
+                }
+
+                if keys == 0 {
+                    self.migrating.remove(&slot);
+                }
+
+                let imported = target == self.myself && self.importing.remove(&slot).is_some();
+
+                self.slots[slot as usize] = Some(target);
+
+                if imported && self.bump_epoch() {
+                    println!(
+                        "configEpoch updated after importing slot {slot}: {}",
+                        self.current_epoch
+                    );
+                    self.broadcast(Kind::Pong);
+                }
+            }
+            _ => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+        }
+
+        self.update_state();
+        self.save().map_err(|error| error.to_string())
+    }
+
+    /// Moves this node to a new configuration epoch without the agreement of the others, unless no node has a greater
+    /// one already. Returns whether it moved.
+    fn bump_epoch(&mut self) -> bool {
+        let greatest = self
+            .nodes
+            .values()
+            .map(|node| node.config_epoch)
+            .fold(self.current_epoch, u64::max);
+        let myself = self.myself();
+
+        if myself.config_epoch != 0 && myself.config_epoch == greatest {
+            return false;
+        }
+
+        self.current_epoch += 1;
+
+        let epoch = self.current_epoch;
+
+        self.myself_mut().config_epoch = epoch;
+
+        true
+    }
+
+    /// Whether this node has the keys in `slot`: it serves the slot, imports it, or replicates the primary serving it.
+    pub(crate) fn serves(&self, slot: u16) -> bool {
+        let owner = self.slots[slot as usize].as_deref();
+
+        owner == Some(&self.myself)
+            || self.importing.contains_key(&slot)
+            || owner.is_some() && owner == self.myself().primary()
+    }
+
+    /// Whether this node may run commands on keys in `slot`, or which error redirects the client to the node that
+    /// can. Like Redis, a key that isn't here anymore while its slot is migrating is looked up on the node the slot
+    /// is moved to, and a replica serves reads from connections that sent `READONLY`.
+    pub(crate) fn redirect(&self, query: Query) -> Option<Response> {
+        if !self.is_ok() {
+            return Some(Response::Error("CLUSTERDOWN The cluster is down"));
+        }
+
+        let Some(owner) = self.owner(query.slot) else {
+            return Some(Response::Error("CLUSTERDOWN Hash slot not served"));
+        };
+        let rehashing = query.missing > 0 && query.missing < query.keys;
+
+        if owner.id == self.myself {
+            return match self.migrating.get(&query.slot) {
+                Some(_) if rehashing => Some(Response::Error(
+                    "TRYAGAIN Multiple keys request during rehashing of slot",
+                )),
+                Some(target) if query.missing > 0 => self.nodes.get(target).map(|target| {
+                    Response::OwnedError(format!("ASK {} {}", query.slot, target.address()))
+                }),
+                _ => None,
+            };
+        }
+
+        if query.asking && self.importing.contains_key(&query.slot) {
+            if rehashing {
+                return Some(Response::Error(
+                    "TRYAGAIN Multiple keys request during rehashing of slot",
+                ));
+            }
+
+            return None;
+        }
+
+        if query.read_only && !query.write && self.myself().primary.as_deref() == Some(&owner.id) {
+            return None;
+        }
+
+        Some(Response::OwnedError(format!(
+            "MOVED {} {}",
+            query.slot,
+            owner.address()
+        )))
+    }
+
+    /// Describes this node in a message, with what it knows about the other nodes if it's a ping.
+    fn message(&self, kind: Kind) -> Message {
+        let myself = self.myself();
+        // A replica tells the slots of its primary, which it would take over.
+        let served = myself
+            .primary
+            .as_deref()
+            .and_then(|id| self.nodes.get(id))
+            .unwrap_or(myself);
+        let gossip = match kind {
+            Kind::Meet | Kind::Ping | Kind::Pong => self
+                .nodes
+                .values()
+                .filter(|node| node.id != self.myself)
+                .map(|node| Gossip {
+                    id: node.id.clone(),
+                    ip: node.ip.clone(),
+                    port: node.port,
+                    bus_port: node.bus_port,
+                    failing: node.is_failing(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Message {
+            kind,
+            sender: myself.id.clone(),
+            port: myself.port,
+            bus_port: myself.bus_port,
+            primary: myself.primary.clone(),
+            config_epoch: served.config_epoch,
+            current_epoch: self.current_epoch,
+            offset: self.offset,
+            slots: self.ranges(&served.id),
+            gossip,
+        }
+    }
+
+    fn send(&mut self, ip: String, bus_port: u16, kind: Kind) {
+        let message = self.message(kind).encode();
+
+        self.outbox.push((ip, bus_port, message));
+    }
+
+    /// Sends a message to every other node.
+    fn broadcast(&mut self, kind: Kind) {
+        let message = self.message(kind).encode();
+
+        for node in self.nodes.values().filter(|node| node.id != self.myself) {
+            self.outbox
+                .push((node.ip.clone(), node.bus_port, message.clone()));
+        }
+    }
+
+    /// The messages to send since this was last called, with the IP and bus port of the node to send each to.
+    pub(crate) fn take_outbox(&mut self) -> Vec<(String, u16, Vec<u8>)> {
+        mem::take(&mut self.outbox)
+    }
+
+    /// Saves the configuration file after a change that happened on its own rather than because of a command, so
+    /// there's no one to report an error to but the log.
+    fn persist(&self) {
+        if let Err(error) = self.save() {
+            eprintln!("Error: saving the cluster configuration: {error}");
+        }
+    }
+
+    /// Starts introducing this node to the node at `ip` with the given bus port, unless it's already known.
+    fn start_handshake(&mut self, ip: String, bus_port: u16, meet: bool, now: u64) {
+        let known = self
+            .nodes
+            .values()
+            .any(|node| node.ip == ip && node.bus_port == bus_port)
+            || self
+                .handshakes
+                .iter()
+                .any(|handshake| handshake.ip == ip && handshake.bus_port == bus_port);
+
+        if !known {
+            self.handshakes.push(Handshake {
+                ip,
+                bus_port,
+                started: now,
+                last_ping: 0,
+                meet,
+            });
+        }
+    }
+
+    /// Adds the node at `ip` and `port` to the cluster, as `CLUSTER MEET` does. It's added once it answers.
+    pub(crate) fn meet(&mut self, ip: String, bus_port: u16, now: u64) {
+        self.start_handshake(ip, bus_port, true, now);
+    }
+
+    /// Removes a node, as `CLUSTER FORGET` does. Other nodes mentioning it don't add it back for a minute, which gives
+    /// time to remove it from all of them.
+    pub(crate) fn forget(&mut self, id: &str, now: u64) -> Result<(), String> {
+        if id == self.myself {
+            return Err("I tried hard but I can't forget myself...".to_string());
+        }
+
+        if !self.nodes.contains_key(id) {
+            return Err(format!("Unknown node {id}"));
+        }
+
+        if self.myself().primary.as_deref() == Some(id) {
+            return Err("Can't forget my master!".to_string());
+        }
+
+        self.nodes.remove(id);
+
+        for owner in self.slots.iter_mut() {
+            if owner.as_deref() == Some(id) {
+                *owner = None;
+            }
+        }
+
+        for node in self.nodes.values_mut() {
+            node.failure_reports.remove(id);
+        }
+
+        self.forgotten.insert(id.to_string(), now + FORGET_PERIOD);
+        self.update_state();
+        self.save().map_err(|error| error.to_string())
+    }
+
+    /// Makes this node a replica of the primary `id`, as `CLUSTER REPLICATE` does.
+    pub(crate) fn replicate(&mut self, id: &str) -> Result<(), String> {
+        let Some(node) = self.nodes.get(id) else {
+            return Err(format!("Unknown node {id}"));
+        };
+
+        if id == self.myself {
+            return Err("Can't replicate myself".to_string());
+        }
+
+        if node.primary.is_some() {
+            return Err("I can only replicate a master, not a replica.".to_string());
+        }
+
+        if self.myself().primary.is_none() && !self.ranges(&self.myself).is_empty() {
+            return Err(
+                "To set a master the node must be empty and without assigned slots.".to_string(),
+            );
+        }
+
+        self.myself_mut().primary = Some(id.to_string());
+        self.election = None;
+        self.save().map_err(|error| error.to_string())
+    }
+
+    /// Starts replacing this replica's primary, as `CLUSTER FAILOVER` does.
+    pub(crate) fn failover(&mut self, mode: FailoverMode, now: u64) -> Result<(), &'static str> {
+        let Some(primary) = self.myself().primary.as_deref() else {
+            return Err("You should send CLUSTER FAILOVER to a replica");
+        };
+        let Some(primary) = self.nodes.get(primary) else {
+            return Err("I'm a replica but my master is unknown to me");
+        };
+
+        if mode == FailoverMode::Default && primary.is_failing() {
+            return Err("Master is down or failed, please use CLUSTER FAILOVER FORCE");
+        }
+
+        if mode == FailoverMode::Takeover {
+            println!("Taking over the master (user request).");
+            self.current_epoch += 1;
+            self.promote(self.current_epoch);
+
+            return Ok(());
+        }
+
+        println!("Manual failover user request accepted.");
+        self.election = Some(Election {
+            start: now,
+            deadline: now + MANUAL_FAILOVER_TIMEOUT,
+            epoch: None,
+            votes: BTreeSet::new(),
+            manual: true,
+            catch_up: mode == FailoverMode::Default,
+        });
+
+        Ok(())
+    }
+
+    /// Pings the other nodes, suspects the ones that don't answer, and replaces this replica's primary if it failed.
+    /// Called periodically.
+    pub(crate) fn cron(&mut self, now: u64) {
+        let period = PING_PERIOD.min(self.node_timeout / 2).max(1);
+        let handshake_timeout = self.node_timeout.max(1000);
+
+        self.forgotten.retain(|_, until| *until > now);
+        self.handshakes
+            .retain(|handshake| now.saturating_sub(handshake.started) < handshake_timeout);
+
+        let mut handshakes = mem::take(&mut self.handshakes);
+
+        for handshake in &mut handshakes {
+            if now.saturating_sub(handshake.last_ping) >= period {
+                handshake.last_ping = now;
+                self.send(
+                    handshake.ip.clone(),
+                    handshake.bus_port,
+                    if handshake.meet {
+                        Kind::Meet
+                    } else {
+                        Kind::Ping
+                    },
+                );
+            }
+        }
+
+        self.handshakes = handshakes;
+
+        let ids: Vec<String> = self
+            .nodes
+            .keys()
+            .filter(|id| **id != self.myself)
+            .cloned()
+            .collect();
+        let validity = self.node_timeout * 2;
+
+        for id in ids {
+            let node = self.nodes.get_mut(&id).expect("the node is known");
+            let ping = now.saturating_sub(node.last_ping) >= period;
+
+            if ping {
+                node.last_ping = now;
+
+                if node.ping_sent == 0 {
+                    node.ping_sent = now;
+                }
+            }
+
+            node.failure_reports
+                .retain(|_, time| now.saturating_sub(*time) <= validity);
+
+            if node.ping_sent != 0
+                && now.saturating_sub(node.ping_sent) > self.node_timeout
+                && !node.is_failing()
+            {
+                println!("*** NODE {id} possibly failing");
+                node.pfail = true;
+            }
+
+            if ping {
+                let (ip, bus_port) = (node.ip.clone(), node.bus_port);
+
+                self.send(ip, bus_port, Kind::Ping);
+            }
+
+            self.check_failure(&id, now);
+        }
+
+        self.failover_cron(now);
+        self.update_state();
+    }
+
+    /// Agrees that a node failed if it's suspected by this node and by a majority of the primaries, and tells the
+    /// other nodes.
+    fn check_failure(&mut self, id: &str, now: u64) {
+        let Some(node) = self.nodes.get(id) else {
+            return;
+        };
+
+        if !node.pfail || node.has_failed() {
+            return;
+        }
+
+        let reports = node
+            .failure_reports
+            .keys()
+            .filter(|reporter| {
+                self.nodes
+                    .get(*reporter)
+                    .is_some_and(|reporter| reporter.primary.is_none())
+            })
+            .count();
+        let myself = self.myself().primary.is_none() as usize;
+
+        if reports + myself < self.quorum() {
+            return;
+        }
+
+        println!("Marking node {id} as failing (quorum reached).");
+
+        let node = self.nodes.get_mut(id).expect("the node is known");
+
+        node.pfail = false;
+        node.fail_time = Some(now);
+        self.broadcast(Kind::Fail(id.to_string()));
+        self.persist();
+    }
+
+    /// Starts an election if this replica's primary failed, and asks for votes when it's time.
+    fn failover_cron(&mut self, now: u64) {
+        let Some(primary) = self.myself().primary.clone() else {
+            self.election = None;
+            return;
+        };
+        let Some(primary_node) = self.nodes.get(&primary) else {
+            return;
+        };
+        let (failed, primary_offset) = (primary_node.has_failed(), primary_node.offset);
+        let serves_slots = !self.ranges(&primary).is_empty();
+
+        let Some(election) = &mut self.election else {
+            if !failed || !serves_slots {
+                return;
+            }
+
+            // Like Redis, replicas that have less data than others wait a second more for each, so the one with the
+            // most data likely asks for votes first.
+            let rank = self
+                .replicas_of(&primary)
+                .filter(|replica| replica.id != self.myself && replica.offset > self.offset)
+                .count() as u64;
+            let delay = 500 + random() % 500 + rank * 1000;
+
+            println!(
+                "Start of election delayed for {delay} milliseconds (rank #{rank}, offset {}).",
+                self.offset
+            );
+            self.election = Some(Election {
+                start: now + delay,
+                deadline: now + delay + (self.node_timeout * 2).max(2000),
+                epoch: None,
+                votes: BTreeSet::new(),
+                manual: false,
+                catch_up: false,
+            });
+
+            return;
+        };
+
+        if now >= election.deadline || !election.manual && !failed {
+            if election.manual {
+                println!("Manual failover timed out.");
+            }
+
+            self.election = None;
+            return;
+        }
+
+        if election.epoch.is_some()
+            || now < election.start
+            || election.catch_up && self.offset < primary_offset
+        {
+            return;
+        }
+
+        self.current_epoch += 1;
+        election.epoch = Some(self.current_epoch);
+
+        let force = election.manual;
+
+        println!(
+            "Starting a failover election for epoch {}.",
+            self.current_epoch
+        );
+        self.broadcast(Kind::AuthRequest { force });
+        self.persist();
+    }
+
+    /// Takes over the slots of this replica's primary, claiming them in `epoch`, and tells the other nodes.
+    fn promote(&mut self, epoch: u64) {
+        let Some(primary) = self.myself().primary.clone() else {
+            return;
+        };
+
+        for owner in self.slots.iter_mut() {
+            if owner.as_deref() == Some(&primary) {
+                *owner = Some(self.myself.clone());
+            }
+        }
+
+        let myself = self.myself_mut();
+
+        myself.primary = None;
+        myself.config_epoch = myself.config_epoch.max(epoch);
+        println!("Failover won: I'm the new master, with configEpoch {epoch}.");
+        self.election = None;
+        self.update_state();
+        self.broadcast(Kind::Pong);
+        self.persist();
+    }
+
+    /// Handles a message from another node, which came from `ip`, returning the reply to send back, if any.
+    pub(crate) fn receive(&mut self, ip: &str, line: &str, now: u64) -> Option<Vec<u8>> {
+        let message = Message::decode(line)?;
+        let reply = self.handle(ip, message, now)?;
+
+        Some(self.message(reply).encode())
+    }
+
+    fn handle(&mut self, ip: &str, message: Message, now: u64) -> Option<Kind> {
+        let sender = message.sender.clone();
+
+        if sender == self.myself || self.forgotten.contains_key(&sender) {
+            return None;
+        }
+
+        let handshake = self
+            .handshakes
+            .iter()
+            .position(|handshake| handshake.ip == ip && handshake.bus_port == message.bus_port);
+
+        if let (Kind::Meet | Kind::Pong, Some(handshake)) = (&message.kind, handshake) {
+            self.handshakes.remove(handshake);
+        }
+
+        if !self.nodes.contains_key(&sender) {
+            match (&message.kind, handshake) {
+                (Kind::Meet, _) | (Kind::Pong, Some(_)) => {
+                    println!("Node {sender} ({ip}:{}) added to the cluster", message.port);
+                    self.nodes.insert(
+                        sender.clone(),
+                        Node::new(
+                            sender.clone(),
+                            ip.to_string(),
+                            message.port,
+                            message.bus_port,
+                        ),
+                    );
+                }
+                // Answer nodes introducing themselves, so they add this one.
+                (Kind::Ping, _) => return Some(Kind::Pong),
+                _ => return None,
+            }
+        }
+
+        let mut changed = false;
+
+        if message.current_epoch > self.current_epoch {
+            self.current_epoch = message.current_epoch;
+            changed = true;
+        }
+
+        let served = self
+            .slots
+            .iter()
+            .any(|owner| owner.as_ref() == Some(&sender));
+        let node = self.nodes.get_mut(&sender).expect("the sender is known");
+
+        node.port = message.port;
+        node.bus_port = message.bus_port;
+        node.offset = message.offset;
+
+        if message.kind == Kind::Pong {
+            node.ping_sent = 0;
+            node.pong_received = now;
+            node.pfail = false;
+
+            // Like Redis, a primary that failed is only back once its slots weren't taken over for a while.
+            if let Some(fail_time) = node.fail_time {
+                if node.primary.is_some()
+                    || !served
+                    || now.saturating_sub(fail_time) > self.node_timeout * 2
+                {
+                    println!("Clear FAIL state for node {sender}: it's reachable again.");
+                    node.fail_time = None;
+                    changed = true;
+                }
+            }
+        }
+
+        if node.primary != message.primary {
+            // A primary that became a replica gave up its slots.
+            if node.primary.is_none() {
+                for owner in self.slots.iter_mut() {
+                    if owner.as_ref() == Some(&sender) {
+                        *owner = None;
+                    }
+                }
+            }
+
+            node.primary = message.primary.clone();
+            changed = true;
+        }
+
+        let is_ping = matches!(message.kind, Kind::Meet | Kind::Ping | Kind::Pong);
+
+        if message.primary.is_none() {
+            if node.config_epoch != message.config_epoch {
+                node.config_epoch = message.config_epoch;
+                changed = true;
+            }
+
+            if is_ping {
+                changed |= self.update_slots(&sender, &message.slots);
+                changed |= self.resolve_epoch_collision(&sender);
+            }
+        }
+
+        if is_ping {
+            self.gossip(&sender, message.primary.is_none(), &message.gossip, now);
+        }
+
+        let reply = match message.kind {
+            Kind::Meet | Kind::Ping => Some(Kind::Pong),
+            Kind::Pong => None,
+            Kind::Fail(id) => {
+                if let Some(node) = self
+                    .nodes
+                    .get_mut(&id)
+                    .filter(|node| node.id != self.myself)
+                {
+                    if !node.has_failed() {
+                        println!("FAIL message received from {sender} about {id}");
+                        node.pfail = false;
+                        node.fail_time = Some(now);
+                        changed = true;
+                    }
+                }
+
+                None
+            }
+            Kind::AuthRequest { force } => self.vote(&message, force, now).then_some(Kind::AuthAck),
+            Kind::AuthAck => {
+                self.count_vote(&sender, message.current_epoch);
+
+                None
+            }
+        };
+
+        self.update_state();
+
+        if changed {
+            self.persist();
+        }
+
+        reply
+    }
+
+    /// Updates the slots that the primary `sender` serves from its claim, giving it those that were claimed in an
+    /// older epoch. If that takes all the slots of this node, or of its primary, this node becomes a replica of the
+    /// sender, like a primary that comes back after it was replaced.
+    fn update_slots(&mut self, sender: &str, ranges: &[(u16, u16)]) -> bool {
+        let epoch = self.nodes[sender].config_epoch;
+        let myself = self.myself();
+        // The primary whose slots this node has: itself or the one it replicates.
+        let served = myself.primary.clone().unwrap_or_else(|| myself.id.clone());
+        let had_slots = !self.ranges(&served).is_empty();
+        let mut changed = false;
+
+        for slot in ranges.iter().flat_map(|&(start, end)| start..=end) {
+            let owner = self.slots[slot as usize].as_deref();
+
+            if owner == Some(sender) || self.importing.contains_key(&slot) {
+                continue;
+            }
+
+            let owner_epoch = owner
+                .and_then(|id| self.nodes.get(id))
+                .map(|node| node.config_epoch);
+
+            if owner_epoch.is_some_and(|owner_epoch| owner_epoch >= epoch) {
+                continue;
+            }
+
+            // The slot has finished moving away from this node.
+            if owner == Some(&self.myself) {
+                self.migrating.remove(&slot);
+            }
+
+            self.slots[slot as usize] = Some(sender.to_string());
+            changed = true;
+        }
+
+        if changed && had_slots && self.ranges(&served).is_empty() {
+            println!(
+                "Configuration change detected. Reconfiguring myself as a replica of {sender}"
+            );
+            self.myself_mut().primary = Some(sender.to_string());
+            self.election = None;
+        }
+
+        changed
+    }
+
+    /// Makes sure no two primaries have the same configuration epoch, which would make their claims to slots
+    /// ambiguous: like Redis, the one with the lower ID moves to a new epoch.
+    fn resolve_epoch_collision(&mut self, sender: &str) -> bool {
+        let myself = self.myself();
+
+        if myself.primary.is_some()
+            || myself.config_epoch != self.nodes[sender].config_epoch
+            || sender <= self.myself.as_str()
+        {
+            return false;
+        }
+
+        self.current_epoch += 1;
+
+        let epoch = self.current_epoch;
+
+        self.myself_mut().config_epoch = epoch;
+        println!("WARNING: configEpoch collision with node {sender}. configEpoch set to {epoch}");
+
+        true
+    }
+
+    /// Learns from the gossip of `sender` which nodes it suspects, counting its reports if it's a primary, and about
+    /// the nodes this node doesn't know yet.
+    fn gossip(&mut self, sender: &str, from_primary: bool, gossip: &[Gossip], now: u64) {
+        for entry in gossip {
+            if entry.id == self.myself {
+                continue;
+            }
+
+            match self.nodes.get_mut(&entry.id) {
+                Some(node) => {
+                    if !from_primary {
+                        continue;
+                    }
+
+                    if entry.failing {
+                        node.failure_reports.insert(sender.to_string(), now);
+                        self.check_failure(&entry.id, now);
+                    } else {
+                        node.failure_reports.remove(sender);
+                    }
+                }
+                None if !self.forgotten.contains_key(&entry.id) => {
+                    self.start_handshake(entry.ip.clone(), entry.bus_port, false, now)
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Whether to vote for the replica that sent `message` to replace its primary. Like Redis, only primaries serving
+    /// slots vote, once per epoch, for a replica of a primary that failed (unless `force`), at most once per primary
+    /// in twice the node timeout, and only if none of the slots it claims were claimed in a later epoch.
+    fn vote(&mut self, message: &Message, force: bool, now: u64) -> bool {
+        if self.myself().primary.is_some() || self.ranges(&self.myself).is_empty() {
+            return false;
+        }
+
+        let Some(primary) = message.primary.as_deref().and_then(|id| self.nodes.get(id)) else {
+            return false;
+        };
+
+        if message.current_epoch < self.current_epoch
+            || self.last_vote_epoch == self.current_epoch
+            || !force && !primary.has_failed()
+            || now.saturating_sub(primary.voted_time) < self.node_timeout * 2
+        {
+            return false;
+        }
+
+        let newer_claim = message
+            .slots
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .filter_map(|slot| self.owner(slot))
+            .any(|owner| owner.config_epoch > message.config_epoch);
+
+        if newer_claim {
+            return false;
+        }
+
+        let primary = primary.id.clone();
+
+        self.last_vote_epoch = self.current_epoch;
+        self.nodes
+            .get_mut(&primary)
+            .expect("the primary is known")
+            .voted_time = now;
+        println!(
+            "Failover auth granted to {} for epoch {}",
+            message.sender, self.current_epoch
+        );
+        self.persist();
+
+        true
+    }
+
+    /// Counts the vote of `sender` in this replica's election, and takes over once a majority voted.
+    fn count_vote(&mut self, sender: &str, epoch: u64) {
+        let voter = self
+            .nodes
+            .get(sender)
+            .is_some_and(|node| node.primary.is_none())
+            && !self.ranges(sender).is_empty();
+        let quorum = self.quorum();
+        let Some(election) = &mut self.election else {
+            return;
+        };
+        let Some(election_epoch) = election
+            .epoch
+            .filter(|&election_epoch| epoch >= election_epoch)
+        else {
+            return;
+        };
+
+        if voter {
+            election.votes.insert(sender.to_string());
+        }
+
+        if election.votes.len() >= quorum {
+            self.promote(election_epoch);
+        }
+    }
+}
+
+/// The hash slot of a key or sharded channel.
+pub(crate) fn slot(key: &BulkString) -> u16 {
+    match key {
+        BulkString::Filled(bytes) => key_hash_slot(bytes),
+        _ => key_hash_slot(&[]),
+    }
+}
+
+/// Parses a slot number, checking that it's in range.
+pub(crate) fn parse_slot(slot: &str) -> Option<u16> {
+    slot.parse().ok().filter(|&slot| slot < SLOTS)
+}
+
+/// Parses a node's address, `ip:port@bus_port`, which may be followed by `,hostname`.
+fn parse_address(address: &str) -> Option<(String, u16, u16)> {
+    let address = address.split(',').next()?;
+    let (address, bus_port) = address.split_once('@')?;
+    let (ip, port) = address.rsplit_once(':')?;
+
+    Some((ip.to_string(), port.parse().ok()?, bus_port.parse().ok()?))
+}
+
+/// A random number, for delays that keep nodes from acting at the same time.
+pub(crate) fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Sends messages to the other nodes of the cluster. Each node gets its own connection and thread, so that a node that
+/// doesn't answer doesn't hold up messages to the others.
+pub(crate) struct Bus {
+    data: Arc<Mutex<Data>>,
+    links: HashMap<(String, u16), Sender<Vec<u8>>>,
+}
+
+impl Bus {
+    pub(crate) fn new(data: Arc<Mutex<Data>>) -> Bus {
+        Bus {
+            data,
+            links: HashMap::new(),
+        }
+    }
+
+    /// Sends a message to the node with the given IP and bus port.
+    pub(crate) fn send(&mut self, ip: String, port: u16, message: Vec<u8>) {
+        let sender = self.links.entry((ip.clone(), port)).or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            let data = Arc::clone(&self.data);
+
+            thread::spawn(move || link(data, ip, port, receiver));
+            sender
+        });
+
+        let _ = sender.send(message);
+    }
+}
+
+/// Sends the messages for one node, connecting again whenever the connection fails, and handles the replies.
+fn link(data: Arc<Mutex<Data>>, ip: String, port: u16, messages: Receiver<Vec<u8>>) {
+    let mut stream: Option<TcpStream> = None;
+
+    while let Ok(message) = messages.recv() {
+        if stream.is_none() {
+            stream = connect(&ip, port).ok();
+
+            if let Some(reader) = stream.as_ref().and_then(|stream| stream.try_clone().ok()) {
+                let data = Arc::clone(&data);
+                let ip = ip.clone();
+
+                thread::spawn(move || serve(&data, reader, &ip));
+            }
+        }
+
+        let Some(connection) = &mut stream else {
+            // Messages queued while the node is unreachable are stale by the time it's reachable again.
+            while messages.try_recv().is_ok() {}
+
+            continue;
+        };
+
+        if connection.write_all(&message).is_err() {
+            let _ = connection.shutdown(Shutdown::Both);
+            stream = None;
+        }
+    }
+}
+
+pub(crate) fn connect(ip: &str, port: u16) -> io::Result<TcpStream> {
+    let address = (ip, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+
+    TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+}
+
+/// Accepts connections from the other nodes of the cluster.
+pub(crate) fn listen(data: Arc<Mutex<Data>>, listener: TcpListener) {
+    for stream in listener.incoming().flatten() {
+        let data = Arc::clone(&data);
+        let ip = match stream.peer_addr() {
+            Ok(address) => address.ip().to_string(),
+            Err(_) => continue,
+        };
+
+        thread::spawn(move || serve(&data, stream, &ip));
+    }
+}
+
+/// Handles the messages that come from `ip` on a connection, writing back the replies, until it's closed. Serves both
+/// the connections other nodes open and the replies on the ones this node opens.
+fn serve(data: &Mutex<Data>, stream: TcpStream, ip: &str) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let reply = data
+            .lock()
+            .expect("failed to acquire lock")
+            .cluster_message(ip, &line);
+
+        if let Some(reply) = reply {
+            if writer.write_all(&reply).is_err() {
+                break;
+            }
+        }
+    }
+
+    // Makes the link sending on this connection, if any, notice and connect again.
+    let _ = writer.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    const CONFIG: &str = "\
+        aaaa 127.0.0.1:7001@17001 myself,master - 0 0 1 connected 0-8191 [8191->-bbbb] [8192-<-bbbb]\n\
+        bbbb 127.0.0.1:7002@17002 master - 0 0 2 connected 8192-16383\n\
+        cccc 127.0.0.1:7003@17003 slave bbbb 0 0 2 connected\n\
+        vars currentEpoch 2 lastVoteEpoch 0\n";
+
+    fn temp_cluster(name: &str, config: Option<&str>) -> Cluster {
+        temp_node(name, config, 7001)
+    }
+
+    fn temp_node(name: &str, config: Option<&str>, port: u16) -> Cluster {
+        let path = env::temp_dir().join(format!("red-{}-{name}.conf", process::id()));
+
+        match config {
+            Some(config) => fs::write(&path, config).unwrap(),
+            None => {
+                let _ = fs::remove_file(&path);
+            }
+        }
+
+        let mut cluster = Cluster::new();
+
+        cluster.enable(path, port).unwrap();
+        cluster
+    }
+
+    #[test]
+    fn configuration() {
+        let cluster = temp_cluster("configuration", Some(CONFIG));
+
+        assert_eq!("aaaa", cluster.myself().id());
+        assert_eq!(3, cluster.nodes().count());
+        assert_eq!(2, cluster.current_epoch());
+        assert_eq!(vec![(0, 8191)], cluster.ranges("aaaa"));
+        assert_eq!(Some("bbbb"), cluster.owner(9000).map(Node::id));
+        assert_eq!(
+            vec!["cccc"],
+            cluster
+                .replicas_of("bbbb")
+                .map(Node::id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&"bbbb".to_string()), cluster.migrating.get(&8191));
+        assert!(cluster.is_ok());
+
+        // The file is saved as it was read.
+        assert_eq!(CONFIG, fs::read_to_string(&cluster.path).unwrap());
+
+        let cluster = temp_cluster("new", None);
+
+        assert_eq!(40, cluster.myself().id().len());
+        assert_eq!(17001, cluster.myself().bus_port);
+        assert!(!cluster.is_ok());
+        assert!(parse_address("127.0.0.1:7001").is_none());
+        assert!(Cluster::new()
+            .parse("aaaa 127.0.0.1:7001@17001 master")
+            .is_err());
+    }
+
+    #[test]
+    fn slots() {
+        let mut cluster = temp_cluster("slots", None);
+
+        assert_eq!(Ok(()), cluster.add_slots(&[1, 2, 3, 5]));
+        assert_eq!(vec![(1, 3), (5, 5)], cluster.ranges(cluster.myself().id()));
+        assert_eq!(
+            Err("Slot 3 is already busy".to_string()),
+            cluster.add_slots(&[4, 3])
+        );
+        assert_eq!(
+            Err("Slot 4 specified multiple times".to_string()),
+            cluster.add_slots(&[4, 4])
+        );
+        assert_eq!(4, cluster.assigned_slots());
+        assert_eq!(Ok(()), cluster.delete_slots(&[2]));
+        assert_eq!(
+            Err("Slot 2 is already unassigned".to_string()),
+            cluster.delete_slots(&[2])
+        );
+        assert_eq!(3, cluster.assigned_slots());
+    }
+
+    #[test]
+    fn redirect() {
+        let cluster = temp_cluster("redirect", Some(CONFIG));
+        let query = |slot, keys, missing| Query {
+            slot,
+            keys,
+            missing,
+            ..Query::default()
+        };
+
+        assert_eq!(None, cluster.redirect(query(0, 1, 1)));
+        assert_eq!(
+            Some(Response::OwnedError(
+                "MOVED 9000 127.0.0.1:7002".to_string()
+            )),
+            cluster.redirect(query(9000, 1, 0))
+        );
+
+        // Keys that were already migrated are looked up on the node the slot is moved to.
+        assert_eq!(None, cluster.redirect(query(8191, 1, 0)));
+        assert_eq!(
+            Some(Response::OwnedError("ASK 8191 127.0.0.1:7002".to_string())),
+            cluster.redirect(query(8191, 1, 1))
+        );
+        assert_eq!(
+            Some(Response::Error(
+                "TRYAGAIN Multiple keys request during rehashing of slot"
+            )),
+            cluster.redirect(query(8191, 2, 1))
+        );
+
+        // Keys in a slot being imported are only served to connections that were redirected with `ASK`.
+        assert_eq!(
+            Some(Response::OwnedError(
+                "MOVED 8192 127.0.0.1:7002".to_string()
+            )),
+            cluster.redirect(query(8192, 1, 1))
+        );
+        assert_eq!(
+            None,
+            cluster.redirect(Query {
+                asking: true,
+                ..query(8192, 1, 1)
+            })
+        );
+
+        let mut cluster = temp_cluster("down", None);
+
+        assert_eq!(
+            Some(Response::Error("CLUSTERDOWN The cluster is down")),
+            cluster.redirect(query(0, 1, 0))
+        );
+
+        cluster.add_slots(&(0..SLOTS).collect::<Vec<_>>()).unwrap();
+        assert_eq!(None, cluster.redirect(query(0, 1, 0)));
+    }
+
+    #[test]
+    fn messages() {
+        let message = Message {
+            kind: Kind::Ping,
+            sender: "aaaa".to_string(),
+            port: 7001,
+            bus_port: 17001,
+            primary: None,
+            config_epoch: 1,
+            current_epoch: 2,
+            offset: 3,
+            slots: vec![(0, 99), (100, 100)],
+            gossip: vec![Gossip {
+                id: "bbbb".to_string(),
+                ip: "127.0.0.1".to_string(),
+                port: 7002,
+                bus_port: 17002,
+                failing: true,
+            }],
+        };
+        let encoded = String::from_utf8(message.encode()).unwrap();
+
+        assert_eq!(
+            "PING aaaa 7001 17001 - 1 2 3 0-99,100-100 bbbb,127.0.0.1,7002,17002,fail?\n",
+            encoded
+        );
+        assert_eq!(Some(message.clone()), Message::decode(&encoded));
+
+        let message = Message {
+            kind: Kind::AuthRequest { force: true },
+            primary: Some("bbbb".to_string()),
+            slots: Vec::new(),
+            gossip: Vec::new(),
+            ..message
+        };
+
+        assert_eq!(
+            Some(message.clone()),
+            Message::decode(&String::from_utf8(message.encode()).unwrap())
+        );
+        assert_eq!(None, Message::decode("PING aaaa 7001"));
+        assert_eq!(None, Message::decode("FAIL aaaa 7001 17001 - 1 2 3 -"));
+    }
+
+    /// Runs the periodic tasks of the nodes that are up, and delivers their messages and replies until there are no
+    /// more.
+    fn tick(clusters: &mut [Cluster], up: &[bool], now: u64) {
+        for (cluster, _) in clusters.iter_mut().zip(up).filter(|(_, up)| **up) {
+            cluster.cron(now);
+        }
+
+        loop {
+            let mut messages = Vec::new();
+
+            for (i, cluster) in clusters.iter_mut().enumerate() {
+                messages.extend(
+                    cluster
+                        .take_outbox()
+                        .into_iter()
+                        .map(|message| (i, message)),
+                );
+            }
+
+            if messages.is_empty() {
+                return;
+            }
+
+            for (from, (_, bus_port, message)) in messages {
+                let Some(to) = clusters
+                    .iter()
+                    .position(|cluster| cluster.myself().bus_port == bus_port)
+                else {
+                    continue;
+                };
+
+                if !up[from] || !up[to] {
+                    continue;
+                }
+
+                let message = String::from_utf8(message).unwrap();
+
+                if let Some(reply) = clusters[to].receive("127.0.0.1", &message, now) {
+                    clusters[from].receive("127.0.0.1", &String::from_utf8(reply).unwrap(), now);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn failover() {
+        let nodes = [
+            ("aaaa", "127.0.0.1:7001@17001", "master -", "1", "0-5460"),
+            (
+                "bbbb",
+                "127.0.0.1:7002@17002",
+                "master -",
+                "2",
+                "5461-10922",
+            ),
+            (
+                "cccc",
+                "127.0.0.1:7003@17003",
+                "master -",
+                "3",
+                "10923-16383",
+            ),
+            ("dddd", "127.0.0.1:7004@17004", "slave aaaa", "1", ""),
+        ];
+        let mut clusters: Vec<Cluster> = (0..nodes.len())
+            .map(|i| {
+                let config: String = nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(j, (id, address, role, epoch, slots))| {
+                        let (flags, primary) = role.split_once(' ').unwrap();
+                        let flags = if i == j {
+                            format!("myself,{flags}")
+                        } else {
+                            flags.to_string()
+                        };
+
+                        format!("{id} {address} {flags} {primary} 0 0 {epoch} connected {slots}\n")
+                    })
+                    .collect();
+                let mut cluster =
+                    temp_node(&format!("failover-{i}"), Some(&config), 7001 + i as u16);
+
+                cluster.set_node_timeout(1000);
+                cluster
+            })
+            .collect();
+        let mut up = [true; 4];
+
+        tick(&mut clusters, &up, 1000);
+        assert!(clusters.iter().all(Cluster::is_ok));
+
+        // The primary stops answering: once the node timeout passes, the other primaries suspect it, and agree that
+        // it failed.
+        up[0] = false;
+
+        for now in (1100..=3500).step_by(100) {
+            tick(&mut clusters, &up, now);
+        }
+
+        assert!(clusters[1].node("aaaa").unwrap().has_failed());
+        assert!(!clusters[1].is_ok());
+
+        // Its replica is elected to replace it.
+        for now in (3600..=6000).step_by(100) {
+            tick(&mut clusters, &up, now);
+        }
+
+        for cluster in &clusters[1..] {
+            assert!(cluster.is_ok());
+            assert_eq!(Some("dddd"), cluster.owner(0).map(Node::id));
+            assert_eq!(4, cluster.node("dddd").unwrap().config_epoch());
+        }
+
+        assert_eq!(None, clusters[3].myself().primary());
+        assert_eq!(4, clusters[1].last_vote_epoch);
+
+        // When the former primary is back, it finds its slots were taken over, and becomes a replica.
+        up[0] = true;
+
+        for now in (6100..=7000).step_by(100) {
+            tick(&mut clusters, &up, now);
+        }
+
+        assert_eq!(Some("dddd"), clusters[0].myself().primary());
+        assert_eq!(Some("dddd"), clusters[0].owner(0).map(Node::id));
+        assert!(clusters.iter().all(Cluster::is_ok));
+    }
+
+    #[test]
+    fn migrate_slot() {
+        let nodes = [("aaaa", 7001, 1, "0-16383"), ("bbbb", 7002, 0, "")];
+        let mut clusters: Vec<Cluster> = (0..nodes.len())
+            .map(|i| {
+                let config: String = nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(j, (id, port, epoch, slots))| {
+                        let flags = if i == j { "myself,master" } else { "master" };
+
+                        format!("{id} 127.0.0.1:{port}@1{port} {flags} - 0 0 {epoch} connected {slots}\n")
+                    })
+                    .collect();
+
+                temp_node(&format!("migrate-{i}"), Some(&config), 7001 + i as u16)
+            })
+            .collect();
+        let node = |id: &str| id.to_string();
+
+        assert_eq!(
+            Ok(()),
+            clusters[1].set_slot(100, SlotState::Importing(node("aaaa")), 0)
+        );
+        assert_eq!(
+            Ok(()),
+            clusters[0].set_slot(100, SlotState::Migrating(node("bbbb")), 1)
+        );
+        assert_eq!(
+            Err("I'm not the owner of hash slot 100".to_string()),
+            clusters[1].set_slot(100, SlotState::Migrating(node("aaaa")), 0)
+        );
+        assert_eq!(
+            Err("I don't know about node cccc".to_string()),
+            clusters[1].set_slot(100, SlotState::Importing(node("cccc")), 0)
+        );
+        assert_eq!(
+            Err(
+                "Can't assign hashslot 100 to a different node while I still hold keys for this hash slot."
+                    .to_string()
+            ),
+            clusters[0].set_slot(100, SlotState::Node(node("bbbb")), 1)
+        );
+
+        // Once the keys have moved, the node that imported the slot claims it in a new epoch, so the others accept it.
+        assert_eq!(
+            Ok(()),
+            clusters[1].set_slot(100, SlotState::Node(node("bbbb")), 0)
+        );
+        assert_eq!(2, clusters[1].myself().config_epoch());
+
+        tick(&mut clusters, &[true; 2], 1000);
+
+        assert_eq!(Some("bbbb"), clusters[0].owner(100).map(Node::id));
+        assert_eq!(Some("aaaa"), clusters[0].owner(101).map(Node::id));
+        assert!(clusters[0].migrating.is_empty());
+        assert!(clusters[1].importing.is_empty());
+    }
+
+    #[test]
+    fn meet_and_forget() {
+        let mut clusters: Vec<Cluster> = (0..3)
+            .map(|i| temp_node(&format!("meet-{i}"), None, 7001 + i))
+            .collect();
+
+        // Meeting one node is enough to learn about the others.
+        clusters[0].meet("127.0.0.1".to_string(), 17002, 0);
+        clusters[1].meet("127.0.0.1".to_string(), 17003, 0);
+
+        for now in (0..=3000).step_by(100) {
+            tick(&mut clusters, &[true; 3], now);
+        }
+
+        assert!(clusters.iter().all(|cluster| cluster.nodes().count() == 3));
+
+        let id = clusters[2].myself().id().to_string();
+
+        assert_eq!(
+            Err("I tried hard but I can't forget myself...".to_string()),
+            clusters[2].forget(&id, 0)
+        );
+        assert_eq!(Ok(()), clusters[0].forget(&id, 3000));
+
+        // The node isn't added back although the others still know it.
+        tick(&mut clusters, &[true; 3], 3100);
+        assert_eq!(2, clusters[0].nodes().count());
+    }
+}
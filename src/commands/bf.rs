@@ -0,0 +1,365 @@
+use super::{bytes, keyword, number, Command, Data, Response, WRONG_TYPE};
+use crate::array::Value;
+use crate::bloom_filter::{
+    BloomFilterError, ScalableBloomFilter, DEFAULT_CAPACITY, DEFAULT_ERROR_RATE, DEFAULT_EXPANSION,
+    MAX_EXPANSION,
+};
+use crate::bulk_string::BulkString;
+use crate::object::Object;
+
+pub(crate) struct BfReserve;
+pub(crate) struct BfAdd;
+pub(crate) struct BfMAdd;
+pub(crate) struct BfExists;
+pub(crate) struct BfMExists;
+pub(crate) struct BfInfo;
+
+fn get_filter<'a>(
+    data: &'a mut Data,
+    key: &BulkString,
+) -> Result<Option<&'a ScalableBloomFilter>, Response> {
+    match data.get(key) {
+        Some(Object::BloomFilter(filter)) => Ok(Some(filter)),
+        Some(_) => Err(Response::Error(WRONG_TYPE)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the filter stored at `key`, creating one with the default parameters if the key doesn't exist.
+fn get_or_create_filter<'a>(
+    data: &'a mut Data,
+    key: &BulkString,
+) -> Result<&'a mut ScalableBloomFilter, Response> {
+    let object = data.get_or_insert_with(key, || {
+        Object::BloomFilter(
+            ScalableBloomFilter::new(
+                DEFAULT_ERROR_RATE,
+                DEFAULT_CAPACITY,
+                Some(DEFAULT_EXPANSION),
+            )
+            .expect("the default filter isn't too large"),
+        )
+    });
+
+    match object {
+        Object::BloomFilter(filter) => Ok(filter),
+        _ => Err(Response::Error(WRONG_TYPE)),
+    }
+}
+
+fn insert(filter: &mut ScalableBloomFilter, item: &[u8]) -> Response {
+    match filter.insert(item) {
+        Ok(added) => Response::Integer(added as i64),
+        Err(BloomFilterError::Full) => Response::Error("non scaling filter is full"),
+        Err(BloomFilterError::TooLarge) => Response::Error("Maximum expansions reached"),
+    }
+}
+
+impl Command for BfReserve {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !(3..=6).contains(&arguments.len()) {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let error_rate: f64 = match number(&arguments[1]) {
+            Some(error_rate) => error_rate,
+            None => return Response::Error("bad error rate"),
+        };
+
+        if !(error_rate > 0.0 && error_rate < 1.0) {
+            return Response::Error("(0 < error rate range < 1)");
+        }
+
+        let capacity: u64 = match number(&arguments[2]) {
+            Some(capacity) => capacity,
+            None => return Response::Error("bad capacity"),
+        };
+
+        if capacity == 0 {
+            return Response::Error("(capacity should be larger than 0)");
+        }
+
+        let mut expansion = Some(DEFAULT_EXPANSION);
+        let mut expansion_specified = false;
+        let mut non_scaling = false;
+        let mut options = arguments[3..].iter();
+
+        while let Some(option) = options.next() {
+            match keyword(option).as_deref() {
+                Some("EXPANSION") => {
+                    expansion = match options.next().and_then(number) {
+                        Some(0) => {
+                            return Response::Error("expansion should be greater or equal to 1")
+                        }
+                        Some(expansion @ 1..=MAX_EXPANSION) => Some(expansion),
+                        _ => return Response::Error("bad expansion"),
+                    };
+                    expansion_specified = true;
+                }
+                Some("NONSCALING") => non_scaling = true,
+                _ => return Response::Error("syntax error"),
+            }
+        }
+
+        if non_scaling {
+            if expansion_specified {
+                return Response::Error("Nonscaling filters cannot expand");
+            }
+
+            expansion = None;
+        }
+
+        if data.contains_key(key) {
+            return Response::Error("item exists");
+        }
+
+        let filter = match ScalableBloomFilter::new(error_rate, capacity, expansion) {
+            Ok(filter) => filter,
+            Err(_) => return Response::Error("Insufficient memory to create filter"),
+        };
+
+        data.insert(key.clone(), Object::BloomFilter(filter));
+
+        Response::SimpleString("OK")
+    }
+}
+
+impl Command for BfAdd {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+        let item = match bytes(&arguments[1]) {
+            Some(item) => item,
+            None => return Response::Error("invalid argument"),
+        };
+
+        let response = match get_or_create_filter(data, key) {
+            Ok(filter) => insert(filter, item),
+            Err(e) => return e,
+        };
+
+        if response == Response::Integer(1) {
+            data.touch(key);
+        }
+
+        response
+    }
+}
+
+impl Command for BfMAdd {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+        let items: Option<Vec<&[u8]>> = arguments[1..].iter().map(bytes).collect();
+        let items = match items {
+            Some(items) => items,
+            None => return Response::Error("invalid argument"),
+        };
+
+        let responses: Vec<Response> = match get_or_create_filter(data, key) {
+            Ok(filter) => items.into_iter().map(|item| insert(filter, item)).collect(),
+            Err(e) => return e,
+        };
+
+        if responses.contains(&Response::Integer(1)) {
+            data.touch(key);
+        }
+
+        Response::Array(responses)
+    }
+}
+
+impl Command for BfExists {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+        let item = match bytes(&arguments[1]) {
+            Some(item) => item,
+            None => return Response::Error("invalid argument"),
+        };
+
+        match get_filter(data, key) {
+            Ok(filter) => Response::Integer(filter.is_some_and(|f| f.contains(item)) as i64),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for BfMExists {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+        let items: Option<Vec<&[u8]>> = arguments[1..].iter().map(bytes).collect();
+        let items = match items {
+            Some(items) => items,
+            None => return Response::Error("invalid argument"),
+        };
+
+        match get_filter(data, key) {
+            Ok(filter) => Response::Array(
+                items
+                    .into_iter()
+                    .map(|item| Response::Integer(filter.is_some_and(|f| f.contains(item)) as i64))
+                    .collect(),
+            ),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for BfInfo {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !(1..=2).contains(&arguments.len()) {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let filter = match get_filter(data, key) {
+            Ok(Some(filter)) => filter,
+            Ok(None) => return Response::Error("not found"),
+            Err(e) => return e,
+        };
+
+        let expansion = match filter.expansion() {
+            Some(expansion) => Response::Integer(expansion as i64),
+            None => Response::BulkString(BulkString::Null),
+        };
+
+        if arguments.len() == 2 {
+            let field = match keyword(&arguments[1]).as_deref() {
+                Some("CAPACITY") => Response::Integer(filter.capacity() as i64),
+                Some("SIZE") => Response::Integer(filter.size() as i64),
+                Some("FILTERS") => Response::Integer(filter.filter_count() as i64),
+                Some("ITEMS") => Response::Integer(filter.items() as i64),
+                Some("EXPANSION") => expansion,
+                _ => return Response::Error("Invalid information value"),
+            };
+
+            return Response::Array(vec![field]);
+        }
+
+        Response::Array(vec![
+            Response::SimpleString("Capacity"),
+            Response::Integer(filter.capacity() as i64),
+            Response::SimpleString("Size"),
+            Response::Integer(filter.size() as i64),
+            Response::SimpleString("Number of filters"),
+            Response::Integer(filter.filter_count() as i64),
+            Response::SimpleString("Number of items inserted"),
+            Response::Integer(filter.items() as i64),
+            Response::SimpleString("Expansion rate"),
+            expansion,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(BulkString::Filled($value.as_bytes().to_vec()))),*]
+        };
+    }
+
+    #[test]
+    fn add_creates_a_filter() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Integer(1),
+            BfAdd.execute(&mut data, arguments!["bf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            BfAdd.execute(&mut data, arguments!["bf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            BfExists.execute(&mut data, arguments!["bf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            BfExists.execute(&mut data, arguments!["bf", "bar"])
+        );
+    }
+
+    #[test]
+    fn reserve_rejects_existing_keys() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            BfReserve.execute(&mut data, arguments!["bf", "0.01", "100"])
+        );
+        assert_eq!(
+            Response::Error("item exists"),
+            BfReserve.execute(&mut data, arguments!["bf", "0.01", "100"])
+        );
+    }
+
+    #[test]
+    fn reserve_rejects_huge_filters() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Error("Insufficient memory to create filter"),
+            BfReserve.execute(&mut data, arguments!["bf", "0.01", "18446744073709551615"])
+        );
+        assert_eq!(
+            Response::Error("bad expansion"),
+            BfReserve.execute(
+                &mut data,
+                arguments!["bf", "0.01", "100", "EXPANSION", "18446744073709551615"]
+            )
+        );
+        assert!(!data.contains_key(&BulkString::Filled(b"bf".to_vec())));
+    }
+
+    #[test]
+    fn non_scaling_filter_reports_full() {
+        let mut data = Data::new();
+
+        BfReserve.execute(&mut data, arguments!["bf", "0.01", "2", "NONSCALING"]);
+
+        let response = BfMAdd.execute(&mut data, arguments!["bf", "a", "b", "c"]);
+
+        assert_eq!(
+            Response::Array(vec![
+                Response::Integer(1),
+                Response::Integer(1),
+                Response::Error("non scaling filter is full"),
+            ]),
+            response
+        );
+    }
+
+    #[test]
+    fn wrong_type() {
+        let mut data = Data::from([(
+            BulkString::Filled(b"key".to_vec()),
+            Object::String(BulkString::Filled(b"value".to_vec())),
+        )]);
+
+        assert_eq!(
+            Response::Error(WRONG_TYPE),
+            BfAdd.execute(&mut data, arguments!["key", "foo"])
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use super::{bytes, keyword, number, Command, Data, Response, WRONG_TYPE};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::cuckoo_filter::{
+    CuckooFilter, DEFAULT_BUCKET_SIZE, DEFAULT_CAPACITY, DEFAULT_EXPANSION, DEFAULT_MAX_ITERATIONS,
+    MAX_BUCKET_SIZE, MAX_EXPANSION, MAX_ITERATIONS,
+};
+use crate::object::Object;
+
+pub(crate) struct CfReserve;
+pub(crate) struct CfAdd;
+pub(crate) struct CfAddNx;
+pub(crate) struct CfInsert;
+pub(crate) struct CfInsertNx;
+pub(crate) struct CfExists;
+pub(crate) struct CfMExists;
+pub(crate) struct CfDel;
+pub(crate) struct CfCount;
+pub(crate) struct CfInfo;
+
+const TOO_LARGE: &str = "Insufficient memory to create filter";
+
+fn get_filter<'a>(
+    data: &'a mut Data,
+    key: &BulkString,
+) -> Result<Option<&'a CuckooFilter>, Response> {
+    match data.get(key) {
+        Some(Object::CuckooFilter(filter)) => Ok(Some(filter)),
+        Some(_) => Err(Response::Error(WRONG_TYPE)),
+        None => Ok(None),
+    }
+}
+
+fn get_filter_mut<'a>(
+    data: &'a mut Data,
+    key: &BulkString,
+) -> Result<Option<&'a mut CuckooFilter>, Response> {
+    match data.get_mut(key) {
+        Some(Object::CuckooFilter(filter)) => Ok(Some(filter)),
+        Some(_) => Err(Response::Error(WRONG_TYPE)),
+        None => Ok(None),
+    }
+}
+
+fn get_or_create_filter<'a>(
+    data: &'a mut Data,
+    key: &BulkString,
+    capacity: u64,
+) -> Result<&'a mut CuckooFilter, Response> {
+    if !data.contains_key(key) {
+        let filter = CuckooFilter::new(
+            capacity,
+            DEFAULT_BUCKET_SIZE,
+            DEFAULT_MAX_ITERATIONS,
+            DEFAULT_EXPANSION,
+        )
+        .map_err(|_| Response::Error(TOO_LARGE))?;
+
+        data.insert(key.clone(), Object::CuckooFilter(filter));
+    }
+
+    match data.get_mut(key) {
+        Some(Object::CuckooFilter(filter)) => Ok(filter),
+        _ => Err(Response::Error(WRONG_TYPE)),
+    }
+}
+
+fn key_and_item(arguments: &[Value]) -> Result<(&BulkString, &[u8]), Response> {
+    if arguments.len() != 2 {
+        return Err(Response::Error("wrong number of arguments"));
+    }
+
+    let key = match &arguments[0] {
+        Value::BulkString(key @ BulkString::Filled(_)) => key,
+        _ => return Err(Response::Error("invalid argument")),
+    };
+
+    match bytes(&arguments[1]) {
+        Some(item) => Ok((key, item)),
+        None => Err(Response::Error("invalid argument")),
+    }
+}
+
+impl Command for CfReserve {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !(2..=8).contains(&arguments.len()) || !arguments.len().is_multiple_of(2) {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let capacity = match number(&arguments[1]) {
+            Some(capacity) if capacity > 0 => capacity,
+            _ => return Response::Error("Bad capacity"),
+        };
+
+        let mut bucket_size = DEFAULT_BUCKET_SIZE;
+        let mut max_iterations = DEFAULT_MAX_ITERATIONS;
+        let mut expansion = DEFAULT_EXPANSION;
+
+        for pair in arguments[2..].chunks(2) {
+            match keyword(&pair[0]).as_deref() {
+                Some("BUCKETSIZE") => match number(&pair[1]) {
+                    Some(value @ 1..=MAX_BUCKET_SIZE) => bucket_size = value,
+                    _ => return Response::Error("Bad bucket size"),
+                },
+                Some("MAXITERATIONS") => match number(&pair[1]) {
+                    Some(value @ 1..=MAX_ITERATIONS) => max_iterations = value,
+                    _ => return Response::Error("Bad maxiterations"),
+                },
+                Some("EXPANSION") => match number(&pair[1]) {
+                    Some(value @ 0..=MAX_EXPANSION) => expansion = value,
+                    _ => return Response::Error("Bad expansion"),
+                },
+                _ => return Response::Error("syntax error"),
+            }
+        }
+
+        if data.contains_key(key) {
+            return Response::Error("item exists");
+        }
+
+        match CuckooFilter::new(capacity, bucket_size, max_iterations, expansion) {
+            Ok(filter) => data.insert(key.clone(), Object::CuckooFilter(filter)),
+            Err(_) => return Response::Error(TOO_LARGE),
+        };
+
+        Response::SimpleString("OK")
+    }
+}
+
+impl Command for CfAdd {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let (key, item) = match key_and_item(arguments) {
+            Ok(key_and_item) => key_and_item,
+            Err(e) => return e,
+        };
+
+        let filter = match get_or_create_filter(data, key, DEFAULT_CAPACITY) {
+            Ok(filter) => filter,
+            Err(e) => return e,
+        };
+
+        match filter.insert(item) {
+            Ok(()) => {
+                data.touch(key);
+
+                Response::Integer(1)
+            }
+            Err(_) => Response::Error("Filter is full"),
+        }
+    }
+}
+
+impl Command for CfAddNx {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let (key, item) = match key_and_item(arguments) {
+            Ok(key_and_item) => key_and_item,
+            Err(e) => return e,
+        };
+
+        let filter = match get_or_create_filter(data, key, DEFAULT_CAPACITY) {
+            Ok(filter) => filter,
+            Err(e) => return e,
+        };
+
+        if filter.contains(item) {
+            return Response::Integer(0);
+        }
+
+        match filter.insert(item) {
+            Ok(()) => {
+                data.touch(key);
+
+                Response::Integer(1)
+            }
+            Err(_) => Response::Error("Filter is full"),
+        }
+    }
+}
+
+/// Shared implementation of `CF.INSERT` and `CF.INSERTNX`.
+fn insert_many(data: &mut Data, arguments: &[Value], only_new: bool) -> Response {
+    if arguments.len() < 3 {
+        return Response::Error("wrong number of arguments");
+    }
+
+    let key = bulk_string_or_error!(&arguments[0]);
+
+    let mut capacity = DEFAULT_CAPACITY;
+    let mut create = true;
+    let mut options = arguments[1..].iter();
+
+    loop {
+        match options.next().and_then(keyword).as_deref() {
+            Some("CAPACITY") => match options.next().and_then(number) {
+                Some(value) if value > 0 => capacity = value,
+                _ => return Response::Error("Bad capacity"),
+            },
+            Some("NOCREATE") => create = false,
+            Some("ITEMS") => break,
+            _ => return Response::Error("syntax error"),
+        }
+    }
+
+    let items: Option<Vec<&[u8]>> = options.map(bytes).collect();
+    let items = match items {
+        Some(items) if !items.is_empty() => items,
+        _ => return Response::Error("wrong number of arguments"),
+    };
+
+    let filter = if create {
+        get_or_create_filter(data, key, capacity)
+    } else {
+        match get_filter_mut(data, key) {
+            Ok(Some(filter)) => Ok(filter),
+            Ok(None) => Err(Response::Error("not found")),
+            Err(e) => Err(e),
+        }
+    };
+
+    let filter = match filter {
+        Ok(filter) => filter,
+        Err(e) => return e,
+    };
+
+    let responses: Vec<Response> = items
+        .into_iter()
+        .map(|item| {
+            if only_new && filter.contains(item) {
+                return Response::Integer(0);
+            }
+
+            match filter.insert(item) {
+                Ok(()) => Response::Integer(1),
+                Err(_) => Response::Integer(-1),
+            }
+        })
+        .collect();
+
+    if responses.contains(&Response::Integer(1)) {
+        data.touch(key);
+    }
+
+    Response::Array(responses)
+}
+
+impl Command for CfInsert {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        insert_many(data, arguments, false)
+    }
+}
+
+impl Command for CfInsertNx {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        insert_many(data, arguments, true)
+    }
+}
+
+impl Command for CfExists {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let (key, item) = match key_and_item(arguments) {
+            Ok(key_and_item) => key_and_item,
+            Err(e) => return e,
+        };
+
+        match get_filter(data, key) {
+            Ok(filter) => Response::Integer(filter.is_some_and(|f| f.contains(item)) as i64),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for CfMExists {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+        let items: Option<Vec<&[u8]>> = arguments[1..].iter().map(bytes).collect();
+        let items = match items {
+            Some(items) => items,
+            None => return Response::Error("invalid argument"),
+        };
+
+        match get_filter(data, key) {
+            Ok(filter) => Response::Array(
+                items
+                    .into_iter()
+                    .map(|item| Response::Integer(filter.is_some_and(|f| f.contains(item)) as i64))
+                    .collect(),
+            ),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for CfDel {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let (key, item) = match key_and_item(arguments) {
+            Ok(key_and_item) => key_and_item,
+            Err(e) => return e,
+        };
+
+        let deleted = match get_filter_mut(data, key) {
+            Ok(Some(filter)) => filter.delete(item),
+            Ok(None) => return Response::Error("Not found"),
+            Err(e) => return e,
+        };
+
+        if deleted {
+            data.touch(key);
+        }
+
+        Response::Integer(deleted as i64)
+    }
+}
+
+impl Command for CfCount {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let (key, item) = match key_and_item(arguments) {
+            Ok(key_and_item) => key_and_item,
+            Err(e) => return e,
+        };
+
+        match get_filter(data, key) {
+            Ok(filter) => Response::Integer(filter.map_or(0, |f| f.count(item)) as i64),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for CfInfo {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 1 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let filter = match get_filter(data, key) {
+            Ok(Some(filter)) => filter,
+            Ok(None) => return Response::Error("not found"),
+            Err(e) => return e,
+        };
+
+        Response::Array(vec![
+            Response::SimpleString("Size"),
+            Response::Integer(filter.size() as i64),
+            Response::SimpleString("Number of buckets"),
+            Response::Integer(filter.bucket_count() as i64),
+            Response::SimpleString("Number of filters"),
+            Response::Integer(filter.filter_count() as i64),
+            Response::SimpleString("Number of items inserted"),
+            Response::Integer(filter.items() as i64),
+            Response::SimpleString("Number of items deleted"),
+            Response::Integer(filter.deleted() as i64),
+            Response::SimpleString("Bucket size"),
+            Response::Integer(filter.bucket_size() as i64),
+            Response::SimpleString("Expansion rate"),
+            Response::Integer(filter.expansion() as i64),
+            Response::SimpleString("Max iterations"),
+            Response::Integer(filter.max_iterations() as i64),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(BulkString::Filled($value.as_bytes().to_vec()))),*]
+        };
+    }
+
+    #[test]
+    fn add_delete_and_count() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Integer(1),
+            CfAdd.execute(&mut data, arguments!["cf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            CfAdd.execute(&mut data, arguments!["cf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(2),
+            CfCount.execute(&mut data, arguments!["cf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            CfDel.execute(&mut data, arguments!["cf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            CfExists.execute(&mut data, arguments!["cf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            CfDel.execute(&mut data, arguments!["cf", "foo"])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            CfExists.execute(&mut data, arguments!["cf", "foo"])
+        );
+    }
+
+    #[test]
+    fn reserve_rejects_huge_filters() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Error(TOO_LARGE),
+            CfReserve.execute(&mut data, arguments!["cf", "18446744073709551615"])
+        );
+        assert_eq!(
+            Response::Error("Bad expansion"),
+            CfReserve.execute(
+                &mut data,
+                arguments!["cf", "100", "EXPANSION", "18446744073709551615"]
+            )
+        );
+        assert_eq!(
+            Response::Error(TOO_LARGE),
+            CfInsert.execute(
+                &mut data,
+                arguments!["cf", "CAPACITY", "1099511627776", "ITEMS", "foo"]
+            )
+        );
+        assert!(!data.contains_key(&BulkString::Filled(b"cf".to_vec())));
+    }
+
+    #[test]
+    fn insert_nx() {
+        let mut data = Data::new();
+
+        let response = CfInsertNx.execute(&mut data, arguments!["cf", "ITEMS", "a", "b", "a"]);
+
+        assert_eq!(
+            Response::Array(vec![
+                Response::Integer(1),
+                Response::Integer(1),
+                Response::Integer(0)
+            ]),
+            response
+        );
+    }
+
+    #[test]
+    fn insert_no_create() {
+        let mut data = Data::new();
+
+        let response = CfInsert.execute(&mut data, arguments!["cf", "NOCREATE", "ITEMS", "a"]);
+
+        assert_eq!(Response::Error("not found"), response);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn del_on_missing_key() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Error("Not found"),
+            CfDel.execute(&mut data, arguments!["cf", "foo"])
+        );
+    }
+}
@@ -0,0 +1,569 @@
+use std::net::IpAddr;
+
+use super::{bulk_string, bytes, keyword, number, Command, Data, Response};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::cluster::{self, parse_slot, FailoverMode, Node, SlotState, BUS_PORT_OFFSET};
+use crate::crc16::SLOTS;
+use crate::database::now_ms;
+
+pub(crate) struct Cluster;
+
+/// Parses slot numbers, checking that they're in range.
+fn slots(arguments: &[Value]) -> Result<Vec<u16>, Response> {
+    arguments
+        .iter()
+        .map(|argument| {
+            bytes(argument)
+                .and_then(|slot| parse_slot(&String::from_utf8_lossy(slot)))
+                .ok_or(Response::Error("Invalid or out of range slot"))
+        })
+        .collect()
+}
+
+/// Parses pairs of slot numbers into the slots in each range, both ends included.
+fn slot_ranges(arguments: &[Value]) -> Result<Vec<u16>, Response> {
+    if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+        return Err(Response::Error("wrong number of arguments"));
+    }
+
+    let bounds = slots(arguments)?;
+    let mut slots = Vec::new();
+
+    for pair in bounds.chunks_exact(2) {
+        let [start, end] = [pair[0], pair[1]];
+
+        if start > end {
+            return Err(Response::OwnedError(format!(
+                "start slot number {start} is greater than end slot number {end}"
+            )));
+        }
+
+        slots.extend(start..=end);
+    }
+
+    Ok(slots)
+}
+
+/// The fields of `CLUSTER INFO`.
+fn info(data: &Data) -> String {
+    let cluster = data.cluster();
+    let myself = cluster.myself();
+    let (pfail, fail) = cluster.failing_slots();
+    // A replica reports the epoch of its primary, whose slots it would take over.
+    let my_epoch = myself
+        .primary()
+        .and_then(|id| cluster.node(id))
+        .unwrap_or(myself)
+        .config_epoch();
+    let fields = [
+        (
+            "cluster_state",
+            if cluster.is_ok() { "ok" } else { "fail" }.to_string(),
+        ),
+        (
+            "cluster_slots_assigned",
+            cluster.assigned_slots().to_string(),
+        ),
+        (
+            "cluster_slots_ok",
+            (cluster.assigned_slots() - pfail - fail).to_string(),
+        ),
+        ("cluster_slots_pfail", pfail.to_string()),
+        ("cluster_slots_fail", fail.to_string()),
+        ("cluster_known_nodes", cluster.nodes().count().to_string()),
+        ("cluster_size", cluster.size().to_string()),
+        ("cluster_current_epoch", cluster.current_epoch().to_string()),
+        ("cluster_my_epoch", my_epoch.to_string()),
+    ];
+
+    fields
+        .iter()
+        .map(|(name, value)| format!("{name}:{value}\r\n"))
+        .collect()
+}
+
+/// A node as `CLUSTER SLOTS` describes it: its IP, port and ID, followed by its hostname and other metadata, which
+/// there are none of.
+fn slots_node(node: &Node) -> Response {
+    Response::Array(vec![
+        bulk_string(node.ip()),
+        Response::Integer(node.port() as i64),
+        bulk_string(node.id()),
+        Response::Array(Vec::new()),
+    ])
+}
+
+/// The ranges of slots with the nodes serving them, primary first, as `CLUSTER SLOTS` replies.
+fn slots_reply(data: &Data) -> Response {
+    let cluster = data.cluster();
+
+    Response::Array(
+        cluster
+            .slot_ranges()
+            .into_iter()
+            .map(|(start, end, primary)| {
+                let mut entry = vec![
+                    Response::Integer(start as i64),
+                    Response::Integer(end as i64),
+                    slots_node(primary),
+                ];
+
+                entry.extend(cluster.replicas_of(primary.id()).map(slots_node));
+
+                Response::Array(entry)
+            })
+            .collect(),
+    )
+}
+
+/// A node as `CLUSTER SHARDS` describes it.
+fn shards_node(data: &Data, node: &Node) -> Response {
+    let cluster = data.cluster();
+    // Only this node's replication offset is known.
+    let offset = if node.id() == cluster.myself().id() {
+        data.replication().offset()
+    } else {
+        0
+    };
+
+    Response::Array(vec![
+        bulk_string("id"),
+        bulk_string(node.id()),
+        bulk_string("port"),
+        Response::Integer(node.port() as i64),
+        bulk_string("ip"),
+        bulk_string(node.ip()),
+        bulk_string("endpoint"),
+        bulk_string(node.ip()),
+        bulk_string("role"),
+        bulk_string(if node.primary().is_some() {
+            "replica"
+        } else {
+            "master"
+        }),
+        bulk_string("replication-offset"),
+        Response::Integer(offset as i64),
+        bulk_string("health"),
+        bulk_string(if node.has_failed() { "fail" } else { "online" }),
+    ])
+}
+
+/// Each primary with the slots it serves and its replicas, as `CLUSTER SHARDS` replies.
+fn shards_reply(data: &Data) -> Response {
+    let cluster = data.cluster();
+
+    Response::Array(
+        cluster
+            .nodes()
+            .filter(|node| node.primary().is_none())
+            .map(|primary| {
+                let slots = cluster
+                    .ranges(primary.id())
+                    .into_iter()
+                    .flat_map(|(start, end)| {
+                        [
+                            Response::Integer(start as i64),
+                            Response::Integer(end as i64),
+                        ]
+                    })
+                    .collect();
+                let mut nodes = vec![shards_node(data, primary)];
+
+                nodes.extend(
+                    cluster
+                        .replicas_of(primary.id())
+                        .map(|replica| shards_node(data, replica)),
+                );
+
+                Response::Array(vec![
+                    bulk_string("slots"),
+                    Response::Array(slots),
+                    bulk_string("nodes"),
+                    Response::Array(nodes),
+                ])
+            })
+            .collect(),
+    )
+}
+
+impl Command for Cluster {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !data.cluster().is_enabled() {
+            return Response::Error("This instance has cluster support disabled");
+        }
+
+        let Some(subcommand) = keyword(&arguments[0]) else {
+            return Response::Error("unknown subcommand or wrong number of arguments");
+        };
+
+        match (subcommand.as_str(), &arguments[1..]) {
+            ("INFO", []) => Response::BulkString(BulkString::Filled(info(data).into_bytes())),
+            ("MYID", []) => bulk_string(data.cluster().myself().id()),
+            ("NODES", []) => {
+                let cluster = data.cluster();
+                let nodes: String = cluster
+                    .nodes()
+                    .map(|node| cluster.describe(node) + "\n")
+                    .collect();
+
+                Response::BulkString(BulkString::Filled(nodes.into_bytes()))
+            }
+            ("SLOTS", []) => slots_reply(data),
+            ("SHARDS", []) => shards_reply(data),
+            ("REPLICAS" | "SLAVES", [id]) => {
+                let cluster = data.cluster();
+                let id = String::from_utf8_lossy(bytes(id).unwrap_or_default());
+
+                match cluster.node(&id) {
+                    Some(node) if node.primary().is_none() => Response::Array(
+                        cluster
+                            .replicas_of(node.id())
+                            .map(|replica| bulk_string(&cluster.describe(replica)))
+                            .collect(),
+                    ),
+                    Some(_) => Response::Error("The specified node is not a master"),
+                    None => Response::OwnedError(format!("Unknown node {id}")),
+                }
+            }
+            ("ADDSLOTS" | "DELSLOTS", slots_arguments) if !slots_arguments.is_empty() => {
+                let slots = match slots(slots_arguments) {
+                    Ok(slots) => slots,
+                    Err(response) => return response,
+                };
+
+                change_slots(data, &subcommand, &slots)
+            }
+            ("ADDSLOTSRANGE" | "DELSLOTSRANGE", ranges) => {
+                let slots = match slot_ranges(ranges) {
+                    Ok(slots) => slots,
+                    Err(response) => return response,
+                };
+
+                change_slots(data, &subcommand, &slots)
+            }
+            ("SAVECONFIG", []) => match data.cluster().save() {
+                Ok(()) => Response::SimpleString("OK"),
+                Err(error) => Response::OwnedError(error.to_string()),
+            },
+            ("MEET", [ip, port] | [ip, port, _]) => {
+                let ip = String::from_utf8_lossy(bytes(ip).unwrap_or_default()).into_owned();
+                let Some(port) = number::<u16>(port) else {
+                    return Response::Error("Invalid TCP base port specified");
+                };
+                let bus_port = match arguments.get(3) {
+                    Some(bus_port) => number::<u16>(bus_port),
+                    None => port.checked_add(BUS_PORT_OFFSET),
+                };
+                let Some(bus_port) = bus_port else {
+                    return Response::Error("Invalid TCP bus port specified");
+                };
+
+                if ip.parse::<IpAddr>().is_err() {
+                    return Response::OwnedError(format!(
+                        "Invalid node address specified: {ip}:{port}"
+                    ));
+                }
+
+                data.cluster_mut().meet(ip, bus_port, now_ms());
+
+                Response::SimpleString("OK")
+            }
+            ("FORGET", [id]) => {
+                let id = String::from_utf8_lossy(bytes(id).unwrap_or_default());
+
+                match data.cluster_mut().forget(&id, now_ms()) {
+                    Ok(()) => Response::SimpleString("OK"),
+                    Err(message) => Response::OwnedError(message),
+                }
+            }
+            ("REPLICATE", [id]) => {
+                let id = String::from_utf8_lossy(bytes(id).unwrap_or_default());
+
+                if data.cluster().myself().primary().is_none() && !data.is_empty() {
+                    return Response::Error(
+                        "To set a master the node must be empty and without assigned slots.",
+                    );
+                }
+
+                match data.cluster_mut().replicate(&id) {
+                    Ok(()) => {
+                        data.follow_cluster_role();
+
+                        Response::SimpleString("OK")
+                    }
+                    Err(message) => Response::OwnedError(message),
+                }
+            }
+            ("FAILOVER", options) => {
+                let mode = match options {
+                    [] => FailoverMode::Default,
+                    [option] => match keyword(option).as_deref() {
+                        Some("FORCE") => FailoverMode::Force,
+                        Some("TAKEOVER") => FailoverMode::Takeover,
+                        _ => return Response::Error("syntax error"),
+                    },
+                    _ => return Response::Error("syntax error"),
+                };
+
+                match data.cluster_mut().failover(mode, now_ms()) {
+                    Ok(()) => {
+                        data.follow_cluster_role();
+
+                        Response::SimpleString("OK")
+                    }
+                    Err(message) => Response::Error(message),
+                }
+            }
+            ("KEYSLOT", [key]) => {
+                let Value::BulkString(key) = key;
+
+                Response::Integer(cluster::slot(key) as i64)
+            }
+            ("COUNTKEYSINSLOT", [slot]) => match number::<i64>(slot) {
+                Some(slot) if (0..SLOTS as i64).contains(&slot) => {
+                    Response::Integer(data.count_keys_in_slot(slot as u16) as i64)
+                }
+                Some(_) => Response::Error("Invalid slot"),
+                None => Response::Error("value is not an integer or out of range"),
+            },
+            ("GETKEYSINSLOT", [slot, count]) => match (number::<i64>(slot), number::<i64>(count)) {
+                (Some(slot), Some(count)) if (0..SLOTS as i64).contains(&slot) && count >= 0 => {
+                    Response::Array(
+                        data.keys_in_slot(slot as u16, count as usize)
+                            .into_iter()
+                            .map(Response::BulkString)
+                            .collect(),
+                    )
+                }
+                (Some(_), Some(_)) => Response::Error("Invalid slot or number of keys"),
+                _ => Response::Error("value is not an integer or out of range"),
+            },
+            ("SETSLOT", [slot, action, rest @ ..]) => {
+                let Some(slot) =
+                    bytes(slot).and_then(|slot| parse_slot(&String::from_utf8_lossy(slot)))
+                else {
+                    return Response::Error("Invalid or out of range slot");
+                };
+                let id = |id: &Value| {
+                    String::from_utf8_lossy(bytes(id).unwrap_or_default()).into_owned()
+                };
+                let state = match (keyword(action).as_deref(), rest) {
+                    (Some("MIGRATING"), [node]) => SlotState::Migrating(id(node)),
+                    (Some("IMPORTING"), [node]) => SlotState::Importing(id(node)),
+                    (Some("STABLE"), []) => SlotState::Stable,
+                    (Some("NODE"), [node]) => SlotState::Node(id(node)),
+                    _ => return Response::Error(
+                        "Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+                    ),
+                };
+                let keys = data.count_keys_in_slot(slot);
+
+                match data.cluster_mut().set_slot(slot, state, keys) {
+                    Ok(()) => Response::SimpleString("OK"),
+                    Err(message) => Response::OwnedError(message),
+                }
+            }
+            ("COUNT-FAILURE-REPORTS", [id]) => {
+                let id = String::from_utf8_lossy(bytes(id).unwrap_or_default());
+
+                match data.cluster().node(&id) {
+                    Some(node) => Response::Integer(node.failure_reports() as i64),
+                    None => Response::OwnedError(format!("Unknown node {id}")),
+                }
+            }
+            _ => Response::Error("unknown subcommand or wrong number of arguments"),
+        }
+    }
+}
+
+/// Assigns or unassigns slots, for the `ADDSLOTS` and `DELSLOTS` subcommands and their `RANGE` forms.
+fn change_slots(data: &mut Data, subcommand: &str, slots: &[u16]) -> Response {
+    let cluster = data.cluster_mut();
+    let result = if subcommand.starts_with("ADD") {
+        cluster.add_slots(slots)
+    } else {
+        cluster.delete_slots(slots)
+    };
+
+    match result {
+        Ok(()) => Response::SimpleString("OK"),
+        Err(message) => Response::OwnedError(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::object::Object;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn cluster() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Error("This instance has cluster support disabled"),
+            Cluster.execute(&mut data, arguments!["INFO"])
+        );
+
+        let path = env::temp_dir().join(format!("red-{}-cluster-command.conf", process::id()));
+
+        fs::write(
+            &path,
+            "aaaa 127.0.0.1:7001@17001 myself,master - 0 0 1 connected 0-99\n\
+            bbbb 127.0.0.1:7002@17002 slave aaaa 0 0 1 connected\n",
+        )
+        .unwrap();
+        data.cluster_mut().enable(path, 7001).unwrap();
+        data.database_mut(0).index_slots();
+
+        assert_eq!(
+            bulk_string("aaaa"),
+            Cluster.execute(&mut data, arguments!["myid"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Cluster.execute(&mut data, arguments!["ADDSLOTSRANGE", "100", "16383"])
+        );
+        assert_eq!(
+            Response::Error("Invalid or out of range slot"),
+            Cluster.execute(&mut data, arguments!["ADDSLOTS", "16384"])
+        );
+        assert_eq!(
+            Response::OwnedError(
+                "start slot number 2 is greater than end slot number 1".to_string()
+            ),
+            Cluster.execute(&mut data, arguments!["DELSLOTSRANGE", "2", "1"])
+        );
+
+        let Response::BulkString(BulkString::Filled(info)) =
+            Cluster.execute(&mut data, arguments!["INFO"])
+        else {
+            panic!("expected a bulk string");
+        };
+        let info = String::from_utf8(info).unwrap();
+
+        assert!(info.starts_with("cluster_state:ok\r\ncluster_slots_assigned:16384\r\n"));
+        assert!(info.contains("\r\ncluster_known_nodes:2\r\ncluster_size:1\r\n"));
+
+        let node = |id: &str, port: i64| {
+            Response::Array(vec![
+                bulk_string("127.0.0.1"),
+                Response::Integer(port),
+                bulk_string(id),
+                Response::Array(Vec::new()),
+            ])
+        };
+
+        assert_eq!(
+            Response::Array(vec![Response::Array(vec![
+                Response::Integer(0),
+                Response::Integer(16383),
+                node("aaaa", 7001),
+                node("bbbb", 7002),
+            ])]),
+            Cluster.execute(&mut data, arguments!["SLOTS"])
+        );
+        assert_eq!(
+            Response::BulkString(bulk_string!(
+                "aaaa 127.0.0.1:7001@17001 myself,master - 0 0 1 connected 0-16383\n\
+                bbbb 127.0.0.1:7002@17002 slave aaaa 0 0 1 connected\n"
+            )),
+            Cluster.execute(&mut data, arguments!["NODES"])
+        );
+        assert_eq!(
+            Response::Error("The specified node is not a master"),
+            Cluster.execute(&mut data, arguments!["REPLICAS", "bbbb"])
+        );
+        assert_eq!(
+            Response::OwnedError("Invalid node address specified: localhost:7003".to_string()),
+            Cluster.execute(&mut data, arguments!["MEET", "localhost", "7003"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Cluster.execute(&mut data, arguments!["MEET", "127.0.0.1", "7003"])
+        );
+        assert_eq!(
+            Response::Error("You should send CLUSTER FAILOVER to a replica"),
+            Cluster.execute(&mut data, arguments!["FAILOVER", "FORCE"])
+        );
+        assert_eq!(
+            Response::OwnedError("I can only replicate a master, not a replica.".to_string()),
+            Cluster.execute(&mut data, arguments!["REPLICATE", "bbbb"])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            Cluster.execute(&mut data, arguments!["COUNT-FAILURE-REPORTS", "bbbb"])
+        );
+
+        data.insert(bulk_string!("a"), Object::String(bulk_string!("1")));
+
+        assert_eq!(
+            Response::Integer(15495),
+            Cluster.execute(&mut data, arguments!["KEYSLOT", "a"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            Cluster.execute(&mut data, arguments!["COUNTKEYSINSLOT", "15495"])
+        );
+        assert_eq!(
+            Response::Error("Invalid slot"),
+            Cluster.execute(&mut data, arguments!["COUNTKEYSINSLOT", "16384"])
+        );
+        assert_eq!(
+            Response::Array(vec![bulk_string("a")]),
+            Cluster.execute(&mut data, arguments!["GETKEYSINSLOT", "15495", "10"])
+        );
+        assert_eq!(
+            Response::Error("Invalid slot or number of keys"),
+            Cluster.execute(&mut data, arguments!["GETKEYSINSLOT", "15495", "-1"])
+        );
+        assert_eq!(
+            Response::OwnedError("Target node is not a master".to_string()),
+            Cluster.execute(
+                &mut data,
+                arguments!["SETSLOT", "15495", "MIGRATING", "bbbb"]
+            )
+        );
+        assert_eq!(
+            Response::OwnedError("I'm already the owner of hash slot 15495".to_string()),
+            Cluster.execute(
+                &mut data,
+                arguments!["SETSLOT", "15495", "IMPORTING", "aaaa"]
+            )
+        );
+        assert_eq!(
+            Response::OwnedError("Unknown node cccc".to_string()),
+            Cluster.execute(&mut data, arguments!["SETSLOT", "15495", "NODE", "cccc"])
+        );
+        assert_eq!(
+            Response::Error(
+                "Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP"
+            ),
+            Cluster.execute(&mut data, arguments!["SETSLOT", "15495", "STABLE", "aaaa"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Cluster.execute(&mut data, arguments!["SETSLOT", "15495", "STABLE"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Cluster.execute(&mut data, arguments!["FORGET", "bbbb"])
+        );
+        assert_eq!(1, data.cluster().nodes().count());
+    }
+}
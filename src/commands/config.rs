@@ -0,0 +1,291 @@
+use std::str;
+
+use super::{bulk_string, bytes, keyword, Command, Data, Response};
+use crate::aof::FsyncPolicy;
+use crate::array::Value;
+use crate::config::parse_memory;
+use crate::glob;
+use crate::notify;
+use crate::rdb;
+use crate::scripting;
+
+pub(crate) struct Config;
+
+/// The parameters that `CONFIG GET` reports, in the order they're reported.
+const PARAMETERS: [&str; 17] = [
+    "aof-use-rdb-preamble",
+    "appenddirname",
+    "appendfilename",
+    "appendfsync",
+    "appendonly",
+    "auto-aof-rewrite-min-size",
+    "auto-aof-rewrite-percentage",
+    "busy-reply-threshold",
+    "databases",
+    "dbfilename",
+    "dir",
+    "lua-time-limit",
+    "notify-keyspace-events",
+    "port",
+    "repl-backlog-size",
+    "replica-read-only",
+    "save",
+];
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn parameter_value(data: &Data, name: &str) -> String {
+    match name {
+        "aof-use-rdb-preamble" => yes_no(data.aof().use_rdb_preamble()),
+        "appenddirname" => data.aof().dirname().to_string(),
+        "appendfilename" => data.aof().filename().to_string(),
+        "appendfsync" => data.aof().fsync().name().to_string(),
+        "appendonly" => yes_no(data.aof().is_enabled()),
+        "auto-aof-rewrite-min-size" => data.aof().rewrite_min_size().to_string(),
+        "auto-aof-rewrite-percentage" => data.aof().rewrite_percentage().to_string(),
+        "busy-reply-threshold" | "lua-time-limit" => scripting::busy_reply_threshold().to_string(),
+        "databases" => data.database_count().to_string(),
+        "dbfilename" => data
+            .snapshots()
+            .path()
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+        "dir" => match data.snapshots().path().parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.display().to_string(),
+            _ => ".".to_string(),
+        },
+        "notify-keyspace-events" => notify::format_flags(data.notify_flags()),
+        "port" => data.replication().port().to_string(),
+        "repl-backlog-size" => data.replication().backlog_size().to_string(),
+        "replica-read-only" => yes_no(data.replication().read_only()),
+        "save" => rdb::format_save_points(data.snapshots().save_points()),
+        _ => unreachable!("unknown parameter {name}"),
+    }
+}
+
+impl Command for Config {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let Some(subcommand) = arguments.first().and_then(keyword) else {
+            return Response::Error("wrong number of arguments");
+        };
+
+        match subcommand.as_str() {
+            "GET" if arguments.len() >= 2 => {
+                let mut reply = Vec::new();
+
+                for name in PARAMETERS {
+                    let matches = arguments[1..].iter().any(|pattern| {
+                        bytes(pattern)
+                            .is_some_and(|pattern| glob::matches(pattern, name.as_bytes(), true))
+                    });
+
+                    if matches {
+                        reply.push(bulk_string(name));
+                        reply.push(bulk_string(&parameter_value(data, name)));
+                    }
+                }
+
+                Response::Array(reply)
+            }
+            "SET" if arguments.len() >= 3 && arguments.len() % 2 == 1 => {
+                let mut notify_flags = None;
+                let mut busy_reply_threshold = None;
+                let mut save_points = None;
+                let mut fsync = None;
+                let mut use_rdb_preamble = None;
+                let mut rewrite_percentage = None;
+                let mut rewrite_min_size = None;
+                let mut read_only = None;
+                let mut backlog_size = None;
+
+                // Every value is checked before any is applied, so that an error leaves the configuration unchanged.
+                for pair in arguments[1..].chunks_exact(2) {
+                    let name = keyword(&pair[0]).map(|name| name.to_lowercase());
+                    let value = bytes(&pair[1]).and_then(|value| str::from_utf8(value).ok());
+
+                    match (name.as_deref(), value) {
+                        (Some("notify-keyspace-events"), Some(value)) => {
+                            match notify::parse_flags(value) {
+                                Some(flags) => notify_flags = Some(flags),
+                                None => return Response::Error("invalid argument"),
+                            }
+                        }
+                        (Some("busy-reply-threshold" | "lua-time-limit"), Some(value)) => {
+                            match value.parse::<u64>() {
+                                Ok(milliseconds) => busy_reply_threshold = Some(milliseconds),
+                                Err(_) => return Response::Error("invalid argument"),
+                            }
+                        }
+                        (Some("save"), Some(value)) => match rdb::parse_save_points(value) {
+                            Some(points) => save_points = Some(points),
+                            None => return Response::Error("invalid argument"),
+                        },
+                        (Some("appendfsync"), Some(value)) => match FsyncPolicy::parse(value) {
+                            Some(policy) => fsync = Some(policy),
+                            None => return Response::Error("invalid argument"),
+                        },
+                        (Some("aof-use-rdb-preamble"), Some(value)) => {
+                            match value.to_lowercase().as_str() {
+                                "yes" => use_rdb_preamble = Some(true),
+                                "no" => use_rdb_preamble = Some(false),
+                                _ => return Response::Error("invalid argument"),
+                            }
+                        }
+                        (Some("repl-backlog-size"), Some(value)) => match parse_memory(value) {
+                            Some(size) if size > 0 => backlog_size = Some(size),
+                            _ => return Response::Error("invalid argument"),
+                        },
+                        (Some("replica-read-only"), Some(value)) => {
+                            match value.to_lowercase().as_str() {
+                                "yes" => read_only = Some(true),
+                                "no" => read_only = Some(false),
+                                _ => return Response::Error("invalid argument"),
+                            }
+                        }
+                        (Some("auto-aof-rewrite-percentage"), Some(value)) => {
+                            match value.parse::<u64>() {
+                                Ok(percentage) => rewrite_percentage = Some(percentage),
+                                Err(_) => return Response::Error("invalid argument"),
+                            }
+                        }
+                        (Some("auto-aof-rewrite-min-size"), Some(value)) => {
+                            match parse_memory(value) {
+                                Some(size) => rewrite_min_size = Some(size),
+                                None => return Response::Error("invalid argument"),
+                            }
+                        }
+                        (
+                            Some(
+                                "databases" | "dbfilename" | "dir" | "appendonly"
+                                | "appendfilename" | "appenddirname" | "port",
+                            ),
+                            _,
+                        ) => return Response::Error("can't set immutable config"),
+                        _ => {
+                            return Response::Error(
+                                "unknown option or number of arguments for CONFIG SET",
+                            )
+                        }
+                    }
+                }
+
+                if let Some(flags) = notify_flags {
+                    data.set_notify_flags(flags);
+                }
+
+                if let Some(milliseconds) = busy_reply_threshold {
+                    scripting::set_busy_reply_threshold(milliseconds);
+                }
+
+                if let Some(points) = save_points {
+                    data.snapshots_mut().set_save_points(points);
+                }
+
+                if let Some(policy) = fsync {
+                    data.aof_mut().set_fsync(policy);
+                }
+
+                if let Some(use_rdb_preamble) = use_rdb_preamble {
+                    data.aof_mut().set_use_rdb_preamble(use_rdb_preamble);
+                }
+
+                if let Some(read_only) = read_only {
+                    data.replication_mut().set_read_only(read_only);
+                }
+
+                if let Some(size) = backlog_size {
+                    data.replication_mut().set_backlog_size(size);
+                }
+
+                if rewrite_percentage.is_some() || rewrite_min_size.is_some() {
+                    let aof = data.aof_mut();
+
+                    aof.set_rewrite_growth(
+                        rewrite_percentage.unwrap_or(aof.rewrite_percentage()),
+                        rewrite_min_size.unwrap_or(aof.rewrite_min_size()),
+                    );
+                }
+
+                Response::SimpleString("OK")
+            }
+            _ => Response::Error("unknown subcommand or wrong number of arguments"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk_string::BulkString;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn get_and_set() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Config.execute(
+                &mut data,
+                arguments!["SET", "notify-keyspace-events", "xKE"]
+            )
+        );
+        assert_eq!(
+            Response::Array(vec![
+                bulk_string("notify-keyspace-events"),
+                bulk_string("xKE"),
+            ]),
+            Config.execute(&mut data, arguments!["GET", "notify-*"])
+        );
+        assert_eq!(
+            Response::Array(vec![bulk_string("databases"), bulk_string("16")]),
+            Config.execute(&mut data, arguments!["get", "DATABASES"])
+        );
+        assert_eq!(
+            Response::Error("invalid argument"),
+            Config.execute(&mut data, arguments!["SET", "notify-keyspace-events", "Q"])
+        );
+        assert_eq!(
+            Response::Error("can't set immutable config"),
+            Config.execute(&mut data, arguments!["SET", "databases", "4"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Config.execute(&mut data, arguments!["SET", "save", "900 1 60 100"])
+        );
+        assert_eq!(
+            Response::Array(vec![bulk_string("save"), bulk_string("900 1 60 100")]),
+            Config.execute(&mut data, arguments!["GET", "save"])
+        );
+        assert_eq!(
+            Response::Error("invalid argument"),
+            Config.execute(&mut data, arguments!["SET", "save", "900"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Config.execute(&mut data, arguments!["SET", "appendfsync", "always"])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                bulk_string("appendfsync"),
+                bulk_string("always"),
+                bulk_string("appendonly"),
+                bulk_string("no"),
+            ]),
+            Config.execute(&mut data, arguments!["GET", "appendfs*", "appendonly"])
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use super::{keyword, number, Command, Data, Response};
+use crate::array::Value;
+use crate::database::Database;
+use crate::lazy_free;
+
+pub(crate) struct Select;
+pub(crate) struct SwapDb;
+pub(crate) struct FlushDb;
+pub(crate) struct FlushAll;
+
+/// Parses a database index, checking that it's in range.
+pub(crate) fn database_index(data: &Data, argument: &Value) -> Result<usize, Response> {
+    match number::<i64>(argument) {
+        Some(index) if (0..data.database_count() as i64).contains(&index) => Ok(index as usize),
+        Some(_) => Err(Response::Error("DB index is out of range")),
+        None => Err(Response::Error("value is not an integer or out of range")),
+    }
+}
+
+impl Command for Select {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 1 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        match database_index(data, &arguments[0]) {
+            Ok(index) if index != 0 && data.cluster().is_enabled() => {
+                Response::Error("SELECT is not allowed in cluster mode")
+            }
+            Ok(index) => {
+                data.select(index);
+
+                Response::SimpleString("OK")
+            }
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for SwapDb {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        if data.cluster().is_enabled() {
+            return Response::Error("SWAPDB is not allowed in cluster mode");
+        }
+
+        let a = match database_index(data, &arguments[0]) {
+            Ok(index) => index,
+            Err(_) => return Response::Error("invalid first DB index"),
+        };
+        let b = match database_index(data, &arguments[1]) {
+            Ok(index) => index,
+            Err(_) => return Response::Error("invalid second DB index"),
+        };
+
+        data.swap(a, b);
+
+        Response::SimpleString("OK")
+    }
+}
+
+/// Parses the `ASYNC` or `SYNC` option of the flush commands, returning whether to free the data in the background.
+fn flush_mode(arguments: &[Value]) -> Result<bool, Response> {
+    match arguments {
+        [] => Ok(false),
+        [mode] => match keyword(mode).as_deref() {
+            Some("ASYNC") => Ok(true),
+            Some("SYNC") => Ok(false),
+            _ => Err(Response::Error("syntax error")),
+        },
+        _ => Err(Response::Error("syntax error")),
+    }
+}
+
+fn flush(database: &mut Database, asynchronous: bool) {
+    let old = database.clear();
+
+    if asynchronous {
+        lazy_free::free_database(old);
+    }
+}
+
+impl Command for FlushDb {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        match flush_mode(arguments) {
+            Ok(asynchronous) => {
+                flush(data, asynchronous);
+
+                Response::SimpleString("OK")
+            }
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for FlushAll {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        match flush_mode(arguments) {
+            Ok(asynchronous) => {
+                for database in data.databases_mut() {
+                    flush(database, asynchronous);
+                }
+
+                Response::SimpleString("OK")
+            }
+            Err(e) => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk_string::BulkString;
+    use crate::object::Object;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn select_and_swap() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Select.execute(&mut data, arguments!["3"])
+        );
+        assert!(!data.contains_key(&bulk_string!("a")));
+        assert_eq!(
+            Response::Error("DB index is out of range"),
+            Select.execute(&mut data, arguments!["16"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            SwapDb.execute(&mut data, arguments!["0", "3"])
+        );
+        assert!(data.contains_key(&bulk_string!("a")));
+    }
+
+    #[test]
+    fn flush() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+
+        data.select(1);
+        data.insert(bulk_string!("b"), Object::String(bulk_string!("2")));
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            FlushDb.execute(&mut data, arguments!["ASYNC"])
+        );
+        assert!(data.is_empty());
+        assert_eq!(1, data.database_mut(0).len());
+        assert_eq!(
+            Response::SimpleString("OK"),
+            FlushAll.execute(&mut data, arguments!["SYNC"])
+        );
+        assert!(data.database_mut(0).is_empty());
+        assert_eq!(
+            Response::Error("syntax error"),
+            FlushAll.execute(&mut data, arguments!["LATER"])
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use super::{Command, Data, Response};
 use crate::array::Value;
 use crate::bulk_string::BulkString;
+use crate::notify;
 
 pub(crate) struct Del;
 
@@ -16,6 +17,7 @@ impl Command for Del {
             let key = bulk_string_or_error!(argument);
 
             if data.remove(key).is_some() {
+                data.notify(notify::GENERIC, "del", key);
                 deleted += 1;
             }
         }
@@ -0,0 +1,602 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::{bytes, keyword, number, Command, Data, Response};
+use crate::aof;
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::database::now_ms;
+use crate::notify;
+use crate::rdb;
+
+/// How long `MIGRATE` waits for the target instance when it's given no timeout, in milliseconds, like Redis.
+const DEFAULT_MIGRATE_TIMEOUT: u64 = 1000;
+
+pub(crate) struct Dump;
+pub(crate) struct Migrate;
+pub(crate) struct Restore;
+
+impl Command for Dump {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        match data.get(key) {
+            Some(object) => Response::BulkString(BulkString::Filled(rdb::dump(object))),
+            None => Response::BulkString(BulkString::Null),
+        }
+    }
+}
+
+impl Command for Restore {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let key = bulk_string_or_error!(&arguments[0]);
+        let mut replace = false;
+        let mut absolute_ttl = false;
+        // There's no eviction, so the idle time and access frequency are checked but not kept.
+        let mut idle_time_or_frequency = false;
+        let mut options = arguments[3..].iter();
+
+        while let Some(option) = options.next() {
+            match keyword(option).as_deref() {
+                Some("REPLACE") => replace = true,
+                Some("ABSTTL") => absolute_ttl = true,
+                Some("IDLETIME") if !idle_time_or_frequency => {
+                    match options.next().map(number::<i64>) {
+                        Some(Some(seconds)) if seconds >= 0 => {}
+                        Some(Some(_)) => {
+                            return Response::Error("Invalid IDLETIME value, must be >= 0")
+                        }
+                        Some(None) => {
+                            return Response::Error("value is not an integer or out of range")
+                        }
+                        None => return Response::Error("syntax error"),
+                    }
+
+                    idle_time_or_frequency = true;
+                }
+                Some("FREQ") if !idle_time_or_frequency => {
+                    match options.next().map(number::<i64>) {
+                        Some(Some(0..=255)) => {}
+                        Some(Some(_)) => {
+                            return Response::Error("Invalid FREQ value, must be >= 0 and <= 255")
+                        }
+                        Some(None) => {
+                            return Response::Error("value is not an integer or out of range")
+                        }
+                        None => return Response::Error("syntax error"),
+                    }
+
+                    idle_time_or_frequency = true;
+                }
+                _ => return Response::Error("syntax error"),
+            }
+        }
+
+        let exists = data.contains_key(key);
+
+        if exists && !replace {
+            return Response::Error("BUSYKEY Target key name already exists.");
+        }
+
+        let ttl = match number::<i64>(&arguments[1]) {
+            Some(ttl) if ttl >= 0 => ttl as u64,
+            Some(_) => return Response::Error("Invalid TTL value, must be >= 0"),
+            None => return Response::Error("value is not an integer or out of range"),
+        };
+
+        let object = match rdb::undump(bytes(&arguments[2]).unwrap_or_default()) {
+            Ok(object) => object,
+            Err(message) => return Response::Error(message),
+        };
+
+        let expires_at = match ttl {
+            0 => None,
+            ttl if absolute_ttl => Some(ttl),
+            ttl => Some(now_ms().saturating_add(ttl)),
+        };
+
+        // A key restored with an expiry time that's already passed is deleted instead.
+        if expires_at.is_some_and(|at| at <= now_ms()) {
+            if exists {
+                data.remove(key);
+                data.notify(notify::GENERIC, "del", key);
+            }
+
+            return Response::SimpleString("OK");
+        }
+
+        data.insert(key.clone(), object);
+
+        if let Some(at) = expires_at {
+            data.set_expires_at(key, at);
+        }
+
+        data.notify(notify::GENERIC, "restore", key);
+
+        Response::SimpleString("OK")
+    }
+}
+
+impl Command for Migrate {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let migration = match Migration::prepare(data, arguments) {
+            Ok(migration) => migration,
+            Err(response) => return response,
+        };
+        let replies = migration.transfer();
+
+        migration.finish(data, replies).0
+    }
+}
+
+/// A `MIGRATE` in progress: the commands that send its keys to the target instance, made from a copy of the keys
+/// taken while holding the lock. Clients run it in three steps, so that the lock isn't held while the keys are sent,
+/// whereas within a transaction or script [`Migrate`] runs all three at once.
+pub(crate) struct Migration {
+    host: String,
+    port: u16,
+    timeout: Duration,
+    copy: bool,
+    commands: Vec<Vec<Vec<u8>>>,
+    /// The keys sent, whose `RESTORE` commands are the last of `commands`.
+    keys: Vec<BulkString>,
+}
+
+impl Migration {
+    /// Parses the arguments of `MIGRATE` and serializes the keys to send. Returns the reply instead if there's nothing
+    /// to send.
+    pub(crate) fn prepare(data: &mut Data, arguments: &[Value]) -> Result<Migration, Response> {
+        let host = String::from_utf8_lossy(bytes(&arguments[0]).unwrap_or_default()).into_owned();
+        let (Some(port), Some(database), Some(timeout)) = (
+            number::<u16>(&arguments[1]),
+            number::<u64>(&arguments[3]),
+            number::<i64>(&arguments[4]),
+        ) else {
+            return Err(Response::Error("value is not an integer or out of range"));
+        };
+        let timeout = match timeout {
+            timeout if timeout <= 0 => DEFAULT_MIGRATE_TIMEOUT,
+            timeout => timeout as u64,
+        };
+        let mut copy = false;
+        let mut replace = false;
+        let mut auth = None;
+        let mut keys = None;
+        let mut options = arguments[5..].iter();
+
+        while let Some(option) = options.next() {
+            match keyword(option).as_deref() {
+                Some("COPY") => copy = true,
+                Some("REPLACE") => replace = true,
+                Some("AUTH") => match options.next() {
+                    Some(password) => auth = Some(vec![argument_bytes(password)]),
+                    None => return Err(Response::Error("syntax error")),
+                },
+                Some("AUTH2") => match (options.next(), options.next()) {
+                    (Some(username), Some(password)) => {
+                        auth = Some(vec![argument_bytes(username), argument_bytes(password)]);
+                    }
+                    _ => return Err(Response::Error("syntax error")),
+                },
+                Some("KEYS") => {
+                    if !bytes(&arguments[2]).is_some_and(<[u8]>::is_empty) {
+                        return Err(Response::Error(
+                            "When using MIGRATE KEYS option, the key argument must be set to the empty string",
+                        ));
+                    }
+
+                    keys = Some(options.by_ref().collect::<Vec<_>>());
+                }
+                _ => return Err(Response::Error("syntax error")),
+            }
+        }
+
+        let keys = keys.unwrap_or_else(|| vec![&arguments[2]]);
+        let now = now_ms();
+        let mut moved = Vec::new();
+        // A node of a cluster sends keys to the node importing their slot, which only accepts them that way.
+        let restore = if data.cluster().is_enabled() {
+            "RESTORE-ASKING"
+        } else {
+            "RESTORE"
+        };
+        let mut commands = Vec::new();
+
+        if let Some(auth) = auth {
+            commands.push([b"AUTH".to_vec()].into_iter().chain(auth).collect());
+        }
+
+        commands.push(vec![b"SELECT".to_vec(), database.to_string().into_bytes()]);
+
+        for argument in keys {
+            let Value::BulkString(key) = argument;
+            let Some(object) = data.get(key) else {
+                continue;
+            };
+            let payload = rdb::dump(object);
+            // The TTL is sent relative to now, so that the clocks of the two instances needn't agree.
+            let ttl = data
+                .expires_at(key)
+                .map_or(0, |at| at.saturating_sub(now).max(1));
+            let mut command = vec![
+                restore.as_bytes().to_vec(),
+                argument_bytes(argument),
+                ttl.to_string().into_bytes(),
+                payload,
+            ];
+
+            if replace {
+                command.push(b"REPLACE".to_vec());
+            }
+
+            commands.push(command);
+            moved.push(key.clone());
+        }
+
+        if moved.is_empty() {
+            return Err(Response::SimpleString("NOKEY"));
+        }
+
+        Ok(Migration {
+            host,
+            port,
+            timeout: Duration::from_millis(timeout),
+            copy,
+            commands,
+            keys: moved,
+        })
+    }
+
+    /// Sends the commands to the target instance and reads the reply to each, a status or an error, which is all that
+    /// `MIGRATE` sends commands for. Each command waits for the reply to the previous one, like Redis's `MIGRATE`
+    /// does for the authentication and `SELECT`.
+    pub(crate) fn transfer(&self) -> Result<Vec<String>, &'static str> {
+        const CONNECT_ERROR: &str = "IOERR error or timeout connecting to the client";
+        const WRITE_ERROR: &str = "IOERR error or timeout writing to target instance";
+        const READ_ERROR: &str = "IOERR error or timeout reading to target instance";
+
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or(CONNECT_ERROR)?;
+        let mut stream =
+            TcpStream::connect_timeout(&address, self.timeout).map_err(|_| CONNECT_ERROR)?;
+
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .and_then(|()| stream.set_read_timeout(Some(self.timeout)))
+            .map_err(|_| CONNECT_ERROR)?;
+
+        let mut reader = BufReader::new(stream.try_clone().map_err(|_| CONNECT_ERROR)?);
+
+        self.commands
+            .iter()
+            .map(|command| {
+                let mut bytes = Vec::new();
+                let mut line = String::new();
+
+                aof::encode(&mut bytes, command);
+                stream.write_all(&bytes).map_err(|_| WRITE_ERROR)?;
+
+                match reader.read_line(&mut line) {
+                    Ok(0) | Err(_) => Err(READ_ERROR),
+                    Ok(_) => Ok(line.trim_end().to_string()),
+                }
+            })
+            .collect()
+    }
+
+    /// Deletes the keys sent, unless they were copied, once the target instance has accepted all of them. A key that
+    /// was changed while it was being sent is kept, since the target only has its old value. Returns the reply, and
+    /// the keys deleted.
+    pub(crate) fn finish(
+        self,
+        data: &mut Data,
+        replies: Result<Vec<String>, &'static str>,
+    ) -> (Response, Vec<BulkString>) {
+        let replies = match replies {
+            Ok(replies) => replies,
+            Err(message) => return (Response::Error(message), Vec::new()),
+        };
+
+        // If the target refused any of the keys, none are deleted here, so that nothing is lost.
+        if let Some(error) = replies.iter().find_map(|reply| reply.strip_prefix('-')) {
+            return (
+                Response::OwnedError(format!("Target instance replied with error: {error}")),
+                Vec::new(),
+            );
+        }
+
+        let mut deleted = Vec::new();
+
+        if !self.copy {
+            let restores = &self.commands[self.commands.len() - self.keys.len()..];
+
+            for (key, restore) in self.keys.into_iter().zip(restores) {
+                if data
+                    .get(&key)
+                    .is_some_and(|object| rdb::dump(object) == restore[3])
+                {
+                    data.remove(&key);
+                    deleted.push(key);
+                }
+            }
+        }
+
+        (Response::SimpleString("OK"), deleted)
+    }
+}
+
+fn argument_bytes(argument: &Value) -> Vec<u8> {
+    bytes(argument).unwrap_or_default().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+    use crate::object::Object;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    fn dump(data: &mut Data, key: &str) -> Value {
+        match Dump.execute(data, &[Value::BulkString(bulk_string!(key))]) {
+            Response::BulkString(payload) => Value::BulkString(payload),
+            reply => panic!("unexpected reply {reply:?}"),
+        }
+    }
+
+    fn restore(
+        data: &mut Data,
+        key: &str,
+        ttl: &str,
+        payload: &Value,
+        options: &[&str],
+    ) -> Response {
+        let mut arguments = vec![
+            Value::BulkString(bulk_string!(key)),
+            Value::BulkString(bulk_string!(ttl)),
+            payload.clone(),
+        ];
+
+        arguments.extend(
+            options
+                .iter()
+                .map(|option| Value::BulkString(bulk_string!(option))),
+        );
+
+        Restore.execute(data, &arguments)
+    }
+
+    #[test]
+    fn dump_and_restore() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+        let payload = dump(&mut data, "a");
+
+        assert_eq!(Value::BulkString(BulkString::Null), dump(&mut data, "b"));
+        assert_eq!(
+            Response::Error("BUSYKEY Target key name already exists."),
+            restore(&mut data, "a", "0", &payload, &[])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            restore(&mut data, "b", "0", &payload, &[])
+        );
+        assert_eq!(
+            Some(&Object::String(bulk_string!("1"))),
+            data.get(&bulk_string!("b"))
+        );
+        assert_eq!(None, data.expires_at(&bulk_string!("b")));
+        assert_eq!(
+            Response::SimpleString("OK"),
+            restore(
+                &mut data,
+                "a",
+                "1000",
+                &payload,
+                &["REPLACE", "IDLETIME", "10"]
+            )
+        );
+        assert!(data
+            .expires_at(&bulk_string!("a"))
+            .is_some_and(|at| at > now_ms()));
+
+        let at = (now_ms() + 60_000).to_string();
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            restore(&mut data, "c", &at, &payload, &["ABSTTL", "FREQ", "5"])
+        );
+        assert_eq!(
+            Some(at.parse().unwrap()),
+            data.expires_at(&bulk_string!("c"))
+        );
+
+        // An expiry time in the past deletes the key that would be replaced.
+        assert_eq!(
+            Response::SimpleString("OK"),
+            restore(&mut data, "c", "1", &payload, &["ABSTTL", "REPLACE"])
+        );
+        assert!(!data.contains_key(&bulk_string!("c")));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let mut data = Data::new();
+        let payload = Value::BulkString(BulkString::Filled(rdb::dump(&Object::String(
+            bulk_string!("1"),
+        ))));
+
+        assert_eq!(
+            Response::Error("Invalid TTL value, must be >= 0"),
+            restore(&mut data, "a", "-1", &payload, &[])
+        );
+        assert_eq!(
+            Response::Error("syntax error"),
+            restore(
+                &mut data,
+                "a",
+                "0",
+                &payload,
+                &["IDLETIME", "1", "FREQ", "1"]
+            )
+        );
+        assert_eq!(
+            Response::Error("Invalid FREQ value, must be >= 0 and <= 255"),
+            restore(&mut data, "a", "0", &payload, &["FREQ", "256"])
+        );
+        assert_eq!(
+            Response::Error("DUMP payload version or checksum are wrong"),
+            restore(
+                &mut data,
+                "a",
+                "0",
+                &Value::BulkString(bulk_string!("garbage")),
+                &[]
+            )
+        );
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn restore_refuses_forged_lengths() {
+        let mut data = Data::new();
+
+        // A string compressed with LZF whose 4 bytes of compressed data claim to decompress to 4 GB.
+        let mut payload = vec![
+            0, 0xc3, 4, 0x80, 0xff, 0xff, 0xff, 0xff, 2, b'a', b'b', b'c',
+        ];
+
+        payload.extend(rdb::VERSION.to_le_bytes());
+        payload.extend(crate::crc64::crc64(0, &payload).to_le_bytes());
+
+        assert_eq!(
+            Response::Error("Bad data format"),
+            restore(
+                &mut data,
+                "a",
+                "0",
+                &Value::BulkString(BulkString::Filled(payload)),
+                &[]
+            )
+        );
+        assert!(data.is_empty());
+    }
+
+    /// Accepts a connection from `MIGRATE`, sends it `replies`, and returns what it sent.
+    fn target(replies: &'static str) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+
+            stream.write_all(replies.as_bytes()).unwrap();
+            stream.read_to_end(&mut request).unwrap();
+
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        (port, handle)
+    }
+
+    fn migrate(data: &mut Data, port: u16, key: &str, options: &[&str]) -> Response {
+        let port = port.to_string();
+        let arguments: Vec<Value> = ["127.0.0.1", &port, key, "0", "1000"]
+            .iter()
+            .chain(options)
+            .map(|argument| Value::BulkString(bulk_string!(argument)))
+            .collect();
+
+        Migrate.execute(data, &arguments)
+    }
+
+    #[test]
+    fn migrate_keys() {
+        let mut data = Data::from([
+            (bulk_string!("a"), Object::String(bulk_string!("1"))),
+            (bulk_string!("b"), Object::String(bulk_string!("2"))),
+        ]);
+
+        assert_eq!(
+            Response::SimpleString("NOKEY"),
+            migrate(&mut data, 1, "c", &[])
+        );
+        assert_eq!(
+            Response::Error(
+                "When using MIGRATE KEYS option, the key argument must be set to the empty string"
+            ),
+            migrate(&mut data, 1, "a", &["KEYS", "b"])
+        );
+        assert_eq!(
+            Response::Error("syntax error"),
+            migrate(&mut data, 1, "a", &["AUTH"])
+        );
+
+        let (port, handle) = target("+OK\r\n+OK\r\n");
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            migrate(&mut data, port, "a", &[])
+        );
+        assert!(handle
+            .join()
+            .unwrap()
+            .contains("*4\r\n$7\r\nRESTORE\r\n$1\r\na\r\n$1\r\n0\r\n"));
+        assert!(!data.contains_key(&bulk_string!("a")));
+
+        let (port, handle) = target("+OK\r\n+OK\r\n-BUSYKEY Target key name already exists.\r\n");
+
+        data.set_expires_at(&bulk_string!("b"), now_ms() + 10_000);
+        data.insert(bulk_string!("c"), Object::String(bulk_string!("3")));
+
+        assert_eq!(
+            Response::OwnedError(
+                "Target instance replied with error: BUSYKEY Target key name already exists."
+                    .to_string()
+            ),
+            migrate(&mut data, port, "", &["REPLACE", "KEYS", "b", "c", "d"])
+        );
+
+        let request = handle.join().unwrap();
+
+        // The TTL is relative to now.
+        assert!(
+            request.contains("$1\r\nb\r\n$4\r\n9")
+                || request.contains("$1\r\nb\r\n$5\r\n10000\r\n")
+        );
+        assert!(request.ends_with("$7\r\nREPLACE\r\n"));
+        assert!(data.contains_key(&bulk_string!("b")));
+
+        let (port, handle) = target("+OK\r\n+OK\r\n");
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            migrate(&mut data, port, "b", &["COPY"])
+        );
+        handle.join().unwrap();
+        assert!(data.contains_key(&bulk_string!("b")));
+
+        // Nothing listens on the port once the listener is dropped.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        assert_eq!(
+            Response::Error("IOERR error or timeout connecting to the client"),
+            migrate(&mut data, port, "b", &[])
+        );
+        assert!(data.contains_key(&bulk_string!("b")));
+    }
+}
@@ -0,0 +1,251 @@
+use super::{bytes, keyword, number, Command, Data, Response};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::scripting;
+
+pub(crate) struct Eval;
+pub(crate) struct EvalRo;
+pub(crate) struct EvalSha;
+pub(crate) struct EvalShaRo;
+pub(crate) struct Script;
+
+/// Runs `EVAL` and its variants. The arguments are the script (or its SHA-1), the number of keys, the keys and the
+/// other arguments.
+fn eval(data: &mut Data, arguments: &[Value], by_sha: bool, read_only: bool) -> Response {
+    let (keys, script_arguments) = match keys_and_arguments(arguments) {
+        Ok(split) => split,
+        Err(reply) => return reply,
+    };
+    let script = bytes(&arguments[0]).unwrap_or_default();
+
+    let (sha, body) = if by_sha {
+        let sha = String::from_utf8_lossy(script).to_lowercase();
+
+        match data.scripts_mut().get(&sha) {
+            Some(body) => (sha, body.clone()),
+            None => return Response::Error("NOSCRIPT No matching script. Please use EVAL."),
+        }
+    } else {
+        match scripting::load(data, script) {
+            Ok(loaded) => loaded,
+            Err(reply) => return reply,
+        }
+    };
+
+    scripting::run(data, &sha, body, keys, script_arguments, read_only)
+}
+
+/// Splits the arguments of `EVAL` or `FCALL` after the script or function into the keys and the other arguments,
+/// using the number of keys that comes first.
+pub(crate) fn keys_and_arguments(arguments: &[Value]) -> Result<(&[Value], &[Value]), Response> {
+    if arguments.len() < 2 {
+        return Err(Response::Error("wrong number of arguments"));
+    }
+
+    let key_count = match number::<i64>(&arguments[1]) {
+        Some(count) if count < 0 => {
+            return Err(Response::Error("Number of keys can't be negative"))
+        }
+        Some(count) if count as usize > arguments.len() - 2 => {
+            return Err(Response::Error(
+                "Number of keys can't be greater than number of args",
+            ))
+        }
+        Some(count) => count as usize,
+        None => return Err(Response::Error("value is not an integer or out of range")),
+    };
+
+    Ok(arguments[2..].split_at(key_count))
+}
+
+impl Command for Eval {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        eval(data, arguments, false, false)
+    }
+}
+
+impl Command for EvalRo {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        eval(data, arguments, false, true)
+    }
+}
+
+impl Command for EvalSha {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        eval(data, arguments, true, false)
+    }
+}
+
+impl Command for EvalShaRo {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        eval(data, arguments, true, true)
+    }
+}
+
+impl Command for Script {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let Some(subcommand) = arguments.first().and_then(keyword) else {
+            return Response::Error("wrong number of arguments");
+        };
+
+        match (subcommand.as_str(), &arguments[1..]) {
+            ("LOAD", [script]) => match scripting::load(data, bytes(script).unwrap_or_default()) {
+                Ok((sha, _)) => Response::BulkString(BulkString::Filled(sha.into_bytes())),
+                Err(reply) => reply,
+            },
+            ("EXISTS", shas) if !shas.is_empty() => Response::Array(
+                shas.iter()
+                    .map(|sha| {
+                        let sha =
+                            String::from_utf8_lossy(bytes(sha).unwrap_or_default()).to_lowercase();
+
+                        Response::Integer(data.scripts_mut().contains_key(&sha) as i64)
+                    })
+                    .collect(),
+            ),
+            ("FLUSH", modifier) => {
+                match modifier {
+                    [] => {}
+                    [mode] if matches!(keyword(mode).as_deref(), Some("ASYNC" | "SYNC")) => {}
+                    _ => return Response::Error("SCRIPT FLUSH only support SYNC|ASYNC option"),
+                }
+
+                data.scripts_mut().clear();
+
+                Response::SimpleString("OK")
+            }
+            // Connections handle `SCRIPT KILL` before taking the lock that the running script holds, so this is only
+            // reached when no script is running.
+            ("KILL", []) => scripting::kill(),
+            _ => Response::Error("unknown subcommand or wrong number of arguments"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::TEST_LOCK;
+    use crate::sha1::sha1_hex;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn eval_and_evalsha() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+        let script = "return {KEYS[1], ARGV[1], #KEYS, #ARGV}";
+        let sha = sha1_hex(script.as_bytes());
+
+        assert_eq!(
+            Response::Array(vec![
+                Response::BulkString(bulk_string!("k")),
+                Response::BulkString(bulk_string!("a")),
+                Response::Integer(1),
+                Response::Integer(2),
+            ]),
+            Eval.execute(&mut data, arguments![script, "1", "k", "a", "b"])
+        );
+        assert_eq!(
+            Response::Array(vec![]),
+            EvalSha.execute(&mut data, arguments![&sha.to_uppercase(), "0"])
+        );
+        assert_eq!(
+            Response::Error("Number of keys can't be greater than number of args"),
+            Eval.execute(&mut data, arguments![script, "2", "k"])
+        );
+        assert_eq!(
+            Response::Error("Number of keys can't be negative"),
+            Eval.execute(&mut data, arguments![script, "-1"])
+        );
+        assert_eq!(
+            Response::OwnedError(
+                "Error compiling script (new function): user_script:1: '=' expected near 'y'"
+                    .to_string()
+            ),
+            Eval.execute(&mut data, arguments!["x y", "0"])
+        );
+        assert_eq!(
+            Response::Error("NOSCRIPT No matching script. Please use EVAL."),
+            EvalShaRo.execute(&mut data, arguments![&sha1_hex(b"unknown"), "0"])
+        );
+        assert_eq!(
+            Response::Array(vec![]),
+            Eval.execute(&mut data, arguments!["return setmetatable({}, {})", "0"])
+        );
+        assert_eq!(
+            Response::Integer(15),
+            Eval.execute(&mut data, arguments!["return bit.band(0xff, 0x0f)", "0"])
+        );
+    }
+
+    #[test]
+    fn read_only_and_select() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::OwnedError(
+                "Write commands are not allowed from read-only scripts.".to_string()
+            ),
+            EvalRo.execute(
+                &mut data,
+                arguments!["return redis.call('SET', 'a', '1')", "0"]
+            )
+        );
+        assert_eq!(
+            Response::BulkString(BulkString::Null),
+            EvalRo.execute(&mut data, arguments!["return redis.call('GET', 'a')", "0"])
+        );
+        assert_eq!(
+            Response::OwnedSimpleString("OK".to_string()),
+            Eval.execute(
+                &mut data,
+                arguments![
+                    "redis.call('SELECT', '1') return redis.call('SET', 'a', '1')",
+                    "0"
+                ]
+            )
+        );
+        assert_eq!(0, data.selected());
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn script() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+        let sha = sha1_hex(b"return 1");
+
+        assert_eq!(
+            Response::BulkString(BulkString::Filled(sha.clone().into_bytes())),
+            Script.execute(&mut data, arguments!["LOAD", "return 1"])
+        );
+        assert_eq!(
+            Response::Array(vec![Response::Integer(1), Response::Integer(0)]),
+            Script.execute(&mut data, arguments!["EXISTS", &sha, "nope"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Script.execute(&mut data, arguments!["FLUSH", "ASYNC"])
+        );
+        assert_eq!(
+            Response::Array(vec![Response::Integer(0)]),
+            Script.execute(&mut data, arguments!["EXISTS", &sha])
+        );
+        assert_eq!(
+            Response::Error("NOTBUSY No scripts in execution right now."),
+            Script.execute(&mut data, arguments!["KILL"])
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use super::{keyword, number, Command, Data, Response};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::database::now_ms;
+use crate::notify;
+
+pub(crate) struct Expire;
+pub(crate) struct PExpire;
+pub(crate) struct ExpireAt;
+pub(crate) struct PExpireAt;
+pub(crate) struct Ttl;
+pub(crate) struct PTtl;
+pub(crate) struct Persist;
+
+#[derive(PartialEq)]
+enum Condition {
+    Always,
+    IfNoTtl,
+    IfTtl,
+    IfGreater,
+    IfLess,
+}
+
+/// Sets the expiry time of a key. `to_ms` converts the time argument to a Unix time in milliseconds.
+fn expire(data: &mut Data, arguments: &[Value], to_ms: impl Fn(i64) -> Option<i64>) -> Response {
+    if arguments.len() < 2 {
+        return Response::Error("wrong number of arguments");
+    }
+
+    let key = bulk_string_or_error!(&arguments[0]);
+
+    let at = match number(&arguments[1]).and_then(to_ms) {
+        Some(at) => at,
+        None => return Response::Error("invalid expire time"),
+    };
+
+    let mut condition = Condition::Always;
+
+    for option in &arguments[2..] {
+        let option = match keyword(option).as_deref() {
+            Some("NX") => Condition::IfNoTtl,
+            Some("XX") => Condition::IfTtl,
+            Some("GT") => Condition::IfGreater,
+            Some("LT") => Condition::IfLess,
+            _ => return Response::Error("unsupported option"),
+        };
+
+        if condition != Condition::Always && condition != option {
+            return Response::Error("NX, XX, GT and LT options are mutually exclusive");
+        }
+
+        condition = option;
+    }
+
+    if !data.contains_key(key) {
+        return Response::Integer(0);
+    }
+
+    // A key without a TTL is treated as expiring infinitely far in the future.
+    let current = data.expires_at(key);
+
+    let allowed = match condition {
+        Condition::Always => true,
+        Condition::IfNoTtl => current.is_none(),
+        Condition::IfTtl => current.is_some(),
+        Condition::IfGreater => current.is_some_and(|current| at > current as i64),
+        Condition::IfLess => current.is_none_or(|current| at < current as i64),
+    };
+
+    if !allowed {
+        return Response::Integer(0);
+    }
+
+    if at <= now_ms() as i64 {
+        data.remove(key);
+        data.notify(notify::GENERIC, "del", key);
+    } else {
+        data.set_expires_at(key, at as u64);
+        data.notify(notify::GENERIC, "expire", key);
+    }
+
+    Response::Integer(1)
+}
+
+impl Command for Expire {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let now = now_ms() as i64;
+
+        expire(data, arguments, |seconds| {
+            seconds.checked_mul(1000)?.checked_add(now)
+        })
+    }
+}
+
+impl Command for PExpire {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let now = now_ms() as i64;
+
+        expire(data, arguments, |milliseconds| {
+            milliseconds.checked_add(now)
+        })
+    }
+}
+
+impl Command for ExpireAt {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        expire(data, arguments, |seconds| seconds.checked_mul(1000))
+    }
+}
+
+impl Command for PExpireAt {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        expire(data, arguments, Some)
+    }
+}
+
+/// The remaining time to live of a key in milliseconds, or -2 if it doesn't exist and -1 if it has no TTL.
+fn ttl(data: &mut Data, arguments: &[Value]) -> Result<i64, Response> {
+    if arguments.len() != 1 {
+        return Err(Response::Error("wrong number of arguments"));
+    }
+
+    let key = match &arguments[0] {
+        Value::BulkString(key @ BulkString::Filled(_)) => key,
+        _ => return Err(Response::Error("invalid argument")),
+    };
+
+    if !data.contains_key(key) {
+        return Ok(-2);
+    }
+
+    Ok(match data.expires_at(key) {
+        Some(at) => at.saturating_sub(now_ms()) as i64,
+        None => -1,
+    })
+}
+
+impl Command for Ttl {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        match ttl(data, arguments) {
+            Ok(ttl) if ttl >= 0 => Response::Integer((ttl + 500) / 1000),
+            Ok(ttl) => Response::Integer(ttl),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for PTtl {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        match ttl(data, arguments) {
+            Ok(ttl) => Response::Integer(ttl),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Command for Persist {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 1 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        if !data.persist(key) {
+            return Response::Integer(0);
+        }
+
+        data.notify(notify::GENERIC, "persist", key);
+
+        Response::Integer(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn expire_ttl_and_persist() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+
+        assert_eq!(
+            Response::Integer(-1),
+            Ttl.execute(&mut data, arguments!["a"])
+        );
+        assert_eq!(
+            Response::Integer(-2),
+            Ttl.execute(&mut data, arguments!["b"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            Expire.execute(&mut data, arguments!["a", "100"])
+        );
+        assert_eq!(
+            Response::Integer(100),
+            Ttl.execute(&mut data, arguments!["a"])
+        );
+        assert_eq!(
+            Response::Integer(0),
+            Expire.execute(&mut data, arguments!["a", "200", "LT"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            Expire.execute(&mut data, arguments!["a", "200", "GT"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            Persist.execute(&mut data, arguments!["a"])
+        );
+        assert_eq!(
+            Response::Integer(-1),
+            PTtl.execute(&mut data, arguments!["a"])
+        );
+    }
+
+    #[test]
+    fn expiring_in_the_past_deletes() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+
+        assert_eq!(
+            Response::Integer(1),
+            PExpireAt.execute(&mut data, arguments!["a", "1"])
+        );
+        assert!(data.is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+use super::eval::keys_and_arguments;
+use super::{bulk_string, bytes, keyword, Command, Data, Response};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::functions::{self, RestorePolicy};
+use crate::glob;
+use crate::scripting;
+
+pub(crate) struct FCall;
+pub(crate) struct FCallRo;
+pub(crate) struct Function;
+
+/// Runs `FCALL` or `FCALL_RO`. The arguments are the function's name, the number of keys, the keys and the other
+/// arguments.
+fn fcall(data: &mut Data, arguments: &[Value], read_only: bool) -> Response {
+    let (keys, function_arguments) = match keys_and_arguments(arguments) {
+        Ok(split) => split,
+        Err(reply) => return reply,
+    };
+    let name = String::from_utf8_lossy(bytes(&arguments[0]).unwrap_or_default());
+
+    functions::call(data, &name, keys, function_arguments, read_only)
+}
+
+impl Command for FCall {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        fcall(data, arguments, false)
+    }
+}
+
+impl Command for FCallRo {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        fcall(data, arguments, true)
+    }
+}
+
+/// Lists the libraries whose names match `pattern`, like `FUNCTION LIST [WITHCODE] [LIBRARYNAME pattern]`.
+fn list(data: &Data, pattern: Option<&[u8]>, with_code: bool) -> Response {
+    let libraries = data
+        .libraries()
+        .iter()
+        .filter(|library| {
+            pattern.is_none_or(|pattern| glob::matches(pattern, library.name.as_bytes(), false))
+        })
+        .map(|library| {
+            let functions = library
+                .functions
+                .iter()
+                .map(|function| {
+                    Response::Array(vec![
+                        bulk_string("name"),
+                        bulk_string(&function.name),
+                        bulk_string("description"),
+                        match &function.description {
+                            Some(description) => bulk_string(description),
+                            None => Response::BulkString(BulkString::Null),
+                        },
+                        bulk_string("flags"),
+                        Response::Array(function.flag_names().map(bulk_string).collect()),
+                    ])
+                })
+                .collect();
+            let mut reply = vec![
+                bulk_string("library_name"),
+                bulk_string(&library.name),
+                bulk_string("engine"),
+                bulk_string("LUA"),
+                bulk_string("functions"),
+                Response::Array(functions),
+            ];
+
+            if with_code {
+                reply.push(bulk_string("library_code"));
+                reply.push(Response::BulkString(BulkString::Filled(
+                    library.code.clone(),
+                )));
+            }
+
+            Response::Array(reply)
+        })
+        .collect();
+
+    Response::Array(libraries)
+}
+
+impl Command for Function {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let Some(subcommand) = arguments.first().and_then(keyword) else {
+            return Response::Error("wrong number of arguments");
+        };
+
+        match (subcommand.as_str(), &arguments[1..]) {
+            ("LOAD", [options @ .., code]) => {
+                let replace = match options {
+                    [] => false,
+                    [option] if keyword(option).as_deref() == Some("REPLACE") => true,
+                    [option] => {
+                        return Response::OwnedError(format!(
+                            "Unknown option given: {}",
+                            String::from_utf8_lossy(bytes(option).unwrap_or_default())
+                        ))
+                    }
+                    _ => return Response::Error("wrong number of arguments"),
+                };
+
+                match data
+                    .libraries_mut()
+                    .load(bytes(code).unwrap_or_default(), replace)
+                {
+                    Ok(name) => Response::BulkString(BulkString::Filled(name.into_bytes())),
+                    Err(message) => Response::OwnedError(message),
+                }
+            }
+            ("DELETE", [name]) => {
+                let name = String::from_utf8_lossy(bytes(name).unwrap_or_default());
+
+                if data.libraries_mut().delete(&name) {
+                    Response::SimpleString("OK")
+                } else {
+                    Response::Error("Library not found")
+                }
+            }
+            ("FLUSH", modifier) => {
+                match modifier {
+                    [] => {}
+                    [mode] if matches!(keyword(mode).as_deref(), Some("ASYNC" | "SYNC")) => {}
+                    _ => return Response::Error("FUNCTION FLUSH only supports SYNC|ASYNC option"),
+                }
+
+                data.libraries_mut().clear();
+
+                Response::SimpleString("OK")
+            }
+            ("LIST", options) => {
+                let mut pattern = None;
+                let mut with_code = false;
+                let mut options = options.iter();
+
+                while let Some(option) = options.next() {
+                    match keyword(option).as_deref() {
+                        Some("WITHCODE") if !with_code => with_code = true,
+                        Some("LIBRARYNAME") if pattern.is_none() => match options.next() {
+                            Some(name) => pattern = Some(bytes(name).unwrap_or_default()),
+                            None => return Response::Error("library name argument was not given"),
+                        },
+                        _ => return Response::Error("Unknown argument given"),
+                    }
+                }
+
+                list(data, pattern, with_code)
+            }
+            ("DUMP", []) => Response::BulkString(BulkString::Filled(data.libraries().dump())),
+            ("RESTORE", [payload, policy @ ..]) => {
+                let policy = match policy {
+                    [] => RestorePolicy::Append,
+                    [policy] => match keyword(policy).as_deref() {
+                        Some("FLUSH") => RestorePolicy::Flush,
+                        Some("APPEND") => RestorePolicy::Append,
+                        Some("REPLACE") => RestorePolicy::Replace,
+                        _ => {
+                            return Response::Error(
+                                "Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.",
+                            )
+                        }
+                    },
+                    _ => return Response::Error("wrong number of arguments"),
+                };
+
+                match data
+                    .libraries_mut()
+                    .restore(bytes(payload).unwrap_or_default(), policy)
+                {
+                    Ok(()) => Response::SimpleString("OK"),
+                    Err(message) => Response::OwnedError(message),
+                }
+            }
+            // Like `SCRIPT KILL`, this is handled before taking the lock, so it's only reached when nothing is running.
+            ("KILL", []) => scripting::kill(),
+            _ => Response::Error("unknown subcommand or wrong number of arguments"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::TEST_LOCK;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    const LIBRARY: &str = "#!lua name=mylib\n\
+        redis.register_function('set', function(keys, args) return redis.call('SET', keys[1], args[1]) end)\n\
+        redis.register_function{function_name='get', callback=function(keys) return redis.call('GET', keys[1]) end, \
+        flags={'no-writes'}}";
+
+    #[test]
+    fn load_and_call() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::BulkString(bulk_string!("mylib")),
+            Function.execute(&mut data, arguments!["LOAD", LIBRARY])
+        );
+        assert_eq!(
+            Response::OwnedError("Library 'mylib' already exists".to_string()),
+            Function.execute(&mut data, arguments!["LOAD", LIBRARY])
+        );
+        assert_eq!(
+            Response::BulkString(bulk_string!("mylib")),
+            Function.execute(&mut data, arguments!["LOAD", "replace", LIBRARY])
+        );
+        assert_eq!(
+            Response::OwnedSimpleString("OK".to_string()),
+            FCall.execute(&mut data, arguments!["set", "1", "k", "v"])
+        );
+        assert_eq!(
+            Response::BulkString(bulk_string!("v")),
+            FCallRo.execute(&mut data, arguments!["get", "1", "k"])
+        );
+        assert_eq!(
+            Response::Error("Can not execute a script with write flag using *_ro command."),
+            FCallRo.execute(&mut data, arguments!["set", "1", "k", "v"])
+        );
+        assert_eq!(
+            Response::Error("Function not found"),
+            FCall.execute(&mut data, arguments!["nope", "0"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Function.execute(&mut data, arguments!["DELETE", "mylib"])
+        );
+        assert_eq!(
+            Response::Error("Library not found"),
+            Function.execute(&mut data, arguments!["DELETE", "mylib"])
+        );
+    }
+
+    #[test]
+    fn no_writes_flag() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+
+        Function.execute(
+            &mut data,
+            arguments![
+                "LOAD",
+                "#!lua name=lib\nredis.register_function{function_name='f', \
+                callback=function() return redis.call('SET', 'a', '1') end, flags={'no-writes'}}"
+            ],
+        );
+
+        assert_eq!(
+            Response::OwnedError(
+                "Write commands are not allowed from read-only scripts.".to_string()
+            ),
+            FCall.execute(&mut data, arguments!["f", "0"])
+        );
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn list() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+
+        Function.execute(&mut data, arguments!["LOAD", LIBRARY]);
+
+        let get = Response::Array(vec![
+            Response::BulkString(bulk_string!("name")),
+            Response::BulkString(bulk_string!("get")),
+            Response::BulkString(bulk_string!("description")),
+            Response::BulkString(BulkString::Null),
+            Response::BulkString(bulk_string!("flags")),
+            Response::Array(vec![Response::BulkString(bulk_string!("no-writes"))]),
+        ]);
+        let set = Response::Array(vec![
+            Response::BulkString(bulk_string!("name")),
+            Response::BulkString(bulk_string!("set")),
+            Response::BulkString(bulk_string!("description")),
+            Response::BulkString(BulkString::Null),
+            Response::BulkString(bulk_string!("flags")),
+            Response::Array(vec![]),
+        ]);
+
+        assert_eq!(
+            Response::Array(vec![Response::Array(vec![
+                Response::BulkString(bulk_string!("library_name")),
+                Response::BulkString(bulk_string!("mylib")),
+                Response::BulkString(bulk_string!("engine")),
+                Response::BulkString(bulk_string!("LUA")),
+                Response::BulkString(bulk_string!("functions")),
+                Response::Array(vec![get, set]),
+                Response::BulkString(bulk_string!("library_code")),
+                Response::BulkString(bulk_string!(LIBRARY)),
+            ])]),
+            Function.execute(
+                &mut data,
+                arguments!["LIST", "WITHCODE", "LIBRARYNAME", "my*"]
+            )
+        );
+        assert_eq!(
+            Response::Array(vec![]),
+            Function.execute(&mut data, arguments!["LIST", "LIBRARYNAME", "other*"])
+        );
+    }
+
+    #[test]
+    fn dump_and_restore() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+
+        Function.execute(&mut data, arguments!["LOAD", LIBRARY]);
+
+        let Response::BulkString(BulkString::Filled(payload)) =
+            Function.execute(&mut data, arguments!["DUMP"])
+        else {
+            panic!("FUNCTION DUMP should reply with a bulk string");
+        };
+        let payload = Value::BulkString(BulkString::Filled(payload));
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Function.execute(&mut data, arguments!["FLUSH"])
+        );
+        assert!(data.libraries().iter().next().is_none());
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Function.execute(&mut data, &[bulk_string_value("RESTORE"), payload.clone()])
+        );
+        assert_eq!(
+            Response::OwnedError("Library 'mylib' already exists".to_string()),
+            Function.execute(&mut data, &[bulk_string_value("RESTORE"), payload.clone()])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Function.execute(
+                &mut data,
+                &[
+                    bulk_string_value("RESTORE"),
+                    payload,
+                    bulk_string_value("REPLACE")
+                ]
+            )
+        );
+        assert!(data.libraries().function("get").is_some());
+    }
+
+    fn bulk_string_value(s: &str) -> Value {
+        Value::BulkString(bulk_string!(s))
+    }
+}
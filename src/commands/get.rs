@@ -1,6 +1,8 @@
-use super::{Command, Data, Response};
+use super::{Command, Data, Response, WRONG_TYPE};
 use crate::array::Value;
 use crate::bulk_string::BulkString;
+use crate::notify;
+use crate::object::Object;
 
 pub(crate) struct Get;
 
@@ -13,8 +15,13 @@ impl Command for Get {
         let key = bulk_string_or_error!(&arguments[0]);
 
         match data.get(key) {
-            Some(value) => Response::BulkString(value.clone()),
-            None => Response::BulkString(BulkString::Null),
+            Some(Object::String(value)) => Response::BulkString(value.clone()),
+            Some(_) => Response::Error(WRONG_TYPE),
+            None => {
+                data.notify(notify::KEY_MISS, "keymiss", key);
+
+                Response::BulkString(BulkString::Null)
+            }
         }
     }
 }
@@ -0,0 +1,302 @@
+use std::borrow::Cow;
+
+use super::{keyword, Command, Data, Response};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::replication::LinkState;
+
+pub(crate) struct Info;
+
+type Fields = Vec<(Cow<'static, str>, String)>;
+type Section = (&'static str, fn(&Data) -> Fields);
+
+/// The sections `INFO` reports, in order, with the functions that produce their fields.
+const SECTIONS: &[Section] = &[
+    ("Persistence", persistence),
+    ("Replication", replication),
+    ("Cluster", cluster),
+];
+
+/// The sections `INFO` reports in sentinel mode.
+const SENTINEL_SECTIONS: &[Section] = &[("Sentinel", sentinel)];
+
+fn persistence(data: &Data) -> Fields {
+    let snapshots = data.snapshots();
+
+    vec![
+        ("loading".into(), "0".to_string()),
+        (
+            "rdb_changes_since_last_save".into(),
+            snapshots.changes_since_save(data.changes()).to_string(),
+        ),
+        (
+            "rdb_bgsave_in_progress".into(),
+            (snapshots.is_saving() as u8).to_string(),
+        ),
+        (
+            "rdb_last_save_time".into(),
+            snapshots.last_save().to_string(),
+        ),
+        (
+            "rdb_last_bgsave_status".into(),
+            if snapshots.last_save_succeeded() {
+                "ok"
+            } else {
+                "err"
+            }
+            .to_string(),
+        ),
+        (
+            "rdb_last_bgsave_time_sec".into(),
+            snapshots.last_bgsave_duration().to_string(),
+        ),
+        (
+            "aof_enabled".into(),
+            (data.aof().is_enabled() as u8).to_string(),
+        ),
+        (
+            "aof_rewrite_in_progress".into(),
+            (data.aof().is_rewriting() as u8).to_string(),
+        ),
+        (
+            "aof_last_bgrewrite_status".into(),
+            if data.aof().last_rewrite_succeeded() {
+                "ok"
+            } else {
+                "err"
+            }
+            .to_string(),
+        ),
+        (
+            "aof_last_write_status".into(),
+            if data.aof().last_write_succeeded() {
+                "ok"
+            } else {
+                "err"
+            }
+            .to_string(),
+        ),
+        (
+            "aof_current_size".into(),
+            data.aof().current_size().to_string(),
+        ),
+        ("aof_base_size".into(), data.aof().base_size().to_string()),
+    ]
+}
+
+fn cluster(data: &Data) -> Fields {
+    vec![(
+        "cluster_enabled".into(),
+        (data.cluster().is_enabled() as u8).to_string(),
+    )]
+}
+
+fn sentinel(data: &Data) -> Fields {
+    data.sentinel()
+        .info_fields()
+        .into_iter()
+        .map(|(name, value)| (name.into(), value))
+        .collect()
+}
+
+fn replication(data: &Data) -> Fields {
+    let replication = data.replication();
+    let mut fields = Vec::new();
+
+    match replication.primary() {
+        Some(primary) => {
+            let state = primary.state();
+
+            fields.extend([
+                ("role".into(), "slave".to_string()),
+                ("master_host".into(), primary.host().to_string()),
+                ("master_port".into(), primary.port().to_string()),
+                (
+                    "master_link_status".into(),
+                    if state == LinkState::Connected {
+                        "up"
+                    } else {
+                        "down"
+                    }
+                    .to_string(),
+                ),
+                (
+                    "master_last_io_seconds_ago".into(),
+                    primary
+                        .last_io_seconds_ago()
+                        .map_or("-1".to_string(), |seconds| seconds.to_string()),
+                ),
+                (
+                    "master_sync_in_progress".into(),
+                    ((state == LinkState::Sync) as u8).to_string(),
+                ),
+                ("slave_repl_offset".into(), replication.offset().to_string()),
+                (
+                    "slave_read_only".into(),
+                    (replication.read_only() as u8).to_string(),
+                ),
+            ]);
+        }
+        None => fields.push(("role".into(), "master".to_string())),
+    }
+
+    fields.push((
+        "connected_slaves".into(),
+        replication.replicas().len().to_string(),
+    ));
+
+    for (index, replica) in replication.replicas().iter().enumerate() {
+        fields.push((
+            format!("slave{index}").into(),
+            format!(
+                "ip={},port={},state=online,offset={},lag={}",
+                replica.address(),
+                replica.port(),
+                replica.ack_offset(),
+                replica.lag()
+            ),
+        ));
+    }
+
+    let (id2, second_offset) = match replication.id2() {
+        // Like Redis, the secondary offset is that of the first byte that isn't shared with the former primary.
+        Some((id, offset)) => (id.to_string(), (offset + 1).to_string()),
+        None => ("0".repeat(40), "-1".to_string()),
+    };
+    let (first_byte_offset, history_length) = replication.backlog_range().unwrap_or((0, 0));
+
+    fields.extend([
+        ("master_replid".into(), replication.id().to_string()),
+        ("master_replid2".into(), id2),
+        (
+            "master_repl_offset".into(),
+            replication.offset().to_string(),
+        ),
+        ("second_repl_offset".into(), second_offset),
+        (
+            "repl_backlog_active".into(),
+            (replication.backlog_range().is_some() as u8).to_string(),
+        ),
+        (
+            "repl_backlog_size".into(),
+            replication.backlog_size().to_string(),
+        ),
+        (
+            "repl_backlog_first_byte_offset".into(),
+            first_byte_offset.to_string(),
+        ),
+        ("repl_backlog_histlen".into(), history_length.to_string()),
+    ]);
+
+    fields
+}
+
+impl Command for Info {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let names: Vec<String> = arguments.iter().filter_map(keyword).collect();
+        let all = names.is_empty()
+            || names
+                .iter()
+                .any(|name| matches!(name.as_str(), "ALL" | "DEFAULT" | "EVERYTHING"));
+        let sections = if data.sentinel().is_enabled() {
+            SENTINEL_SECTIONS
+        } else {
+            SECTIONS
+        };
+        let mut reply = String::new();
+
+        for (section, fields) in sections {
+            if !all && !names.iter().any(|name| name.eq_ignore_ascii_case(section)) {
+                continue;
+            }
+
+            if !reply.is_empty() {
+                reply.push_str("\r\n");
+            }
+
+            reply.push_str(&format!("# {section}\r\n"));
+
+            for (name, value) in fields(data) {
+                reply.push_str(&format!("{name}:{value}\r\n"));
+            }
+        }
+
+        Response::BulkString(BulkString::Filled(reply.into_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    fn info(data: &mut Data, arguments: &[Value]) -> String {
+        match Info.execute(data, arguments) {
+            Response::BulkString(BulkString::Filled(bytes)) => String::from_utf8(bytes).unwrap(),
+            reply => panic!("unexpected reply {reply:?}"),
+        }
+    }
+
+    #[test]
+    fn persistence() {
+        let mut data = Data::new();
+
+        data.insert(bulk_string!("a"), Object::String(bulk_string!("1")));
+        data.remove(&bulk_string!("a"));
+
+        let reply = info(&mut data, arguments!["persistence"]);
+
+        assert!(reply.starts_with("# Persistence\r\n"));
+        assert!(reply.contains("\r\nrdb_changes_since_last_save:2\r\n"));
+        assert!(reply.contains("\r\nrdb_last_bgsave_status:ok\r\n"));
+        assert!(info(&mut data, &[]).starts_with(&reply));
+        assert_eq!("", info(&mut data, arguments!["stats"]));
+    }
+
+    #[test]
+    fn replication() {
+        let mut data = Data::new();
+        let reply = info(&mut data, arguments!["replication"]);
+
+        assert!(reply.starts_with("# Replication\r\nrole:master\r\nconnected_slaves:0\r\n"));
+        assert!(reply.contains("\r\nmaster_repl_offset:0\r\n"));
+
+        data.replication_mut()
+            .set_primary("localhost".to_string(), 6380);
+
+        let reply = info(&mut data, arguments!["replication"]);
+
+        assert!(reply.contains("\r\nrole:slave\r\nmaster_host:localhost\r\nmaster_port:6380\r\n"));
+        assert!(reply.contains("\r\nmaster_link_status:down\r\n"));
+        assert!(reply.contains("\r\nslave_read_only:1\r\n"));
+    }
+
+    #[test]
+    fn sentinel() {
+        let mut data = Data::new();
+
+        data.sentinel_mut().enable(26379, 30_000, 180_000);
+        data.sentinel_mut()
+            .monitor("mymaster", "127.0.0.1", 6379, 2, 0)
+            .unwrap();
+
+        assert_eq!(
+            "# Sentinel\r\nsentinel_masters:1\r\nsentinel_tilt:0\r\n\
+            master0:name=mymaster,status=ok,address=127.0.0.1:6379,slaves=0,sentinels=1\r\n",
+            info(&mut data, &[])
+        );
+        assert_eq!("", info(&mut data, arguments!["replication"]));
+    }
+}
@@ -0,0 +1,820 @@
+use std::cmp::Ordering;
+use std::str;
+
+use super::{bulk_string, bytes, keyword, Command, Data, Response, WRONG_TYPE};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::json::{parse, Format, Json};
+use crate::json_path::{get, get_mut, JsonPath, Step};
+use crate::object::Object;
+
+pub(crate) struct JsonSet;
+pub(crate) struct JsonGet;
+pub(crate) struct JsonDel;
+pub(crate) struct JsonMGet;
+pub(crate) struct JsonNumIncrBy;
+pub(crate) struct JsonArrAppend;
+pub(crate) struct JsonObjKeys;
+pub(crate) struct JsonType;
+
+const INVALID_JSON: &str = "invalid JSON";
+const INVALID_PATH: &str = "invalid path";
+const PATH_DOES_NOT_EXIST: &str = "path does not exist";
+const WRONG_PATH_TYPE: &str = "wrong type of path value";
+const NO_SUCH_KEY: &str = "could not perform this operation on a key that doesn't exist";
+const NOT_A_NUMBER: &str = "result is not a number";
+
+fn parse_path(argument: &Value) -> Result<JsonPath, Response> {
+    bytes(argument)
+        .and_then(|b| str::from_utf8(b).ok())
+        .and_then(|s| JsonPath::parse(s).ok())
+        .ok_or(Response::Error(INVALID_PATH))
+}
+
+fn parse_json(argument: &Value) -> Result<Json, Response> {
+    bytes(argument)
+        .and_then(|b| parse(b).ok())
+        .ok_or(Response::Error(INVALID_JSON))
+}
+
+fn get_document<'a>(data: &'a mut Data, key: &BulkString) -> Result<Option<&'a Json>, Response> {
+    match data.get(key) {
+        Some(Object::Json(document)) => Ok(Some(document)),
+        Some(_) => Err(Response::Error(WRONG_TYPE)),
+        None => Ok(None),
+    }
+}
+
+fn get_document_mut<'a>(data: &'a mut Data, key: &BulkString) -> Result<&'a mut Json, Response> {
+    match data.get_mut(key) {
+        Some(Object::Json(document)) => Ok(document),
+        Some(_) => Err(Response::Error(WRONG_TYPE)),
+        None => Err(Response::Error(NO_SUCH_KEY)),
+    }
+}
+
+/// Matches `path` against `document`, returning the values in the shape `JSON.GET` uses: the first match for legacy
+/// paths and an array of all matches for JSONPath.
+fn query(document: &Json, path: &JsonPath) -> Option<Json> {
+    let matches = path.evaluate(document);
+
+    if path.is_legacy() {
+        matches.first().and_then(|p| get(document, p)).cloned()
+    } else {
+        Some(Json::Array(
+            matches
+                .iter()
+                .filter_map(|p| get(document, p))
+                .cloned()
+                .collect(),
+        ))
+    }
+}
+
+impl Command for JsonSet {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !(3..=4).contains(&arguments.len()) {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let path = match parse_path(&arguments[1]) {
+            Ok(path) => path,
+            Err(e) => return e,
+        };
+
+        let value = match parse_json(&arguments[2]) {
+            Ok(value) => value,
+            Err(e) => return e,
+        };
+
+        let (only_new, only_existing) = match arguments.get(3).map(keyword) {
+            None => (false, false),
+            Some(Some(option)) if option == "NX" => (true, false),
+            Some(Some(option)) if option == "XX" => (false, true),
+            Some(_) => return Response::Error("syntax error"),
+        };
+
+        let document = match data.get_mut(key) {
+            Some(Object::Json(document)) => document,
+            Some(_) => return Response::Error(WRONG_TYPE),
+            None => {
+                if only_existing {
+                    return Response::BulkString(BulkString::Null);
+                }
+
+                if !path.is_root() {
+                    return Response::Error("new objects must be created at the root");
+                }
+
+                data.insert(key.clone(), Object::Json(value));
+
+                return Response::SimpleString("OK");
+            }
+        };
+
+        let matches = path.evaluate(document);
+
+        if !matches.is_empty() {
+            if only_new {
+                return Response::BulkString(BulkString::Null);
+            }
+
+            // Later matches can be nested inside earlier ones, so replace them first.
+            for p in matches.iter().rev() {
+                if let Some(target) = get_mut(document, p) {
+                    *target = value.clone();
+                }
+            }
+
+            data.touch(key);
+
+            return Response::SimpleString("OK");
+        }
+
+        if only_existing {
+            return Response::BulkString(BulkString::Null);
+        }
+
+        let (parent, name) = match path.split_last_name() {
+            Some(split) => split,
+            None => return Response::BulkString(BulkString::Null),
+        };
+
+        let mut created = false;
+
+        for p in parent.evaluate(document) {
+            if let Some(Json::Object(members)) = get_mut(document, &p) {
+                members.push((name.to_string(), value.clone()));
+                created = true;
+            }
+        }
+
+        if created {
+            data.touch(key);
+
+            Response::SimpleString("OK")
+        } else {
+            Response::BulkString(BulkString::Null)
+        }
+    }
+}
+
+impl Command for JsonGet {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.is_empty() {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let mut format = Format::default();
+        let mut rest = &arguments[1..];
+
+        while let [option, value, tail @ ..] = rest {
+            let target = match keyword(option).as_deref() {
+                Some("INDENT") => &mut format.indent,
+                Some("NEWLINE") => &mut format.newline,
+                Some("SPACE") => &mut format.space,
+                _ => break,
+            };
+
+            match bytes(value).and_then(|b| str::from_utf8(b).ok()) {
+                Some(value) => *target = value.to_string(),
+                None => return Response::Error("invalid argument"),
+            }
+
+            rest = tail;
+        }
+
+        let paths: Result<Vec<JsonPath>, Response> = rest.iter().map(parse_path).collect();
+        let mut paths = match paths {
+            Ok(paths) => paths,
+            Err(e) => return e,
+        };
+
+        if paths.is_empty() {
+            paths.push(JsonPath::parse(".").unwrap());
+        }
+
+        let document = match get_document(data, key) {
+            Ok(Some(document)) => document,
+            Ok(None) => return Response::BulkString(BulkString::Null),
+            Err(e) => return e,
+        };
+
+        let result = if let [path] = paths.as_slice() {
+            match query(document, path) {
+                Some(result) => result,
+                None => return Response::Error(PATH_DOES_NOT_EXIST),
+            }
+        } else {
+            // With several paths, the reply is an object keyed by path. A single JSONPath among them means all of
+            // them are answered with arrays of matches.
+            let legacy = paths.iter().all(JsonPath::is_legacy);
+            let mut members = Vec::new();
+
+            for (argument, path) in rest.iter().zip(&paths) {
+                let name =
+                    String::from_utf8_lossy(bytes(argument).unwrap_or_default()).into_owned();
+
+                let value = if legacy {
+                    match query(document, path) {
+                        Some(value) => value,
+                        None => return Response::Error(PATH_DOES_NOT_EXIST),
+                    }
+                } else {
+                    Json::Array(
+                        path.evaluate(document)
+                            .iter()
+                            .filter_map(|p| get(document, p))
+                            .cloned()
+                            .collect(),
+                    )
+                };
+
+                members.push((name, value));
+            }
+
+            Json::Object(members)
+        };
+
+        bulk_string(&result.serialize_with(&format))
+    }
+}
+
+fn compare_paths(a: &[Step], b: &[Step]) -> Ordering {
+    for (x, y) in a.iter().zip(b) {
+        let ordering = match (x, y) {
+            (Step::Index(x), Step::Index(y)) => x.cmp(y),
+            (Step::Key(x), Step::Key(y)) => x.cmp(y),
+            (Step::Index(_), Step::Key(_)) => Ordering::Less,
+            (Step::Key(_), Step::Index(_)) => Ordering::Greater,
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+impl Command for JsonDel {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !(1..=2).contains(&arguments.len()) {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let path = match arguments.get(1).map(parse_path) {
+            Some(Ok(path)) => path,
+            Some(Err(e)) => return e,
+            None => JsonPath::parse("$").unwrap(),
+        };
+
+        let document = match data.get_mut(key) {
+            Some(Object::Json(document)) => document,
+            Some(_) => return Response::Error(WRONG_TYPE),
+            None => return Response::Integer(0),
+        };
+
+        let mut matches = path.evaluate(document);
+
+        if matches.iter().any(|p| p.is_empty()) {
+            data.remove(key);
+
+            return Response::Integer(1);
+        }
+
+        // Deleting from the back means removing an array element never shifts the index of another match, and
+        // descendants are removed before their ancestors.
+        matches.sort_by(|a, b| compare_paths(b, a));
+        matches.dedup();
+
+        let mut deleted = 0;
+
+        for p in matches {
+            let (last, parent) = p.split_last().unwrap();
+
+            let removed = match (get_mut(document, parent), last) {
+                (Some(Json::Object(members)), Step::Key(name)) => {
+                    let before = members.len();
+
+                    members.retain(|(k, _)| k != name);
+
+                    members.len() != before
+                }
+                (Some(Json::Array(items)), Step::Index(i)) if *i < items.len() => {
+                    items.remove(*i);
+
+                    true
+                }
+                _ => false,
+            };
+
+            if removed {
+                deleted += 1;
+            }
+        }
+
+        if deleted > 0 {
+            data.touch(key);
+        }
+
+        Response::Integer(deleted)
+    }
+}
+
+impl Command for JsonMGet {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let (path, keys) = arguments.split_last().unwrap();
+
+        let path = match parse_path(path) {
+            Ok(path) => path,
+            Err(e) => return e,
+        };
+
+        Response::Array(
+            keys.iter()
+                .map(|key| {
+                    let document = match key {
+                        Value::BulkString(key) => match data.get(key) {
+                            Some(Object::Json(document)) => document,
+                            _ => return Response::BulkString(BulkString::Null),
+                        },
+                    };
+
+                    match query(document, &path) {
+                        Some(value) => bulk_string(&value.serialize()),
+                        None => Response::BulkString(BulkString::Null),
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Adds `increment` to `value`, returning `None` if `value` isn't a number, and an error if the sum can't be
+/// represented in JSON.
+fn add(value: &Json, increment: &Json) -> Result<Option<Json>, Response> {
+    let result = match (value, increment) {
+        (Json::Integer(a), Json::Integer(b)) => match a.checked_add(*b) {
+            Some(sum) => return Ok(Some(Json::Integer(sum))),
+            None => *a as f64 + *b as f64,
+        },
+        (Json::Integer(a), Json::Float(b)) => *a as f64 + b,
+        (Json::Float(a), Json::Integer(b)) => a + *b as f64,
+        (Json::Float(a), Json::Float(b)) => a + b,
+        _ => return Ok(None),
+    };
+
+    if result.is_finite() {
+        Ok(Some(Json::Float(result)))
+    } else {
+        Err(Response::Error(NOT_A_NUMBER))
+    }
+}
+
+impl Command for JsonNumIncrBy {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 3 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let path = match parse_path(&arguments[1]) {
+            Ok(path) => path,
+            Err(e) => return e,
+        };
+
+        let increment = match parse_json(&arguments[2]) {
+            Ok(increment @ (Json::Integer(_) | Json::Float(_))) => increment,
+            Ok(_) => return Response::Error("increment must be a number"),
+            Err(e) => return e,
+        };
+
+        let document = match get_document_mut(data, key) {
+            Ok(document) => document,
+            Err(e) => return e,
+        };
+
+        let matches = path.evaluate(document);
+
+        if path.is_legacy() {
+            let target = match matches.first().and_then(|p| get_mut(document, p)) {
+                Some(target) => target,
+                None => return Response::Error(PATH_DOES_NOT_EXIST),
+            };
+
+            return match add(target, &increment) {
+                Ok(Some(result)) => {
+                    *target = result.clone();
+                    data.touch(key);
+
+                    bulk_string(&result.serialize())
+                }
+                Ok(None) => Response::Error(WRONG_PATH_TYPE),
+                Err(e) => e,
+            };
+        }
+
+        // Every sum is worked out before any is stored, so an overflow leaves the whole document unchanged.
+        let mut results = Vec::new();
+
+        for p in &matches {
+            let result = match get_mut(document, p).map(|target| add(target, &increment)) {
+                Some(Ok(result)) => result,
+                Some(Err(e)) => return e,
+                None => None,
+            };
+
+            results.push(result);
+        }
+
+        for (p, result) in matches.iter().zip(&results) {
+            if let (Some(target), Some(result)) = (get_mut(document, p), result) {
+                *target = result.clone();
+            }
+        }
+
+        if results.iter().any(Option::is_some) {
+            data.touch(key);
+        }
+
+        bulk_string(
+            &Json::Array(
+                results
+                    .into_iter()
+                    .map(|result| result.unwrap_or(Json::Null))
+                    .collect(),
+            )
+            .serialize(),
+        )
+    }
+}
+
+impl Command for JsonArrAppend {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 3 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let path = match parse_path(&arguments[1]) {
+            Ok(path) => path,
+            Err(e) => return e,
+        };
+
+        let values: Result<Vec<Json>, Response> = arguments[2..].iter().map(parse_json).collect();
+        let values = match values {
+            Ok(values) => values,
+            Err(e) => return e,
+        };
+
+        let document = match get_document_mut(data, key) {
+            Ok(document) => document,
+            Err(e) => return e,
+        };
+
+        let matches = path.evaluate(document);
+
+        let mut append = |p: &[Step]| match get_mut(document, p) {
+            Some(Json::Array(items)) => {
+                items.extend(values.iter().cloned());
+
+                Some(items.len() as i64)
+            }
+            _ => None,
+        };
+
+        let lengths: Vec<Option<i64>> = if path.is_legacy() {
+            matches.first().map(|p| append(p)).into_iter().collect()
+        } else {
+            matches.iter().map(|p| append(p)).collect()
+        };
+
+        if lengths.iter().any(Option::is_some) {
+            data.touch(key);
+        }
+
+        if path.is_legacy() {
+            return match lengths.first() {
+                None => Response::Error(PATH_DOES_NOT_EXIST),
+                Some(Some(length)) => Response::Integer(*length),
+                Some(None) => Response::Error(WRONG_PATH_TYPE),
+            };
+        }
+
+        Response::Array(
+            lengths
+                .into_iter()
+                .map(|length| match length {
+                    Some(length) => Response::Integer(length),
+                    None => Response::BulkString(BulkString::Null),
+                })
+                .collect(),
+        )
+    }
+}
+
+fn object_keys(value: Option<&Json>) -> Option<Response> {
+    match value {
+        Some(Json::Object(members)) => Some(Response::Array(
+            members.iter().map(|(name, _)| bulk_string(name)).collect(),
+        )),
+        _ => None,
+    }
+}
+
+impl Command for JsonObjKeys {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !(1..=2).contains(&arguments.len()) {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let path = match arguments.get(1).map(parse_path) {
+            Some(Ok(path)) => path,
+            Some(Err(e)) => return e,
+            None => JsonPath::parse(".").unwrap(),
+        };
+
+        let document = match get_document(data, key) {
+            Ok(Some(document)) => document,
+            Ok(None) => return Response::BulkString(BulkString::Null),
+            Err(e) => return e,
+        };
+
+        let matches = path.evaluate(document);
+
+        if path.is_legacy() {
+            return match matches.first() {
+                None => Response::BulkString(BulkString::Null),
+                Some(p) => {
+                    object_keys(get(document, p)).unwrap_or(Response::Error(WRONG_PATH_TYPE))
+                }
+            };
+        }
+
+        Response::Array(
+            matches
+                .iter()
+                .map(|p| {
+                    object_keys(get(document, p)).unwrap_or(Response::BulkString(BulkString::Null))
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Command for JsonType {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !(1..=2).contains(&arguments.len()) {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let path = match arguments.get(1).map(parse_path) {
+            Some(Ok(path)) => path,
+            Some(Err(e)) => return e,
+            None => JsonPath::parse(".").unwrap(),
+        };
+
+        let document = match get_document(data, key) {
+            Ok(Some(document)) => document,
+            Ok(None) => return Response::BulkString(BulkString::Null),
+            Err(e) => return e,
+        };
+
+        let matches = path.evaluate(document);
+        let mut types = matches
+            .iter()
+            .filter_map(|p| get(document, p))
+            .map(|value| Response::SimpleString(value.type_name()));
+
+        if path.is_legacy() {
+            types
+                .next()
+                .unwrap_or(Response::BulkString(BulkString::Null))
+        } else {
+            Response::Array(types.collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(BulkString::Filled($value.as_bytes().to_vec()))),*]
+        };
+    }
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            Response::BulkString(BulkString::Filled($value.as_bytes().to_vec()))
+        };
+    }
+
+    fn document(data: &mut Data) -> Response {
+        JsonGet.execute(data, arguments!["doc"])
+    }
+
+    #[test]
+    fn set_and_get() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Error("new objects must be created at the root"),
+            JsonSet.execute(&mut data, arguments!["doc", "$.a", "1"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            JsonSet.execute(&mut data, arguments!["doc", "$", r#"{"a":{"b":1}}"#])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            JsonSet.execute(&mut data, arguments!["doc", "$.a.c", "[1,2]"])
+        );
+        assert_eq!(
+            bulk_string!(r#"{"a":{"b":1,"c":[1,2]}}"#),
+            document(&mut data)
+        );
+        assert_eq!(
+            bulk_string!("[[1,2]]"),
+            JsonGet.execute(&mut data, arguments!["doc", "$.a.c"])
+        );
+        assert_eq!(
+            bulk_string!("[1,2]"),
+            JsonGet.execute(&mut data, arguments!["doc", ".a.c"])
+        );
+        assert_eq!(
+            Response::BulkString(BulkString::Null),
+            JsonSet.execute(&mut data, arguments!["doc", "$.a.b", "2", "NX"])
+        );
+        assert_eq!(
+            Response::BulkString(BulkString::Null),
+            JsonSet.execute(&mut data, arguments!["doc", "$.x.y", "2"])
+        );
+    }
+
+    #[test]
+    fn get_with_several_paths() {
+        let mut data = Data::new();
+
+        JsonSet.execute(&mut data, arguments!["doc", "$", r#"{"a":1,"b":{"a":2}}"#]);
+
+        assert_eq!(
+            bulk_string!(r#"{"$..a":[1,2],".b":[{"a":2}]}"#),
+            JsonGet.execute(&mut data, arguments!["doc", "$..a", ".b"])
+        );
+        assert_eq!(
+            bulk_string!("{\n\t\"a\": 1,\n\t\"b\": {\n\t\t\"a\": 2\n\t}\n}"),
+            JsonGet.execute(
+                &mut data,
+                arguments!["doc", "INDENT", "\t", "NEWLINE", "\n", "SPACE", " "]
+            )
+        );
+    }
+
+    #[test]
+    fn del_removes_every_match() {
+        let mut data = Data::new();
+
+        JsonSet.execute(
+            &mut data,
+            arguments!["doc", "$", r#"{"a":[1,2,3,4],"b":{"a":1}}"#],
+        );
+
+        assert_eq!(
+            Response::Integer(2),
+            JsonDel.execute(&mut data, arguments!["doc", "$.a[0,2]"])
+        );
+        assert_eq!(
+            bulk_string!(r#"{"a":[2,4],"b":{"a":1}}"#),
+            document(&mut data)
+        );
+        assert_eq!(
+            Response::Integer(2),
+            JsonDel.execute(&mut data, arguments!["doc", "$..a"])
+        );
+        assert_eq!(bulk_string!(r#"{"b":{}}"#), document(&mut data));
+        assert_eq!(
+            Response::Integer(1),
+            JsonDel.execute(&mut data, arguments!["doc"])
+        );
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn num_incr_by() {
+        let mut data = Data::new();
+
+        JsonSet.execute(
+            &mut data,
+            arguments!["doc", "$", r#"{"a":1,"b":"x","c":{"a":1.5}}"#],
+        );
+
+        assert_eq!(
+            bulk_string!("[3,3.5]"),
+            JsonNumIncrBy.execute(&mut data, arguments!["doc", "$..a", "2"])
+        );
+        assert_eq!(
+            bulk_string!("[null]"),
+            JsonNumIncrBy.execute(&mut data, arguments!["doc", "$.b", "2"])
+        );
+        assert_eq!(
+            bulk_string!("4"),
+            JsonNumIncrBy.execute(&mut data, arguments!["doc", ".a", "1"])
+        );
+        assert_eq!(
+            Response::Error(WRONG_PATH_TYPE),
+            JsonNumIncrBy.execute(&mut data, arguments!["doc", ".b", "1"])
+        );
+
+        JsonSet.execute(&mut data, arguments!["big", "$", "[1e308,1]"]);
+
+        assert_eq!(
+            Response::Error(NOT_A_NUMBER),
+            JsonNumIncrBy.execute(&mut data, arguments!["big", "$[*]", "1e308"])
+        );
+        assert_eq!(
+            Response::Error(NOT_A_NUMBER),
+            JsonNumIncrBy.execute(&mut data, arguments!["big", "[0]", "1e308"])
+        );
+        assert_eq!(
+            bulk_string!("[1e308,1]"),
+            JsonGet.execute(&mut data, arguments!["big"])
+        );
+    }
+
+    #[test]
+    fn arr_append_obj_keys_and_type() {
+        let mut data = Data::new();
+
+        JsonSet.execute(
+            &mut data,
+            arguments!["doc", "$", r#"{"a":[1],"b":{"c":true}}"#],
+        );
+
+        assert_eq!(
+            Response::Array(vec![
+                Response::Integer(3),
+                Response::BulkString(BulkString::Null)
+            ]),
+            JsonArrAppend.execute(&mut data, arguments!["doc", "$.*", "2", r#""three""#])
+        );
+        assert_eq!(
+            Response::Array(vec![bulk_string!("a"), bulk_string!("b")]),
+            JsonObjKeys.execute(&mut data, arguments!["doc"])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                Response::BulkString(BulkString::Null),
+                Response::Array(vec![bulk_string!("c")]),
+            ]),
+            JsonObjKeys.execute(&mut data, arguments!["doc", "$.*"])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                Response::SimpleString("array"),
+                Response::SimpleString("object")
+            ]),
+            JsonType.execute(&mut data, arguments!["doc", "$.*"])
+        );
+        assert_eq!(
+            Response::SimpleString("boolean"),
+            JsonType.execute(&mut data, arguments!["doc", ".b.c"])
+        );
+    }
+
+    #[test]
+    fn mget() {
+        let mut data = Data::new();
+
+        JsonSet.execute(&mut data, arguments!["a", "$", r#"{"x":1}"#]);
+        JsonSet.execute(&mut data, arguments!["b", "$", r#"{"x":2}"#]);
+
+        assert_eq!(
+            Response::Array(vec![
+                bulk_string!("[1]"),
+                bulk_string!("[2]"),
+                Response::BulkString(BulkString::Null),
+            ]),
+            JsonMGet.execute(&mut data, arguments!["a", "b", "c", "$.x"])
+        );
+    }
+}
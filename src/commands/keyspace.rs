@@ -0,0 +1,399 @@
+use super::db::database_index;
+use super::{keyword, Command, Data, Response};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::lazy_free;
+use crate::notify;
+
+pub(crate) struct Exists;
+pub(crate) struct Type;
+pub(crate) struct Rename;
+pub(crate) struct RenameNx;
+pub(crate) struct Copy;
+pub(crate) struct Move;
+pub(crate) struct Touch;
+pub(crate) struct Unlink;
+pub(crate) struct RandomKey;
+pub(crate) struct DbSize;
+
+/// Counts the arguments that name existing keys. A key that's given several times is counted several times.
+fn count_existing(data: &mut Data, arguments: &[Value]) -> Response {
+    if arguments.is_empty() {
+        return Response::Error("wrong number of arguments");
+    }
+
+    let mut count = 0;
+
+    for argument in arguments {
+        let key = bulk_string_or_error!(argument);
+
+        if data.contains_key(key) {
+            count += 1;
+        }
+    }
+
+    Response::Integer(count)
+}
+
+impl Command for Exists {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        count_existing(data, arguments)
+    }
+}
+
+impl Command for Touch {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        count_existing(data, arguments)
+    }
+}
+
+impl Command for Type {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 1 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        Response::SimpleString(data.get(key).map_or("none", |object| object.type_name()))
+    }
+}
+
+/// Moves the value at `from` to `to`, along with its TTL. Returns whether `from` existed.
+fn rename(data: &mut Data, from: &BulkString, to: &BulkString) -> bool {
+    let expires_at = data.expires_at(from);
+
+    let object = match data.remove(from) {
+        Some(object) => object,
+        None => return false,
+    };
+
+    data.insert(to.clone(), object);
+
+    if let Some(at) = expires_at {
+        data.set_expires_at(to, at);
+    }
+
+    data.notify(notify::GENERIC, "rename_from", from);
+    data.notify(notify::GENERIC, "rename_to", to);
+
+    true
+}
+
+impl Command for Rename {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let from = bulk_string_or_error!(&arguments[0]);
+        let to = bulk_string_or_error!(&arguments[1]);
+
+        if from == to {
+            return match data.contains_key(from) {
+                true => Response::SimpleString("OK"),
+                false => Response::Error("no such key"),
+            };
+        }
+
+        match rename(data, from, to) {
+            true => Response::SimpleString("OK"),
+            false => Response::Error("no such key"),
+        }
+    }
+}
+
+impl Command for RenameNx {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let from = bulk_string_or_error!(&arguments[0]);
+        let to = bulk_string_or_error!(&arguments[1]);
+
+        if !data.contains_key(from) {
+            return Response::Error("no such key");
+        }
+
+        if from == to || data.contains_key(to) {
+            return Response::Integer(0);
+        }
+
+        rename(data, from, to);
+
+        Response::Integer(1)
+    }
+}
+
+impl Command for Copy {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let source = bulk_string_or_error!(&arguments[0]);
+        let destination = bulk_string_or_error!(&arguments[1]);
+
+        let mut replace = false;
+        let mut database = data.selected();
+        let mut options = arguments[2..].iter();
+
+        while let Some(option) = options.next() {
+            match keyword(option).as_deref() {
+                Some("REPLACE") => replace = true,
+                Some("DB") => match options.next().map(|index| database_index(data, index)) {
+                    Some(Ok(index)) if index != 0 && data.cluster().is_enabled() => {
+                        return Response::Error(
+                            "Copying to another database is not allowed in cluster mode",
+                        )
+                    }
+                    Some(Ok(index)) => database = index,
+                    Some(Err(e)) => return e,
+                    None => return Response::Error("syntax error"),
+                },
+                _ => return Response::Error("syntax error"),
+            }
+        }
+
+        if source == destination && database == data.selected() {
+            return Response::Error("source and destination objects are the same");
+        }
+
+        let object = match data.get(source) {
+            Some(object) => object.clone(),
+            None => return Response::Integer(0),
+        };
+        let expires_at = data.expires_at(source);
+
+        let target = data.database_mut(database);
+
+        if !replace && target.contains_key(destination) {
+            return Response::Integer(0);
+        }
+
+        target.insert(destination.clone(), object);
+
+        if let Some(at) = expires_at {
+            target.set_expires_at(destination, at);
+        }
+
+        target.notify(notify::GENERIC, "copy_to", destination);
+
+        Response::Integer(1)
+    }
+}
+
+impl Command for Move {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        if data.cluster().is_enabled() {
+            return Response::Error("MOVE is not allowed in cluster mode");
+        }
+
+        let database = match database_index(data, &arguments[1]) {
+            Ok(index) => index,
+            Err(e) => return e,
+        };
+
+        if database == data.selected() {
+            return Response::Error("source and destination objects are the same");
+        }
+
+        if !data.contains_key(key) || data.database_mut(database).contains_key(key) {
+            return Response::Integer(0);
+        }
+
+        let expires_at = data.expires_at(key);
+        let object = data.remove(key).expect("key exists");
+
+        data.notify(notify::GENERIC, "move_from", key);
+
+        let target = data.database_mut(database);
+
+        target.insert(key.clone(), object);
+
+        if let Some(at) = expires_at {
+            target.set_expires_at(key, at);
+        }
+
+        target.notify(notify::GENERIC, "move_to", key);
+
+        Response::Integer(1)
+    }
+}
+
+impl Command for Unlink {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.is_empty() {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let mut unlinked = 0;
+
+        for argument in arguments {
+            let key = bulk_string_or_error!(argument);
+
+            if let Some(object) = data.remove(key) {
+                lazy_free::free(object);
+                data.notify(notify::GENERIC, "del", key);
+                unlinked += 1;
+            }
+        }
+
+        Response::Integer(unlinked)
+    }
+}
+
+impl Command for RandomKey {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !arguments.is_empty() {
+            return Response::Error("wrong number of arguments");
+        }
+
+        Response::BulkString(data.random_key().unwrap_or(BulkString::Null))
+    }
+}
+
+impl Command for DbSize {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !arguments.is_empty() {
+            return Response::Error("wrong number of arguments");
+        }
+
+        Response::Integer(data.len() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::now_ms;
+    use crate::object::Object;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn exists_counts_duplicates() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+
+        assert_eq!(
+            Response::Integer(2),
+            Exists.execute(&mut data, arguments!["a", "b", "a"])
+        );
+    }
+
+    #[test]
+    fn rename_keeps_the_ttl() {
+        let mut data = Data::from([
+            (bulk_string!("a"), Object::String(bulk_string!("1"))),
+            (bulk_string!("b"), Object::String(bulk_string!("2"))),
+        ]);
+        let at = now_ms() + 60_000;
+
+        data.set_expires_at(&bulk_string!("a"), at);
+
+        assert_eq!(
+            Response::Integer(0),
+            RenameNx.execute(&mut data, arguments!["a", "b"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Rename.execute(&mut data, arguments!["a", "b"])
+        );
+        assert_eq!(Some(at), data.expires_at(&bulk_string!("b")));
+        assert_eq!(
+            Response::Error("no such key"),
+            Rename.execute(&mut data, arguments!["a", "c"])
+        );
+        assert_eq!(
+            Response::SimpleString("string"),
+            Type.execute(&mut data, arguments!["b"])
+        );
+    }
+
+    #[test]
+    fn copy() {
+        let mut data = Data::from([
+            (bulk_string!("a"), Object::String(bulk_string!("1"))),
+            (bulk_string!("b"), Object::String(bulk_string!("2"))),
+        ]);
+
+        assert_eq!(
+            Response::Integer(0),
+            Copy.execute(&mut data, arguments!["a", "b"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            Copy.execute(&mut data, arguments!["a", "b", "DB", "0", "REPLACE"])
+        );
+        assert_eq!(
+            Some(&Object::String(bulk_string!("1"))),
+            data.get(&bulk_string!("b"))
+        );
+        assert_eq!(
+            Response::Integer(1),
+            Copy.execute(&mut data, arguments!["a", "a", "DB", "1"])
+        );
+        assert_eq!(
+            Response::Error("DB index is out of range"),
+            Copy.execute(&mut data, arguments!["a", "c", "DB", "16"])
+        );
+    }
+
+    #[test]
+    fn move_between_databases() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+        let at = now_ms() + 60_000;
+
+        data.set_expires_at(&bulk_string!("a"), at);
+
+        assert_eq!(
+            Response::Integer(1),
+            Move.execute(&mut data, arguments!["a", "2"])
+        );
+        assert!(data.is_empty());
+        assert_eq!(
+            Response::Integer(0),
+            Move.execute(&mut data, arguments!["a", "2"])
+        );
+
+        data.select(2);
+
+        assert_eq!(Some(at), data.expires_at(&bulk_string!("a")));
+        assert_eq!(
+            Response::Error("source and destination objects are the same"),
+            Move.execute(&mut data, arguments!["a", "2"])
+        );
+    }
+
+    #[test]
+    fn unlink() {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+
+        assert_eq!(
+            Response::Integer(1),
+            Unlink.execute(&mut data, arguments!["a", "b"])
+        );
+        assert_eq!(Response::Integer(0), DbSize.execute(&mut data, &[]));
+        assert_eq!(
+            Response::BulkString(BulkString::Null),
+            RandomKey.execute(&mut data, &[])
+        );
+    }
+}
@@ -1,9 +1,14 @@
+use std::str::{self, FromStr};
+
 use crate::array::Value;
 use crate::bulk_string::BulkString;
 
 pub(crate) use crate::Data;
 
-pub(crate) trait Command {
+pub(crate) const WRONG_TYPE: &str =
+    "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+pub(crate) trait Command: Sync {
     fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response;
 }
 
@@ -14,6 +19,14 @@ pub(crate) enum Response {
     Error(&'static str),
     BulkString(BulkString),
     Integer(i64),
+    Array(Vec<Response>),
+    NullArray,
+    /// Several replies sent one after the other, like the confirmations of `SUBSCRIBE` with multiple channels.
+    Sequence(Vec<Response>),
+    /// A status reply whose text is only known at runtime, like the statuses scripts return.
+    OwnedSimpleString(String),
+    /// An error whose message is only known at runtime, like the errors scripts raise.
+    OwnedError(String),
 }
 
 // TODO: I think TryFrom would technically be more appropriate here, because the conversion can yield semantically
@@ -21,7 +34,8 @@ pub(crate) enum Response {
 impl From<Response> for Vec<u8> {
     fn from(response: Response) -> Vec<u8> {
         match response {
-            Response::SimpleString(s) => {
+            Response::SimpleString(s) => Response::OwnedSimpleString(s.to_string()).into(),
+            Response::OwnedSimpleString(s) => {
                 let mut vec = vec![b'+'];
 
                 vec.extend(s.as_bytes());
@@ -29,7 +43,8 @@ impl From<Response> for Vec<u8> {
 
                 vec
             }
-            Response::Error(e) => {
+            Response::Error(e) => Response::OwnedError(e.to_string()).into(),
+            Response::OwnedError(e) => {
                 let mut vec = vec![b'-'];
 
                 vec.extend(e.as_bytes());
@@ -60,20 +75,244 @@ impl From<Response> for Vec<u8> {
 
                 v
             }
+            Response::Array(responses) => {
+                let mut vec = vec![b'*'];
+
+                vec.extend(format!("{}\r\n", responses.len()).as_bytes());
+
+                for response in responses {
+                    vec.extend(Vec::<u8>::from(response));
+                }
+
+                vec
+            }
+            Response::NullArray => b"*-1\r\n".to_vec(),
+            Response::Sequence(responses) => {
+                responses.into_iter().flat_map(Vec::<u8>::from).collect()
+            }
         }
     }
 }
 
-pub(crate) fn get_command(command: &str) -> Option<&dyn Command> {
-    match command {
-        "DEL" => Some(&Del),
-        "GET" => Some(&Get),
-        "PING" => Some(&Ping),
-        "SET" => Some(&Set),
-        _ => None,
+/// An entry in the command table.
+pub(crate) struct CommandSpec {
+    pub(crate) name: &'static str,
+    pub(crate) command: &'static dyn Command,
+    /// The number of arguments, counting the command name, like in Redis's command table. A negative arity `-n` means
+    /// at least `n` arguments.
+    pub(crate) arity: i32,
+    /// A combination of [`WRITE`], [`NO_SCRIPT`] and [`ASKING`].
+    pub(crate) flags: u32,
+    /// Where the keys are among the arguments, so that a cluster can tell which node serves the command.
+    pub(crate) keys: KeySpec,
+}
+
+/// Where a command's keys are among its arguments. Positions count the command name, like the arity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum KeySpec {
+    None,
+    /// The arguments from `first` to `last` included, every `step`. A negative `last` counts from the end, `-1` being
+    /// the last argument.
+    Range(usize, i32, usize),
+    /// As many arguments as the number at the given position says, right after it, like `EVAL`'s keys.
+    Counted(usize),
+    /// The argument at the given position, or if it's empty, every argument after the given keyword, like `MIGRATE`'s
+    /// keys.
+    Keyword(usize, &'static str),
+}
+
+/// Only the first argument is a key.
+const FIRST_KEY: KeySpec = KeySpec::Range(1, 1, 1);
+/// Every argument is a key.
+const ALL_KEYS: KeySpec = KeySpec::Range(1, -1, 1);
+
+/// The command may modify the dataset, so read-only scripts can't call it.
+pub(crate) const WRITE: u32 = 1 << 0;
+/// The command can't be called from scripts, like `EVAL` itself.
+pub(crate) const NO_SCRIPT: u32 = 1 << 1;
+/// The command may use a slot this node is importing as if `ASKING` had been sent first, like `RESTORE-ASKING`.
+pub(crate) const ASKING: u32 = 1 << 2;
+
+impl CommandSpec {
+    const fn new(
+        name: &'static str,
+        command: &'static dyn Command,
+        arity: i32,
+        flags: u32,
+    ) -> CommandSpec {
+        CommandSpec {
+            name,
+            command,
+            arity,
+            flags,
+            keys: KeySpec::None,
+        }
+    }
+
+    const fn with_keys(self, keys: KeySpec) -> CommandSpec {
+        CommandSpec { keys, ..self }
+    }
+
+    /// The keys among `arguments`, which don't include the command name.
+    pub(crate) fn keys<'a>(&self, arguments: &'a [Value]) -> Vec<&'a BulkString> {
+        let count = arguments.len() as i32 + 1;
+        let positions = match self.keys {
+            KeySpec::None => return Vec::new(),
+            KeySpec::Range(first, last, step) => {
+                let last = if last < 0 { count + last } else { last };
+
+                (first..=last.max(0) as usize).step_by(step)
+            }
+            KeySpec::Counted(position) => {
+                let Some(keys) = arguments.get(position - 1).and_then(number::<usize>) else {
+                    return Vec::new();
+                };
+
+                (position + 1..=position + keys).step_by(1)
+            }
+            KeySpec::Keyword(position, keyword) => {
+                if !arguments
+                    .get(position - 1)
+                    .and_then(bytes)
+                    .is_some_and(<[u8]>::is_empty)
+                {
+                    (position..=position).step_by(1)
+                } else {
+                    let Some(index) = arguments.iter().skip(position).position(|argument| {
+                        bytes(argument).is_some_and(|b| b.eq_ignore_ascii_case(keyword.as_bytes()))
+                    }) else {
+                        return Vec::new();
+                    };
+
+                    (position + index + 2..=arguments.len()).step_by(1)
+                }
+            }
+        };
+
+        positions
+            .filter_map(|position| arguments.get(position.checked_sub(1)?))
+            .map(|Value::BulkString(key)| key)
+            .collect()
+    }
+
+    pub(crate) fn is_write(&self) -> bool {
+        self.flags & WRITE != 0
+    }
+
+    pub(crate) fn is_asking(&self) -> bool {
+        self.flags & ASKING != 0
+    }
+
+    /// Checks the number of arguments, not counting the command name.
+    pub(crate) fn accepts(&self, arguments: usize) -> bool {
+        let arguments = arguments as i32 + 1;
+
+        match self.arity {
+            arity if arity < 0 => arguments >= -arity,
+            arity => arguments == arity,
+        }
     }
 }
 
+/// Every command, sorted by name.
+static COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("BF.ADD", &BfAdd, 3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("BF.EXISTS", &BfExists, 3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("BF.INFO", &BfInfo, -2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("BF.MADD", &BfMAdd, -3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("BF.MEXISTS", &BfMExists, -3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("BF.RESERVE", &BfReserve, -4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("BGREWRITEAOF", &BgRewriteAof, 1, NO_SCRIPT),
+    CommandSpec::new("BGSAVE", &BgSave, 1, NO_SCRIPT),
+    CommandSpec::new("CF.ADD", &CfAdd, 3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.ADDNX", &CfAddNx, 3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.COUNT", &CfCount, 3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.DEL", &CfDel, 3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.EXISTS", &CfExists, 3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.INFO", &CfInfo, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.INSERT", &CfInsert, -4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.INSERTNX", &CfInsertNx, -4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.MEXISTS", &CfMExists, -3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("CF.RESERVE", &CfReserve, -3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("CLUSTER", &Cluster, -2, NO_SCRIPT),
+    CommandSpec::new("CONFIG", &Config, -2, NO_SCRIPT),
+    CommandSpec::new("COPY", &Copy, -3, WRITE).with_keys(KeySpec::Range(1, 2, 1)),
+    CommandSpec::new("DBSIZE", &DbSize, 1, 0),
+    CommandSpec::new("DEL", &Del, -2, WRITE).with_keys(ALL_KEYS),
+    CommandSpec::new("DUMP", &Dump, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("EVAL", &Eval, -3, NO_SCRIPT).with_keys(KeySpec::Counted(2)),
+    CommandSpec::new("EVALSHA", &EvalSha, -3, NO_SCRIPT).with_keys(KeySpec::Counted(2)),
+    CommandSpec::new("EVALSHA_RO", &EvalShaRo, -3, NO_SCRIPT).with_keys(KeySpec::Counted(2)),
+    CommandSpec::new("EVAL_RO", &EvalRo, -3, NO_SCRIPT).with_keys(KeySpec::Counted(2)),
+    CommandSpec::new("EXISTS", &Exists, -2, 0).with_keys(ALL_KEYS),
+    CommandSpec::new("EXPIRE", &Expire, -3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("EXPIREAT", &ExpireAt, -3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("FCALL", &FCall, -3, NO_SCRIPT).with_keys(KeySpec::Counted(2)),
+    CommandSpec::new("FCALL_RO", &FCallRo, -3, NO_SCRIPT).with_keys(KeySpec::Counted(2)),
+    CommandSpec::new("FLUSHALL", &FlushAll, -1, WRITE),
+    CommandSpec::new("FLUSHDB", &FlushDb, -1, WRITE),
+    CommandSpec::new("FUNCTION", &Function, -2, NO_SCRIPT),
+    CommandSpec::new("GET", &Get, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("HSCAN", &HScan, -3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("INFO", &Info, -1, 0),
+    CommandSpec::new("JSON.ARRAPPEND", &JsonArrAppend, -4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("JSON.DEL", &JsonDel, -2, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("JSON.GET", &JsonGet, -2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("JSON.MGET", &JsonMGet, -3, 0).with_keys(KeySpec::Range(1, -2, 1)),
+    CommandSpec::new("JSON.NUMINCRBY", &JsonNumIncrBy, 4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("JSON.OBJKEYS", &JsonObjKeys, -2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("JSON.SET", &JsonSet, -4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("JSON.TYPE", &JsonType, -2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("KEYS", &Keys, 2, 0),
+    CommandSpec::new("LASTSAVE", &LastSave, 1, 0),
+    CommandSpec::new("MIGRATE", &Migrate, -6, WRITE).with_keys(KeySpec::Keyword(3, "KEYS")),
+    CommandSpec::new("MOVE", &Move, 3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("PERSIST", &Persist, 2, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("PEXPIRE", &PExpire, -3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("PEXPIREAT", &PExpireAt, -3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("PING", &Ping, -1, 0),
+    CommandSpec::new("PTTL", &PTtl, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("PUBLISH", &Publish, 3, 0),
+    CommandSpec::new("PUBSUB", &PubSub, -2, 0),
+    CommandSpec::new("RANDOMKEY", &RandomKey, 1, 0),
+    CommandSpec::new("RENAME", &Rename, 3, WRITE).with_keys(KeySpec::Range(1, 2, 1)),
+    CommandSpec::new("RENAMENX", &RenameNx, 3, WRITE).with_keys(KeySpec::Range(1, 2, 1)),
+    CommandSpec::new("REPLICAOF", &ReplicaOf, 3, NO_SCRIPT),
+    CommandSpec::new("RESTORE", &Restore, -4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("RESTORE-ASKING", &Restore, -4, WRITE | ASKING).with_keys(FIRST_KEY),
+    CommandSpec::new("ROLE", &Role, 1, NO_SCRIPT),
+    CommandSpec::new("SAVE", &Save, 1, NO_SCRIPT),
+    CommandSpec::new("SCAN", &Scan, -2, 0),
+    CommandSpec::new("SCRIPT", &Script, -2, NO_SCRIPT),
+    CommandSpec::new("SELECT", &Select, 2, 0),
+    CommandSpec::new("SENTINEL", &Sentinel, -2, NO_SCRIPT),
+    CommandSpec::new("SET", &Set, -3, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("SLAVEOF", &ReplicaOf, 3, NO_SCRIPT),
+    CommandSpec::new("SPUBLISH", &SPublish, 3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("SSCAN", &SScan, -3, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("SWAPDB", &SwapDb, 3, WRITE),
+    CommandSpec::new("TOUCH", &Touch, -2, 0).with_keys(ALL_KEYS),
+    CommandSpec::new("TS.ADD", &TsAdd, -4, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("TS.CREATE", &TsCreate, -2, WRITE).with_keys(FIRST_KEY),
+    CommandSpec::new("TS.GET", &TsGet, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("TS.INFO", &TsInfo, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("TS.MRANGE", &TsMRange, -5, 0),
+    CommandSpec::new("TS.RANGE", &TsRange, -4, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("TS.REVRANGE", &TsRevRange, -4, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("TTL", &Ttl, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("TYPE", &Type, 2, 0).with_keys(FIRST_KEY),
+    CommandSpec::new("UNLINK", &Unlink, -2, WRITE).with_keys(ALL_KEYS),
+    CommandSpec::new("ZSCAN", &ZScan, -3, 0).with_keys(FIRST_KEY),
+];
+
+/// Looks up a command by its uppercased name.
+pub(crate) fn get_command(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .binary_search_by(|spec| spec.name.cmp(name))
+        .ok()
+        .map(|index| &COMMANDS[index])
+}
+
 macro_rules! bulk_string_or_error {
     ($argument:expr) => {
         bulk_string_or_error!($argument, "invalid argument")
@@ -88,12 +327,154 @@ macro_rules! bulk_string_or_error {
     };
 }
 
+/// Returns the raw bytes of an argument, treating an empty bulk string as an empty slice.
+pub(crate) fn bytes(argument: &Value) -> Option<&[u8]> {
+    match argument {
+        Value::BulkString(BulkString::Filled(b)) => Some(b),
+        Value::BulkString(BulkString::Empty) => Some(&[]),
+        Value::BulkString(BulkString::Null) => None,
+    }
+}
+
+/// A bulk string reply holding `s`.
+pub(crate) fn bulk_string(s: &str) -> Response {
+    Response::BulkString(BulkString::Filled(s.as_bytes().to_vec()))
+}
+
+/// Decodes an argument as an uppercased option name like `NX` or `EXPANSION`.
+pub(crate) fn keyword(argument: &Value) -> Option<String> {
+    bytes(argument)
+        .and_then(|b| str::from_utf8(b).ok())
+        .map(str::to_uppercase)
+}
+
+/// Parses a numeric argument.
+pub(crate) fn number<T: FromStr>(argument: &Value) -> Option<T> {
+    bytes(argument)
+        .and_then(|b| str::from_utf8(b).ok())
+        .and_then(|s| s.parse().ok())
+}
+
+pub(crate) mod bf;
+pub(crate) mod cf;
+pub(crate) mod cluster;
+pub(crate) mod config;
+pub(crate) mod db;
 pub(crate) mod del;
+pub(crate) mod dump;
+pub(crate) mod eval;
+pub(crate) mod expire;
+pub(crate) mod function;
 pub(crate) mod get;
+pub(crate) mod info;
+pub(crate) mod json;
+pub(crate) mod keyspace;
 pub(crate) mod ping;
+pub(crate) mod pubsub;
+pub(crate) mod replication;
+pub(crate) mod save;
+pub(crate) mod scan;
+pub(crate) mod sentinel;
 pub(crate) mod set;
+pub(crate) mod ts;
 
+pub(crate) use bf::{BfAdd, BfExists, BfInfo, BfMAdd, BfMExists, BfReserve};
+pub(crate) use cf::{
+    CfAdd, CfAddNx, CfCount, CfDel, CfExists, CfInfo, CfInsert, CfInsertNx, CfMExists, CfReserve,
+};
+pub(crate) use cluster::Cluster;
+pub(crate) use config::Config;
+pub(crate) use db::{FlushAll, FlushDb, Select, SwapDb};
 pub(crate) use del::Del;
+pub(crate) use dump::{Dump, Migrate, Migration, Restore};
+pub(crate) use eval::{Eval, EvalRo, EvalSha, EvalShaRo, Script};
+pub(crate) use expire::{Expire, ExpireAt, PExpire, PExpireAt, PTtl, Persist, Ttl};
+pub(crate) use function::{FCall, FCallRo, Function};
 pub(crate) use get::Get;
+pub(crate) use info::Info;
+pub(crate) use json::{
+    JsonArrAppend, JsonDel, JsonGet, JsonMGet, JsonNumIncrBy, JsonObjKeys, JsonSet, JsonType,
+};
+pub(crate) use keyspace::{
+    Copy, DbSize, Exists, Move, RandomKey, Rename, RenameNx, Touch, Type, Unlink,
+};
 pub(crate) use ping::Ping;
+pub(crate) use pubsub::{PubSub, Publish, SPublish};
+pub(crate) use replication::{ReplicaOf, Role};
+pub(crate) use save::{BgRewriteAof, BgSave, LastSave, Save};
+pub(crate) use scan::{HScan, Keys, SScan, Scan, ZScan};
+pub(crate) use sentinel::Sentinel;
 pub(crate) use set::Set;
+pub(crate) use ts::{TsAdd, TsCreate, TsGet, TsInfo, TsMRange, TsRange, TsRevRange};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_table_is_sorted() {
+        assert!(COMMANDS.windows(2).all(|pair| pair[0].name < pair[1].name));
+    }
+
+    #[test]
+    fn arity() {
+        let get = get_command("GET").unwrap();
+        let set = get_command("SET").unwrap();
+
+        assert!(get.accepts(1));
+        assert!(!get.accepts(2));
+        assert!(!set.accepts(1));
+        assert!(set.accepts(4));
+        assert!(get_command("get").is_none());
+    }
+
+    #[test]
+    fn keys() {
+        let arguments = |values: &[&str]| -> Vec<Value> {
+            values
+                .iter()
+                .map(|value| Value::BulkString(BulkString::Filled(value.as_bytes().to_vec())))
+                .collect()
+        };
+        let keys = |name: &str, values: &[&str]| -> Vec<String> {
+            get_command(name)
+                .unwrap()
+                .keys(&arguments(values))
+                .into_iter()
+                .map(|key| match key {
+                    BulkString::Filled(key) => String::from_utf8_lossy(key).into_owned(),
+                    _ => panic!("expected a filled bulk string"),
+                })
+                .collect()
+        };
+
+        assert_eq!(vec!["a"], keys("SET", &["a", "1", "EX", "10"]));
+        assert_eq!(vec!["a", "b"], keys("DEL", &["a", "b"]));
+        assert_eq!(vec!["a", "b"], keys("JSON.MGET", &["a", "b", "$"]));
+        assert_eq!(vec!["a"], keys("EVAL", &["return 1", "1", "a", "b"]));
+        assert!(keys("EVAL", &["return 1", "x", "a"]).is_empty());
+        assert_eq!(vec!["a"], keys("EVAL", &["return 1", "3", "a"]));
+        assert_eq!(
+            vec!["a"],
+            keys("MIGRATE", &["127.0.0.1", "7002", "a", "0", "1000"])
+        );
+        assert_eq!(
+            vec!["a", "b"],
+            keys(
+                "MIGRATE",
+                &[
+                    "127.0.0.1",
+                    "7002",
+                    "",
+                    "0",
+                    "1000",
+                    "COPY",
+                    "KEYS",
+                    "a",
+                    "b"
+                ]
+            )
+        );
+        assert!(keys("PING", &[]).is_empty());
+    }
+}
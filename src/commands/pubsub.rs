@@ -0,0 +1,161 @@
+use super::{bytes, keyword, Command, Data, Response};
+use crate::array::Value;
+use crate::pubsub::Kind;
+
+pub(crate) struct Publish;
+pub(crate) struct SPublish;
+pub(crate) struct PubSub;
+
+impl Command for Publish {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let Value::BulkString(channel) = &arguments[0];
+        let Value::BulkString(message) = &arguments[1];
+
+        Response::Integer(data.subscriptions().publish(channel, message) as i64)
+    }
+}
+
+impl Command for SPublish {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 2 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let Value::BulkString(channel) = &arguments[0];
+        let Value::BulkString(message) = &arguments[1];
+
+        Response::Integer(data.subscriptions().spublish(channel, message) as i64)
+    }
+}
+
+impl Command for PubSub {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        let Some(subcommand) = arguments.first().and_then(keyword) else {
+            return Response::Error("wrong number of arguments");
+        };
+
+        let kind = match subcommand.as_str() {
+            "SHARDCHANNELS" | "SHARDNUMSUB" => Kind::ShardChannel,
+            _ => Kind::Channel,
+        };
+
+        match (subcommand.as_str(), &arguments[1..]) {
+            ("CHANNELS" | "SHARDCHANNELS", [] | [_]) => {
+                let pattern = match arguments.get(1) {
+                    Some(pattern) => match bytes(pattern) {
+                        Some(pattern) => Some(pattern),
+                        None => return Response::Error("invalid argument"),
+                    },
+                    None => None,
+                };
+
+                let mut channels = data.subscriptions().channels(kind, pattern);
+
+                channels.sort();
+
+                Response::Array(channels.into_iter().map(Response::BulkString).collect())
+            }
+            ("NUMSUB" | "SHARDNUMSUB", channels) => {
+                let mut counts = Vec::new();
+
+                for channel in channels {
+                    let Value::BulkString(channel) = channel;
+                    let count = data.subscriptions().subscriber_count(kind, channel);
+
+                    counts.push(Response::BulkString(channel.clone()));
+                    counts.push(Response::Integer(count as i64));
+                }
+
+                Response::Array(counts)
+            }
+            ("NUMPAT", []) => Response::Integer(data.subscriptions().pattern_count() as i64),
+            _ => Response::Error("unknown subcommand or wrong number of arguments"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::bulk_string::BulkString;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn publish_and_introspect() {
+        let mut data = Data::new();
+        let (sender, receiver) = mpsc::channel();
+
+        let subscriptions = data.subscriptions_mut();
+
+        subscriptions.subscribe(Kind::Channel, &bulk_string!("news"), 1, &sender);
+        subscriptions.subscribe(Kind::Channel, &bulk_string!("sport"), 2, &sender);
+        subscriptions.subscribe(Kind::Pattern, &bulk_string!("n*"), 2, &sender);
+        subscriptions.subscribe(Kind::ShardChannel, &bulk_string!("news"), 3, &sender);
+
+        assert_eq!(
+            Response::Integer(2),
+            Publish.execute(&mut data, arguments!["news", "hi"])
+        );
+        assert_eq!(2, receiver.try_iter().count());
+        assert_eq!(
+            Response::Array(vec![
+                Response::BulkString(bulk_string!("news")),
+                Response::BulkString(bulk_string!("sport")),
+            ]),
+            PubSub.execute(&mut data, arguments!["CHANNELS"])
+        );
+        assert_eq!(
+            Response::Array(vec![Response::BulkString(bulk_string!("sport"))]),
+            PubSub.execute(&mut data, arguments!["channels", "s*"])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                Response::BulkString(bulk_string!("news")),
+                Response::Integer(1),
+                Response::BulkString(bulk_string!("other")),
+                Response::Integer(0),
+            ]),
+            PubSub.execute(&mut data, arguments!["NUMSUB", "news", "other"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            PubSub.execute(&mut data, arguments!["NUMPAT"])
+        );
+        assert_eq!(
+            Response::Error("unknown subcommand or wrong number of arguments"),
+            PubSub.execute(&mut data, arguments!["NUMPAT", "x"])
+        );
+        assert_eq!(
+            Response::Integer(1),
+            SPublish.execute(&mut data, arguments!["news", "hi"])
+        );
+        assert_eq!(
+            Response::Array(vec![Response::BulkString(bulk_string!("news"))]),
+            PubSub.execute(&mut data, arguments!["SHARDCHANNELS"])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                Response::BulkString(bulk_string!("sport")),
+                Response::Integer(0),
+            ]),
+            PubSub.execute(&mut data, arguments!["SHARDNUMSUB", "sport"])
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use super::{bulk_string, bytes, keyword, number, Command, Data, Response};
+use crate::array::Value;
+
+pub(crate) struct ReplicaOf;
+pub(crate) struct Role;
+
+impl Command for ReplicaOf {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if data.cluster().is_enabled() {
+            return Response::Error("REPLICAOF not allowed in cluster mode.");
+        }
+
+        if keyword(&arguments[0]).as_deref() == Some("NO")
+            && keyword(&arguments[1]).as_deref() == Some("ONE")
+        {
+            if data.replication().primary().is_some() {
+                data.replication_mut().remove_primary();
+                println!("MASTER MODE enabled");
+            }
+
+            return Response::SimpleString("OK");
+        }
+
+        let host = String::from_utf8_lossy(bytes(&arguments[0]).unwrap_or_default()).into_owned();
+        let Some(port) = number::<u16>(&arguments[1]) else {
+            return Response::Error("value is not an integer or out of range");
+        };
+
+        if !data.replication_mut().set_primary(host.clone(), port) {
+            return Response::SimpleString("OK Already connected to specified master");
+        }
+
+        println!("REPLICAOF {host}:{port} enabled");
+
+        Response::SimpleString("OK")
+    }
+}
+
+impl Command for Role {
+    fn execute(&self, data: &mut Data, _arguments: &[Value]) -> Response {
+        let sentinel = data.sentinel();
+
+        if sentinel.is_enabled() {
+            return Response::Array(vec![
+                bulk_string("sentinel"),
+                Response::Array(sentinel.master_names().map(bulk_string).collect()),
+            ]);
+        }
+
+        let replication = data.replication();
+
+        match replication.primary() {
+            Some(primary) => Response::Array(vec![
+                bulk_string("slave"),
+                bulk_string(primary.host()),
+                Response::Integer(primary.port() as i64),
+                bulk_string(primary.state().name()),
+                Response::Integer(replication.offset() as i64),
+            ]),
+            None => Response::Array(vec![
+                bulk_string("master"),
+                Response::Integer(replication.offset() as i64),
+                Response::Array(
+                    replication
+                        .replicas()
+                        .iter()
+                        .map(|replica| {
+                            Response::Array(vec![
+                                bulk_string(replica.address()),
+                                bulk_string(&replica.port().to_string()),
+                                bulk_string(&replica.ack_offset().to_string()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk_string::BulkString;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn replica_of_and_role() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Array(vec![
+                bulk_string("master"),
+                Response::Integer(0),
+                Response::Array(Vec::new()),
+            ]),
+            Role.execute(&mut data, &[])
+        );
+        assert_eq!(
+            Response::Error("value is not an integer or out of range"),
+            ReplicaOf.execute(&mut data, arguments!["localhost", "port"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            ReplicaOf.execute(&mut data, arguments!["localhost", "6380"])
+        );
+        assert_eq!(
+            Response::SimpleString("OK Already connected to specified master"),
+            ReplicaOf.execute(&mut data, arguments!["localhost", "6380"])
+        );
+        assert_eq!(
+            Response::Array(vec![
+                bulk_string("slave"),
+                bulk_string("localhost"),
+                Response::Integer(6380),
+                bulk_string("connect"),
+                Response::Integer(0),
+            ]),
+            Role.execute(&mut data, &[])
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            ReplicaOf.execute(&mut data, arguments!["no", "one"])
+        );
+        assert!(data.replication().primary().is_none());
+    }
+
+    #[test]
+    fn sentinel_role() {
+        let mut data = Data::new();
+
+        data.sentinel_mut().enable(26379, 30_000, 180_000);
+        data.sentinel_mut()
+            .monitor("mymaster", "127.0.0.1", 6379, 2, 0)
+            .unwrap();
+
+        assert_eq!(
+            Response::Array(vec![
+                bulk_string("sentinel"),
+                Response::Array(vec![bulk_string("mymaster")]),
+            ]),
+            Role.execute(&mut data, &[])
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use super::{Command, Data, Response};
+use crate::array::Value;
+
+pub(crate) struct Save;
+pub(crate) struct BgSave;
+pub(crate) struct LastSave;
+pub(crate) struct BgRewriteAof;
+
+impl Command for Save {
+    fn execute(&self, data: &mut Data, _: &[Value]) -> Response {
+        if data.snapshots().is_saving() {
+            return Response::Error("Background save already in progress");
+        }
+
+        match data.snapshots().save(&data.snapshot(), data.changes()) {
+            Ok(()) => Response::SimpleString("OK"),
+            Err(error) => Response::OwnedError(error.to_string()),
+        }
+    }
+}
+
+impl Command for BgSave {
+    fn execute(&self, data: &mut Data, _: &[Value]) -> Response {
+        if data.snapshots().is_saving() {
+            return Response::Error("Background save already in progress");
+        }
+
+        // Only copying the data holds up other connections; it's serialized and written on another thread.
+        let snapshot = data.snapshot();
+
+        if data
+            .snapshots()
+            .save_in_background(snapshot, data.changes())
+        {
+            Response::SimpleString("Background saving started")
+        } else {
+            Response::Error("Background save already in progress")
+        }
+    }
+}
+
+impl Command for LastSave {
+    fn execute(&self, data: &mut Data, _: &[Value]) -> Response {
+        Response::Integer(data.snapshots().last_save() as i64)
+    }
+}
+
+impl Command for BgRewriteAof {
+    fn execute(&self, data: &mut Data, _: &[Value]) -> Response {
+        match data.rewrite_aof() {
+            Ok(()) => Response::SimpleString("Background append only file rewriting started"),
+            Err(error) => Response::OwnedError(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::bulk_string::BulkString;
+    use crate::object::Object;
+    use crate::rdb::{self, Snapshots};
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    fn data_saved_to(name: &str) -> Data {
+        let mut data = Data::from([(bulk_string!("a"), Object::String(bulk_string!("1")))]);
+        let path = std::env::temp_dir().join(format!("red-{}-{name}.rdb", process::id()));
+
+        data.set_snapshots(Snapshots::new(path));
+
+        data
+    }
+
+    #[test]
+    fn save() {
+        let mut data = data_saved_to("save");
+
+        assert_eq!(1, data.snapshots().changes_since_save(data.changes()));
+        assert_eq!(Response::SimpleString("OK"), Save.execute(&mut data, &[]));
+        assert_eq!(0, data.snapshots().changes_since_save(data.changes()));
+        assert!(matches!(LastSave.execute(&mut data, &[]), Response::Integer(n) if n > 0));
+
+        let path = data.snapshots().path().to_path_buf();
+        let mut loaded = Data::new();
+
+        loaded.load(rdb::load(&path).unwrap().unwrap()).unwrap();
+
+        assert!(loaded.contains_key(&bulk_string!("a")));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bgsave() {
+        let mut data = data_saved_to("bgsave");
+
+        assert_eq!(
+            Response::SimpleString("Background saving started"),
+            BgSave.execute(&mut data, &[])
+        );
+
+        while data.snapshots().is_saving() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let path = data.snapshots().path().to_path_buf();
+
+        assert_eq!(
+            data.snapshot().databases[0],
+            rdb::load(&path).unwrap().unwrap().databases[0]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bgrewriteaof() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::OwnedError("Append only file is disabled".to_string()),
+            BgRewriteAof.execute(&mut data, &[])
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use super::{bytes, keyword, number, Command, Data, Response, WRONG_TYPE};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::glob;
+
+pub(crate) struct Keys;
+pub(crate) struct Scan;
+pub(crate) struct HScan;
+pub(crate) struct SScan;
+pub(crate) struct ZScan;
+
+const DEFAULT_COUNT: usize = 10;
+
+impl Command for Keys {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 1 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let pattern = match bytes(&arguments[0]) {
+            Some(pattern) => pattern,
+            None => return Response::Error("invalid argument"),
+        };
+
+        Response::Array(
+            data.iter()
+                .filter(|(key, _)| glob::matches(pattern, key_bytes(key), false))
+                .map(|(key, _)| Response::BulkString(key.clone()))
+                .collect(),
+        )
+    }
+}
+
+fn key_bytes(key: &BulkString) -> &[u8] {
+    match key {
+        BulkString::Filled(bytes) => bytes,
+        _ => &[],
+    }
+}
+
+struct ScanOptions<'a> {
+    cursor: u64,
+    pattern: Option<&'a [u8]>,
+    count: usize,
+    type_name: Option<String>,
+}
+
+fn parse_options(arguments: &[Value], allow_type: bool) -> Result<ScanOptions<'_>, Response> {
+    let mut options = ScanOptions {
+        cursor: number(&arguments[0]).ok_or(Response::Error("invalid cursor"))?,
+        pattern: None,
+        count: DEFAULT_COUNT,
+        type_name: None,
+    };
+
+    let mut arguments = arguments[1..].iter();
+
+    while let Some(argument) = arguments.next() {
+        match keyword(argument).as_deref() {
+            Some("MATCH") => {
+                options.pattern = Some(
+                    arguments
+                        .next()
+                        .and_then(bytes)
+                        .ok_or(Response::Error("syntax error"))?,
+                );
+            }
+            Some("COUNT") => {
+                options.count = match arguments.next().and_then(number) {
+                    Some(count) if count > 0 => count,
+                    Some(_) => return Err(Response::Error("syntax error")),
+                    None => return Err(Response::Error("value is not an integer or out of range")),
+                };
+            }
+            Some("TYPE") if allow_type => {
+                options.type_name = Some(
+                    arguments
+                        .next()
+                        .and_then(keyword)
+                        .ok_or(Response::Error("syntax error"))?,
+                );
+            }
+            _ => return Err(Response::Error("syntax error")),
+        }
+    }
+
+    Ok(options)
+}
+
+fn scan_response(cursor: u64, keys: Vec<BulkString>) -> Response {
+    Response::Array(vec![
+        Response::BulkString(BulkString::Filled(cursor.to_string().into_bytes())),
+        Response::Array(keys.into_iter().map(Response::BulkString).collect()),
+    ])
+}
+
+impl Command for Scan {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.is_empty() {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let options = match parse_options(arguments, true) {
+            Ok(options) => options,
+            Err(e) => return e,
+        };
+
+        let mut keys = Vec::new();
+        let mut cursor = options.cursor;
+        // COUNT is only a hint. Like Redis, give up after visiting ten times as many buckets, so that a sparse table
+        // or a selective pattern doesn't make a single call walk all of it.
+        let mut buckets = options.count.saturating_mul(10);
+
+        loop {
+            cursor = data.scan(cursor, |key, object| {
+                let pattern_matches = options
+                    .pattern
+                    .is_none_or(|pattern| glob::matches(pattern, key_bytes(key), false));
+                let type_matches = options
+                    .type_name
+                    .as_ref()
+                    .is_none_or(|name| object.type_name().eq_ignore_ascii_case(name));
+
+                if pattern_matches && type_matches {
+                    keys.push(key.clone());
+                }
+            });
+
+            buckets -= 1;
+
+            if cursor == 0 || keys.len() >= options.count || buckets == 0 {
+                break;
+            }
+        }
+
+        scan_response(cursor, keys)
+    }
+}
+
+/// Scans the elements of a collection. There are no hash, set or sorted set types yet, so the key either doesn't
+/// exist, which scans like an empty collection, or holds some other type.
+fn scan_collection(data: &mut Data, arguments: &[Value]) -> Response {
+    if arguments.len() < 2 {
+        return Response::Error("wrong number of arguments");
+    }
+
+    let key = bulk_string_or_error!(&arguments[0]);
+
+    if let Err(e) = parse_options(&arguments[1..], false) {
+        return e;
+    }
+
+    match data.get(key) {
+        Some(_) => Response::Error(WRONG_TYPE),
+        None => scan_response(0, Vec::new()),
+    }
+}
+
+impl Command for HScan {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        scan_collection(data, arguments)
+    }
+}
+
+impl Command for SScan {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        scan_collection(data, arguments)
+    }
+}
+
+impl Command for ZScan {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        scan_collection(data, arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::object::Object;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    fn data() -> Data {
+        let mut data = Data::new();
+
+        for i in 0..100 {
+            data.insert(
+                bulk_string!(format!("key:{i}")),
+                Object::String(bulk_string!("value")),
+            );
+        }
+
+        data.insert(bulk_string!("other"), Object::String(bulk_string!("value")));
+
+        data
+    }
+
+    #[test]
+    fn keys() {
+        let mut data = data();
+
+        match Keys.execute(&mut data, arguments!["key:1?"]) {
+            Response::Array(keys) => assert_eq!(10, keys.len()),
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[test]
+    fn scan_returns_every_key() {
+        let mut data = data();
+        let mut seen = HashSet::new();
+        let mut cursor = "0".to_string();
+
+        loop {
+            let response = Scan.execute(
+                &mut data,
+                arguments![
+                    cursor.as_str(),
+                    "MATCH",
+                    "key:*",
+                    "COUNT",
+                    "7",
+                    "TYPE",
+                    "string"
+                ],
+            );
+
+            match response {
+                Response::Array(mut parts) => {
+                    let Response::Array(keys) = parts.pop().unwrap() else {
+                        panic!("expected an array of keys");
+                    };
+                    let Response::BulkString(BulkString::Filled(next)) = parts.pop().unwrap()
+                    else {
+                        panic!("expected a cursor");
+                    };
+
+                    seen.extend(keys.into_iter().map(|key| match key {
+                        Response::BulkString(key) => key,
+                        response => panic!("unexpected key {response:?}"),
+                    }));
+                    cursor = String::from_utf8(next).unwrap();
+                }
+                response => panic!("unexpected response {response:?}"),
+            }
+
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        assert_eq!(100, seen.len());
+    }
+
+    #[test]
+    fn scan_with_a_huge_count() {
+        let mut data = data();
+
+        match Scan.execute(&mut data, arguments!["0", "COUNT", "18446744073709551615"]) {
+            Response::Array(parts) => {
+                assert_eq!(Response::BulkString(bulk_string!("0")), parts[0]);
+                assert!(matches!(&parts[1], Response::Array(keys) if keys.len() == 101));
+            }
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[test]
+    fn collection_scans() {
+        let mut data = data();
+
+        assert_eq!(
+            Response::Error(WRONG_TYPE),
+            HScan.execute(&mut data, arguments!["other", "0"])
+        );
+        assert_eq!(
+            scan_response(0, Vec::new()),
+            SScan.execute(&mut data, arguments!["missing", "0", "COUNT", "5"])
+        );
+        assert_eq!(
+            Response::Error("invalid cursor"),
+            ZScan.execute(&mut data, arguments!["missing", "x"])
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use std::net::IpAddr;
+
+use super::{bulk_string, bytes, keyword, number, Command, Data, Response};
+use crate::array::Value;
+use crate::database::now_ms;
+use crate::sentinel::Fields;
+
+pub(crate) struct Sentinel;
+
+const NO_SUCH_MASTER: &str = "No such master with that name";
+
+fn text(value: &Value) -> String {
+    String::from_utf8_lossy(bytes(value).unwrap_or_default()).into_owned()
+}
+
+/// Replies with fields as a flat array of names and values, like Redis Sentinel.
+fn fields_reply(fields: Fields) -> Response {
+    Response::Array(
+        fields
+            .into_iter()
+            .flat_map(|(name, value)| [bulk_string(name), bulk_string(&value)])
+            .collect(),
+    )
+}
+
+impl Command for Sentinel {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if !data.sentinel().is_enabled() {
+            return Response::Error("unknown command");
+        }
+
+        let Some(subcommand) = keyword(&arguments[0]) else {
+            return Response::Error("unknown subcommand or wrong number of arguments");
+        };
+        let now = now_ms();
+
+        match (subcommand.as_str(), &arguments[1..]) {
+            ("MYID", []) => bulk_string(data.sentinel().id()),
+            ("MASTERS", []) => {
+                let sentinel = data.sentinel();
+
+                Response::Array(
+                    sentinel
+                        .master_names()
+                        .filter_map(|name| sentinel.master_fields(name, now))
+                        .map(fields_reply)
+                        .collect(),
+                )
+            }
+            ("MASTER", [name]) => match data.sentinel().master_fields(&text(name), now) {
+                Some(fields) => fields_reply(fields),
+                None => Response::Error(NO_SUCH_MASTER),
+            },
+            ("REPLICAS" | "SLAVES", [name]) => {
+                match data.sentinel().replica_fields(&text(name), now) {
+                    Some(replicas) => {
+                        Response::Array(replicas.into_iter().map(fields_reply).collect())
+                    }
+                    None => Response::Error(NO_SUCH_MASTER),
+                }
+            }
+            ("SENTINELS", [name]) => match data.sentinel().sentinel_fields(&text(name), now) {
+                Some(sentinels) => {
+                    Response::Array(sentinels.into_iter().map(fields_reply).collect())
+                }
+                None => Response::Error(NO_SUCH_MASTER),
+            },
+            ("GET-MASTER-ADDR-BY-NAME", [name]) => {
+                match data.sentinel().master_address(&text(name)) {
+                    Some((host, port)) => {
+                        Response::Array(vec![bulk_string(host), bulk_string(&port.to_string())])
+                    }
+                    None => Response::NullArray,
+                }
+            }
+            ("IS-MASTER-DOWN-BY-ADDR", [ip, port, epoch, candidate]) => {
+                let (Some(port), Some(epoch)) = (number::<u16>(port), number::<u64>(epoch)) else {
+                    return Response::Error("value is not an integer or out of range");
+                };
+                let (down, leader, leader_epoch) = data.sentinel_mut().is_master_down_by_addr(
+                    &text(ip),
+                    port,
+                    epoch,
+                    &text(candidate),
+                    now,
+                );
+
+                Response::Array(vec![
+                    Response::Integer(down as i64),
+                    bulk_string(&leader),
+                    Response::Integer(leader_epoch as i64),
+                ])
+            }
+            ("MONITOR", [name, ip, port, quorum]) => {
+                let ip = text(ip);
+
+                if ip.parse::<IpAddr>().is_err() {
+                    return Response::Error("Invalid IP address or hostname specified");
+                }
+
+                let Some(port) = number::<u16>(port).filter(|&port| port > 0) else {
+                    return Response::Error("Invalid port number");
+                };
+                let Some(quorum) = number::<usize>(quorum) else {
+                    return Response::Error("value is not an integer or out of range");
+                };
+
+                match data
+                    .sentinel_mut()
+                    .monitor(&text(name), &ip, port, quorum, now)
+                {
+                    Ok(()) => Response::SimpleString("OK"),
+                    Err(error) => Response::Error(error),
+                }
+            }
+            ("REMOVE", [name]) => {
+                if data.sentinel_mut().remove(&text(name)) {
+                    Response::SimpleString("OK")
+                } else {
+                    Response::Error(NO_SUCH_MASTER)
+                }
+            }
+            ("FAILOVER", [name]) => match data.sentinel_mut().failover(&text(name), now) {
+                Ok(()) => Response::SimpleString("OK"),
+                Err(error) => Response::Error(error),
+            },
+            _ => Response::Error("unknown subcommand or wrong number of arguments"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk_string::BulkString;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    #[test]
+    fn sentinel() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Error("unknown command"),
+            Sentinel.execute(&mut data, arguments!["MASTERS"])
+        );
+
+        data.sentinel_mut().enable(26379, 30_000, 180_000);
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Sentinel.execute(
+                &mut data,
+                arguments!["MONITOR", "mymaster", "127.0.0.1", "6379", "2"]
+            )
+        );
+        assert_eq!(
+            Response::Error("Duplicated master name"),
+            Sentinel.execute(
+                &mut data,
+                arguments!["MONITOR", "mymaster", "127.0.0.1", "6380", "2"]
+            )
+        );
+        assert_eq!(
+            Response::Error("Invalid IP address or hostname specified"),
+            Sentinel.execute(
+                &mut data,
+                arguments!["MONITOR", "other", "nowhere", "6380", "2"]
+            )
+        );
+        assert_eq!(
+            Response::Error("Quorum must be 1 or greater."),
+            Sentinel.execute(
+                &mut data,
+                arguments!["MONITOR", "other", "127.0.0.1", "6380", "0"]
+            )
+        );
+        assert_eq!(
+            Response::Array(vec![bulk_string("127.0.0.1"), bulk_string("6379")]),
+            Sentinel.execute(&mut data, arguments!["GET-MASTER-ADDR-BY-NAME", "mymaster"])
+        );
+        assert_eq!(
+            Response::NullArray,
+            Sentinel.execute(&mut data, arguments!["get-master-addr-by-name", "other"])
+        );
+
+        let Response::Array(fields) = Sentinel.execute(&mut data, arguments!["MASTER", "mymaster"])
+        else {
+            panic!("expected an array");
+        };
+
+        assert_eq!(bulk_string("name"), fields[0]);
+        assert_eq!(bulk_string("mymaster"), fields[1]);
+        assert!(fields
+            .windows(2)
+            .any(|pair| pair == [bulk_string("flags"), bulk_string("master")]));
+        assert!(fields
+            .windows(2)
+            .any(|pair| pair == [bulk_string("quorum"), bulk_string("2")]));
+        assert_eq!(
+            Response::Array(Vec::new()),
+            Sentinel.execute(&mut data, arguments!["REPLICAS", "mymaster"])
+        );
+        assert_eq!(
+            Response::Error(NO_SUCH_MASTER),
+            Sentinel.execute(&mut data, arguments!["SENTINELS", "other"])
+        );
+        assert_eq!(
+            Response::Error("NOGOODSLAVE No suitable replica to promote"),
+            Sentinel.execute(&mut data, arguments!["FAILOVER", "mymaster"])
+        );
+
+        // Another sentinel asking for a vote gets it, and one asking in the same epoch afterwards doesn't.
+        assert_eq!(
+            Response::Array(vec![
+                Response::Integer(0),
+                bulk_string("aaaa"),
+                Response::Integer(1)
+            ]),
+            Sentinel.execute(
+                &mut data,
+                arguments!["IS-MASTER-DOWN-BY-ADDR", "127.0.0.1", "6379", "1", "aaaa"]
+            )
+        );
+        assert_eq!(
+            Response::Array(vec![
+                Response::Integer(0),
+                bulk_string("aaaa"),
+                Response::Integer(1)
+            ]),
+            Sentinel.execute(
+                &mut data,
+                arguments!["IS-MASTER-DOWN-BY-ADDR", "127.0.0.1", "6379", "1", "bbbb"]
+            )
+        );
+        assert_eq!(
+            Response::Array(vec![
+                Response::Integer(0),
+                bulk_string("*"),
+                Response::Integer(0)
+            ]),
+            Sentinel.execute(
+                &mut data,
+                arguments!["IS-MASTER-DOWN-BY-ADDR", "127.0.0.1", "6379", "1", "*"]
+            )
+        );
+        assert_eq!(
+            Response::SimpleString("OK"),
+            Sentinel.execute(&mut data, arguments!["REMOVE", "mymaster"])
+        );
+        assert_eq!(
+            Response::Error(NO_SUCH_MASTER),
+            Sentinel.execute(&mut data, arguments!["MASTER", "mymaster"])
+        );
+    }
+}
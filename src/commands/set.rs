@@ -1,8 +1,10 @@
 use std::str;
 
-use super::{Command, Data, Response};
+use super::{Command, Data, Response, WRONG_TYPE};
 use crate::array::Value;
 use crate::bulk_string::BulkString;
+use crate::notify;
+use crate::object::Object;
 
 pub(crate) struct Set;
 
@@ -61,6 +63,20 @@ fn parse_options(options: &[Value]) -> Result<(SetOption, GetOption), Response>
     Ok((set_option, get_option))
 }
 
+/// Returns the string stored at `key` for the `GET` option, or a null bulk string if there is none.
+fn old_value(data: &mut Data, key: &BulkString) -> Result<BulkString, Response> {
+    match data.get(key) {
+        Some(Object::String(value)) => Ok(value.clone()),
+        Some(_) => Err(Response::Error(WRONG_TYPE)),
+        None => Ok(BulkString::Null),
+    }
+}
+
+fn set(data: &mut Data, key: &BulkString, value: &BulkString) {
+    data.insert(key.clone(), Object::String(value.clone()));
+    data.notify(notify::STRING, "set", key);
+}
+
 impl Command for Set {
     fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
         if !(2..=4).contains(&arguments.len()) {
@@ -83,20 +99,23 @@ impl Command for Set {
 
         match (set_option, get_option) {
             (SetOption::NotSpecified, GetOption::NotSpecified) => {
-                data.insert(key.clone(), value.clone());
+                set(data, key, value);
 
                 Response::SimpleString("OK")
             }
             (SetOption::NotSpecified, GetOption::Get) => {
-                let old_value = data.get(key).unwrap_or(&BulkString::Null).clone();
+                let old_value = match old_value(data, key) {
+                    Ok(old_value) => old_value,
+                    Err(e) => return e,
+                };
 
-                data.insert(key.clone(), value.clone());
+                set(data, key, value);
 
                 Response::BulkString(old_value)
             }
             (SetOption::IfExists, GetOption::NotSpecified) => {
                 if data.contains_key(key) {
-                    data.insert(key.clone(), value.clone());
+                    set(data, key, value);
 
                     return Response::SimpleString("OK");
                 }
@@ -105,9 +124,12 @@ impl Command for Set {
             }
             (SetOption::IfExists, GetOption::Get) => {
                 if data.contains_key(key) {
-                    let old_value = data.get(key).unwrap().clone();
+                    let old_value = match old_value(data, key) {
+                        Ok(old_value) => old_value,
+                        Err(e) => return e,
+                    };
 
-                    data.insert(key.clone(), value.clone());
+                    set(data, key, value);
 
                     return Response::BulkString(old_value);
                 }
@@ -116,7 +138,7 @@ impl Command for Set {
             }
             (SetOption::IfNotExists, GetOption::NotSpecified) => {
                 if !data.contains_key(key) {
-                    data.insert(key.clone(), value.clone());
+                    set(data, key, value);
 
                     return Response::SimpleString("OK");
                 }
@@ -125,14 +147,15 @@ impl Command for Set {
             }
             (SetOption::IfNotExists, GetOption::Get) => {
                 if !data.contains_key(key) {
-                    data.insert(key.clone(), value.clone());
+                    set(data, key, value);
 
                     return Response::BulkString(BulkString::Null);
                 }
 
-                let old_value = data.get(key).unwrap().clone();
-
-                Response::BulkString(old_value)
+                match old_value(data, key) {
+                    Ok(old_value) => Response::BulkString(old_value),
+                    Err(e) => e,
+                }
             }
         }
     }
@@ -151,6 +174,12 @@ mod tests {
         };
     }
 
+    macro_rules! string {
+        ($value:expr) => {
+            Object::String(bulk_string!($value))
+        };
+    }
+
     macro_rules! arguments {
         ($($value:expr),*) => {
             &[$(Value::BulkString(bulk_string!($value))),*]
@@ -168,7 +197,7 @@ mod tests {
         let response = Set.execute(&mut data, arguments!["key", "value2"]);
 
         assert_eq!(
-            bulk_string!("value2"),
+            string!("value2"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
 
@@ -188,12 +217,12 @@ mod tests {
 
     #[test]
     fn xx_met() {
-        let mut data = Data::from([(bulk_string!("key"), bulk_string!("value"))]);
+        let mut data = Data::from([(bulk_string!("key"), string!("value"))]);
 
         let response = Set.execute(&mut data, arguments!["key", "value2", "XX"]);
 
         assert_eq!(
-            bulk_string!("value2"),
+            string!("value2"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
 
@@ -202,12 +231,12 @@ mod tests {
 
     #[test]
     fn nx_not_met() {
-        let mut data = Data::from([(bulk_string!("key"), bulk_string!("value"))]);
+        let mut data = Data::from([(bulk_string!("key"), string!("value"))]);
 
         let response = Set.execute(&mut data, arguments!["key", "value2", "NX"]);
 
         assert_eq!(
-            bulk_string!("value"),
+            string!("value"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
 
@@ -227,7 +256,7 @@ mod tests {
         assert!(matches!(response, Response::BulkString(bulk_string!(null))));
 
         assert_eq!(
-            bulk_string!("value"),
+            string!("value"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
     }
@@ -253,42 +282,42 @@ mod tests {
 
         assert!(matches!(response, Response::BulkString(bulk_string!(null))));
 
-        data.insert(bulk_string!("key"), bulk_string!("value"));
+        data.insert(bulk_string!("key"), string!("value"));
 
         let response = Set.execute(&mut data, arguments!["key", "value2", "XX", "GET"]);
 
         assert_eq!(response, Response::BulkString(bulk_string!("value")));
 
         assert_eq!(
-            bulk_string!("value2"),
+            string!("value2"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
     }
 
     #[test]
     fn xx_get_met() {
-        let mut data = Data::from([(bulk_string!("key"), bulk_string!("value"))]);
+        let mut data = Data::from([(bulk_string!("key"), string!("value"))]);
 
         let response = Set.execute(&mut data, arguments!["key", "value2", "XX", "GET"]);
 
         assert_eq!(Response::BulkString(bulk_string!("value")), response);
 
         assert_eq!(
-            bulk_string!("value2"),
+            string!("value2"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
     }
 
     #[test]
     fn nx_get_not_met() {
-        let mut data = Data::from([(bulk_string!("key"), bulk_string!("value"))]);
+        let mut data = Data::from([(bulk_string!("key"), string!("value"))]);
 
         let response = Set.execute(&mut data, arguments!["key", "value2", "NX", "GET"]);
 
         assert_eq!(Response::BulkString(bulk_string!("value")), response);
 
         assert_eq!(
-            bulk_string!("value"),
+            string!("value"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
     }
@@ -306,7 +335,7 @@ mod tests {
         assert_eq!(Response::BulkString(bulk_string!("value")), response);
 
         assert_eq!(
-            bulk_string!("value"),
+            string!("value"),
             data.get(&bulk_string!("key")).cloned().unwrap()
         );
     }
@@ -0,0 +1,777 @@
+use std::str;
+
+use super::{bulk_string, bytes, keyword, number, Command, Data, Response, WRONG_TYPE};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::database::now_ms;
+use crate::object::Object;
+use crate::time_series::{
+    Aggregation, Aggregator, BucketTimestamp, DuplicatePolicy, Sample, TimeSeries, TimeSeriesError,
+    DEFAULT_CHUNK_SIZE,
+};
+
+pub(crate) struct TsCreate;
+pub(crate) struct TsAdd;
+pub(crate) struct TsGet;
+pub(crate) struct TsRange;
+pub(crate) struct TsRevRange;
+pub(crate) struct TsMRange;
+pub(crate) struct TsInfo;
+
+fn text(argument: &Value) -> Option<String> {
+    bytes(argument)
+        .and_then(|b| str::from_utf8(b).ok())
+        .map(str::to_string)
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Options shared by `TS.CREATE` and `TS.ADD`, which creates the series if it doesn't exist.
+struct CreateOptions {
+    retention: u64,
+    compressed: bool,
+    chunk_size: usize,
+    duplicate_policy: DuplicatePolicy,
+    on_duplicate: Option<DuplicatePolicy>,
+    labels: Vec<(String, String)>,
+}
+
+impl CreateOptions {
+    fn parse(arguments: &[Value], allow_on_duplicate: bool) -> Result<CreateOptions, Response> {
+        let mut options = CreateOptions {
+            retention: 0,
+            compressed: true,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            duplicate_policy: DuplicatePolicy::Block,
+            on_duplicate: None,
+            labels: Vec::new(),
+        };
+
+        let mut arguments = arguments.iter();
+
+        while let Some(argument) = arguments.next() {
+            match keyword(argument).as_deref() {
+                Some("RETENTION") => {
+                    options.retention = arguments
+                        .next()
+                        .and_then(number)
+                        .ok_or(Response::Error("TSDB: Couldn't parse RETENTION"))?;
+                }
+                Some("ENCODING") => {
+                    options.compressed = match arguments.next().and_then(keyword).as_deref() {
+                        Some("COMPRESSED") => true,
+                        Some("UNCOMPRESSED") => false,
+                        _ => return Err(Response::Error("TSDB: Unknown ENCODING parameter")),
+                    };
+                }
+                Some("CHUNK_SIZE") => {
+                    options.chunk_size = match arguments.next().and_then(number) {
+                        Some(size @ 48..=1048576) if size % 8 == 0 => size,
+                        _ => return Err(Response::Error("TSDB: Couldn't parse CHUNK_SIZE")),
+                    };
+                }
+                Some("DUPLICATE_POLICY") if !allow_on_duplicate => {
+                    options.duplicate_policy = arguments
+                        .next()
+                        .and_then(keyword)
+                        .and_then(|p| DuplicatePolicy::parse(&p))
+                        .ok_or(Response::Error("TSDB: Unknown DUPLICATE_POLICY"))?;
+                }
+                Some("ON_DUPLICATE") if allow_on_duplicate => {
+                    options.on_duplicate = Some(
+                        arguments
+                            .next()
+                            .and_then(keyword)
+                            .and_then(|p| DuplicatePolicy::parse(&p))
+                            .ok_or(Response::Error("TSDB: Unknown ON_DUPLICATE"))?,
+                    );
+                }
+                Some("LABELS") => {
+                    let rest: Vec<&Value> = arguments.by_ref().collect();
+
+                    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                        return Err(Response::Error("TSDB: Invalid labels"));
+                    }
+
+                    for pair in rest.chunks(2) {
+                        match (text(pair[0]), text(pair[1])) {
+                            (Some(label), Some(value)) if !label.is_empty() => {
+                                options.labels.push((label, value))
+                            }
+                            _ => return Err(Response::Error("TSDB: Invalid labels")),
+                        }
+                    }
+                }
+                _ => return Err(Response::Error("syntax error")),
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn into_series(self) -> TimeSeries {
+        TimeSeries::new(
+            self.retention,
+            self.compressed,
+            self.chunk_size,
+            self.duplicate_policy,
+            self.labels,
+        )
+    }
+}
+
+impl Command for TsCreate {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.is_empty() {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let options = match CreateOptions::parse(&arguments[1..], false) {
+            Ok(options) => options,
+            Err(e) => return e,
+        };
+
+        if data.contains_key(key) {
+            return Response::Error("TSDB: key already exists");
+        }
+
+        data.insert(key.clone(), Object::TimeSeries(options.into_series()));
+
+        Response::SimpleString("OK")
+    }
+}
+
+impl Command for TsAdd {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 3 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let timestamp = match bytes(&arguments[1]) {
+            Some(b"*") => now_ms(),
+            _ => match number(&arguments[1]) {
+                Some(timestamp) => timestamp,
+                None => return Response::Error("TSDB: invalid timestamp"),
+            },
+        };
+
+        let value: f64 = match number(&arguments[2]) {
+            Some(value) if !f64::is_nan(value) => value,
+            _ => return Response::Error("TSDB: invalid value"),
+        };
+
+        let options = match CreateOptions::parse(&arguments[3..], true) {
+            Ok(options) => options,
+            Err(e) => return e,
+        };
+
+        let on_duplicate = options.on_duplicate;
+
+        let series =
+            match data.get_or_insert_with(key, || Object::TimeSeries(options.into_series())) {
+                Object::TimeSeries(series) => series,
+                _ => return Response::Error(WRONG_TYPE),
+            };
+
+        match series.add(timestamp, value, on_duplicate) {
+            Ok(timestamp) => {
+                data.touch(key);
+
+                Response::Integer(timestamp as i64)
+            }
+            Err(TimeSeriesError::OlderThanRetention) => {
+                Response::Error("TSDB: Timestamp is older than retention")
+            }
+            Err(TimeSeriesError::Duplicate) => Response::Error(
+                "TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode",
+            ),
+        }
+    }
+}
+
+impl Command for TsGet {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 1 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        match data.get(key) {
+            Some(Object::TimeSeries(series)) => match series.last_sample() {
+                Some((timestamp, value)) => Response::Array(vec![
+                    Response::Integer(timestamp as i64),
+                    bulk_string(&format_value(value)),
+                ]),
+                None => Response::Array(Vec::new()),
+            },
+            Some(_) => Response::Error(WRONG_TYPE),
+            None => Response::Error("TSDB: the key does not exist"),
+        }
+    }
+}
+
+enum Alignment {
+    Start,
+    End,
+    Timestamp(u64),
+}
+
+enum Matcher {
+    Equal(String, Vec<String>),
+    NotEqual(String, Vec<String>),
+}
+
+impl Matcher {
+    fn parse(filter: &str) -> Option<Matcher> {
+        let (label, values, negated) = match filter.split_once("!=") {
+            Some((label, values)) => (label, values, true),
+            None => {
+                let (label, values) = filter.split_once('=')?;
+
+                (label, values, false)
+            }
+        };
+
+        if label.is_empty() {
+            return None;
+        }
+
+        let values = match values.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+            Some(list) => list.split(',').map(str::to_string).collect(),
+            None if values.is_empty() => Vec::new(),
+            None => vec![values.to_string()],
+        };
+
+        Some(if negated {
+            Matcher::NotEqual(label.to_string(), values)
+        } else {
+            Matcher::Equal(label.to_string(), values)
+        })
+    }
+
+    /// Whether the matcher selects series by having a label, rather than only excluding some.
+    fn is_positive(&self) -> bool {
+        matches!(self, Matcher::Equal(_, values) if !values.is_empty())
+    }
+
+    fn matches(&self, labels: &[(String, String)]) -> bool {
+        let value = |label: &str| labels.iter().find(|(l, _)| l == label).map(|(_, v)| v);
+
+        match self {
+            Matcher::Equal(label, values) if values.is_empty() => value(label).is_none(),
+            Matcher::Equal(label, values) => value(label).is_some_and(|v| values.contains(v)),
+            Matcher::NotEqual(label, values) if values.is_empty() => value(label).is_some(),
+            Matcher::NotEqual(label, values) => !value(label).is_some_and(|v| values.contains(v)),
+        }
+    }
+}
+
+/// The options of `TS.RANGE`, `TS.REVRANGE` and `TS.MRANGE`.
+struct RangeOptions {
+    from: u64,
+    to: u64,
+    filter_by_timestamp: Option<Vec<u64>>,
+    filter_by_value: Option<(f64, f64)>,
+    count: Option<usize>,
+    alignment: Option<Alignment>,
+    aggregation: Option<(Aggregator, u64)>,
+    bucket_timestamp: BucketTimestamp,
+    empty: bool,
+    with_labels: bool,
+    selected_labels: Option<Vec<String>>,
+    matchers: Vec<Matcher>,
+}
+
+fn parse_timestamp(argument: &Value, error: &'static str) -> Result<u64, Response> {
+    match bytes(argument) {
+        Some(b"-") => Ok(0),
+        Some(b"+") => Ok(u64::MAX),
+        _ => number(argument).ok_or(Response::Error(error)),
+    }
+}
+
+impl RangeOptions {
+    fn parse(arguments: &[Value], multi: bool) -> Result<RangeOptions, Response> {
+        let mut options = RangeOptions {
+            from: parse_timestamp(&arguments[0], "TSDB: wrong fromTimestamp")?,
+            to: parse_timestamp(&arguments[1], "TSDB: wrong toTimestamp")?,
+            filter_by_timestamp: None,
+            filter_by_value: None,
+            count: None,
+            alignment: None,
+            aggregation: None,
+            bucket_timestamp: BucketTimestamp::Start,
+            empty: false,
+            with_labels: false,
+            selected_labels: None,
+            matchers: Vec::new(),
+        };
+
+        let mut arguments = arguments[2..].iter().peekable();
+
+        while let Some(argument) = arguments.next() {
+            match keyword(argument).as_deref() {
+                Some("LATEST") => {}
+                Some("FILTER_BY_TS") => {
+                    let mut timestamps = Vec::new();
+
+                    while let Some(timestamp) = arguments.peek().and_then(|a| number(a)) {
+                        timestamps.push(timestamp);
+                        arguments.next();
+                    }
+
+                    if timestamps.is_empty() {
+                        return Err(Response::Error(
+                            "TSDB: FILTER_BY_TS one or more arguments are missing",
+                        ));
+                    }
+
+                    options.filter_by_timestamp = Some(timestamps);
+                }
+                Some("FILTER_BY_VALUE") => {
+                    match (
+                        arguments.next().and_then(number),
+                        arguments.next().and_then(number),
+                    ) {
+                        (Some(min), Some(max)) => options.filter_by_value = Some((min, max)),
+                        _ => return Err(Response::Error("TSDB: Couldn't parse MIN or MAX")),
+                    }
+                }
+                Some("COUNT") => {
+                    options.count = match arguments.next().and_then(number) {
+                        Some(count) if count > 0 => Some(count),
+                        _ => return Err(Response::Error("TSDB: Couldn't parse COUNT")),
+                    };
+                }
+                Some("ALIGN") => {
+                    options.alignment = Some(match arguments.next() {
+                        Some(argument) => match keyword(argument).as_deref() {
+                            Some("-" | "START") => Alignment::Start,
+                            Some("+" | "END") => Alignment::End,
+                            _ => Alignment::Timestamp(
+                                number(argument)
+                                    .ok_or(Response::Error("TSDB: unknown ALIGN parameter"))?,
+                            ),
+                        },
+                        None => return Err(Response::Error("TSDB: ALIGN parameter is missing")),
+                    });
+                }
+                Some("AGGREGATION") => {
+                    let aggregator = arguments
+                        .next()
+                        .and_then(keyword)
+                        .and_then(|a| Aggregator::parse(&a))
+                        .ok_or(Response::Error("TSDB: Unknown aggregation type"))?;
+
+                    let bucket_duration = match arguments.next().and_then(number) {
+                        Some(duration) if duration > 0 => duration,
+                        _ => {
+                            return Err(Response::Error(
+                                "TSDB: bucketDuration must be greater than zero",
+                            ))
+                        }
+                    };
+
+                    options.aggregation = Some((aggregator, bucket_duration));
+                }
+                Some("BUCKETTIMESTAMP") => {
+                    options.bucket_timestamp = match arguments.next().and_then(keyword).as_deref() {
+                        Some("-" | "START") => BucketTimestamp::Start,
+                        Some("~" | "MID") => BucketTimestamp::Middle,
+                        Some("+" | "END") => BucketTimestamp::End,
+                        _ => {
+                            return Err(Response::Error("TSDB: unknown BUCKETTIMESTAMP parameter"))
+                        }
+                    };
+                }
+                Some("EMPTY") => options.empty = true,
+                Some("WITHLABELS") if multi => options.with_labels = true,
+                Some("SELECTED_LABELS") if multi => {
+                    let mut labels = Vec::new();
+
+                    while let Some(label) =
+                        arguments.next_if(|a| keyword(a).as_deref() != Some("FILTER"))
+                    {
+                        labels.push(text(label).ok_or(Response::Error("invalid argument"))?);
+                    }
+
+                    options.selected_labels = Some(labels);
+                }
+                Some("FILTER") if multi => {
+                    for filter in arguments.by_ref() {
+                        let matcher = text(filter)
+                            .and_then(|f| Matcher::parse(&f))
+                            .ok_or(Response::Error("TSDB: failed parsing labels"))?;
+
+                        options.matchers.push(matcher);
+                    }
+                }
+                _ => return Err(Response::Error("TSDB: wrong arguments")),
+            }
+        }
+
+        if options.aggregation.is_none() && (options.alignment.is_some() || options.empty) {
+            return Err(Response::Error("TSDB: ALIGN and EMPTY require AGGREGATION"));
+        }
+
+        if multi {
+            if options.matchers.is_empty() {
+                return Err(Response::Error("TSDB: missing FILTER argument"));
+            }
+
+            if !options.matchers.iter().any(Matcher::is_positive) {
+                return Err(Response::Error("TSDB: please provide at least one matcher"));
+            }
+
+            if options.with_labels && options.selected_labels.is_some() {
+                return Err(Response::Error(
+                    "TSDB: cannot accept WITHLABELS and SELECT_LABELS together",
+                ));
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn query(&self, series: &TimeSeries, reverse: bool) -> Vec<Sample> {
+        let mut samples = series.range(self.from, self.to);
+
+        if let Some(timestamps) = &self.filter_by_timestamp {
+            samples.retain(|(t, _)| timestamps.contains(t));
+        }
+
+        if let Some((min, max)) = self.filter_by_value {
+            samples.retain(|(_, v)| (min..=max).contains(v));
+        }
+
+        if let Some((aggregator, bucket_duration)) = self.aggregation {
+            let alignment = match self.alignment {
+                None => 0,
+                Some(Alignment::Start) => self.from,
+                Some(Alignment::End) => self.to,
+                Some(Alignment::Timestamp(timestamp)) => timestamp,
+            };
+
+            let aggregation = Aggregation {
+                aggregator,
+                bucket_duration,
+                alignment,
+                bucket_timestamp: self.bucket_timestamp,
+                empty: self.empty,
+            };
+
+            samples = aggregation.apply(&samples);
+        }
+
+        if reverse {
+            samples.reverse();
+        }
+
+        if let Some(count) = self.count {
+            samples.truncate(count);
+        }
+
+        samples
+    }
+}
+
+fn samples_response(samples: Vec<Sample>) -> Response {
+    Response::Array(
+        samples
+            .into_iter()
+            .map(|(timestamp, value)| {
+                Response::Array(vec![
+                    Response::Integer(timestamp as i64),
+                    bulk_string(&format_value(value)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn range(data: &mut Data, arguments: &[Value], reverse: bool) -> Response {
+    if arguments.len() < 3 {
+        return Response::Error("wrong number of arguments");
+    }
+
+    let key = bulk_string_or_error!(&arguments[0]);
+
+    let options = match RangeOptions::parse(&arguments[1..], false) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+
+    match data.get(key) {
+        Some(Object::TimeSeries(series)) => samples_response(options.query(series, reverse)),
+        Some(_) => Response::Error(WRONG_TYPE),
+        None => Response::Error("TSDB: the key does not exist"),
+    }
+}
+
+impl Command for TsRange {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        range(data, arguments, false)
+    }
+}
+
+impl Command for TsRevRange {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        range(data, arguments, true)
+    }
+}
+
+fn labels_response<'a>(labels: impl Iterator<Item = (&'a str, Option<&'a str>)>) -> Response {
+    Response::Array(
+        labels
+            .map(|(label, value)| {
+                Response::Array(vec![
+                    bulk_string(label),
+                    value.map_or(Response::BulkString(BulkString::Null), bulk_string),
+                ])
+            })
+            .collect(),
+    )
+}
+
+impl Command for TsMRange {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() < 4 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let options = match RangeOptions::parse(arguments, true) {
+            Ok(options) => options,
+            Err(e) => return e,
+        };
+
+        let mut matching: Vec<(&BulkString, &TimeSeries)> = data
+            .iter()
+            .filter_map(|(key, object)| match object {
+                Object::TimeSeries(series) => Some((key, series)),
+                _ => None,
+            })
+            .filter(|(_, series)| options.matchers.iter().all(|m| m.matches(&series.labels)))
+            .collect();
+
+        matching.sort_by(|a, b| a.0.cmp(b.0));
+
+        Response::Array(
+            matching
+                .into_iter()
+                .map(|(key, series)| {
+                    let labels = if options.with_labels {
+                        labels_response(
+                            series
+                                .labels
+                                .iter()
+                                .map(|(l, v)| (l.as_str(), Some(v.as_str()))),
+                        )
+                    } else if let Some(selected) = &options.selected_labels {
+                        labels_response(selected.iter().map(|label| {
+                            let value = series.labels.iter().find(|(l, _)| l == label);
+
+                            (label.as_str(), value.map(|(_, v)| v.as_str()))
+                        }))
+                    } else {
+                        Response::Array(Vec::new())
+                    };
+
+                    Response::Array(vec![
+                        Response::BulkString(key.clone()),
+                        labels,
+                        samples_response(options.query(series, false)),
+                    ])
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Command for TsInfo {
+    fn execute(&self, data: &mut Data, arguments: &[Value]) -> Response {
+        if arguments.len() != 1 {
+            return Response::Error("wrong number of arguments");
+        }
+
+        let key = bulk_string_or_error!(&arguments[0]);
+
+        let series = match data.get(key) {
+            Some(Object::TimeSeries(series)) => series,
+            Some(_) => return Response::Error(WRONG_TYPE),
+            None => return Response::Error("TSDB: the key does not exist"),
+        };
+
+        let timestamp = |t: Option<u64>| Response::Integer(t.unwrap_or(0) as i64);
+
+        Response::Array(vec![
+            Response::SimpleString("totalSamples"),
+            Response::Integer(series.len() as i64),
+            Response::SimpleString("memoryUsage"),
+            Response::Integer(series.memory_usage() as i64),
+            Response::SimpleString("firstTimestamp"),
+            timestamp(series.first_timestamp()),
+            Response::SimpleString("lastTimestamp"),
+            timestamp(series.last_timestamp()),
+            Response::SimpleString("retentionTime"),
+            Response::Integer(series.retention as i64),
+            Response::SimpleString("chunkCount"),
+            Response::Integer(series.chunk_count() as i64),
+            Response::SimpleString("chunkSize"),
+            Response::Integer(series.chunk_size as i64),
+            Response::SimpleString("chunkType"),
+            Response::SimpleString(if series.is_compressed() {
+                "compressed"
+            } else {
+                "uncompressed"
+            }),
+            Response::SimpleString("duplicatePolicy"),
+            bulk_string(series.duplicate_policy.name()),
+            Response::SimpleString("labels"),
+            labels_response(
+                series
+                    .labels
+                    .iter()
+                    .map(|(l, v)| (l.as_str(), Some(v.as_str()))),
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(Value::BulkString(BulkString::Filled($value.as_bytes().to_vec()))),*]
+        };
+    }
+
+    fn sample(timestamp: i64, value: &str) -> Response {
+        Response::Array(vec![Response::Integer(timestamp), bulk_string(value)])
+    }
+
+    #[test]
+    fn add_and_range() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::SimpleString("OK"),
+            TsCreate.execute(
+                &mut data,
+                arguments!["ts", "RETENTION", "0", "LABELS", "a", "b"]
+            )
+        );
+
+        for (timestamp, value) in [("10", "1"), ("20", "2.5"), ("30", "3")] {
+            TsAdd.execute(&mut data, arguments!["ts", timestamp, value]);
+        }
+
+        assert_eq!(
+            Response::Array(vec![sample(10, "1"), sample(20, "2.5"), sample(30, "3")]),
+            TsRange.execute(&mut data, arguments!["ts", "-", "+"])
+        );
+        assert_eq!(sample(30, "3"), TsGet.execute(&mut data, arguments!["ts"]));
+        assert_eq!(
+            Response::Array(vec![sample(30, "3"), sample(20, "2.5")]),
+            TsRevRange.execute(&mut data, arguments!["ts", "-", "+", "COUNT", "2"])
+        );
+        assert_eq!(
+            Response::Array(vec![sample(0, "1"), sample(20, "5.5")]),
+            TsRange.execute(
+                &mut data,
+                arguments!["ts", "0", "100", "AGGREGATION", "sum", "20"]
+            )
+        );
+        assert_eq!(
+            Response::Array(vec![sample(20, "2.5")]),
+            TsRange.execute(
+                &mut data,
+                arguments!["ts", "-", "+", "FILTER_BY_VALUE", "2", "2.9"]
+            )
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut data = Data::new();
+
+        TsAdd.execute(&mut data, arguments!["ts", "10", "1"]);
+
+        assert!(matches!(
+            TsAdd.execute(&mut data, arguments!["ts", "10", "2"]),
+            Response::Error(_)
+        ));
+        assert_eq!(
+            Response::Integer(10),
+            TsAdd.execute(
+                &mut data,
+                arguments!["ts", "10", "2", "ON_DUPLICATE", "SUM"]
+            )
+        );
+        assert_eq!(
+            Response::Array(vec![sample(10, "3")]),
+            TsRange.execute(&mut data, arguments!["ts", "-", "+"])
+        );
+    }
+
+    #[test]
+    fn mrange() {
+        let mut data = Data::new();
+
+        TsAdd.execute(
+            &mut data,
+            arguments!["a", "1", "1", "LABELS", "type", "cpu", "host", "x"],
+        );
+        TsAdd.execute(
+            &mut data,
+            arguments!["b", "1", "2", "LABELS", "type", "cpu"],
+        );
+        TsAdd.execute(
+            &mut data,
+            arguments!["c", "1", "3", "LABELS", "type", "mem"],
+        );
+
+        assert_eq!(
+            Response::Array(vec![
+                Response::Array(vec![
+                    bulk_string("a"),
+                    Response::Array(vec![Response::Array(vec![
+                        bulk_string("host"),
+                        bulk_string("x")
+                    ])]),
+                    Response::Array(vec![sample(1, "1")]),
+                ]),
+                Response::Array(vec![
+                    bulk_string("b"),
+                    Response::Array(vec![Response::Array(vec![
+                        bulk_string("host"),
+                        Response::BulkString(BulkString::Null)
+                    ])]),
+                    Response::Array(vec![sample(1, "2")]),
+                ]),
+            ]),
+            TsMRange.execute(
+                &mut data,
+                arguments!["-", "+", "SELECTED_LABELS", "host", "FILTER", "type=cpu"]
+            )
+        );
+        assert_eq!(
+            Response::Error("TSDB: please provide at least one matcher"),
+            TsMRange.execute(&mut data, arguments!["-", "+", "FILTER", "type!=cpu"])
+        );
+    }
+}
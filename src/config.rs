@@ -0,0 +1,475 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::aof::{
+    FsyncPolicy, DEFAULT_APPEND_DIRNAME, DEFAULT_APPEND_FILENAME, DEFAULT_REWRITE_MIN_SIZE,
+    DEFAULT_REWRITE_PERCENTAGE,
+};
+use crate::cluster::{DEFAULT_CONFIG_FILE, DEFAULT_NODE_TIMEOUT};
+use crate::data::{DEFAULT_DATABASES, DEFAULT_DB_FILENAME};
+use crate::notify;
+use crate::rdb::{self, SavePoint, DEFAULT_SAVE_POINTS};
+use crate::replication::{DEFAULT_BACKLOG_SIZE, DEFAULT_PORT};
+use crate::scripting::DEFAULT_BUSY_REPLY_THRESHOLD;
+use crate::sentinel::{DEFAULT_DOWN_AFTER, DEFAULT_FAILOVER_TIMEOUT, DEFAULT_SENTINEL_PORT};
+
+/// Server settings, given on the command line as `--name value` pairs like Redis's.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The port to listen on, on the loopback interface.
+    pub(crate) port: u16,
+    pub(crate) databases: usize,
+    /// The classes of keyspace events to publish. See [`notify::parse_flags`].
+    pub(crate) notify_keyspace_events: u32,
+    /// How long a script can run, in milliseconds, before other connections get `BUSY` errors.
+    pub(crate) busy_reply_threshold: u64,
+    /// The directory snapshots are saved in.
+    pub(crate) dir: PathBuf,
+    /// The name of the snapshot file, which is loaded at startup if it exists.
+    pub(crate) dbfilename: String,
+    /// When snapshots are saved automatically. See [`SavePoint`].
+    pub(crate) save_points: Vec<SavePoint>,
+    /// Whether write commands are logged to the append-only file, which is then loaded at startup instead of the
+    /// snapshot.
+    pub(crate) appendonly: bool,
+    /// The prefix of the names of the files the append-only file is made of.
+    pub(crate) appendfilename: String,
+    /// The directory, in `dir`, that holds the files the append-only file is made of.
+    pub(crate) appenddirname: String,
+    pub(crate) appendfsync: FsyncPolicy,
+    /// Whether an append-only file that ends in the middle of a command is loaded anyway, without the incomplete part.
+    pub(crate) aof_load_truncated: bool,
+    /// Whether rewrites save the data as an RDB snapshot rather than as commands.
+    pub(crate) aof_use_rdb_preamble: bool,
+    /// How much the append-only file must grow since the latest rewrite, in percent, to be rewritten automatically.
+    /// Zero disables automatic rewrites.
+    pub(crate) auto_aof_rewrite_percentage: u64,
+    /// The size, in bytes, below which the append-only file isn't rewritten automatically.
+    pub(crate) auto_aof_rewrite_min_size: u64,
+    /// The host and port of the primary to replicate, given as `"<host> <port>"`.
+    pub(crate) replicaof: Option<(String, u16)>,
+    /// Whether a replica refuses writes from clients.
+    pub(crate) replica_read_only: bool,
+    /// How many bytes of the replication stream are kept for replicas that reconnect.
+    pub(crate) repl_backlog_size: u64,
+    pub(crate) cluster_enabled: bool,
+    /// The name of the file, in `dir`, that the cluster configuration is saved to and loaded from.
+    pub(crate) cluster_config_file: String,
+    /// How long a node may not answer pings, in milliseconds, before it's suspected of having failed.
+    pub(crate) cluster_node_timeout: u64,
+    /// Whether the server runs as a sentinel, which monitors primaries instead of storing data.
+    pub(crate) sentinel: bool,
+    /// The primaries a sentinel monitors, given as `"<name> <ip> <port> <quorum>"`.
+    pub(crate) sentinel_monitors: Vec<(String, String, u16, usize)>,
+    /// How long an instance may not answer pings, in milliseconds, before a sentinel considers it down.
+    pub(crate) sentinel_down_after: u64,
+    /// How long a failover may take, in milliseconds, before it's aborted. Another one isn't tried for twice as long.
+    pub(crate) sentinel_failover_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            port: DEFAULT_PORT,
+            databases: DEFAULT_DATABASES,
+            notify_keyspace_events: 0,
+            busy_reply_threshold: DEFAULT_BUSY_REPLY_THRESHOLD,
+            dir: PathBuf::from("."),
+            dbfilename: DEFAULT_DB_FILENAME.to_string(),
+            save_points: DEFAULT_SAVE_POINTS.to_vec(),
+            appendonly: false,
+            appendfilename: DEFAULT_APPEND_FILENAME.to_string(),
+            appenddirname: DEFAULT_APPEND_DIRNAME.to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            aof_load_truncated: true,
+            aof_use_rdb_preamble: true,
+            auto_aof_rewrite_percentage: DEFAULT_REWRITE_PERCENTAGE,
+            auto_aof_rewrite_min_size: DEFAULT_REWRITE_MIN_SIZE,
+            replicaof: None,
+            replica_read_only: true,
+            repl_backlog_size: DEFAULT_BACKLOG_SIZE,
+            cluster_enabled: false,
+            cluster_config_file: DEFAULT_CONFIG_FILE.to_string(),
+            cluster_node_timeout: DEFAULT_NODE_TIMEOUT,
+            sentinel: false,
+            sentinel_monitors: Vec::new(),
+            sentinel_down_after: DEFAULT_DOWN_AFTER,
+            sentinel_failover_timeout: DEFAULT_FAILOVER_TIMEOUT,
+        }
+    }
+}
+
+/// Parses a `yes` or `no` setting.
+fn parse_yes_no(name: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!(
+            "invalid value '{value}' for '{name}', expected yes or no"
+        )),
+    }
+}
+
+/// Parses a number of bytes with an optional unit, like Redis's memory settings: `k`, `m` and `g` are powers of 1000,
+/// `kb`, `mb` and `gb` powers of 1024.
+pub(crate) fn parse_memory(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &value[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Checks the name of a file in `dir`.
+fn file_name(value: String) -> Result<String, String> {
+    if value.is_empty() || value.contains('/') {
+        return Err(format!("invalid file name '{value}'"));
+    }
+
+    Ok(value)
+}
+
+impl Config {
+    pub(crate) fn parse(arguments: impl IntoIterator<Item = String>) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut arguments = arguments.into_iter();
+        let mut port_given = false;
+
+        while let Some(argument) = arguments.next() {
+            let name = argument
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument '{argument}'"))?
+                .to_lowercase();
+
+            // Like `redis-server --sentinel`, the only option without a value.
+            if name == "sentinel" {
+                config.sentinel = true;
+
+                continue;
+            }
+
+            let value = arguments
+                .next()
+                .ok_or_else(|| format!("missing value for '{argument}'"))?;
+
+            match name.as_str() {
+                "port" => {
+                    port_given = true;
+                    config.port = match value.parse() {
+                        Ok(port) if port > 0 => port,
+                        _ => return Err(format!("invalid port '{value}'")),
+                    }
+                }
+                "databases" => {
+                    config.databases = match value.parse() {
+                        Ok(databases) if databases > 0 => databases,
+                        _ => return Err(format!("invalid number of databases '{value}'")),
+                    }
+                }
+                "notify-keyspace-events" => {
+                    config.notify_keyspace_events = notify::parse_flags(&value)
+                        .ok_or_else(|| format!("invalid keyspace event flags '{value}'"))?
+                }
+                "busy-reply-threshold" | "lua-time-limit" => {
+                    config.busy_reply_threshold = value
+                        .parse()
+                        .map_err(|_| format!("invalid busy reply threshold '{value}'"))?
+                }
+                "dir" => config.dir = PathBuf::from(value),
+                "dbfilename" => config.dbfilename = file_name(value)?,
+                "save" => {
+                    config.save_points = rdb::parse_save_points(&value)
+                        .ok_or_else(|| format!("invalid save points '{value}'"))?
+                }
+                "appendonly" => config.appendonly = parse_yes_no(&name, &value)?,
+                "appendfilename" => config.appendfilename = file_name(value)?,
+                "appenddirname" => config.appenddirname = file_name(value)?,
+                "appendfsync" => {
+                    config.appendfsync = FsyncPolicy::parse(&value)
+                        .ok_or_else(|| format!("invalid fsync policy '{value}'"))?
+                }
+                "aof-load-truncated" => config.aof_load_truncated = parse_yes_no(&name, &value)?,
+                "aof-use-rdb-preamble" => {
+                    config.aof_use_rdb_preamble = parse_yes_no(&name, &value)?
+                }
+                "auto-aof-rewrite-percentage" => {
+                    config.auto_aof_rewrite_percentage = value
+                        .parse()
+                        .map_err(|_| format!("invalid rewrite percentage '{value}'"))?
+                }
+                "auto-aof-rewrite-min-size" => {
+                    config.auto_aof_rewrite_min_size = parse_memory(&value)
+                        .ok_or_else(|| format!("invalid rewrite minimum size '{value}'"))?
+                }
+                "replicaof" | "slaveof" => {
+                    config.replicaof = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                        [host, port] => match port.parse() {
+                            Ok(port) => Some((host.to_string(), port)),
+                            Err(_) => return Err(format!("invalid primary port '{port}'")),
+                        },
+                        _ => {
+                            return Err(format!(
+                                "invalid primary '{value}', expected <host> <port>"
+                            ))
+                        }
+                    }
+                }
+                "replica-read-only" | "slave-read-only" => {
+                    config.replica_read_only = parse_yes_no(&name, &value)?
+                }
+                "repl-backlog-size" => {
+                    config.repl_backlog_size = parse_memory(&value)
+                        .filter(|&size| size > 0)
+                        .ok_or_else(|| format!("invalid backlog size '{value}'"))?
+                }
+                "cluster-enabled" => config.cluster_enabled = parse_yes_no(&name, &value)?,
+                "cluster-config-file" => config.cluster_config_file = file_name(value)?,
+                "cluster-node-timeout" => {
+                    config.cluster_node_timeout = value
+                        .parse()
+                        .ok()
+                        .filter(|&timeout| timeout > 0)
+                        .ok_or_else(|| format!("invalid node timeout '{value}'"))?
+                }
+                "sentinel-monitor" => {
+                    let monitor = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                        [name, host, port, quorum] => match (
+                            host.parse::<IpAddr>(),
+                            port.parse(),
+                            quorum.parse(),
+                        ) {
+                            (Ok(_), Ok(port), Ok(quorum)) if quorum > 0 => {
+                                (name.to_string(), host.to_string(), port, quorum)
+                            }
+                            _ => return Err(format!("invalid monitored primary '{value}'")),
+                        },
+                        _ => {
+                            return Err(format!(
+                                "invalid monitored primary '{value}', expected <name> <ip> <port> <quorum>"
+                            ))
+                        }
+                    };
+
+                    config.sentinel_monitors.push(monitor);
+                }
+                "sentinel-down-after-milliseconds" => {
+                    config.sentinel_down_after = value
+                        .parse()
+                        .ok()
+                        .filter(|&period| period > 0)
+                        .ok_or_else(|| format!("invalid down after period '{value}'"))?
+                }
+                "sentinel-failover-timeout" => {
+                    config.sentinel_failover_timeout = value
+                        .parse()
+                        .ok()
+                        .filter(|&timeout| timeout > 0)
+                        .ok_or_else(|| format!("invalid failover timeout '{value}'"))?
+                }
+                _ => return Err(format!("unknown option '{argument}'")),
+            }
+        }
+
+        if config.sentinel {
+            if config.cluster_enabled {
+                return Err("sentinel mode can't be used with cluster mode".to_string());
+            }
+
+            if !port_given {
+                config.port = DEFAULT_SENTINEL_PORT;
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(arguments: &[&str]) -> Result<Config, String> {
+        Config::parse(arguments.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parse_databases() {
+        assert_eq!(Ok(Config::default()), parse(&[]));
+        assert_eq!(4, parse(&["--databases", "4"]).unwrap().databases);
+        assert!(parse(&["--databases", "0"]).is_err());
+        assert!(parse(&["--databases"]).is_err());
+        assert!(parse(&["databases", "4"]).is_err());
+    }
+
+    #[test]
+    fn parse_notify_keyspace_events() {
+        assert_eq!(
+            notify::parse_flags("Ex"),
+            Some(
+                parse(&["--notify-keyspace-events", "Ex"])
+                    .unwrap()
+                    .notify_keyspace_events
+            )
+        );
+        assert!(parse(&["--notify-keyspace-events", "Q"]).is_err());
+    }
+
+    #[test]
+    fn parse_busy_reply_threshold() {
+        assert_eq!(
+            100,
+            parse(&["--lua-time-limit", "100"])
+                .unwrap()
+                .busy_reply_threshold
+        );
+        assert!(parse(&["--busy-reply-threshold", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parse_snapshot_path() {
+        let config = parse(&["--dir", "/tmp", "--dbfilename", "red.rdb"]).unwrap();
+
+        assert_eq!(PathBuf::from("/tmp"), config.dir);
+        assert_eq!("red.rdb", config.dbfilename);
+        assert!(parse(&["--dbfilename", "a/b.rdb"]).is_err());
+    }
+
+    #[test]
+    fn parse_save() {
+        assert_eq!(
+            vec![SavePoint {
+                seconds: 10,
+                changes: 2
+            }],
+            parse(&["--save", "10 2"]).unwrap().save_points
+        );
+        assert!(parse(&["--save", ""]).unwrap().save_points.is_empty());
+        assert!(parse(&["--save", "10"]).is_err());
+    }
+
+    #[test]
+    fn parse_append_only() {
+        let config = parse(&[
+            "--appendonly",
+            "yes",
+            "--appendfsync",
+            "always",
+            "--aof-load-truncated",
+            "no",
+        ])
+        .unwrap();
+
+        assert!(config.appendonly);
+        assert_eq!(FsyncPolicy::Always, config.appendfsync);
+        assert!(!config.aof_load_truncated);
+        assert!(parse(&["--appendonly", "maybe"]).is_err());
+        assert!(parse(&["--appendfsync", "sometimes"]).is_err());
+        assert!(parse(&["--appendfilename", ""]).is_err());
+    }
+
+    #[test]
+    fn parse_rewrite_growth() {
+        let config = parse(&[
+            "--auto-aof-rewrite-percentage",
+            "50",
+            "--auto-aof-rewrite-min-size",
+            "1mb",
+        ])
+        .unwrap();
+
+        assert_eq!(50, config.auto_aof_rewrite_percentage);
+        assert_eq!(1024 * 1024, config.auto_aof_rewrite_min_size);
+        assert_eq!(Some(2000), parse_memory("2K"));
+        assert_eq!(Some(10), parse_memory("10"));
+        assert_eq!(None, parse_memory("10xb"));
+        assert_eq!(None, parse_memory("mb"));
+    }
+
+    #[test]
+    fn parse_replication() {
+        let config = parse(&[
+            "--port",
+            "6380",
+            "--replicaof",
+            "localhost 6379",
+            "--replica-read-only",
+            "no",
+            "--repl-backlog-size",
+            "10kb",
+        ])
+        .unwrap();
+
+        assert_eq!(6380, config.port);
+        assert_eq!(Some(("localhost".to_string(), 6379)), config.replicaof);
+        assert!(!config.replica_read_only);
+        assert_eq!(10 * 1024, config.repl_backlog_size);
+        assert!(parse(&["--repl-backlog-size", "0"]).is_err());
+        assert!(parse(&["--port", "0"]).is_err());
+        assert!(parse(&["--replicaof", "localhost"]).is_err());
+        assert!(parse(&["--replicaof", "localhost port"]).is_err());
+    }
+
+    #[test]
+    fn parse_cluster() {
+        let config = parse(&[
+            "--cluster-enabled",
+            "yes",
+            "--cluster-config-file",
+            "nodes-7001.conf",
+            "--cluster-node-timeout",
+            "500",
+        ])
+        .unwrap();
+
+        assert!(config.cluster_enabled);
+        assert_eq!("nodes-7001.conf", config.cluster_config_file);
+        assert_eq!(500, config.cluster_node_timeout);
+        assert!(parse(&["--cluster-node-timeout", "0"]).is_err());
+        assert!(!parse(&[]).unwrap().cluster_enabled);
+        assert!(parse(&["--cluster-config-file", "a/nodes.conf"]).is_err());
+    }
+
+    #[test]
+    fn parse_sentinel() {
+        let config = parse(&[
+            "--sentinel",
+            "--sentinel-monitor",
+            "mymaster 127.0.0.1 6379 2",
+            "--sentinel-monitor",
+            "other 127.0.0.2 6380 1",
+            "--sentinel-down-after-milliseconds",
+            "5000",
+        ])
+        .unwrap();
+
+        assert!(config.sentinel);
+        assert_eq!(DEFAULT_SENTINEL_PORT, config.port);
+        assert_eq!(
+            vec![
+                ("mymaster".to_string(), "127.0.0.1".to_string(), 6379, 2),
+                ("other".to_string(), "127.0.0.2".to_string(), 6380, 1),
+            ],
+            config.sentinel_monitors
+        );
+        assert_eq!(5000, config.sentinel_down_after);
+        assert_eq!(DEFAULT_FAILOVER_TIMEOUT, config.sentinel_failover_timeout);
+        assert_eq!(
+            26380,
+            parse(&["--port", "26380", "--sentinel"]).unwrap().port
+        );
+        assert_eq!(DEFAULT_PORT, parse(&[]).unwrap().port);
+        assert!(parse(&["--sentinel-monitor", "mymaster 127.0.0.1 6379 0"]).is_err());
+        assert!(parse(&["--sentinel-monitor", "mymaster 127.0.0.1 6379"]).is_err());
+        assert!(parse(&["--sentinel-monitor", "mymaster localhost 6379 2"]).is_err());
+        assert!(parse(&["--sentinel", "--cluster-enabled", "yes"]).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+/// The number of hash slots that keys and sharded channels are divided into.
+pub(crate) const SLOTS: u16 = 16384;
+
+/// CRC-16/XMODEM (polynomial 0x1021, no reflection, initial value 0), as used by Redis Cluster.
+pub(crate) fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Returns the hash slot of a key. If the key contains a non-empty `{hash tag}`, only the tag is hashed, so that related
+/// keys can be put in the same slot.
+pub(crate) fn key_hash_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|&b| b == b'{').and_then(|start| {
+        key[start + 1..]
+            .iter()
+            .position(|&b| b == b'}')
+            .filter(|&length| length > 0)
+            .map(|length| &key[start + 1..start + 1 + length])
+    });
+
+    crc16(tag.unwrap_or(key)) % SLOTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_slots() {
+        assert_eq!(0x31c3, crc16(b"123456789"));
+        assert_eq!(12182, key_hash_slot(b"foo"));
+        assert_eq!(
+            key_hash_slot(b"user1000"),
+            key_hash_slot(b"{user1000}.following")
+        );
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"{user1000}.followers")
+        );
+        assert_eq!(crc16(b"{}foo") % SLOTS, key_hash_slot(b"{}foo"));
+        assert_eq!(key_hash_slot(b"bar"), key_hash_slot(b"foo{bar}{zap}"));
+    }
+}
@@ -0,0 +1,31 @@
+/// CRC-64/Jones (reflected polynomial 0x95ac9329ac4bc9b5, initial value 0, no final XOR), which Redis uses to check
+/// RDB files and `DUMP` payloads.
+pub(crate) fn crc64(crc: u64, bytes: &[u8]) -> u64 {
+    let mut crc = crc;
+
+    for byte in bytes {
+        crc ^= *byte as u64;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x95ac9329ac4bc9b5
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(0xe9c6d914c4b8d9ca, crc64(0, b"123456789"));
+        assert_eq!(crc64(0, b"123456789"), crc64(crc64(0, b"1234"), b"56789"));
+        assert_eq!(0, crc64(0, b""));
+    }
+}
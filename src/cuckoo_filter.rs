@@ -0,0 +1,467 @@
+use crate::murmur::murmur_hash_64a;
+use crate::rdb::{ModuleReader, ModuleWriter};
+
+pub(crate) const DEFAULT_CAPACITY: u64 = 1024;
+pub(crate) const DEFAULT_BUCKET_SIZE: u64 = 2;
+pub(crate) const DEFAULT_MAX_ITERATIONS: u64 = 20;
+pub(crate) const DEFAULT_EXPANSION: u64 = 1;
+pub(crate) const MAX_BUCKET_SIZE: u64 = 255;
+pub(crate) const MAX_ITERATIONS: u64 = 65535;
+pub(crate) const MAX_EXPANSION: u64 = 32768;
+
+/// The most memory a single sub-filter may take, in bytes, so a huge capacity gets an error instead of an
+/// allocation that takes the server down.
+const MAX_SUB_FILTER_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Fingerprints are a single non-zero byte; zero marks an empty slot.
+type Fingerprint = u8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum CuckooFilterError {
+    Full,
+    TooLarge,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct SubFilter {
+    /// `bucket_count * bucket_size` slots, bucket by bucket.
+    slots: Vec<Fingerprint>,
+    bucket_count: u64,
+}
+
+impl SubFilter {
+    fn new(bucket_count: u64, bucket_size: u64) -> Result<SubFilter, CuckooFilterError> {
+        let size = bucket_count
+            .checked_mul(bucket_size)
+            .filter(|&size| size <= MAX_SUB_FILTER_SIZE)
+            .ok_or(CuckooFilterError::TooLarge)?;
+
+        Ok(SubFilter {
+            slots: vec![0; size as usize],
+            bucket_count,
+        })
+    }
+}
+
+/// A Cuckoo filter which, unlike a Bloom filter, supports deleting items. When a sub-filter runs out of room
+/// another, larger one is added (unless the expansion rate is zero).
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct CuckooFilter {
+    filters: Vec<SubFilter>,
+    bucket_size: u64,
+    max_iterations: u64,
+    expansion: u64,
+    items: u64,
+    deleted: u64,
+    /// State for the xorshift generator that picks which fingerprint to evict.
+    random_state: u64,
+}
+
+struct Hashes {
+    fingerprint: Fingerprint,
+    hash: u64,
+}
+
+impl Hashes {
+    fn new(item: &[u8]) -> Hashes {
+        let hash = murmur_hash_64a(item, 0);
+
+        Hashes {
+            fingerprint: (hash % 255 + 1) as Fingerprint,
+            hash,
+        }
+    }
+}
+
+fn alternate_index(index: u64, fingerprint: Fingerprint, bucket_count: u64) -> u64 {
+    (index ^ (fingerprint as u64).wrapping_mul(0x5bd1e995)) & (bucket_count - 1)
+}
+
+impl CuckooFilter {
+    pub(crate) fn new(
+        capacity: u64,
+        bucket_size: u64,
+        max_iterations: u64,
+        expansion: u64,
+    ) -> Result<CuckooFilter, CuckooFilterError> {
+        let bucket_count = capacity
+            .div_ceil(bucket_size)
+            .checked_next_power_of_two()
+            .ok_or(CuckooFilterError::TooLarge)?;
+
+        Ok(CuckooFilter {
+            filters: vec![SubFilter::new(bucket_count, bucket_size)?],
+            bucket_size,
+            max_iterations,
+            // Bucket counts have to stay powers of two for the alternate index calculation to be reversible.
+            expansion: if expansion == 0 {
+                0
+            } else {
+                expansion
+                    .checked_next_power_of_two()
+                    .ok_or(CuckooFilterError::TooLarge)?
+            },
+            items: 0,
+            deleted: 0,
+            random_state: 0x2545f4914f6cdd1d,
+        })
+    }
+
+    fn bucket(&self, filter: usize, index: u64) -> &[Fingerprint] {
+        let start = (index * self.bucket_size) as usize;
+
+        &self.filters[filter].slots[start..start + self.bucket_size as usize]
+    }
+
+    fn bucket_mut(&mut self, filter: usize, index: u64) -> &mut [Fingerprint] {
+        let start = (index * self.bucket_size) as usize;
+
+        &mut self.filters[filter].slots[start..start + self.bucket_size as usize]
+    }
+
+    fn indices(&self, filter: usize, hashes: &Hashes) -> (u64, u64) {
+        let bucket_count = self.filters[filter].bucket_count;
+        let i1 = hashes.hash & (bucket_count - 1);
+
+        (i1, alternate_index(i1, hashes.fingerprint, bucket_count))
+    }
+
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.random_state;
+
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+
+        self.random_state = x;
+
+        x
+    }
+
+    pub(crate) fn count(&self, item: &[u8]) -> u64 {
+        let hashes = Hashes::new(item);
+
+        (0..self.filters.len())
+            .map(|filter| {
+                let (i1, i2) = self.indices(filter, &hashes);
+                let matches = |index| {
+                    self.bucket(filter, index)
+                        .iter()
+                        .filter(|slot| **slot == hashes.fingerprint)
+                        .count() as u64
+                };
+
+                if i1 == i2 {
+                    matches(i1)
+                } else {
+                    matches(i1) + matches(i2)
+                }
+            })
+            .sum()
+    }
+
+    pub(crate) fn contains(&self, item: &[u8]) -> bool {
+        self.count(item) > 0
+    }
+
+    fn try_place(&mut self, filter: usize, index: u64, fingerprint: Fingerprint) -> bool {
+        match self
+            .bucket_mut(filter, index)
+            .iter_mut()
+            .find(|slot| **slot == 0)
+        {
+            Some(slot) => {
+                *slot = fingerprint;
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Tries to insert into a single sub-filter by evicting fingerprints to their alternate buckets. If that doesn't
+    /// succeed within `max_iterations` kicks, every eviction is undone so the sub-filter is left unchanged.
+    fn insert_into(&mut self, filter: usize, hashes: &Hashes) -> bool {
+        let (i1, i2) = self.indices(filter, hashes);
+
+        if self.try_place(filter, i1, hashes.fingerprint)
+            || self.try_place(filter, i2, hashes.fingerprint)
+        {
+            return true;
+        }
+
+        let bucket_count = self.filters[filter].bucket_count;
+        let mut evictions: Vec<(u64, usize)> = Vec::new();
+        let mut fingerprint = hashes.fingerprint;
+        let mut index = if self.next_random().is_multiple_of(2) {
+            i1
+        } else {
+            i2
+        };
+
+        for _ in 0..self.max_iterations {
+            let slot = (self.next_random() % self.bucket_size) as usize;
+            let bucket = self.bucket_mut(filter, index);
+
+            std::mem::swap(&mut bucket[slot], &mut fingerprint);
+            evictions.push((index, slot));
+
+            index = alternate_index(index, fingerprint, bucket_count);
+
+            if self.try_place(filter, index, fingerprint) {
+                return true;
+            }
+        }
+
+        for (index, slot) in evictions.into_iter().rev() {
+            let bucket = self.bucket_mut(filter, index);
+
+            std::mem::swap(&mut bucket[slot], &mut fingerprint);
+        }
+
+        false
+    }
+
+    /// Adds `item` to the filter. Items can be added more than once.
+    pub(crate) fn insert(&mut self, item: &[u8]) -> Result<(), CuckooFilterError> {
+        let hashes = Hashes::new(item);
+
+        // Only the newest sub-filter is written to; older ones are full by definition.
+        let last = self.filters.len() - 1;
+
+        if !self.insert_into(last, &hashes) {
+            if self.expansion == 0 {
+                return Err(CuckooFilterError::Full);
+            }
+
+            // A sub-filter that would be too large to allocate can't be added, so the filter is as full as it gets.
+            let sub_filter = self.filters[last]
+                .bucket_count
+                .checked_mul(self.expansion)
+                .ok_or(CuckooFilterError::TooLarge)
+                .and_then(|bucket_count| SubFilter::new(bucket_count, self.bucket_size))
+                .map_err(|_| CuckooFilterError::Full)?;
+
+            self.filters.push(sub_filter);
+
+            if !self.insert_into(last + 1, &hashes) {
+                return Err(CuckooFilterError::Full);
+            }
+        }
+
+        self.items += 1;
+
+        Ok(())
+    }
+
+    /// Removes one copy of `item`, returning `false` if it wasn't found. Newer sub-filters are searched first.
+    pub(crate) fn delete(&mut self, item: &[u8]) -> bool {
+        let hashes = Hashes::new(item);
+
+        for filter in (0..self.filters.len()).rev() {
+            let (i1, i2) = self.indices(filter, &hashes);
+
+            for index in [i1, i2] {
+                let bucket = self.bucket_mut(filter, index);
+
+                if let Some(slot) = bucket.iter_mut().find(|slot| **slot == hashes.fingerprint) {
+                    *slot = 0;
+
+                    self.items -= 1;
+                    self.deleted += 1;
+
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// The approximate memory used by the filter, in bytes.
+    pub(crate) fn size(&self) -> u64 {
+        let slots: usize = self.filters.iter().map(|filter| filter.slots.len()).sum();
+
+        (std::mem::size_of::<CuckooFilter>()
+            + self.filters.len() * std::mem::size_of::<SubFilter>()
+            + slots) as u64
+    }
+
+    pub(crate) fn bucket_count(&self) -> u64 {
+        self.filters.iter().map(|filter| filter.bucket_count).sum()
+    }
+
+    pub(crate) fn filter_count(&self) -> u64 {
+        self.filters.len() as u64
+    }
+
+    pub(crate) fn items(&self) -> u64 {
+        self.items
+    }
+
+    pub(crate) fn deleted(&self) -> u64 {
+        self.deleted
+    }
+
+    pub(crate) fn bucket_size(&self) -> u64 {
+        self.bucket_size
+    }
+
+    pub(crate) fn expansion(&self) -> u64 {
+        self.expansion
+    }
+
+    pub(crate) fn max_iterations(&self) -> u64 {
+        self.max_iterations
+    }
+
+    /// Saves the filter to an RDB file.
+    pub(crate) fn save(&self, writer: &mut ModuleWriter) {
+        writer.save_unsigned(self.bucket_size);
+        writer.save_unsigned(self.max_iterations);
+        writer.save_unsigned(self.expansion);
+        writer.save_unsigned(self.items);
+        writer.save_unsigned(self.deleted);
+        writer.save_unsigned(self.random_state);
+        writer.save_unsigned(self.filters.len() as u64);
+
+        for filter in &self.filters {
+            writer.save_unsigned(filter.bucket_count);
+            writer.save_string(&filter.slots);
+        }
+    }
+
+    pub(crate) fn load(reader: &mut ModuleReader) -> Option<CuckooFilter> {
+        let mut filter = CuckooFilter {
+            filters: Vec::new(),
+            bucket_size: reader.load_unsigned()?,
+            max_iterations: reader.load_unsigned()?,
+            expansion: reader.load_unsigned()?,
+            items: reader.load_unsigned()?,
+            deleted: reader.load_unsigned()?,
+            random_state: reader.load_unsigned()?,
+        };
+        let filter_count = reader.load_unsigned()?;
+
+        for _ in 0..filter_count {
+            let sub_filter = SubFilter {
+                bucket_count: reader.load_unsigned()?,
+                slots: reader.load_string()?,
+            };
+
+            if !sub_filter.bucket_count.is_power_of_two()
+                || sub_filter.slots.len() as u64
+                    != sub_filter.bucket_count.checked_mul(filter.bucket_size)?
+            {
+                return None;
+            }
+
+            filter.filters.push(sub_filter);
+        }
+
+        if filter.filters.is_empty() || filter.bucket_size == 0 {
+            return None;
+        }
+
+        // Deleting relies on the count matching the fingerprints stored, and expanding on bucket counts staying powers
+        // of two.
+        let occupied = filter
+            .filters
+            .iter()
+            .flat_map(|sub_filter| &sub_filter.slots)
+            .filter(|&&slot| slot != 0)
+            .count() as u64;
+
+        if occupied != filter.items
+            || (filter.expansion != 0 && !filter.expansion.is_power_of_two())
+        {
+            return None;
+        }
+
+        Some(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_count_and_delete() {
+        let mut filter = CuckooFilter::new(1024, 2, 20, 1).unwrap();
+
+        assert!(!filter.contains(b"foo"));
+
+        filter.insert(b"foo").unwrap();
+        filter.insert(b"foo").unwrap();
+
+        assert_eq!(2, filter.count(b"foo"));
+        assert!(filter.delete(b"foo"));
+        assert_eq!(1, filter.count(b"foo"));
+        assert!(filter.delete(b"foo"));
+        assert!(!filter.contains(b"foo"));
+        assert!(!filter.delete(b"foo"));
+        assert_eq!(0, filter.items());
+        assert_eq!(2, filter.deleted());
+    }
+
+    #[test]
+    fn expands_when_full() {
+        let mut filter = CuckooFilter::new(8, 2, 20, 1).unwrap();
+
+        for i in 0..64 {
+            filter.insert(format!("item{i}").as_bytes()).unwrap();
+        }
+
+        assert!(filter.filter_count() > 1);
+
+        for i in 0..64 {
+            assert!(filter.contains(format!("item{i}").as_bytes()));
+        }
+    }
+
+    #[test]
+    fn non_expanding_filter_fills_up_without_losing_items() {
+        let mut filter = CuckooFilter::new(8, 2, 20, 0).unwrap();
+        let mut inserted = Vec::new();
+
+        for i in 0..64 {
+            let item = format!("item{i}");
+
+            match filter.insert(item.as_bytes()) {
+                Ok(()) => inserted.push(item),
+                Err(e) => {
+                    assert_eq!(CuckooFilterError::Full, e);
+
+                    break;
+                }
+            }
+        }
+
+        assert!(inserted.len() < 64);
+        assert_eq!(1, filter.filter_count());
+
+        for item in inserted {
+            assert!(filter.contains(item.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn refuses_huge_filters() {
+        assert_eq!(
+            Err(CuckooFilterError::TooLarge),
+            CuckooFilter::new(u64::MAX, 1, 20, 1)
+        );
+        assert_eq!(
+            Err(CuckooFilterError::TooLarge),
+            CuckooFilter::new(1 << 40, MAX_BUCKET_SIZE, 20, 1)
+        );
+
+        let mut filter = CuckooFilter::new(1 << 20, 2, 20, MAX_EXPANSION).unwrap();
+
+        filter.filters[0].slots.fill(1);
+
+        assert_eq!(Err(CuckooFilterError::Full), filter.insert(b"foo"));
+        assert_eq!(1, filter.filter_count());
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::aof::{self, Aof, FsyncPolicy, DEFAULT_APPEND_DIRNAME, DEFAULT_APPEND_FILENAME};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::cluster::Cluster;
+use crate::commands::{CommandSpec, Response};
+use crate::database::{now_ms, Database};
+use crate::functions::Libraries;
+use crate::lazy_free;
+use crate::lua::FunctionBody;
+use crate::notify;
+use crate::object::Object;
+use crate::pubsub::Subscriptions;
+use crate::rdb::{Snapshot, Snapshots};
+use crate::replication::Replication;
+use crate::sentinel::{Reply, Request, Sentinel};
+
+pub(crate) const DEFAULT_DATABASES: usize = 16;
+pub(crate) const DEFAULT_DB_FILENAME: &str = "dump.rdb";
+
+/// Everything that's shared between connections: the numbered databases, the Pub/Sub subscriptions, the script cache,
+/// the function libraries, where snapshots are saved, the append-only file, replication, the cluster and the
+/// sentinel.
+///
+/// Each connection has its own selected database. It's stored here while the connection holds the lock, so that
+/// commands can use `Data` as if it were the selected `Database`.
+#[derive(Debug)]
+pub(crate) struct Data {
+    databases: Vec<Database>,
+    selected: usize,
+    subscriptions: Subscriptions,
+    notify_flags: u32,
+    /// Compiled scripts, by the SHA-1 of their source.
+    scripts: HashMap<String, Arc<FunctionBody>>,
+    libraries: Libraries,
+    /// How many times the function libraries have been modified. The databases count their own changes.
+    library_changes: u64,
+    snapshots: Snapshots,
+    aof: Aof,
+    replication: Replication,
+    cluster: Cluster,
+    sentinel: Sentinel,
+}
+
+impl Data {
+    pub(crate) fn new() -> Data {
+        Data::with_databases(DEFAULT_DATABASES)
+    }
+
+    pub(crate) fn with_databases(count: usize) -> Data {
+        Data {
+            databases: (0..count).map(|_| Database::new()).collect(),
+            selected: 0,
+            subscriptions: Subscriptions::new(),
+            notify_flags: 0,
+            scripts: HashMap::new(),
+            libraries: Libraries::new(),
+            library_changes: 0,
+            snapshots: Snapshots::new(PathBuf::from(DEFAULT_DB_FILENAME)),
+            aof: Aof::new(
+                PathBuf::from("."),
+                DEFAULT_APPEND_DIRNAME.to_string(),
+                DEFAULT_APPEND_FILENAME.to_string(),
+                FsyncPolicy::EverySec,
+            ),
+            replication: Replication::new(),
+            cluster: Cluster::new(),
+            sentinel: Sentinel::new(),
+        }
+    }
+
+    pub(crate) fn database_count(&self) -> usize {
+        self.databases.len()
+    }
+
+    pub(crate) fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Selects the database that commands operate on. The index must be in range.
+    pub(crate) fn select(&mut self, index: usize) {
+        assert!(index < self.databases.len(), "database index out of range");
+
+        self.selected = index;
+    }
+
+    pub(crate) fn database_mut(&mut self, index: usize) -> &mut Database {
+        &mut self.databases[index]
+    }
+
+    pub(crate) fn databases_mut(&mut self) -> impl Iterator<Item = &mut Database> {
+        self.databases.iter_mut()
+    }
+
+    pub(crate) fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    pub(crate) fn subscriptions_mut(&mut self) -> &mut Subscriptions {
+        &mut self.subscriptions
+    }
+
+    pub(crate) fn scripts_mut(&mut self) -> &mut HashMap<String, Arc<FunctionBody>> {
+        &mut self.scripts
+    }
+
+    pub(crate) fn libraries(&self) -> &Libraries {
+        &self.libraries
+    }
+
+    /// Gets the function libraries to modify them, which counts as a change.
+    pub(crate) fn libraries_mut(&mut self) -> &mut Libraries {
+        self.library_changes += 1;
+
+        &mut self.libraries
+    }
+
+    pub(crate) fn snapshots(&self) -> &Snapshots {
+        &self.snapshots
+    }
+
+    pub(crate) fn set_snapshots(&mut self, snapshots: Snapshots) {
+        self.snapshots = snapshots;
+    }
+
+    pub(crate) fn snapshots_mut(&mut self) -> &mut Snapshots {
+        &mut self.snapshots
+    }
+
+    pub(crate) fn aof(&self) -> &Aof {
+        &self.aof
+    }
+
+    pub(crate) fn set_aof(&mut self, aof: Aof) {
+        self.aof = aof;
+    }
+
+    pub(crate) fn aof_mut(&mut self) -> &mut Aof {
+        &mut self.aof
+    }
+
+    pub(crate) fn cluster(&self) -> &Cluster {
+        &self.cluster
+    }
+
+    pub(crate) fn cluster_mut(&mut self) -> &mut Cluster {
+        &mut self.cluster
+    }
+
+    pub(crate) fn sentinel(&self) -> &Sentinel {
+        &self.sentinel
+    }
+
+    pub(crate) fn sentinel_mut(&mut self) -> &mut Sentinel {
+        &mut self.sentinel
+    }
+
+    pub(crate) fn replication(&self) -> &Replication {
+        &self.replication
+    }
+
+    pub(crate) fn replication_mut(&mut self) -> &mut Replication {
+        &mut self.replication
+    }
+
+    /// Logs the effects of a command that just ran against the selected database to the append-only file, if it's
+    /// enabled, and replicates them. They're written once the command, transaction or script finishes, by
+    /// [`Data::flush_propagated`].
+    pub(crate) fn propagate(
+        &mut self,
+        spec: &CommandSpec,
+        arguments: &[Value],
+        response: &Response,
+    ) {
+        self.propagate_expired();
+
+        if !self.aof.is_enabled() && !self.replication.is_streaming() {
+            return;
+        }
+
+        for command in aof::effects(self, spec, arguments, response) {
+            self.aof.feed(self.selected, command.clone());
+            self.replication.feed(self.selected, command);
+        }
+    }
+
+    /// Propagates a `DEL` for each key that expired since the last call, so that the append-only file and replicas
+    /// drop it too. This comes before the effects of the command that found the key expired.
+    fn propagate_expired(&mut self) {
+        for (index, database) in self.databases.iter_mut().enumerate() {
+            for key in database.take_expired() {
+                let key = match key {
+                    BulkString::Filled(bytes) => bytes,
+                    _ => Vec::new(),
+                };
+                let command = vec![b"DEL".to_vec(), key];
+
+                self.aof.feed(index, command.clone());
+                self.replication.feed(index, command);
+            }
+        }
+    }
+
+    /// How much of the replication stream is in the append-only file on disk, or `None` if it's disabled. Anything
+    /// applied before the file was last flushed to disk counts.
+    pub(crate) fn aof_replication_offset(&mut self) -> Option<u64> {
+        if !self.aof.is_enabled() {
+            return None;
+        }
+
+        if self.aof.is_synced() {
+            let offset = self.replication.offset();
+
+            self.replication.set_aof_offset(offset);
+        }
+
+        Some(self.replication.aof_offset())
+    }
+
+    /// Runs the periodic replication tasks. See [`Replication::cron`].
+    pub(crate) fn replication_cron(&mut self) -> Option<(String, u16, u64)> {
+        let aof_offset = self.aof_replication_offset();
+
+        self.replication.cron(aof_offset)
+    }
+
+    /// Runs the periodic cluster tasks, if cluster mode is enabled. See [`Cluster::cron`]. Returns the messages to
+    /// send to other nodes, with the IP and bus port of each.
+    pub(crate) fn cluster_cron(&mut self) -> Vec<(String, u16, Vec<u8>)> {
+        if !self.cluster.is_enabled() {
+            return Vec::new();
+        }
+
+        self.cluster.set_offset(self.replication.offset());
+        self.cluster.cron(now_ms());
+        self.follow_cluster_role();
+        self.cluster.take_outbox()
+    }
+
+    /// Handles a message from another node of the cluster, which came from `ip`. Returns the reply, if any.
+    pub(crate) fn cluster_message(&mut self, ip: &str, message: &str) -> Option<Vec<u8>> {
+        self.cluster.set_offset(self.replication.offset());
+
+        let reply = self.cluster.receive(ip, message, now_ms());
+
+        self.follow_cluster_role();
+        reply
+    }
+
+    /// Runs the periodic sentinel tasks, if sentinel mode is enabled. See [`Sentinel::cron`]. Returns the requests to
+    /// send to instances and other sentinels.
+    pub(crate) fn sentinel_cron(&mut self) -> Vec<Request> {
+        if !self.sentinel.is_enabled() {
+            return Vec::new();
+        }
+
+        self.sentinel.cron(now_ms());
+        self.publish_events();
+        self.sentinel.take_outbox()
+    }
+
+    /// Handles the reply to a request the sentinel sent, or `None` if the request failed.
+    pub(crate) fn sentinel_reply(&mut self, request: &Request, reply: Option<Reply>) {
+        self.sentinel.reply(request, reply, now_ms());
+        self.publish_events();
+    }
+
+    /// Handles a hello published by a sentinel on an instance this one monitors.
+    pub(crate) fn sentinel_hello(&mut self, hello: &str) {
+        self.sentinel.hello(hello, now_ms());
+        self.publish_events();
+    }
+
+    /// Makes replication follow this node's role in the cluster, after it became a replica or was promoted.
+    pub(crate) fn follow_cluster_role(&mut self) {
+        let myself = self.cluster.myself();
+
+        let Some(primary) = myself.primary() else {
+            if self.replication.primary().is_some() {
+                self.replication.remove_primary();
+                println!("MASTER MODE enabled");
+            }
+
+            return;
+        };
+
+        if let Some(primary) = self.cluster.node(primary) {
+            let (host, port) = (primary.ip().to_string(), primary.port());
+
+            if self.replication.set_primary(host.clone(), port) {
+                println!("REPLICAOF {host}:{port} enabled");
+            }
+        }
+    }
+
+    pub(crate) fn flush_propagated(&mut self) {
+        self.aof.flush();
+        self.replication.flush();
+    }
+
+    /// Starts rewriting the append-only file in the background, from a copy of the data as it is now.
+    pub(crate) fn rewrite_aof(&mut self) -> Result<(), String> {
+        self.aof.check_rewrite()?;
+
+        let snapshot = self.snapshot();
+
+        self.aof.rewrite(snapshot)
+    }
+
+    /// Flushes the append-only file to disk, finishes a rewrite that's done, and starts one if the file has grown
+    /// enough. Called periodically.
+    pub(crate) fn maintain_aof(&mut self) {
+        self.aof.sync_if_needed();
+        self.aof.finish_rewrite();
+
+        if self.aof.should_rewrite() {
+            println!(
+                "Starting automatic rewriting of AOF on {}% growth",
+                self.aof.rewrite_percentage()
+            );
+
+            if let Err(error) = self.rewrite_aof() {
+                eprintln!("Can't rewrite the append only file: {error}");
+            }
+        }
+    }
+
+    /// How many changes have been made to the databases and function libraries since the server started.
+    pub(crate) fn changes(&self) -> u64 {
+        self.databases.iter().map(Database::changes).sum::<u64>() + self.library_changes
+    }
+
+    /// Starts a background save if a save point has been reached. Called periodically.
+    pub(crate) fn save_if_needed(&mut self) {
+        let changes = self.changes();
+
+        if self.snapshots.should_save(changes) {
+            println!(
+                "{} changes since the last save. Saving...",
+                self.snapshots.changes_since_save(changes)
+            );
+            self.snapshots.save_in_background(self.snapshot(), changes);
+        }
+    }
+
+    /// Copies every database and function library, so that they can be saved without holding the lock.
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            databases: self.databases.iter().map(Database::snapshot).collect(),
+            libraries: self
+                .libraries
+                .iter()
+                .map(|library| library.code.clone())
+                .collect(),
+        }
+    }
+
+    /// Adds the keys and function libraries of a snapshot, like when it's loaded at startup.
+    pub(crate) fn load(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        self.add(snapshot)?;
+        self.snapshots.mark_saved(self.changes());
+
+        Ok(())
+    }
+
+    /// Replaces every key and function library with a snapshot's, like when a replica syncs with its primary.
+    pub(crate) fn replace(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        for database in &mut self.databases {
+            lazy_free::free_database(database.clear());
+        }
+
+        self.libraries_mut().clear();
+        self.add(snapshot)
+    }
+
+    fn add(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        if snapshot.databases.len() > self.databases.len() {
+            return Err(format!(
+                "the snapshot has {} databases, but only {} are configured",
+                snapshot.databases.len(),
+                self.databases.len()
+            ));
+        }
+
+        for code in snapshot.libraries {
+            self.libraries.load(&code, false)?;
+        }
+
+        let now = now_ms();
+
+        for (database, entries) in self.databases.iter_mut().zip(snapshot.databases) {
+            for (key, object, expires_at) in entries {
+                // Keys that expired while the server was down aren't loaded at all.
+                if expires_at.is_some_and(|at| at <= now) {
+                    continue;
+                }
+
+                database.insert(key.clone(), object);
+
+                if let Some(at) = expires_at {
+                    database.set_expires_at(&key, at);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn notify_flags(&self) -> u32 {
+        self.notify_flags
+    }
+
+    pub(crate) fn set_notify_flags(&mut self, flags: u32) {
+        self.notify_flags = flags;
+
+        for database in &mut self.databases {
+            database.set_notify_flags(flags);
+        }
+    }
+
+    /// Publishes the keyspace events that commands have recorded since the last call, and the sentinel's events, which
+    /// are also logged.
+    pub(crate) fn publish_events(&mut self) {
+        for (channel, message) in self.sentinel.take_events() {
+            println!("{channel} {message}");
+            self.subscriptions.publish(
+                &BulkString::Filled(channel.as_bytes().to_vec()),
+                &BulkString::Filled(message.into_bytes()),
+            );
+        }
+
+        for (index, database) in self.databases.iter_mut().enumerate() {
+            for (event, key) in database.take_events() {
+                let key = match key {
+                    BulkString::Filled(bytes) => bytes,
+                    _ => Vec::new(),
+                };
+
+                if self.notify_flags & notify::KEYSPACE != 0 {
+                    let mut channel = format!("__keyspace@{index}__:").into_bytes();
+
+                    channel.extend(&key);
+                    self.subscriptions.publish(
+                        &BulkString::Filled(channel),
+                        &BulkString::Filled(event.as_bytes().to_vec()),
+                    );
+                }
+
+                if self.notify_flags & notify::KEYEVENT != 0 {
+                    self.subscriptions.publish(
+                        &BulkString::Filled(format!("__keyevent@{index}__:{event}").into_bytes()),
+                        &BulkString::Filled(key),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Removes some of the expired keys from every database. Called periodically, so that keys expire, and their
+    /// `expired` events are published and their deletion propagated, even if they're never looked up.
+    pub(crate) fn active_expire_cycle(&mut self) {
+        for database in &mut self.databases {
+            database.expire_cycle();
+        }
+
+        self.propagate_expired();
+        self.flush_propagated();
+        self.publish_events();
+    }
+
+    pub(crate) fn swap(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+
+        let (low, high) = self.databases.split_at_mut(a.max(b));
+
+        low[a.min(b)].swap_contents(&mut high[0]);
+    }
+}
+
+impl Deref for Data {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.databases[self.selected]
+    }
+}
+
+impl DerefMut for Data {
+    fn deref_mut(&mut self) -> &mut Database {
+        &mut self.databases[self.selected]
+    }
+}
+
+impl<const N: usize> From<[(BulkString, Object); N]> for Data {
+    fn from(entries: [(BulkString, Object); N]) -> Data {
+        let mut data = Data::new();
+
+        data.databases[0] = Database::from(entries);
+
+        data
+    }
+}
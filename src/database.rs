@@ -0,0 +1,591 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bulk_string::BulkString;
+use crate::cluster;
+use crate::dict::Dict;
+use crate::notify;
+use crate::object::Object;
+
+/// How many keys with a TTL each round of an active expiry cycle looks at, like Redis's.
+const EXPIRE_CYCLE_SAMPLE: usize = 20;
+/// The most rounds an active expiry cycle runs, however many of the keys it looks at have expired.
+const EXPIRE_CYCLE_ROUNDS: usize = 16;
+
+/// The current Unix time, in milliseconds.
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Advances a splitmix64 generator. Every bit of its output is well mixed, unlike a plain xorshift's, whose
+/// consecutive low bits are related: [`Dict::random_entry`] draws again after an empty bucket, so that would make it
+/// miss some buckets entirely.
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+
+    let mut z = *state;
+
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+    z ^ (z >> 31)
+}
+
+/// A key that connections are watching.
+#[derive(Debug)]
+struct Watch {
+    watchers: usize,
+    /// Changes whenever the key is modified.
+    version: u64,
+}
+
+/// A keyspace: the stored values and the times at which some of them expire.
+///
+/// Expired keys are removed lazily, whenever they're looked up. Methods that only take `&self` skip them instead.
+#[derive(Debug)]
+pub(crate) struct Database {
+    entries: Dict<BulkString, Object>,
+    /// Unix times in milliseconds, for keys that have a TTL.
+    expires: Dict<BulkString, u64>,
+    watched: HashMap<BulkString, Watch>,
+    random_state: u64,
+    /// The `notify-keyspace-events` flags.
+    notify_flags: u32,
+    /// Keyspace events that haven't been published yet.
+    events: Vec<(&'static str, BulkString)>,
+    /// Keys that expired, whose deletion hasn't been propagated yet. See [`Data::propagate`](crate::Data::propagate).
+    expired: Vec<BulkString>,
+    /// How many times keys have been modified, for deciding when to save a snapshot.
+    changes: u64,
+    /// The keys in each hash slot that has any, only kept in cluster mode. See [`Database::index_slots`].
+    slots: Option<BTreeMap<u16, BTreeSet<BulkString>>>,
+}
+
+impl Database {
+    pub(crate) fn new() -> Database {
+        Database {
+            entries: Dict::new(),
+            expires: Dict::new(),
+            watched: HashMap::new(),
+            random_state: 0x9e3779b97f4a7c15,
+            notify_flags: 0,
+            events: Vec::new(),
+            expired: Vec::new(),
+            changes: 0,
+            slots: None,
+        }
+    }
+
+    /// Starts keeping track of the keys in each hash slot, as a node of a cluster needs to count them and move them
+    /// to other nodes.
+    pub(crate) fn index_slots(&mut self) {
+        let mut slots: BTreeMap<u16, BTreeSet<BulkString>> = BTreeMap::new();
+
+        for (key, _) in self.entries.iter() {
+            slots
+                .entry(cluster::slot(key))
+                .or_default()
+                .insert(key.clone());
+        }
+
+        self.slots = Some(slots);
+    }
+
+    fn index_key(&mut self, key: &BulkString) {
+        if let Some(slots) = &mut self.slots {
+            slots
+                .entry(cluster::slot(key))
+                .or_default()
+                .insert(key.clone());
+        }
+    }
+
+    fn unindex_key(&mut self, key: &BulkString) {
+        let Some(slots) = &mut self.slots else {
+            return;
+        };
+        let slot = cluster::slot(key);
+
+        if let Some(keys) = slots.get_mut(&slot) {
+            keys.remove(key);
+
+            if keys.is_empty() {
+                slots.remove(&slot);
+            }
+        }
+    }
+
+    fn is_expired(&self, key: &BulkString) -> bool {
+        self.expires.get(key).is_some_and(|&at| at <= now_ms())
+    }
+
+    /// Records that `key` was modified, for connections that are watching it and for snapshots. Commands that change
+    /// a value they got from [`Database::get_mut`] call this once they have, so failed or no-op writes don't count.
+    pub(crate) fn touch(&mut self, key: &BulkString) {
+        self.changes += 1;
+
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.version += 1;
+        }
+    }
+
+    /// Records a keyspace event of the given class, if notifications for it are enabled. Events are published by
+    /// [`Data::publish_events`](crate::Data::publish_events) once the command that caused them has finished.
+    pub(crate) fn notify(&mut self, class: u32, event: &'static str, key: &BulkString) {
+        if self.notify_flags & class != 0
+            && self.notify_flags & (notify::KEYSPACE | notify::KEYEVENT) != 0
+        {
+            self.events.push((event, key.clone()));
+        }
+    }
+
+    pub(crate) fn set_notify_flags(&mut self, flags: u32) {
+        self.notify_flags = flags;
+    }
+
+    pub(crate) fn take_events(&mut self) -> Vec<(&'static str, BulkString)> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub(crate) fn take_expired(&mut self) -> Vec<BulkString> {
+        std::mem::take(&mut self.expired)
+    }
+
+    /// Removes `key` if it has expired.
+    pub(crate) fn expire_if_needed(&mut self, key: &BulkString) {
+        if self.is_expired(key) {
+            self.expire(key);
+        }
+    }
+
+    /// Removes `key`, which has expired.
+    fn expire(&mut self, key: &BulkString) {
+        self.remove(key);
+        self.notify(notify::EXPIRED, "expired", key);
+        self.expired.push(key.clone());
+    }
+
+    /// Removes keys that have expired, even if nobody looks them up. Like Redis, this looks at random keys with a
+    /// TTL, and only keeps going while more than a quarter of them had expired, so that the lock isn't held for long
+    /// however many keys have a TTL.
+    pub(crate) fn expire_cycle(&mut self) {
+        for _ in 0..EXPIRE_CYCLE_ROUNDS {
+            let now = now_ms();
+            let sample = EXPIRE_CYCLE_SAMPLE.min(self.expires.len());
+            let mut expired = Vec::new();
+
+            for _ in 0..sample {
+                let random_state = &mut self.random_state;
+
+                if let Some((key, &at)) = self.expires.random_entry(|| next_random(random_state)) {
+                    if at <= now {
+                        expired.push(key.clone());
+                    }
+                }
+            }
+
+            let before = self.expires.len();
+
+            for key in expired {
+                self.expire_if_needed(&key);
+            }
+
+            if (before - self.expires.len()) * 4 <= sample {
+                break;
+            }
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: &BulkString) -> Option<&Object> {
+        self.expire_if_needed(key);
+        self.entries.get(key)
+    }
+
+    /// Gets a value to modify it. See [`Database::touch`].
+    pub(crate) fn get_mut(&mut self, key: &BulkString) -> Option<&mut Object> {
+        self.expire_if_needed(key);
+        self.entries.get_mut(key)
+    }
+
+    /// Gets a value to modify it, inserting one if there's none. Only the insertion counts as a modification; see
+    /// [`Database::touch`].
+    pub(crate) fn get_or_insert_with(
+        &mut self,
+        key: &BulkString,
+        f: impl FnOnce() -> Object,
+    ) -> &mut Object {
+        self.expire_if_needed(key);
+
+        if !self.entries.contains_key(key) {
+            self.touch(key);
+            self.notify(notify::NEW, "new", key);
+            self.index_key(key);
+        }
+
+        self.entries.get_or_insert_with(key.clone(), f)
+    }
+
+    pub(crate) fn contains_key(&mut self, key: &BulkString) -> bool {
+        self.expire_if_needed(key);
+        self.entries.contains_key(key)
+    }
+
+    /// Stores `object` at `key`, discarding the previous value and its TTL.
+    pub(crate) fn insert(&mut self, key: BulkString, object: Object) -> Option<Object> {
+        self.touch(&key);
+        self.expires.remove(&key);
+
+        if !self.entries.contains_key(&key) {
+            self.notify(notify::NEW, "new", &key);
+            self.index_key(&key);
+        }
+
+        self.entries.insert(key, object)
+    }
+
+    pub(crate) fn remove(&mut self, key: &BulkString) -> Option<Object> {
+        self.expires.remove(key);
+
+        let object = self.entries.remove(key)?;
+
+        self.touch(key);
+        self.unindex_key(key);
+
+        Some(object)
+    }
+
+    /// Removes every key, returning them as a separate database so that the caller can choose where to free them.
+    /// Connections watching keys that existed see them as modified.
+    pub(crate) fn clear(&mut self) -> Database {
+        let mut old = Database::new();
+
+        for (key, watch) in &mut self.watched {
+            if self.entries.contains_key(key) {
+                watch.version += 1;
+            }
+        }
+
+        self.changes += self.entries.len() as u64;
+
+        std::mem::swap(&mut self.entries, &mut old.entries);
+        std::mem::swap(&mut self.expires, &mut old.expires);
+
+        if let Some(slots) = &mut self.slots {
+            slots.clear();
+        }
+
+        old
+    }
+
+    /// Exchanges the keys of two databases. Connections watching keys that exist in either see them as modified,
+    /// because their values may have changed.
+    pub(crate) fn swap_contents(&mut self, other: &mut Database) {
+        let (a, b) = (&self.entries, &other.entries);
+
+        for watched in [&mut self.watched, &mut other.watched] {
+            for (key, watch) in watched {
+                if a.contains_key(key) || b.contains_key(key) {
+                    watch.version += 1;
+                }
+            }
+        }
+
+        self.changes += 1;
+        other.changes += 1;
+
+        std::mem::swap(&mut self.entries, &mut other.entries);
+        std::mem::swap(&mut self.expires, &mut other.expires);
+
+        for database in [self, other] {
+            if database.slots.is_some() {
+                database.index_slots();
+            }
+        }
+    }
+
+    /// Starts watching `key`, returning its current version.
+    pub(crate) fn watch(&mut self, key: &BulkString) -> u64 {
+        // An expired key is removed now, so that the change isn't mistaken for one made after the watch started.
+        self.expire_if_needed(key);
+
+        let watch = self.watched.entry(key.clone()).or_insert(Watch {
+            watchers: 0,
+            version: 0,
+        });
+
+        watch.watchers += 1;
+        watch.version
+    }
+
+    pub(crate) fn unwatch(&mut self, key: &BulkString) {
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.watchers -= 1;
+
+            if watch.watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// The current version of a watched key. Keys that have expired since are removed first, so expiry counts as a
+    /// modification.
+    pub(crate) fn watched_version(&mut self, key: &BulkString) -> Option<u64> {
+        self.expire_if_needed(key);
+        self.watched.get(key).map(|watch| watch.version)
+    }
+
+    pub(crate) fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// The number of keys, including expired keys that haven't been removed yet.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The number of keys in a hash slot, including expired keys that haven't been removed yet. Only known once
+    /// [`Database::index_slots`] has been called.
+    pub(crate) fn count_keys_in_slot(&self, slot: u16) -> usize {
+        self.slots
+            .as_ref()
+            .and_then(|slots| slots.get(&slot))
+            .map_or(0, BTreeSet::len)
+    }
+
+    /// Up to `count` of the keys in a hash slot, in order. Only known once [`Database::index_slots`] has been called.
+    pub(crate) fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<BulkString> {
+        self.slots
+            .as_ref()
+            .and_then(|slots| slots.get(&slot))
+            .map(|keys| keys.iter().take(count).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Iterates over the keys that haven't expired, in no particular order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&BulkString, &Object)> {
+        let now = now_ms();
+
+        self.entries
+            .iter()
+            .filter(move |(key, _)| self.expires.get(*key).is_none_or(|&at| at > now))
+    }
+
+    /// Copies the keys that haven't expired, with their values and expiry times, for a snapshot.
+    pub(crate) fn snapshot(&self) -> Vec<(BulkString, Object, Option<u64>)> {
+        self.iter()
+            .map(|(key, object)| (key.clone(), object.clone(), self.expires.get(key).copied()))
+            .collect()
+    }
+
+    /// Visits the keys in the bucket `cursor` points to that haven't expired, returning the next cursor. See
+    /// [`Dict::scan`].
+    pub(crate) fn scan(&self, cursor: u64, mut f: impl FnMut(&BulkString, &Object)) -> u64 {
+        let now = now_ms();
+
+        self.entries.scan(cursor, |key, object| {
+            if self.expires.get(key).is_none_or(|&at| at > now) {
+                f(key, object);
+            }
+        })
+    }
+
+    /// The Unix time in milliseconds at which `key` expires, if it exists and has a TTL.
+    pub(crate) fn expires_at(&mut self, key: &BulkString) -> Option<u64> {
+        self.expire_if_needed(key);
+        self.expires.get(key).copied()
+    }
+
+    /// Sets the expiry time of `key`, returning whether it exists.
+    pub(crate) fn set_expires_at(&mut self, key: &BulkString, at: u64) -> bool {
+        if !self.contains_key(key) {
+            return false;
+        }
+
+        self.touch(key);
+        self.expires.insert(key.clone(), at);
+
+        true
+    }
+
+    /// Removes the TTL of `key`, returning whether it had one.
+    pub(crate) fn persist(&mut self, key: &BulkString) -> bool {
+        self.expire_if_needed(key);
+
+        if self.expires.remove(key).is_none() {
+            return false;
+        }
+
+        self.touch(key);
+
+        true
+    }
+
+    pub(crate) fn random_key(&mut self) -> Option<BulkString> {
+        // Expired keys are removed as they're drawn, so this terminates once all of them are gone.
+        while !self.is_empty() {
+            let key = self
+                .entries
+                .random_entry(|| next_random(&mut self.random_state))
+                .map(|(key, _)| key.clone())?;
+
+            if !self.is_expired(&key) {
+                return Some(key);
+            }
+
+            self.expire(&key);
+        }
+
+        None
+    }
+}
+
+impl<const N: usize> From<[(BulkString, Object); N]> for Database {
+    fn from(entries: [(BulkString, Object); N]) -> Database {
+        let mut database = Database::new();
+
+        for (key, object) in entries {
+            database.insert(key, object);
+        }
+
+        database
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! key {
+        ($key:expr) => {
+            BulkString::Filled($key.as_bytes().to_vec())
+        };
+    }
+
+    #[test]
+    fn expired_keys_disappear() {
+        let mut database = Database::new();
+
+        database.insert(key!("a"), Object::String(key!("1")));
+        database.insert(key!("b"), Object::String(key!("2")));
+
+        assert!(database.set_expires_at(&key!("a"), now_ms() - 1));
+        assert!(!database.set_expires_at(&key!("c"), 0));
+
+        assert_eq!(1, database.iter().count());
+        assert_eq!(None, database.get(&key!("a")));
+        assert_eq!(1, database.len());
+        assert_eq!(Some(key!("b")), database.random_key());
+    }
+
+    #[test]
+    fn random_key_expires_keys() {
+        let mut database = Database::new();
+
+        database.set_notify_flags(notify::parse_flags("Ex").unwrap());
+        database.insert(key!("a"), Object::String(key!("1")));
+        database.set_expires_at(&key!("a"), now_ms() - 1);
+
+        assert_eq!(None, database.random_key());
+        assert_eq!(vec![("expired", key!("a"))], database.take_events());
+    }
+
+    #[test]
+    fn insert_discards_the_ttl() {
+        let mut database = Database::new();
+
+        database.insert(key!("a"), Object::String(key!("1")));
+        database.set_expires_at(&key!("a"), now_ms() + 10_000);
+        database.insert(key!("a"), Object::String(key!("2")));
+
+        assert_eq!(None, database.expires_at(&key!("a")));
+        assert!(!database.persist(&key!("a")));
+    }
+
+    #[test]
+    fn expire_cycle() {
+        let mut database = Database::new();
+
+        database.set_notify_flags(notify::parse_flags("Exn").unwrap());
+        database.insert(key!("a"), Object::String(key!("1")));
+        database.insert(key!("b"), Object::String(key!("2")));
+        database.set_expires_at(&key!("a"), now_ms() - 1);
+        database.expire_cycle();
+
+        assert_eq!(1, database.len());
+        assert_eq!(
+            vec![
+                ("new", key!("a")),
+                ("new", key!("b")),
+                ("expired", key!("a"))
+            ],
+            database.take_events()
+        );
+        assert_eq!(vec![key!("a")], database.take_expired());
+    }
+
+    #[test]
+    fn expire_cycle_samples_keys() {
+        let mut database = Database::new();
+
+        for i in 0..1000 {
+            let key = key!(format!("key:{i}"));
+
+            database.insert(key.clone(), Object::String(key!("value")));
+            database.set_expires_at(
+                &key,
+                if i < 900 {
+                    now_ms() - 1
+                } else {
+                    now_ms() + 60_000
+                },
+            );
+        }
+
+        database.expire_cycle();
+
+        // A cycle only looks at a bounded number of keys, but others remove the rest.
+        assert!(database.len() >= 1000 - EXPIRE_CYCLE_SAMPLE * EXPIRE_CYCLE_ROUNDS);
+
+        for _ in 0..200 {
+            database.expire_cycle();
+        }
+
+        assert_eq!(100, database.len());
+    }
+
+    #[test]
+    fn slot_index() {
+        let mut database = Database::from([(key!("a"), Object::String(key!("1")))]);
+
+        assert_eq!(0, database.count_keys_in_slot(15495));
+
+        database.index_slots();
+        database.insert(key!("{a}b"), Object::String(key!("2")));
+        database.get_or_insert_with(&key!("b"), || Object::String(key!("3")));
+
+        assert_eq!(2, database.count_keys_in_slot(15495));
+        assert_eq!(vec![key!("a")], database.keys_in_slot(15495, 1));
+        assert_eq!(
+            vec![key!("a"), key!("{a}b")],
+            database.keys_in_slot(15495, 10)
+        );
+
+        database.remove(&key!("a"));
+        database.set_expires_at(&key!("b"), now_ms() - 1);
+        database.expire_cycle();
+
+        assert_eq!(1, database.count_keys_in_slot(15495));
+        assert_eq!(0, database.count_keys_in_slot(3300));
+
+        let old = database.clear();
+
+        assert_eq!(1, old.len());
+        assert_eq!(0, database.count_keys_in_slot(15495));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+const MIN_BUCKETS: usize = 4;
+
+/// A chained hash table whose buckets can be walked with a stable cursor, like Redis's `dict`.
+///
+/// The number of buckets is always a power of two. The table doubles once it holds as many entries as buckets and
+/// halves when fewer than a tenth of them are used.
+#[derive(Debug, Clone)]
+pub(crate) struct Dict<K, V> {
+    buckets: Vec<Vec<(K, V)>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Dict<K, V> {
+        Dict {
+            buckets: (0..MIN_BUCKETS).map(|_| Vec::new()).collect(),
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    pub(crate) fn new() -> Dict<K, V> {
+        Dict::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn mask(&self) -> usize {
+        self.buckets.len() - 1
+    }
+
+    fn bucket_index(&self, key: &K) -> usize {
+        self.hasher.hash_one(key) as usize & self.mask()
+    }
+
+    pub(crate) fn get(&self, key: &K) -> Option<&V> {
+        self.buckets[self.bucket_index(key)]
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let index = self.bucket_index(key);
+
+        self.buckets[index]
+            .iter_mut()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts a value, returning the one it replaced.
+    pub(crate) fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(existing) = self.get_mut(&key) {
+            return Some(std::mem::replace(existing, value));
+        }
+
+        self.push(key, value);
+
+        None
+    }
+
+    pub(crate) fn get_or_insert_with(&mut self, key: K, f: impl FnOnce() -> V) -> &mut V {
+        let index = self.bucket_index(&key);
+
+        match self.buckets[index].iter().position(|(k, _)| *k == key) {
+            Some(position) => &mut self.buckets[index][position].1,
+            None => self.push(key, f()),
+        }
+    }
+
+    /// Adds an entry for a key that isn't in the table yet.
+    fn push(&mut self, key: K, value: V) -> &mut V {
+        if self.len >= self.buckets.len() {
+            self.resize(self.buckets.len() * 2);
+        }
+
+        let index = self.bucket_index(&key);
+
+        self.buckets[index].push((key, value));
+        self.len += 1;
+
+        &mut self.buckets[index].last_mut().unwrap().1
+    }
+
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        let index = self.bucket_index(key);
+        let position = self.buckets[index].iter().position(|(k, _)| k == key)?;
+        let (_, value) = self.buckets[index].swap_remove(position);
+
+        self.len -= 1;
+
+        if self.buckets.len() > MIN_BUCKETS && self.len * 10 < self.buckets.len() {
+            self.resize(self.buckets.len() / 2);
+        }
+
+        Some(value)
+    }
+
+    fn resize(&mut self, size: usize) {
+        let old = std::mem::replace(&mut self.buckets, (0..size).map(|_| Vec::new()).collect());
+
+        for (key, value) in old.into_iter().flatten() {
+            let index = self.bucket_index(&key);
+
+            self.buckets[index].push((key, value));
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.buckets.iter().flatten().map(|(k, v)| (k, v))
+    }
+
+    /// Returns the entry at a random position, using `random` as the source of randomness.
+    pub(crate) fn random_entry(&self, mut random: impl FnMut() -> u64) -> Option<(&K, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // At least one in ten buckets is used, so this doesn't take long.
+        loop {
+            let bucket = &self.buckets[random() as usize & self.mask()];
+
+            if !bucket.is_empty() {
+                let (k, v) = &bucket[random() as usize % bucket.len()];
+
+                return Some((k, v));
+            }
+        }
+    }
+
+    /// Calls `f` with the entries of the bucket `cursor` points to, and returns the cursor of the next bucket, or 0
+    /// once every bucket has been visited.
+    ///
+    /// The cursor's bits are incremented from the most significant end, so that when the table doubles or halves
+    /// between calls, the buckets that were already visited map onto buckets the cursor has already passed. That
+    /// guarantees that every entry present for a whole scan is returned at least once, though some may be returned
+    /// more than once if the table shrinks.
+    pub(crate) fn scan(&self, cursor: u64, mut f: impl FnMut(&K, &V)) -> u64 {
+        let mask = self.mask() as u64;
+
+        for (k, v) in &self.buckets[(cursor & mask) as usize] {
+            f(k, v);
+        }
+
+        // Set the bits above the mask, so that incrementing the reversed cursor carries into the masked bits.
+        let cursor = cursor | !mask;
+
+        cursor.reverse_bits().wrapping_add(1).reverse_bits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn insert_get_and_remove() {
+        let mut dict = Dict::new();
+
+        for i in 0..1000 {
+            assert_eq!(None, dict.insert(i, i * 2));
+        }
+
+        assert_eq!(Some(10), dict.insert(5, 10));
+        assert_eq!(1000, dict.len());
+        assert_eq!(Some(&20), dict.get(&10));
+
+        for i in 0..990 {
+            assert!(dict.remove(&i).is_some());
+        }
+
+        assert_eq!(10, dict.len());
+        assert!(dict.buckets.len() < 128);
+        assert_eq!(None, dict.get(&5));
+    }
+
+    #[test]
+    fn scan_survives_resizing() {
+        let mut dict = Dict::new();
+
+        for i in 0..100 {
+            dict.insert(i, ());
+        }
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        let mut steps = 0;
+
+        loop {
+            cursor = dict.scan(cursor, |k, _| {
+                seen.insert(*k);
+            });
+            steps += 1;
+
+            // Grow the table partway through, then shrink it again.
+            if steps == 10 {
+                for i in 100..1000 {
+                    dict.insert(i, ());
+                }
+            }
+
+            if steps == 200 {
+                for i in 100..1000 {
+                    dict.remove(&i);
+                }
+            }
+
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        assert!((0..100).all(|i| seen.contains(&i)));
+    }
+}
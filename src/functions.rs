@@ -0,0 +1,619 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::array;
+use crate::byte_reader::ByteReader;
+use crate::commands::Response;
+use crate::crc64::crc64;
+use crate::database::now_ms;
+use crate::lua::{self, Builtin, Error, FunctionBody, Host, Interpreter, Table, Value};
+use crate::rdb;
+use crate::scripting;
+use crate::Data;
+
+/// The function may not call write commands, which lets `FCALL_RO` run it.
+pub(crate) const NO_WRITES: u32 = 1 << 0;
+pub(crate) const ALLOW_OOM: u32 = 1 << 1;
+pub(crate) const ALLOW_STALE: u32 = 1 << 2;
+pub(crate) const NO_CLUSTER: u32 = 1 << 3;
+pub(crate) const ALLOW_CROSS_SLOT_KEYS: u32 = 1 << 4;
+
+const FLAGS: &[(&str, u32)] = &[
+    ("no-writes", NO_WRITES),
+    ("allow-oom", ALLOW_OOM),
+    ("allow-stale", ALLOW_STALE),
+    ("no-cluster", NO_CLUSTER),
+    ("allow-cross-slot-keys", ALLOW_CROSS_SLOT_KEYS),
+];
+
+/// How long a library's code can run when it's loaded, in milliseconds. It should only register functions.
+const LOAD_TIMEOUT: u64 = 500;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) flags: u32,
+}
+
+impl Function {
+    pub(crate) fn flag_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        FLAGS
+            .iter()
+            .filter(|(_, flag)| self.flags & flag != 0)
+            .map(|(name, _)| *name)
+    }
+}
+
+/// A library loaded with `FUNCTION LOAD`. Lua values can't be shared between connections, so only the compiled code
+/// is kept, and it's run again to get the functions' callbacks whenever one of them is called.
+#[derive(Clone, Debug)]
+pub(crate) struct Library {
+    pub(crate) name: String,
+    pub(crate) code: Vec<u8>,
+    body: Arc<FunctionBody>,
+    pub(crate) functions: Vec<Function>,
+}
+
+/// What `FUNCTION RESTORE` does with the libraries that already exist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RestorePolicy {
+    /// Deletes them first.
+    Flush,
+    /// Keeps them, failing if a restored library has the same name as one of them.
+    Append,
+    /// Keeps them, except the ones that restored libraries replace.
+    Replace,
+}
+
+/// The loaded libraries, by name.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Libraries {
+    libraries: BTreeMap<String, Library>,
+}
+
+impl Libraries {
+    pub(crate) fn new() -> Libraries {
+        Libraries::default()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Library> {
+        self.libraries.values()
+    }
+
+    /// Finds a function and the library that registered it.
+    pub(crate) fn function(&self, name: &str) -> Option<(&Library, &Function)> {
+        self.libraries.values().find_map(|library| {
+            library
+                .functions
+                .iter()
+                .find(|function| function.name == name)
+                .map(|function| (library, function))
+        })
+    }
+
+    /// Compiles a library and adds it, replacing the library with the same name if `replace` is set. Returns the
+    /// library's name.
+    pub(crate) fn load(&mut self, code: &[u8], replace: bool) -> Result<String, String> {
+        let library = compile(code)?;
+
+        self.add(library, replace)
+    }
+
+    fn add(&mut self, library: Library, replace: bool) -> Result<String, String> {
+        if self.libraries.contains_key(&library.name) && !replace {
+            return Err(format!("Library '{}' already exists", library.name));
+        }
+
+        for function in &library.functions {
+            if let Some((other, _)) = self.function(&function.name) {
+                if other.name != library.name {
+                    return Err(format!("Function {} already exists", function.name));
+                }
+            }
+        }
+
+        let name = library.name.clone();
+
+        self.libraries.insert(name.clone(), library);
+
+        Ok(name)
+    }
+
+    pub(crate) fn delete(&mut self, name: &str) -> bool {
+        self.libraries.remove(name).is_some()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.libraries.clear();
+    }
+
+    /// Serializes every library's code, in the format of Redis's `FUNCTION DUMP`: each library is an opcode followed
+    /// by its code, and the payload ends with an RDB version and a CRC-64 of everything before it.
+    pub(crate) fn dump(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+
+        for library in self.libraries.values() {
+            payload.push(rdb::OPCODE_FUNCTION);
+            rdb::write_string(&mut payload, &library.code);
+        }
+
+        payload.extend(rdb::VERSION.to_le_bytes());
+
+        let checksum = crc64(0, &payload);
+
+        payload.extend(checksum.to_le_bytes());
+
+        payload
+    }
+
+    /// Loads the libraries in a `FUNCTION DUMP` payload. Nothing changes if any of them can't be loaded.
+    pub(crate) fn restore(&mut self, payload: &[u8], policy: RestorePolicy) -> Result<(), String> {
+        let Some(body_length) = payload.len().checked_sub(10) else {
+            return Err("payload version or checksum are wrong".to_string());
+        };
+
+        let (body, footer) = payload.split_at(body_length);
+        let version = u16::from_le_bytes([footer[0], footer[1]]);
+        let checksum = u64::from_le_bytes(footer[2..].try_into().expect("8 bytes"));
+
+        if version > rdb::VERSION || checksum != crc64(0, &payload[..body_length + 2]) {
+            return Err("payload version or checksum are wrong".to_string());
+        }
+
+        let mut restored = Vec::new();
+        let mut reader = ByteReader::new(body);
+
+        while let Some(opcode) = reader.read_byte() {
+            if opcode != rdb::OPCODE_FUNCTION {
+                return Err("given type is not a function".to_string());
+            }
+
+            let code = rdb::read_string(&mut reader).ok_or("payload is truncated")?;
+
+            restored.push(compile(&code)?);
+        }
+
+        let mut libraries = match policy {
+            RestorePolicy::Flush => Libraries::new(),
+            RestorePolicy::Append | RestorePolicy::Replace => self.clone(),
+        };
+
+        for library in restored {
+            libraries.add(library, policy == RestorePolicy::Replace)?;
+        }
+
+        *self = libraries;
+
+        Ok(())
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Compiles a library and runs it to find out which functions it registers. The code must start with a line like
+/// `#!lua name=mylib`.
+fn compile(code: &[u8]) -> Result<Library, String> {
+    let first_line = code.split(|&b| b == b'\n').next().unwrap_or_default();
+    let Some(metadata) = first_line.strip_prefix(b"#!") else {
+        return Err("Missing library metadata".to_string());
+    };
+
+    let metadata = String::from_utf8_lossy(metadata);
+    let mut parts = metadata.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("Engine '{engine}' not found"));
+    }
+
+    let mut name = None;
+
+    for part in parts {
+        match part.split_once('=') {
+            Some(("name", value)) => name = Some(value.to_string()),
+            _ => return Err(format!("Invalid metadata value given: {part}")),
+        }
+    }
+
+    let name = name.ok_or("Library name was not given")?;
+
+    if !is_valid_name(&name) {
+        return Err("Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    }
+
+    // The metadata line isn't Lua, but it's kept as an empty line so that errors have the right line numbers.
+    let body = lua::parse(&code[first_line.len()..]).map_err(|error| {
+        format!(
+            "Error compiling function: user_function:{}: {}",
+            error.line, error.message
+        )
+    })?;
+
+    let deadline = now_ms() + LOAD_TIMEOUT;
+    let mut host = Loading { deadline };
+    let mut interpreter = Interpreter::new("user_function", &mut host);
+    let registry = register(&mut interpreter, body.clone());
+
+    let mut functions = match registry {
+        Ok(registry) => registry
+            .into_iter()
+            .map(|registration| registration.function)
+            .collect::<Vec<_>>(),
+        Err(Error::Interrupted) => return Err("FUNCTION LOAD timeout".to_string()),
+        Err(Error::Raised(value)) => {
+            return Err(format!(
+                "Error registering functions: {}",
+                String::from_utf8_lossy(&value.to_display())
+            ))
+        }
+    };
+
+    if functions.is_empty() {
+        return Err("No functions registered".to_string());
+    }
+
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Library {
+        name,
+        code: code.to_vec(),
+        body,
+        functions,
+    })
+}
+
+/// Runs a library with a host that can't call commands, stopping it if it takes too long.
+struct Loading {
+    deadline: u64,
+}
+
+impl Host for Loading {
+    fn interrupted(&mut self) -> bool {
+        now_ms() > self.deadline
+    }
+}
+
+struct Registration {
+    function: Function,
+    callback: Value,
+}
+
+/// Runs a library's code, collecting the functions it registers with `redis.register_function`. Commands can't be
+/// called while it runs, and functions can't be registered afterwards.
+fn register(
+    interpreter: &mut Interpreter<'_>,
+    body: Arc<FunctionBody>,
+) -> Result<Vec<Registration>, Error> {
+    scripting::open(interpreter);
+    interpreter.strict_globals = true;
+
+    let redis = match interpreter.globals.borrow().get_str("redis") {
+        Value::Table(redis) => redis,
+        _ => unreachable!("scripting::open defines the redis table"),
+    };
+    let call = redis.borrow().get_str("call");
+    let pcall = redis.borrow().get_str("pcall");
+    let registry = Rc::new(RefCell::new(Table::new()));
+
+    redis.borrow_mut().set_str("call", Value::Nil);
+    redis.borrow_mut().set_str("pcall", Value::Nil);
+    redis.borrow_mut().set_str(
+        "register_function",
+        Value::Builtin(Rc::new(Builtin {
+            function: register_function,
+            bound: Some(Value::Table(registry.clone())),
+        })),
+    );
+
+    interpreter.execute(body, Vec::new())?;
+
+    redis.borrow_mut().set_str("call", call);
+    redis.borrow_mut().set_str("pcall", pcall);
+    redis.borrow_mut().set_str(
+        "register_function",
+        Value::builtin(register_function_after_load),
+    );
+
+    let registry = registry.borrow();
+    let mut registrations = Vec::new();
+    let mut key = Value::Nil;
+
+    while let Ok(Some((name, entry))) = registry.next(&key) {
+        let Value::Table(entry) = &entry else {
+            unreachable!("register_function stores tables");
+        };
+        let entry = entry.borrow();
+        let description = entry.get_str("description");
+
+        registrations.push(Registration {
+            function: Function {
+                name: String::from_utf8_lossy(&name.to_display()).into_owned(),
+                description: description
+                    .to_bytes()
+                    .map(|d| String::from_utf8_lossy(&d).into_owned()),
+                flags: entry.get_str("flags").to_number().unwrap_or_default() as u32,
+            },
+            callback: entry.get_str("callback"),
+        });
+        key = name;
+    }
+
+    Ok(registrations)
+}
+
+/// `redis.register_function(name, callback)`, or `redis.register_function{function_name=..., callback=...,
+/// flags=..., description=...}`. The registry the function is bound to maps names to the registrations.
+fn register_function(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let Value::Table(registry) = &arguments[0] else {
+        unreachable!("register_function is bound to its registry");
+    };
+
+    let (name, callback, flags, description) = match &arguments[1..] {
+        [Value::Table(arguments)] => {
+            let arguments = arguments.borrow();
+            let mut named = (Value::Nil, Value::Nil, Value::Nil, Value::Nil);
+            let mut key = Value::Nil;
+
+            while let Ok(Some((name, value))) = arguments.next(&key) {
+                match &*name.to_display() {
+                    b"function_name" => named.0 = value,
+                    b"callback" => named.1 = value,
+                    b"flags" => named.2 = value,
+                    b"description" => named.3 = value,
+                    _ => {
+                        return Err(interpreter
+                            .error("unknown argument given to redis.register_function"))
+                    }
+                }
+
+                key = name;
+            }
+
+            if named.0.is_nil() {
+                return Err(
+                    interpreter.error("redis.register_function must get a function name argument")
+                );
+            }
+
+            if named.1.is_nil() {
+                return Err(
+                    interpreter.error("redis.register_function must get a callback argument")
+                );
+            }
+
+            named
+        }
+        [_] => return Err(interpreter.error(
+            "calling redis.register_function with a single argument is only applicable to Lua table (representing named arguments).",
+        )),
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => {
+            return Err(interpreter.error("wrong number of arguments to redis.register_function"))
+        }
+    };
+
+    let Value::String(name) = name else {
+        return Err(interpreter
+            .error("function_name argument given to redis.register_function must be a string"));
+    };
+
+    if !matches!(callback, Value::Function(_) | Value::Builtin(_)) {
+        return Err(interpreter
+            .error("callback argument given to redis.register_function must be a function"));
+    }
+
+    let flags = match flags {
+        Value::Nil => 0,
+        Value::Table(names) => {
+            let mut flags = 0;
+
+            for i in 1..=names.borrow().length() {
+                let name = names.borrow().get(&Value::Number(i as f64));
+
+                match FLAGS
+                    .iter()
+                    .find(|(flag, _)| name.to_bytes().as_deref() == Some(flag.as_bytes()))
+                {
+                    Some((_, flag)) => flags |= flag,
+                    None => return Err(interpreter.error("unknown flag given")),
+                }
+            }
+
+            flags
+        }
+        _ => return Err(interpreter.error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    };
+
+    if !matches!(description, Value::Nil | Value::String(_)) {
+        return Err(interpreter
+            .error("description argument given to redis.register_function must be a string"));
+    }
+
+    if !is_valid_name(&String::from_utf8_lossy(&name)) {
+        return Err(interpreter.error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+
+    let key = Value::String(name);
+
+    if !registry.borrow().get(&key).is_nil() {
+        return Err(interpreter.error("Function already exists in the library"));
+    }
+
+    let mut entry = Table::new();
+
+    entry.set_str("callback", callback);
+    entry.set_str("flags", Value::Number(flags as f64));
+    entry.set_str("description", description);
+    registry
+        .borrow_mut()
+        .set(key, Value::table(entry))
+        .expect("names are strings");
+
+    Ok(Vec::new())
+}
+
+fn register_function_after_load(
+    interpreter: &mut Interpreter<'_>,
+    _: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    Err(interpreter.error("redis.register_function can only be called on FUNCTION LOAD command"))
+}
+
+/// Runs a function with its keys and arguments, as `FCALL` and `FCALL_RO` (when `read_only` is set) do.
+pub(crate) fn call(
+    data: &mut Data,
+    name: &str,
+    keys: &[array::Value],
+    arguments: &[array::Value],
+    read_only: bool,
+) -> Response {
+    let Some((library, function)) = data.libraries().function(name) else {
+        return Response::Error("Function not found");
+    };
+
+    if function.flags & NO_CLUSTER != 0 && data.cluster().is_enabled() {
+        return Response::Error("Can not run script on cluster, 'no-cluster' flag is set.");
+    }
+
+    let no_writes = function.flags & NO_WRITES != 0;
+
+    if read_only && !no_writes {
+        return Response::Error("Can not execute a script with write flag using *_ro command.");
+    }
+
+    let body = library.body.clone();
+    let origin = format!("script: {name}, on @user_function.");
+
+    let result = scripting::track(data, |data| {
+        let mut host = scripting::Redis::new(data, no_writes);
+        let mut interpreter = Interpreter::new("user_function", &mut host);
+        let registrations = register(&mut interpreter, body)?;
+        let callback = registrations
+            .into_iter()
+            .find(|registration| registration.function.name == name)
+            .map(|registration| registration.callback)
+            .unwrap_or_default();
+
+        interpreter
+            .call(
+                &callback,
+                vec![scripting::strings(keys), scripting::strings(arguments)],
+            )
+            .map(|values| values.into_iter().next().unwrap_or_default())
+    });
+
+    match result {
+        Ok(value) => scripting::to_reply(&value, 0),
+        Err(error) => scripting::error_reply(error, &origin),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &[u8] = b"#!lua name=mylib\n\
+        redis.register_function('echo', function(keys, args) return args[1] end)\n\
+        redis.register_function{function_name='get', callback=function(keys) return redis.call('GET', keys[1]) end, \
+        flags={'no-writes'}, description='Gets a key'}";
+
+    #[test]
+    fn load() {
+        let mut libraries = Libraries::new();
+
+        assert_eq!(Ok("mylib".to_string()), libraries.load(LIBRARY, false));
+        assert_eq!(
+            Err("Library 'mylib' already exists".to_string()),
+            libraries.load(LIBRARY, false)
+        );
+        assert_eq!(Ok("mylib".to_string()), libraries.load(LIBRARY, true));
+
+        let (library, function) = libraries.function("get").unwrap();
+
+        assert_eq!("mylib", library.name);
+        assert_eq!(Some("Gets a key"), function.description.as_deref());
+        assert_eq!(vec!["no-writes"], function.flag_names().collect::<Vec<_>>());
+
+        assert_eq!(
+            Err("Function echo already exists".to_string()),
+            libraries.load(
+                b"#!lua name=other\nredis.register_function('echo', function() end)",
+                false
+            )
+        );
+
+        for (code, error) in [
+            ("return 1", "Missing library metadata"),
+            ("#!js name=x", "Engine 'js' not found"),
+            ("#!lua name=x foo=bar", "Invalid metadata value given: foo=bar"),
+            ("#!lua\n", "Library name was not given"),
+            ("#!lua name=x\nreturn 1", "No functions registered"),
+            (
+                "#!lua name=x\n\nx y",
+                "Error compiling function: user_function:3: '=' expected near 'y'",
+            ),
+            (
+                "#!lua name=x\nredis.call('PING')",
+                "Error registering functions: user_function:2: attempt to call a nil value (field 'call')",
+            ),
+            (
+                "#!lua name=x\nredis.register_function('a', function() end, 1)",
+                "Error registering functions: user_function:2: wrong number of arguments to redis.register_function",
+            ),
+            (
+                "#!lua name=x\nredis.register_function{function_name='a', callback=function() end, flags={'nope'}}",
+                "Error registering functions: user_function:2: unknown flag given",
+            ),
+            (
+                "#!lua name=x\nredis.register_function('a b', function() end)",
+                "Error registering functions: user_function:2: Function names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+            ),
+            ("#!lua name=x\nwhile true do end", "FUNCTION LOAD timeout"),
+        ] {
+            assert_eq!(Err(error.to_string()), compile(code.as_bytes()).map(|_| ()));
+        }
+    }
+
+    #[test]
+    fn dump_and_restore() {
+        let mut libraries = Libraries::new();
+
+        libraries.load(LIBRARY, false).unwrap();
+
+        let payload = libraries.dump();
+        let mut restored = Libraries::new();
+
+        assert_eq!(Ok(()), restored.restore(&payload, RestorePolicy::Append));
+        assert!(restored.function("echo").is_some());
+        assert_eq!(
+            Err("Library 'mylib' already exists".to_string()),
+            restored.restore(&payload, RestorePolicy::Append)
+        );
+        assert_eq!(Ok(()), restored.restore(&payload, RestorePolicy::Replace));
+
+        let mut corrupted = payload.clone();
+
+        corrupted[3] ^= 1;
+
+        assert_eq!(
+            Err("payload version or checksum are wrong".to_string()),
+            restored.restore(&corrupted, RestorePolicy::Flush)
+        );
+        assert!(restored.iter().next().is_some());
+        assert_eq!(
+            Ok(()),
+            restored.restore(&Libraries::new().dump(), RestorePolicy::Flush)
+        );
+        assert!(restored.iter().next().is_none());
+    }
+}
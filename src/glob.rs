@@ -0,0 +1,153 @@
+/// Matches `string` against a glob-style pattern with Redis's syntax: `*`, `?`, character classes like `[a-z]` and
+/// `[^x]`, and `\` to escape the next character.
+pub(crate) fn matches(pattern: &[u8], string: &[u8], ignore_case: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if ignore_case {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let mut p = 0;
+    let mut s = 0;
+    // Where to resume after the most recent `*` if the rest of the pattern doesn't match.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                while pattern.get(p) == Some(&b'*') {
+                    p += 1;
+                }
+
+                if p == pattern.len() {
+                    return true;
+                }
+
+                backtrack = Some((p, s));
+
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                true
+            }
+            Some(b'[') => match class(&pattern[p + 1..], string[s], eq) {
+                Some((matched, length)) => {
+                    p += 1 + length;
+                    matched
+                }
+                // An unterminated class matches like a literal `[`.
+                None => {
+                    p += 1;
+                    string[s] == b'['
+                }
+            },
+            Some(b'\\') if p + 1 < pattern.len() => {
+                p += 2;
+                eq(pattern[p - 1], string[s])
+            }
+            Some(&c) => {
+                p += 1;
+                eq(c, string[s])
+            }
+            None => false,
+        };
+
+        if matched {
+            s += 1;
+        } else if let Some((star_p, star_s)) = backtrack {
+            // Let the last `*` swallow one more character and try again.
+            p = star_p;
+            s = star_s + 1;
+            backtrack = Some((star_p, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the character class at the start of `pattern`, which is just past the opening `[`. Returns
+/// whether it matched and how many bytes of the pattern the class took up, including the closing `]`.
+fn class(pattern: &[u8], c: u8, eq: impl Fn(u8, u8) -> bool) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = pattern.first() == Some(&b'^');
+
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+
+    loop {
+        match *pattern.get(i)? {
+            b']' => break,
+            b'\\' if i + 1 < pattern.len() => {
+                matched |= eq(pattern[i + 1], c);
+                i += 2;
+            }
+            start
+                if pattern.get(i + 1) == Some(&b'-')
+                    && pattern.get(i + 2).is_some_and(|&e| e != b']') =>
+            {
+                let end = pattern[i + 2];
+                let (low, high) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+
+                matched |= (low..=high).any(|x| eq(x, c));
+                i += 3;
+            }
+            other => {
+                matched |= eq(other, c);
+                i += 1;
+            }
+        }
+    }
+
+    Some((matched != negated, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(pattern: &str, string: &str) -> bool {
+        matches(pattern.as_bytes(), string.as_bytes(), false)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(m("*", ""));
+        assert!(m("h?llo", "hello"));
+        assert!(m("h*llo", "heeeello"));
+        assert!(m("*:*:end", "a:b:c:end"));
+        assert!(!m("h*llo", "hellx"));
+        assert!(!m("h?llo", "hllo"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(m("h[ae]llo", "hallo"));
+        assert!(!m("h[ae]llo", "hillo"));
+        assert!(m("h[^e]llo", "hallo"));
+        assert!(!m("h[^e]llo", "hello"));
+        assert!(m("h[a-b]llo", "hbllo"));
+        assert!(m("h[z-a]llo", "hqllo"));
+        assert!(m("[", "["));
+    }
+
+    #[test]
+    fn escapes_and_case() {
+        assert!(m("h\\*llo", "h*llo"));
+        assert!(!m("h\\*llo", "hello"));
+        assert!(m("[\\]]", "]"));
+        assert!(matches(b"HELLO", b"hello", true));
+        assert!(!m("HELLO", "hello"));
+    }
+}
@@ -0,0 +1,267 @@
+/// A chunk of time series samples compressed the way Facebook's Gorilla paper describes: timestamps are stored as
+/// deltas of deltas and values as the XOR with their predecessor, both using variable-length bit fields.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct CompressedChunk {
+    bytes: Vec<u8>,
+    bit_len: usize,
+    count: usize,
+    last_timestamp: u64,
+    last_delta: i64,
+    last_value: u64,
+    last_leading: u32,
+    last_trailing: u32,
+}
+
+/// `(bits used for the tag, tag, bits used for the value)` for each delta-of-delta range, smallest first.
+const DELTA_BUCKETS: [(u32, u64, u32); 4] = [
+    (2, 0b10, 7),
+    (3, 0b110, 9),
+    (4, 0b1110, 12),
+    (4, 0b1111, 64),
+];
+
+impl CompressedChunk {
+    pub(crate) fn new() -> CompressedChunk {
+        CompressedChunk::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.count
+    }
+
+    /// The size of the compressed data, in bytes.
+    pub(crate) fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn write_bits(&mut self, value: u64, count: u32) {
+        for i in (0..count).rev() {
+            if self.bit_len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+
+            if (value >> i) & 1 == 1 {
+                self.bytes[self.bit_len / 8] |= 0x80 >> (self.bit_len % 8);
+            }
+
+            self.bit_len += 1;
+        }
+    }
+
+    /// Appends a sample. Timestamps must be increasing.
+    pub(crate) fn push(&mut self, timestamp: u64, value: f64) {
+        let value = value.to_bits();
+
+        if self.count == 0 {
+            self.write_bits(timestamp, 64);
+            self.write_bits(value, 64);
+
+            self.last_timestamp = timestamp;
+            self.last_value = value;
+            self.last_leading = u32::MAX;
+            self.count = 1;
+
+            return;
+        }
+
+        let delta = timestamp.wrapping_sub(self.last_timestamp) as i64;
+        let delta_of_delta = delta.wrapping_sub(self.last_delta);
+
+        if delta_of_delta == 0 {
+            self.write_bits(0, 1);
+        } else {
+            let (tag_bits, tag, value_bits) = *DELTA_BUCKETS
+                .iter()
+                .find(|(_, _, bits)| {
+                    *bits == 64 || (-(1 << (bits - 1))..(1 << (bits - 1))).contains(&delta_of_delta)
+                })
+                .unwrap();
+
+            self.write_bits(tag, tag_bits);
+            self.write_bits(delta_of_delta as u64, value_bits);
+        }
+
+        let xor = value ^ self.last_value;
+
+        if xor == 0 {
+            self.write_bits(0, 1);
+        } else {
+            let leading = xor.leading_zeros().min(31);
+            let trailing = xor.trailing_zeros();
+
+            if self.last_leading != u32::MAX
+                && leading >= self.last_leading
+                && trailing >= self.last_trailing
+            {
+                let meaningful = 64 - self.last_leading - self.last_trailing;
+
+                self.write_bits(0b10, 2);
+                self.write_bits(xor >> self.last_trailing, meaningful);
+            } else {
+                let meaningful = 64 - leading - trailing;
+
+                self.write_bits(0b11, 2);
+                self.write_bits(leading as u64, 5);
+                // A block of 64 meaningful bits is stored as 0; it can't otherwise occur.
+                self.write_bits((meaningful % 64) as u64, 6);
+                self.write_bits(xor >> trailing, meaningful);
+
+                self.last_leading = leading;
+                self.last_trailing = trailing;
+            }
+        }
+
+        self.last_delta = delta;
+        self.last_timestamp = timestamp;
+        self.last_value = value;
+        self.count += 1;
+    }
+
+    pub(crate) fn iter(&self) -> CompressedChunkIter<'_> {
+        CompressedChunkIter {
+            chunk: self,
+            position: 0,
+            index: 0,
+            timestamp: 0,
+            delta: 0,
+            value: 0,
+            leading: 0,
+            trailing: 0,
+        }
+    }
+}
+
+pub(crate) struct CompressedChunkIter<'a> {
+    chunk: &'a CompressedChunk,
+    position: usize,
+    index: usize,
+    timestamp: u64,
+    delta: i64,
+    value: u64,
+    leading: u32,
+    trailing: u32,
+}
+
+impl CompressedChunkIter<'_> {
+    fn read_bits(&mut self, count: u32) -> u64 {
+        let mut value = 0;
+
+        for _ in 0..count {
+            let bit = (self.chunk.bytes[self.position / 8] >> (7 - self.position % 8)) & 1;
+
+            value = (value << 1) | bit as u64;
+            self.position += 1;
+        }
+
+        value
+    }
+
+    fn read_bit(&mut self) -> bool {
+        self.read_bits(1) == 1
+    }
+}
+
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    if bits == 64 {
+        return value as i64;
+    }
+
+    let shift = 64 - bits;
+
+    ((value << shift) as i64) >> shift
+}
+
+impl Iterator for CompressedChunkIter<'_> {
+    type Item = (u64, f64);
+
+    fn next(&mut self) -> Option<(u64, f64)> {
+        if self.index >= self.chunk.count {
+            return None;
+        }
+
+        if self.index == 0 {
+            self.timestamp = self.read_bits(64);
+            self.value = self.read_bits(64);
+        } else {
+            // The tag is a run of up to four ones, terminated by a zero unless it's the longest one.
+            let mut ones = 0;
+
+            while ones < DELTA_BUCKETS.len() && self.read_bit() {
+                ones += 1;
+            }
+
+            let delta_of_delta = match ones {
+                0 => 0,
+                _ => {
+                    let (_, _, bits) = DELTA_BUCKETS[ones - 1];
+
+                    sign_extend(self.read_bits(bits), bits)
+                }
+            };
+
+            self.delta = self.delta.wrapping_add(delta_of_delta);
+            self.timestamp = self.timestamp.wrapping_add(self.delta as u64);
+
+            if self.read_bit() {
+                if self.read_bit() {
+                    self.leading = self.read_bits(5) as u32;
+
+                    let meaningful = match self.read_bits(6) as u32 {
+                        0 => 64,
+                        meaningful => meaningful,
+                    };
+
+                    self.trailing = 64 - self.leading - meaningful;
+                }
+
+                let meaningful = 64 - self.leading - self.trailing;
+
+                self.value ^= self.read_bits(meaningful) << self.trailing;
+            }
+        }
+
+        self.index += 1;
+
+        Some((self.timestamp, f64::from_bits(self.value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let samples = [
+            (1000, 1.0),
+            (2000, 1.0),
+            (3000, 1.5),
+            (4001, -2.25),
+            (4002, 1e300),
+            (90000, 0.0),
+            (1 << 40, f64::MIN_POSITIVE),
+            ((1 << 40) + 1, 12345.678),
+        ];
+
+        let mut chunk = CompressedChunk::new();
+
+        for (timestamp, value) in samples {
+            chunk.push(timestamp, value);
+        }
+
+        assert_eq!(samples.to_vec(), chunk.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn regular_series_compress_well() {
+        let mut chunk = CompressedChunk::new();
+
+        for i in 0..1000 {
+            chunk.push(1_700_000_000_000 + i * 1000, 42.0);
+        }
+
+        // Two bits per sample after the first one.
+        assert!(chunk.size() < 300);
+        assert_eq!(1000, chunk.iter().count());
+    }
+}
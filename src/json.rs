@@ -0,0 +1,429 @@
+use std::fmt::Write;
+use std::str;
+
+use crate::byte_reader::ByteReader;
+
+/// Documents nested deeper than this are rejected rather than risking a stack overflow while parsing.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members are kept in insertion order.
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct JsonParseError;
+
+impl Json {
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "boolean",
+            Json::Integer(_) => "integer",
+            Json::Float(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    pub(crate) fn member(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn member_mut(&mut self, name: &str) -> Option<&mut Json> {
+        match self {
+            Json::Object(members) => members.iter_mut().find(|(k, _)| k == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Serializes the value compactly, e.g. `{"a":[1,2]}`.
+    pub(crate) fn serialize(&self) -> String {
+        self.serialize_with(&Format::default())
+    }
+
+    pub(crate) fn serialize_with(&self, format: &Format) -> String {
+        let mut out = String::new();
+
+        self.write(&mut out, format, 0);
+
+        out
+    }
+
+    fn write(&self, out: &mut String, format: &Format, depth: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Integer(i) => write!(out, "{i}").unwrap(),
+            // The debug representation always includes a fractional part or exponent, so floats survive a round trip
+            // without turning into integers.
+            Json::Float(f) => write!(out, "{f:?}").unwrap(),
+            Json::String(s) => write_string(out, s),
+            Json::Array(items) => {
+                if items.is_empty() {
+                    out.push_str("[]");
+
+                    return;
+                }
+
+                out.push('[');
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+
+                    format.break_line(out, depth + 1);
+                    item.write(out, format, depth + 1);
+                }
+
+                format.break_line(out, depth);
+                out.push(']');
+            }
+            Json::Object(members) => {
+                if members.is_empty() {
+                    out.push_str("{}");
+
+                    return;
+                }
+
+                out.push('{');
+
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+
+                    format.break_line(out, depth + 1);
+                    write_string(out, name);
+                    out.push(':');
+                    out.push_str(&format.space);
+                    value.write(out, format, depth + 1);
+                }
+
+                format.break_line(out, depth);
+                out.push('}');
+            }
+        }
+    }
+}
+
+/// Whitespace options for `JSON.GET`'s `INDENT`, `NEWLINE` and `SPACE` arguments.
+#[derive(Default)]
+pub(crate) struct Format {
+    pub(crate) indent: String,
+    pub(crate) newline: String,
+    pub(crate) space: String,
+}
+
+impl Format {
+    fn break_line(&self, out: &mut String, depth: usize) {
+        out.push_str(&self.newline);
+
+        for _ in 0..depth {
+            out.push_str(&self.indent);
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+pub(crate) fn parse(data: &[u8]) -> Result<Json, JsonParseError> {
+    let mut reader = ByteReader::new(data);
+
+    let value = parse_value(&mut reader, 0)?;
+
+    skip_whitespace(&mut reader);
+
+    if reader.bytes_remaining() != 0 {
+        return Err(JsonParseError);
+    }
+
+    Ok(value)
+}
+
+fn skip_whitespace(reader: &mut ByteReader) {
+    reader.read_while(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'));
+}
+
+fn expect_literal(reader: &mut ByteReader, literal: &[u8]) -> Result<(), JsonParseError> {
+    if reader.bytes_remaining() < literal.len() || reader.slice(literal.len()) != literal {
+        return Err(JsonParseError);
+    }
+
+    Ok(())
+}
+
+fn parse_value(reader: &mut ByteReader, depth: usize) -> Result<Json, JsonParseError> {
+    if depth > MAX_DEPTH {
+        return Err(JsonParseError);
+    }
+
+    skip_whitespace(reader);
+
+    match reader.peek_byte() {
+        Some(b'n') => expect_literal(reader, b"null").map(|_| Json::Null),
+        Some(b't') => expect_literal(reader, b"true").map(|_| Json::Bool(true)),
+        Some(b'f') => expect_literal(reader, b"false").map(|_| Json::Bool(false)),
+        Some(b'"') => parse_string(reader).map(Json::String),
+        Some(b'[') => {
+            reader.read_byte();
+
+            let mut items = Vec::new();
+
+            skip_whitespace(reader);
+
+            if reader.peek_byte() == Some(b']') {
+                reader.read_byte();
+
+                return Ok(Json::Array(items));
+            }
+
+            loop {
+                items.push(parse_value(reader, depth + 1)?);
+
+                skip_whitespace(reader);
+
+                match reader.read_byte() {
+                    Some(b',') => continue,
+                    Some(b']') => return Ok(Json::Array(items)),
+                    _ => return Err(JsonParseError),
+                }
+            }
+        }
+        Some(b'{') => {
+            reader.read_byte();
+
+            let mut members: Vec<(String, Json)> = Vec::new();
+
+            skip_whitespace(reader);
+
+            if reader.peek_byte() == Some(b'}') {
+                reader.read_byte();
+
+                return Ok(Json::Object(members));
+            }
+
+            loop {
+                skip_whitespace(reader);
+
+                let name = parse_string(reader)?;
+
+                skip_whitespace(reader);
+
+                if reader.read_byte() != Some(b':') {
+                    return Err(JsonParseError);
+                }
+
+                let value = parse_value(reader, depth + 1)?;
+
+                // Like most parsers, the last occurrence of a duplicate name wins.
+                match members.iter_mut().find(|(k, _)| *k == name) {
+                    Some((_, existing)) => *existing = value,
+                    None => members.push((name, value)),
+                }
+
+                skip_whitespace(reader);
+
+                match reader.read_byte() {
+                    Some(b',') => continue,
+                    Some(b'}') => return Ok(Json::Object(members)),
+                    _ => return Err(JsonParseError),
+                }
+            }
+        }
+        Some(b'-' | b'0'..=b'9') => parse_number(reader),
+        _ => Err(JsonParseError),
+    }
+}
+
+fn parse_number(reader: &mut ByteReader) -> Result<Json, JsonParseError> {
+    let mut is_float = false;
+
+    let bytes = reader.read_while(|b| match b {
+        b'0'..=b'9' | b'-' | b'+' => true,
+        b'.' | b'e' | b'E' => {
+            is_float = true;
+
+            true
+        }
+        _ => false,
+    });
+
+    let text = str::from_utf8(bytes).map_err(|_| JsonParseError)?;
+
+    // Rust's parsers are more lenient than the JSON grammar (e.g. they accept a leading `+`), so check the shape first.
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let integer_part = digits.split(['.', 'e', 'E']).next().unwrap_or("");
+
+    if integer_part.is_empty()
+        || !integer_part.bytes().all(|b| b.is_ascii_digit())
+        || (integer_part.len() > 1 && integer_part.starts_with('0'))
+        || digits.ends_with(['.', 'e', 'E', '+', '-'])
+    {
+        return Err(JsonParseError);
+    }
+
+    if !is_float {
+        if let Ok(i) = text.parse() {
+            return Ok(Json::Integer(i));
+        }
+    }
+
+    match text.parse::<f64>() {
+        Ok(f) if f.is_finite() => Ok(Json::Float(f)),
+        _ => Err(JsonParseError),
+    }
+}
+
+fn parse_hex_escape(reader: &mut ByteReader) -> Result<u32, JsonParseError> {
+    if reader.bytes_remaining() < 4 {
+        return Err(JsonParseError);
+    }
+
+    let hex = str::from_utf8(reader.slice(4)).map_err(|_| JsonParseError)?;
+
+    u32::from_str_radix(hex, 16).map_err(|_| JsonParseError)
+}
+
+fn parse_string(reader: &mut ByteReader) -> Result<String, JsonParseError> {
+    if reader.read_byte() != Some(b'"') {
+        return Err(JsonParseError);
+    }
+
+    let mut bytes = Vec::new();
+
+    loop {
+        match reader.read_byte() {
+            None => return Err(JsonParseError),
+            Some(b'"') => break,
+            Some(b'\\') => {
+                let c = match reader.read_byte() {
+                    Some(b'"') => '"',
+                    Some(b'\\') => '\\',
+                    Some(b'/') => '/',
+                    Some(b'b') => '\u{8}',
+                    Some(b'f') => '\u{c}',
+                    Some(b'n') => '\n',
+                    Some(b'r') => '\r',
+                    Some(b't') => '\t',
+                    Some(b'u') => {
+                        let mut code = parse_hex_escape(reader)?;
+
+                        if (0xd800..0xdc00).contains(&code) {
+                            expect_literal(reader, b"\\u")?;
+
+                            let low = parse_hex_escape(reader)?;
+
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(JsonParseError);
+                            }
+
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+
+                        char::from_u32(code).ok_or(JsonParseError)?
+                    }
+                    _ => return Err(JsonParseError),
+                };
+
+                let mut buf = [0; 4];
+
+                bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            }
+            Some(b) if b < 0x20 => return Err(JsonParseError),
+            Some(b) => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| JsonParseError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"a":[1,2.5,-3,true,false,null],"b":{"c":"d\"e\n"},"f":{}}"#;
+
+        assert_eq!(text, parse(text.as_bytes()).unwrap().serialize());
+    }
+
+    #[test]
+    fn floats_keep_their_fractional_part() {
+        assert_eq!("3.0", parse(b"3.0").unwrap().serialize());
+        assert_eq!(Json::Integer(3), parse(b"3").unwrap());
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(
+            Json::String("é😀".to_string()),
+            parse(br#""\u00e9\ud83d\ude00""#).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for text in [
+            "",
+            "{",
+            "[1,]",
+            "01",
+            "+1",
+            "1.",
+            "\"abc",
+            "{\"a\" 1}",
+            "nul",
+            "[] []",
+        ] {
+            assert_eq!(Err(JsonParseError), parse(text.as_bytes()), "{text}");
+        }
+    }
+
+    #[test]
+    fn pretty_printing() {
+        let format = Format {
+            indent: "  ".to_string(),
+            newline: "\n".to_string(),
+            space: " ".to_string(),
+        };
+
+        assert_eq!(
+            "{\n  \"a\": [\n    1\n  ],\n  \"b\": {}\n}",
+            parse(br#"{"a":[1],"b":{}}"#)
+                .unwrap()
+                .serialize_with(&format)
+        );
+    }
+}
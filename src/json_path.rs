@@ -0,0 +1,447 @@
+use crate::json::Json;
+
+/// One step of a concrete location inside a document.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Step {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct JsonPathError;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Selector {
+    Name(String),
+    Wildcard,
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, i64),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Segment {
+    Child(Vec<Selector>),
+    Descendant(Vec<Selector>),
+}
+
+/// A parsed path. Paths starting with `$` follow JSONPath semantics and can match any number of values; anything else
+/// is a legacy path (like `.a.b` or `a[0]`), which refers to at most one value.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct JsonPath {
+    segments: Vec<Segment>,
+    legacy: bool,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, c: char) -> bool {
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn name(&mut self) -> Result<String, JsonPathError> {
+        let mut name = String::new();
+
+        while let Some(&c) = self.chars.peek() {
+            if c == '.' || c == '[' {
+                break;
+            }
+
+            name.push(c);
+            self.chars.next();
+        }
+
+        if name.is_empty() {
+            return Err(JsonPathError);
+        }
+
+        Ok(name)
+    }
+
+    fn dot_selector(&mut self) -> Result<Vec<Selector>, JsonPathError> {
+        if self.eat('*') {
+            Ok(vec![Selector::Wildcard])
+        } else if self.eat('[') {
+            self.bracket_selectors()
+        } else {
+            Ok(vec![Selector::Name(self.name()?)])
+        }
+    }
+
+    fn quoted(&mut self, quote: char) -> Result<String, JsonPathError> {
+        let mut s = String::new();
+
+        loop {
+            match self.chars.next() {
+                None => return Err(JsonPathError),
+                Some('\\') => match self.chars.next() {
+                    Some(c) => s.push(c),
+                    None => return Err(JsonPathError),
+                },
+                Some(c) if c == quote => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn integer(&mut self) -> Result<Option<i64>, JsonPathError> {
+        let mut text = String::new();
+
+        while let Some(&c) = self.chars.peek() {
+            if !(c.is_ascii_digit() || c == '-') {
+                break;
+            }
+
+            text.push(c);
+            self.chars.next();
+        }
+
+        if text.is_empty() {
+            return Ok(None);
+        }
+
+        text.parse().map(Some).map_err(|_| JsonPathError)
+    }
+
+    /// Parses the selectors of a bracketed segment; the opening bracket has been consumed already.
+    fn bracket_selectors(&mut self) -> Result<Vec<Selector>, JsonPathError> {
+        let mut selectors = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            let selector = match self.chars.peek() {
+                Some(&quote @ ('\'' | '"')) => {
+                    self.chars.next();
+
+                    Selector::Name(self.quoted(quote)?)
+                }
+                Some('*') => {
+                    self.chars.next();
+
+                    Selector::Wildcard
+                }
+                _ => {
+                    let start = self.integer()?;
+
+                    self.skip_whitespace();
+
+                    if self.eat(':') {
+                        self.skip_whitespace();
+
+                        let end = self.integer()?;
+
+                        self.skip_whitespace();
+
+                        let step = if self.eat(':') {
+                            self.skip_whitespace();
+                            self.integer()?.unwrap_or(1)
+                        } else {
+                            1
+                        };
+
+                        if step == 0 {
+                            return Err(JsonPathError);
+                        }
+
+                        Selector::Slice(start, end, step)
+                    } else {
+                        Selector::Index(start.ok_or(JsonPathError)?)
+                    }
+                }
+            };
+
+            selectors.push(selector);
+
+            self.skip_whitespace();
+
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(selectors),
+                _ => return Err(JsonPathError),
+            }
+        }
+    }
+}
+
+impl JsonPath {
+    pub(crate) fn parse(text: &str) -> Result<JsonPath, JsonPathError> {
+        let text = text.trim();
+
+        let (rest, legacy) = match text.strip_prefix('$') {
+            Some(rest) => (rest.to_string(), false),
+            None if text == "." => (String::new(), true),
+            None if text.starts_with('.') || text.starts_with('[') => (text.to_string(), true),
+            None => (format!(".{text}"), true),
+        };
+
+        let mut parser = Parser {
+            chars: rest.chars().peekable(),
+        };
+
+        let mut segments = Vec::new();
+
+        while parser.chars.peek().is_some() {
+            let segment = if parser.eat('.') {
+                if parser.eat('.') {
+                    Segment::Descendant(parser.dot_selector()?)
+                } else {
+                    Segment::Child(parser.dot_selector()?)
+                }
+            } else if parser.eat('[') {
+                Segment::Child(parser.bracket_selectors()?)
+            } else {
+                return Err(JsonPathError);
+            };
+
+            segments.push(segment);
+        }
+
+        Ok(JsonPath { segments, legacy })
+    }
+
+    pub(crate) fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub(crate) fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Returns the locations of every value in `root` that the path matches, in document order.
+    pub(crate) fn evaluate(&self, root: &Json) -> Vec<Vec<Step>> {
+        let mut current: Vec<(Vec<Step>, &Json)> = vec![(Vec::new(), root)];
+
+        for segment in &self.segments {
+            let mut next = Vec::new();
+
+            for (path, node) in current {
+                match segment {
+                    Segment::Child(selectors) => select(selectors, path, node, &mut next),
+                    Segment::Descendant(selectors) => {
+                        let mut descendants = Vec::new();
+
+                        collect_descendants(path, node, &mut descendants);
+
+                        for (path, node) in descendants {
+                            select(selectors, path, node, &mut next);
+                        }
+                    }
+                }
+            }
+
+            current = next;
+        }
+
+        let paths = current.into_iter().map(|(path, _)| path);
+
+        if self.legacy {
+            paths.take(1).collect()
+        } else {
+            paths.collect()
+        }
+    }
+
+    /// If the path ends in a plain member name (like `$.a.b`), splits it into the path of the parent and that name.
+    /// Used to create new members, which can't be matched before they exist.
+    pub(crate) fn split_last_name(&self) -> Option<(JsonPath, &str)> {
+        match self.segments.last() {
+            Some(Segment::Child(selectors)) => match selectors.as_slice() {
+                [Selector::Name(name)] => Some((
+                    JsonPath {
+                        segments: self.segments[..self.segments.len() - 1].to_vec(),
+                        legacy: self.legacy,
+                    },
+                    name,
+                )),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+fn collect_descendants<'a>(path: Vec<Step>, node: &'a Json, out: &mut Vec<(Vec<Step>, &'a Json)>) {
+    out.push((path.clone(), node));
+
+    match node {
+        Json::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let mut child = path.clone();
+
+                child.push(Step::Index(i));
+                collect_descendants(child, item, out);
+            }
+        }
+        Json::Object(members) => {
+            for (name, value) in members {
+                let mut child = path.clone();
+
+                child.push(Step::Key(name.clone()));
+                collect_descendants(child, value, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+fn select<'a>(
+    selectors: &[Selector],
+    path: Vec<Step>,
+    node: &'a Json,
+    out: &mut Vec<(Vec<Step>, &'a Json)>,
+) {
+    let mut push = |step: Step, value: &'a Json| {
+        let mut child = path.clone();
+
+        child.push(step);
+        out.push((child, value));
+    };
+
+    for selector in selectors {
+        match (selector, node) {
+            (Selector::Name(name), Json::Object(members)) => {
+                if let Some((name, value)) = members.iter().find(|(k, _)| k == name) {
+                    push(Step::Key(name.clone()), value);
+                }
+            }
+            (Selector::Wildcard, Json::Object(members)) => {
+                for (name, value) in members {
+                    push(Step::Key(name.clone()), value);
+                }
+            }
+            (Selector::Wildcard, Json::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    push(Step::Index(i), item);
+                }
+            }
+            (Selector::Index(index), Json::Array(items)) => {
+                if let Some(i) = normalize_index(*index, items.len()) {
+                    push(Step::Index(i), &items[i]);
+                }
+            }
+            (Selector::Slice(start, end, step), Json::Array(items)) => {
+                let len = items.len() as i64;
+                let clamp = |i: i64| if i < 0 { (len + i).max(0) } else { i.min(len) };
+
+                if *step > 0 {
+                    let mut i = clamp(start.unwrap_or(0));
+                    let end = clamp(end.unwrap_or(len));
+
+                    while i < end {
+                        push(Step::Index(i as usize), &items[i as usize]);
+                        i += step;
+                    }
+                } else {
+                    let mut i = start.map_or(len - 1, |s| clamp(s).min(len - 1));
+                    let end = end.map_or(-1, clamp);
+
+                    while i > end {
+                        push(Step::Index(i as usize), &items[i as usize]);
+                        i += step;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn get<'a>(root: &'a Json, path: &[Step]) -> Option<&'a Json> {
+    path.iter().try_fold(root, |node, step| match (step, node) {
+        (Step::Key(name), _) => node.member(name),
+        (Step::Index(i), Json::Array(items)) => items.get(*i),
+        _ => None,
+    })
+}
+
+pub(crate) fn get_mut<'a>(root: &'a mut Json, path: &[Step]) -> Option<&'a mut Json> {
+    path.iter().try_fold(root, |node, step| match step {
+        Step::Key(name) => node.member_mut(name),
+        Step::Index(i) => match node {
+            Json::Array(items) => items.get_mut(*i),
+            _ => None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse;
+
+    fn matches(path: &str, document: &str) -> Vec<String> {
+        let document = parse(document.as_bytes()).unwrap();
+        let path = JsonPath::parse(path).unwrap();
+
+        path.evaluate(&document)
+            .iter()
+            .map(|p| get(&document, p).unwrap().serialize())
+            .collect()
+    }
+
+    #[test]
+    fn members_and_indices() {
+        let document = r#"{"a":{"b":[1,2,3]}}"#;
+
+        assert_eq!(vec![r#"{"a":{"b":[1,2,3]}}"#], matches("$", document));
+        assert_eq!(vec!["[1,2,3]"], matches("$.a.b", document));
+        assert_eq!(vec!["3"], matches("$.a.b[-1]", document));
+        assert_eq!(vec!["1", "3"], matches("$['a'][\"b\"][0,2]", document));
+        assert!(matches("$.a.c", document).is_empty());
+    }
+
+    #[test]
+    fn wildcards_and_slices() {
+        let document = r#"{"a":[1,2,3,4],"b":{"c":5}}"#;
+
+        assert_eq!(vec!["[1,2,3,4]", r#"{"c":5}"#], matches("$.*", document));
+        assert_eq!(vec!["2", "3"], matches("$.a[1:3]", document));
+        assert_eq!(vec!["4", "3", "2", "1"], matches("$.a[::-1]", document));
+    }
+
+    #[test]
+    fn recursive_descent() {
+        let document = r#"{"a":{"x":1},"b":[{"x":2},{"y":{"x":3}}]}"#;
+
+        assert_eq!(vec!["1", "2", "3"], matches("$..x", document));
+    }
+
+    #[test]
+    fn legacy_paths() {
+        let document = r#"{"a":{"b":[1,2]}}"#;
+
+        assert_eq!(vec![r#"{"a":{"b":[1,2]}}"#], matches(".", document));
+        assert_eq!(vec!["[1,2]"], matches(".a.b", document));
+        assert_eq!(vec!["2"], matches("a.b[1]", document));
+        assert!(JsonPath::parse("a.b").unwrap().is_legacy());
+        assert!(!JsonPath::parse("$.a").unwrap().is_legacy());
+    }
+
+    #[test]
+    fn invalid_paths() {
+        for path in ["$.", "$[", "$[abc]", "$..", "$a", "$[1:2:0]"] {
+            assert_eq!(Err(JsonPathError), JsonPath::parse(path), "{path}");
+        }
+    }
+}
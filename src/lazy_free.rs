@@ -0,0 +1,41 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+use std::thread;
+
+use crate::database::Database;
+use crate::object::Object;
+
+/// Values that take fewer allocations than this to drop are freed right away, because handing them to another thread
+/// would cost more than freeing them.
+const LAZY_FREE_THRESHOLD: usize = 64;
+
+static SENDER: OnceLock<Sender<Box<dyn Send>>> = OnceLock::new();
+
+fn free_in_background(value: Box<dyn Send>) {
+    let sender = SENDER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Box<dyn Send>>();
+
+        thread::spawn(move || {
+            for value in receiver {
+                drop(value);
+            }
+        });
+
+        sender
+    });
+
+    // The receiving thread never exits, so this can't fail.
+    let _ = sender.send(value);
+}
+
+/// Drops `object`, on a background thread if it's large, so that callers holding the `Data` lock don't wait for it.
+pub(crate) fn free(object: Object) {
+    if object.free_effort() > LAZY_FREE_THRESHOLD {
+        free_in_background(Box::new(object));
+    }
+}
+
+/// Drops a whole database on a background thread, for `FLUSHDB ASYNC` and `FLUSHALL ASYNC`.
+pub(crate) fn free_database(database: Database) {
+    free_in_background(Box::new(database));
+}
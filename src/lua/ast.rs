@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+/// A compiled function. Local variables are resolved to slots in the function's frame, and variables of enclosing
+/// functions to upvalues, so the interpreter never looks names up except for globals.
+///
+/// Function bodies are shared with `Arc`, so that compiled scripts can be cached and used from any connection.
+#[derive(Debug)]
+pub(crate) struct FunctionBody {
+    /// The slots of the parameters, in order.
+    pub(crate) parameters: Vec<usize>,
+    pub(crate) is_vararg: bool,
+    /// The number of slots the frame needs.
+    pub(crate) slots: usize,
+    /// Where each upvalue is captured from when a closure is created.
+    pub(crate) upvalues: Vec<Capture>,
+    pub(crate) block: Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Capture {
+    /// A local variable of the enclosing function.
+    Local(usize),
+    /// An upvalue of the enclosing function.
+    Upvalue(usize),
+}
+
+pub(crate) type Block = Vec<Statement>;
+
+#[derive(Debug)]
+pub(crate) enum Statement {
+    Local {
+        slots: Vec<usize>,
+        values: Vec<Expression>,
+    },
+    Assign {
+        targets: Vec<Expression>,
+        values: Vec<Expression>,
+        line: usize,
+    },
+    Call(Expression),
+    Do(Block),
+    While {
+        condition: Expression,
+        block: Block,
+    },
+    Repeat {
+        block: Block,
+        condition: Expression,
+    },
+    If {
+        branches: Vec<(Expression, Block)>,
+        otherwise: Option<Block>,
+    },
+    NumericFor {
+        slot: usize,
+        start: Expression,
+        limit: Expression,
+        step: Option<Expression>,
+        block: Block,
+        line: usize,
+    },
+    GenericFor {
+        slots: Vec<usize>,
+        values: Vec<Expression>,
+        block: Block,
+        line: usize,
+    },
+    /// `local function`, whose slot is assigned before the closure is created so that it can call itself.
+    LocalFunction {
+        slot: usize,
+        body: Arc<FunctionBody>,
+    },
+    Return(Vec<Expression>),
+    Break,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    Concat,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UnaryOperator {
+    Negate,
+    Not,
+    Length,
+}
+
+#[derive(Debug)]
+pub(crate) enum Expression {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    String(Vec<u8>),
+    Vararg,
+    Function(Arc<FunctionBody>),
+    Table(Vec<Field>),
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+        line: usize,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expression>,
+        line: usize,
+    },
+    Local(usize),
+    Upvalue(usize),
+    Global {
+        name: String,
+        line: usize,
+    },
+    Index {
+        table: Box<Expression>,
+        key: Box<Expression>,
+        line: usize,
+    },
+    Call {
+        function: Box<Expression>,
+        /// The method name of a `object:method(...)` call.
+        method: Option<Vec<u8>>,
+        arguments: Vec<Expression>,
+        line: usize,
+    },
+    /// An expression in parentheses, which is truncated to a single value.
+    Parenthesized(Box<Expression>),
+}
+
+impl Expression {
+    /// Whether the expression can produce several values when it comes last in a list.
+    pub(crate) fn is_multiple(&self) -> bool {
+        matches!(self, Expression::Call { .. } | Expression::Vararg)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Field {
+    Positional(Expression),
+    Named(Expression, Expression),
+}
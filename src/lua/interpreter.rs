@@ -0,0 +1,1088 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::ast::{
+    BinaryOperator, Block, Capture, Expression, Field, FunctionBody, Statement, UnaryOperator,
+};
+use super::library;
+use super::value::{Closure, Table, Value};
+
+/// How deeply calls can nest before a script fails with "stack overflow".
+const MAX_CALL_DEPTH: usize = 150;
+
+/// The stack size of threads that run scripts.
+pub(crate) const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How much stack a script can use before it fails with "stack overflow". Frames for deeply nested expressions are
+/// large enough that [`MAX_CALL_DEPTH`] alone doesn't keep a script within [`STACK_SIZE`].
+const MAX_STACK_USE: usize = STACK_SIZE / 2;
+
+/// How many statements run between checks of [`Host::interrupted`].
+const INTERRUPT_INTERVAL: u64 = 1000;
+
+/// How many `__index` or `__newindex` tables a lookup can go through, like Lua's `MAXTAGLOOP`.
+const MAX_TAG_LOOP: usize = 100;
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// An error raised by `error` or by a failed operation, which `pcall` can catch.
+    Raised(Value),
+    /// The host stopped the script. This can't be caught.
+    Interrupted,
+}
+
+/// What the interpreter needs from its embedder.
+pub(crate) trait Host {
+    /// Runs the host function called `name`, see [`Value::HostFunction`].
+    fn call(
+        &mut self,
+        interpreter: &mut Interpreter<'_>,
+        name: &'static str,
+        arguments: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        let _ = arguments;
+
+        Err(interpreter.error(format!("unknown host function '{name}'")))
+    }
+
+    /// Called regularly while a script runs. Returning true stops the script with [`Error::Interrupted`].
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+}
+
+/// A host without any functions, for testing the language on its own.
+#[cfg(test)]
+pub(crate) struct NoHost;
+
+#[cfg(test)]
+impl Host for NoHost {}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+/// The variables of a running function. Each variable is a cell of its own, so that closures can capture it.
+struct Frame {
+    slots: Vec<Rc<RefCell<Value>>>,
+    closure: Rc<Closure>,
+    varargs: Vec<Value>,
+}
+
+fn cell(value: Value) -> Rc<RefCell<Value>> {
+    Rc::new(RefCell::new(value))
+}
+
+/// The field `event` of the metatable of `value`, or nil. Only tables have metatables.
+pub(crate) fn metamethod(value: &Value, event: &str) -> Value {
+    match value {
+        Value::Table(table) => table
+            .borrow()
+            .metatable
+            .as_ref()
+            .map_or(Value::Nil, |metatable| metatable.borrow().get_str(event)),
+        _ => Value::Nil,
+    }
+}
+
+/// The address of a local, which moves as the stack grows.
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+fn is_function(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Function(_) | Value::Builtin(_) | Value::HostFunction(_)
+    )
+}
+
+pub(crate) struct Interpreter<'a> {
+    pub(crate) globals: Rc<RefCell<Table>>,
+    /// The `string` table, where the methods of strings are looked up.
+    pub(crate) string_library: Rc<RefCell<Table>>,
+    /// When set, reading a global that doesn't exist and creating a global are errors.
+    pub(crate) strict_globals: bool,
+    host: Option<&'a mut dyn Host>,
+    chunk_name: String,
+    /// The line being run, for error messages.
+    line: usize,
+    depth: usize,
+    /// Where the stack was when the outermost call started.
+    stack_base: usize,
+    steps: u64,
+    pub(crate) random_state: u64,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Creates an interpreter with the standard library loaded.
+    pub(crate) fn new(chunk_name: &str, host: &'a mut dyn Host) -> Interpreter<'a> {
+        let mut interpreter = Interpreter {
+            globals: Rc::new(RefCell::new(Table::new())),
+            string_library: Rc::new(RefCell::new(Table::new())),
+            strict_globals: false,
+            host: Some(host),
+            chunk_name: chunk_name.to_string(),
+            line: 0,
+            depth: 0,
+            stack_base: 0,
+            steps: 0,
+            random_state: 0,
+        };
+
+        library::open(&mut interpreter);
+
+        interpreter
+    }
+
+    pub(crate) fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    /// Creates an error with the current position, like `error` does.
+    pub(crate) fn error(&self, message: impl AsRef<str>) -> Error {
+        Error::Raised(Value::string(format!(
+            "{}:{}: {}",
+            self.chunk_name,
+            self.line,
+            message.as_ref()
+        )))
+    }
+
+    /// Prefixes a message with the current position.
+    pub(crate) fn position(&self) -> String {
+        format!("{}:{}:", self.chunk_name, self.line)
+    }
+
+    /// Runs a compiled chunk.
+    pub(crate) fn execute(
+        &mut self,
+        body: Arc<FunctionBody>,
+        arguments: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        let function = Value::Function(Rc::new(Closure {
+            body,
+            upvalues: Vec::new(),
+        }));
+
+        self.call(&function, arguments)
+    }
+
+    pub(crate) fn call(
+        &mut self,
+        function: &Value,
+        arguments: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        if self.depth == 0 {
+            self.stack_base = stack_position();
+        }
+
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(self.error("stack overflow"));
+        }
+
+        self.check_stack()?;
+
+        self.depth += 1;
+
+        let result = match function {
+            Value::Function(closure) => self.call_closure(closure.clone(), arguments),
+            Value::Builtin(builtin) => match &builtin.bound {
+                Some(bound) => {
+                    let mut bound_arguments = vec![bound.clone()];
+
+                    bound_arguments.extend(arguments);
+                    (builtin.function)(self, bound_arguments)
+                }
+                None => (builtin.function)(self, arguments),
+            },
+            Value::HostFunction(name) => {
+                // The host is taken out while it runs, since it needs the interpreter to convert values and to raise
+                // errors.
+                let host = self.host.take().expect("host isn't reentered");
+                let result = host.call(self, name, arguments);
+
+                self.host = Some(host);
+                result
+            }
+            other => match metamethod(other, "__call") {
+                Value::Nil => {
+                    Err(self.error(format!("attempt to call a {} value", other.type_name())))
+                }
+                handler => {
+                    let mut with_object = vec![other.clone()];
+
+                    with_object.extend(arguments);
+                    self.call(&handler, with_object)
+                }
+            },
+        };
+
+        self.depth -= 1;
+
+        result
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        arguments: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        let body = closure.body.clone();
+        // Every variable gets a fresh cell when it's declared, so the slots start out sharing one.
+        let unset = cell(Value::Nil);
+        let mut slots = vec![unset; body.slots];
+        let mut arguments = arguments.into_iter();
+
+        for &slot in &body.parameters {
+            slots[slot] = cell(arguments.next().unwrap_or_default());
+        }
+
+        let mut frame = Frame {
+            slots,
+            closure,
+            varargs: if body.is_vararg {
+                arguments.collect()
+            } else {
+                Vec::new()
+            },
+        };
+
+        match self.block(&mut frame, &body.block)? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal | Flow::Break => Ok(Vec::new()),
+        }
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+
+        if self.steps.is_multiple_of(INTERRUPT_INTERVAL)
+            && self.host.as_mut().is_some_and(|host| host.interrupted())
+        {
+            return Err(Error::Interrupted);
+        }
+
+        Ok(())
+    }
+
+    fn block(&mut self, frame: &mut Frame, block: &Block) -> Result<Flow, Error> {
+        for statement in block {
+            match self.statement(frame, statement)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    /// Runs the body of a loop, returning the flow that leaves the loop, if any.
+    fn loop_body(&mut self, frame: &mut Frame, block: &Block) -> Result<Option<Flow>, Error> {
+        Ok(match self.block(frame, block)? {
+            Flow::Normal => None,
+            Flow::Break => Some(Flow::Normal),
+            flow @ Flow::Return(_) => Some(flow),
+        })
+    }
+
+    fn statement(&mut self, frame: &mut Frame, statement: &Statement) -> Result<Flow, Error> {
+        self.step()?;
+
+        match statement {
+            Statement::Local { slots, values } => {
+                let mut values = self.expression_list(frame, values)?.into_iter();
+
+                for &slot in slots {
+                    frame.slots[slot] = cell(values.next().unwrap_or_default());
+                }
+            }
+            Statement::Assign {
+                targets,
+                values,
+                line,
+            } => self.assign(frame, targets, values, *line)?,
+            Statement::Call(call) => {
+                self.multiple(frame, call)?;
+            }
+            Statement::Do(block) => return self.block(frame, block),
+            Statement::While { condition, block } => {
+                while self.expression(frame, condition)?.is_truthy() {
+                    if let Some(flow) = self.loop_body(frame, block)? {
+                        return Ok(flow);
+                    }
+
+                    self.step()?;
+                }
+            }
+            Statement::Repeat { block, condition } => loop {
+                if let Some(flow) = self.loop_body(frame, block)? {
+                    return Ok(flow);
+                }
+
+                if self.expression(frame, condition)?.is_truthy() {
+                    break;
+                }
+
+                self.step()?;
+            },
+            Statement::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, block) in branches {
+                    if self.expression(frame, condition)?.is_truthy() {
+                        return self.block(frame, block);
+                    }
+                }
+
+                if let Some(block) = otherwise {
+                    return self.block(frame, block);
+                }
+            }
+            Statement::NumericFor {
+                slot,
+                start,
+                limit,
+                step,
+                block,
+                line,
+            } => {
+                let start = self.expression(frame, start)?.to_number();
+                let limit = self.expression(frame, limit)?.to_number();
+                let step = match step {
+                    Some(step) => self.expression(frame, step)?.to_number(),
+                    None => Some(1.0),
+                };
+
+                self.line = *line;
+
+                let start =
+                    start.ok_or_else(|| self.error("'for' initial value must be a number"))?;
+                let limit = limit.ok_or_else(|| self.error("'for' limit must be a number"))?;
+                let step = step.ok_or_else(|| self.error("'for' step must be a number"))?;
+
+                let mut i = start;
+
+                while (step > 0.0 && i <= limit) || (step <= 0.0 && i >= limit) {
+                    frame.slots[*slot] = cell(Value::Number(i));
+
+                    if let Some(flow) = self.loop_body(frame, block)? {
+                        return Ok(flow);
+                    }
+
+                    self.step()?;
+                    i += step;
+                }
+            }
+            Statement::GenericFor {
+                slots,
+                values,
+                block,
+                line,
+            } => {
+                let mut values = self.expression_list(frame, values)?.into_iter();
+                let function = values.next().unwrap_or_default();
+                let state = values.next().unwrap_or_default();
+                let mut control = values.next().unwrap_or_default();
+
+                loop {
+                    self.line = *line;
+
+                    let mut results = self
+                        .call(&function, vec![state.clone(), control.clone()])?
+                        .into_iter();
+                    let first = results.next().unwrap_or_default();
+
+                    if first.is_nil() {
+                        break;
+                    }
+
+                    control = first.clone();
+                    frame.slots[slots[0]] = cell(first);
+
+                    for &slot in &slots[1..] {
+                        frame.slots[slot] = cell(results.next().unwrap_or_default());
+                    }
+
+                    if let Some(flow) = self.loop_body(frame, block)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            Statement::LocalFunction { slot, body } => {
+                frame.slots[*slot] = cell(Value::Nil);
+
+                let function = self.closure(frame, body);
+
+                *frame.slots[*slot].borrow_mut() = function;
+            }
+            Statement::Return(values) => {
+                return Ok(Flow::Return(self.expression_list(frame, values)?));
+            }
+            Statement::Break => return Ok(Flow::Break),
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn assign(
+        &mut self,
+        frame: &mut Frame,
+        targets: &[Expression],
+        values: &[Expression],
+        line: usize,
+    ) -> Result<(), Error> {
+        // Tables and keys are evaluated before any assignment is made, as are the values.
+        let mut places = Vec::with_capacity(targets.len());
+
+        for target in targets {
+            places.push(match target {
+                Expression::Index { table, key, .. } => {
+                    Some((self.expression(frame, table)?, self.expression(frame, key)?))
+                }
+                _ => None,
+            });
+        }
+
+        let mut values = self.expression_list(frame, values)?.into_iter();
+
+        self.line = line;
+
+        for (target, place) in targets.iter().zip(places) {
+            let value = values.next().unwrap_or_default();
+
+            match (target, place) {
+                (_, Some((table, key))) => self.set_index(&table, key, value)?,
+                (Expression::Local(slot), None) => *frame.slots[*slot].borrow_mut() = value,
+                (Expression::Upvalue(index), None) => {
+                    *frame.closure.upvalues[*index].borrow_mut() = value
+                }
+                (Expression::Global { name, .. }, None) => {
+                    if self.strict_globals && self.globals.borrow().get_str(name).is_nil() {
+                        return Err(self.error(format!(
+                            "Script attempted to create global variable '{name}'"
+                        )));
+                    }
+
+                    self.set_global(name, value);
+                }
+                _ => unreachable!("the parser only produces assignable targets"),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn closure(&self, frame: &Frame, body: &Arc<FunctionBody>) -> Value {
+        let upvalues = body
+            .upvalues
+            .iter()
+            .map(|capture| match capture {
+                Capture::Local(slot) => frame.slots[*slot].clone(),
+                Capture::Upvalue(index) => frame.closure.upvalues[*index].clone(),
+            })
+            .collect();
+
+        Value::Function(Rc::new(Closure {
+            body: body.clone(),
+            upvalues,
+        }))
+    }
+
+    /// Evaluates a list of expressions, where the last one can produce several values.
+    fn expression_list(
+        &mut self,
+        frame: &mut Frame,
+        expressions: &[Expression],
+    ) -> Result<Vec<Value>, Error> {
+        let mut values = Vec::with_capacity(expressions.len());
+
+        for (i, expression) in expressions.iter().enumerate() {
+            if i + 1 == expressions.len() && expression.is_multiple() {
+                values.extend(self.multiple(frame, expression)?);
+            } else {
+                values.push(self.expression(frame, expression)?);
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Evaluates an expression that can produce several values.
+    fn multiple(
+        &mut self,
+        frame: &mut Frame,
+        expression: &Expression,
+    ) -> Result<Vec<Value>, Error> {
+        match expression {
+            Expression::Call {
+                function,
+                method,
+                arguments,
+                line,
+            } => self.call_expression(frame, function, method.as_deref(), arguments, *line),
+            Expression::Vararg => Ok(frame.varargs.clone()),
+            expression => Ok(vec![self.expression(frame, expression)?]),
+        }
+    }
+
+    fn call_expression(
+        &mut self,
+        frame: &mut Frame,
+        function: &Expression,
+        method: Option<&[u8]>,
+        arguments: &[Expression],
+        line: usize,
+    ) -> Result<Vec<Value>, Error> {
+        let (callee, mut values) = match method {
+            Some(method) => {
+                let object = self.expression(frame, function)?;
+
+                self.line = line;
+
+                let callee = self.index(&object, &Value::string(method), Some(function))?;
+
+                (callee, vec![object])
+            }
+            None => (self.expression(frame, function)?, Vec::new()),
+        };
+
+        values.extend(self.expression_list(frame, arguments)?);
+        self.line = line;
+
+        if matches!(
+            callee,
+            Value::Nil | Value::Boolean(_) | Value::Number(_) | Value::String(_) | Value::Table(_)
+        ) && metamethod(&callee, "__call").is_nil()
+        {
+            let description = match method {
+                Some(method) => format!(" (method '{}')", String::from_utf8_lossy(method)),
+                None => describe(function),
+            };
+
+            return Err(self.error(format!(
+                "attempt to call a {} value{description}",
+                callee.type_name()
+            )));
+        }
+
+        self.call(&callee, values)
+    }
+
+    /// Fails once the script has used [`MAX_STACK_USE`] bytes of stack.
+    fn check_stack(&self) -> Result<(), Error> {
+        if stack_position().abs_diff(self.stack_base) > MAX_STACK_USE {
+            return Err(self.error("stack overflow"));
+        }
+
+        Ok(())
+    }
+
+    fn expression(&mut self, frame: &mut Frame, expression: &Expression) -> Result<Value, Error> {
+        self.check_stack()?;
+
+        Ok(match expression {
+            Expression::Nil => Value::Nil,
+            Expression::True => Value::Boolean(true),
+            Expression::False => Value::Boolean(false),
+            Expression::Number(n) => Value::Number(*n),
+            Expression::String(s) => Value::string(s),
+            Expression::Vararg => frame.varargs.first().cloned().unwrap_or_default(),
+            Expression::Function(body) => self.closure(frame, body),
+            Expression::Table(fields) => self.table(frame, fields)?,
+            Expression::Binary {
+                operator: BinaryOperator::And,
+                left,
+                right,
+                ..
+            } => {
+                let left = self.expression(frame, left)?;
+
+                if left.is_truthy() {
+                    self.expression(frame, right)?
+                } else {
+                    left
+                }
+            }
+            Expression::Binary {
+                operator: BinaryOperator::Or,
+                left,
+                right,
+                ..
+            } => {
+                let left = self.expression(frame, left)?;
+
+                if left.is_truthy() {
+                    left
+                } else {
+                    self.expression(frame, right)?
+                }
+            }
+            Expression::Binary {
+                operator,
+                left,
+                right,
+                line,
+            } => {
+                let left = self.expression(frame, left)?;
+                let right = self.expression(frame, right)?;
+
+                self.line = *line;
+                self.binary(*operator, left, right)?
+            }
+            Expression::Unary {
+                operator,
+                operand,
+                line,
+            } => {
+                let operand = self.expression(frame, operand)?;
+
+                self.line = *line;
+                self.unary(*operator, operand)?
+            }
+            Expression::Local(slot) => frame.slots[*slot].borrow().clone(),
+            Expression::Upvalue(index) => frame.closure.upvalues[*index].borrow().clone(),
+            Expression::Global { name, line } => {
+                let value = self.globals.borrow().get_str(name);
+
+                if value.is_nil() && self.strict_globals {
+                    self.line = *line;
+
+                    return Err(self.error(format!(
+                        "Script attempted to access nonexistent global variable '{name}'"
+                    )));
+                }
+
+                value
+            }
+            Expression::Index { table, key, line } => {
+                let object = self.expression(frame, table)?;
+                let key = self.expression(frame, key)?;
+
+                self.line = *line;
+                self.index(&object, &key, Some(table))?
+            }
+            Expression::Call { .. } => self
+                .multiple(frame, expression)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expression::Parenthesized(expression) => self.expression(frame, expression)?,
+        })
+    }
+
+    fn table(&mut self, frame: &mut Frame, fields: &[Field]) -> Result<Value, Error> {
+        let mut table = Table::new();
+        let mut position = 1;
+
+        for (i, field) in fields.iter().enumerate() {
+            match field {
+                Field::Positional(expression)
+                    if i + 1 == fields.len() && expression.is_multiple() =>
+                {
+                    for value in self.multiple(frame, expression)? {
+                        table
+                            .set(Value::Number(position as f64), value)
+                            .expect("valid index");
+                        position += 1;
+                    }
+                }
+                Field::Positional(expression) => {
+                    let value = self.expression(frame, expression)?;
+
+                    table
+                        .set(Value::Number(position as f64), value)
+                        .expect("valid index");
+                    position += 1;
+                }
+                Field::Named(key, value) => {
+                    let key = self.expression(frame, key)?;
+                    let value = self.expression(frame, value)?;
+
+                    table
+                        .set(key, value)
+                        .map_err(|message| self.error(message))?;
+                }
+            }
+        }
+
+        Ok(Value::table(table))
+    }
+
+    /// Indexes a table, following its `__index` metamethod for missing keys, or looks up a method of a string.
+    /// `expression` describes the indexed value in errors.
+    pub(crate) fn index(
+        &mut self,
+        object: &Value,
+        key: &Value,
+        mut expression: Option<&Expression>,
+    ) -> Result<Value, Error> {
+        let mut object = object.clone();
+
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match &object {
+                Value::Table(table) => {
+                    let value = table.borrow().get(key);
+
+                    if !value.is_nil() {
+                        return Ok(value);
+                    }
+
+                    match metamethod(&object, "__index") {
+                        Value::Nil => return Ok(Value::Nil),
+                        handler => handler,
+                    }
+                }
+                Value::String(_) => return Ok(self.string_library.borrow().get(key)),
+                other => {
+                    return Err(self.error(format!(
+                        "attempt to index a {} value{}",
+                        other.type_name(),
+                        expression.map(describe).unwrap_or_default()
+                    )))
+                }
+            };
+
+            if is_function(&handler) {
+                let results = self.call(&handler, vec![object, key.clone()])?;
+
+                return Ok(results.into_iter().next().unwrap_or_default());
+            }
+
+            object = handler;
+            expression = None;
+        }
+
+        Err(self.error("loop in gettable"))
+    }
+
+    /// Assigns to a field of a table, following its `__newindex` metamethod for missing keys.
+    fn set_index(&mut self, object: &Value, key: Value, value: Value) -> Result<(), Error> {
+        let mut object = object.clone();
+
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match &object {
+                Value::Table(table) => {
+                    let handler = if table.borrow().get(&key).is_nil() {
+                        metamethod(&object, "__newindex")
+                    } else {
+                        Value::Nil
+                    };
+
+                    if handler.is_nil() {
+                        return table
+                            .borrow_mut()
+                            .set(key, value)
+                            .map_err(|message| self.error(message));
+                    }
+
+                    handler
+                }
+                other => {
+                    return Err(
+                        self.error(format!("attempt to index a {} value", other.type_name()))
+                    )
+                }
+            };
+
+            if is_function(&handler) {
+                self.call(&handler, vec![object, key, value])?;
+
+                return Ok(());
+            }
+
+            object = handler;
+        }
+
+        Err(self.error("loop in settable"))
+    }
+
+    /// Calls the metamethod `event` of `left`, or failing that of `right`, with both operands. Returns `None` if
+    /// neither has one.
+    fn arithmetic_metamethod(
+        &mut self,
+        left: &Value,
+        right: &Value,
+        event: &str,
+    ) -> Result<Option<Value>, Error> {
+        let handler = match metamethod(left, event) {
+            Value::Nil => metamethod(right, event),
+            handler => handler,
+        };
+
+        if handler.is_nil() {
+            return Ok(None);
+        }
+
+        let results = self.call(&handler, vec![left.clone(), right.clone()])?;
+
+        Ok(Some(results.into_iter().next().unwrap_or_default()))
+    }
+
+    /// Calls the comparison metamethod `event`, if both operands have the same one.
+    fn comparison_metamethod(
+        &mut self,
+        left: &Value,
+        right: &Value,
+        event: &str,
+    ) -> Result<Option<bool>, Error> {
+        let handler = metamethod(left, event);
+
+        if handler.is_nil() || !handler.raw_equals(&metamethod(right, event)) {
+            return Ok(None);
+        }
+
+        let results = self.call(&handler, vec![left.clone(), right.clone()])?;
+
+        Ok(Some(results.first().is_some_and(Value::is_truthy)))
+    }
+
+    /// Compares values like `==`: tables that aren't the same table are only equal if their `__eq` metamethod says
+    /// so.
+    fn equals(&mut self, left: &Value, right: &Value) -> Result<bool, Error> {
+        if left.raw_equals(right) {
+            return Ok(true);
+        }
+
+        if !matches!((left, right), (Value::Table(_), Value::Table(_))) {
+            return Ok(false);
+        }
+
+        Ok(self
+            .comparison_metamethod(left, right, "__eq")?
+            .unwrap_or(false))
+    }
+
+    fn arithmetic_error(&self, left: &Value, right: &Value) -> Error {
+        let culprit = if left.to_number().is_none() {
+            left
+        } else {
+            right
+        };
+
+        self.error(format!(
+            "attempt to perform arithmetic on a {} value",
+            culprit.type_name()
+        ))
+    }
+
+    fn binary(
+        &mut self,
+        operator: BinaryOperator,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, Error> {
+        use BinaryOperator::*;
+
+        match operator {
+            Add | Subtract | Multiply | Divide | Modulo | Power => {
+                let (Some(a), Some(b)) = (left.to_number(), right.to_number()) else {
+                    let event = match operator {
+                        Add => "__add",
+                        Subtract => "__sub",
+                        Multiply => "__mul",
+                        Divide => "__div",
+                        Modulo => "__mod",
+                        _ => "__pow",
+                    };
+
+                    return match self.arithmetic_metamethod(&left, &right, event)? {
+                        Some(result) => Ok(result),
+                        None => Err(self.arithmetic_error(&left, &right)),
+                    };
+                };
+
+                Ok(Value::Number(match operator {
+                    Add => a + b,
+                    Subtract => a - b,
+                    Multiply => a * b,
+                    Divide => a / b,
+                    Modulo => a - (a / b).floor() * b,
+                    _ => a.powf(b),
+                }))
+            }
+            Concat => match (left.to_bytes(), right.to_bytes()) {
+                (Some(a), Some(b)) => Ok(Value::string([&a[..], &b[..]].concat())),
+                _ => {
+                    if let Some(result) = self.arithmetic_metamethod(&left, &right, "__concat")? {
+                        return Ok(result);
+                    }
+
+                    let culprit = if left.to_bytes().is_none() {
+                        &left
+                    } else {
+                        &right
+                    };
+
+                    Err(self.error(format!(
+                        "attempt to concatenate a {} value",
+                        culprit.type_name()
+                    )))
+                }
+            },
+            Equal => Ok(Value::Boolean(self.equals(&left, &right)?)),
+            NotEqual => Ok(Value::Boolean(!self.equals(&left, &right)?)),
+            Less => Ok(Value::Boolean(self.less_than(&left, &right, false)?)),
+            LessEqual => Ok(Value::Boolean(self.less_than(&left, &right, true)?)),
+            Greater => Ok(Value::Boolean(self.less_than(&right, &left, false)?)),
+            GreaterEqual => Ok(Value::Boolean(self.less_than(&right, &left, true)?)),
+            And | Or => unreachable!("logical operators short-circuit"),
+        }
+    }
+
+    /// Compares numbers or strings, or tables with the `__lt` and `__le` metamethods, like `<` (or `<=` if
+    /// `or_equal` is set).
+    pub(crate) fn less_than(
+        &mut self,
+        left: &Value,
+        right: &Value,
+        or_equal: bool,
+    ) -> Result<bool, Error> {
+        match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            (Value::String(a), Value::String(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            (a, b) if a.type_name() == b.type_name() => {
+                let event = if or_equal { "__le" } else { "__lt" };
+
+                if let Some(result) = self.comparison_metamethod(a, b, event)? {
+                    return Ok(result);
+                }
+
+                // Like Lua 5.1, `a <= b` can also be worked out as `not (b < a)`.
+                if or_equal {
+                    if let Some(result) = self.comparison_metamethod(b, a, "__lt")? {
+                        return Ok(!result);
+                    }
+                }
+
+                Err(self.error(format!("attempt to compare two {} values", a.type_name())))
+            }
+            (a, b) => Err(self.error(format!(
+                "attempt to compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        }
+    }
+
+    fn unary(&mut self, operator: UnaryOperator, operand: Value) -> Result<Value, Error> {
+        match operator {
+            UnaryOperator::Negate => match operand.to_number() {
+                Some(n) => Ok(Value::Number(-n)),
+                None => match self.arithmetic_metamethod(&operand, &operand, "__unm")? {
+                    Some(result) => Ok(result),
+                    None => Err(self.arithmetic_error(&operand, &operand)),
+                },
+            },
+            UnaryOperator::Not => Ok(Value::Boolean(!operand.is_truthy())),
+            UnaryOperator::Length => match &operand {
+                Value::String(s) => Ok(Value::Number(s.len() as f64)),
+                Value::Table(t) => Ok(Value::Number(t.borrow().length() as f64)),
+                other => Err(self.error(format!(
+                    "attempt to get length of a {} value",
+                    other.type_name()
+                ))),
+            },
+        }
+    }
+}
+
+/// Describes where a value came from, for error messages like "attempt to call a nil value (global 'f')".
+fn describe(expression: &Expression) -> String {
+    match expression {
+        Expression::Global { name, .. } => format!(" (global '{name}')"),
+        Expression::Local(_) => " (local)".to_string(),
+        Expression::Upvalue(_) => " (upvalue)".to_string(),
+        Expression::Index { key, .. } => match key.as_ref() {
+            Expression::String(name) => format!(" (field '{}')", String::from_utf8_lossy(name)),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    fn run(source: &str) -> Result<Vec<Value>, String> {
+        let mut host = NoHost;
+        let mut interpreter = Interpreter::new("user_script", &mut host);
+        let body = parse(source.as_bytes()).map_err(|error| error.message)?;
+
+        interpreter
+            .execute(body, Vec::new())
+            .map_err(|error| match error {
+                Error::Raised(value) => String::from_utf8_lossy(&value.to_display()).into_owned(),
+                Error::Interrupted => "interrupted".to_string(),
+            })
+    }
+
+    fn number(n: f64) -> Value {
+        Value::Number(n)
+    }
+
+    #[test]
+    fn evaluation() {
+        assert_eq!(Ok(vec![number(7.0)]), run("return 1 + 2 * 3"));
+        assert_eq!(
+            Ok(vec![number(3.0), Value::string("ab")]),
+            run("return 7 % 4, 'a' .. 'b'")
+        );
+        assert_eq!(
+            Ok(vec![number(55.0)]),
+            run("local n = 0 for i = 1, 10 do n = n + i end return n")
+        );
+        assert_eq!(
+            Ok(vec![number(120.0)]),
+            run("local function f(n) if n <= 1 then return 1 end return n * f(n - 1) end return f(5)")
+        );
+        assert_eq!(
+            Ok(vec![number(1.0), number(2.0), number(3.0)]),
+            run("local t = {} for i = 1, 3 do t[i] = function() return i end end return t[1](), t[2](), t[3]()")
+        );
+        assert_eq!(
+            Ok(vec![number(3.0), number(2.0)]),
+            run("local function f(...) return select('#', ...), ... end local a, b = f(2, 3, 4) return a, b")
+        );
+        assert_eq!(
+            Ok(vec![Value::string("b"), number(2.0)]),
+            run("local t = {a = 1, b = 2} t.a = nil local k, v = next(t) return k, v")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err("user_script:1: attempt to perform arithmetic on a nil value".to_string()),
+            run("return 1 + nil")
+        );
+        assert_eq!(
+            Err("user_script:2: attempt to call a nil value (global 'f')".to_string()),
+            run("local x = 1\nf()")
+        );
+        assert_eq!(
+            Err("user_script:1: attempt to index a nil value (field 'b')".to_string()),
+            run("local a = {} return a.b.c")
+        );
+        assert_eq!(
+            Err("user_script:1: stack overflow".to_string()),
+            run("local function f() return f() end return f()")
+        );
+        assert_eq!(
+            Ok(vec![
+                Value::Boolean(false),
+                Value::string("user_script:1: oops")
+            ]),
+            run("return pcall(function() error('oops') end)")
+        );
+    }
+}
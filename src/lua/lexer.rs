@@ -0,0 +1,448 @@
+use super::number::parse_number;
+use super::SyntaxError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Name(String),
+    Number(f64),
+    String(Vec<u8>),
+    And,
+    Break,
+    Do,
+    Else,
+    ElseIf,
+    End,
+    False,
+    For,
+    Function,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Hash,
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+    Less,
+    Greater,
+    Assign,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Semicolon,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Ellipsis,
+    Eof,
+}
+
+fn keyword(name: &str) -> Option<Token> {
+    Some(match name {
+        "and" => Token::And,
+        "break" => Token::Break,
+        "do" => Token::Do,
+        "else" => Token::Else,
+        "elseif" => Token::ElseIf,
+        "end" => Token::End,
+        "false" => Token::False,
+        "for" => Token::For,
+        "function" => Token::Function,
+        "if" => Token::If,
+        "in" => Token::In,
+        "local" => Token::Local,
+        "nil" => Token::Nil,
+        "not" => Token::Not,
+        "or" => Token::Or,
+        "repeat" => Token::Repeat,
+        "return" => Token::Return,
+        "then" => Token::Then,
+        "true" => Token::True,
+        "until" => Token::Until,
+        "while" => Token::While,
+        _ => return None,
+    })
+}
+
+struct Lexer<'a> {
+    source: &'a [u8],
+    position: usize,
+    line: usize,
+}
+
+/// Splits a chunk into tokens, each with the line it starts on. The last token is always [`Token::Eof`].
+pub(crate) fn tokenize(source: &[u8]) -> Result<Vec<(Token, usize)>, SyntaxError> {
+    let mut lexer = Lexer {
+        source,
+        position: 0,
+        line: 1,
+    };
+    let mut tokens = Vec::new();
+
+    // A first line starting with `#` is skipped, like in the standalone interpreter.
+    if source.starts_with(b"#") {
+        while lexer.peek().is_some_and(|c| c != b'\n') {
+            lexer.position += 1;
+        }
+    }
+
+    loop {
+        let token = lexer.next_token()?;
+        let done = token.0 == Token::Eof;
+
+        tokens.push(token);
+
+        if done {
+            return Ok(tokens);
+        }
+    }
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.source.get(self.position + offset).copied()
+    }
+
+    fn error(&self, message: impl Into<String>) -> SyntaxError {
+        SyntaxError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), SyntaxError> {
+        while let Some(c) = self.peek() {
+            match c {
+                b'\n' => {
+                    self.line += 1;
+                    self.position += 1;
+                }
+                b' ' | b'\t' | b'\r' | b'\x0b' | b'\x0c' => self.position += 1,
+                b'-' if self.peek_at(1) == Some(b'-') => {
+                    self.position += 2;
+
+                    if let Some(level) = self.long_bracket_level() {
+                        self.long_string(level)?;
+                    } else {
+                        while self.peek().is_some_and(|c| c != b'\n') {
+                            self.position += 1;
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// If a long bracket like `[[` or `[==[` starts here, returns its level (the number of `=`).
+    fn long_bracket_level(&self) -> Option<usize> {
+        if self.peek() != Some(b'[') {
+            return None;
+        }
+
+        let level = self.source[self.position + 1..]
+            .iter()
+            .take_while(|&&c| c == b'=')
+            .count();
+
+        (self.peek_at(level + 1) == Some(b'[')).then_some(level)
+    }
+
+    fn long_string(&mut self, level: usize) -> Result<Vec<u8>, SyntaxError> {
+        let start_line = self.line;
+
+        self.position += level + 2;
+
+        // A newline right after the opening bracket isn't part of the string.
+        if self.peek() == Some(b'\r') {
+            self.position += 1;
+        }
+
+        if self.peek() == Some(b'\n') {
+            self.line += 1;
+            self.position += 1;
+        }
+
+        let mut string = Vec::new();
+
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(SyntaxError {
+                        line: start_line,
+                        message: "unfinished long string".to_string(),
+                    })
+                }
+                Some(b']')
+                    if self.source[self.position + 1..]
+                        .iter()
+                        .take_while(|&&c| c == b'=')
+                        .count()
+                        == level
+                        && self.peek_at(level + 1) == Some(b']') =>
+                {
+                    self.position += level + 2;
+
+                    return Ok(string);
+                }
+                Some(c) => {
+                    if c == b'\n' {
+                        self.line += 1;
+                    }
+
+                    string.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn quoted_string(&mut self, quote: u8) -> Result<Vec<u8>, SyntaxError> {
+        self.position += 1;
+
+        let mut string = Vec::new();
+
+        loop {
+            let c = match self.peek() {
+                None | Some(b'\n') => return Err(self.error("unfinished string")),
+                Some(c) => c,
+            };
+
+            self.position += 1;
+
+            if c == quote {
+                return Ok(string);
+            }
+
+            if c != b'\\' {
+                string.push(c);
+                continue;
+            }
+
+            let escaped = self.peek().ok_or_else(|| self.error("unfinished string"))?;
+
+            self.position += 1;
+
+            match escaped {
+                b'n' => string.push(b'\n'),
+                b't' => string.push(b'\t'),
+                b'r' => string.push(b'\r'),
+                b'a' => string.push(0x07),
+                b'b' => string.push(0x08),
+                b'f' => string.push(0x0c),
+                b'v' => string.push(0x0b),
+                b'\\' | b'"' | b'\'' => string.push(escaped),
+                b'\n' => {
+                    self.line += 1;
+                    string.push(b'\n');
+                }
+                b'x' => {
+                    let digits = self
+                        .source
+                        .get(self.position..self.position + 2)
+                        .and_then(|digits| std::str::from_utf8(digits).ok())
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                        .ok_or_else(|| self.error("hexadecimal digit expected"))?;
+
+                    string.push(digits);
+                    self.position += 2;
+                }
+                b'0'..=b'9' => {
+                    let mut value = (escaped - b'0') as u32;
+
+                    for _ in 0..2 {
+                        match self.peek() {
+                            Some(digit @ b'0'..=b'9') => {
+                                value = value * 10 + (digit - b'0') as u32;
+                                self.position += 1;
+                            }
+                            _ => break,
+                        }
+                    }
+
+                    string.push(
+                        u8::try_from(value).map_err(|_| self.error("escape sequence too large"))?,
+                    );
+                }
+                _ => return Err(self.error("invalid escape sequence")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, SyntaxError> {
+        let start = self.position;
+
+        let hex = self.peek() == Some(b'0') && matches!(self.peek_at(1), Some(b'x' | b'X'));
+
+        if hex {
+            self.position += 2;
+        }
+
+        while let Some(c) = self.peek() {
+            let exponent = if hex { b"pP" } else { b"eE" };
+
+            if exponent.contains(&c) && matches!(self.peek_at(1), Some(b'+' | b'-')) {
+                self.position += 2;
+            } else if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+
+        let text = std::str::from_utf8(&self.source[start..self.position]).unwrap_or("");
+
+        parse_number(text).ok_or_else(|| self.error(format!("malformed number near '{text}'")))
+    }
+
+    fn next_token(&mut self) -> Result<(Token, usize), SyntaxError> {
+        self.skip_whitespace_and_comments()?;
+
+        let line = self.line;
+
+        let c = match self.peek() {
+            None => return Ok((Token::Eof, line)),
+            Some(c) => c,
+        };
+
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let start = self.position;
+
+            while self
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+            {
+                self.position += 1;
+            }
+
+            let name = std::str::from_utf8(&self.source[start..self.position])
+                .expect("names are ASCII")
+                .to_string();
+
+            return Ok((keyword(&name).unwrap_or(Token::Name(name)), line));
+        }
+
+        if c.is_ascii_digit() || (c == b'.' && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            return Ok((Token::Number(self.number()?), line));
+        }
+
+        if c == b'"' || c == b'\'' {
+            return Ok((Token::String(self.quoted_string(c)?), line));
+        }
+
+        if let Some(level) = self.long_bracket_level() {
+            return Ok((Token::String(self.long_string(level)?), line));
+        }
+
+        let two = [c, self.peek_at(1).unwrap_or(0)];
+
+        let (token, length) = match &two {
+            b"==" => (Token::Equal, 2),
+            b"~=" => (Token::NotEqual, 2),
+            b"<=" => (Token::LessEqual, 2),
+            b">=" => (Token::GreaterEqual, 2),
+            b".." if self.peek_at(2) == Some(b'.') => (Token::Ellipsis, 3),
+            b".." => (Token::Concat, 2),
+            _ => (
+                match c {
+                    b'+' => Token::Plus,
+                    b'-' => Token::Minus,
+                    b'*' => Token::Star,
+                    b'/' => Token::Slash,
+                    b'%' => Token::Percent,
+                    b'^' => Token::Caret,
+                    b'#' => Token::Hash,
+                    b'<' => Token::Less,
+                    b'>' => Token::Greater,
+                    b'=' => Token::Assign,
+                    b'(' => Token::LeftParen,
+                    b')' => Token::RightParen,
+                    b'{' => Token::LeftBrace,
+                    b'}' => Token::RightBrace,
+                    b'[' => Token::LeftBracket,
+                    b']' => Token::RightBracket,
+                    b';' => Token::Semicolon,
+                    b':' => Token::Colon,
+                    b',' => Token::Comma,
+                    b'.' => Token::Dot,
+                    _ => return Err(self.error(format!("unexpected symbol near '{}'", c as char))),
+                },
+                1,
+            ),
+        };
+
+        self.position += length;
+
+        Ok((token, line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    #[test]
+    fn tokens_and_lines() {
+        assert_eq!(
+            vec![
+                Token::Local,
+                Token::Name("x".to_string()),
+                Token::Assign,
+                Token::Number(255.0),
+                Token::Concat,
+                Token::String(b"a\n\x41".to_vec()),
+                Token::Ellipsis,
+                Token::String(b"long ]] string".to_vec()),
+                Token::NotEqual,
+                Token::Number(0.5),
+                Token::Eof,
+            ],
+            tokens("local x = 0xff .. 'a\\n\\65' ... [==[\nlong ]] string]==] -- comment\n ~= .5 --[[ block\n ]]")
+        );
+        assert_eq!(
+            vec![1, 2, 4],
+            tokenize(b"a\nb --[[\n]]\nc")
+                .unwrap()
+                .iter()
+                .take(3)
+                .map(|(_, line)| *line)
+                .collect::<Vec<_>>()
+        );
+        assert!(tokenize(b"'unfinished").is_err());
+        assert!(tokenize(b"3x").is_err());
+    }
+}
@@ -0,0 +1,1776 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::interpreter::{metamethod, Error, Interpreter};
+use super::number::{format_e, format_g, format_number};
+use super::pattern::{self, Captured, Match};
+use super::value::{Builtin, BuiltinFunction, Table, Value};
+
+/// Strings built by `string.rep` can't be longer than this.
+const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+/// Loads the base, `string`, `table` and `math` libraries of Lua 5.1, and the `bit` library of LuaBitOp.
+pub(crate) fn open(interpreter: &mut Interpreter<'_>) {
+    for (name, function) in [
+        ("assert", assert as BuiltinFunction),
+        ("error", error),
+        ("getmetatable", get_metatable),
+        ("ipairs", ipairs),
+        ("next", next),
+        ("pairs", pairs),
+        ("pcall", pcall),
+        ("rawequal", raw_equal),
+        ("rawget", raw_get),
+        ("rawset", raw_set),
+        ("select", select),
+        ("setmetatable", set_metatable),
+        ("tonumber", to_number),
+        ("tostring", to_string),
+        ("type", type_name),
+        ("unpack", unpack),
+        ("xpcall", xpcall),
+    ] {
+        interpreter.set_global(name, Value::builtin(function));
+    }
+
+    let string = library(&[
+        ("byte", string_byte),
+        ("char", string_char),
+        ("find", string_find),
+        ("format", string_format),
+        ("gmatch", string_gmatch),
+        ("gsub", string_gsub),
+        ("len", string_len),
+        ("lower", string_lower),
+        ("match", string_match),
+        ("rep", string_rep),
+        ("reverse", string_reverse),
+        ("sub", string_sub),
+        ("upper", string_upper),
+    ]);
+
+    let table = library(&[
+        ("concat", table_concat),
+        ("getn", table_getn),
+        ("insert", table_insert),
+        ("maxn", table_maxn),
+        ("remove", table_remove),
+        ("sort", table_sort),
+    ]);
+
+    let mut math = library(&[
+        ("abs", math_abs),
+        ("acos", math_acos),
+        ("asin", math_asin),
+        ("atan", math_atan),
+        ("atan2", math_atan2),
+        ("ceil", math_ceil),
+        ("cos", math_cos),
+        ("cosh", math_cosh),
+        ("deg", math_deg),
+        ("exp", math_exp),
+        ("floor", math_floor),
+        ("fmod", math_fmod),
+        ("log", math_log),
+        ("log10", math_log10),
+        ("max", math_max),
+        ("min", math_min),
+        ("modf", math_modf),
+        ("pow", math_pow),
+        ("random", math_random),
+        ("randomseed", math_randomseed),
+        ("rad", math_rad),
+        ("sin", math_sin),
+        ("sinh", math_sinh),
+        ("sqrt", math_sqrt),
+        ("tan", math_tan),
+        ("tanh", math_tanh),
+    ]);
+
+    let bit = library(&[
+        ("arshift", bit_arshift),
+        ("band", bit_band),
+        ("bnot", bit_bnot),
+        ("bor", bit_bor),
+        ("bswap", bit_bswap),
+        ("bxor", bit_bxor),
+        ("lshift", bit_lshift),
+        ("rol", bit_rol),
+        ("ror", bit_ror),
+        ("rshift", bit_rshift),
+        ("tobit", bit_tobit),
+        ("tohex", bit_tohex),
+    ]);
+
+    math.set_str("huge", Value::Number(f64::INFINITY));
+    math.set_str("pi", Value::Number(std::f64::consts::PI));
+
+    let string = Rc::new(RefCell::new(string));
+
+    interpreter.string_library = string.clone();
+    interpreter.set_global("string", Value::Table(string));
+    interpreter.set_global("table", Value::table(table));
+    interpreter.set_global("math", Value::table(math));
+    interpreter.set_global("bit", Value::table(bit));
+    randomseed(interpreter, 0);
+}
+
+/// Creates a table of builtins.
+pub(crate) fn library(functions: &[(&'static str, BuiltinFunction)]) -> Table {
+    let mut table = Table::new();
+
+    for &(name, function) in functions {
+        table.set_str(name, Value::builtin(function));
+    }
+
+    table
+}
+
+fn argument(arguments: &[Value], index: usize) -> Value {
+    arguments.get(index).cloned().unwrap_or_default()
+}
+
+/// An error about the argument at `index` (0-based) of the builtin `name`.
+pub(crate) fn bad_argument(
+    interpreter: &Interpreter<'_>,
+    index: usize,
+    name: &str,
+    message: &str,
+) -> Error {
+    interpreter.error(format!(
+        "bad argument #{} to '{name}' ({message})",
+        index + 1
+    ))
+}
+
+fn expected(
+    interpreter: &Interpreter<'_>,
+    arguments: &[Value],
+    index: usize,
+    name: &str,
+    expected: &str,
+) -> Error {
+    let got = arguments.get(index).map_or("no value", Value::type_name);
+
+    bad_argument(
+        interpreter,
+        index,
+        name,
+        &format!("{expected} expected, got {got}"),
+    )
+}
+
+pub(crate) fn check_number(
+    interpreter: &Interpreter<'_>,
+    arguments: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<f64, Error> {
+    argument(arguments, index)
+        .to_number()
+        .ok_or_else(|| expected(interpreter, arguments, index, name, "number"))
+}
+
+pub(crate) fn check_integer(
+    interpreter: &Interpreter<'_>,
+    arguments: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<i64, Error> {
+    check_number(interpreter, arguments, index, name).map(|n| n as i64)
+}
+
+fn optional_integer(
+    interpreter: &Interpreter<'_>,
+    arguments: &[Value],
+    index: usize,
+    name: &str,
+    default: i64,
+) -> Result<i64, Error> {
+    match arguments.get(index) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_integer(interpreter, arguments, index, name),
+    }
+}
+
+pub(crate) fn check_string<'v>(
+    interpreter: &Interpreter<'_>,
+    arguments: &'v [Value],
+    index: usize,
+    name: &str,
+) -> Result<Cow<'v, [u8]>, Error> {
+    arguments
+        .get(index)
+        .and_then(Value::to_bytes)
+        .ok_or_else(|| expected(interpreter, arguments, index, name, "string"))
+}
+
+pub(crate) fn check_table(
+    interpreter: &Interpreter<'_>,
+    arguments: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<Rc<RefCell<Table>>, Error> {
+    match arguments.get(index) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => Err(expected(interpreter, arguments, index, name, "table")),
+    }
+}
+
+fn assert(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    if argument(&arguments, 0).is_truthy() {
+        return Ok(arguments);
+    }
+
+    match arguments.get(1).and_then(Value::to_bytes) {
+        Some(message) => Err(interpreter.error(String::from_utf8_lossy(&message))),
+        None => Err(interpreter.error("assertion failed!")),
+    }
+}
+
+fn error(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let value = argument(&arguments, 0);
+    let level = optional_integer(interpreter, &arguments, 1, "error", 1)?;
+
+    match &value {
+        Value::String(message) if level > 0 => {
+            let mut positioned = interpreter.position().into_bytes();
+
+            positioned.push(b' ');
+            positioned.extend_from_slice(message);
+
+            Err(Error::Raised(Value::string(positioned)))
+        }
+        _ => Err(Error::Raised(value)),
+    }
+}
+
+fn get_metatable(_: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let Value::Table(table) = argument(&arguments, 0) else {
+        return Ok(vec![Value::Nil]);
+    };
+    let Some(metatable) = table.borrow().metatable.clone() else {
+        return Ok(vec![Value::Nil]);
+    };
+    let protected = metatable.borrow().get_str("__metatable");
+
+    if protected.is_nil() {
+        Ok(vec![Value::Table(metatable)])
+    } else {
+        Ok(vec![protected])
+    }
+}
+
+fn set_metatable(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "setmetatable")?;
+    let metatable = match argument(&arguments, 1) {
+        Value::Nil => None,
+        Value::Table(metatable) => Some(metatable),
+        _ => {
+            return Err(bad_argument(
+                interpreter,
+                1,
+                "setmetatable",
+                "nil or table expected",
+            ))
+        }
+    };
+
+    if !metamethod(&Value::Table(table.clone()), "__metatable").is_nil() {
+        return Err(interpreter.error("cannot change a protected metatable"));
+    }
+
+    table.borrow_mut().metatable = metatable;
+
+    Ok(vec![Value::Table(table)])
+}
+
+fn ipairs(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "ipairs")?;
+
+    Ok(vec![
+        Value::builtin(ipairs_iterator),
+        Value::Table(table),
+        Value::Number(0.0),
+    ])
+}
+
+fn ipairs_iterator(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "ipairs")?;
+    let index = Value::Number(check_number(interpreter, &arguments, 1, "ipairs")? + 1.0);
+    let value = table.borrow().get(&index);
+
+    Ok(if value.is_nil() {
+        vec![Value::Nil]
+    } else {
+        vec![index, value]
+    })
+}
+
+fn next(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "next")?;
+    let next = table.borrow().next(&argument(&arguments, 1));
+
+    match next {
+        Ok(Some((key, value))) => Ok(vec![key, value]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(()) => Err(interpreter.error("invalid key to 'next'")),
+    }
+}
+
+fn pairs(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "pairs")?;
+
+    Ok(vec![Value::builtin(next), Value::Table(table), Value::Nil])
+}
+
+fn pcall(
+    interpreter: &mut Interpreter<'_>,
+    mut arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    if arguments.is_empty() {
+        return Err(expected(interpreter, &arguments, 0, "pcall", "value"));
+    }
+
+    let function = arguments.remove(0);
+
+    match interpreter.call(&function, arguments) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(Error::Raised(value)) => Ok(vec![Value::Boolean(false), value]),
+        Err(Error::Interrupted) => Err(Error::Interrupted),
+    }
+}
+
+fn xpcall(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let handler = argument(&arguments, 1);
+
+    match interpreter.call(&argument(&arguments, 0), Vec::new()) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(Error::Raised(value)) => {
+            let handled = interpreter.call(&handler, vec![value])?;
+
+            Ok(vec![
+                Value::Boolean(false),
+                handled.into_iter().next().unwrap_or_default(),
+            ])
+        }
+        Err(Error::Interrupted) => Err(Error::Interrupted),
+    }
+}
+
+fn raw_equal(_: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    Ok(vec![Value::Boolean(
+        argument(&arguments, 0).raw_equals(&argument(&arguments, 1)),
+    )])
+}
+
+fn raw_get(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "rawget")?;
+    let value = table.borrow().get(&argument(&arguments, 1));
+
+    Ok(vec![value])
+}
+
+fn raw_set(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "rawset")?;
+
+    table
+        .borrow_mut()
+        .set(argument(&arguments, 1), argument(&arguments, 2))
+        .map_err(|message| interpreter.error(message))?;
+
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(
+    interpreter: &mut Interpreter<'_>,
+    mut arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let count = arguments.len().saturating_sub(1) as i64;
+
+    if let Some(Value::String(s)) = arguments.first() {
+        if &s[..] == b"#" {
+            return Ok(vec![Value::Number(count as f64)]);
+        }
+    }
+
+    let n = check_integer(interpreter, &arguments, 0, "select")?;
+
+    let start = if n < 0 { count + n } else { n - 1 };
+
+    if n == 0 || start < 0 {
+        return Err(bad_argument(interpreter, 0, "select", "index out of range"));
+    }
+
+    Ok(arguments.split_off((start as usize + 1).min(arguments.len())))
+}
+
+fn to_number(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let base = optional_integer(interpreter, &arguments, 1, "tonumber", 10)?;
+    let value = argument(&arguments, 0);
+
+    if base == 10 {
+        return Ok(vec![value.to_number().map_or(Value::Nil, Value::Number)]);
+    }
+
+    if !(2..=36).contains(&base) {
+        return Err(bad_argument(
+            interpreter,
+            1,
+            "tonumber",
+            "base out of range",
+        ));
+    }
+
+    let text = check_string(interpreter, &arguments, 0, "tonumber")?;
+    let parsed = std::str::from_utf8(&text)
+        .ok()
+        .and_then(|text| i64::from_str_radix(text.trim(), base as u32).ok());
+
+    Ok(vec![parsed.map_or(Value::Nil, |n| Value::Number(n as f64))])
+}
+
+fn to_string(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let value = argument(&arguments, 0);
+
+    match metamethod(&value, "__tostring") {
+        Value::Nil => Ok(vec![Value::string(value.to_display())]),
+        handler => {
+            let results = interpreter.call(&handler, vec![value])?;
+
+            Ok(vec![results.into_iter().next().unwrap_or_default()])
+        }
+    }
+}
+
+fn type_name(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    match arguments.first() {
+        Some(value) => Ok(vec![Value::string(value.type_name())]),
+        None => Err(expected(interpreter, &arguments, 0, "type", "value")),
+    }
+}
+
+fn unpack(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "unpack")?;
+    let table = table.borrow();
+    let start = optional_integer(interpreter, &arguments, 1, "unpack", 1)?;
+    let end = optional_integer(interpreter, &arguments, 2, "unpack", table.length() as i64)?;
+
+    if end.saturating_sub(start) >= 8000 {
+        return Err(interpreter.error("too many results to unpack"));
+    }
+
+    Ok((start..=end)
+        .map(|i| table.get(&Value::Number(i as f64)))
+        .collect())
+}
+
+/// Converts a 1-based string position that can be negative to count from the end, like Lua's `posrelat`.
+fn relative_position(position: i64, length: usize) -> i64 {
+    if position < 0 {
+        length as i64 + position + 1
+    } else {
+        position
+    }
+}
+
+fn string_byte(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "byte")?;
+    let start = relative_position(
+        optional_integer(interpreter, &arguments, 1, "byte", 1)?,
+        s.len(),
+    );
+    let end = relative_position(
+        optional_integer(interpreter, &arguments, 2, "byte", start)?,
+        s.len(),
+    );
+    let (start, end) = (start.max(1), end.min(s.len() as i64));
+
+    if start > end {
+        return Ok(Vec::new());
+    }
+
+    Ok(s[start as usize - 1..end as usize]
+        .iter()
+        .map(|&c| Value::Number(c as f64))
+        .collect())
+}
+
+fn string_char(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let mut s = Vec::with_capacity(arguments.len());
+
+    for i in 0..arguments.len() {
+        let c = check_integer(interpreter, &arguments, i, "char")?;
+
+        s.push(u8::try_from(c).map_err(|_| bad_argument(interpreter, i, "char", "invalid value"))?);
+    }
+
+    Ok(vec![Value::string(s)])
+}
+
+/// Converts the captures of a match to values. Without captures, the whole match is returned if `whole` is set.
+fn captures(source: &[u8], start: usize, found: &Match, whole: bool) -> Vec<Value> {
+    if found.captures.is_empty() && whole {
+        return vec![Value::string(&source[start..found.end])];
+    }
+
+    found
+        .captures
+        .iter()
+        .map(|capture| match capture {
+            Captured::Position(position) => Value::Number(*position as f64),
+            Captured::Range(start, end) => Value::string(&source[*start..*end]),
+        })
+        .collect()
+}
+
+/// Implements `string.find` and `string.match`.
+fn find(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+    name: &str,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, name)?;
+    let p = check_string(interpreter, &arguments, 1, name)?;
+    let init = relative_position(
+        optional_integer(interpreter, &arguments, 2, name, 1)?,
+        s.len(),
+    )
+    .max(1) as usize
+        - 1;
+
+    if init > s.len() {
+        return Ok(vec![Value::Nil]);
+    }
+
+    let plain = argument(&arguments, 3).is_truthy() || !pattern::has_specials(&p);
+
+    if name == "find" && plain {
+        let position = if p.is_empty() {
+            Some(init)
+        } else {
+            s[init..]
+                .windows(p.len())
+                .position(|window| window == &p[..])
+                .map(|i| init + i)
+        };
+
+        return Ok(match position {
+            Some(start) => vec![
+                Value::Number((start + 1) as f64),
+                Value::Number((start + p.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+
+    match pattern::find(&s, &p, init).map_err(|message| interpreter.error(message))? {
+        Some((start, found)) if name == "find" => {
+            let mut values = vec![
+                Value::Number((start + 1) as f64),
+                Value::Number(found.end as f64),
+            ];
+
+            values.extend(captures(&s, start, &found, false));
+            Ok(values)
+        }
+        Some((start, found)) => Ok(captures(&s, start, &found, true)),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+fn string_find(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    find(interpreter, arguments, "find")
+}
+
+fn string_match(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    find(interpreter, arguments, "match")
+}
+
+fn string_gmatch(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "gmatch")?;
+    let p = check_string(interpreter, &arguments, 1, "gmatch")?;
+    // The iterator's state: the string, the pattern and where to continue.
+    let state = Table::from_values(vec![Value::string(s), Value::string(p), Value::Number(0.0)]);
+
+    Ok(vec![Value::Builtin(Rc::new(Builtin {
+        function: gmatch_iterator,
+        bound: Some(Value::table(state)),
+    }))])
+}
+
+fn gmatch_iterator(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let Value::Table(state) = argument(&arguments, 0) else {
+        unreachable!("gmatch iterators are bound to their state");
+    };
+
+    let (s, p, position) = {
+        let state = state.borrow();
+
+        (
+            state.get(&Value::Number(1.0)),
+            state.get(&Value::Number(2.0)),
+            state.get(&Value::Number(3.0)),
+        )
+    };
+
+    let (Value::String(s), Value::String(p), Value::Number(position)) = (s, p, position) else {
+        unreachable!("gmatch state has a string, a pattern and a position");
+    };
+
+    for start in position as usize..=s.len() {
+        if let Some(found) =
+            pattern::match_at(&s, &p, start).map_err(|message| interpreter.error(message))?
+        {
+            // An empty match moves on, so that the iterator doesn't get stuck.
+            let next = if found.end == start {
+                found.end + 1
+            } else {
+                found.end
+            };
+
+            state
+                .borrow_mut()
+                .set(Value::Number(3.0), Value::Number(next as f64))
+                .expect("valid index");
+
+            return Ok(captures(&s, start, &found, true));
+        }
+    }
+
+    state
+        .borrow_mut()
+        .set(Value::Number(3.0), Value::Number((s.len() + 1) as f64))
+        .expect("valid index");
+
+    Ok(vec![Value::Nil])
+}
+
+fn string_gsub(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "gsub")?;
+    let p = check_string(interpreter, &arguments, 1, "gsub")?;
+    let replacement = argument(&arguments, 2);
+
+    if !matches!(
+        replacement,
+        Value::Number(_)
+            | Value::String(_)
+            | Value::Table(_)
+            | Value::Function(_)
+            | Value::Builtin(_)
+    ) {
+        return Err(bad_argument(
+            interpreter,
+            2,
+            "gsub",
+            "string/function/table expected",
+        ));
+    }
+
+    let max = optional_integer(interpreter, &arguments, 3, "gsub", s.len() as i64 + 1)?;
+    let (anchored, p) = match p.strip_prefix(b"^") {
+        Some(rest) => (true, rest),
+        None => (false, &p[..]),
+    };
+
+    let mut out = Vec::with_capacity(s.len());
+    let mut position = 0;
+    let mut count = 0;
+
+    while count < max {
+        let found =
+            pattern::match_at(&s, p, position).map_err(|message| interpreter.error(message))?;
+
+        if let Some(found) = &found {
+            count += 1;
+            replace(interpreter, &mut out, &s, position, found, &replacement)?;
+        }
+
+        match found {
+            Some(found) if found.end > position => position = found.end,
+            _ if position < s.len() => {
+                out.push(s[position]);
+                position += 1;
+            }
+            _ => break,
+        }
+
+        if anchored {
+            break;
+        }
+    }
+
+    out.extend_from_slice(&s[position..]);
+
+    Ok(vec![Value::string(out), Value::Number(count as f64)])
+}
+
+/// Appends the replacement of one match for `string.gsub`.
+fn replace(
+    interpreter: &mut Interpreter<'_>,
+    out: &mut Vec<u8>,
+    s: &[u8],
+    start: usize,
+    found: &Match,
+    replacement: &Value,
+) -> Result<(), Error> {
+    let whole = &s[start..found.end];
+    let values = captures(s, start, found, true);
+
+    let value = match replacement {
+        Value::Table(table) => table.borrow().get(&values[0]),
+        Value::Function(_) | Value::Builtin(_) => interpreter
+            .call(replacement, values)?
+            .into_iter()
+            .next()
+            .unwrap_or_default(),
+        replacement => {
+            let replacement = replacement.to_bytes().expect("strings and numbers");
+            let mut i = 0;
+
+            while i < replacement.len() {
+                let c = replacement[i];
+
+                i += 1;
+
+                if c != b'%' || i == replacement.len() {
+                    out.push(c);
+                    continue;
+                }
+
+                let escaped = replacement[i];
+
+                i += 1;
+
+                match escaped {
+                    b'0' => out.extend_from_slice(whole),
+                    b'1'..=b'9' => {
+                        let index = (escaped - b'1') as usize;
+
+                        if found.captures.is_empty() && index == 0 {
+                            out.extend_from_slice(whole);
+                        } else {
+                            match found.captures.get(index) {
+                                Some(Captured::Position(position)) => out
+                                    .extend_from_slice(format_number(*position as f64).as_bytes()),
+                                Some(Captured::Range(start, end)) => {
+                                    out.extend_from_slice(&s[*start..*end])
+                                }
+                                None => return Err(interpreter.error("invalid capture index")),
+                            }
+                        }
+                    }
+                    other => out.push(other),
+                }
+            }
+
+            return Ok(());
+        }
+    };
+
+    match &value {
+        Value::Nil | Value::Boolean(false) => out.extend_from_slice(whole),
+        Value::String(_) | Value::Number(_) => {
+            out.extend_from_slice(&value.to_bytes().expect("strings and numbers"))
+        }
+        other => {
+            return Err(interpreter.error(format!(
+                "invalid replacement value (a {})",
+                other.type_name()
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+fn string_len(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "len")?;
+
+    Ok(vec![Value::Number(s.len() as f64)])
+}
+
+fn string_lower(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "lower")?;
+
+    Ok(vec![Value::string(s.to_ascii_lowercase())])
+}
+
+fn string_upper(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "upper")?;
+
+    Ok(vec![Value::string(s.to_ascii_uppercase())])
+}
+
+fn string_rep(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "rep")?;
+    let n = check_integer(interpreter, &arguments, 1, "rep")?.max(0) as usize;
+
+    if s.len().saturating_mul(n) > MAX_STRING_LENGTH {
+        return Err(interpreter.error("resulting string too large"));
+    }
+
+    Ok(vec![Value::string(s.repeat(n))])
+}
+
+fn string_reverse(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let mut s = check_string(interpreter, &arguments, 0, "reverse")?.into_owned();
+
+    s.reverse();
+
+    Ok(vec![Value::string(s)])
+}
+
+fn string_sub(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = check_string(interpreter, &arguments, 0, "sub")?;
+    let start = relative_position(
+        optional_integer(interpreter, &arguments, 1, "sub", 1)?,
+        s.len(),
+    )
+    .max(1);
+    let end = relative_position(
+        optional_integer(interpreter, &arguments, 2, "sub", -1)?,
+        s.len(),
+    )
+    .min(s.len() as i64);
+
+    Ok(vec![if start <= end {
+        Value::string(&s[start as usize - 1..end as usize])
+    } else {
+        Value::string("")
+    }])
+}
+
+/// Pads a formatted number or string to `width`, like `printf` does.
+fn pad(out: &mut Vec<u8>, sign: &str, body: &[u8], flags: &[u8], width: usize, numeric: bool) {
+    let padding = width.saturating_sub(sign.len() + body.len());
+
+    if flags.contains(&b'-') {
+        out.extend_from_slice(sign.as_bytes());
+        out.extend_from_slice(body);
+        out.resize(out.len() + padding, b' ');
+    } else if flags.contains(&b'0') && numeric {
+        out.extend_from_slice(sign.as_bytes());
+        out.resize(out.len() + padding, b'0');
+        out.extend_from_slice(body);
+    } else {
+        out.resize(out.len() + padding, b' ');
+        out.extend_from_slice(sign.as_bytes());
+        out.extend_from_slice(body);
+    }
+}
+
+fn sign(negative: bool, flags: &[u8]) -> &'static str {
+    if negative {
+        "-"
+    } else if flags.contains(&b'+') {
+        "+"
+    } else if flags.contains(&b' ') {
+        " "
+    } else {
+        ""
+    }
+}
+
+fn string_format(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let format = check_string(interpreter, &arguments, 0, "format")?;
+    let mut out = Vec::new();
+    let mut next_argument = 1;
+    let mut i = 0;
+
+    while i < format.len() {
+        let c = format[i];
+
+        i += 1;
+
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+
+        if format.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        let spec_start = i;
+
+        while format.get(i).is_some_and(|c| b"-+ #0".contains(c)) {
+            i += 1;
+        }
+
+        let flags = &format[spec_start..i];
+
+        if flags.len() > 5 {
+            return Err(interpreter.error("invalid format (repeated flags)"));
+        }
+
+        let digits = |i: &mut usize| -> Result<Option<usize>, Error> {
+            let start = *i;
+
+            while format.get(*i).is_some_and(u8::is_ascii_digit) {
+                *i += 1;
+            }
+
+            if *i - start > 2 {
+                return Err(interpreter.error("invalid format (width or precision too long)"));
+            }
+
+            Ok(std::str::from_utf8(&format[start..*i])
+                .ok()
+                .and_then(|d| d.parse().ok()))
+        };
+
+        let width = digits(&mut i)?.unwrap_or(0);
+        let precision = if format.get(i) == Some(&b'.') {
+            i += 1;
+            Some(digits(&mut i)?.unwrap_or(0))
+        } else {
+            None
+        };
+
+        let Some(&conversion) = format.get(i) else {
+            return Err(interpreter.error("invalid option '%' to 'format'"));
+        };
+
+        i += 1;
+
+        let index = next_argument;
+
+        next_argument += 1;
+
+        if index >= arguments.len() && conversion != b'%' {
+            return Err(bad_argument(interpreter, index, "format", "no value"));
+        }
+
+        match conversion {
+            b'c' => out.push(check_integer(interpreter, &arguments, index, "format")? as u8),
+            b'd' | b'i' => {
+                let n = check_integer(interpreter, &arguments, index, "format")?;
+                let mut digits = n.unsigned_abs().to_string();
+
+                if let Some(precision) = precision {
+                    digits = format!("{digits:0>precision$}");
+                }
+
+                pad(
+                    &mut out,
+                    sign(n < 0, flags),
+                    digits.as_bytes(),
+                    flags,
+                    width,
+                    precision.is_none(),
+                );
+            }
+            b'o' | b'u' | b'x' | b'X' => {
+                let n = check_integer(interpreter, &arguments, index, "format")? as u64;
+                let mut digits = match conversion {
+                    b'o' => format!("{n:o}"),
+                    b'u' => n.to_string(),
+                    b'x' => format!("{n:x}"),
+                    _ => format!("{n:X}"),
+                };
+
+                if let Some(precision) = precision {
+                    digits = format!("{digits:0>precision$}");
+                }
+
+                let prefix = match conversion {
+                    b'x' if flags.contains(&b'#') && n != 0 => "0x",
+                    b'X' if flags.contains(&b'#') && n != 0 => "0X",
+                    _ => "",
+                };
+
+                pad(
+                    &mut out,
+                    prefix,
+                    digits.as_bytes(),
+                    flags,
+                    width,
+                    precision.is_none(),
+                );
+            }
+            b'e' | b'E' | b'f' | b'g' | b'G' => {
+                let n = check_number(interpreter, &arguments, index, "format")?;
+                let precision = precision.unwrap_or(6);
+                let body = if n.is_nan() {
+                    "nan".to_string()
+                } else {
+                    match conversion {
+                        b'e' | b'E' => format_e(n.abs(), precision),
+                        b'f' if n.is_infinite() => "inf".to_string(),
+                        b'f' => format!("{:.precision$}", n.abs()),
+                        _ => format_g(n.abs(), if precision == 0 { 1 } else { precision }),
+                    }
+                };
+                let body = if conversion.is_ascii_uppercase() {
+                    body.to_ascii_uppercase()
+                } else {
+                    body
+                };
+
+                pad(
+                    &mut out,
+                    sign(n.is_sign_negative() && !n.is_nan(), flags),
+                    body.as_bytes(),
+                    flags,
+                    width,
+                    n.is_finite(),
+                );
+            }
+            b'q' => {
+                let s = check_string(interpreter, &arguments, index, "format")?;
+
+                out.push(b'"');
+
+                for &c in s.iter() {
+                    match c {
+                        b'"' | b'\\' | b'\n' => out.extend_from_slice(&[b'\\', c]),
+                        b'\r' => out.extend_from_slice(b"\\r"),
+                        0 => out.extend_from_slice(b"\\000"),
+                        c => out.push(c),
+                    }
+                }
+
+                out.push(b'"');
+            }
+            b's' => {
+                let s = check_string(interpreter, &arguments, index, "format")?;
+                let s = match precision {
+                    Some(precision) => &s[..precision.min(s.len())],
+                    None => &s[..],
+                };
+
+                pad(&mut out, "", s, flags, width, false);
+            }
+            other => {
+                return Err(
+                    interpreter.error(format!("invalid option '%{}' to 'format'", other as char))
+                )
+            }
+        }
+    }
+
+    Ok(vec![Value::string(out)])
+}
+
+fn table_concat(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "concat")?;
+    let table = table.borrow();
+    let separator = match arguments.get(1) {
+        None | Some(Value::Nil) => Cow::Borrowed(&b""[..]),
+        Some(_) => check_string(interpreter, &arguments, 1, "concat")?,
+    };
+    let start = optional_integer(interpreter, &arguments, 2, "concat", 1)?;
+    let end = optional_integer(interpreter, &arguments, 3, "concat", table.length() as i64)?;
+    let mut out = Vec::new();
+
+    for i in start..=end {
+        match table.get(&Value::Number(i as f64)).to_bytes() {
+            Some(s) => out.extend_from_slice(&s),
+            None => {
+                return Err(interpreter.error(format!(
+                    "invalid value (at index {i}) in table for 'concat'"
+                )))
+            }
+        }
+
+        if i != end {
+            out.extend_from_slice(&separator);
+        }
+    }
+
+    Ok(vec![Value::string(out)])
+}
+
+fn table_getn(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "getn")?;
+    let length = table.borrow().length();
+
+    Ok(vec![Value::Number(length as f64)])
+}
+
+fn table_maxn(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "maxn")?;
+    let table = table.borrow();
+    let mut max = 0.0_f64;
+    let mut key = Value::Nil;
+
+    while let Ok(Some((k, _))) = table.next(&key) {
+        if let Value::Number(n) = k {
+            max = max.max(n);
+        }
+
+        key = k;
+    }
+
+    Ok(vec![Value::Number(max)])
+}
+
+fn table_insert(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "insert")?;
+    let length = table.borrow().length();
+
+    let (position, value) = match arguments.len() {
+        2 => (length + 1, argument(&arguments, 1)),
+        3 => {
+            let position = check_integer(interpreter, &arguments, 1, "insert")?;
+
+            if position < 1 {
+                return Err(bad_argument(
+                    interpreter,
+                    1,
+                    "insert",
+                    "position out of bounds",
+                ));
+            }
+
+            (position as usize, argument(&arguments, 2))
+        }
+        _ => return Err(interpreter.error("wrong number of arguments to 'insert'")),
+    };
+
+    table.borrow_mut().insert(position, value);
+
+    Ok(Vec::new())
+}
+
+fn table_remove(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "remove")?;
+    let length = table.borrow().length();
+
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+
+    let position = optional_integer(interpreter, &arguments, 1, "remove", length as i64)?;
+
+    if position < 1 || position as usize > length {
+        return Ok(Vec::new());
+    }
+
+    let value = table.borrow_mut().remove(position as usize);
+
+    Ok(vec![value])
+}
+
+fn table_sort(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let table = check_table(interpreter, &arguments, 0, "sort")?;
+    let comparator = argument(&arguments, 1);
+    let length = table.borrow().length();
+    let mut values: Vec<Value> = (1..=length)
+        .map(|i| table.borrow().get(&Value::Number(i as f64)))
+        .collect();
+
+    let mut less =
+        |interpreter: &mut Interpreter<'_>, a: &Value, b: &Value| -> Result<bool, Error> {
+            if comparator.is_nil() {
+                interpreter.less_than(a, b, false)
+            } else {
+                Ok(interpreter
+                    .call(&comparator, vec![a.clone(), b.clone()])?
+                    .first()
+                    .is_some_and(Value::is_truthy))
+            }
+        };
+
+    merge_sort(interpreter, &mut values, &mut less)?;
+
+    let mut table = table.borrow_mut();
+
+    for (i, value) in values.into_iter().enumerate() {
+        table
+            .set(Value::Number((i + 1) as f64), value)
+            .expect("valid index");
+    }
+
+    Ok(Vec::new())
+}
+
+/// A merge sort whose comparisons can fail. Comparators written in Lua might not be consistent, which Rust's sorts
+/// don't allow.
+fn merge_sort<F>(
+    interpreter: &mut Interpreter<'_>,
+    values: &mut Vec<Value>,
+    less: &mut F,
+) -> Result<(), Error>
+where
+    F: FnMut(&mut Interpreter<'_>, &Value, &Value) -> Result<bool, Error>,
+{
+    if values.len() <= 1 {
+        return Ok(());
+    }
+
+    let mut right = values.split_off(values.len() / 2);
+    let mut left = std::mem::take(values);
+
+    merge_sort(interpreter, &mut left, less)?;
+    merge_sort(interpreter, &mut right, less)?;
+
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if less(interpreter, b, a)? {
+            values.push(right.next().expect("peeked"));
+        } else {
+            values.push(left.next().expect("peeked"));
+        }
+    }
+
+    values.extend(left);
+    values.extend(right);
+
+    Ok(())
+}
+
+fn math_unary(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+    name: &str,
+    function: fn(f64) -> f64,
+) -> Result<Vec<Value>, Error> {
+    Ok(vec![Value::Number(function(check_number(
+        interpreter,
+        &arguments,
+        0,
+        name,
+    )?))])
+}
+
+fn math_abs(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "abs", f64::abs)
+}
+
+fn math_ceil(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "ceil", f64::ceil)
+}
+
+fn math_acos(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "acos", f64::acos)
+}
+
+fn math_asin(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "asin", f64::asin)
+}
+
+fn math_atan(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "atan", f64::atan)
+}
+
+fn math_atan2(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let y = check_number(interpreter, &arguments, 0, "atan2")?;
+    let x = check_number(interpreter, &arguments, 1, "atan2")?;
+
+    Ok(vec![Value::Number(y.atan2(x))])
+}
+
+fn math_cos(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "cos", f64::cos)
+}
+
+fn math_cosh(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "cosh", f64::cosh)
+}
+
+fn math_deg(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "deg", f64::to_degrees)
+}
+
+fn math_rad(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "rad", f64::to_radians)
+}
+
+fn math_sin(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "sin", f64::sin)
+}
+
+fn math_sinh(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "sinh", f64::sinh)
+}
+
+fn math_tan(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "tan", f64::tan)
+}
+
+fn math_tanh(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "tanh", f64::tanh)
+}
+
+fn math_exp(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "exp", f64::exp)
+}
+
+fn math_floor(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "floor", f64::floor)
+}
+
+fn math_log(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "log", f64::ln)
+}
+
+fn math_log10(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "log10", f64::log10)
+}
+
+fn math_sqrt(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    math_unary(interpreter, arguments, "sqrt", f64::sqrt)
+}
+
+fn math_fmod(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let a = check_number(interpreter, &arguments, 0, "fmod")?;
+    let b = check_number(interpreter, &arguments, 1, "fmod")?;
+
+    Ok(vec![Value::Number(a % b)])
+}
+
+fn math_pow(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    let a = check_number(interpreter, &arguments, 0, "pow")?;
+    let b = check_number(interpreter, &arguments, 1, "pow")?;
+
+    Ok(vec![Value::Number(a.powf(b))])
+}
+
+fn math_modf(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let n = check_number(interpreter, &arguments, 0, "modf")?;
+
+    Ok(vec![Value::Number(n.trunc()), Value::Number(n.fract())])
+}
+
+fn extremum(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+    name: &str,
+) -> Result<Vec<Value>, Error> {
+    let mut result = check_number(interpreter, &arguments, 0, name)?;
+
+    for i in 1..arguments.len() {
+        let n = check_number(interpreter, &arguments, i, name)?;
+
+        if (name == "max" && n > result) || (name == "min" && n < result) {
+            result = n;
+        }
+    }
+
+    Ok(vec![Value::Number(result)])
+}
+
+fn math_max(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    extremum(interpreter, arguments, "max")
+}
+
+fn math_min(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    extremum(interpreter, arguments, "min")
+}
+
+/// Seeds the generator behind `math.random`, which works like `lrand48`, so that scripts are deterministic.
+fn randomseed(interpreter: &mut Interpreter<'_>, seed: i64) {
+    interpreter.random_state = ((seed as u64) << 16 | 0x330e) & 0xffff_ffff_ffff;
+}
+
+fn math_randomseed(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let seed = check_integer(interpreter, &arguments, 0, "randomseed")?;
+
+    randomseed(interpreter, seed);
+
+    Ok(Vec::new())
+}
+
+fn math_random(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    interpreter.random_state = interpreter
+        .random_state
+        .wrapping_mul(0x5_deec_e66d)
+        .wrapping_add(0xb)
+        & 0xffff_ffff_ffff;
+
+    let r = (interpreter.random_state >> 17) as f64 / 2_147_483_648.0;
+
+    let (low, high) = match arguments.len() {
+        0 => return Ok(vec![Value::Number(r)]),
+        1 => (1, check_integer(interpreter, &arguments, 0, "random")?),
+        2 => (
+            check_integer(interpreter, &arguments, 0, "random")?,
+            check_integer(interpreter, &arguments, 1, "random")?,
+        ),
+        _ => return Err(interpreter.error("wrong number of arguments")),
+    };
+
+    if low > high {
+        return Err(bad_argument(
+            interpreter,
+            arguments.len() - 1,
+            "random",
+            "interval is empty",
+        ));
+    }
+
+    Ok(vec![Value::Number(
+        (r * (high - low + 1) as f64).floor() + low as f64,
+    )])
+}
+
+/// Converts a number to a signed 32-bit integer the way LuaBitOp does: rounded, then wrapped modulo 2^32.
+fn to_bit(n: f64) -> i32 {
+    if !n.is_finite() {
+        return 0;
+    }
+
+    n.round_ties_even().rem_euclid(4_294_967_296.0) as u32 as i32
+}
+
+fn check_bit(
+    interpreter: &Interpreter<'_>,
+    arguments: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<i32, Error> {
+    check_number(interpreter, arguments, index, name).map(to_bit)
+}
+
+fn bit_result(n: i32) -> Result<Vec<Value>, Error> {
+    Ok(vec![Value::Number(n as f64)])
+}
+
+fn bit_tobit(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    bit_result(check_bit(interpreter, &arguments, 0, "tobit")?)
+}
+
+fn bit_bnot(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    bit_result(!check_bit(interpreter, &arguments, 0, "bnot")?)
+}
+
+fn bit_bswap(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    bit_result(check_bit(interpreter, &arguments, 0, "bswap")?.swap_bytes())
+}
+
+/// Folds all the arguments of `band`, `bor` or `bxor` with `operation`.
+fn bit_fold(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+    name: &str,
+    operation: fn(i32, i32) -> i32,
+) -> Result<Vec<Value>, Error> {
+    let mut result = check_bit(interpreter, &arguments, 0, name)?;
+
+    for i in 1..arguments.len() {
+        result = operation(result, check_bit(interpreter, &arguments, i, name)?);
+    }
+
+    bit_result(result)
+}
+
+fn bit_band(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    bit_fold(interpreter, arguments, "band", |a, b| a & b)
+}
+
+fn bit_bor(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    bit_fold(interpreter, arguments, "bor", |a, b| a | b)
+}
+
+fn bit_bxor(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    bit_fold(interpreter, arguments, "bxor", |a, b| a ^ b)
+}
+
+/// Shifts or rotates the first argument by the second, of which only the low 5 bits count.
+fn bit_shift(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+    name: &str,
+    operation: fn(i32, u32) -> i32,
+) -> Result<Vec<Value>, Error> {
+    let n = check_bit(interpreter, &arguments, 0, name)?;
+    let by = check_bit(interpreter, &arguments, 1, name)? as u32 & 31;
+
+    bit_result(operation(n, by))
+}
+
+fn bit_lshift(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    bit_shift(interpreter, arguments, "lshift", |n, by| n << by)
+}
+
+fn bit_rshift(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    bit_shift(interpreter, arguments, "rshift", |n, by| {
+        ((n as u32) >> by) as i32
+    })
+}
+
+fn bit_arshift(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    bit_shift(interpreter, arguments, "arshift", |n, by| n >> by)
+}
+
+fn bit_rol(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    bit_shift(interpreter, arguments, "rol", i32::rotate_left)
+}
+
+fn bit_ror(interpreter: &mut Interpreter<'_>, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+    bit_shift(interpreter, arguments, "ror", i32::rotate_right)
+}
+
+/// `bit.tohex(x, n)`: the low `n` hex digits of `x` (8 by default), in upper case if `n` is negative.
+fn bit_tohex(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let n = check_bit(interpreter, &arguments, 0, "tohex")? as u32;
+    let digits = match arguments.get(1) {
+        None | Some(Value::Nil) => 8,
+        Some(_) => check_bit(interpreter, &arguments, 1, "tohex")?,
+    };
+    let width = digits.unsigned_abs().min(8) as usize;
+    let hex = if digits < 0 {
+        format!("{n:08X}")
+    } else {
+        format!("{n:08x}")
+    };
+
+    Ok(vec![Value::string(&hex[8 - width..])])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::interpreter::NoHost;
+    use super::super::parse;
+    use super::*;
+
+    fn run(source: &str) -> Vec<Value> {
+        let mut host = NoHost;
+        let mut interpreter = Interpreter::new("user_script", &mut host);
+
+        interpreter
+            .execute(parse(source.as_bytes()).unwrap(), Vec::new())
+            .unwrap_or_else(|error| panic!("{source}: {error:?}"))
+    }
+
+    fn strings(source: &str) -> Vec<String> {
+        run(source)
+            .iter()
+            .map(|value| String::from_utf8_lossy(&value.to_display()).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn strings_library() {
+        assert_eq!(
+            vec!["ell", "lo", "HELLO", "3"],
+            strings("local s = 'hello' return s:sub(2, 4), s:sub(-2), s:upper(), #s - 2")
+        );
+        assert_eq!(
+            vec!["7", "9", "wor"],
+            strings("return string.find('hello world', '(w%a%a)')")
+        );
+        assert_eq!(
+            vec!["key", "value"],
+            strings("return string.match('key=value', '(%w+)=(%w+)')")
+        );
+        assert_eq!(
+            vec!["hello Lua Lua", "2"],
+            strings("return string.gsub('hello world world', 'world', 'Lua')")
+        );
+        assert_eq!(
+            vec!["a-b-c"],
+            strings("return (string.gsub('a b c', '%s', '-'))")
+        );
+        assert_eq!(vec!["[x][y]"], strings("local out = '' for w in string.gmatch('x y', '%a') do out = out .. '[' .. w .. ']' end return out"));
+        assert_eq!(vec!["  42|-7|0x1f|3.14|1.5e+00|'x' "], strings("return string.format('%4d|%d|%#x|%.2f|%.1e|%-4s', 42, -7, 31, 3.14159, 1.5, \"'x'\")"));
+        assert_eq!(
+            vec!["\"a\\\"b\""],
+            strings("return string.format('%q', 'a\"b')")
+        );
+        assert_eq!(vec!["abab", "cba", "97", "A"], strings("return string.rep('ab', 2), string.reverse('abc'), string.byte('a'), string.char(65)"));
+    }
+
+    #[test]
+    fn tables_and_math() {
+        assert_eq!(
+            vec!["1,2,3,4"],
+            strings("local t = {3, 1, 4, 2} table.sort(t) return table.concat(t, ',')")
+        );
+        assert_eq!(vec!["4,3,2,1"], strings("local t = {3, 1, 4, 2} table.sort(t, function(a, b) return a > b end) return table.concat(t, ',')"));
+        assert_eq!(vec!["a,x,b", "b", "2"], strings("local t = {'a', 'b'} table.insert(t, 2, 'x') local s = table.concat(t, ',') return s, table.remove(t), #t"));
+        assert_eq!(vec!["3", "-4", "2", "1", "10"], strings("return math.floor(3.7), math.ceil(-4.2), math.max(1, 2), math.min(1, 2), tonumber('a', 16)"));
+        assert_eq!(
+            vec!["true"],
+            strings("local r = math.random(1, 10) return r >= 1 and r <= 10")
+        );
+        assert_eq!(vec!["1", "2", "nil"], strings("local t = {} for i, v in ipairs({1, 2, nil, 4}) do t[i] = v end return t[1], t[2], t[3]"));
+        assert_eq!(
+            vec!["0", "1", "180"],
+            strings("return math.sin(0), math.cos(0), math.deg(math.pi)")
+        );
+    }
+
+    #[test]
+    fn metatables() {
+        assert_eq!(
+            vec!["1", "nil"],
+            strings("local t = setmetatable({}, {__index = {a = 1}}) return t.a, rawget(t, 'a')")
+        );
+        assert_eq!(vec!["A"], strings("local t = setmetatable({}, {__index = function(t, k) return k:upper() end}) return t.a"));
+        assert_eq!(vec!["nil", "2"], strings("local log = {} local t = setmetatable({}, {__newindex = log}) t.x = 2 return rawget(t, 'x'), log.x"));
+        assert_eq!(vec!["3", "true", "true", "v3"], strings("local mt = {} mt.__add = function(a, b) return a.n + b.n end mt.__eq = function(a, b) return a.n == b.n end mt.__lt = function(a, b) return a.n < b.n end mt.__tostring = function(v) return 'v' .. v.n end local function new(n) return setmetatable({n = n}, mt) end return new(1) + new(2), new(1) == new(1), new(1) <= new(2), tostring(new(3))"));
+        assert_eq!(vec!["5"], strings("local t = setmetatable({}, {__call = function(self, a) return a + 1 end}) return t(4)"));
+        assert_eq!(vec!["true", "locked"], strings("local mt = {} local t = setmetatable({}, mt) local same = getmetatable(t) == mt mt.__metatable = 'locked' return same, getmetatable(t)"));
+        assert_eq!(vec!["false", "cannot change a protected metatable"], strings("local t = setmetatable({}, {__metatable = false}) local ok, e = pcall(setmetatable, t, {}) return ok, string.match(e, 'cannot.*')"));
+    }
+
+    #[test]
+    fn bit_library() {
+        assert_eq!(
+            vec!["15", "255", "-1", "-2147483648", "1"],
+            strings("return bit.band(0xff, 0x0f), bit.bor(0xf0, 0x0f), bit.tobit(0xffffffff), bit.lshift(1, 31), bit.rshift(-1, 31)")
+        );
+        assert_eq!(
+            vec!["-1", "6", "0", "-2"],
+            strings(
+                "return bit.arshift(-1, 4), bit.bxor(5, 3), bit.bnot(-1), bit.rol(0x7fffffff, 1)"
+            )
+        );
+        assert_eq!(
+            vec!["000000ff", "FF", "12345678", "2018915346"],
+            strings("return bit.tohex(255), bit.tohex(255, -2), bit.tohex(0x12345678), bit.bswap(0x12345678)")
+        );
+    }
+}
@@ -0,0 +1,21 @@
+mod ast;
+mod interpreter;
+mod lexer;
+mod library;
+mod number;
+mod parser;
+mod pattern;
+mod value;
+
+pub(crate) use ast::FunctionBody;
+pub(crate) use interpreter::{Error, Host, Interpreter, STACK_SIZE};
+pub(crate) use library::{check_number, check_string, library};
+pub(crate) use number::format_g;
+pub(crate) use parser::parse;
+pub(crate) use value::{Builtin, Table, Value};
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct SyntaxError {
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
@@ -0,0 +1,134 @@
+/// Parses a numeral the way Lua 5.1 does: decimal with an optional fraction and exponent, or a hexadecimal integer.
+/// Surrounding whitespace is allowed, which matters for `tonumber` and for strings used in arithmetic.
+pub(crate) fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let value = if let Some(hex) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    {
+        if hex.is_empty() || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        hex.bytes().fold(0.0, |value, digit| {
+            value * 16.0 + (digit as char).to_digit(16).unwrap() as f64
+        })
+    } else {
+        // Rust accepts words like "inf" and "nan", which aren't Lua numerals.
+        if !unsigned
+            .bytes()
+            .all(|c| c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'))
+            || !unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        {
+            return None;
+        }
+
+        unsigned.parse().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// Formats a number like C's `%.<precision>e`, e.g. `1.500000e+02`.
+pub(crate) fn format_e(value: f64, precision: usize) -> String {
+    if !value.is_finite() {
+        return format_special(value);
+    }
+
+    let formatted = format!("{value:.precision$e}");
+    let (mantissa, exponent) = formatted.split_once('e').expect("exponent");
+    let exponent: i32 = exponent.parse().expect("exponent");
+
+    format!(
+        "{mantissa}e{}{:02}",
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+/// Formats a number like C's `%.<precision>g`: with `precision` significant digits, in scientific notation only if
+/// the exponent is large, and without trailing zeros.
+pub(crate) fn format_g(value: f64, precision: usize) -> String {
+    if !value.is_finite() {
+        return format_special(value);
+    }
+
+    let precision = precision.max(1);
+
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+
+    // The exponent after rounding to the requested precision, which can differ from the exponent of `value`.
+    let exponent: i32 = format!("{value:.*e}", precision - 1)
+        .split_once('e')
+        .expect("exponent")
+        .1
+        .parse()
+        .expect("exponent");
+
+    if exponent < -4 || exponent >= precision as i32 {
+        let formatted = format_e(value, precision - 1);
+        let (mantissa, exponent) = formatted.split_once('e').expect("exponent");
+
+        format!("{}e{exponent}", strip_zeros(mantissa))
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+
+        strip_zeros(&format!("{value:.decimals$}")).to_string()
+    }
+}
+
+fn strip_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+fn format_special(value: f64) -> String {
+    match value {
+        v if v.is_nan() => "nan".to_string(),
+        v if v > 0.0 => "inf".to_string(),
+        _ => "-inf".to_string(),
+    }
+}
+
+/// Converts a number to a string, like `tostring` and concatenation do.
+pub(crate) fn format_number(value: f64) -> String {
+    format_g(value, 14)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(Some(10.0), parse_number(" 10 "));
+        assert_eq!(Some(255.0), parse_number("0xFF"));
+        assert_eq!(Some(-0.5), parse_number("-.5"));
+        assert_eq!(Some(1500.0), parse_number("1.5e3"));
+        assert_eq!(None, parse_number("inf"));
+        assert_eq!(None, parse_number("1x"));
+        assert_eq!(None, parse_number(""));
+
+        assert_eq!("10", format_number(10.0));
+        assert_eq!("0.1", format_number(0.1));
+        assert_eq!("3.1415926535898", format_number(std::f64::consts::PI));
+        assert_eq!("1e+15", format_number(1e15));
+        assert_eq!("123456789012", format_number(123456789012.0));
+        assert_eq!("1e-05", format_number(0.00001));
+        assert_eq!("-inf", format_number(f64::NEG_INFINITY));
+        assert_eq!("1.500000e+02", format_e(150.0, 6));
+        assert_eq!("1e+02", format_g(99.99, 2));
+        assert_eq!("99.99", format_g(99.99, 6));
+    }
+}
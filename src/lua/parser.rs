@@ -0,0 +1,1009 @@
+use std::sync::Arc;
+
+use super::ast::{
+    BinaryOperator, Block, Capture, Expression, Field, FunctionBody, Statement, UnaryOperator,
+};
+use super::lexer::{tokenize, Token};
+use super::SyntaxError;
+
+/// Expressions and blocks nested deeper than this are rejected rather than risking a stack overflow, like Lua's
+/// `LUAI_MAXCCALLS`.
+const MAX_DEPTH: usize = 200;
+
+/// The priority of unary operators, between multiplication and exponentiation.
+const UNARY_PRIORITY: u8 = 8;
+
+/// The state of a function that's being parsed.
+struct FunctionState {
+    /// The local variables in scope, innermost block last.
+    blocks: Vec<Vec<(String, usize)>>,
+    slots: usize,
+    upvalues: Vec<(String, Capture)>,
+    is_vararg: bool,
+    /// The number of loops around the current position, so that `break` outside a loop can be rejected.
+    loops: usize,
+}
+
+impl FunctionState {
+    fn new(is_vararg: bool) -> FunctionState {
+        FunctionState {
+            blocks: vec![Vec::new()],
+            slots: 0,
+            upvalues: Vec::new(),
+            is_vararg,
+            loops: 0,
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    functions: Vec<FunctionState>,
+    depth: usize,
+}
+
+/// Compiles a chunk into the body of its main function, which takes any number of arguments as `...`.
+pub(crate) fn parse(source: &[u8]) -> Result<Arc<FunctionBody>, SyntaxError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+        functions: vec![FunctionState::new(true)],
+        depth: 0,
+    };
+
+    let block = parser.block()?;
+
+    if parser.peek() != &Token::Eof {
+        return Err(parser.error_near("'<eof>' expected"));
+    }
+
+    let state = parser.functions.pop().expect("main function");
+
+    Ok(Arc::new(FunctionBody {
+        parameters: Vec::new(),
+        is_vararg: true,
+        slots: state.slots,
+        upvalues: Vec::new(),
+        block,
+    }))
+}
+
+fn binary_operator(token: &Token) -> Option<(BinaryOperator, u8, u8)> {
+    Some(match token {
+        Token::Or => (BinaryOperator::Or, 1, 1),
+        Token::And => (BinaryOperator::And, 2, 2),
+        Token::Less => (BinaryOperator::Less, 3, 3),
+        Token::Greater => (BinaryOperator::Greater, 3, 3),
+        Token::LessEqual => (BinaryOperator::LessEqual, 3, 3),
+        Token::GreaterEqual => (BinaryOperator::GreaterEqual, 3, 3),
+        Token::NotEqual => (BinaryOperator::NotEqual, 3, 3),
+        Token::Equal => (BinaryOperator::Equal, 3, 3),
+        // Concatenation and exponentiation are right associative.
+        Token::Concat => (BinaryOperator::Concat, 5, 4),
+        Token::Plus => (BinaryOperator::Add, 6, 6),
+        Token::Minus => (BinaryOperator::Subtract, 6, 6),
+        Token::Star => (BinaryOperator::Multiply, 7, 7),
+        Token::Slash => (BinaryOperator::Divide, 7, 7),
+        Token::Percent => (BinaryOperator::Modulo, 7, 7),
+        Token::Caret => (BinaryOperator::Power, 10, 9),
+        _ => return None,
+    })
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Name(name) => name.clone(),
+        Token::Number(n) => super::number::format_number(*n),
+        Token::String(s) => String::from_utf8_lossy(s).into_owned(),
+        Token::Eof => "<eof>".to_string(),
+        token => {
+            let text = match token {
+                Token::And => "and",
+                Token::Break => "break",
+                Token::Do => "do",
+                Token::Else => "else",
+                Token::ElseIf => "elseif",
+                Token::End => "end",
+                Token::False => "false",
+                Token::For => "for",
+                Token::Function => "function",
+                Token::If => "if",
+                Token::In => "in",
+                Token::Local => "local",
+                Token::Nil => "nil",
+                Token::Not => "not",
+                Token::Or => "or",
+                Token::Repeat => "repeat",
+                Token::Return => "return",
+                Token::Then => "then",
+                Token::True => "true",
+                Token::Until => "until",
+                Token::While => "while",
+                Token::Plus => "+",
+                Token::Minus => "-",
+                Token::Star => "*",
+                Token::Slash => "/",
+                Token::Percent => "%",
+                Token::Caret => "^",
+                Token::Hash => "#",
+                Token::Equal => "==",
+                Token::NotEqual => "~=",
+                Token::LessEqual => "<=",
+                Token::GreaterEqual => ">=",
+                Token::Less => "<",
+                Token::Greater => ">",
+                Token::Assign => "=",
+                Token::LeftParen => "(",
+                Token::RightParen => ")",
+                Token::LeftBrace => "{",
+                Token::RightBrace => "}",
+                Token::LeftBracket => "[",
+                Token::RightBracket => "]",
+                Token::Semicolon => ";",
+                Token::Colon => ":",
+                Token::Comma => ",",
+                Token::Dot => ".",
+                Token::Concat => "..",
+                Token::Ellipsis => "...",
+                _ => unreachable!(),
+            };
+
+            text.to_string()
+        }
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn peek_next(&self) -> &Token {
+        &self.tokens[(self.position + 1).min(self.tokens.len() - 1)].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.position].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+
+        if token != Token::Eof {
+            self.position += 1;
+        }
+
+        token
+    }
+
+    fn error_near(&self, message: &str) -> SyntaxError {
+        SyntaxError {
+            line: self.line(),
+            message: format!("{message} near '{}'", describe(self.peek())),
+        }
+    }
+
+    fn check(&mut self, token: Token) -> bool {
+        if self.peek() == &token {
+            self.advance();
+
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), SyntaxError> {
+        if self.check(token.clone()) {
+            Ok(())
+        } else {
+            Err(self.error_near(&format!("'{}' expected", describe(&token))))
+        }
+    }
+
+    /// Expects the token that closes a construct, mentioning where it was opened if that's on another line.
+    fn expect_match(
+        &mut self,
+        token: Token,
+        opening: Token,
+        line: usize,
+    ) -> Result<(), SyntaxError> {
+        if self.check(token.clone()) {
+            return Ok(());
+        }
+
+        if line == self.line() {
+            return self.expect(token);
+        }
+
+        Err(self.error_near(&format!(
+            "'{}' expected (to close '{}' at line {line})",
+            describe(&token),
+            describe(&opening)
+        )))
+    }
+
+    fn name(&mut self) -> Result<String, SyntaxError> {
+        match self.peek() {
+            Token::Name(_) => match self.advance() {
+                Token::Name(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => Err(self.error_near("<name> expected")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), SyntaxError> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            return Err(self.error_near("chunk has too many syntax levels"));
+        }
+
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn function(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("function")
+    }
+
+    fn declare(&mut self, name: String) -> usize {
+        let function = self.function();
+        let slot = function.slots;
+
+        function.slots += 1;
+        function
+            .blocks
+            .last_mut()
+            .expect("block")
+            .push((name, slot));
+
+        slot
+    }
+
+    fn resolve(&mut self, name: String) -> Expression {
+        match self.resolve_in(self.functions.len() - 1, &name) {
+            Some(Capture::Local(slot)) => Expression::Local(slot),
+            Some(Capture::Upvalue(index)) => Expression::Upvalue(index),
+            None => Expression::Global {
+                name,
+                line: self.line(),
+            },
+        }
+    }
+
+    fn resolve_in(&mut self, level: usize, name: &str) -> Option<Capture> {
+        let function = &self.functions[level];
+
+        for block in function.blocks.iter().rev() {
+            if let Some((_, slot)) = block.iter().rev().find(|(n, _)| n == name) {
+                return Some(Capture::Local(*slot));
+            }
+        }
+
+        if let Some(index) = function.upvalues.iter().position(|(n, _)| n == name) {
+            return Some(Capture::Upvalue(index));
+        }
+
+        if level == 0 {
+            return None;
+        }
+
+        let capture = self.resolve_in(level - 1, name)?;
+        let upvalues = &mut self.functions[level].upvalues;
+
+        upvalues.push((name.to_string(), capture));
+
+        Some(Capture::Upvalue(upvalues.len() - 1))
+    }
+
+    fn block_follows(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Else | Token::ElseIf | Token::End | Token::Until | Token::Eof
+        )
+    }
+
+    /// Parses statements in a new scope.
+    fn block(&mut self) -> Result<Block, SyntaxError> {
+        self.function().blocks.push(Vec::new());
+
+        let block = self.statements();
+
+        self.function().blocks.pop();
+
+        block
+    }
+
+    fn statements(&mut self) -> Result<Block, SyntaxError> {
+        self.enter()?;
+
+        let mut block = Vec::new();
+
+        while !self.block_follows() {
+            if self.peek() == &Token::Return {
+                self.advance();
+
+                let values = match self.block_follows() || self.peek() == &Token::Semicolon {
+                    true => Vec::new(),
+                    false => self.expression_list()?,
+                };
+
+                self.check(Token::Semicolon);
+                block.push(Statement::Return(values));
+
+                if !self.block_follows() {
+                    return Err(self.error_near("'<eof>' expected"));
+                }
+
+                break;
+            }
+
+            if let Some(statement) = self.statement()? {
+                block.push(statement);
+            }
+        }
+
+        self.leave();
+
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<Option<Statement>, SyntaxError> {
+        let line = self.line();
+
+        let statement = match self.peek() {
+            Token::Semicolon => {
+                self.advance();
+
+                return Ok(None);
+            }
+            Token::If => self.if_statement(line)?,
+            Token::While => {
+                self.advance();
+
+                let condition = self.expression()?;
+
+                self.expect(Token::Do)?;
+
+                let block = self.loop_block()?;
+
+                self.expect_match(Token::End, Token::While, line)?;
+
+                Statement::While { condition, block }
+            }
+            Token::Do => {
+                self.advance();
+
+                let block = self.block()?;
+
+                self.expect_match(Token::End, Token::Do, line)?;
+
+                Statement::Do(block)
+            }
+            Token::For => self.for_statement(line)?,
+            Token::Repeat => {
+                self.advance();
+
+                // The condition can refer to the block's local variables, so they share a scope.
+                self.function().blocks.push(Vec::new());
+                self.function().loops += 1;
+
+                let block = self.statements();
+
+                self.function().loops -= 1;
+
+                let block = block?;
+
+                self.expect_match(Token::Until, Token::Repeat, line)?;
+
+                let condition = self.expression();
+
+                self.function().blocks.pop();
+
+                Statement::Repeat {
+                    block,
+                    condition: condition?,
+                }
+            }
+            Token::Function => {
+                self.advance();
+
+                let mut target = self.name().map(|name| self.resolve(name))?;
+                let mut is_method = false;
+
+                loop {
+                    let key_line = self.line();
+
+                    let is_dot = self.check(Token::Dot);
+
+                    if !is_dot {
+                        if !self.check(Token::Colon) {
+                            break;
+                        }
+
+                        is_method = true;
+                    }
+
+                    let key = self.name()?;
+
+                    target = Expression::Index {
+                        table: Box::new(target),
+                        key: Box::new(Expression::String(key.into_bytes())),
+                        line: key_line,
+                    };
+
+                    if is_method {
+                        break;
+                    }
+                }
+
+                let body = self.function_body(is_method, line)?;
+
+                Statement::Assign {
+                    targets: vec![target],
+                    values: vec![Expression::Function(body)],
+                    line,
+                }
+            }
+            Token::Local => {
+                self.advance();
+
+                if self.check(Token::Function) {
+                    let name = self.name()?;
+                    let slot = self.declare(name);
+                    let body = self.function_body(false, line)?;
+
+                    Statement::LocalFunction { slot, body }
+                } else {
+                    let mut names = vec![self.name()?];
+
+                    while self.check(Token::Comma) {
+                        names.push(self.name()?);
+                    }
+
+                    let values = match self.check(Token::Assign) {
+                        true => self.expression_list()?,
+                        false => Vec::new(),
+                    };
+
+                    // The variables are only in scope after the statement, so `local x = x` reads the outer `x`.
+                    let slots = names.into_iter().map(|name| self.declare(name)).collect();
+
+                    Statement::Local { slots, values }
+                }
+            }
+            Token::Break => {
+                self.advance();
+
+                if self.function().loops == 0 {
+                    return Err(SyntaxError {
+                        line,
+                        message: "no loop to break".to_string(),
+                    });
+                }
+
+                Statement::Break
+            }
+            _ => self.expression_statement()?,
+        };
+
+        Ok(Some(statement))
+    }
+
+    fn loop_block(&mut self) -> Result<Block, SyntaxError> {
+        self.function().loops += 1;
+
+        let block = self.block();
+
+        self.function().loops -= 1;
+
+        block
+    }
+
+    fn if_statement(&mut self, line: usize) -> Result<Statement, SyntaxError> {
+        self.advance();
+
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+
+        loop {
+            let condition = self.expression()?;
+
+            self.expect(Token::Then)?;
+            branches.push((condition, self.block()?));
+
+            match self.peek() {
+                Token::ElseIf => {
+                    self.advance();
+                }
+                Token::Else => {
+                    self.advance();
+                    otherwise = Some(self.block()?);
+                    self.expect_match(Token::End, Token::If, line)?;
+
+                    break;
+                }
+                _ => {
+                    self.expect_match(Token::End, Token::If, line)?;
+
+                    break;
+                }
+            }
+        }
+
+        Ok(Statement::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn for_statement(&mut self, line: usize) -> Result<Statement, SyntaxError> {
+        self.advance();
+
+        let first = self.name()?;
+
+        if self.check(Token::Assign) {
+            let start = self.expression()?;
+
+            self.expect(Token::Comma)?;
+
+            let limit = self.expression()?;
+            let step = match self.check(Token::Comma) {
+                true => Some(self.expression()?),
+                false => None,
+            };
+
+            self.expect(Token::Do)?;
+            self.function().blocks.push(Vec::new());
+
+            let slot = self.declare(first);
+            let block = self.loop_block();
+
+            self.function().blocks.pop();
+            self.expect_match(Token::End, Token::For, line)?;
+
+            return Ok(Statement::NumericFor {
+                slot,
+                start,
+                limit,
+                step,
+                block: block?,
+                line,
+            });
+        }
+
+        let mut names = vec![first];
+
+        while self.check(Token::Comma) {
+            names.push(self.name()?);
+        }
+
+        if !self.check(Token::In) {
+            return Err(self.error_near("'=' or 'in' expected"));
+        }
+
+        let values = self.expression_list()?;
+
+        self.expect(Token::Do)?;
+        self.function().blocks.push(Vec::new());
+
+        let slots = names.into_iter().map(|name| self.declare(name)).collect();
+        let block = self.loop_block();
+
+        self.function().blocks.pop();
+        self.expect_match(Token::End, Token::For, line)?;
+
+        Ok(Statement::GenericFor {
+            slots,
+            values,
+            block: block?,
+            line,
+        })
+    }
+
+    fn expression_statement(&mut self) -> Result<Statement, SyntaxError> {
+        let line = self.line();
+        let expression = self.suffixed_expression()?;
+
+        if matches!(expression, Expression::Call { .. })
+            && !matches!(self.peek(), Token::Assign | Token::Comma)
+        {
+            return Ok(Statement::Call(expression));
+        }
+
+        let mut targets = vec![expression];
+
+        while self.check(Token::Comma) {
+            targets.push(self.suffixed_expression()?);
+        }
+
+        if targets.iter().any(|target| {
+            !matches!(
+                target,
+                Expression::Local(_)
+                    | Expression::Upvalue(_)
+                    | Expression::Global { .. }
+                    | Expression::Index { .. }
+            )
+        }) {
+            return Err(self.error_near("syntax error"));
+        }
+
+        self.expect(Token::Assign)?;
+
+        let values = self.expression_list()?;
+
+        Ok(Statement::Assign {
+            targets,
+            values,
+            line,
+        })
+    }
+
+    fn function_body(
+        &mut self,
+        is_method: bool,
+        line: usize,
+    ) -> Result<Arc<FunctionBody>, SyntaxError> {
+        self.functions.push(FunctionState::new(false));
+
+        let body = self.function_body_inner(is_method, line);
+        let state = self.functions.pop().expect("function");
+        let (parameters, block) = body?;
+
+        Ok(Arc::new(FunctionBody {
+            parameters,
+            is_vararg: state.is_vararg,
+            slots: state.slots,
+            upvalues: state
+                .upvalues
+                .into_iter()
+                .map(|(_, capture)| capture)
+                .collect(),
+            block,
+        }))
+    }
+
+    fn function_body_inner(
+        &mut self,
+        is_method: bool,
+        line: usize,
+    ) -> Result<(Vec<usize>, Block), SyntaxError> {
+        let mut parameters = Vec::new();
+
+        if is_method {
+            parameters.push(self.declare("self".to_string()));
+        }
+
+        self.expect(Token::LeftParen)?;
+
+        if !self.check(Token::RightParen) {
+            loop {
+                if self.check(Token::Ellipsis) {
+                    self.function().is_vararg = true;
+
+                    break;
+                }
+
+                let name = self.name()?;
+
+                parameters.push(self.declare(name));
+
+                if !self.check(Token::Comma) {
+                    break;
+                }
+            }
+
+            self.expect(Token::RightParen)?;
+        }
+
+        let block = self.statements()?;
+
+        self.expect_match(Token::End, Token::Function, line)?;
+
+        Ok((parameters, block))
+    }
+
+    fn expression_list(&mut self) -> Result<Vec<Expression>, SyntaxError> {
+        let mut expressions = vec![self.expression()?];
+
+        while self.check(Token::Comma) {
+            expressions.push(self.expression()?);
+        }
+
+        Ok(expressions)
+    }
+
+    pub(crate) fn expression(&mut self) -> Result<Expression, SyntaxError> {
+        self.subexpression(0)
+    }
+
+    /// Parses an expression whose binary operators all have a priority above `limit`.
+    fn subexpression(&mut self, limit: u8) -> Result<Expression, SyntaxError> {
+        self.enter()?;
+
+        let unary = match self.peek() {
+            Token::Not => Some(UnaryOperator::Not),
+            Token::Minus => Some(UnaryOperator::Negate),
+            Token::Hash => Some(UnaryOperator::Length),
+            _ => None,
+        };
+
+        let mut left = match unary {
+            Some(operator) => {
+                let line = self.line();
+
+                self.advance();
+
+                match (operator, self.subexpression(UNARY_PRIORITY)?) {
+                    (UnaryOperator::Negate, Expression::Number(n)) => Expression::Number(-n),
+                    (operator, operand) => Expression::Unary {
+                        operator,
+                        operand: Box::new(operand),
+                        line,
+                    },
+                }
+            }
+            None => self.simple_expression()?,
+        };
+
+        while let Some((operator, left_priority, right_priority)) = binary_operator(self.peek()) {
+            if left_priority <= limit {
+                break;
+            }
+
+            let line = self.line();
+
+            self.advance();
+
+            let right = self.subexpression(right_priority)?;
+
+            left = Expression::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+                line,
+            };
+        }
+
+        self.leave();
+
+        Ok(left)
+    }
+
+    fn simple_expression(&mut self) -> Result<Expression, SyntaxError> {
+        let expression = match self.peek() {
+            Token::Number(n) => Expression::Number(*n),
+            Token::String(_) => match self.advance() {
+                Token::String(s) => return Ok(Expression::String(s)),
+                _ => unreachable!(),
+            },
+            Token::Nil => Expression::Nil,
+            Token::True => Expression::True,
+            Token::False => Expression::False,
+            Token::Ellipsis => {
+                if !self.function().is_vararg {
+                    return Err(self.error_near("cannot use '...' outside a vararg function"));
+                }
+
+                Expression::Vararg
+            }
+            Token::LeftBrace => return self.table_constructor(),
+            Token::Function => {
+                let line = self.line();
+
+                self.advance();
+
+                return Ok(Expression::Function(self.function_body(false, line)?));
+            }
+            _ => return self.suffixed_expression(),
+        };
+
+        self.advance();
+
+        Ok(expression)
+    }
+
+    fn primary_expression(&mut self) -> Result<Expression, SyntaxError> {
+        match self.peek() {
+            Token::Name(_) => {
+                let name = self.name()?;
+
+                Ok(self.resolve(name))
+            }
+            Token::LeftParen => {
+                let line = self.line();
+
+                self.advance();
+
+                let expression = self.expression()?;
+
+                self.expect_match(Token::RightParen, Token::LeftParen, line)?;
+
+                Ok(Expression::Parenthesized(Box::new(expression)))
+            }
+            _ => Err(self.error_near("unexpected symbol")),
+        }
+    }
+
+    fn suffixed_expression(&mut self) -> Result<Expression, SyntaxError> {
+        self.enter()?;
+
+        let mut expression = self.primary_expression()?;
+
+        loop {
+            let line = self.line();
+
+            expression = match self.peek() {
+                Token::Dot => {
+                    self.advance();
+
+                    Expression::Index {
+                        table: Box::new(expression),
+                        key: Box::new(Expression::String(self.name()?.into_bytes())),
+                        line,
+                    }
+                }
+                Token::LeftBracket => {
+                    self.advance();
+
+                    let key = self.expression()?;
+
+                    self.expect(Token::RightBracket)?;
+
+                    Expression::Index {
+                        table: Box::new(expression),
+                        key: Box::new(key),
+                        line,
+                    }
+                }
+                Token::Colon => {
+                    self.advance();
+
+                    let method = self.name()?.into_bytes();
+
+                    Expression::Call {
+                        function: Box::new(expression),
+                        method: Some(method),
+                        arguments: self.call_arguments()?,
+                        line,
+                    }
+                }
+                Token::LeftParen | Token::String(_) | Token::LeftBrace => Expression::Call {
+                    function: Box::new(expression),
+                    method: None,
+                    arguments: self.call_arguments()?,
+                    line,
+                },
+                _ => break,
+            };
+        }
+
+        self.leave();
+
+        Ok(expression)
+    }
+
+    fn call_arguments(&mut self) -> Result<Vec<Expression>, SyntaxError> {
+        match self.peek() {
+            Token::String(_) => match self.advance() {
+                Token::String(s) => Ok(vec![Expression::String(s)]),
+                _ => unreachable!(),
+            },
+            Token::LeftBrace => Ok(vec![self.table_constructor()?]),
+            Token::LeftParen => {
+                let line = self.line();
+
+                self.advance();
+
+                if self.check(Token::RightParen) {
+                    return Ok(Vec::new());
+                }
+
+                let arguments = self.expression_list()?;
+
+                self.expect_match(Token::RightParen, Token::LeftParen, line)?;
+
+                Ok(arguments)
+            }
+            _ => Err(self.error_near("function arguments expected")),
+        }
+    }
+
+    fn table_constructor(&mut self) -> Result<Expression, SyntaxError> {
+        let line = self.line();
+
+        self.expect(Token::LeftBrace)?;
+
+        let mut fields = Vec::new();
+
+        while self.peek() != &Token::RightBrace {
+            let field = match self.peek() {
+                Token::LeftBracket => {
+                    self.advance();
+
+                    let key = self.expression()?;
+
+                    self.expect(Token::RightBracket)?;
+                    self.expect(Token::Assign)?;
+
+                    Field::Named(key, self.expression()?)
+                }
+                Token::Name(_) if self.peek_next() == &Token::Assign => {
+                    let name = self.name()?;
+
+                    self.advance();
+
+                    Field::Named(Expression::String(name.into_bytes()), self.expression()?)
+                }
+                _ => Field::Positional(self.expression()?),
+            };
+
+            fields.push(field);
+
+            if !self.check(Token::Comma) && !self.check(Token::Semicolon) {
+                break;
+            }
+        }
+
+        self.expect_match(Token::RightBrace, Token::LeftBrace, line)?;
+
+        Ok(Expression::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        let error = parse(source.as_bytes()).unwrap_err();
+
+        format!("{}: {}", error.line, error.message)
+    }
+
+    #[test]
+    fn scopes_and_upvalues() {
+        let body =
+            parse(b"local a = 1 local function f() return a end local a = a return f").unwrap();
+
+        assert_eq!(3, body.slots);
+
+        let Statement::LocalFunction { body: f, .. } = &body.block[1] else {
+            panic!("expected a local function");
+        };
+
+        assert_eq!(vec![Capture::Local(0)], f.upvalues);
+
+        let Statement::Local { values, .. } = &body.block[2] else {
+            panic!("expected a local statement");
+        };
+
+        assert!(matches!(values[0], Expression::Local(0)));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!("1: '=' expected near 'y'", error("x y"));
+        assert_eq!("1: no loop to break", error("break"));
+        assert_eq!(
+            "3: 'end' expected (to close 'if' at line 1) near '<eof>'",
+            error("if x then\n\n")
+        );
+        assert_eq!("1: unexpected symbol near ')'", error("x = )"));
+        assert_eq!(
+            "1: cannot use '...' outside a vararg function near '...'",
+            error("function f() return ... end")
+        );
+        assert_eq!(
+            "1: chunk has too many syntax levels near '('",
+            error(&format!("x = {}", "(".repeat(300)))
+        );
+    }
+}
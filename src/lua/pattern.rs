@@ -0,0 +1,469 @@
+/// How deeply matching can recurse before a pattern is rejected as too complex.
+const MAX_DEPTH: usize = 200;
+
+const UNFINISHED: isize = -1;
+const POSITION: isize = -2;
+
+/// A capture of a successful match.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Captured {
+    /// A position capture `()`, which is 1-based like string indices in Lua.
+    Position(usize),
+    /// The start and end of a captured substring.
+    Range(usize, usize),
+}
+
+/// A successful match of a pattern.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Match {
+    pub(crate) end: usize,
+    pub(crate) captures: Vec<Captured>,
+}
+
+struct State<'a> {
+    source: &'a [u8],
+    pattern: &'a [u8],
+    /// The start of each capture, and its length, [`UNFINISHED`] or [`POSITION`].
+    captures: Vec<(usize, isize)>,
+    depth: usize,
+}
+
+/// Whether a pattern needs the matcher, rather than a plain search.
+pub(crate) fn has_specials(pattern: &[u8]) -> bool {
+    pattern.iter().any(|c| b"^$*+?.([%-".contains(c))
+}
+
+/// Matches `pattern` (without a leading `^`) against `source`, starting exactly at `start`.
+pub(crate) fn match_at(
+    source: &[u8],
+    pattern: &[u8],
+    start: usize,
+) -> Result<Option<Match>, String> {
+    let mut state = State {
+        source,
+        pattern,
+        captures: Vec::new(),
+        depth: 0,
+    };
+
+    let Some(end) = state.do_match(start, 0)? else {
+        return Ok(None);
+    };
+
+    let captures = state
+        .captures
+        .iter()
+        .map(|&(start, length)| match length {
+            POSITION => Ok(Captured::Position(start + 1)),
+            UNFINISHED => Err("unfinished capture".to_string()),
+            length => Ok(Captured::Range(start, start + length as usize)),
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(Match { end, captures }))
+}
+
+/// Searches `source` for `pattern` from `init`, honouring a leading `^`. Returns the start of the match too.
+pub(crate) fn find(
+    source: &[u8],
+    pattern: &[u8],
+    init: usize,
+) -> Result<Option<(usize, Match)>, String> {
+    let (anchored, pattern) = match pattern.strip_prefix(b"^") {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+
+    let mut start = init;
+
+    loop {
+        if let Some(found) = match_at(source, pattern, start)? {
+            return Ok(Some((start, found)));
+        }
+
+        start += 1;
+
+        if anchored || start > source.len() {
+            return Ok(None);
+        }
+    }
+}
+
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r')
+}
+
+/// Whether `c` is in the class `%class`.
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => is_space(c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+impl State<'_> {
+    /// Returns the end of the single-character class that starts at `p`.
+    fn class_end(&self, p: usize) -> Result<usize, String> {
+        let pattern = self.pattern;
+        let c = pattern[p];
+        let mut p = p + 1;
+
+        if c == b'%' {
+            if p >= pattern.len() {
+                return Err("malformed pattern (ends with '%')".to_string());
+            }
+
+            return Ok(p + 1);
+        }
+
+        if c == b'[' {
+            if pattern.get(p) == Some(&b'^') {
+                p += 1;
+            }
+
+            // The first character can be a `]`, which doesn't end the set.
+            loop {
+                if p >= pattern.len() {
+                    return Err("malformed pattern (missing ']')".to_string());
+                }
+
+                let c = pattern[p];
+
+                p += 1;
+
+                if c == b'%' && p < pattern.len() {
+                    p += 1;
+                }
+
+                if p >= pattern.len() {
+                    return Err("malformed pattern (missing ']')".to_string());
+                }
+
+                if pattern[p] == b']' {
+                    return Ok(p + 1);
+                }
+            }
+        }
+
+        Ok(p)
+    }
+
+    /// Whether `c` is in the set that starts with `[` at `p` and ends with `]` at `end`.
+    fn match_bracket_class(&self, c: u8, p: usize, end: usize) -> bool {
+        let pattern = self.pattern;
+        let mut p = p;
+        let mut matches = true;
+
+        if pattern[p + 1] == b'^' {
+            matches = false;
+            p += 1;
+        }
+
+        loop {
+            p += 1;
+
+            if p >= end {
+                return !matches;
+            }
+
+            if pattern[p] == b'%' {
+                p += 1;
+
+                if match_class(c, pattern[p]) {
+                    return matches;
+                }
+            } else if pattern[p + 1] == b'-' && p + 2 < end {
+                p += 2;
+
+                if pattern[p - 2] <= c && c <= pattern[p] {
+                    return matches;
+                }
+            } else if pattern[p] == c {
+                return matches;
+            }
+        }
+    }
+
+    fn single_match(&self, s: usize, p: usize, end: usize) -> bool {
+        let Some(&c) = self.source.get(s) else {
+            return false;
+        };
+
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, end - 1),
+            other => other == c,
+        }
+    }
+
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            return Err("pattern too complex".to_string());
+        }
+
+        let result = self.do_match_inner(s, p);
+
+        self.depth -= 1;
+
+        result
+    }
+
+    fn do_match_inner(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        let pattern = self.pattern;
+
+        loop {
+            if p == pattern.len() {
+                return Ok(Some(s));
+            }
+
+            match pattern[p] {
+                b'(' => {
+                    return if pattern.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, POSITION)
+                    } else {
+                        self.start_capture(s, p + 1, UNFINISHED)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == pattern.len() => {
+                    return Ok((s == self.source.len()).then_some(s));
+                }
+                b'%' if pattern.get(p + 1) == Some(&b'b') => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                b'%' if pattern.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+
+                    if pattern.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+
+                    let end = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.source[s - 1] };
+                    let current = self.source.get(s).copied().unwrap_or(0);
+
+                    if !self.match_bracket_class(previous, p, end - 1)
+                        && self.match_bracket_class(current, p, end - 1)
+                    {
+                        p = end;
+                        continue;
+                    }
+
+                    return Ok(None);
+                }
+                b'%' if pattern.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    match self.match_capture(s, pattern[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => {}
+            }
+
+            let end = self.class_end(p)?;
+            let matches = self.single_match(s, p, end);
+
+            match pattern.get(end) {
+                Some(b'?') => {
+                    if matches {
+                        if let Some(result) = self.do_match(s + 1, end + 1)? {
+                            return Ok(Some(result));
+                        }
+                    }
+
+                    p = end + 1;
+                }
+                Some(b'*') => return self.max_expand(s, p, end),
+                Some(b'+') => {
+                    return if matches {
+                        self.max_expand(s + 1, p, end)
+                    } else {
+                        Ok(None)
+                    };
+                }
+                Some(b'-') => return self.min_expand(s, p, end),
+                _ => {
+                    if !matches {
+                        return Ok(None);
+                    }
+
+                    s += 1;
+                    p = end;
+                }
+            }
+        }
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let (Some(&open), Some(&close)) = (self.pattern.get(p), self.pattern.get(p + 1)) else {
+            return Err("unbalanced pattern".to_string());
+        };
+
+        if self.source.get(s) != Some(&open) {
+            return Ok(None);
+        }
+
+        let mut depth = 1;
+
+        for (i, &c) in self.source.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut count = 0;
+
+        while self.single_match(s + count, p, end) {
+            count += 1;
+        }
+
+        loop {
+            if let Some(result) = self.do_match(s + count, end + 1)? {
+                return Ok(Some(result));
+            }
+
+            if count == 0 {
+                return Ok(None);
+            }
+
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(result) = self.do_match(s, end + 1)? {
+                return Ok(Some(result));
+            }
+
+            if self.single_match(s, p, end) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, kind: isize) -> Result<Option<usize>, String> {
+        self.captures.push((s, kind));
+
+        let result = self.do_match(s, p)?;
+
+        if result.is_none() {
+            self.captures.pop();
+        }
+
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let index = self
+            .captures
+            .iter()
+            .rposition(|&(_, length)| length == UNFINISHED)
+            .ok_or_else(|| "invalid pattern capture".to_string())?;
+
+        self.captures[index].1 = (s - self.captures[index].0) as isize;
+
+        let result = self.do_match(s, p)?;
+
+        if result.is_none() {
+            self.captures[index].1 = UNFINISHED;
+        }
+
+        Ok(result)
+    }
+
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let index = (digit as usize).wrapping_sub(b'1' as usize);
+
+        let (start, length) = match self.captures.get(index) {
+            Some(&(start, length)) if length != UNFINISHED => (start, length.max(0) as usize),
+            _ => return Err("invalid capture index".to_string()),
+        };
+
+        let captured = &self.source[start..start + length];
+
+        Ok(self.source[s..].starts_with(captured).then_some(s + length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched(source: &str, pattern: &str) -> Option<(usize, usize)> {
+        find(source.as_bytes(), pattern.as_bytes(), 0)
+            .unwrap()
+            .map(|(start, found)| (start, found.end))
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(Some((6, 11)), matched("hello world", "w%a+"));
+        assert_eq!(Some((0, 3)), matched("123abc", "^%d+"));
+        assert_eq!(None, matched("abc123", "^%d+"));
+        assert_eq!(Some((3, 6)), matched("abc123", "%d+$"));
+        assert_eq!(Some((1, 8)), matched("x(a(b)c)y", "%b()"));
+        assert_eq!(Some((0, 2)), matched("ab-cd", "[%a]-b"));
+        assert_eq!(Some((5, 10)), matched("THE (quick)", "%f[%a]%l+"));
+        assert_eq!(Some((4, 9)), matched("THE quick", "%f[%a]%l+"));
+        assert_eq!(Some((0, 6)), matched("abcabc", "(abc)%1"));
+
+        let (_, found) = find(b"key=value", b"(%w+)=()(%w+)", 0).unwrap().unwrap();
+
+        assert_eq!(
+            vec![
+                Captured::Range(0, 3),
+                Captured::Position(5),
+                Captured::Range(4, 9)
+            ],
+            found.captures
+        );
+        assert_eq!(
+            Err("malformed pattern (missing ']')".to_string()),
+            find(b"a", b"[a", 0)
+        );
+        assert_eq!(
+            Err("malformed pattern (ends with '%')".to_string()),
+            find(b"a", b"a%", 0)
+        );
+    }
+}
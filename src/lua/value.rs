@@ -0,0 +1,449 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::ast::FunctionBody;
+use super::interpreter::{Error, Interpreter};
+use super::number::{format_number, parse_number};
+
+pub(crate) type BuiltinFunction = fn(&mut Interpreter<'_>, Vec<Value>) -> Result<Vec<Value>, Error>;
+
+/// A function implemented in Rust.
+pub(crate) struct Builtin {
+    pub(crate) function: BuiltinFunction,
+    /// A value passed as the first argument, for builtins that keep state between calls like `string.gmatch`'s
+    /// iterators.
+    pub(crate) bound: Option<Value>,
+}
+
+/// A Lua function together with the variables it captured.
+pub(crate) struct Closure {
+    pub(crate) body: Arc<FunctionBody>,
+    pub(crate) upvalues: Vec<Rc<RefCell<Value>>>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<[u8]>),
+    Table(Rc<RefCell<Table>>),
+    Function(Rc<Closure>),
+    Builtin(Rc<Builtin>),
+    /// A function provided by the host, like `redis.call`, which is run by [`Host::call`](super::Host::call).
+    HostFunction(&'static str),
+}
+
+impl Value {
+    pub(crate) fn string(bytes: impl AsRef<[u8]>) -> Value {
+        Value::String(Rc::from(bytes.as_ref()))
+    }
+
+    pub(crate) fn table(table: Table) -> Value {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub(crate) fn builtin(function: BuiltinFunction) -> Value {
+        Value::Builtin(Rc::new(Builtin {
+            function,
+            bound: None,
+        }))
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) | Value::Builtin(_) | Value::HostFunction(_) => "function",
+        }
+    }
+
+    pub(crate) fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub(crate) fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// Converts numbers, and strings that contain numerals, to numbers.
+    pub(crate) fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::String(s) => std::str::from_utf8(s).ok().and_then(parse_number),
+            _ => None,
+        }
+    }
+
+    /// Converts strings and numbers to strings.
+    pub(crate) fn to_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Value::String(s) => Some(Cow::Borrowed(s)),
+            Value::Number(n) => Some(Cow::Owned(format_number(*n).into_bytes())),
+            _ => None,
+        }
+    }
+
+    /// Converts any value to a string, like `tostring`.
+    pub(crate) fn to_display(&self) -> Vec<u8> {
+        match self {
+            Value::Nil => b"nil".to_vec(),
+            Value::Boolean(b) => b.to_string().into_bytes(),
+            Value::Number(n) => format_number(*n).into_bytes(),
+            Value::String(s) => s.to_vec(),
+            Value::Table(t) => format!("table: {:p}", Rc::as_ptr(t)).into_bytes(),
+            Value::Function(f) => format!("function: {:p}", Rc::as_ptr(f)).into_bytes(),
+            Value::Builtin(b) => format!("function: builtin: {:p}", Rc::as_ptr(b)).into_bytes(),
+            Value::HostFunction(name) => format!("function: {name}").into_bytes(),
+        }
+    }
+
+    /// Compares values without metamethods: by value for numbers, strings and booleans, by identity otherwise.
+    pub(crate) fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => Rc::ptr_eq(a, b),
+            (Value::HostFunction(a), Value::HostFunction(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.raw_equals(other)
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            value => f.write_str(&String::from_utf8_lossy(&value.to_display())),
+        }
+    }
+}
+
+/// A table key. Numbers are compared by value, with `-0` and `0` being the same key, and other values like
+/// [`Value::raw_equals`] does. `nil` and NaN can't be keys.
+#[derive(Clone)]
+struct Key(Value);
+
+impl Key {
+    fn new(value: Value) -> Key {
+        match value {
+            Value::Number(0.0) => Key(Value::Number(0.0)),
+            value => Key(value),
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.0.raw_equals(&other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Value::Nil => 0.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Number(n) => n.to_bits().hash(state),
+            Value::String(s) => s.hash(state),
+            Value::Table(t) => Rc::as_ptr(t).hash(state),
+            Value::Function(f) => Rc::as_ptr(f).hash(state),
+            Value::Builtin(b) => Rc::as_ptr(b).hash(state),
+            Value::HostFunction(name) => name.hash(state),
+        }
+    }
+}
+
+/// A Lua table. Keys `1..=n` of a sequence are stored in an array; other keys are kept in insertion order, so that
+/// iterating over a table is deterministic.
+#[derive(Default)]
+pub(crate) struct Table {
+    array: Vec<Value>,
+    /// Entries set to `nil` stay until the table is compacted, so that `next` still finds them during a traversal.
+    entries: Vec<(Key, Value)>,
+    index: HashMap<Key, usize>,
+    removed: usize,
+    /// Set with `setmetatable`.
+    pub(crate) metatable: Option<Rc<RefCell<Table>>>,
+}
+
+/// The array index that `key` refers to, if it's a positive integer.
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(n) if n.fract() == 0.0 && *n >= 1.0 && *n < usize::MAX as f64 => {
+            Some(*n as usize)
+        }
+        _ => None,
+    }
+}
+
+impl Table {
+    pub(crate) fn new() -> Table {
+        Table::default()
+    }
+
+    /// Creates a sequence.
+    pub(crate) fn from_values(values: Vec<Value>) -> Table {
+        let mut table = Table::new();
+
+        for value in values {
+            table.push(value);
+        }
+
+        table
+    }
+
+    pub(crate) fn get(&self, key: &Value) -> Value {
+        if let Some(index) = array_index(key) {
+            if index <= self.array.len() {
+                return self.array[index - 1].clone();
+            }
+        }
+
+        match key {
+            Value::Nil => Value::Nil,
+            key => self
+                .index
+                .get(&Key::new(key.clone()))
+                .map_or(Value::Nil, |&i| self.entries[i].1.clone()),
+        }
+    }
+
+    pub(crate) fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::string(key))
+    }
+
+    pub(crate) fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        match key {
+            Value::Nil => return Err("table index is nil"),
+            Value::Number(n) if n.is_nan() => return Err("table index is NaN"),
+            _ => {}
+        }
+
+        if let Some(index) = array_index(&key) {
+            if index <= self.array.len() {
+                self.array[index - 1] = value;
+
+                while self.array.last().is_some_and(Value::is_nil) {
+                    self.array.pop();
+                }
+
+                return Ok(());
+            }
+
+            if index == self.array.len() + 1 && !value.is_nil() {
+                self.remove_entry(&Key::new(key));
+                self.array.push(value);
+
+                // Keys that follow the new end of the sequence move from the entries to the array.
+                loop {
+                    let next = Key::new(Value::Number((self.array.len() + 1) as f64));
+
+                    match self.remove_entry(&next) {
+                        Some(value) => self.array.push(value),
+                        None => break,
+                    }
+                }
+
+                return Ok(());
+            }
+        }
+
+        let key = Key::new(key);
+
+        if value.is_nil() {
+            self.remove_entry(&key);
+
+            return Ok(());
+        }
+
+        match self.index.get(&key) {
+            Some(&i) => {
+                if self.entries[i].1.is_nil() {
+                    self.removed -= 1;
+                }
+
+                self.entries[i].1 = value;
+            }
+            None => {
+                if self.removed > 16 && self.removed > self.entries.len() / 2 {
+                    self.compact();
+                }
+
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn set_str(&mut self, key: &str, value: Value) {
+        self.set(Value::string(key), value)
+            .expect("strings are valid keys");
+    }
+
+    fn remove_entry(&mut self, key: &Key) -> Option<Value> {
+        let &i = self.index.get(key)?;
+
+        if self.entries[i].1.is_nil() {
+            return None;
+        }
+
+        self.removed += 1;
+
+        Some(std::mem::take(&mut self.entries[i].1))
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(|(_, value)| !value.is_nil());
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (key.clone(), i))
+            .collect();
+        self.removed = 0;
+    }
+
+    /// The length of the sequence, like the `#` operator.
+    pub(crate) fn length(&self) -> usize {
+        self.array.len()
+    }
+
+    /// Appends a value to the sequence.
+    pub(crate) fn push(&mut self, value: Value) {
+        let index = Value::Number((self.array.len() + 1) as f64);
+
+        self.set(index, value).expect("valid index");
+    }
+
+    /// Inserts a value into the sequence at `position`, shifting up the values after it.
+    pub(crate) fn insert(&mut self, position: usize, value: Value) {
+        if position > self.array.len() || value.is_nil() {
+            self.set(Value::Number(position as f64), value)
+                .expect("valid index");
+        } else {
+            self.array.insert(position - 1, value);
+        }
+    }
+
+    /// Removes the value at `position` from the sequence, shifting down the values after it.
+    pub(crate) fn remove(&mut self, position: usize) -> Value {
+        if position == 0 || position > self.array.len() {
+            return Value::Nil;
+        }
+
+        let value = self.array.remove(position - 1);
+
+        while self.array.last().is_some_and(Value::is_nil) {
+            self.array.pop();
+        }
+
+        value
+    }
+
+    /// Returns the entry after `key` in the traversal order, like `next`. Returns an error if `key` isn't in the table.
+    pub(crate) fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, ()> {
+        let start = match key {
+            Value::Nil => 0,
+            key => match array_index(key) {
+                Some(index) if index <= self.array.len() => index,
+                _ => match self.index.get(&Key::new(key.clone())) {
+                    Some(&i) => self.array.len() + i + 1,
+                    // The key was at the end of the array, which shrank because values were removed while iterating.
+                    None if array_index(key).is_some() => self.array.len(),
+                    None => return Err(()),
+                },
+            },
+        };
+
+        for index in start..self.array.len() {
+            if !self.array[index].is_nil() {
+                return Ok(Some((
+                    Value::Number((index + 1) as f64),
+                    self.array[index].clone(),
+                )));
+            }
+        }
+
+        let first_entry = start.saturating_sub(self.array.len());
+
+        Ok(self.entries[first_entry.min(self.entries.len())..]
+            .iter()
+            .find(|(_, value)| !value.is_nil())
+            .map(|(key, value)| (key.0.clone(), value.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(n: f64) -> Value {
+        Value::Number(n)
+    }
+
+    #[test]
+    fn array_and_entries() {
+        let mut table = Table::new();
+
+        table.set(number(2.0), Value::string("b")).unwrap();
+        table.set_str("x", number(1.0));
+        assert_eq!(0, table.length());
+
+        table.set(number(1.0), Value::string("a")).unwrap();
+        assert_eq!(2, table.length());
+        assert_eq!(Value::string("b"), table.get(&number(2.0)));
+
+        table.set(number(2.0), Value::Nil).unwrap();
+        assert_eq!(1, table.length());
+        assert_eq!(
+            Err("table index is nil"),
+            table.set(Value::Nil, number(1.0))
+        );
+
+        let mut key = Value::Nil;
+        let mut keys = Vec::new();
+
+        while let Some((k, _)) = table.next(&key).unwrap() {
+            keys.push(k.clone());
+            key = k;
+        }
+
+        assert_eq!(vec![number(1.0), Value::string("x")], keys);
+        assert_eq!(Err(()), table.next(&Value::string("missing")));
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut table = Table::from_values(vec![number(1.0), number(3.0)]);
+
+        table.insert(2, number(2.0));
+        assert_eq!(number(2.0), table.get(&number(2.0)));
+        assert_eq!(number(1.0), table.remove(1));
+        assert_eq!(2, table.length());
+        assert_eq!(number(3.0), table.get(&number(2.0)));
+    }
+}
@@ -0,0 +1,73 @@
+/// Strings can't decompress to more than this, Redis's default `proto-max-bulk-len`.
+const MAX_LENGTH: usize = 512 * 1024 * 1024;
+
+/// The most output a byte of input can make: a 3-byte back reference copies up to 264 bytes.
+const MAX_RATIO: usize = 88;
+
+/// Decompresses LZF data, which Redis uses for long strings in RDB files. Returns `None` if the data is malformed or
+/// doesn't decompress to exactly `length` bytes.
+///
+/// `length` comes from the file or `RESTORE` payload, so it isn't trusted: lengths the input couldn't possibly
+/// decompress to are refused up front, and the output only grows as bytes are actually decompressed.
+pub(crate) fn decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    if length > MAX_LENGTH || length > input.len().saturating_mul(MAX_RATIO) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < input.len() {
+        let control = input[i] as usize;
+
+        i += 1;
+
+        if control < 32 {
+            // A literal run of `control + 1` bytes.
+            output.extend(input.get(i..i + control + 1)?);
+            i += control + 1;
+        } else {
+            // A back reference: `len + 2` bytes copied from earlier in the output, which may overlap what's copied.
+            let mut len = control >> 5;
+
+            if len == 7 {
+                len += *input.get(i)? as usize;
+                i += 1;
+            }
+
+            let offset = ((control & 0x1f) << 8) + *input.get(i)? as usize + 1;
+
+            i += 1;
+
+            let start = output.len().checked_sub(offset)?;
+
+            for j in start..start + len + 2 {
+                output.push(output[j]);
+            }
+        }
+
+        if output.len() > length {
+            return None;
+        }
+    }
+
+    (output.len() == length).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_and_back_references() {
+        // "abcabcabcabc": a literal "abc", then 9 bytes copied from 3 bytes back.
+        assert_eq!(
+            Some(b"abcabcabcabc".to_vec()),
+            decompress(&[2, b'a', b'b', b'c', 7 << 5, 0, 2], 12)
+        );
+        assert_eq!(None, decompress(&[2, b'a', b'b', b'c'], 4));
+        assert_eq!(None, decompress(&[0, b'a', 1 << 5, 5], 4));
+        assert_eq!(None, decompress(&[2, b'a', b'b', b'c'], usize::MAX));
+        assert_eq!(None, decompress(&[2, b'a', b'b', b'c'], 1 << 30));
+    }
+}
@@ -1,100 +1,350 @@
-use std::collections::HashMap;
+use std::env;
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
+use std::process;
 use std::str;
+use std::sync::mpsc::{self, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-use crate::array::{parse, Array, Value};
+use crate::aof::Aof;
+use crate::array::{frame_length, parse, Array, Value};
 use crate::bulk_string::BulkString;
-use crate::commands::*;
+use crate::client::Client;
+use crate::config::Config;
+use crate::database::now_ms;
+use crate::rdb::Snapshots;
 
+mod aof;
 mod array;
+mod bloom_filter;
 mod bulk_string;
 mod byte_reader;
+mod client;
+mod cluster;
 mod commands;
+mod config;
+mod crc16;
+mod crc64;
+mod cuckoo_filter;
+mod data;
+mod database;
+mod dict;
+mod functions;
+mod glob;
+mod gorilla;
+mod json;
+mod json_path;
+mod lazy_free;
+mod lua;
+mod lzf;
+mod murmur;
+mod notify;
+mod object;
+mod pubsub;
+mod rdb;
+mod replication;
+mod scripting;
+mod sentinel;
+mod sha1;
+mod time_series;
 
-pub(crate) type Data = HashMap<BulkString, BulkString>;
+pub(crate) use crate::data::Data;
 
-fn handle_client(mut stream: TcpStream, data: Arc<Mutex<Data>>) {
-    let mut buf = [0; 1024];
+/// Serves a connection. `sentinel` is whether the server runs as a sentinel, which is decided at startup, so that it
+/// needn't take the lock to find out: a running script holds it, and new connections must still get through to kill it.
+fn handle_client(stream: TcpStream, data: Arc<Mutex<Data>>, sentinel: bool) {
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    let mut writer = stream.try_clone().expect("failed to clone stream");
+
+    // Replies and published messages are written by one thread, so that they can't interleave.
+    thread::spawn(move || {
+        for bytes in receiver {
+            if writer.write_all(&bytes).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut client = Client::new(sender.clone());
+
+    if sentinel {
+        client.set_sentinel();
+    }
+
+    if let Ok(stream) = stream.try_clone() {
+        client.set_stream(stream);
+    }
+
+    serve(stream, &data, &mut client, &sender);
+
+    client.disconnect(&data);
+}
+
+/// Reads and runs commands until the connection is closed.
+fn serve(mut stream: TcpStream, data: &Mutex<Data>, client: &mut Client, sender: &Sender<Vec<u8>>) {
+    // Like Redis's `PROTO_IOBUF_LEN`.
+    let mut buf = [0; 16 * 1024];
+    // What's been read of commands that haven't been run yet.
+    let mut pending = Vec::new();
 
     loop {
         match stream.read(&mut buf) {
-            Ok(n) => {
-                if n == 0 {
-                    return;
-                }
+            Ok(0) | Err(_) => return,
+            Ok(n) => pending.extend_from_slice(&buf[..n]),
+        }
 
-                if let Ok(result) = parse(&buf[..n]) {
-                    match result {
-                        Array::Null => write_error(&mut stream, "unexpected null array"),
-                        Array::Empty => write_error(&mut stream, "unexpected empty array"),
-                        Array::Filled(values) => match &values[0] {
-                            Value::BulkString(bs) => match bs {
-                                BulkString::Null => {
-                                    write_error(&mut stream, "unexpected null bulk string")
-                                }
-                                BulkString::Empty => {
-                                    write_error(&mut stream, "unexpected empty bulk string")
-                                }
-                                BulkString::Filled(command) => {
-                                    let command = match str::from_utf8(command) {
-                                        Ok(command) => command,
-                                        Err(_) => {
-                                            write_error(&mut stream, "invalid command");
-
-                                            continue;
-                                        }
-                                    };
-
-                                    if let Some(command) =
-                                        get_command(command.to_uppercase().as_str())
-                                    {
-                                        let mut data = data.lock().expect("failed to acquire lock");
-
-                                        let bytes: Vec<u8> =
-                                            command.execute(&mut data, &values[1..]).into();
-
-                                        stream.write_all(&bytes).unwrap();
-                                    } else {
-                                        write_error(&mut stream, "unknown command");
-                                    }
-                                }
-                            },
-                        },
-                    };
-                } else {
-                    write_error(&mut stream, "an error occurred");
+        loop {
+            let length = match frame_length(&pending) {
+                Ok(Some(length)) => length,
+                Ok(None) => break,
+                Err(_) => {
+                    // There's no telling where the next command starts, so everything read is dropped.
+                    write_error(sender, "an error occurred");
+                    pending.clear();
+
+                    break;
                 }
+            };
+            let request: Vec<u8> = pending.drain(..length).collect();
+
+            run(&request, data, client, sender);
+
+            if client.is_closing() {
+                return;
             }
-            Err(_) => return,
         }
     }
 }
 
+/// Parses and runs a single command.
+fn run(request: &[u8], data: &Mutex<Data>, client: &mut Client, sender: &Sender<Vec<u8>>) {
+    let Ok(result) = parse(request) else {
+        write_error(sender, "an error occurred");
+
+        return;
+    };
+
+    match result {
+        Array::Null => write_error(sender, "unexpected null array"),
+        Array::Empty => write_error(sender, "unexpected empty array"),
+        Array::Filled(values) => match &values[0] {
+            Value::BulkString(bs) => match bs {
+                BulkString::Null => write_error(sender, "unexpected null bulk string"),
+                BulkString::Empty => write_error(sender, "unexpected empty bulk string"),
+                BulkString::Filled(command) => {
+                    let command = match str::from_utf8(command) {
+                        Ok(command) => command,
+                        Err(_) => {
+                            write_error(sender, "invalid command");
+
+                            return;
+                        }
+                    };
+
+                    let response =
+                        client.process(data, command.to_uppercase().as_str(), &values[1..]);
+                    client.send(response);
+                }
+            },
+        },
+    }
+}
+
+/// Loads the data saved by a previous run: from the append-only file if it's enabled and exists, since it's more up to
+/// date, or else from the snapshot. Then opens the append-only file if it's enabled.
+fn load(data: Data, mut aof: Aof, config: &Config) -> Result<Data, String> {
+    let rdb_path = data.snapshots().path().to_path_buf();
+    let data = Mutex::new(data);
+    let loaded_aof = config.appendonly
+        && aof
+            .load(config.aof_load_truncated, &data)
+            .map_err(|e| format!("loading the append only file: {e}"))?;
+    let mut data = data.into_inner().expect("failed to acquire lock");
+
+    if loaded_aof {
+        println!("DB loaded from append only file");
+    } else if let Some(snapshot) =
+        rdb::load(&rdb_path).map_err(|e| format!("loading {}: {e}", rdb_path.display()))?
+    {
+        data.load(snapshot)
+            .map_err(|e| format!("loading {}: {e}", rdb_path.display()))?;
+        println!("DB loaded from disk");
+    }
+
+    if config.appendonly {
+        aof.enable(|| data.snapshot())
+            .map_err(|e| format!("opening the append only file: {e}"))?;
+    }
+
+    data.set_aof(aof);
+
+    Ok(data)
+}
+
 fn main() {
-    let data = Arc::new(Mutex::new(HashMap::new()));
-    let listener = TcpListener::bind("127.0.0.1:6379").expect("failed to bind to port 6379");
+    let config = match Config::parse(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+    };
+
+    let mut data = Data::with_databases(config.databases);
+
+    if config.sentinel {
+        // A sentinel doesn't store data, so it has nothing to load or save.
+        let sentinel = data.sentinel_mut();
+
+        sentinel.enable(
+            config.port,
+            config.sentinel_down_after,
+            config.sentinel_failover_timeout,
+        );
+
+        for (name, host, port, quorum) in &config.sentinel_monitors {
+            if let Err(e) = sentinel.monitor(name, host, *port, *quorum, now_ms()) {
+                eprintln!("Error: monitoring {name}: {e}");
+                process::exit(1);
+            }
+        }
+
+        println!("Sentinel ID is {}", sentinel.id());
+    } else {
+        let mut snapshots = Snapshots::new(config.dir.join(&config.dbfilename));
+
+        snapshots.set_save_points(config.save_points.clone());
+        data.set_snapshots(snapshots);
+
+        let mut aof = Aof::new(
+            config.dir.clone(),
+            config.appenddirname.clone(),
+            config.appendfilename.clone(),
+            config.appendfsync,
+        );
+
+        aof.set_use_rdb_preamble(config.aof_use_rdb_preamble);
+        aof.set_rewrite_growth(
+            config.auto_aof_rewrite_percentage,
+            config.auto_aof_rewrite_min_size,
+        );
+
+        data = match load(data, aof, &config) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        };
+    }
+
+    data.set_notify_flags(config.notify_keyspace_events);
+    scripting::set_busy_reply_threshold(config.busy_reply_threshold);
+
+    let replication = data.replication_mut();
+
+    replication.set_port(config.port);
+    replication.set_read_only(config.replica_read_only);
+    replication.set_backlog_size(config.repl_backlog_size);
+
+    if let Some((host, port)) = config.replicaof.clone() {
+        replication.set_primary(host, port);
+    }
+
+    let bus_listener = if config.cluster_enabled {
+        let cluster = data.cluster_mut();
+
+        cluster.set_node_timeout(config.cluster_node_timeout);
+
+        if let Err(e) = cluster.enable(config.dir.join(&config.cluster_config_file), config.port) {
+            eprintln!("Error: loading the cluster configuration: {e}");
+            process::exit(1);
+        }
+
+        // Cluster mode only uses the first database, whose keys move between nodes by slot.
+        data.database_mut(0).index_slots();
+        // A replica in the cluster replicates the primary its configuration names.
+        data.follow_cluster_role();
+
+        let bus_port = config.port + cluster::BUS_PORT_OFFSET;
+
+        match TcpListener::bind(("127.0.0.1", bus_port)) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                eprintln!("Error: binding the cluster bus to port {bus_port}: {e}");
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let data = Arc::new(Mutex::new(data));
+
+    if let Some(listener) = bus_listener {
+        let data = Arc::clone(&data);
+
+        thread::spawn(move || cluster::listen(data, listener));
+    }
+
+    {
+        let shared = Arc::clone(&data);
+        let mut bus = cluster::Bus::new(Arc::clone(&data));
+        let mut links = sentinel::Links::new(Arc::clone(&data));
+
+        // Like Redis's `hz` setting of 10.
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(100));
+            let mut data = shared.lock().expect("failed to acquire lock");
+
+            data.active_expire_cycle();
+            data.save_if_needed();
+            data.maintain_aof();
+
+            if let Some((host, port, link)) = data.replication_cron() {
+                let data = Arc::clone(&shared);
+
+                thread::Builder::new()
+                    .stack_size(lua::STACK_SIZE)
+                    .spawn(move || replication::replicate(data, host, port, link))
+                    .expect("failed to spawn thread");
+            }
+
+            for (ip, port, message) in data.cluster_cron() {
+                bus.send(ip, port, message);
+            }
+
+            for request in data.sentinel_cron() {
+                links.send(request);
+            }
+        });
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", config.port))
+        .unwrap_or_else(|_| panic!("failed to bind to port {}", config.port));
+
+    println!("Listening on port {}", config.port);
 
-    println!("Listening on port 6379");
+    let sentinel = config.sentinel;
 
     for stream in listener.incoming() {
         let data = Arc::clone(&data);
 
         match stream {
             Ok(stream) => {
-                thread::spawn(|| {
-                    handle_client(stream, data);
-                });
+                // Clients run scripts, which need a known amount of stack.
+                thread::Builder::new()
+                    .stack_size(lua::STACK_SIZE)
+                    .spawn(move || handle_client(stream, data, sentinel))
+                    .expect("failed to spawn thread");
             }
             Err(e) => eprintln!("Error: {e}"),
         }
     }
 }
 
-fn write_error(stream: &mut TcpStream, message: &str) {
-    stream
-        .write_all(format!("-ERR {message}\r\n").as_bytes())
-        .unwrap();
+fn write_error(sender: &Sender<Vec<u8>>, message: &str) {
+    let _ = sender.send(format!("-ERR {message}\r\n").into_bytes());
 }
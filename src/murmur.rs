@@ -0,0 +1,60 @@
+const M: u64 = 0xc6a4a7935bd1e995;
+const R: u32 = 47;
+
+/// Austin Appleby's MurmurHash64A, as used by RedisBloom for both its Bloom and Cuckoo filters.
+pub(crate) fn murmur_hash_64a(key: &[u8], seed: u64) -> u64 {
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_key() {
+        assert_eq!(0, murmur_hash_64a(b"", 0));
+    }
+
+    #[test]
+    fn tail_bytes_change_the_hash() {
+        assert_ne!(
+            murmur_hash_64a(b"abcdefgh", 0),
+            murmur_hash_64a(b"abcdefghi", 0)
+        );
+        assert_ne!(murmur_hash_64a(b"abc", 0), murmur_hash_64a(b"abd", 0));
+    }
+
+    #[test]
+    fn seed_changes_the_hash() {
+        assert_ne!(murmur_hash_64a(b"hello", 0), murmur_hash_64a(b"hello", 1));
+    }
+}
@@ -0,0 +1,94 @@
+/// Publish events to `__keyspace@<db>__:<key>`, with the event as the message.
+pub(crate) const KEYSPACE: u32 = 1 << 0;
+/// Publish events to `__keyevent@<db>__:<event>`, with the key as the message.
+pub(crate) const KEYEVENT: u32 = 1 << 1;
+/// Commands that work on keys of any type, like `DEL`, `EXPIRE` and `RENAME`.
+pub(crate) const GENERIC: u32 = 1 << 2;
+pub(crate) const STRING: u32 = 1 << 3;
+pub(crate) const LIST: u32 = 1 << 4;
+pub(crate) const SET: u32 = 1 << 5;
+pub(crate) const HASH: u32 = 1 << 6;
+pub(crate) const ZSET: u32 = 1 << 7;
+/// Keys removed because their TTL passed.
+pub(crate) const EXPIRED: u32 = 1 << 8;
+/// Keys removed to free memory.
+pub(crate) const EVICTED: u32 = 1 << 9;
+pub(crate) const STREAM: u32 = 1 << 10;
+/// Lookups of keys that don't exist.
+pub(crate) const KEY_MISS: u32 = 1 << 11;
+/// Keys added to a database.
+pub(crate) const NEW: u32 = 1 << 12;
+
+/// The classes selected by `A`. Key misses and new keys are left out, because they're so frequent.
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+const CLASSES: [(char, u32); 9] = [
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+];
+
+/// Parses a `notify-keyspace-events` value like `"KEA"` or `"Egx"`.
+pub(crate) fn parse_flags(flags: &str) -> Option<u32> {
+    flags.chars().try_fold(0, |parsed, c| {
+        let flag = match c {
+            'A' => ALL,
+            'K' => KEYSPACE,
+            'E' => KEYEVENT,
+            'm' => KEY_MISS,
+            'n' => NEW,
+            c => CLASSES.iter().find(|(name, _)| *name == c)?.1,
+        };
+
+        Some(parsed | flag)
+    })
+}
+
+/// Formats flags the way Redis reports them, with `A` standing in for all the classes it selects.
+pub(crate) fn format_flags(flags: u32) -> String {
+    let mut formatted = String::new();
+
+    if flags & ALL == ALL {
+        formatted.push('A');
+    } else {
+        formatted.extend(
+            CLASSES
+                .iter()
+                .filter(|(_, flag)| flags & flag != 0)
+                .map(|(name, _)| name),
+        );
+    }
+
+    for (name, flag) in [
+        ('K', KEYSPACE),
+        ('E', KEYEVENT),
+        ('m', KEY_MISS),
+        ('n', NEW),
+    ] {
+        if flags & flag != 0 {
+            formatted.push(name);
+        }
+    }
+
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        assert_eq!(Some(0), parse_flags(""));
+        assert_eq!(Some(KEYEVENT | GENERIC | EXPIRED), parse_flags("Egx"));
+        assert_eq!(None, parse_flags("Eq"));
+        assert_eq!("AKE", format_flags(parse_flags("KEA").unwrap()));
+        assert_eq!("g$xEn", format_flags(parse_flags("nE$xg").unwrap()));
+    }
+}
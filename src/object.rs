@@ -0,0 +1,49 @@
+use crate::bloom_filter::ScalableBloomFilter;
+use crate::bulk_string::BulkString;
+use crate::cuckoo_filter::CuckooFilter;
+use crate::json::Json;
+use crate::time_series::TimeSeries;
+
+/// A value stored in the keyspace.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Object {
+    String(BulkString),
+    BloomFilter(ScalableBloomFilter),
+    CuckooFilter(CuckooFilter),
+    Json(Json),
+    TimeSeries(TimeSeries),
+}
+
+impl Object {
+    /// The name `TYPE` reports. Module types use the names their Redis modules register.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Object::String(_) => "string",
+            Object::BloomFilter(_) => "MBbloom--",
+            Object::CuckooFilter(_) => "MBbloomCF",
+            Object::Json(_) => "ReJSON-RL",
+            Object::TimeSeries(_) => "TSDB-TYPE",
+        }
+    }
+
+    /// Roughly the number of allocations dropping this value frees.
+    pub(crate) fn free_effort(&self) -> usize {
+        fn json_nodes(json: &Json) -> usize {
+            match json {
+                Json::Array(elements) => 1 + elements.iter().map(json_nodes).sum::<usize>(),
+                Json::Object(members) => {
+                    1 + members.iter().map(|(_, v)| json_nodes(v)).sum::<usize>()
+                }
+                _ => 1,
+            }
+        }
+
+        match self {
+            Object::String(_) => 1,
+            Object::BloomFilter(filter) => filter.filter_count() as usize,
+            Object::CuckooFilter(filter) => filter.filter_count() as usize,
+            Object::Json(document) => json_nodes(document),
+            Object::TimeSeries(series) => series.chunk_count(),
+        }
+    }
+}
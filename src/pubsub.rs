@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+use crate::bulk_string::BulkString;
+use crate::commands::{bulk_string, Response};
+use crate::glob;
+
+/// Identifies a connection.
+pub(crate) type ClientId = u64;
+
+/// Connections subscribed to a channel or pattern, with the senders that deliver messages to their sockets.
+type Subscribers = HashMap<ClientId, Sender<Vec<u8>>>;
+
+/// What a subscription is to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Kind {
+    Channel,
+    Pattern,
+    /// A channel of sharded Pub/Sub. Sharded channels have their own namespace and are assigned to hash slots like keys.
+    ShardChannel,
+}
+
+impl Kind {
+    /// The first element of the replies to subscribing.
+    pub(crate) fn subscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "subscribe",
+            Kind::Pattern => "psubscribe",
+            Kind::ShardChannel => "ssubscribe",
+        }
+    }
+
+    /// The first element of the replies to unsubscribing.
+    pub(crate) fn unsubscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "unsubscribe",
+            Kind::Pattern => "punsubscribe",
+            Kind::ShardChannel => "sunsubscribe",
+        }
+    }
+}
+
+/// The channels and patterns that connections are subscribed to.
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions {
+    channels: HashMap<BulkString, Subscribers>,
+    patterns: HashMap<BulkString, Subscribers>,
+    shard_channels: HashMap<BulkString, Subscribers>,
+}
+
+fn bytes(name: &BulkString) -> &[u8] {
+    match name {
+        BulkString::Filled(bytes) => bytes,
+        _ => &[],
+    }
+}
+
+impl Subscriptions {
+    pub(crate) fn new() -> Subscriptions {
+        Subscriptions::default()
+    }
+
+    fn map(&self, kind: Kind) -> &HashMap<BulkString, Subscribers> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::ShardChannel => &self.shard_channels,
+        }
+    }
+
+    fn map_mut(&mut self, kind: Kind) -> &mut HashMap<BulkString, Subscribers> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    pub(crate) fn subscribe(
+        &mut self,
+        kind: Kind,
+        name: &BulkString,
+        id: ClientId,
+        sender: &Sender<Vec<u8>>,
+    ) {
+        self.map_mut(kind)
+            .entry(name.clone())
+            .or_default()
+            .insert(id, sender.clone());
+    }
+
+    pub(crate) fn unsubscribe(&mut self, kind: Kind, name: &BulkString, id: ClientId) {
+        let map = self.map_mut(kind);
+
+        if let Some(subscribers) = map.get_mut(name) {
+            subscribers.remove(&id);
+
+            if subscribers.is_empty() {
+                map.remove(name);
+            }
+        }
+    }
+
+    /// Sends `message` to the subscribers of `channel` and of the patterns that match it, returning how many
+    /// deliveries were made. A connection subscribed through several patterns gets the message once per pattern.
+    pub(crate) fn publish(&self, channel: &BulkString, message: &BulkString) -> usize {
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.channels.get(channel) {
+            let bytes: Vec<u8> = Response::Array(vec![
+                bulk_string("message"),
+                Response::BulkString(channel.clone()),
+                Response::BulkString(message.clone()),
+            ])
+            .into();
+
+            for sender in subscribers.values() {
+                // The connection may be closing, in which case it unsubscribes shortly.
+                let _ = sender.send(bytes.clone());
+                receivers += 1;
+            }
+        }
+
+        for (pattern, subscribers) in &self.patterns {
+            if !glob::matches(bytes(pattern), bytes(channel), false) {
+                continue;
+            }
+
+            let bytes: Vec<u8> = Response::Array(vec![
+                bulk_string("pmessage"),
+                Response::BulkString(pattern.clone()),
+                Response::BulkString(channel.clone()),
+                Response::BulkString(message.clone()),
+            ])
+            .into();
+
+            for sender in subscribers.values() {
+                let _ = sender.send(bytes.clone());
+                receivers += 1;
+            }
+        }
+
+        receivers
+    }
+
+    /// Sends `message` to the subscribers of the sharded channel `channel`, returning how many there were.
+    pub(crate) fn spublish(&self, channel: &BulkString, message: &BulkString) -> usize {
+        let Some(subscribers) = self.shard_channels.get(channel) else {
+            return 0;
+        };
+
+        let bytes: Vec<u8> = Response::Array(vec![
+            bulk_string("smessage"),
+            Response::BulkString(channel.clone()),
+            Response::BulkString(message.clone()),
+        ])
+        .into();
+
+        for sender in subscribers.values() {
+            let _ = sender.send(bytes.clone());
+        }
+
+        subscribers.len()
+    }
+
+    /// The channels, or sharded channels, with at least one subscriber, optionally only those matching a pattern.
+    pub(crate) fn channels(&self, kind: Kind, pattern: Option<&[u8]>) -> Vec<BulkString> {
+        self.map(kind)
+            .keys()
+            .filter(|channel| {
+                pattern.is_none_or(|pattern| glob::matches(pattern, bytes(channel), false))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn subscriber_count(&self, kind: Kind, channel: &BulkString) -> usize {
+        self.map(kind).get(channel).map_or(0, HashMap::len)
+    }
+
+    /// The number of patterns with at least one subscriber.
+    pub(crate) fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    #[test]
+    fn publish_to_channels_and_patterns() {
+        let mut subscriptions = Subscriptions::new();
+        let (sender, receiver) = mpsc::channel();
+
+        subscriptions.subscribe(Kind::Channel, &bulk_string!("news"), 1, &sender);
+        subscriptions.subscribe(Kind::Pattern, &bulk_string!("n*"), 1, &sender);
+        subscriptions.subscribe(Kind::Pattern, &bulk_string!("x*"), 2, &sender);
+
+        assert_eq!(
+            2,
+            subscriptions.publish(&bulk_string!("news"), &bulk_string!("hi"))
+        );
+        assert_eq!(
+            b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n".to_vec(),
+            receiver.try_recv().unwrap()
+        );
+        assert_eq!(
+            b"*4\r\n$8\r\npmessage\r\n$2\r\nn*\r\n$4\r\nnews\r\n$2\r\nhi\r\n".to_vec(),
+            receiver.try_recv().unwrap()
+        );
+
+        subscriptions.unsubscribe(Kind::Channel, &bulk_string!("news"), 1);
+        subscriptions.unsubscribe(Kind::Pattern, &bulk_string!("n*"), 1);
+
+        assert_eq!(
+            0,
+            subscriptions.publish(&bulk_string!("news"), &bulk_string!("hi"))
+        );
+        assert!(subscriptions.channels(Kind::Channel, None).is_empty());
+        assert_eq!(1, subscriptions.pattern_count());
+    }
+
+    #[test]
+    fn sharded_channels_have_their_own_namespace() {
+        let mut subscriptions = Subscriptions::new();
+        let (sender, receiver) = mpsc::channel();
+
+        subscriptions.subscribe(Kind::ShardChannel, &bulk_string!("news"), 1, &sender);
+        subscriptions.subscribe(Kind::Pattern, &bulk_string!("*"), 2, &sender);
+
+        assert_eq!(
+            1,
+            subscriptions.spublish(&bulk_string!("news"), &bulk_string!("hi"))
+        );
+        assert_eq!(
+            b"*3\r\n$8\r\nsmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n".to_vec(),
+            receiver.try_recv().unwrap()
+        );
+        assert_eq!(
+            1,
+            subscriptions.publish(&bulk_string!("news"), &bulk_string!("hi"))
+        );
+        assert_eq!(
+            vec![bulk_string!("news")],
+            subscriptions.channels(Kind::ShardChannel, None)
+        );
+        assert!(subscriptions.channels(Kind::Channel, None).is_empty());
+    }
+}
@@ -0,0 +1,925 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::bloom_filter::ScalableBloomFilter;
+use crate::bulk_string::BulkString;
+use crate::byte_reader::ByteReader;
+use crate::crc64::crc64;
+use crate::cuckoo_filter::CuckooFilter;
+use crate::database::now_ms;
+use crate::json;
+use crate::lzf;
+use crate::object::Object;
+use crate::time_series::TimeSeries;
+
+/// The RDB version written, which is Redis 7.0's. Files with newer versions are refused.
+pub(crate) const VERSION: u16 = 11;
+
+/// Precedes the code of each function library.
+pub(crate) const OPCODE_FUNCTION: u8 = 245;
+const OPCODE_SLOT_INFO: u8 = 244;
+const OPCODE_MODULE_AUX: u8 = 247;
+const OPCODE_IDLE: u8 = 248;
+const OPCODE_FREQ: u8 = 249;
+const OPCODE_AUX: u8 = 250;
+const OPCODE_RESIZE_DB: u8 = 251;
+const OPCODE_EXPIRE_TIME_MS: u8 = 252;
+const OPCODE_EXPIRE_TIME: u8 = 253;
+const OPCODE_SELECT_DB: u8 = 254;
+const OPCODE_EOF: u8 = 255;
+
+const TYPE_STRING: u8 = 0;
+/// A value of a module type, whose fields are each preceded by an opcode so that tools can skip them.
+const TYPE_MODULE: u8 = 7;
+
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+/// The module types that values other than strings are saved as, with the versions of their encodings. JSON documents
+/// are saved like RedisJSON saves them. Filters and time series are saved in layouts of Red's own, so they use names
+/// of Red's own too: a file written with RedisBloom's or RedisTimeSeries's names would be misread by those modules.
+const BLOOM_FILTER: (&str, u64) = ("RedBloom-", 1);
+const CUCKOO_FILTER: (&str, u64) = ("RedCuckoo", 1);
+const JSON: (&str, u64) = ("ReJSON-RL", 3);
+const TIME_SERIES: (&str, u64) = ("RedSeries", 1);
+
+/// The characters module type names are made of. A name's 9 characters and its encoding version are packed into a
+/// 64-bit ID, 6 bits per character and 10 bits for the version.
+const MODULE_NAME_CHARACTERS: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// A copy of the data to save, taken while holding the lock so that it can be written without it.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Snapshot {
+    /// The keys of each database, with their values and the Unix times in milliseconds at which they expire.
+    pub(crate) databases: Vec<Vec<(BulkString, Object, Option<u64>)>>,
+    /// The code of each function library.
+    pub(crate) libraries: Vec<Vec<u8>>,
+}
+
+/// Writes a length in 1, 2, 5 or 9 bytes, depending on how big it is.
+pub(crate) fn write_length(bytes: &mut Vec<u8>, length: u64) {
+    match length {
+        0..=0x3f => bytes.push(length as u8),
+        0x40..=0x3fff => bytes.extend([0x40 | (length >> 8) as u8, length as u8]),
+        _ if length <= u32::MAX as u64 => {
+            bytes.push(0x80);
+            bytes.extend((length as u32).to_be_bytes());
+        }
+        _ => {
+            bytes.push(0x81);
+            bytes.extend(length.to_be_bytes());
+        }
+    }
+}
+
+pub(crate) fn write_string(bytes: &mut Vec<u8>, string: &[u8]) {
+    write_length(bytes, string.len() as u64);
+    bytes.extend(string);
+}
+
+/// Either a length, or the kind of special encoding the string that follows uses.
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+fn read_length_or_encoding(reader: &mut ByteReader) -> Option<Length> {
+    let first = reader.read_byte()?;
+
+    let length = match first >> 6 {
+        0 => (first & 0x3f) as u64,
+        1 => (((first & 0x3f) as u64) << 8) | reader.read_byte()? as u64,
+        3 => return Some(Length::Encoded(first & 0x3f)),
+        _ if first == 0x80 => u32::from_be_bytes(reader.read_bytes(4)?.try_into().ok()?) as u64,
+        _ if first == 0x81 => u64::from_be_bytes(reader.read_bytes(8)?.try_into().ok()?),
+        _ => return None,
+    };
+
+    Some(Length::Plain(length))
+}
+
+pub(crate) fn read_length(reader: &mut ByteReader) -> Option<u64> {
+    match read_length_or_encoding(reader)? {
+        Length::Plain(length) => Some(length),
+        Length::Encoded(_) => None,
+    }
+}
+
+/// Reads a string, which may be stored as an integer or compressed with LZF.
+pub(crate) fn read_string(reader: &mut ByteReader) -> Option<Vec<u8>> {
+    let length = match read_length_or_encoding(reader)? {
+        Length::Plain(length) => length,
+        Length::Encoded(0) => return Some((reader.read_byte()? as i8).to_string().into_bytes()),
+        Length::Encoded(1) => {
+            let n = i16::from_le_bytes(reader.read_bytes(2)?.try_into().ok()?);
+
+            return Some(n.to_string().into_bytes());
+        }
+        Length::Encoded(2) => {
+            let n = i32::from_le_bytes(reader.read_bytes(4)?.try_into().ok()?);
+
+            return Some(n.to_string().into_bytes());
+        }
+        Length::Encoded(3) => {
+            let compressed_length = read_length(reader)?;
+            let length = read_length(reader)?;
+            let compressed = reader.read_bytes(usize::try_from(compressed_length).ok()?)?;
+
+            return lzf::decompress(compressed, usize::try_from(length).ok()?);
+        }
+        Length::Encoded(_) => return None,
+    };
+
+    reader
+        .read_bytes(usize::try_from(length).ok()?)
+        .map(<[u8]>::to_vec)
+}
+
+/// Writes the fields of a module type's value. Each field is preceded by an opcode saying what it is.
+pub(crate) struct ModuleWriter<'a> {
+    bytes: &'a mut Vec<u8>,
+}
+
+impl ModuleWriter<'_> {
+    pub(crate) fn save_unsigned(&mut self, n: u64) {
+        write_length(self.bytes, MODULE_OPCODE_UINT);
+        write_length(self.bytes, n);
+    }
+
+    pub(crate) fn save_double(&mut self, n: f64) {
+        write_length(self.bytes, MODULE_OPCODE_DOUBLE);
+        self.bytes.extend(n.to_le_bytes());
+    }
+
+    pub(crate) fn save_string(&mut self, string: &[u8]) {
+        write_length(self.bytes, MODULE_OPCODE_STRING);
+        write_string(self.bytes, string);
+    }
+}
+
+/// Reads the fields a [`ModuleWriter`] wrote, in the same order. Each method returns `None` if the next field isn't
+/// of the expected kind.
+pub(crate) struct ModuleReader<'r, 'a> {
+    reader: &'r mut ByteReader<'a>,
+}
+
+impl ModuleReader<'_, '_> {
+    fn expect(&mut self, opcode: u64) -> Option<()> {
+        (read_length(self.reader)? == opcode).then_some(())
+    }
+
+    pub(crate) fn load_unsigned(&mut self) -> Option<u64> {
+        self.expect(MODULE_OPCODE_UINT)?;
+
+        read_length(self.reader)
+    }
+
+    pub(crate) fn load_double(&mut self) -> Option<f64> {
+        self.expect(MODULE_OPCODE_DOUBLE)?;
+
+        Some(f64::from_le_bytes(
+            self.reader.read_bytes(8)?.try_into().ok()?,
+        ))
+    }
+
+    pub(crate) fn load_string(&mut self) -> Option<Vec<u8>> {
+        self.expect(MODULE_OPCODE_STRING)?;
+
+        read_string(self.reader)
+    }
+}
+
+fn module_id((name, version): (&str, u64)) -> u64 {
+    let id = name.bytes().fold(0, |id, c| {
+        let index = MODULE_NAME_CHARACTERS
+            .iter()
+            .position(|&d| d == c)
+            .expect("module type names only use the module name characters");
+
+        (id << 6) | index as u64
+    });
+
+    (id << 10) | version
+}
+
+fn write_module(bytes: &mut Vec<u8>, module: (&str, u64), save: impl FnOnce(&mut ModuleWriter)) {
+    write_length(bytes, module_id(module));
+    save(&mut ModuleWriter { bytes });
+    write_length(bytes, MODULE_OPCODE_EOF);
+}
+
+fn key_bytes(key: &BulkString) -> &[u8] {
+    match key {
+        BulkString::Filled(bytes) => bytes,
+        _ => &[],
+    }
+}
+
+fn value_type(object: &Object) -> u8 {
+    match object {
+        Object::String(_) => TYPE_STRING,
+        _ => TYPE_MODULE,
+    }
+}
+
+fn write_value(bytes: &mut Vec<u8>, object: &Object) {
+    match object {
+        Object::String(value) => write_string(bytes, key_bytes(value)),
+        Object::BloomFilter(filter) => write_module(bytes, BLOOM_FILTER, |w| filter.save(w)),
+        Object::CuckooFilter(filter) => write_module(bytes, CUCKOO_FILTER, |w| filter.save(w)),
+        Object::Json(document) => write_module(bytes, JSON, |w| {
+            w.save_string(document.serialize().as_bytes())
+        }),
+        Object::TimeSeries(series) => write_module(bytes, TIME_SERIES, |w| series.save(w)),
+    }
+}
+
+fn write_entry(bytes: &mut Vec<u8>, key: &BulkString, object: &Object, expires_at: Option<u64>) {
+    if let Some(at) = expires_at {
+        bytes.push(OPCODE_EXPIRE_TIME_MS);
+        bytes.extend(at.to_le_bytes());
+    }
+
+    bytes.push(value_type(object));
+    write_string(bytes, key_bytes(key));
+    write_value(bytes, object);
+}
+
+fn write_aux(bytes: &mut Vec<u8>, name: &str, value: &str) {
+    bytes.push(OPCODE_AUX);
+    write_string(bytes, name.as_bytes());
+    write_string(bytes, value.as_bytes());
+}
+
+/// Serializes a snapshot in the RDB format, ending with a CRC-64 of the whole file.
+pub(crate) fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let mut bytes = format!("REDIS{VERSION:04}").into_bytes();
+
+    write_aux(&mut bytes, "redis-ver", "7.0.0");
+    write_aux(&mut bytes, "redis-bits", &(usize::BITS).to_string());
+    write_aux(&mut bytes, "ctime", &(now_ms() / 1000).to_string());
+    write_aux(&mut bytes, "aof-base", "0");
+
+    for code in &snapshot.libraries {
+        bytes.push(OPCODE_FUNCTION);
+        write_string(&mut bytes, code);
+    }
+
+    for (index, entries) in snapshot.databases.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+
+        bytes.push(OPCODE_SELECT_DB);
+        write_length(&mut bytes, index as u64);
+        bytes.push(OPCODE_RESIZE_DB);
+        write_length(&mut bytes, entries.len() as u64);
+        write_length(
+            &mut bytes,
+            entries.iter().filter(|(_, _, at)| at.is_some()).count() as u64,
+        );
+
+        for (key, object, expires_at) in entries {
+            write_entry(&mut bytes, key, object, *expires_at);
+        }
+    }
+
+    bytes.push(OPCODE_EOF);
+
+    let checksum = crc64(0, &bytes);
+
+    bytes.extend(checksum.to_le_bytes());
+
+    bytes
+}
+
+fn read_module(reader: &mut ByteReader) -> Result<Object, String> {
+    let id = read_length(reader).ok_or("unexpected end of file")?;
+    let mut module = ModuleReader { reader };
+
+    let object = if id == module_id(BLOOM_FILTER) {
+        ScalableBloomFilter::load(&mut module).map(Object::BloomFilter)
+    } else if id == module_id(CUCKOO_FILTER) {
+        CuckooFilter::load(&mut module).map(Object::CuckooFilter)
+    } else if id == module_id(JSON) {
+        module
+            .load_string()
+            .and_then(|document| json::parse(&document).ok())
+            .map(Object::Json)
+    } else if id == module_id(TIME_SERIES) {
+        TimeSeries::load(&mut module).map(Object::TimeSeries)
+    } else {
+        return Err(format!("unsupported module type with ID {id}"));
+    };
+
+    match (object, module.expect(MODULE_OPCODE_EOF)) {
+        (Some(object), Some(())) => Ok(object),
+        _ => Err("malformed module value".to_string()),
+    }
+}
+
+fn read_object(reader: &mut ByteReader, value_type: u8) -> Result<Object, String> {
+    match value_type {
+        TYPE_STRING => read_string(reader)
+            .map(|value| Object::String(to_bulk_string(value)))
+            .ok_or_else(|| "unexpected end of file".to_string()),
+        TYPE_MODULE => read_module(reader),
+        _ => Err(format!("unsupported value type {value_type}")),
+    }
+}
+
+fn to_bulk_string(bytes: Vec<u8>) -> BulkString {
+    if bytes.is_empty() {
+        BulkString::Empty
+    } else {
+        BulkString::Filled(bytes)
+    }
+}
+
+/// Serializes a value like Redis's `DUMP`: its type and RDB encoding, followed by the RDB version and a CRC-64 of
+/// everything before it.
+pub(crate) fn dump(object: &Object) -> Vec<u8> {
+    let mut bytes = vec![value_type(object)];
+
+    write_value(&mut bytes, object);
+    bytes.extend(VERSION.to_le_bytes());
+
+    let checksum = crc64(0, &bytes);
+
+    bytes.extend(checksum.to_le_bytes());
+
+    bytes
+}
+
+/// Parses a payload made by [`dump`], or by Redis's `DUMP`.
+pub(crate) fn undump(payload: &[u8]) -> Result<Object, &'static str> {
+    const WRONG_VERSION_OR_CHECKSUM: &str = "DUMP payload version or checksum are wrong";
+
+    let Some(body_length) = payload.len().checked_sub(10) else {
+        return Err(WRONG_VERSION_OR_CHECKSUM);
+    };
+    let (body, footer) = payload.split_at(body_length);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let checksum = u64::from_le_bytes(footer[2..].try_into().expect("8 bytes"));
+
+    if version > VERSION || checksum != crc64(0, &payload[..body_length + 2]) {
+        return Err(WRONG_VERSION_OR_CHECKSUM);
+    }
+
+    let mut reader = ByteReader::new(body);
+    let value_type = reader.read_byte().ok_or("Bad data format")?;
+
+    match read_object(&mut reader, value_type) {
+        Ok(object) if reader.bytes_remaining() == 0 => Ok(object),
+        _ => Err("Bad data format"),
+    }
+}
+
+/// Parses a file in the RDB format. Only strings and the module types Red saves can be loaded.
+pub(crate) fn decode(bytes: &[u8]) -> Result<Snapshot, String> {
+    decode_prefix(bytes).map(|(snapshot, _)| snapshot)
+}
+
+/// Like [`decode`], but the snapshot may be followed by other data, like the commands of an append-only file. Returns
+/// the snapshot and its length in bytes.
+pub(crate) fn decode_prefix(bytes: &[u8]) -> Result<(Snapshot, usize), String> {
+    const EOF: &str = "unexpected end of file";
+
+    let mut reader = ByteReader::new(bytes);
+    let header = reader.read_bytes(9).ok_or(EOF)?;
+    let version = header
+        .strip_prefix(b"REDIS")
+        .and_then(|version| std::str::from_utf8(version).ok())
+        .and_then(|version| version.parse::<u16>().ok())
+        .ok_or("wrong signature")?;
+
+    if version > VERSION {
+        return Err(format!("can't handle RDB format version {version}"));
+    }
+
+    let mut snapshot = Snapshot::default();
+    let mut database = 0;
+    let mut expires_at = None;
+
+    loop {
+        let opcode = reader.read_byte().ok_or(EOF)?;
+
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_SELECT_DB => {
+                database = read_length(&mut reader)
+                    .and_then(|index| usize::try_from(index).ok())
+                    .ok_or(EOF)?;
+            }
+            OPCODE_RESIZE_DB => {
+                read_length(&mut reader).ok_or(EOF)?;
+                read_length(&mut reader).ok_or(EOF)?;
+            }
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    read_length(&mut reader).ok_or(EOF)?;
+                }
+            }
+            OPCODE_AUX => {
+                read_string(&mut reader).ok_or(EOF)?;
+                read_string(&mut reader).ok_or(EOF)?;
+            }
+            OPCODE_FUNCTION => snapshot
+                .libraries
+                .push(read_string(&mut reader).ok_or(EOF)?),
+            OPCODE_EXPIRE_TIME_MS => {
+                let at = reader.read_bytes(8).ok_or(EOF)?;
+
+                expires_at = Some(u64::from_le_bytes(at.try_into().expect("8 bytes")));
+            }
+            OPCODE_EXPIRE_TIME => {
+                let at = reader.read_bytes(4).ok_or(EOF)?;
+
+                expires_at =
+                    Some(u32::from_le_bytes(at.try_into().expect("4 bytes")) as u64 * 1000);
+            }
+            // Eviction hints, which don't matter without a memory limit.
+            OPCODE_IDLE => {
+                read_length(&mut reader).ok_or(EOF)?;
+            }
+            OPCODE_FREQ => {
+                reader.read_byte().ok_or(EOF)?;
+            }
+            OPCODE_MODULE_AUX => return Err("unsupported module auxiliary data".to_string()),
+            value_type => {
+                let key = read_string(&mut reader).ok_or(EOF)?;
+                let object = read_object(&mut reader, value_type)?;
+
+                if snapshot.databases.len() <= database {
+                    snapshot.databases.resize_with(database + 1, Vec::new);
+                }
+
+                snapshot.databases[database].push((to_bulk_string(key), object, expires_at.take()));
+            }
+        }
+    }
+
+    let body_length = bytes.len() - reader.bytes_remaining();
+    let checksum = reader.read_bytes(8).ok_or(EOF)?;
+    let checksum = u64::from_le_bytes(checksum.try_into().expect("8 bytes"));
+
+    // Redis writes a zero checksum when checksums are disabled.
+    if checksum != 0 && checksum != crc64(0, &bytes[..body_length]) {
+        return Err("wrong checksum".to_string());
+    }
+
+    Ok((snapshot, body_length + 8))
+}
+
+/// Writes `bytes` to a temporary file next to `path`, then renames it, so that `path` never holds a partial file. The
+/// temporary file is named after `path`, so that writes to different files in a directory don't share it.
+pub(crate) fn write_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!("temp-{}-{file_name}", process::id()));
+
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    });
+
+    match result.and_then(|()| fs::rename(&temp, path)) {
+        Ok(()) => Ok(()),
+        Err(error) => {
+            let _ = fs::remove_file(&temp);
+
+            Err(error)
+        }
+    }
+}
+
+/// Reads the snapshot at `path`, or returns `None` if there's no file there.
+pub(crate) fn load(path: &Path) -> Result<Option<Snapshot>, String> {
+    match fs::read(path) {
+        Ok(bytes) => decode(&bytes).map(Some),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// A `save <seconds> <changes>` rule: a snapshot is saved in the background once `seconds` have passed since the
+/// latest one, if at least `changes` changes were made in the meantime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SavePoint {
+    pub(crate) seconds: u64,
+    pub(crate) changes: u64,
+}
+
+/// Redis's default rules: after an hour if anything changed, after 5 minutes if 100 keys changed, and after a minute
+/// if 10000 keys changed.
+pub(crate) const DEFAULT_SAVE_POINTS: [SavePoint; 3] = [
+    SavePoint {
+        seconds: 3600,
+        changes: 1,
+    },
+    SavePoint {
+        seconds: 300,
+        changes: 100,
+    },
+    SavePoint {
+        seconds: 60,
+        changes: 10000,
+    },
+];
+
+/// Parses rules like `"3600 1 300 100"`, as pairs of seconds and changes. An empty string means no rules.
+pub(crate) fn parse_save_points(rules: &str) -> Option<Vec<SavePoint>> {
+    let numbers = rules
+        .split_whitespace()
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+
+    if numbers.len() % 2 != 0 {
+        return None;
+    }
+
+    Some(
+        numbers
+            .chunks(2)
+            .map(|pair| SavePoint {
+                seconds: pair[0],
+                changes: pair[1],
+            })
+            .collect(),
+    )
+}
+
+pub(crate) fn format_save_points(save_points: &[SavePoint]) -> String {
+    save_points
+        .iter()
+        .map(|point| format!("{} {}", point.seconds, point.changes))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// How long to wait after a background save fails before a save point can trigger another one, in seconds.
+const RETRY_DELAY: u64 = 5;
+
+/// The state of the latest snapshots, which background saves update from their own thread.
+#[derive(Debug)]
+struct State {
+    /// When the latest snapshot was saved, in seconds since the Unix epoch. Starts at the time the server started.
+    last_save: AtomicU64,
+    /// How many changes had been made when the latest snapshot was taken.
+    saved_changes: AtomicU64,
+    /// When the latest save was started, in seconds since the Unix epoch.
+    last_attempt: AtomicU64,
+    saving: AtomicBool,
+    last_save_failed: AtomicBool,
+    /// How long the latest background save took, in seconds, or -1 if there hasn't been one.
+    last_bgsave_duration: AtomicI64,
+}
+
+/// Where snapshots are saved, when they're saved automatically, and how the latest ones went.
+#[derive(Debug, Clone)]
+pub(crate) struct Snapshots {
+    path: PathBuf,
+    save_points: Vec<SavePoint>,
+    state: Arc<State>,
+}
+
+impl Snapshots {
+    pub(crate) fn new(path: PathBuf) -> Snapshots {
+        let now = now_ms() / 1000;
+
+        Snapshots {
+            path,
+            save_points: Vec::new(),
+            state: Arc::new(State {
+                last_save: AtomicU64::new(now),
+                saved_changes: AtomicU64::new(0),
+                last_attempt: AtomicU64::new(now),
+                saving: AtomicBool::new(false),
+                last_save_failed: AtomicBool::new(false),
+                last_bgsave_duration: AtomicI64::new(-1),
+            }),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn save_points(&self) -> &[SavePoint] {
+        &self.save_points
+    }
+
+    pub(crate) fn set_save_points(&mut self, save_points: Vec<SavePoint>) {
+        self.save_points = save_points;
+    }
+
+    pub(crate) fn last_save(&self) -> u64 {
+        self.state.last_save.load(Ordering::Relaxed)
+    }
+
+    /// Whether a background save is in progress.
+    pub(crate) fn is_saving(&self) -> bool {
+        self.state.saving.load(Ordering::Relaxed)
+    }
+
+    /// Whether the latest save, in the background or not, succeeded. `true` if there hasn't been one.
+    pub(crate) fn last_save_succeeded(&self) -> bool {
+        !self.state.last_save_failed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn last_bgsave_duration(&self) -> i64 {
+        self.state.last_bgsave_duration.load(Ordering::Relaxed)
+    }
+
+    /// The number of changes made since the latest snapshot was taken, given the number made in total.
+    pub(crate) fn changes_since_save(&self, changes: u64) -> u64 {
+        changes.saturating_sub(self.state.saved_changes.load(Ordering::Relaxed))
+    }
+
+    /// Records that the data is as it was when `changes` changes had been made, like after it was loaded.
+    pub(crate) fn mark_saved(&self, changes: u64) {
+        self.state.saved_changes.store(changes, Ordering::Relaxed);
+        self.state
+            .last_save
+            .store(now_ms() / 1000, Ordering::Relaxed);
+    }
+
+    /// Saves a snapshot taken when `changes` changes had been made.
+    pub(crate) fn save(&self, snapshot: &Snapshot, changes: u64) -> io::Result<()> {
+        self.state
+            .last_attempt
+            .store(now_ms() / 1000, Ordering::Relaxed);
+
+        let result = write_file(&self.path, &encode(snapshot));
+
+        self.state
+            .last_save_failed
+            .store(result.is_err(), Ordering::Relaxed);
+
+        if result.is_ok() {
+            self.mark_saved(changes);
+        }
+
+        result
+    }
+
+    /// Saves a snapshot on another thread. Returns `false` if a background save is already in progress.
+    pub(crate) fn save_in_background(&self, snapshot: Snapshot, changes: u64) -> bool {
+        if self.state.saving.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+
+        let snapshots = self.clone();
+
+        thread::spawn(move || {
+            let start = now_ms();
+
+            if let Err(error) = snapshots.save(&snapshot, changes) {
+                eprintln!("Background saving error: {error}");
+            }
+
+            snapshots
+                .state
+                .last_bgsave_duration
+                .store(((now_ms() - start) / 1000) as i64, Ordering::Relaxed);
+            snapshots.state.saving.store(false, Ordering::Relaxed);
+        });
+
+        true
+    }
+
+    /// Whether a save point has been reached, given the number of changes made in total. After a failed save, this
+    /// waits a few seconds before allowing another attempt.
+    pub(crate) fn should_save(&self, changes: u64) -> bool {
+        let now = now_ms() / 1000;
+        let since_save = now.saturating_sub(self.last_save());
+        let changes = self.changes_since_save(changes);
+        let can_retry = self.last_save_succeeded()
+            || now.saturating_sub(self.state.last_attempt.load(Ordering::Relaxed)) > RETRY_DELAY;
+
+        !self.is_saving()
+            && can_retry
+            && self
+                .save_points
+                .iter()
+                .any(|point| changes >= point.changes && since_save >= point.seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::Json;
+    use crate::time_series::DuplicatePolicy;
+
+    macro_rules! key {
+        ($key:expr) => {
+            BulkString::Filled($key.as_bytes().to_vec())
+        };
+    }
+
+    #[test]
+    fn lengths_and_strings() {
+        for length in [0, 0x3f, 0x40, 0x3fff, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut bytes = Vec::new();
+
+            write_length(&mut bytes, length);
+
+            assert_eq!(Some(length), read_length(&mut ByteReader::new(&bytes)));
+        }
+
+        let read = |bytes: &[u8]| read_string(&mut ByteReader::new(bytes));
+
+        assert_eq!(Some(b"-2".to_vec()), read(&[0xc0, 0xfe]));
+        assert_eq!(Some(b"1000".to_vec()), read(&[0xc1, 0xe8, 0x03]));
+        assert_eq!(
+            Some(b"70000".to_vec()),
+            read(&[0xc2, 0x70, 0x11, 0x01, 0x00])
+        );
+        assert_eq!(
+            Some(b"aaaa".to_vec()),
+            read(&[0xc3, 4, 4, 0, b'a', 1 << 5, 0])
+        );
+        assert_eq!(None, read(&[3, b'a']));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bloom_filter = ScalableBloomFilter::new(0.01, 10, Some(2)).unwrap();
+        let mut cuckoo_filter = CuckooFilter::new(16, 2, 20, 1).unwrap();
+        let mut time_series = TimeSeries::new(0, true, 64, DuplicatePolicy::Last, Vec::new());
+
+        for i in 0..20 {
+            bloom_filter.insert(&[i]).unwrap();
+            cuckoo_filter.insert(&[i]).unwrap();
+            time_series
+                .add(i as u64 * 1000, i as f64 / 2.0, None)
+                .unwrap();
+        }
+
+        cuckoo_filter.delete(&[3]);
+
+        let snapshot = Snapshot {
+            databases: vec![
+                vec![
+                    (key!("string"), Object::String(key!("value")), None),
+                    (key!("empty"), Object::String(BulkString::Empty), Some(1234)),
+                    (BulkString::Empty, Object::Json(Json::Null), None),
+                ],
+                Vec::new(),
+                vec![
+                    (key!("bloom"), Object::BloomFilter(bloom_filter), None),
+                    (key!("cuckoo"), Object::CuckooFilter(cuckoo_filter), None),
+                    (
+                        key!("json"),
+                        Object::Json(json::parse(br#"{"a":[1,2.5,"x",true]}"#).unwrap()),
+                        None,
+                    ),
+                    (key!("ts"), Object::TimeSeries(time_series), None),
+                ],
+            ],
+            libraries: vec![
+                b"#!lua name=lib\nredis.register_function('f', function() end)".to_vec(),
+            ],
+        };
+
+        let bytes = encode(&snapshot);
+
+        assert!(bytes.starts_with(b"REDIS0011"));
+        assert_eq!(Ok(snapshot), decode(&bytes));
+
+        let mut corrupted = bytes.clone();
+        let value = bytes.windows(5).position(|w| w == b"value").unwrap();
+
+        corrupted[value] ^= 1;
+
+        assert_eq!(Err("wrong checksum".to_string()), decode(&corrupted));
+        assert_eq!(
+            Err("unexpected end of file".to_string()),
+            decode(&bytes[..bytes.len() - 9])
+        );
+        assert_eq!(Err("wrong signature".to_string()), decode(b"REDIX0011"));
+    }
+
+    #[test]
+    fn dump_and_undump() {
+        let object = Object::Json(Json::Array(vec![Json::Integer(1)]));
+        let payload = dump(&object);
+
+        assert_eq!(Ok(object), undump(&payload));
+
+        // The payload in Redis's documentation of `DUMP`, for the integer-encoded string "10".
+        assert_eq!(
+            Ok(Object::String(BulkString::Filled(b"10".to_vec()))),
+            undump(b"\x00\xc0\x0a\x09\x00\xbe\x6d\x06\x89\x5a\x28\x00\x0a")
+        );
+
+        let mut corrupted = payload.clone();
+
+        corrupted[2] ^= 1;
+
+        assert_eq!(
+            Err("DUMP payload version or checksum are wrong"),
+            undump(&corrupted)
+        );
+        assert_eq!(
+            Err("DUMP payload version or checksum are wrong"),
+            undump(b"short")
+        );
+    }
+
+    #[test]
+    fn inconsistent_cuckoo_filters() {
+        let cuckoo_filter = |expansion, items| {
+            let mut bytes = Vec::new();
+
+            write_module(&mut bytes, CUCKOO_FILTER, |writer| {
+                for field in [2, 20, expansion, items, 0, 1, 1, 4] {
+                    writer.save_unsigned(field);
+                }
+
+                writer.save_string(&[0, 7, 0, 0, 0, 0, 0, 9]);
+            });
+
+            read_module(&mut ByteReader::new(&bytes))
+        };
+
+        assert!(cuckoo_filter(2, 2).is_ok());
+        assert!(cuckoo_filter(0, 2).is_ok());
+        assert!(cuckoo_filter(2, 3).is_err());
+        assert!(cuckoo_filter(2, 0).is_err());
+        assert!(cuckoo_filter(3, 2).is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("red-{}-save_and_load.rdb", process::id()));
+        let snapshots = Snapshots::new(path.clone());
+        let snapshot = Snapshot {
+            databases: vec![vec![(key!("a"), Object::String(key!("1")), None)]],
+            libraries: Vec::new(),
+        };
+
+        assert_eq!(Ok(None), load(&path));
+
+        snapshots.save(&snapshot, 3).unwrap();
+
+        assert_eq!(Ok(Some(snapshot)), load(&path));
+        assert_eq!(2, snapshots.changes_since_save(5));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writes_to_different_files() {
+        let directory = std::env::temp_dir();
+
+        thread::scope(|scope| {
+            for name in ["a", "b"] {
+                let path = directory.join(format!("red-{}-write-{name}.rdb", process::id()));
+
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        write_file(&path, name.as_bytes()).unwrap();
+                        assert_eq!(name.as_bytes(), fs::read(&path).unwrap());
+                    }
+
+                    fs::remove_file(path).unwrap();
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn save_points() {
+        let mut snapshots = Snapshots::new(PathBuf::from("unused.rdb"));
+
+        assert_eq!(
+            Some(DEFAULT_SAVE_POINTS.to_vec()),
+            parse_save_points("3600 1  300 100 60 10000")
+        );
+        assert_eq!(Some(Vec::new()), parse_save_points(""));
+        assert_eq!(None, parse_save_points("3600"));
+        assert_eq!(None, parse_save_points("3600 -1"));
+        assert_eq!(
+            "3600 1 300 100 60 10000",
+            format_save_points(&DEFAULT_SAVE_POINTS)
+        );
+
+        snapshots.set_save_points(parse_save_points("0 2").unwrap());
+
+        assert!(!snapshots.should_save(1));
+        assert!(snapshots.should_save(2));
+
+        snapshots.mark_saved(2);
+
+        assert!(!snapshots.should_save(3));
+    }
+}
@@ -0,0 +1,1078 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::net::{Shutdown, TcpStream};
+use std::str;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::aof::{encode, encode_transaction};
+use crate::array::Value;
+use crate::bulk_string::BulkString;
+use crate::client::Client;
+use crate::database::now_ms;
+use crate::pubsub::ClientId;
+use crate::rdb;
+use crate::Data;
+
+pub(crate) const DEFAULT_PORT: u16 = 6379;
+pub(crate) const DEFAULT_BACKLOG_SIZE: u64 = 1024 * 1024;
+/// How often a primary pings its replicas when nothing else is sent, in milliseconds, like Redis's
+/// `repl-ping-replica-period`.
+const PING_PERIOD: u64 = 10_000;
+/// How often a replica tells its primary how much of the stream it has processed, in milliseconds.
+const ACK_PERIOD: u64 = 1000;
+/// How long a replica waits before connecting again after the link with its primary failed, in milliseconds.
+const RETRY_DELAY: u64 = 1000;
+/// How long a replica waits for data from its primary before giving up on the link, like Redis's `repl-timeout`.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Generates a replication ID: 40 random hexadecimal characters, like Redis's. Cluster node IDs look the same.
+pub(crate) fn new_id() -> String {
+    let state = RandomState::new();
+    let id: String = (0..3)
+        .map(|i| {
+            let mut hasher = state.build_hasher();
+
+            hasher.write_u64(i);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect();
+
+    id[..40].to_string()
+}
+
+/// The state of a replica's link with its primary.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LinkState {
+    /// Waiting to connect, after `REPLICAOF` or after the link failed.
+    Connect,
+    /// Connecting and going through the handshake.
+    Connecting,
+    /// Receiving the primary's snapshot.
+    Sync,
+    /// Applying the primary's stream of write commands.
+    Connected,
+}
+
+impl LinkState {
+    /// The name `ROLE` reports.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        }
+    }
+}
+
+/// The primary a replica replicates, and the state of the link with it.
+#[derive(Debug)]
+pub(crate) struct Primary {
+    host: String,
+    port: u16,
+    state: LinkState,
+    /// Identifies the link, so that the thread running a link that was replaced by `REPLICAOF` knows to stop.
+    link: u64,
+    /// The connection, once synced, which acknowledgements are written to.
+    stream: Option<TcpStream>,
+    /// When data was last received from the primary, in milliseconds since the Unix epoch.
+    last_io: u64,
+    last_ack: u64,
+    /// When to connect again after the link failed.
+    retry_at: u64,
+}
+
+impl Primary {
+    pub(crate) fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn state(&self) -> LinkState {
+        self.state
+    }
+
+    /// How long ago data was last received, in seconds, or `None` if the link isn't up.
+    pub(crate) fn last_io_seconds_ago(&self) -> Option<u64> {
+        (self.state == LinkState::Connected).then(|| now_ms().saturating_sub(self.last_io) / 1000)
+    }
+}
+
+/// A replica connected to this server, which is sent the stream of write commands.
+#[derive(Debug)]
+pub(crate) struct Replica {
+    client: ClientId,
+    sender: Sender<Vec<u8>>,
+    /// The replica's connection, to close it when it has to sync again. `None` for connections without a socket.
+    stream: Option<TcpStream>,
+    address: String,
+    /// The port the replica listens on, as announced by `REPLCONF listening-port`.
+    port: u16,
+    /// How much of the stream the replica has processed, as of its latest acknowledgement.
+    ack_offset: u64,
+    /// How much of the stream the replica has in its append-only file on disk, as of its latest acknowledgement.
+    aof_ack_offset: u64,
+    last_ack: u64,
+}
+
+impl Replica {
+    pub(crate) fn new(
+        client: ClientId,
+        sender: Sender<Vec<u8>>,
+        stream: Option<TcpStream>,
+        port: u16,
+    ) -> Replica {
+        let address = stream
+            .as_ref()
+            .and_then(|stream| stream.peer_addr().ok())
+            .map_or("?".to_string(), |address| address.ip().to_string());
+
+        Replica {
+            client,
+            sender,
+            stream,
+            address,
+            port,
+            ack_offset: 0,
+            aof_ack_offset: 0,
+            last_ack: now_ms(),
+        }
+    }
+
+    pub(crate) fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn ack_offset(&self) -> u64 {
+        self.ack_offset
+    }
+
+    /// How long ago the replica last acknowledged the stream, in seconds.
+    pub(crate) fn lag(&self) -> u64 {
+        now_ms().saturating_sub(self.last_ack) / 1000
+    }
+}
+
+/// The end of the replication stream, so that a replica whose link failed for a moment can be sent what it missed
+/// rather than all of the data. Like Redis's, it's created when the first replica connects, and older bytes are
+/// dropped once it's full.
+#[derive(Debug)]
+struct Backlog {
+    bytes: VecDeque<u8>,
+    size: usize,
+}
+
+impl Backlog {
+    fn new(size: u64) -> Backlog {
+        Backlog {
+            bytes: VecDeque::new(),
+            size: size as usize,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+        self.trim();
+    }
+
+    fn resize(&mut self, size: u64) {
+        self.size = size as usize;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let excess = self.bytes.len().saturating_sub(self.size);
+
+        self.bytes.drain(..excess);
+    }
+
+    /// The bytes that follow offset `from` in a stream that ends at offset `end`, or `None` if they aren't all in the
+    /// backlog anymore, or `from` is past the end.
+    fn since(&self, from: u64, end: u64) -> Option<Vec<u8>> {
+        let missing = end.checked_sub(from)?;
+
+        if missing > self.bytes.len() as u64 {
+            return None;
+        }
+
+        Some(
+            self.bytes
+                .range(self.bytes.len() - missing as usize..)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+/// Which role the server has, and the replication stream: the write commands a primary sends its replicas, in the
+/// format of the append-only file. The offset counts the bytes of the stream since the dataset with the replication
+/// ID was created, so that a primary and its replicas can tell how far along they are.
+///
+/// A replica forwards the stream it receives, as is, to replicas of its own. Its own writes, if it isn't read-only,
+/// aren't replicated. A replica asks to continue the stream from its own replication ID and offset, which match its
+/// primary's as long as it has followed that primary's stream, so after a brief disconnection it only needs what it
+/// missed, from the [`Backlog`].
+#[derive(Debug)]
+pub(crate) struct Replication {
+    id: String,
+    offset: u64,
+    /// The replication ID before this server was promoted from a replica, with the offset up to which its stream is
+    /// the same as the current one's. Replicas of the former primary can continue their stream from here.
+    id2: Option<(String, u64)>,
+    /// How much of the stream is known to be in the append-only file on disk, which a replica acknowledges.
+    aof_offset: u64,
+    backlog: Option<Backlog>,
+    backlog_size: u64,
+    /// The database the commands in the stream apply to, as set by the last `SELECT` sent, or `None` before the first.
+    selected: Option<usize>,
+    /// The commands replicated by the command being run, with their database, sent together once it finishes.
+    pending: Vec<(usize, Vec<Vec<u8>>)>,
+    replicas: Vec<Replica>,
+    last_ping: u64,
+    /// The port this server listens on, which it announces to its primary.
+    port: u16,
+    read_only: bool,
+    /// The primary this server replicates, or `None` if it's a primary itself.
+    primary: Option<Primary>,
+    /// The number of links with a primary created, which identifies the latest.
+    links: u64,
+}
+
+impl Replication {
+    pub(crate) fn new() -> Replication {
+        Replication {
+            id: new_id(),
+            offset: 0,
+            id2: None,
+            aof_offset: 0,
+            backlog: None,
+            backlog_size: DEFAULT_BACKLOG_SIZE,
+            selected: None,
+            pending: Vec::new(),
+            replicas: Vec::new(),
+            last_ping: now_ms(),
+            port: DEFAULT_PORT,
+            read_only: true,
+            primary: None,
+            links: 0,
+        }
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn id2(&self) -> Option<(&str, u64)> {
+        self.id2.as_ref().map(|(id, offset)| (id.as_str(), *offset))
+    }
+
+    pub(crate) fn aof_offset(&self) -> u64 {
+        self.aof_offset
+    }
+
+    pub(crate) fn set_aof_offset(&mut self, offset: u64) {
+        self.aof_offset = offset;
+    }
+
+    pub(crate) fn backlog_size(&self) -> u64 {
+        self.backlog_size
+    }
+
+    pub(crate) fn set_backlog_size(&mut self, size: u64) {
+        self.backlog_size = size;
+
+        if let Some(backlog) = &mut self.backlog {
+            backlog.resize(size);
+        }
+    }
+
+    /// The offset of the first byte in the backlog, counting from 1 like Redis, and the number of bytes in it, or
+    /// `None` if there's no backlog.
+    pub(crate) fn backlog_range(&self) -> Option<(u64, u64)> {
+        self.backlog.as_ref().map(|backlog| {
+            let length = backlog.bytes.len() as u64;
+
+            (self.offset - length + 1, length)
+        })
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
+
+    pub(crate) fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub(crate) fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub(crate) fn primary(&self) -> Option<&Primary> {
+        self.primary.as_ref()
+    }
+
+    pub(crate) fn replicas(&self) -> &[Replica] {
+        &self.replicas
+    }
+
+    /// Whether writes from clients are refused, because this is a read-only replica.
+    pub(crate) fn refuses_writes(&self) -> bool {
+        self.primary.is_some() && self.read_only
+    }
+
+    /// Whether replicas can sync with this server. A replica has to be synced with its own primary first.
+    pub(crate) fn can_sync(&self) -> bool {
+        self.primary
+            .as_ref()
+            .is_none_or(|primary| primary.state == LinkState::Connected)
+    }
+
+    /// Makes this server a replica of the primary at `host` and `port`, which it connects to on the next
+    /// [`cron`](Replication::cron). Returns `false` if it already is.
+    pub(crate) fn set_primary(&mut self, host: String, port: u16) -> bool {
+        if self
+            .primary
+            .as_ref()
+            .is_some_and(|primary| primary.host == host && primary.port == port)
+        {
+            return false;
+        }
+
+        self.close_primary_link();
+        self.links += 1;
+        self.primary = Some(Primary {
+            host,
+            port,
+            state: LinkState::Connect,
+            link: self.links,
+            stream: None,
+            last_io: 0,
+            last_ack: 0,
+            retry_at: 0,
+        });
+
+        true
+    }
+
+    /// Makes this server a primary again, keeping its data. It gets a new replication ID, since its stream no longer
+    /// follows the former primary's, but keeps the former one as its secondary ID.
+    pub(crate) fn remove_primary(&mut self) {
+        self.close_primary_link();
+        self.primary = None;
+        self.id2 = Some((mem::replace(&mut self.id, new_id()), self.offset));
+        self.selected = None;
+        self.backlog
+            .get_or_insert_with(|| Backlog::new(self.backlog_size));
+    }
+
+    fn close_primary_link(&mut self) {
+        if let Some(stream) = self
+            .primary
+            .as_mut()
+            .and_then(|primary| primary.stream.take())
+        {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Whether `link` is still the link with the primary.
+    fn is_current(&self, link: u64) -> bool {
+        self.primary
+            .as_ref()
+            .is_some_and(|primary| primary.link == link)
+    }
+
+    fn set_state(&mut self, link: u64, state: LinkState) {
+        if let Some(primary) = &mut self.primary {
+            if primary.link == link {
+                primary.state = state;
+            }
+        }
+    }
+
+    /// Records that `link` failed, so that the next [`cron`](Replication::cron) after a delay connects again.
+    fn link_failed(&mut self, link: u64) {
+        self.close_primary_link();
+
+        if let Some(primary) = &mut self.primary {
+            if primary.link == link {
+                primary.state = LinkState::Connect;
+                primary.retry_at = now_ms() + RETRY_DELAY;
+            }
+        }
+    }
+
+    /// Records that the data was replaced by the snapshot of the primary with replication ID `id`, taken at `offset`.
+    /// Replicas of this server have to sync again, so they're disconnected.
+    fn synced(&mut self, link: u64, id: String, offset: u64, stream: TcpStream) {
+        self.id = id;
+        self.id2 = None;
+        self.offset = offset;
+        self.aof_offset = 0;
+        self.backlog = Some(Backlog::new(self.backlog_size));
+        self.disconnect_replicas();
+        self.link_up(link, stream);
+    }
+
+    /// Records that the primary continued the stream where it was interrupted. If the primary's replication ID
+    /// changed, because it was promoted, the former one becomes the secondary ID, and replicas of this server are
+    /// disconnected so that they learn the new one.
+    fn continued(&mut self, link: u64, id: Option<String>, stream: TcpStream) {
+        if let Some(id) = id.filter(|id| *id != self.id) {
+            self.id2 = Some((mem::replace(&mut self.id, id), self.offset));
+            self.disconnect_replicas();
+        }
+
+        self.backlog
+            .get_or_insert_with(|| Backlog::new(self.backlog_size));
+        self.link_up(link, stream);
+    }
+
+    fn disconnect_replicas(&mut self) {
+        self.selected = None;
+
+        for replica in self.replicas.drain(..) {
+            if let Some(stream) = replica.stream {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    fn link_up(&mut self, link: u64, stream: TcpStream) {
+        let now = now_ms();
+
+        if let Some(primary) = &mut self.primary {
+            if primary.link == link {
+                primary.state = LinkState::Connected;
+                primary.stream = Some(stream);
+                primary.last_io = now;
+                primary.last_ack = now;
+            }
+        }
+    }
+
+    /// Records that a replica applied `bytes` of its primary's stream, which are forwarded to its own replicas.
+    fn applied(&mut self, bytes: &[u8]) {
+        if let Some(primary) = &mut self.primary {
+            primary.last_io = now_ms();
+        }
+
+        self.send(bytes);
+    }
+
+    /// Whether commands that run are replicated: this is a primary that has had replicas, which may reconnect.
+    pub(crate) fn is_streaming(&self) -> bool {
+        self.primary.is_none() && self.backlog.is_some()
+    }
+
+    /// Replicates a command that ran against `database`. It's sent by the next [`flush`](Replication::flush).
+    pub(crate) fn feed(&mut self, database: usize, command: Vec<Vec<u8>>) {
+        if self.is_streaming() {
+            self.pending.push((database, command));
+        }
+    }
+
+    /// Sends the commands replicated since the last call, wrapped in `MULTI` and `EXEC` if there are several.
+    pub(crate) fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let bytes = encode_transaction(&mut self.selected, self.pending.drain(..));
+
+        self.send(&bytes);
+    }
+
+    /// Appends `bytes` to the stream, sending them to every replica. Replicas whose connection closed are dropped.
+    fn send(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as u64;
+
+        if let Some(backlog) = &mut self.backlog {
+            backlog.push(bytes);
+        }
+
+        self.replicas
+            .retain(|replica| replica.sender.send(bytes.to_vec()).is_ok());
+    }
+
+    /// Adds a replica, which is sent the stream from the current offset on.
+    pub(crate) fn add_replica(&mut self, replica: Replica) {
+        self.backlog
+            .get_or_insert_with(|| Backlog::new(self.backlog_size));
+        self.replicas.push(replica);
+    }
+
+    /// What a replica that received the stream with replication ID `id` up to offset `from` missed, or `None` if it
+    /// has to sync from scratch: the ID is unknown, or what it missed isn't in the backlog anymore.
+    pub(crate) fn continuation(&self, id: &str, from: u64) -> Option<Vec<u8>> {
+        let known = id == self.id
+            || self
+                .id2
+                .as_ref()
+                .is_some_and(|(id2, until)| id == id2 && from <= *until);
+
+        if !known {
+            return None;
+        }
+
+        self.backlog.as_ref()?.since(from, self.offset)
+    }
+
+    /// Sends the stream to the replica on connection `client` through `sender` from now on.
+    pub(crate) fn set_replica_sender(&mut self, client: ClientId, sender: Sender<Vec<u8>>) {
+        if let Some(replica) = self
+            .replicas
+            .iter_mut()
+            .find(|replica| replica.client == client)
+        {
+            replica.sender = sender;
+        }
+    }
+
+    pub(crate) fn remove_replica(&mut self, client: ClientId) {
+        self.replicas.retain(|replica| replica.client != client);
+    }
+
+    /// Records a replica's acknowledgement that it processed the stream up to `offset`, and that its append-only file
+    /// on disk has it up to `aof_offset`.
+    pub(crate) fn acknowledge(&mut self, client: ClientId, offset: u64, aof_offset: Option<u64>) {
+        if let Some(replica) = self
+            .replicas
+            .iter_mut()
+            .find(|replica| replica.client == client)
+        {
+            replica.ack_offset = offset;
+            replica.last_ack = now_ms();
+
+            if let Some(aof_offset) = aof_offset {
+                replica.aof_ack_offset = aof_offset;
+            }
+        }
+    }
+
+    /// Asks the replicas to acknowledge the stream right away, rather than at their next periodic acknowledgement.
+    pub(crate) fn request_acks(&mut self) {
+        if self.replicas.is_empty() {
+            return;
+        }
+
+        let mut bytes = Vec::new();
+
+        encode(
+            &mut bytes,
+            &[b"REPLCONF".to_vec(), b"GETACK".to_vec(), b"*".to_vec()],
+        );
+        self.send(&bytes);
+    }
+
+    /// How many replicas acknowledged the stream up to `offset`, counting only what's in their append-only file on
+    /// disk if `aof` is set.
+    pub(crate) fn acknowledged(&self, offset: u64, aof: bool) -> usize {
+        self.replicas
+            .iter()
+            .filter(|replica| {
+                if aof {
+                    replica.aof_ack_offset >= offset
+                } else {
+                    replica.ack_offset >= offset
+                }
+            })
+            .count()
+    }
+
+    /// Pings the replicas of a primary, and sends a replica's acknowledgements to its primary, with how much of the
+    /// stream is in its append-only file on disk. Returns the primary to connect to, with the link's identifier, if a
+    /// replica should connect. Called periodically.
+    pub(crate) fn cron(&mut self, aof_offset: Option<u64>) -> Option<(String, u16, u64)> {
+        let now = now_ms();
+        let offset = self.offset;
+
+        let Some(primary) = &mut self.primary else {
+            if !self.replicas.is_empty() && now - self.last_ping >= PING_PERIOD {
+                let mut bytes = Vec::new();
+
+                encode(&mut bytes, &[b"PING".to_vec()]);
+                self.send(&bytes);
+                self.last_ping = now;
+            }
+
+            return None;
+        };
+
+        match primary.state {
+            LinkState::Connect if now >= primary.retry_at => {
+                primary.state = LinkState::Connecting;
+
+                Some((primary.host.clone(), primary.port, primary.link))
+            }
+            LinkState::Connected if now - primary.last_ack >= ACK_PERIOD => {
+                if let Some(stream) = &mut primary.stream {
+                    // A failed write means the link is down, which the thread reading from it finds out.
+                    let _ = stream.write_all(&ack(offset, aof_offset));
+                }
+
+                primary.last_ack = now;
+
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `REPLCONF ACK <offset> [FACK <aof offset>]`, which a replica sends its primary.
+fn ack(offset: u64, aof_offset: Option<u64>) -> Vec<u8> {
+    let mut command = vec![
+        b"REPLCONF".to_vec(),
+        b"ACK".to_vec(),
+        offset.to_string().into_bytes(),
+    ];
+
+    if let Some(aof_offset) = aof_offset {
+        command.extend([b"FACK".to_vec(), aof_offset.to_string().into_bytes()]);
+    }
+
+    let mut bytes = Vec::new();
+
+    encode(&mut bytes, &command);
+
+    bytes
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a line, appending it to `raw`, and returns it without its line ending.
+fn read_line(reader: &mut impl BufRead, raw: &mut Vec<u8>) -> io::Result<String> {
+    let start = raw.len();
+
+    if reader.read_until(b'\n', raw)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let line = raw[start..]
+        .strip_suffix(b"\n")
+        .ok_or(io::ErrorKind::UnexpectedEof)?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    Ok(String::from_utf8_lossy(line).into_owned())
+}
+
+/// Reads the primary's reply to a handshake command, skipping the empty lines it may send to keep the link alive.
+fn read_reply(reader: &mut impl BufRead) -> io::Result<String> {
+    loop {
+        let line = read_line(reader, &mut Vec::new())?;
+
+        if !line.is_empty() {
+            return Ok(line);
+        }
+    }
+}
+
+/// Reads a command of the stream, returning it along with its bytes.
+fn read_command(reader: &mut impl BufRead) -> io::Result<(Vec<Vec<u8>>, Vec<u8>)> {
+    let mut raw = Vec::new();
+    let line = read_line(reader, &mut raw)?;
+    let count: usize = line
+        .strip_prefix('*')
+        .and_then(|count| count.parse().ok())
+        .filter(|&count| count > 0)
+        .ok_or_else(|| invalid_data(format!("unexpected line '{line}'")))?;
+    let mut command = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let line = read_line(reader, &mut raw)?;
+        let length: usize = line
+            .strip_prefix('$')
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| invalid_data(format!("unexpected line '{line}'")))?;
+        let start = raw.len();
+
+        raw.resize(start + length + 2, 0);
+        reader.read_exact(&mut raw[start..])?;
+
+        if !raw.ends_with(b"\r\n") {
+            return Err(invalid_data("missing line ending"));
+        }
+
+        command.push(raw[start..start + length].to_vec());
+    }
+
+    Ok((command, raw))
+}
+
+/// Sends a handshake command and returns the reply.
+fn request(
+    writer: &mut TcpStream,
+    reader: &mut impl BufRead,
+    command: &[&str],
+) -> io::Result<String> {
+    let mut bytes = Vec::new();
+
+    encode(
+        &mut bytes,
+        &command
+            .iter()
+            .map(|argument| argument.as_bytes().to_vec())
+            .collect::<Vec<_>>(),
+    );
+    writer.write_all(&bytes)?;
+
+    read_reply(reader)
+}
+
+/// Connects to a primary, loads its snapshot and applies its stream of write commands until the link fails or is
+/// replaced. Runs on its own thread, started when [`Replication::cron`] says to connect.
+pub(crate) fn replicate(data: Arc<Mutex<Data>>, host: String, port: u16, link: u64) {
+    println!("Connecting to MASTER {host}:{port}");
+
+    let result = sync_and_apply(&data, &host, port, link);
+    let mut data = data.lock().expect("failed to acquire lock");
+
+    if !data.replication().is_current(link) {
+        return;
+    }
+
+    if let Err(error) = result {
+        eprintln!("Lost the link with MASTER {host}:{port}: {error}");
+    }
+
+    data.replication_mut().link_failed(link);
+}
+
+fn sync_and_apply(data: &Mutex<Data>, host: &str, port: u16, link: u64) -> io::Result<()> {
+    let stream = TcpStream::connect((host, port))?;
+
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let (listening_port, id, offset) = {
+        let data = data.lock().expect("failed to acquire lock");
+        let replication = data.replication();
+
+        (
+            replication.port().to_string(),
+            replication.id().to_string(),
+            replication.offset(),
+        )
+    };
+
+    let reply = request(&mut writer, &mut reader, &["PING"])?;
+
+    if reply.starts_with('-') {
+        return Err(invalid_data(format!("error reply to PING: {reply}")));
+    }
+
+    // Older primaries don't know every option, which isn't a reason to give up.
+    request(
+        &mut writer,
+        &mut reader,
+        &["REPLCONF", "listening-port", &listening_port],
+    )?;
+    request(&mut writer, &mut reader, &["REPLCONF", "capa", "psync2"])?;
+
+    // Like Redis, the offset asked for is that of the first byte that's missing.
+    let reply = request(
+        &mut writer,
+        &mut reader,
+        &["PSYNC", &id, &(offset + 1).to_string()],
+    )?;
+
+    match reply.split(' ').collect::<Vec<_>>()[..] {
+        ["+FULLRESYNC", id, offset] => {
+            let offset = offset
+                .parse()
+                .map_err(|_| invalid_data(format!("unexpected reply to PSYNC: {reply}")))?;
+
+            if !full_sync(data, &mut reader, &writer, link, id.to_string(), offset)? {
+                return Ok(());
+            }
+        }
+        ["+CONTINUE", ..] => {
+            let mut data = data.lock().expect("failed to acquire lock");
+
+            if !data.replication().is_current(link) {
+                return Ok(());
+            }
+
+            let id = reply.split(' ').nth(1).map(str::to_string);
+
+            data.replication_mut()
+                .continued(link, id, writer.try_clone()?);
+            println!("Successful partial resynchronization with MASTER");
+        }
+        _ => return Err(invalid_data(format!("unexpected reply to PSYNC: {reply}"))),
+    }
+
+    let mut client = Client::for_primary(mpsc::channel().0);
+
+    loop {
+        let (command, raw) = read_command(&mut reader)?;
+        let name = String::from_utf8_lossy(&command[0]).to_uppercase();
+
+        // The acknowledgement doesn't count the request for it, which is only processed afterwards.
+        if name == "REPLCONF"
+            && command
+                .get(1)
+                .is_some_and(|option| option.eq_ignore_ascii_case(b"GETACK"))
+        {
+            let mut data = data.lock().expect("failed to acquire lock");
+            let aof_offset = data.aof_replication_offset();
+
+            writer.write_all(&ack(data.replication().offset(), aof_offset))?;
+        } else {
+            let arguments: Vec<Value> = command[1..]
+                .iter()
+                .map(|argument| match argument.as_slice() {
+                    [] => Value::BulkString(BulkString::Empty),
+                    argument => Value::BulkString(BulkString::Filled(argument.to_vec())),
+                })
+                .collect();
+
+            client.process(data, &name, &arguments);
+        }
+
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        if !data.replication().is_current(link) {
+            return Ok(());
+        }
+
+        data.replication_mut().applied(&raw);
+    }
+}
+
+/// Loads the snapshot the primary sends for a full resynchronization, replacing the data. Returns `false` if the link
+/// was replaced in the meantime.
+fn full_sync(
+    data: &Mutex<Data>,
+    reader: &mut impl BufRead,
+    writer: &TcpStream,
+    link: u64,
+    id: String,
+    offset: u64,
+) -> io::Result<bool> {
+    println!("Full resync from MASTER: {id}:{offset}");
+    data.lock()
+        .expect("failed to acquire lock")
+        .replication_mut()
+        .set_state(link, LinkState::Sync);
+
+    let line = read_reply(reader)?;
+    let length: usize = line
+        .strip_prefix('$')
+        .and_then(|length| length.parse().ok())
+        .ok_or_else(|| invalid_data(format!("unexpected snapshot header '{line}'")))?;
+    let mut payload = vec![0; length];
+
+    reader.read_exact(&mut payload)?;
+
+    let snapshot = rdb::decode(&payload).map_err(invalid_data)?;
+
+    {
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        if !data.replication().is_current(link) {
+            return Ok(false);
+        }
+
+        data.replace(snapshot).map_err(invalid_data)?;
+        data.replication_mut()
+            .synced(link, id, offset, writer.try_clone()?);
+
+        // The file has to be rewritten, since it no longer matches the data.
+        if data.aof().is_enabled() {
+            if let Err(error) = data.rewrite_aof() {
+                eprintln!("Can't rewrite the append only file: {error}");
+            }
+        }
+    }
+
+    println!("MASTER <-> REPLICA sync: Finished with success");
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_commands() {
+        let mut bytes = Vec::new();
+
+        encode(
+            &mut bytes,
+            &[b"SET".to_vec(), b"a".to_vec(), b"1\r\n".to_vec()],
+        );
+        encode(&mut bytes, &[b"PING".to_vec()]);
+
+        let mut reader = &bytes[..];
+        let (command, raw) = read_command(&mut reader).unwrap();
+
+        assert_eq!(
+            vec![b"SET".to_vec(), b"a".to_vec(), b"1\r\n".to_vec()],
+            command
+        );
+        assert_eq!(bytes.len() - 14, raw.len());
+        assert_eq!(vec![b"PING".to_vec()], read_command(&mut reader).unwrap().0);
+        assert!(read_command(&mut reader).is_err());
+        assert!(read_command(&mut &b"PING\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn stream() {
+        let mut replication = Replication::new();
+        let (sender, receiver) = mpsc::channel();
+
+        // Nothing is replicated without replicas.
+        replication.feed(0, vec![b"DEL".to_vec(), b"a".to_vec()]);
+        replication.flush();
+        assert_eq!(0, replication.offset());
+
+        replication.add_replica(Replica::new(1, sender, None, 6380));
+        replication.feed(1, vec![b"DEL".to_vec(), b"a".to_vec()]);
+        replication.flush();
+
+        let bytes = receiver.try_recv().unwrap();
+
+        assert_eq!(
+            b"*2\r\n$6\r\nSELECT\r\n$1\r\n1\r\n*2\r\n$3\r\nDEL\r\n$1\r\na\r\n".to_vec(),
+            bytes
+        );
+        assert_eq!(bytes.len() as u64, replication.offset());
+
+        replication.acknowledge(1, 10, None);
+        assert_eq!(10, replication.replicas()[0].ack_offset());
+        assert_eq!(1, replication.acknowledged(10, false));
+        assert_eq!(0, replication.acknowledged(10, true));
+        assert_eq!(0, replication.acknowledged(11, false));
+
+        replication.acknowledge(1, 10, Some(10));
+        assert_eq!(1, replication.acknowledged(10, true));
+
+        // Replicas are asked to acknowledge through the stream.
+        let offset = replication.offset();
+
+        replication.request_acks();
+        assert_eq!(
+            b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n".to_vec(),
+            receiver.try_recv().unwrap()
+        );
+        assert_eq!(offset + 37, replication.offset());
+
+        // Replicas whose connection closed are dropped.
+        drop(receiver);
+        replication.feed(1, vec![b"DEL".to_vec(), b"a".to_vec()]);
+        replication.flush();
+        assert!(replication.replicas().is_empty());
+    }
+
+    #[test]
+    fn backlog() {
+        let mut backlog = Backlog::new(4);
+
+        backlog.push(b"abc");
+        assert_eq!(Some(b"bc".to_vec()), backlog.since(1, 3));
+        assert_eq!(Some(Vec::new()), backlog.since(3, 3));
+        assert_eq!(None, backlog.since(4, 3));
+
+        // Older bytes are dropped once it's full.
+        backlog.push(b"def");
+        assert_eq!(Some(b"cdef".to_vec()), backlog.since(2, 6));
+        assert_eq!(None, backlog.since(1, 6));
+
+        backlog.resize(2);
+        assert_eq!(None, backlog.since(3, 6));
+        assert_eq!(Some(b"ef".to_vec()), backlog.since(4, 6));
+    }
+
+    #[test]
+    fn partial_resynchronization() {
+        let mut replication = Replication::new();
+        let id = replication.id().to_string();
+
+        assert_eq!(None, replication.continuation(&id, 0));
+
+        replication.add_replica(Replica::new(1, mpsc::channel().0, None, 6380));
+        replication.remove_replica(1);
+
+        // Commands are still added to the backlog while the replica is away.
+        replication.feed(0, vec![b"DEL".to_vec(), b"a".to_vec()]);
+        replication.flush();
+
+        let offset = replication.offset();
+        let missed = replication.continuation(&id, 0).unwrap();
+
+        assert_eq!(offset, missed.len() as u64);
+        assert_eq!(Some(Vec::new()), replication.continuation(&id, offset));
+        assert_eq!(None, replication.continuation(&id, offset + 1));
+        assert_eq!(None, replication.continuation("unknown", 0));
+        assert_eq!(Some((1, offset)), replication.backlog_range());
+
+        // A promoted replica accepts its former primary's ID up to where their streams part.
+        replication.set_primary("localhost".to_string(), 6380);
+        replication.remove_primary();
+        assert_ne!(id, replication.id());
+        assert_eq!(Some((id.as_str(), offset)), replication.id2());
+        assert_eq!(Some(Vec::new()), replication.continuation(&id, offset));
+
+        replication.feed(0, vec![b"DEL".to_vec(), b"a".to_vec()]);
+        replication.flush();
+        assert_eq!(None, replication.continuation(&id, offset + 1));
+        assert!(replication.continuation(&id, offset).is_some());
+    }
+
+    #[test]
+    fn roles() {
+        let mut replication = Replication::new();
+        let id = replication.id().to_string();
+
+        assert_eq!(40, id.len());
+        assert!(!replication.refuses_writes());
+        assert!(replication.set_primary("localhost".to_string(), 6380));
+        assert!(!replication.set_primary("localhost".to_string(), 6380));
+        assert!(replication.refuses_writes());
+        assert!(!replication.can_sync());
+        assert_eq!(
+            Some(("localhost".to_string(), 6380, 1)),
+            replication.cron(None)
+        );
+        assert_eq!(
+            LinkState::Connecting,
+            replication.primary().unwrap().state()
+        );
+
+        // A failed link is retried after a delay.
+        replication.link_failed(1);
+        assert_eq!(None, replication.cron(None));
+        assert_eq!(LinkState::Connect, replication.primary().unwrap().state());
+
+        replication.remove_primary();
+        assert!(replication.primary().is_none());
+        assert_ne!(id, replication.id());
+    }
+}
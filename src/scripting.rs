@@ -0,0 +1,713 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::array;
+use crate::bulk_string::BulkString;
+use crate::cluster;
+use crate::commands::{bytes, get_command, keyword, Response, NO_SCRIPT};
+use crate::database::now_ms;
+use crate::json::{self, Json};
+use crate::lua::{self, Error, FunctionBody, Host, Interpreter, Table, Value};
+use crate::sha1::sha1_hex;
+use crate::Data;
+
+/// How long a script can run, in milliseconds, before other connections get `BUSY` errors and it can be killed.
+pub(crate) const DEFAULT_BUSY_REPLY_THRESHOLD: u64 = 5000;
+
+/// Replies nested deeper than this are cut off when a Lua table is converted to a reply, in case a table contains itself.
+const MAX_REPLY_DEPTH: usize = 100;
+
+// Scripts run while `Data` is locked, so the state of the running script is kept outside it, where other connections
+// can see it and ask for the script to be killed.
+static BUSY_REPLY_THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_BUSY_REPLY_THRESHOLD);
+/// When the running script started, in milliseconds since the Unix epoch, or 0 if no script is running.
+static RUNNING_SINCE: AtomicU64 = AtomicU64::new(0);
+/// Whether the running script has called a write command, after which killing it would leave the dataset half
+/// modified.
+static RUNNING_WROTE: AtomicBool = AtomicBool::new(false);
+static KILL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn busy_reply_threshold() -> u64 {
+    BUSY_REPLY_THRESHOLD.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_busy_reply_threshold(milliseconds: u64) {
+    BUSY_REPLY_THRESHOLD.store(milliseconds, Ordering::Relaxed);
+}
+
+/// Handles `SCRIPT KILL` and `FUNCTION KILL`, and rejects every other command while a script has been running for longer than the busy
+/// reply threshold. Called before the lock on `Data` is taken, since the running script holds it. Returns `None` if the
+/// command should run as usual.
+pub(crate) fn intercept(name: &str, arguments: &[array::Value]) -> Option<Response> {
+    if matches!(name, "SCRIPT" | "FUNCTION")
+        && arguments.len() == 1
+        && keyword(&arguments[0]).as_deref() == Some("KILL")
+    {
+        return Some(kill());
+    }
+
+    let since = RUNNING_SINCE.load(Ordering::Relaxed);
+
+    if since != 0 && now_ms().saturating_sub(since) >= busy_reply_threshold() {
+        return Some(Response::Error(
+            "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSCRIPT.",
+        ));
+    }
+
+    None
+}
+
+/// Asks the running script to stop, unless it has already written to the dataset.
+pub(crate) fn kill() -> Response {
+    if RUNNING_SINCE.load(Ordering::Relaxed) == 0 {
+        return Response::Error("NOTBUSY No scripts in execution right now.");
+    }
+
+    if RUNNING_WROTE.load(Ordering::Relaxed) {
+        return Response::Error(
+            "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.",
+        );
+    }
+
+    KILL_REQUESTED.store(true, Ordering::Relaxed);
+
+    Response::SimpleString("OK")
+}
+
+/// Compiles a script, or looks it up in the cache. Returns the script's SHA-1, which `EVALSHA` takes, with it.
+pub(crate) fn load(
+    data: &mut Data,
+    source: &[u8],
+) -> Result<(String, Arc<FunctionBody>), Response> {
+    let sha = sha1_hex(source);
+
+    if let Some(body) = data.scripts_mut().get(&sha) {
+        return Ok((sha, body.clone()));
+    }
+
+    let body = lua::parse(source).map_err(|error| {
+        Response::OwnedError(format!(
+            "Error compiling script (new function): user_script:{}: {}",
+            error.line, error.message
+        ))
+    })?;
+
+    data.scripts_mut().insert(sha.clone(), body.clone());
+
+    Ok((sha, body))
+}
+
+/// Runs a compiled script with its `KEYS` and `ARGV`. Read-only scripts can't call write commands.
+pub(crate) fn run(
+    data: &mut Data,
+    sha: &str,
+    body: Arc<FunctionBody>,
+    keys: &[array::Value],
+    arguments: &[array::Value],
+    read_only: bool,
+) -> Response {
+    let result = track(data, |data| {
+        let mut host = Redis::new(data, read_only);
+        let mut interpreter = Interpreter::new("user_script", &mut host);
+
+        open(&mut interpreter);
+        interpreter.set_global("KEYS", strings(keys));
+        interpreter.set_global("ARGV", strings(arguments));
+        interpreter.strict_globals = true;
+
+        interpreter
+            .execute(body, Vec::new())
+            .map(|values| values.into_iter().next().unwrap_or_default())
+    });
+
+    match result {
+        Ok(value) => to_reply(&value, 0),
+        Err(error) => error_reply(error, &format!("script: {sha}")),
+    }
+}
+
+/// Runs a script or a function, making it visible to other connections so that they get `BUSY` replies and can kill
+/// it.
+pub(crate) fn track<T>(data: &mut Data, run: impl FnOnce(&mut Data) -> T) -> T {
+    // A script can `SELECT` another database, but that doesn't change the connection's.
+    let selected = data.selected();
+
+    KILL_REQUESTED.store(false, Ordering::Relaxed);
+    RUNNING_WROTE.store(false, Ordering::Relaxed);
+    RUNNING_SINCE.store(now_ms().max(1), Ordering::Relaxed);
+
+    let result = run(data);
+
+    RUNNING_SINCE.store(0, Ordering::Relaxed);
+    data.select(selected);
+
+    result
+}
+
+/// Converts the arguments of a script to a table of strings, like `KEYS` and `ARGV`.
+pub(crate) fn strings(arguments: &[array::Value]) -> Value {
+    Value::table(Table::from_values(
+        arguments
+            .iter()
+            .map(|argument| Value::string(bytes(argument).unwrap_or_default()))
+            .collect(),
+    ))
+}
+
+/// Defines the `redis` and `cjson` libraries.
+pub(crate) fn open(interpreter: &mut Interpreter<'_>) {
+    let mut redis = lua::library(&[
+        ("error_reply", redis_error_reply),
+        ("log", redis_log),
+        ("sha1hex", redis_sha1hex),
+        ("status_reply", redis_status_reply),
+    ]);
+
+    redis.set_str("call", Value::HostFunction("call"));
+    redis.set_str("pcall", Value::HostFunction("pcall"));
+
+    for (name, level) in [
+        ("LOG_DEBUG", 0),
+        ("LOG_VERBOSE", 1),
+        ("LOG_NOTICE", 2),
+        ("LOG_WARNING", 3),
+    ] {
+        redis.set_str(name, Value::Number(level as f64));
+    }
+
+    interpreter.set_global("redis", Value::table(redis));
+    interpreter.set_global(
+        "cjson",
+        Value::table(lua::library(&[
+            ("decode", cjson_decode),
+            ("encode", cjson_encode),
+        ])),
+    );
+}
+
+/// Runs `redis.call` and `redis.pcall` against the data the script was started with.
+pub(crate) struct Redis<'d> {
+    data: &'d mut Data,
+    read_only: bool,
+}
+
+impl Host for Redis<'_> {
+    fn call(
+        &mut self,
+        _: &mut Interpreter<'_>,
+        name: &'static str,
+        arguments: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        match self.call_command(arguments) {
+            Ok(reply) => Ok(vec![reply]),
+            // `redis.pcall` returns errors as values, `redis.call` raises them.
+            Err(message) if name == "pcall" => Ok(vec![table_with("err", &message)]),
+            Err(message) => Err(Error::Raised(table_with("err", &message))),
+        }
+    }
+
+    fn interrupted(&mut self) -> bool {
+        KILL_REQUESTED.load(Ordering::Relaxed)
+    }
+}
+
+impl Redis<'_> {
+    pub(crate) fn new(data: &mut Data, read_only: bool) -> Redis<'_> {
+        Redis { data, read_only }
+    }
+
+    fn call_command(&mut self, arguments: Vec<Value>) -> Result<Value, String> {
+        if arguments.is_empty() {
+            return Err("Please specify at least one argument for this redis lib call".to_string());
+        }
+
+        let mut command = Vec::with_capacity(arguments.len());
+
+        for argument in &arguments {
+            command.push(match argument {
+                Value::String(s) => s.to_vec(),
+                Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e18 => {
+                    (*n as i64).to_string().into_bytes()
+                }
+                Value::Number(n) => lua::format_g(*n, 17).into_bytes(),
+                _ => {
+                    return Err(
+                        "Lua redis lib command arguments must be strings or integers".to_string(),
+                    )
+                }
+            });
+        }
+
+        let name = String::from_utf8_lossy(&command[0]).to_uppercase();
+        let spec = get_command(&name).ok_or("Unknown Redis command called from script")?;
+
+        if spec.flags & NO_SCRIPT != 0 {
+            return Err("This Redis command is not allowed from script".to_string());
+        }
+
+        if !spec.accepts(command.len() - 1) {
+            return Err("Wrong number of args calling Redis command from script".to_string());
+        }
+
+        if spec.is_write() {
+            if self.read_only {
+                return Err("Write commands are not allowed from read-only scripts.".to_string());
+            }
+
+            if self.data.replication().refuses_writes() {
+                return Err("READONLY You can't write against a read only replica.".to_string());
+            }
+
+            RUNNING_WROTE.store(true, Ordering::Relaxed);
+        }
+
+        let command_arguments: Vec<array::Value> = command[1..]
+            .iter()
+            .map(|argument| array::Value::BulkString(to_bulk_string(argument)))
+            .collect();
+
+        // Scripts can only use the keys of this node, since they run on it alone.
+        if self.data.cluster().is_enabled() {
+            let cluster = self.data.cluster();
+
+            if !spec
+                .keys(&command_arguments)
+                .iter()
+                .all(|key| cluster.serves(cluster::slot(key)))
+            {
+                return Err(
+                    "Script attempted to access a non local key in a cluster node script"
+                        .to_string(),
+                );
+            }
+        }
+
+        let reply = spec.command.execute(self.data, &command_arguments);
+
+        // The script itself isn't logged to the append-only file, the commands it ran are.
+        self.data.propagate(spec, &command_arguments, &reply);
+
+        match reply {
+            Response::Error(message) => Err(message.to_string()),
+            Response::OwnedError(message) => Err(message),
+            reply => Ok(to_lua(reply)),
+        }
+    }
+}
+
+/// Converts bytes to a bulk string the way the protocol parser does, with no bytes as `Empty`, so that keys a script
+/// names are the same as keys a client names.
+fn to_bulk_string(bytes: &[u8]) -> BulkString {
+    match bytes {
+        [] => BulkString::Empty,
+        bytes => BulkString::Filled(bytes.to_vec()),
+    }
+}
+
+/// A table like `{err = "..."}` or `{ok = "..."}`, which is how scripts represent error and status replies.
+fn table_with(field: &str, message: &str) -> Value {
+    let mut table = Table::new();
+
+    table.set_str(field, Value::string(message));
+
+    Value::table(table)
+}
+
+/// Converts a reply to a Lua value. Nil replies become `false`, since `nil` can't be stored in tables.
+fn to_lua(reply: Response) -> Value {
+    match reply {
+        Response::SimpleString(s) => table_with("ok", s),
+        Response::OwnedSimpleString(s) => table_with("ok", &s),
+        Response::Error(e) => table_with("err", e),
+        Response::OwnedError(e) => table_with("err", &e),
+        Response::BulkString(BulkString::Null) | Response::NullArray => Value::Boolean(false),
+        Response::BulkString(BulkString::Empty) => Value::string(""),
+        Response::BulkString(BulkString::Filled(bytes)) => Value::string(bytes),
+        Response::Integer(i) => Value::Number(i as f64),
+        Response::Array(replies) | Response::Sequence(replies) => Value::table(Table::from_values(
+            replies.into_iter().map(to_lua).collect(),
+        )),
+    }
+}
+
+/// Converts the value a script returns to a reply. Numbers are truncated to integers, and tables become arrays up to
+/// their first `nil`, unless they're error or status replies.
+pub(crate) fn to_reply(value: &Value, depth: usize) -> Response {
+    match value {
+        Value::Boolean(true) => Response::Integer(1),
+        Value::Number(n) => Response::Integer(*n as i64),
+        Value::String(s) => Response::BulkString(to_bulk_string(s)),
+        Value::Table(table) => {
+            let table = table.borrow();
+
+            if let Value::String(message) = table.get_str("err") {
+                return Response::OwnedError(String::from_utf8_lossy(&message).into_owned());
+            }
+
+            if let Value::String(status) = table.get_str("ok") {
+                return Response::OwnedSimpleString(String::from_utf8_lossy(&status).into_owned());
+            }
+
+            if depth >= MAX_REPLY_DEPTH {
+                return Response::Error("reached lua stack limit");
+            }
+
+            let mut replies = Vec::new();
+
+            for i in 1.. {
+                let item = table.get(&Value::Number(i as f64));
+
+                if item.is_nil() {
+                    break;
+                }
+
+                replies.push(to_reply(&item, depth + 1));
+            }
+
+            Response::Array(replies)
+        }
+        _ => Response::BulkString(BulkString::Null),
+    }
+}
+
+/// Converts an error to a reply. `origin` says which script or function raised it.
+pub(crate) fn error_reply(error: Error, origin: &str) -> Response {
+    match error {
+        Error::Interrupted => Response::Error("Script killed by user with SCRIPT KILL..."),
+        Error::Raised(value) => match to_reply(&value, MAX_REPLY_DEPTH) {
+            // Errors from `redis.call`, and `error(redis.error_reply(...))`, are replied as they are.
+            reply @ Response::OwnedError(_) => reply,
+            _ => Response::OwnedError(format!(
+                "{} {origin}",
+                String::from_utf8_lossy(&value.to_display())
+            )),
+        },
+    }
+}
+
+fn redis_error_reply(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let message = lua::check_string(interpreter, &arguments, 0, "error_reply")?;
+
+    Ok(vec![table_with("err", &String::from_utf8_lossy(&message))])
+}
+
+fn redis_status_reply(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let status = lua::check_string(interpreter, &arguments, 0, "status_reply")?;
+
+    Ok(vec![table_with("ok", &String::from_utf8_lossy(&status))])
+}
+
+fn redis_sha1hex(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let s = lua::check_string(interpreter, &arguments, 0, "sha1hex")?;
+
+    Ok(vec![Value::string(sha1_hex(&s))])
+}
+
+fn redis_log(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let level = lua::check_number(interpreter, &arguments, 0, "log")?;
+
+    if arguments.len() < 2 {
+        return Err(interpreter.error("redis.log() requires two arguments or more."));
+    }
+
+    let message: Vec<String> = arguments[1..]
+        .iter()
+        .filter_map(Value::to_bytes)
+        .map(|s| String::from_utf8_lossy(&s).into_owned())
+        .collect();
+
+    // Debug and verbose messages are dropped, like at Redis's default log level.
+    if level >= 2.0 {
+        eprintln!("{}", message.join(" "));
+    }
+
+    Ok(Vec::new())
+}
+
+fn to_json(interpreter: &Interpreter<'_>, value: &Value, depth: usize) -> Result<Json, Error> {
+    Ok(match value {
+        Value::Nil => Json::Null,
+        Value::Boolean(b) => Json::Bool(*b),
+        Value::Number(n) if !n.is_finite() => {
+            return Err(interpreter.error("Cannot serialise number: must not be NaN or Inf"))
+        }
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 9007199254740992.0 => {
+            Json::Integer(*n as i64)
+        }
+        Value::Number(n) => Json::Float(*n),
+        Value::String(s) => Json::String(String::from_utf8_lossy(s).into_owned()),
+        Value::Table(table) => {
+            if depth > 1000 {
+                return Err(interpreter.error("Cannot serialise, excessive nesting (1001)"));
+            }
+
+            let table = table.borrow();
+            let mut entries = Vec::new();
+            let mut key = Value::Nil;
+
+            while let Ok(Some((k, v))) = table.next(&key) {
+                entries.push((k.clone(), v));
+                key = k;
+            }
+
+            // Tables whose keys are exactly 1..n are arrays. Empty tables are objects.
+            if !entries.is_empty() && entries.len() == table.length() {
+                let items = entries
+                    .iter()
+                    .map(|(_, v)| to_json(interpreter, v, depth + 1))
+                    .collect::<Result<_, _>>()?;
+
+                Json::Array(items)
+            } else {
+                let mut members = Vec::with_capacity(entries.len());
+
+                for (k, v) in &entries {
+                    let name = match k {
+                        Value::String(_) | Value::Number(_) => {
+                            String::from_utf8_lossy(&k.to_bytes().expect("strings and numbers"))
+                                .into_owned()
+                        }
+                        _ => {
+                            return Err(interpreter.error(
+                                "Cannot serialise table: table key must be a number or string",
+                            ))
+                        }
+                    };
+
+                    members.push((name, to_json(interpreter, v, depth + 1)?));
+                }
+
+                Json::Object(members)
+            }
+        }
+        other => {
+            return Err(interpreter.error(format!(
+                "Cannot serialise {}: type not supported",
+                other.type_name()
+            )))
+        }
+    })
+}
+
+fn from_json(json: Json) -> Value {
+    match json {
+        Json::Null => Value::Nil,
+        Json::Bool(b) => Value::Boolean(b),
+        Json::Integer(i) => Value::Number(i as f64),
+        Json::Float(f) => Value::Number(f),
+        Json::String(s) => Value::string(s),
+        Json::Array(items) => {
+            let mut table = Table::new();
+
+            for (i, item) in items.into_iter().enumerate() {
+                table
+                    .set(Value::Number((i + 1) as f64), from_json(item))
+                    .expect("valid index");
+            }
+
+            Value::table(table)
+        }
+        Json::Object(members) => {
+            let mut table = Table::new();
+
+            for (name, value) in members {
+                table.set_str(&name, from_json(value));
+            }
+
+            Value::table(table)
+        }
+    }
+}
+
+fn cjson_encode(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let json = to_json(interpreter, arguments.first().unwrap_or(&Value::Nil), 0)?;
+
+    Ok(vec![Value::string(json.serialize())])
+}
+
+fn cjson_decode(
+    interpreter: &mut Interpreter<'_>,
+    arguments: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let text = lua::check_string(interpreter, &arguments, 0, "decode")?;
+
+    match json::parse(&text) {
+        Ok(json) => Ok(vec![from_json(json)]),
+        Err(_) => Err(interpreter.error("Expected value but found invalid token")),
+    }
+}
+
+/// Serializes tests that run scripts, since the state of the running script is global.
+#[cfg(test)]
+pub(crate) static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    macro_rules! bulk_string {
+        ($value:expr) => {
+            BulkString::Filled($value.as_bytes().to_vec())
+        };
+    }
+
+    macro_rules! arguments {
+        ($($value:expr),*) => {
+            &[$(array::Value::BulkString(bulk_string!($value))),*]
+        };
+    }
+
+    fn eval(
+        data: &mut Data,
+        source: &str,
+        keys: &[array::Value],
+        arguments: &[array::Value],
+    ) -> Response {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+
+        match load(data, source.as_bytes()) {
+            Ok((sha, body)) => run(data, &sha, body, keys, arguments, false),
+            Err(reply) => reply,
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Array(vec![
+                Response::Integer(1),
+                Response::Integer(2),
+                Response::Integer(3),
+                Response::BulkString(bulk_string!("a")),
+                Response::Integer(1),
+                Response::BulkString(BulkString::Null),
+                Response::BulkString(bulk_string!("b")),
+            ]),
+            eval(
+                &mut data,
+                "return {1, 2.9, 3, 'a', true, false, 'b'}",
+                &[],
+                &[]
+            )
+        );
+        assert_eq!(
+            Response::OwnedSimpleString("fine".to_string()),
+            eval(&mut data, "return redis.status_reply('fine')", &[], &[])
+        );
+        assert_eq!(
+            Response::OwnedError("my error".to_string()),
+            eval(&mut data, "return {err = 'my error'}", &[], &[])
+        );
+        assert_eq!(
+            Response::BulkString(BulkString::Null),
+            eval(&mut data, "return nil", &[], &[])
+        );
+        assert_eq!(
+            Response::BulkString(bulk_string!("{\"a\":[1,2]}")),
+            eval(
+                &mut data,
+                "return cjson.encode(cjson.decode('{\"a\":[1,2]}'))",
+                &[],
+                &[]
+            )
+        );
+    }
+
+    #[test]
+    fn redis_call() {
+        let mut data = Data::new();
+
+        assert_eq!(
+            Response::Array(vec![
+                Response::OwnedSimpleString("OK".to_string()),
+                Response::BulkString(bulk_string!("v")),
+                Response::BulkString(BulkString::Null),
+            ]),
+            eval(
+                &mut data,
+                "local ok = redis.call('SET', KEYS[1], ARGV[1]) \
+                 return {ok, redis.call('get', KEYS[1]), redis.call('GET', 'missing')}",
+                arguments!["k"],
+                arguments!["v"]
+            )
+        );
+        assert_eq!(
+            Response::OwnedError("Unknown Redis command called from script".to_string()),
+            eval(&mut data, "return redis.call('NOPE')", &[], &[])
+        );
+        assert_eq!(
+            Response::BulkString(bulk_string!(
+                "This Redis command is not allowed from script"
+            )),
+            eval(
+                &mut data,
+                "return redis.pcall('CONFIG', 'GET', '*').err",
+                &[],
+                &[]
+            )
+        );
+        assert_eq!(
+            Response::OwnedError(format!(
+                "user_script:1: Script attempted to access nonexistent global variable 'x' script: {}",
+                sha1_hex(b"return x")
+            )),
+            eval(&mut data, "return x", &[], &[])
+        );
+
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let (sha, body) = load(&mut data, b"return redis.call('SET', 'k', 'w')").unwrap();
+
+        assert_eq!(
+            Response::OwnedError(
+                "Write commands are not allowed from read-only scripts.".to_string()
+            ),
+            run(&mut data, &sha, body, &[], &[], true)
+        );
+    }
+
+    #[test]
+    fn kill() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let mut data = Data::new();
+        let (sha, body) = load(&mut data, b"while true do end").unwrap();
+
+        assert_eq!(
+            Response::Error("NOTBUSY No scripts in execution right now."),
+            super::kill()
+        );
+
+        let killer = thread::spawn(|| {
+            while RUNNING_SINCE.load(Ordering::Relaxed) == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            super::kill()
+        });
+
+        assert_eq!(
+            Response::Error("Script killed by user with SCRIPT KILL..."),
+            run(&mut data, &sha, body, &[], &[], false)
+        );
+        assert_eq!(Response::SimpleString("OK"), killer.join().unwrap());
+    }
+}
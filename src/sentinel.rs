@@ -0,0 +1,1971 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::aof;
+use crate::cluster::{connect, random};
+use crate::replication::new_id;
+use crate::Data;
+
+/// The port a sentinel listens on unless told otherwise, like Redis Sentinel's.
+pub(crate) const DEFAULT_SENTINEL_PORT: u16 = 26379;
+/// How long an instance may not answer pings before it's considered down, in milliseconds, like Redis's
+/// `down-after-milliseconds`.
+pub(crate) const DEFAULT_DOWN_AFTER: u64 = 30_000;
+/// How long a failover may take before it's aborted, in milliseconds, like Redis's `failover-timeout`.
+pub(crate) const DEFAULT_FAILOVER_TIMEOUT: u64 = 180_000;
+/// The channel sentinels announce themselves and their view of a primary on, on every instance they monitor.
+pub(crate) const HELLO_CHANNEL: &str = "__sentinel__:hello";
+/// How often instances are pinged at most, in milliseconds. They're pinged more often if the down after period is
+/// shorter.
+const PING_PERIOD: u64 = 1000;
+/// How often instances are asked for `INFO`, in milliseconds, while their primary is up and isn't failed over.
+const INFO_PERIOD: u64 = 10_000;
+/// How often instances are asked for `INFO` while their primary is down or failed over, in milliseconds.
+const FAST_INFO_PERIOD: u64 = 1000;
+const HELLO_PERIOD: u64 = 2000;
+/// How often the other sentinels are asked whether a primary that's down for this one is down for them too, in
+/// milliseconds.
+const ASK_PERIOD: u64 = 1000;
+/// How long another sentinel's answer that a primary is down counts, in milliseconds.
+const ASK_VALIDITY: u64 = 5 * ASK_PERIOD;
+/// How long the election of the sentinel that fails a primary over may take at most, in milliseconds.
+const ELECTION_TIMEOUT: u64 = 10_000;
+/// The longest random delay added before the next failover, in milliseconds, so that sentinels don't all try at the
+/// same time.
+const MAX_DESYNC: u64 = 1000;
+/// How long an instance must report the wrong role or primary before it's reconfigured, in milliseconds, which leaves
+/// time for a failover another sentinel runs to be heard of.
+const ROLE_GRACE_PERIOD: u64 = 4 * HELLO_PERIOD;
+/// How long a request may wait for its reply, in milliseconds.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a hello subscription waits before connecting again after it failed.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// The role an instance reported in its latest `INFO`.
+#[derive(Clone, Debug, Default, PartialEq)]
+enum Role {
+    #[default]
+    Unknown,
+    Primary,
+    /// A replica of the primary at the given host and port.
+    Replica(String, u16),
+}
+
+/// A primary or replica that a sentinel monitors.
+#[derive(Clone, Debug)]
+struct Instance {
+    host: String,
+    port: u16,
+    /// Whether a ping was sent and its reply hasn't come back yet.
+    ping_pending: bool,
+    /// When the oldest ping the instance didn't answer was sent, which tells whether it's down.
+    ping_sent: Option<u64>,
+    last_ping: u64,
+    /// When the instance last answered a ping, or when it started being monitored.
+    last_pong: u64,
+    info_pending: bool,
+    /// When the instance was last asked for `INFO`.
+    last_info: u64,
+    /// When the instance last answered `INFO`, or 0 if it never did.
+    info_refresh: u64,
+    /// Since when the instance is subjectively down: it hasn't answered pings for the down after period.
+    down_since: Option<u64>,
+    role: Role,
+    /// Since when the instance reports its role.
+    role_since: u64,
+    /// Whether a replica reports that its link to its primary is up.
+    link_up: bool,
+    /// The replication offset the instance reported.
+    offset: u64,
+}
+
+impl Instance {
+    fn new(host: String, port: u16, now: u64) -> Instance {
+        Instance {
+            host,
+            port,
+            ping_pending: false,
+            ping_sent: None,
+            last_ping: 0,
+            last_pong: now,
+            info_pending: false,
+            last_info: 0,
+            info_refresh: 0,
+            down_since: None,
+            role: Role::Unknown,
+            role_since: now,
+            link_up: false,
+            offset: 0,
+        }
+    }
+
+    fn is_at(&self, host: &str, port: u16) -> bool {
+        self.host == host && self.port == port
+    }
+
+    /// The fields `SENTINEL MASTER` and `SENTINEL REPLICAS` report for both primaries and replicas.
+    fn fields(&self, name: String, flags: String, down_after: u64, now: u64) -> Fields {
+        let mut fields = vec![
+            ("name", name),
+            ("ip", self.host.clone()),
+            ("port", self.port.to_string()),
+            ("runid", String::new()),
+            ("flags", flags),
+            (
+                "last-ping-sent",
+                self.ping_sent
+                    .map_or(0, |sent| now.saturating_sub(sent))
+                    .to_string(),
+            ),
+            (
+                "last-ok-ping-reply",
+                now.saturating_sub(self.last_pong).to_string(),
+            ),
+            (
+                "last-ping-reply",
+                now.saturating_sub(self.last_pong).to_string(),
+            ),
+        ];
+
+        if let Some(since) = self.down_since {
+            fields.push(("s-down-time", now.saturating_sub(since).to_string()));
+        }
+
+        fields.extend([
+            ("down-after-milliseconds", down_after.to_string()),
+            (
+                "info-refresh",
+                now.saturating_sub(self.info_refresh).to_string(),
+            ),
+            (
+                "role-reported",
+                match self.role {
+                    Role::Replica(..) => "slave",
+                    _ => "master",
+                }
+                .to_string(),
+            ),
+            (
+                "role-reported-time",
+                now.saturating_sub(self.role_since).to_string(),
+            ),
+        ]);
+        fields
+    }
+}
+
+/// Another sentinel that monitors the same primary, discovered through its hellos.
+#[derive(Clone, Debug)]
+struct Peer {
+    host: String,
+    port: u16,
+    last_hello: u64,
+    /// Whether the sentinel last answered that the primary is down, and when.
+    master_down: bool,
+    last_reply: u64,
+    ask_pending: bool,
+    last_ask: u64,
+    /// The sentinel it voted for to fail the primary over, and in which epoch.
+    leader: Option<String>,
+    leader_epoch: u64,
+}
+
+/// The steps of a failover.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FailoverState {
+    /// Waiting for the other sentinels to elect this one to run the failover.
+    WaitStart,
+    SelectReplica,
+    /// Waiting for the replica sent `REPLICAOF NO ONE` to report that it's a primary.
+    WaitPromotion,
+}
+
+impl FailoverState {
+    fn name(self) -> &'static str {
+        match self {
+            FailoverState::WaitStart => "wait_start",
+            FailoverState::SelectReplica => "select_slave",
+            FailoverState::WaitPromotion => "wait_promotion",
+        }
+    }
+}
+
+/// A failover this sentinel runs.
+#[derive(Clone, Debug)]
+struct Failover {
+    epoch: u64,
+    state: FailoverState,
+    /// When the failover started, and when it reached its current state.
+    started: u64,
+    state_changed: u64,
+    /// The address of the replica being promoted.
+    promoted: Option<(String, u16)>,
+}
+
+/// A primary that a sentinel monitors, with its replicas and the other sentinels that monitor it.
+#[derive(Clone, Debug)]
+struct Master {
+    name: String,
+    /// How many sentinels must agree that the primary is down to fail it over.
+    quorum: usize,
+    instance: Instance,
+    replicas: BTreeMap<(String, u16), Instance>,
+    /// The other sentinels, by ID.
+    sentinels: BTreeMap<String, Peer>,
+    /// The epoch of the failover that made the primary one, which settles which address is up to date.
+    config_epoch: u64,
+    /// Since when the primary is objectively down: enough sentinels agree that it's down.
+    odown_since: Option<u64>,
+    last_hello: u64,
+    /// The sentinel this one voted for to fail the primary over, and in which epoch.
+    leader: Option<String>,
+    leader_epoch: u64,
+    /// When the latest failover started, or was postponed to. Another one isn't started for twice the failover
+    /// timeout.
+    failover_start: u64,
+    failover: Option<Failover>,
+}
+
+impl Master {
+    fn new(name: String, host: String, port: u16, quorum: usize, now: u64) -> Master {
+        Master {
+            name,
+            quorum,
+            instance: Instance::new(host, port, now),
+            replicas: BTreeMap::new(),
+            sentinels: BTreeMap::new(),
+            config_epoch: 0,
+            odown_since: None,
+            last_hello: 0,
+            leader: None,
+            leader_epoch: 0,
+            failover_start: 0,
+            failover: None,
+        }
+    }
+
+    /// The primary or one of its replicas, by address.
+    fn instance_mut(&mut self, host: &str, port: u16) -> Option<&mut Instance> {
+        if self.instance.is_at(host, port) {
+            return Some(&mut self.instance);
+        }
+
+        self.replicas.get_mut(&(host.to_string(), port))
+    }
+
+    fn instances_mut(&mut self) -> impl Iterator<Item = &mut Instance> {
+        std::iter::once(&mut self.instance).chain(self.replicas.values_mut())
+    }
+
+    /// Describes the primary in events, as `master <name> <ip> <port>`.
+    fn describe(&self) -> String {
+        format!(
+            "master {} {} {}",
+            self.name, self.instance.host, self.instance.port
+        )
+    }
+
+    /// Describes a replica in events, followed by the primary's name and address.
+    fn describe_replica(&self, host: &str, port: u16) -> String {
+        format!(
+            "slave {host}:{port} {host} {port} @ {} {} {}",
+            self.name, self.instance.host, self.instance.port
+        )
+    }
+
+    /// Describes an instance in events, whether it's the primary or a replica.
+    fn describe_instance(&self, host: &str, port: u16) -> String {
+        if self.instance.is_at(host, port) {
+            self.describe()
+        } else {
+            self.describe_replica(host, port)
+        }
+    }
+
+    fn describe_sentinel(&self, id: &str, host: &str, port: u16) -> String {
+        format!(
+            "sentinel {id} {host} {port} @ {} {} {}",
+            self.name, self.instance.host, self.instance.port
+        )
+    }
+
+    /// The replica to promote: one that's up, answered pings recently and reports that it's a replica, with the
+    /// greatest replication offset, then the lowest address.
+    fn select_replica(&self, now: u64) -> Option<(String, u16)> {
+        self.replicas
+            .iter()
+            .filter(|(_, replica)| {
+                replica.down_since.is_none()
+                    && now.saturating_sub(replica.last_pong) <= 5 * PING_PERIOD
+                    && matches!(replica.role, Role::Replica(..))
+            })
+            .min_by(|(a, x), (b, y)| y.offset.cmp(&x.offset).then(a.cmp(b)))
+            .map(|(address, _)| address.clone())
+    }
+}
+
+/// What an instance reported in `INFO replication`.
+#[derive(Debug, Default, PartialEq)]
+struct Report {
+    role: Role,
+    offset: u64,
+    link_up: bool,
+    /// The addresses of a primary's replicas.
+    replicas: Vec<(String, u16)>,
+}
+
+impl Report {
+    fn parse(text: &str) -> Report {
+        let fields: HashMap<&str, &str> = text
+            .lines()
+            .filter_map(|line| line.trim_end().split_once(':'))
+            .collect();
+        let role = match fields.get("role") {
+            Some(&"master") => Role::Primary,
+            Some(&"slave") => match (
+                fields.get("master_host"),
+                fields.get("master_port").and_then(|port| port.parse().ok()),
+            ) {
+                (Some(host), Some(port)) => Role::Replica(host.to_string(), port),
+                _ => Role::Unknown,
+            },
+            _ => Role::Unknown,
+        };
+        let offset = fields
+            .get("slave_repl_offset")
+            .or_else(|| fields.get("master_repl_offset"))
+            .and_then(|offset| offset.parse().ok())
+            .unwrap_or(0);
+        let mut replicas = Vec::new();
+
+        for index in 0.. {
+            let Some(replica) = fields.get(format!("slave{index}").as_str()) else {
+                break;
+            };
+            let replica: HashMap<&str, &str> = replica
+                .split(',')
+                .filter_map(|field| field.split_once('='))
+                .collect();
+
+            if let (Some(ip), Some(Ok(port))) = (
+                replica.get("ip"),
+                replica.get("port").map(|port| port.parse()),
+            ) {
+                replicas.push((ip.to_string(), port));
+            }
+        }
+
+        Report {
+            role,
+            offset,
+            link_up: fields.get("master_link_status") == Some(&"up"),
+            replicas,
+        }
+    }
+}
+
+/// Why a sentinel sent a request, which says what to do with the reply.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Purpose {
+    /// A `PING` to an instance of the named primary.
+    Ping(String),
+    /// An `INFO replication` to an instance of the named primary.
+    Info(String),
+    /// Asking another sentinel, by ID, whether the named primary is down, and for its vote.
+    IsMasterDown { master: String, sentinel: String },
+    /// A hello, or a `REPLICAOF`, whose reply doesn't matter.
+    Command,
+}
+
+/// A command a sentinel sends to an instance it monitors or to another sentinel.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Request {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) command: Vec<Vec<u8>>,
+    pub(crate) purpose: Purpose,
+}
+
+impl Request {
+    fn new(host: &str, port: u16, command: &[&str], purpose: Purpose) -> Request {
+        Request {
+            host: host.to_string(),
+            port,
+            command: command
+                .iter()
+                .map(|argument| argument.as_bytes().to_vec())
+                .collect(),
+            purpose,
+        }
+    }
+}
+
+/// A reply to a request, as read from the connection.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+/// The fields `SENTINEL MASTER`, `SENTINEL REPLICAS` and `SENTINEL SENTINELS` report for an instance, in order.
+pub(crate) type Fields = Vec<(&'static str, String)>;
+
+/// Sentinel mode: monitoring primaries and their replicas, and failing a primary over to one of its replicas when
+/// enough sentinels agree that it's down.
+///
+/// Like Redis Sentinel, each sentinel pings the instances it monitors, asks them for `INFO` to discover replicas, and
+/// announces itself on their [`HELLO_CHANNEL`], where the sentinels monitoring the same primary discover each other.
+/// A primary that doesn't answer for the down after period is subjectively down; once a quorum of sentinels agree, it's
+/// objectively down. A sentinel then starts a failover in a new epoch and asks the others for their votes: each votes
+/// once per epoch, and the one that gets a majority promotes a replica with `REPLICAOF NO ONE`, points the other
+/// replicas at it, and announces the new address in its hellos, tagged with the failover's epoch so that the others
+/// switch to it.
+#[derive(Debug)]
+pub(crate) struct Sentinel {
+    enabled: bool,
+    /// The ID of this sentinel.
+    id: String,
+    /// The port this sentinel listens on, which it announces to the others.
+    port: u16,
+    current_epoch: u64,
+    /// How long an instance may not answer pings before it's considered down, in milliseconds.
+    down_after: u64,
+    failover_timeout: u64,
+    masters: BTreeMap<String, Master>,
+    outbox: Vec<Request>,
+    /// The events to publish, with their channel.
+    events: Vec<(&'static str, String)>,
+}
+
+impl Sentinel {
+    pub(crate) fn new() -> Sentinel {
+        Sentinel {
+            enabled: false,
+            id: String::new(),
+            port: DEFAULT_SENTINEL_PORT,
+            current_epoch: 0,
+            down_after: DEFAULT_DOWN_AFTER,
+            failover_timeout: DEFAULT_FAILOVER_TIMEOUT,
+            masters: BTreeMap::new(),
+            outbox: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enables sentinel mode for a sentinel that listens on `port`.
+    pub(crate) fn enable(&mut self, port: u16, down_after: u64, failover_timeout: u64) {
+        self.enabled = true;
+        self.id = new_id();
+        self.port = port;
+        self.down_after = down_after;
+        self.failover_timeout = failover_timeout;
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn master_names(&self) -> impl Iterator<Item = &str> {
+        self.masters.keys().map(String::as_str)
+    }
+
+    /// The address of the named primary, as far as this sentinel knows.
+    pub(crate) fn master_address(&self, name: &str) -> Option<(&str, u16)> {
+        self.masters
+            .get(name)
+            .map(|master| (master.instance.host.as_str(), master.instance.port))
+    }
+
+    /// Whether an instance at this address is a primary or replica this sentinel monitors.
+    pub(crate) fn watches(&self, host: &str, port: u16) -> bool {
+        self.masters.values().any(|master| {
+            master.instance.is_at(host, port)
+                || master.replicas.contains_key(&(host.to_string(), port))
+        })
+    }
+
+    fn event(&mut self, channel: &'static str, message: String) {
+        self.events.push((channel, message));
+    }
+
+    /// Starts monitoring a primary, under a name that clients look it up by.
+    pub(crate) fn monitor(
+        &mut self,
+        name: &str,
+        host: &str,
+        port: u16,
+        quorum: usize,
+        now: u64,
+    ) -> Result<(), &'static str> {
+        if self.masters.contains_key(name) {
+            return Err("Duplicated master name");
+        }
+
+        if quorum == 0 {
+            return Err("Quorum must be 1 or greater.");
+        }
+
+        let master = Master::new(name.to_string(), host.to_string(), port, quorum, now);
+
+        self.event("+monitor", format!("{} quorum {quorum}", master.describe()));
+        self.masters.insert(name.to_string(), master);
+
+        Ok(())
+    }
+
+    /// Stops monitoring a primary. Returns whether it was monitored.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        let Some(master) = self.masters.remove(name) else {
+            return false;
+        };
+
+        self.event("-monitor", master.describe());
+
+        true
+    }
+
+    /// Runs the periodic tasks: pinging instances and asking them for `INFO`, sending hellos, finding out which
+    /// instances are down, asking the other sentinels about primaries that are, and running failovers. Called every
+    /// 100 milliseconds.
+    pub(crate) fn cron(&mut self, now: u64) {
+        let names: Vec<String> = self.masters.keys().cloned().collect();
+
+        for name in names {
+            // Taken out of the map while it's handled, so that it can be changed along with the rest of the sentinel.
+            let Some(mut master) = self.masters.remove(&name) else {
+                continue;
+            };
+
+            self.check_instances(&mut master, now);
+            self.send_hellos(&mut master, now);
+            self.check_objectively_down(&mut master, now);
+            self.ask_sentinels(&mut master, false, now);
+            self.start_failover_if_needed(&mut master, now);
+            self.failover_cron(&mut master, now);
+            self.masters.insert(name, master);
+        }
+    }
+
+    /// Pings the instances and asks them for `INFO` when it's time, and marks those that haven't answered for the down
+    /// after period as subjectively down.
+    fn check_instances(&mut self, master: &mut Master, now: u64) {
+        let ping_period = PING_PERIOD.min(self.down_after);
+        let info_period = if master.odown_since.is_some() || master.failover.is_some() {
+            FAST_INFO_PERIOD
+        } else {
+            INFO_PERIOD
+        };
+        let name = master.name.clone();
+        let mut changes = Vec::new();
+
+        for instance in master.instances_mut() {
+            if !instance.ping_pending && now >= instance.last_ping + ping_period {
+                instance.ping_pending = true;
+                instance.ping_sent.get_or_insert(now);
+                instance.last_ping = now;
+                self.outbox.push(Request::new(
+                    &instance.host,
+                    instance.port,
+                    &["PING"],
+                    Purpose::Ping(name.clone()),
+                ));
+            }
+
+            if !instance.info_pending && now >= instance.last_info + info_period {
+                instance.info_pending = true;
+                instance.last_info = now;
+                self.outbox.push(Request::new(
+                    &instance.host,
+                    instance.port,
+                    &["INFO", "replication"],
+                    Purpose::Info(name.clone()),
+                ));
+            }
+
+            let down = instance
+                .ping_sent
+                .is_some_and(|sent| now.saturating_sub(sent) > self.down_after);
+
+            match (down, instance.down_since) {
+                (true, None) => {
+                    instance.down_since = Some(now);
+                    changes.push(("+sdown", instance.host.clone(), instance.port));
+                }
+                (false, Some(_)) => {
+                    instance.down_since = None;
+                    changes.push(("-sdown", instance.host.clone(), instance.port));
+                }
+                _ => {}
+            }
+        }
+
+        for (channel, host, port) in changes {
+            self.event(channel, master.describe_instance(&host, port));
+        }
+    }
+
+    fn send_hellos(&mut self, master: &mut Master, now: u64) {
+        if now < master.last_hello + HELLO_PERIOD {
+            return;
+        }
+
+        master.last_hello = now;
+
+        let hello = format!(
+            "127.0.0.1,{},{},{},{},{},{},{}",
+            self.port,
+            self.id,
+            self.current_epoch,
+            master.name,
+            master.instance.host,
+            master.instance.port,
+            master.config_epoch
+        );
+
+        for instance in master.instances_mut() {
+            self.outbox.push(Request::new(
+                &instance.host,
+                instance.port,
+                &["PUBLISH", HELLO_CHANNEL, &hello],
+                Purpose::Command,
+            ));
+        }
+    }
+
+    /// Marks the primary as objectively down while it's subjectively down for this sentinel and enough others said it
+    /// is for them too.
+    fn check_objectively_down(&mut self, master: &mut Master, now: u64) {
+        let agreeing = 1 + master
+            .sentinels
+            .values()
+            .filter(|peer| peer.master_down && now.saturating_sub(peer.last_reply) <= ASK_VALIDITY)
+            .count();
+        let odown = master.instance.down_since.is_some() && agreeing >= master.quorum;
+
+        match (odown, master.odown_since) {
+            (true, None) => {
+                master.odown_since = Some(now);
+                self.event(
+                    "+odown",
+                    format!("{} #quorum {agreeing}/{}", master.describe(), master.quorum),
+                );
+            }
+            (false, Some(_)) => {
+                master.odown_since = None;
+                self.event("-odown", master.describe());
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks the other sentinels whether the primary is down for them, while it's down for this one, and for their vote
+    /// while this one runs a failover. They're asked every [`ASK_PERIOD`], or right away if `forced`.
+    fn ask_sentinels(&mut self, master: &mut Master, forced: bool, now: u64) {
+        if master.instance.down_since.is_none() {
+            return;
+        }
+
+        let (epoch, candidate) = match &master.failover {
+            Some(failover) => (failover.epoch, self.id.as_str()),
+            None => (self.current_epoch, "*"),
+        };
+
+        for (id, peer) in &mut master.sentinels {
+            if !forced && (peer.ask_pending || now < peer.last_ask + ASK_PERIOD) {
+                continue;
+            }
+
+            peer.ask_pending = true;
+            peer.last_ask = now;
+            self.outbox.push(Request::new(
+                &peer.host,
+                peer.port,
+                &[
+                    "SENTINEL",
+                    "is-master-down-by-addr",
+                    &master.instance.host,
+                    &master.instance.port.to_string(),
+                    &epoch.to_string(),
+                    candidate,
+                ],
+                Purpose::IsMasterDown {
+                    master: master.name.clone(),
+                    sentinel: id.clone(),
+                },
+            ));
+        }
+    }
+
+    /// Starts a failover in a new epoch, voting for this sentinel, when the primary is objectively down and no failover
+    /// was tried recently.
+    fn start_failover_if_needed(&mut self, master: &mut Master, now: u64) {
+        if master.odown_since.is_none()
+            || master.failover.is_some()
+            || now < master.failover_start + 2 * self.failover_timeout
+        {
+            return;
+        }
+
+        self.start_failover(master, FailoverState::WaitStart, now);
+
+        let (id, epoch) = (self.id.clone(), self.current_epoch);
+
+        master.leader = Some(id.clone());
+        master.leader_epoch = epoch;
+        self.event("+vote-for-leader", format!("{id} {epoch}"));
+        // Asking for votes right away makes it less likely that another sentinel starts a failover meanwhile.
+        self.ask_sentinels(master, true, now);
+    }
+
+    fn start_failover(&mut self, master: &mut Master, state: FailoverState, now: u64) {
+        self.current_epoch += 1;
+        master.failover = Some(Failover {
+            epoch: self.current_epoch,
+            state,
+            started: now,
+            state_changed: now,
+            promoted: None,
+        });
+        master.failover_start = now + random() % MAX_DESYNC;
+        self.event("+new-epoch", self.current_epoch.to_string());
+        self.event("+try-failover", master.describe());
+    }
+
+    /// Moves the failover along: waits to be elected, then selects a replica and promotes it. The failover completes
+    /// when the replica reports that it's a primary, see [`Sentinel::reply`].
+    fn failover_cron(&mut self, master: &mut Master, now: u64) {
+        let Some(failover) = master.failover.clone() else {
+            return;
+        };
+
+        match failover.state {
+            FailoverState::WaitStart => {
+                let mut votes: HashMap<&str, usize> = HashMap::new();
+
+                if master.leader_epoch == failover.epoch {
+                    if let Some(leader) = &master.leader {
+                        *votes.entry(leader).or_default() += 1;
+                    }
+                }
+
+                for peer in master.sentinels.values() {
+                    if let (Some(leader), true) =
+                        (&peer.leader, peer.leader_epoch == failover.epoch)
+                    {
+                        *votes.entry(leader).or_default() += 1;
+                    }
+                }
+
+                // A majority of the sentinels, counting this one, and at least the quorum.
+                let voters = master.sentinels.len() + 1;
+                let needed = master.quorum.max(voters / 2 + 1);
+
+                if votes
+                    .get(self.id.as_str())
+                    .is_some_and(|&votes| votes >= needed)
+                {
+                    set_failover_state(master, FailoverState::SelectReplica, now);
+                    self.event("+elected-leader", master.describe());
+                    self.event("+failover-state-select-slave", master.describe());
+                } else if now.saturating_sub(failover.started)
+                    > ELECTION_TIMEOUT.min(self.failover_timeout)
+                {
+                    master.failover = None;
+                    self.event("-failover-abort-not-elected", master.describe());
+                }
+            }
+            FailoverState::SelectReplica => match master.select_replica(now) {
+                Some((host, port)) => {
+                    set_failover_state(master, FailoverState::WaitPromotion, now);
+
+                    if let Some(failover) = &mut master.failover {
+                        failover.promoted = Some((host.clone(), port));
+                    }
+
+                    self.outbox.push(Request::new(
+                        &host,
+                        port,
+                        &["REPLICAOF", "NO", "ONE"],
+                        Purpose::Command,
+                    ));
+
+                    let replica = master.describe_replica(&host, port);
+
+                    self.event("+selected-slave", replica.clone());
+                    self.event("+failover-state-send-slaveof-noone", replica);
+                }
+                None => {
+                    master.failover = None;
+                    self.event("-failover-abort-no-good-slave", master.describe());
+                }
+            },
+            FailoverState::WaitPromotion => {
+                if now.saturating_sub(failover.state_changed) > self.failover_timeout {
+                    let (host, port) = failover.promoted.unwrap_or_default();
+
+                    master.failover = None;
+                    self.event(
+                        "-failover-abort-slave-timeout",
+                        master.describe_replica(&host, port),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Ends a failover once the promoted replica reports that it's a primary: points the other replicas at it, and
+    /// switches to it.
+    fn complete_failover(&mut self, master: &mut Master, now: u64) {
+        let Some(failover) = master.failover.take() else {
+            return;
+        };
+        let Some((host, port)) = failover.promoted else {
+            return;
+        };
+
+        self.event("+promoted-slave", master.describe_replica(&host, port));
+        self.event("+failover-state-reconf-slaves", master.describe());
+
+        let port_argument = port.to_string();
+
+        for replica in master.replicas.values() {
+            if replica.is_at(&host, port) {
+                continue;
+            }
+
+            self.outbox.push(Request::new(
+                &replica.host,
+                replica.port,
+                &["REPLICAOF", &host, &port_argument],
+                Purpose::Command,
+            ));
+            self.events.push((
+                "+slave-reconf-sent",
+                master.describe_replica(&replica.host, replica.port),
+            ));
+        }
+
+        self.event("+failover-end", master.describe());
+        self.switch_master(master, host, port, failover.epoch, now);
+    }
+
+    /// Makes the instance at the given address the primary, after a failover in `epoch`. The former primary is kept as
+    /// a replica, so that it's reconfigured once it's back.
+    fn switch_master(
+        &mut self,
+        master: &mut Master,
+        host: String,
+        port: u16,
+        epoch: u64,
+        now: u64,
+    ) {
+        self.event(
+            "+switch-master",
+            format!(
+                "{} {} {} {host} {port}",
+                master.name, master.instance.host, master.instance.port
+            ),
+        );
+
+        let mut addresses: Vec<(String, u16)> = master.replicas.keys().cloned().collect();
+
+        addresses.push((master.instance.host.clone(), master.instance.port));
+        master.replicas = addresses
+            .into_iter()
+            .filter(|(replica_host, replica_port)| (replica_host, *replica_port) != (&host, port))
+            .map(|(host, port)| ((host.clone(), port), Instance::new(host, port, now)))
+            .collect();
+        master.instance = Instance::new(host, port, now);
+        master.config_epoch = epoch;
+        master.odown_since = None;
+        master.failover = None;
+
+        for peer in master.sentinels.values_mut() {
+            peer.master_down = false;
+        }
+    }
+
+    /// Handles the reply to a request this sentinel sent, or `None` if the request failed.
+    pub(crate) fn reply(&mut self, request: &Request, reply: Option<Reply>, now: u64) {
+        let name = match &request.purpose {
+            Purpose::Ping(name)
+            | Purpose::Info(name)
+            | Purpose::IsMasterDown { master: name, .. } => name,
+            Purpose::Command => return,
+        };
+        let Some(mut master) = self.masters.remove(name) else {
+            return;
+        };
+
+        match &request.purpose {
+            Purpose::Ping(_) => {
+                if let Some(instance) = master.instance_mut(&request.host, request.port) {
+                    instance.ping_pending = false;
+
+                    // Like Redis, an instance that's loading its data or lost its primary is still up.
+                    let answered = match &reply {
+                        Some(Reply::Status(_)) => true,
+                        Some(Reply::Error(error)) => {
+                            error.starts_with("LOADING") || error.starts_with("MASTERDOWN")
+                        }
+                        _ => false,
+                    };
+
+                    if answered {
+                        instance.last_pong = now;
+                        instance.ping_sent = None;
+                    }
+                }
+            }
+            Purpose::Info(_) => {
+                let text = match reply {
+                    Some(Reply::Bulk(Some(text))) => {
+                        Some(String::from_utf8_lossy(&text).into_owned())
+                    }
+                    _ => None,
+                };
+
+                self.handle_info(&mut master, &request.host, request.port, text, now);
+            }
+            Purpose::IsMasterDown { sentinel, .. } => {
+                if let Some(peer) = master.sentinels.get_mut(sentinel) {
+                    peer.ask_pending = false;
+
+                    if let Some(Reply::Array(reply)) = reply {
+                        if let [Reply::Integer(down), Reply::Bulk(Some(leader)), Reply::Integer(epoch)] =
+                            &reply[..]
+                        {
+                            peer.master_down = *down == 1;
+                            peer.last_reply = now;
+
+                            if leader != b"*" {
+                                peer.leader = Some(String::from_utf8_lossy(leader).into_owned());
+                                peer.leader_epoch = (*epoch).max(0) as u64;
+                            }
+                        }
+                    }
+                }
+            }
+            Purpose::Command => {}
+        }
+
+        self.masters.insert(name.clone(), master);
+    }
+
+    /// Updates what's known about an instance from its `INFO`: the primary's replicas, the replicas' roles, and whether
+    /// the replica being promoted is a primary yet. Replicas that report the wrong primary for long enough, and former
+    /// primaries that are back, are made replicas of the primary.
+    fn handle_info(
+        &mut self,
+        master: &mut Master,
+        host: &str,
+        port: u16,
+        text: Option<String>,
+        now: u64,
+    ) {
+        let Some(instance) = master.instance_mut(host, port) else {
+            return;
+        };
+
+        instance.info_pending = false;
+
+        let Some(text) = text else {
+            return;
+        };
+        let report = Report::parse(&text);
+
+        instance.info_refresh = now;
+        instance.offset = report.offset;
+        instance.link_up = report.link_up;
+
+        if instance.role != report.role {
+            instance.role = report.role.clone();
+            instance.role_since = now;
+        }
+
+        if master.instance.is_at(host, port) {
+            if report.role == Role::Primary {
+                for (replica_host, replica_port) in report.replicas {
+                    let address = (replica_host.clone(), replica_port);
+
+                    if let Entry::Vacant(entry) = master.replicas.entry(address) {
+                        entry.insert(Instance::new(replica_host.clone(), replica_port, now));
+                        self.event(
+                            "+slave",
+                            master.describe_replica(&replica_host, replica_port),
+                        );
+                    }
+                }
+            }
+
+            return;
+        }
+
+        let promoted = master.failover.as_ref().is_some_and(|failover| {
+            failover.state == FailoverState::WaitPromotion
+                && failover.promoted.as_ref() == Some(&(host.to_string(), port))
+        });
+
+        if promoted {
+            if report.role == Role::Primary {
+                self.complete_failover(master, now);
+            }
+
+            return;
+        }
+
+        let expected = Role::Replica(master.instance.host.clone(), master.instance.port);
+
+        if report.role == expected
+            || report.role == Role::Unknown
+            || master.failover.is_some()
+            || master.instance.down_since.is_some()
+            || master.instance.role != Role::Primary
+        {
+            return;
+        }
+
+        let Some(instance) = master.instance_mut(host, port) else {
+            return;
+        };
+
+        if now.saturating_sub(instance.role_since) < ROLE_GRACE_PERIOD {
+            return;
+        }
+
+        // Waits another grace period before trying again, in case the instance doesn't comply.
+        instance.role_since = now;
+
+        let channel = if report.role == Role::Primary {
+            "+convert-to-slave"
+        } else {
+            "+fix-slave-config"
+        };
+        let primary_port = master.instance.port.to_string();
+
+        self.outbox.push(Request::new(
+            host,
+            port,
+            &["REPLICAOF", &master.instance.host, &primary_port],
+            Purpose::Command,
+        ));
+        self.event(channel, master.describe_replica(host, port));
+    }
+
+    /// Handles a hello published by a sentinel, possibly this one: adds the sentinel to those monitoring the primary
+    /// it names, and switches to the address it announces if it was failed over in a later epoch.
+    pub(crate) fn hello(&mut self, hello: &str, now: u64) {
+        let fields: Vec<&str> = hello.split(',').collect();
+        let [host, port, id, current_epoch, name, master_host, master_port, config_epoch] =
+            fields[..]
+        else {
+            return;
+        };
+        let (Ok(port), Ok(current_epoch), Ok(master_port), Ok(config_epoch)) = (
+            port.parse::<u16>(),
+            current_epoch.parse::<u64>(),
+            master_port.parse::<u16>(),
+            config_epoch.parse::<u64>(),
+        ) else {
+            return;
+        };
+
+        if id == self.id {
+            return;
+        }
+
+        let Some(mut master) = self.masters.remove(name) else {
+            return;
+        };
+
+        // A sentinel that restarted comes back with another ID at the same address.
+        let restarted: Vec<String> = master
+            .sentinels
+            .iter()
+            .filter(|(other, peer)| *other != id && peer.host == host && peer.port == port)
+            .map(|(other, _)| other.clone())
+            .collect();
+
+        for other in restarted {
+            master.sentinels.remove(&other);
+            self.event(
+                "-dup-sentinel",
+                master.describe_sentinel(&other, host, port),
+            );
+        }
+
+        if !master.sentinels.contains_key(id) {
+            self.event("+sentinel", master.describe_sentinel(id, host, port));
+        }
+
+        let peer = master
+            .sentinels
+            .entry(id.to_string())
+            .or_insert_with(|| Peer {
+                host: host.to_string(),
+                port,
+                last_hello: now,
+                master_down: false,
+                last_reply: 0,
+                ask_pending: false,
+                last_ask: 0,
+                leader: None,
+                leader_epoch: 0,
+            });
+
+        peer.host = host.to_string();
+        peer.port = port;
+        peer.last_hello = now;
+
+        if current_epoch > self.current_epoch {
+            self.current_epoch = current_epoch;
+            self.event("+new-epoch", current_epoch.to_string());
+        }
+
+        if config_epoch > master.config_epoch {
+            if master.instance.is_at(master_host, master_port) {
+                master.config_epoch = config_epoch;
+            } else {
+                self.event(
+                    "+config-update-from",
+                    master.describe_sentinel(id, host, port),
+                );
+                self.switch_master(
+                    &mut master,
+                    master_host.to_string(),
+                    master_port,
+                    config_epoch,
+                    now,
+                );
+            }
+        }
+
+        self.masters.insert(name.to_string(), master);
+    }
+
+    /// Answers another sentinel asking whether the primary at the given address is down for this one. If `candidate`
+    /// is a sentinel's ID rather than `*`, also votes for it to run the failover, unless this sentinel already voted in
+    /// `epoch`. Returns whether the primary is down, the sentinel this one voted for, or `*`, and the epoch of the vote.
+    pub(crate) fn is_master_down_by_addr(
+        &mut self,
+        host: &str,
+        port: u16,
+        epoch: u64,
+        candidate: &str,
+        now: u64,
+    ) -> (bool, String, u64) {
+        let Some(name) = self
+            .masters
+            .values()
+            .find(|master| master.instance.is_at(host, port))
+            .map(|master| master.name.clone())
+        else {
+            return (false, "*".to_string(), 0);
+        };
+        let mut master = self
+            .masters
+            .remove(&name)
+            .expect("the primary was just found");
+        let down = master.instance.down_since.is_some();
+        let vote = if candidate == "*" {
+            ("*".to_string(), 0)
+        } else {
+            self.vote(&mut master, candidate, epoch, now)
+        };
+
+        self.masters.insert(name, master);
+
+        (down, vote.0, vote.1)
+    }
+
+    /// Votes for a sentinel to fail the primary over in `epoch`, unless this sentinel already voted in that epoch.
+    /// Returns the sentinel this one voted for in the latest epoch it voted in, and that epoch.
+    fn vote(
+        &mut self,
+        master: &mut Master,
+        candidate: &str,
+        epoch: u64,
+        now: u64,
+    ) -> (String, u64) {
+        if epoch > self.current_epoch {
+            self.current_epoch = epoch;
+            self.event("+new-epoch", epoch.to_string());
+        }
+
+        if master.leader_epoch < epoch && self.current_epoch <= epoch {
+            master.leader = Some(candidate.to_string());
+            master.leader_epoch = epoch;
+            self.event("+vote-for-leader", format!("{candidate} {epoch}"));
+
+            // Leave the failover to the candidate, rather than competing with it.
+            if candidate != self.id {
+                master.failover_start = now + random() % MAX_DESYNC;
+            }
+        }
+
+        (
+            master.leader.clone().unwrap_or_else(|| "*".to_string()),
+            master.leader_epoch,
+        )
+    }
+
+    /// Starts failing the named primary over right away, without agreement from the other sentinels, like `SENTINEL
+    /// FAILOVER`.
+    pub(crate) fn failover(&mut self, name: &str, now: u64) -> Result<(), &'static str> {
+        let Some(mut master) = self.masters.remove(name) else {
+            return Err("No such master with that name");
+        };
+        let result = if master.failover.is_some() {
+            Err("INPROG Failover already in progress")
+        } else if master.select_replica(now).is_none() {
+            Err("NOGOODSLAVE No suitable replica to promote")
+        } else {
+            self.start_failover(&mut master, FailoverState::SelectReplica, now);
+
+            Ok(())
+        };
+
+        self.masters.insert(name.to_string(), master);
+
+        result
+    }
+
+    /// The fields `SENTINEL MASTER` reports for the named primary.
+    pub(crate) fn master_fields(&self, name: &str, now: u64) -> Option<Fields> {
+        let master = self.masters.get(name)?;
+        let mut flags = "master".to_string();
+
+        if master.instance.down_since.is_some() {
+            flags.push_str(",s_down");
+        }
+
+        if master.odown_since.is_some() {
+            flags.push_str(",o_down");
+        }
+
+        if master.failover.is_some() {
+            flags.push_str(",failover_in_progress");
+        }
+
+        let mut fields = master
+            .instance
+            .fields(name.to_string(), flags, self.down_after, now);
+
+        if let Some(since) = master.odown_since {
+            fields.push(("o-down-time", now.saturating_sub(since).to_string()));
+        }
+
+        fields.extend([
+            ("config-epoch", master.config_epoch.to_string()),
+            ("num-slaves", master.replicas.len().to_string()),
+            ("num-other-sentinels", master.sentinels.len().to_string()),
+            ("quorum", master.quorum.to_string()),
+            ("failover-timeout", self.failover_timeout.to_string()),
+            ("parallel-syncs", "1".to_string()),
+        ]);
+
+        if let Some(failover) = &master.failover {
+            fields.push(("failover-state", failover.state.name().to_string()));
+        }
+
+        Some(fields)
+    }
+
+    /// The fields `SENTINEL REPLICAS` reports for each replica of the named primary.
+    pub(crate) fn replica_fields(&self, name: &str, now: u64) -> Option<Vec<Fields>> {
+        let master = self.masters.get(name)?;
+        let promoted = master
+            .failover
+            .as_ref()
+            .and_then(|failover| failover.promoted.as_ref());
+
+        Some(
+            master
+                .replicas
+                .values()
+                .map(|replica| {
+                    let mut flags = "slave".to_string();
+
+                    if replica.down_since.is_some() {
+                        flags.push_str(",s_down");
+                    }
+
+                    if promoted == Some(&(replica.host.clone(), replica.port)) {
+                        flags.push_str(",promoted");
+                    }
+
+                    let (primary_host, primary_port) = match &replica.role {
+                        Role::Replica(host, port) => (host.clone(), port.to_string()),
+                        _ => ("?".to_string(), "0".to_string()),
+                    };
+                    let mut fields = replica.fields(
+                        format!("{}:{}", replica.host, replica.port),
+                        flags,
+                        self.down_after,
+                        now,
+                    );
+
+                    fields.extend([
+                        (
+                            "master-link-status",
+                            if replica.link_up { "ok" } else { "err" }.to_string(),
+                        ),
+                        ("master-host", primary_host),
+                        ("master-port", primary_port),
+                        ("slave-priority", "100".to_string()),
+                        ("slave-repl-offset", replica.offset.to_string()),
+                    ]);
+                    fields
+                })
+                .collect(),
+        )
+    }
+
+    /// The fields `SENTINEL SENTINELS` reports for each other sentinel monitoring the named primary.
+    pub(crate) fn sentinel_fields(&self, name: &str, now: u64) -> Option<Vec<Fields>> {
+        let master = self.masters.get(name)?;
+
+        Some(
+            master
+                .sentinels
+                .iter()
+                .map(|(id, peer)| {
+                    vec![
+                        ("name", id.clone()),
+                        ("ip", peer.host.clone()),
+                        ("port", peer.port.to_string()),
+                        ("runid", id.clone()),
+                        ("flags", "sentinel".to_string()),
+                        (
+                            "last-hello-message",
+                            now.saturating_sub(peer.last_hello).to_string(),
+                        ),
+                        (
+                            "voted-leader",
+                            peer.leader.clone().unwrap_or_else(|| "?".to_string()),
+                        ),
+                        ("voted-leader-epoch", peer.leader_epoch.to_string()),
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    /// The fields of the `Sentinel` section of `INFO`: a summary of each primary.
+    pub(crate) fn info_fields(&self) -> Vec<(String, String)> {
+        let mut fields = vec![
+            (
+                "sentinel_masters".to_string(),
+                self.masters.len().to_string(),
+            ),
+            ("sentinel_tilt".to_string(), "0".to_string()),
+        ];
+
+        for (index, master) in self.masters.values().enumerate() {
+            let status = if master.odown_since.is_some() {
+                "odown"
+            } else if master.instance.down_since.is_some() {
+                "sdown"
+            } else {
+                "ok"
+            };
+
+            fields.push((
+                format!("master{index}"),
+                format!(
+                    "name={},status={status},address={}:{},slaves={},sentinels={}",
+                    master.name,
+                    master.instance.host,
+                    master.instance.port,
+                    master.replicas.len(),
+                    master.sentinels.len() + 1
+                ),
+            ));
+        }
+
+        fields
+    }
+
+    /// The requests to send since this was last called.
+    pub(crate) fn take_outbox(&mut self) -> Vec<Request> {
+        mem::take(&mut self.outbox)
+    }
+
+    /// The events since this was last called, with the channel to publish each on.
+    pub(crate) fn take_events(&mut self) -> Vec<(&'static str, String)> {
+        mem::take(&mut self.events)
+    }
+}
+
+fn set_failover_state(master: &mut Master, state: FailoverState, now: u64) {
+    if let Some(failover) = &mut master.failover {
+        failover.state = state;
+        failover.state_changed = now;
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads a reply in the Redis protocol.
+pub(crate) fn read_reply(reader: &mut impl BufRead) -> io::Result<Reply> {
+    let mut line = Vec::new();
+
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let line = String::from_utf8_lossy(&line);
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (kind, rest) = line.split_at(line.len().min(1));
+    let number = || {
+        rest.parse::<i64>()
+            .map_err(|_| invalid_data(format!("unexpected line '{line}'")))
+    };
+
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_string())),
+        "-" => Ok(Reply::Error(rest.to_string())),
+        ":" => Ok(Reply::Integer(number()?)),
+        "$" => match usize::try_from(number()?) {
+            Ok(length) => {
+                let mut bytes = vec![0; length + 2];
+
+                reader.read_exact(&mut bytes)?;
+                bytes.truncate(length);
+
+                Ok(Reply::Bulk(Some(bytes)))
+            }
+            Err(_) => Ok(Reply::Bulk(None)),
+        },
+        "*" => {
+            let count = usize::try_from(number()?).unwrap_or(0);
+
+            (0..count)
+                .map(|_| read_reply(reader))
+                .collect::<io::Result<_>>()
+                .map(Reply::Array)
+        }
+        _ => Err(invalid_data(format!("unexpected line '{line}'"))),
+    }
+}
+
+/// Sends sentinels' requests to the instances they monitor and to other sentinels, and subscribes to the hello channel
+/// of every instance they ping. Each address gets its own connection and thread, so that an instance that doesn't
+/// answer doesn't hold up requests to the others.
+pub(crate) struct Links {
+    data: Arc<Mutex<Data>>,
+    links: HashMap<(String, u16), Sender<Request>>,
+    subscribers: HashMap<(String, u16), JoinHandle<()>>,
+}
+
+impl Links {
+    pub(crate) fn new(data: Arc<Mutex<Data>>) -> Links {
+        Links {
+            data,
+            links: HashMap::new(),
+            subscribers: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn send(&mut self, request: Request) {
+        let address = (request.host.clone(), request.port);
+
+        // Only instances are pinged, and only they have hellos to subscribe to.
+        if matches!(request.purpose, Purpose::Ping(_))
+            && self
+                .subscribers
+                .get(&address)
+                .is_none_or(JoinHandle::is_finished)
+        {
+            let data = Arc::clone(&self.data);
+            let (host, port) = address.clone();
+
+            self.subscribers.insert(
+                address.clone(),
+                thread::spawn(move || subscribe(&data, &host, port)),
+            );
+        }
+
+        let sender = self
+            .links
+            .entry(address)
+            .or_insert_with_key(|(host, port)| {
+                let (sender, receiver) = mpsc::channel();
+                let data = Arc::clone(&self.data);
+                let (host, port) = (host.clone(), *port);
+
+                thread::spawn(move || link(&data, &host, port, receiver));
+                sender
+            });
+
+        let _ = sender.send(request);
+    }
+}
+
+/// Sends the requests for one address, one at a time, connecting again whenever the connection fails, and hands the
+/// replies to the sentinel.
+fn link(data: &Mutex<Data>, host: &str, port: u16, requests: Receiver<Request>) {
+    let mut connection = None;
+
+    while let Ok(request) = requests.recv() {
+        let reply = exchange(&mut connection, host, port, &request.command);
+
+        if reply.is_err() {
+            connection = None;
+        }
+
+        data.lock()
+            .expect("failed to acquire lock")
+            .sentinel_reply(&request, reply.ok());
+    }
+}
+
+/// Sends a command and reads its reply, connecting first if needed.
+fn exchange(
+    connection: &mut Option<(TcpStream, BufReader<TcpStream>)>,
+    host: &str,
+    port: u16,
+    command: &[Vec<u8>],
+) -> io::Result<Reply> {
+    let (stream, reader) = match connection {
+        Some(connection) => connection,
+        None => {
+            let stream = connect(host, port)?;
+
+            stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+            stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+            let reader = BufReader::new(stream.try_clone()?);
+
+            connection.insert((stream, reader))
+        }
+    };
+    let mut bytes = Vec::new();
+
+    aof::encode(&mut bytes, command);
+    stream.write_all(&bytes)?;
+
+    read_reply(reader)
+}
+
+/// Listens to the hellos published on an instance, subscribing again whenever the connection fails, for as long as
+/// the instance is monitored.
+fn subscribe(data: &Mutex<Data>, host: &str, port: u16) {
+    while data
+        .lock()
+        .expect("failed to acquire lock")
+        .sentinel()
+        .watches(host, port)
+    {
+        let _ = listen_for_hellos(data, host, port);
+
+        thread::sleep(RESUBSCRIBE_DELAY);
+    }
+}
+
+fn listen_for_hellos(data: &Mutex<Data>, host: &str, port: u16) -> io::Result<()> {
+    let mut stream = connect(host, port)?;
+    let mut bytes = Vec::new();
+
+    // Hellos are published every couple of seconds, so a silent connection is likely broken.
+    stream.set_read_timeout(Some(Duration::from_millis(3 * HELLO_PERIOD)))?;
+    aof::encode(
+        &mut bytes,
+        &[b"SUBSCRIBE".to_vec(), HELLO_CHANNEL.as_bytes().to_vec()],
+    );
+    stream.write_all(&bytes)?;
+
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let Reply::Array(message) = read_reply(&mut reader)? else {
+            continue;
+        };
+        let [Reply::Bulk(Some(kind)), _, Reply::Bulk(Some(hello))] = &message[..] else {
+            continue;
+        };
+
+        if kind != b"message" {
+            continue;
+        }
+
+        let mut data = data.lock().expect("failed to acquire lock");
+
+        if !data.sentinel().watches(host, port) {
+            return Ok(());
+        }
+
+        data.sentinel_hello(&String::from_utf8_lossy(hello));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_replies() {
+        let mut reader: &[u8] =
+            b"+PONG\r\n-ERR no\r\n:-3\r\n$-1\r\n$5\r\na\r\nbc\r\n*2\r\n:1\r\n*0\r\n";
+
+        assert_eq!(
+            Reply::Status("PONG".to_string()),
+            read_reply(&mut reader).unwrap()
+        );
+        assert_eq!(
+            Reply::Error("ERR no".to_string()),
+            read_reply(&mut reader).unwrap()
+        );
+        assert_eq!(Reply::Integer(-3), read_reply(&mut reader).unwrap());
+        assert_eq!(Reply::Bulk(None), read_reply(&mut reader).unwrap());
+        assert_eq!(
+            Reply::Bulk(Some(b"a\r\nbc".to_vec())),
+            read_reply(&mut reader).unwrap()
+        );
+        assert_eq!(
+            Reply::Array(vec![Reply::Integer(1), Reply::Array(Vec::new())]),
+            read_reply(&mut reader).unwrap()
+        );
+        assert!(read_reply(&mut reader).is_err());
+        assert!(read_reply(&mut &b"?\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn parse_reports() {
+        assert_eq!(
+            Report {
+                role: Role::Primary,
+                offset: 42,
+                link_up: false,
+                replicas: vec![
+                    ("127.0.0.1".to_string(), 6380),
+                    ("127.0.0.1".to_string(), 6381)
+                ],
+            },
+            Report::parse(
+                "# Replication\r\nrole:master\r\nconnected_slaves:2\r\n\
+                slave0:ip=127.0.0.1,port=6380,state=online,offset=42,lag=0\r\n\
+                slave1:ip=127.0.0.1,port=6381,state=online,offset=40,lag=1\r\n\
+                master_repl_offset:42\r\n"
+            )
+        );
+        assert_eq!(
+            Report {
+                role: Role::Replica("127.0.0.1".to_string(), 6379),
+                offset: 40,
+                link_up: true,
+                replicas: Vec::new(),
+            },
+            Report::parse(
+                "role:slave\r\nmaster_host:127.0.0.1\r\nmaster_port:6379\r\n\
+                master_link_status:up\r\nslave_repl_offset:40\r\nmaster_repl_offset:40\r\n"
+            )
+        );
+        assert_eq!(Role::Unknown, Report::parse("").role);
+    }
+
+    /// An instance the simulated sentinels monitor.
+    struct Fake {
+        up: bool,
+        /// The port of its primary.
+        primary: Option<u16>,
+        offset: u64,
+    }
+
+    fn info(instances: &BTreeMap<u16, Fake>, port: u16) -> String {
+        let instance = &instances[&port];
+
+        match instance.primary {
+            Some(primary) => format!(
+                "role:slave\r\nmaster_host:127.0.0.1\r\nmaster_port:{primary}\r\nmaster_link_status:{}\r\n\
+                slave_repl_offset:{}\r\n",
+                if instances[&primary].up { "up" } else { "down" },
+                instance.offset
+            ),
+            None => {
+                let mut info = "role:master\r\n".to_string();
+                let replicas = instances
+                    .iter()
+                    .filter(|(_, replica)| replica.up && replica.primary == Some(port));
+
+                for (index, (replica, _)) in replicas.enumerate() {
+                    info.push_str(&format!("slave{index}:ip=127.0.0.1,port={replica},state=online\r\n"));
+                }
+
+                info
+            }
+        }
+    }
+
+    /// Delivers the requests the sentinels sent, to the fake instances and to each other, and hands them the replies.
+    fn deliver(sentinels: &mut [Sentinel], instances: &mut BTreeMap<u16, Fake>, now: u64) {
+        for index in 0..sentinels.len() {
+            for request in sentinels[index].take_outbox() {
+                let command: Vec<String> = request
+                    .command
+                    .iter()
+                    .map(|argument| String::from_utf8_lossy(argument).into_owned())
+                    .collect();
+                let command: Vec<&str> = command.iter().map(String::as_str).collect();
+                let reply = match command[..] {
+                    ["SENTINEL", _, host, port, epoch, candidate] => {
+                        let other = sentinels
+                            .iter_mut()
+                            .find(|sentinel| sentinel.port == request.port)
+                            .unwrap();
+                        let (down, leader, epoch) = other.is_master_down_by_addr(
+                            host,
+                            port.parse().unwrap(),
+                            epoch.parse().unwrap(),
+                            candidate,
+                            now,
+                        );
+
+                        Some(Reply::Array(vec![
+                            Reply::Integer(down as i64),
+                            Reply::Bulk(Some(leader.into_bytes())),
+                            Reply::Integer(epoch as i64),
+                        ]))
+                    }
+                    _ if !instances[&request.port].up => None,
+                    ["PING"] => Some(Reply::Status("PONG".to_string())),
+                    ["INFO", _] => Some(Reply::Bulk(Some(
+                        info(instances, request.port).into_bytes(),
+                    ))),
+                    ["PUBLISH", _, hello] => {
+                        for sentinel in sentinels.iter_mut() {
+                            sentinel.hello(hello, now);
+                        }
+
+                        Some(Reply::Integer(sentinels.len() as i64))
+                    }
+                    ["REPLICAOF", "NO", "ONE"] => {
+                        instances.get_mut(&request.port).unwrap().primary = None;
+
+                        Some(Reply::Status("OK".to_string()))
+                    }
+                    ["REPLICAOF", _, port] => {
+                        instances.get_mut(&request.port).unwrap().primary =
+                            Some(port.parse().unwrap());
+
+                        Some(Reply::Status("OK".to_string()))
+                    }
+                    _ => panic!("unexpected request {command:?}"),
+                };
+
+                sentinels[index].reply(&request, reply, now);
+            }
+        }
+    }
+
+    /// Runs the sentinels for `duration` milliseconds, in steps of 100 like the server's cron.
+    fn run(
+        sentinels: &mut [Sentinel],
+        instances: &mut BTreeMap<u16, Fake>,
+        now: &mut u64,
+        duration: u64,
+    ) {
+        for _ in 0..duration / 100 {
+            *now += 100;
+
+            // Each sentinel's requests are delivered before the next one runs, like those of separate processes.
+            for index in 0..sentinels.len() {
+                sentinels[index].cron(*now);
+                deliver(sentinels, instances, *now);
+            }
+        }
+    }
+
+    #[test]
+    fn failover() {
+        let mut now = 1_000_000;
+        let mut instances = BTreeMap::from([
+            (
+                6379,
+                Fake {
+                    up: true,
+                    primary: None,
+                    offset: 300,
+                },
+            ),
+            (
+                6380,
+                Fake {
+                    up: true,
+                    primary: Some(6379),
+                    offset: 100,
+                },
+            ),
+            (
+                6381,
+                Fake {
+                    up: true,
+                    primary: Some(6379),
+                    offset: 200,
+                },
+            ),
+        ]);
+        let mut sentinels: Vec<Sentinel> = (0..3)
+            .map(|index| {
+                let mut sentinel = Sentinel::new();
+
+                sentinel.enable(26379 + index, 1000, 10_000);
+                sentinel
+                    .monitor("mymaster", "127.0.0.1", 6379, 2, now)
+                    .unwrap();
+                sentinel
+            })
+            .collect();
+
+        run(&mut sentinels, &mut instances, &mut now, 3000);
+
+        for sentinel in &sentinels {
+            assert_eq!(2, sentinel.replica_fields("mymaster", now).unwrap().len());
+            assert_eq!(2, sentinel.sentinel_fields("mymaster", now).unwrap().len());
+            assert!(sentinel.master_fields("mymaster", now).unwrap()[4].1 == "master");
+        }
+
+        // Instances that answer aren't considered down, however long the down after period is compared to pings'.
+        assert!(sentinels
+            .iter_mut()
+            .flat_map(|sentinel| sentinel.take_events())
+            .all(|(channel, _)| channel != "+sdown"));
+
+        instances.get_mut(&6379).unwrap().up = false;
+        run(&mut sentinels, &mut instances, &mut now, 20_000);
+
+        // The replica with the greatest offset was promoted, and the other one follows it.
+        for sentinel in &sentinels {
+            assert_eq!(
+                Some(("127.0.0.1", 6381)),
+                sentinel.master_address("mymaster")
+            );
+        }
+
+        assert_eq!(None, instances[&6381].primary);
+        assert_eq!(Some(6381), instances[&6380].primary);
+
+        let switches = sentinels
+            .iter_mut()
+            .flat_map(|sentinel| sentinel.take_events())
+            .filter(|(channel, message)| {
+                *channel == "+switch-master" && message == "mymaster 127.0.0.1 6379 127.0.0.1 6381"
+            })
+            .count();
+
+        assert_eq!(3, switches);
+
+        // The former primary comes back as a primary, and is made a replica of the new one.
+        let old = instances.get_mut(&6379).unwrap();
+
+        old.up = true;
+        old.primary = None;
+        run(&mut sentinels, &mut instances, &mut now, 15_000);
+
+        assert_eq!(Some(6381), instances[&6379].primary);
+        assert!(sentinels
+            .iter_mut()
+            .flat_map(|sentinel| sentinel.take_events())
+            .any(|(channel, _)| channel == "+convert-to-slave"));
+
+        for sentinel in &sentinels {
+            assert_eq!(2, sentinel.replica_fields("mymaster", now).unwrap().len());
+        }
+    }
+
+    #[test]
+    fn votes() {
+        let mut sentinel = Sentinel::new();
+
+        sentinel.enable(26379, 1000, 10_000);
+        sentinel
+            .monitor("mymaster", "127.0.0.1", 6379, 2, 0)
+            .unwrap();
+
+        assert_eq!(
+            (false, "*".to_string(), 0),
+            sentinel.is_master_down_by_addr("127.0.0.1", 6380, 1, "aaaa", 0)
+        );
+        assert_eq!(
+            (false, "aaaa".to_string(), 2),
+            sentinel.is_master_down_by_addr("127.0.0.1", 6379, 2, "aaaa", 0)
+        );
+        assert_eq!(2, sentinel.current_epoch);
+        // One vote per epoch, and none for past epochs.
+        assert_eq!(
+            (false, "aaaa".to_string(), 2),
+            sentinel.is_master_down_by_addr("127.0.0.1", 6379, 2, "bbbb", 0)
+        );
+        assert_eq!(
+            (false, "aaaa".to_string(), 2),
+            sentinel.is_master_down_by_addr("127.0.0.1", 6379, 1, "bbbb", 0)
+        );
+        assert_eq!(
+            (false, "bbbb".to_string(), 3),
+            sentinel.is_master_down_by_addr("127.0.0.1", 6379, 3, "bbbb", 0)
+        );
+
+        // Having voted for another sentinel, this one doesn't try a failover itself for a while.
+        assert!(sentinel.masters["mymaster"].failover_start > 0);
+    }
+
+    #[test]
+    fn hellos() {
+        let mut sentinel = Sentinel::new();
+
+        sentinel.enable(26379, 1000, 10_000);
+        sentinel
+            .monitor("mymaster", "127.0.0.1", 6379, 2, 0)
+            .unwrap();
+        sentinel.hello("127.0.0.1,26380,aaaa,0,mymaster,127.0.0.1,6379,0", 0);
+        sentinel.hello("127.0.0.1,26380,aaaa,0,other,127.0.0.1,6379,0", 0);
+        sentinel.hello("garbage", 0);
+
+        assert_eq!(1, sentinel.sentinel_fields("mymaster", 0).unwrap().len());
+
+        // A restarted sentinel replaces its former self.
+        sentinel.hello("127.0.0.1,26380,bbbb,0,mymaster,127.0.0.1,6379,0", 0);
+
+        let sentinels = sentinel.sentinel_fields("mymaster", 0).unwrap();
+
+        assert_eq!(1, sentinels.len());
+        assert_eq!(("name", "bbbb".to_string()), sentinels[0][0]);
+
+        // An address announced in a later epoch wins, and one from an earlier epoch doesn't.
+        sentinel.hello("127.0.0.1,26380,bbbb,3,mymaster,127.0.0.1,6380,2", 0);
+
+        assert_eq!(
+            Some(("127.0.0.1", 6380)),
+            sentinel.master_address("mymaster")
+        );
+        assert_eq!(3, sentinel.current_epoch);
+
+        sentinel.hello("127.0.0.1,26380,bbbb,3,mymaster,127.0.0.1,6381,1", 0);
+
+        assert_eq!(
+            Some(("127.0.0.1", 6380)),
+            sentinel.master_address("mymaster")
+        );
+        assert!(sentinel.watches("127.0.0.1", 6379));
+        assert!(sentinel.take_events().contains(&(
+            "+switch-master",
+            "mymaster 127.0.0.1 6379 127.0.0.1 6380".to_string()
+        )));
+    }
+}
@@ -0,0 +1,571 @@
+use crate::gorilla::CompressedChunk;
+use crate::rdb::{ModuleReader, ModuleWriter};
+
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+pub(crate) type Sample = (u64, f64);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum DuplicatePolicy {
+    Block,
+    First,
+    Last,
+    Min,
+    Max,
+    Sum,
+}
+
+impl DuplicatePolicy {
+    pub(crate) fn parse(name: &str) -> Option<DuplicatePolicy> {
+        match name {
+            "BLOCK" => Some(DuplicatePolicy::Block),
+            "FIRST" => Some(DuplicatePolicy::First),
+            "LAST" => Some(DuplicatePolicy::Last),
+            "MIN" => Some(DuplicatePolicy::Min),
+            "MAX" => Some(DuplicatePolicy::Max),
+            "SUM" => Some(DuplicatePolicy::Sum),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            DuplicatePolicy::Block => "block",
+            DuplicatePolicy::First => "first",
+            DuplicatePolicy::Last => "last",
+            DuplicatePolicy::Min => "min",
+            DuplicatePolicy::Max => "max",
+            DuplicatePolicy::Sum => "sum",
+        }
+    }
+
+    fn resolve(self, old: f64, new: f64) -> Option<f64> {
+        match self {
+            DuplicatePolicy::Block => None,
+            DuplicatePolicy::First => Some(old),
+            DuplicatePolicy::Last => Some(new),
+            DuplicatePolicy::Min => Some(old.min(new)),
+            DuplicatePolicy::Max => Some(old.max(new)),
+            DuplicatePolicy::Sum => Some(old + new),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum TimeSeriesError {
+    OlderThanRetention,
+    Duplicate,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Chunk {
+    Compressed(CompressedChunk),
+    Uncompressed(Vec<Sample>),
+}
+
+impl Chunk {
+    fn new(compressed: bool) -> Chunk {
+        if compressed {
+            Chunk::Compressed(CompressedChunk::new())
+        } else {
+            Chunk::Uncompressed(Vec::new())
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Chunk::Compressed(chunk) => chunk.len(),
+            Chunk::Uncompressed(samples) => samples.len(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Chunk::Compressed(chunk) => chunk.size(),
+            Chunk::Uncompressed(samples) => samples.len() * std::mem::size_of::<Sample>(),
+        }
+    }
+
+    fn push(&mut self, timestamp: u64, value: f64) {
+        match self {
+            Chunk::Compressed(chunk) => chunk.push(timestamp, value),
+            Chunk::Uncompressed(samples) => samples.push((timestamp, value)),
+        }
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        match self {
+            Chunk::Compressed(chunk) => chunk.iter().collect(),
+            Chunk::Uncompressed(samples) => samples.clone(),
+        }
+    }
+
+    fn first_timestamp(&self) -> Option<u64> {
+        match self {
+            Chunk::Compressed(chunk) => chunk.iter().next().map(|(t, _)| t),
+            Chunk::Uncompressed(samples) => samples.first().map(|(t, _)| *t),
+        }
+    }
+}
+
+/// A series of `(timestamp, value)` samples, kept in timestamp order in a list of chunks. Only the last chunk is
+/// appended to; inserting an older sample rewrites the chunk it belongs in.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct TimeSeries {
+    chunks: Vec<Chunk>,
+    /// How long samples are kept, in milliseconds, relative to the newest sample. Zero keeps them forever.
+    pub(crate) retention: u64,
+    pub(crate) chunk_size: usize,
+    pub(crate) duplicate_policy: DuplicatePolicy,
+    pub(crate) labels: Vec<(String, String)>,
+    compressed: bool,
+    last_timestamp: Option<u64>,
+}
+
+impl TimeSeries {
+    pub(crate) fn new(
+        retention: u64,
+        compressed: bool,
+        chunk_size: usize,
+        duplicate_policy: DuplicatePolicy,
+        labels: Vec<(String, String)>,
+    ) -> TimeSeries {
+        TimeSeries {
+            chunks: vec![Chunk::new(compressed)],
+            retention,
+            chunk_size,
+            duplicate_policy,
+            labels,
+            compressed,
+            last_timestamp: None,
+        }
+    }
+
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.chunks.iter().map(Chunk::len).sum()
+    }
+
+    pub(crate) fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        std::mem::size_of::<TimeSeries>() + self.chunks.iter().map(Chunk::size).sum::<usize>()
+    }
+
+    pub(crate) fn first_timestamp(&self) -> Option<u64> {
+        self.chunks.iter().find_map(Chunk::first_timestamp)
+    }
+
+    pub(crate) fn last_timestamp(&self) -> Option<u64> {
+        self.last_timestamp
+    }
+
+    pub(crate) fn last_sample(&self) -> Option<Sample> {
+        self.chunks
+            .iter()
+            .rev()
+            .find_map(|chunk| chunk.samples().last().copied())
+    }
+
+    /// The oldest timestamp that's still within the retention window.
+    fn retention_start(&self) -> u64 {
+        match (self.retention, self.last_timestamp) {
+            (0, _) | (_, None) => 0,
+            (retention, Some(last)) => last.saturating_sub(retention),
+        }
+    }
+
+    /// Adds a sample, resolving a clash with an existing sample at the same timestamp according to `policy` (or the
+    /// series' own policy). Returns the timestamp of the sample.
+    pub(crate) fn add(
+        &mut self,
+        timestamp: u64,
+        value: f64,
+        policy: Option<DuplicatePolicy>,
+    ) -> Result<u64, TimeSeriesError> {
+        if self.retention > 0 && timestamp < self.retention_start() {
+            return Err(TimeSeriesError::OlderThanRetention);
+        }
+
+        match self.last_timestamp {
+            Some(last) if timestamp <= last => {
+                self.upsert(timestamp, value, policy.unwrap_or(self.duplicate_policy))?
+            }
+            _ => {
+                if self.chunks.last().unwrap().size() >= self.chunk_size {
+                    self.chunks.push(Chunk::new(self.compressed));
+                }
+
+                self.chunks.last_mut().unwrap().push(timestamp, value);
+                self.last_timestamp = Some(timestamp);
+            }
+        }
+
+        self.trim();
+
+        Ok(timestamp)
+    }
+
+    fn upsert(
+        &mut self,
+        timestamp: u64,
+        value: f64,
+        policy: DuplicatePolicy,
+    ) -> Result<(), TimeSeriesError> {
+        // The sample belongs in the last chunk that starts at or before it (or the first chunk, if it's older than
+        // everything).
+        let index = self
+            .chunks
+            .iter()
+            .rposition(|chunk| {
+                chunk
+                    .first_timestamp()
+                    .is_some_and(|first| first <= timestamp)
+            })
+            .unwrap_or(0);
+
+        let mut samples = self.chunks[index].samples();
+
+        match samples.binary_search_by(|(t, _)| t.cmp(&timestamp)) {
+            Ok(position) => {
+                let resolved = policy
+                    .resolve(samples[position].1, value)
+                    .ok_or(TimeSeriesError::Duplicate)?;
+
+                samples[position].1 = resolved;
+            }
+            Err(position) => samples.insert(position, (timestamp, value)),
+        }
+
+        let mut chunk = Chunk::new(self.compressed);
+
+        for (timestamp, value) in samples {
+            chunk.push(timestamp, value);
+        }
+
+        self.chunks[index] = chunk;
+
+        Ok(())
+    }
+
+    /// Drops chunks that lie entirely outside the retention window.
+    fn trim(&mut self) {
+        let start = self.retention_start();
+
+        while self.chunks.len() > 1
+            && self.chunks[0]
+                .samples()
+                .last()
+                .is_some_and(|(t, _)| *t < start)
+        {
+            self.chunks.remove(0);
+        }
+    }
+
+    /// Saves the series to an RDB file, chunk by chunk.
+    pub(crate) fn save(&self, writer: &mut ModuleWriter) {
+        writer.save_unsigned(self.retention);
+        writer.save_unsigned(self.chunk_size as u64);
+        writer.save_string(self.duplicate_policy.name().as_bytes());
+        writer.save_unsigned(self.compressed as u64);
+        writer.save_unsigned(self.labels.len() as u64);
+
+        for (name, value) in &self.labels {
+            writer.save_string(name.as_bytes());
+            writer.save_string(value.as_bytes());
+        }
+
+        writer.save_unsigned(self.chunks.len() as u64);
+
+        for chunk in &self.chunks {
+            let samples = chunk.samples();
+
+            writer.save_unsigned(samples.len() as u64);
+
+            for (timestamp, value) in samples {
+                writer.save_unsigned(timestamp);
+                writer.save_double(value);
+            }
+        }
+    }
+
+    pub(crate) fn load(reader: &mut ModuleReader) -> Option<TimeSeries> {
+        let retention = reader.load_unsigned()?;
+        let chunk_size = usize::try_from(reader.load_unsigned()?).ok()?;
+        let duplicate_policy = DuplicatePolicy::parse(
+            &String::from_utf8(reader.load_string()?)
+                .ok()?
+                .to_uppercase(),
+        )?;
+        let compressed = reader.load_unsigned()? != 0;
+        let mut labels = Vec::new();
+
+        for _ in 0..reader.load_unsigned()? {
+            labels.push((
+                String::from_utf8(reader.load_string()?).ok()?,
+                String::from_utf8(reader.load_string()?).ok()?,
+            ));
+        }
+
+        let mut series =
+            TimeSeries::new(retention, compressed, chunk_size, duplicate_policy, labels);
+
+        series.chunks.clear();
+
+        for _ in 0..reader.load_unsigned()? {
+            let mut chunk = Chunk::new(compressed);
+
+            for _ in 0..reader.load_unsigned()? {
+                let timestamp = reader.load_unsigned()?;
+
+                if series.last_timestamp.is_some_and(|last| timestamp <= last) {
+                    return None;
+                }
+
+                chunk.push(timestamp, reader.load_double()?);
+                series.last_timestamp = Some(timestamp);
+            }
+
+            series.chunks.push(chunk);
+        }
+
+        if series.chunks.is_empty() {
+            return None;
+        }
+
+        Some(series)
+    }
+
+    /// Returns the samples between `from` and `to` (inclusive), oldest first.
+    pub(crate) fn range(&self, from: u64, to: u64) -> Vec<Sample> {
+        let from = from.max(self.retention_start());
+
+        self.chunks
+            .iter()
+            .flat_map(Chunk::samples)
+            .filter(|(t, _)| (from..=to).contains(t))
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Aggregator {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregator {
+    pub(crate) fn parse(name: &str) -> Option<Aggregator> {
+        match name {
+            "AVG" => Some(Aggregator::Avg),
+            "SUM" => Some(Aggregator::Sum),
+            "MIN" => Some(Aggregator::Min),
+            "MAX" => Some(Aggregator::Max),
+            "COUNT" => Some(Aggregator::Count),
+            "FIRST" => Some(Aggregator::First),
+            "LAST" => Some(Aggregator::Last),
+            _ => None,
+        }
+    }
+
+    fn apply(self, values: &[f64]) -> f64 {
+        match self {
+            Aggregator::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregator::Sum => values.iter().sum(),
+            Aggregator::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregator::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregator::Count => values.len() as f64,
+            Aggregator::First => values[0],
+            Aggregator::Last => values[values.len() - 1],
+        }
+    }
+
+    /// The value reported for buckets without samples when empty buckets are requested.
+    fn empty_value(self) -> f64 {
+        match self {
+            Aggregator::Sum | Aggregator::Count => 0.0,
+            _ => f64::NAN,
+        }
+    }
+}
+
+/// Where in its bucket an aggregated sample's timestamp is reported.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum BucketTimestamp {
+    Start,
+    Middle,
+    End,
+}
+
+pub(crate) struct Aggregation {
+    pub(crate) aggregator: Aggregator,
+    pub(crate) bucket_duration: u64,
+    pub(crate) alignment: u64,
+    pub(crate) bucket_timestamp: BucketTimestamp,
+    pub(crate) empty: bool,
+}
+
+impl Aggregation {
+    /// The start of the bucket `timestamp` falls in. The first bucket can start before zero when it's aligned to a
+    /// later timestamp.
+    fn bucket_start(&self, timestamp: u64) -> i128 {
+        let duration = self.bucket_duration as i128;
+        let offset = (self.alignment % self.bucket_duration) as i128;
+
+        (timestamp as i128 - offset).div_euclid(duration) * duration + offset
+    }
+
+    fn report(&self, bucket_start: i128) -> u64 {
+        let timestamp = match self.bucket_timestamp {
+            BucketTimestamp::Start => bucket_start,
+            BucketTimestamp::Middle => bucket_start + self.bucket_duration as i128 / 2,
+            BucketTimestamp::End => bucket_start + self.bucket_duration as i128,
+        };
+
+        timestamp.clamp(0, u64::MAX as i128) as u64
+    }
+
+    /// Aggregates samples (sorted oldest first) into one sample per bucket.
+    pub(crate) fn apply(&self, samples: &[Sample]) -> Vec<Sample> {
+        let mut result = Vec::new();
+        let mut values = Vec::new();
+        let mut current: Option<i128> = None;
+
+        for &(timestamp, value) in samples {
+            let start = self.bucket_start(timestamp);
+
+            if let Some(bucket) = current.filter(|bucket| *bucket != start) {
+                result.push((self.report(bucket), self.aggregator.apply(&values)));
+                values.clear();
+
+                if self.empty {
+                    let mut empty = bucket + self.bucket_duration as i128;
+
+                    while empty < start {
+                        result.push((self.report(empty), self.aggregator.empty_value()));
+                        empty += self.bucket_duration as i128;
+                    }
+                }
+            }
+
+            current = Some(start);
+            values.push(value);
+        }
+
+        if let Some(bucket) = current {
+            result.push((self.report(bucket), self.aggregator.apply(&values)));
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series() -> TimeSeries {
+        TimeSeries::new(0, true, 64, DuplicatePolicy::Block, Vec::new())
+    }
+
+    #[test]
+    fn out_of_order_samples_are_sorted() {
+        let mut series = series();
+
+        for timestamp in [10, 30, 20, 5, 40] {
+            series.add(timestamp, timestamp as f64, None).unwrap();
+        }
+
+        assert_eq!(
+            vec![(5, 5.0), (10, 10.0), (20, 20.0), (30, 30.0), (40, 40.0)],
+            series.range(0, u64::MAX)
+        );
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let mut series = series();
+
+        series.add(10, 1.0, None).unwrap();
+
+        assert_eq!(Err(TimeSeriesError::Duplicate), series.add(10, 2.0, None));
+
+        series.add(10, 2.0, Some(DuplicatePolicy::Sum)).unwrap();
+        series.add(10, 1.0, Some(DuplicatePolicy::Max)).unwrap();
+
+        assert_eq!(vec![(10, 3.0)], series.range(0, u64::MAX));
+    }
+
+    #[test]
+    fn many_samples_span_several_chunks() {
+        let mut series = series();
+
+        for i in 0..1000 {
+            series.add(i * 10, (i % 7) as f64, None).unwrap();
+        }
+
+        assert!(series.chunk_count() > 1);
+        assert_eq!(1000, series.len());
+        assert_eq!(vec![(100, 3.0), (110, 4.0)], series.range(100, 119));
+    }
+
+    #[test]
+    fn retention() {
+        let mut series = TimeSeries::new(100, true, 64, DuplicatePolicy::Last, Vec::new());
+
+        for i in 0..100 {
+            series.add(i * 10, 1.0, None).unwrap();
+        }
+
+        assert_eq!(
+            Err(TimeSeriesError::OlderThanRetention),
+            series.add(100, 1.0, None)
+        );
+        assert_eq!(11, series.range(0, u64::MAX).len());
+    }
+
+    #[test]
+    fn aggregation() {
+        let samples = vec![(0, 1.0), (5, 3.0), (10, 5.0), (35, 7.0)];
+
+        let mut aggregation = Aggregation {
+            aggregator: Aggregator::Avg,
+            bucket_duration: 10,
+            alignment: 0,
+            bucket_timestamp: BucketTimestamp::Start,
+            empty: false,
+        };
+
+        assert_eq!(
+            vec![(0, 2.0), (10, 5.0), (30, 7.0)],
+            aggregation.apply(&samples)
+        );
+
+        aggregation.aggregator = Aggregator::Count;
+        aggregation.empty = true;
+
+        assert_eq!(
+            vec![(0, 2.0), (10, 1.0), (20, 0.0), (30, 1.0)],
+            aggregation.apply(&samples)
+        );
+
+        aggregation.alignment = 5;
+        aggregation.bucket_timestamp = BucketTimestamp::End;
+        aggregation.empty = false;
+
+        assert_eq!(
+            vec![(5, 1.0), (15, 2.0), (45, 1.0)],
+            aggregation.apply(&samples)
+        );
+    }
+}
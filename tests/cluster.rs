@@ -0,0 +1,99 @@
+//! Runs a cluster of Red processes on local ports, kills a primary, and checks that its replica takes over.
+
+use std::process;
+
+use common::{wait_until, Node};
+
+mod common;
+
+const NODE_TIMEOUT: &str = "500";
+
+fn start(port: u16) -> Node {
+    Node::start(
+        "cluster",
+        port,
+        &[
+            "--cluster-enabled",
+            "yes",
+            "--cluster-node-timeout",
+            NODE_TIMEOUT,
+        ],
+    )
+}
+
+fn id(node: &Node) -> String {
+    node.command(&["CLUSTER", "MYID"])
+        .lines()
+        .nth(1)
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn failover() {
+    let base = 20000 + (process::id() % 10000) as u16;
+    let mut nodes: Vec<Node> = (0..4).map(|i| start(base + i)).collect();
+
+    for node in &nodes[1..] {
+        assert_eq!(
+            "+OK\r\n",
+            nodes[0].command(&["CLUSTER", "MEET", "127.0.0.1", &node.port.to_string()])
+        );
+    }
+
+    for (node, (start, end)) in
+        nodes
+            .iter()
+            .zip([("0", "5460"), ("5461", "10922"), ("10923", "16383")])
+    {
+        assert_eq!(
+            "+OK\r\n",
+            node.command(&["CLUSTER", "ADDSLOTSRANGE", start, end])
+        );
+    }
+
+    wait_until("every node knows the others", || {
+        nodes.iter().all(|node| {
+            let info = node.command(&["CLUSTER", "INFO"]);
+
+            info.contains("cluster_state:ok") && info.contains("cluster_known_nodes:4")
+        })
+    });
+
+    let primary = id(&nodes[0]);
+
+    assert_eq!(
+        "+OK\r\n",
+        nodes[3].command(&["CLUSTER", "REPLICATE", &primary])
+    );
+    wait_until("the replica is in sync", || {
+        nodes[3].command(&["ROLE"]).contains("connected")
+    });
+
+    // "b" hashes to slot 3300, which the first node serves.
+    assert_eq!("+OK\r\n", nodes[0].command(&["SET", "b", "1"]));
+    assert_eq!(":1\r\n", nodes[0].command(&["WAIT", "1", "5000"]));
+    assert!(nodes[1].command(&["GET", "b"]).starts_with("-MOVED 3300 "));
+
+    nodes[0].kill();
+
+    wait_until("the replica takes over", || {
+        nodes[3]
+            .command(&["ROLE"])
+            .starts_with("*3\r\n$6\r\nmaster")
+    });
+    wait_until("the other nodes redirect to the replica", || {
+        let reply = nodes[1].command(&["GET", "b"]);
+
+        reply == format!("-MOVED 3300 127.0.0.1:{}\r\n", nodes[3].port)
+    });
+    assert_eq!("$1\r\n1\r\n", nodes[3].command(&["GET", "b"]));
+
+    // The former primary comes back as a replica of the node that replaced it.
+    nodes[0].spawn();
+    wait_until("the former primary becomes a replica", || {
+        nodes[0]
+            .command(&["ROLE"])
+            .contains(&format!("slave\r\n$9\r\n127.0.0.1\r\n:{}", nodes[3].port))
+    });
+}
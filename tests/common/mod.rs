@@ -0,0 +1,147 @@
+//! Starts Red processes on local ports and talks to them, for the tests that run several of them.
+
+// Each test uses only some of these.
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A Red process, killed when dropped.
+pub struct Node {
+    pub port: u16,
+    dir: PathBuf,
+    arguments: Vec<String>,
+    child: Option<Child>,
+}
+
+impl Node {
+    /// Starts a node with its own directory, named after `test`, and with `arguments` after the usual ones.
+    pub fn start(test: &str, port: u16, arguments: &[&str]) -> Node {
+        let dir = env::temp_dir().join(format!("red-{test}-{}-{port}", process::id()));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut node = Node {
+            port,
+            dir,
+            arguments: arguments
+                .iter()
+                .map(|argument| argument.to_string())
+                .collect(),
+            child: None,
+        };
+
+        node.spawn();
+        node
+    }
+
+    /// Starts the process again after [`Node::kill`], with the same directory and arguments.
+    pub fn spawn(&mut self) {
+        let child = Command::new(env!("CARGO_BIN_EXE_red"))
+            .args(["--port", &self.port.to_string()])
+            .args(["--save", ""])
+            .arg("--dir")
+            .arg(&self.dir)
+            .args(&self.arguments)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        self.child = Some(child);
+        wait_until("the node listens", || {
+            TcpStream::connect(("127.0.0.1", self.port)).is_ok()
+        });
+    }
+
+    pub fn kill(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    /// Sends a command and returns the raw reply.
+    pub fn command(&self, arguments: &[&str]) -> String {
+        let mut stream = self.send(&[arguments]);
+        let mut reply = Vec::new();
+        let mut buf = [0; 4096];
+
+        // Replies may come in several reads: once one seems complete, only wait a little for more.
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+
+            reply.extend_from_slice(&buf[..n]);
+
+            if reply.ends_with(b"\r\n") && n < buf.len() {
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(20)))
+                    .unwrap();
+            }
+        }
+
+        String::from_utf8(reply).unwrap()
+    }
+
+    /// Sends commands in one write and returns the raw replies, once `replies` of them have arrived.
+    pub fn pipeline(&self, commands: &[&[&str]], replies: usize) -> String {
+        let mut stream = self.send(commands);
+        let mut reply = Vec::new();
+        let mut buf = [0; 64 * 1024];
+
+        // Each reply ends a line, though bulk strings end two.
+        while reply.windows(2).filter(|w| w == b"\r\n").count() < replies {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => reply.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        String::from_utf8(reply).unwrap()
+    }
+
+    /// Connects and writes `commands`, returning the connection to read the replies from.
+    fn send(&self, commands: &[&[&str]]) -> TcpStream {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        let mut request = Vec::new();
+
+        for arguments in commands {
+            request.extend(format!("*{}\r\n", arguments.len()).into_bytes());
+
+            for argument in *arguments {
+                request.extend(format!("${}\r\n{argument}\r\n", argument.len()).into_bytes());
+            }
+        }
+
+        stream.write_all(&request).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.kill();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting until {what}");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,74 @@
+//! Runs two Red processes on local ports and moves keys between them with `MIGRATE`.
+
+use std::net::TcpListener;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::Node;
+
+mod common;
+
+#[test]
+fn migrate() {
+    let base = 30000 + (process::id() % 10000) as u16;
+    let source = Node::start("migrate", base, &[]);
+    let target = Node::start("migrate", base + 1, &[]);
+    let value = "x".repeat(100_000);
+
+    // Commands longer than a read, and several in a single write, are each run once.
+    assert_eq!(
+        "+OK\r\n+OK\r\n:2\r\n",
+        source.pipeline(
+            &[
+                &["SET", "big", &value],
+                &["SET", "small", "1"],
+                &["EXISTS", "big", "small"]
+            ],
+            3
+        )
+    );
+    assert_eq!(
+        "+OK\r\n",
+        source.command(&[
+            "MIGRATE",
+            "127.0.0.1",
+            &target.port.to_string(),
+            "",
+            "0",
+            "5000",
+            "KEYS",
+            "big",
+            "small"
+        ])
+    );
+    assert_eq!(
+        format!("${}\r\n{value}\r\n", value.len()),
+        target.pipeline(&[&["GET", "big"]], 2)
+    );
+    assert_eq!(":0\r\n", source.command(&["EXISTS", "big", "small"]));
+
+    // Other connections aren't blocked while keys are sent to a target that doesn't reply.
+    let stalled = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stalled_port = stalled.local_addr().unwrap().port().to_string();
+
+    assert_eq!("+OK\r\n", source.command(&["SET", "a", "1"]));
+
+    thread::scope(|scope| {
+        let migrating = scope
+            .spawn(|| source.command(&["MIGRATE", "127.0.0.1", &stalled_port, "a", "0", "2000"]));
+
+        thread::sleep(Duration::from_millis(500));
+
+        let started = Instant::now();
+
+        assert_eq!("+PONG\r\n", source.command(&["PING"]));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(migrating
+            .join()
+            .unwrap()
+            .starts_with("-IOERR error or timeout reading"));
+    });
+    assert_eq!(":1\r\n", source.command(&["EXISTS", "a"]));
+    drop(stalled);
+}
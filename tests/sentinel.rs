@@ -0,0 +1,78 @@
+//! Runs a primary, a replica and three sentinels on local ports, kills the primary, and checks that the sentinels
+//! promote the replica and tell clients its address.
+
+use std::process;
+
+use common::{wait_until, Node};
+
+mod common;
+
+fn address_reply(port: u16) -> String {
+    format!(
+        "*2\r\n$9\r\n127.0.0.1\r\n${}\r\n{port}\r\n",
+        port.to_string().len()
+    )
+}
+
+#[test]
+fn failover() {
+    let base = 40000 + (process::id() % 10000) as u16;
+    let mut primary = Node::start("sentinel", base, &[]);
+    let replica = Node::start(
+        "sentinel",
+        base + 1,
+        &["--replicaof", &format!("127.0.0.1 {base}")],
+    );
+
+    wait_until("the replica is in sync", || {
+        replica.command(&["ROLE"]).contains("connected")
+    });
+
+    let monitor = format!("mymaster 127.0.0.1 {base} 2");
+    let sentinels: Vec<Node> = (2..5)
+        .map(|offset| {
+            Node::start(
+                "sentinel",
+                base + offset,
+                &[
+                    "--sentinel",
+                    "--sentinel-monitor",
+                    &monitor,
+                    "--sentinel-down-after-milliseconds",
+                    "500",
+                    "--sentinel-failover-timeout",
+                    "5000",
+                ],
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        address_reply(base),
+        sentinels[0].command(&["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "mymaster"])
+    );
+    assert!(sentinels[0]
+        .command(&["SET", "a", "1"])
+        .starts_with("-unknown command"));
+    wait_until("the sentinels know the replica and each other", || {
+        sentinels.iter().all(|sentinel| {
+            sentinel
+                .command(&["INFO"])
+                .contains(&format!("address=127.0.0.1:{base},slaves=1,sentinels=3"))
+        })
+    });
+
+    assert_eq!("+OK\r\n", primary.command(&["SET", "a", "1"]));
+    assert_eq!(":1\r\n", primary.command(&["WAIT", "1", "5000"]));
+
+    primary.kill();
+
+    wait_until("every sentinel tells the replica's address", || {
+        sentinels.iter().all(|sentinel| {
+            sentinel.command(&["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "mymaster"])
+                == address_reply(replica.port)
+        })
+    });
+    assert!(replica.command(&["ROLE"]).starts_with("*3\r\n$6\r\nmaster"));
+    assert_eq!("$1\r\n1\r\n", replica.command(&["GET", "a"]));
+}